@@ -18,7 +18,171 @@
  */
 
 use async_trait::async_trait;
+use kstd::cancel::CancelToken;
 use resp::RespData;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Kill token of a client and the waker of its connection.
+#[derive(Clone, Default)]
+pub struct KillSignal {
+    token: CancelToken,
+    notify: Arc<Notify>,
+}
+
+impl KillSignal {
+    fn kill(&self) {
+        self.token.cancel();
+        self.notify.notify_one();
+    }
+
+    /// Returns once the client is killed. Only the connection of the
+    /// client waits on it.
+    pub async fn killed(&self) {
+        while !self.token.is_cancelled() {
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Kill signals of the connected clients, by client id.
+fn registry() -> &'static Mutex<HashMap<u64, KillSignal>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<u64, KillSignal>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Kills the client with the given id (CLIENT KILL): the command it is
+/// running is aborted at its next cancellation point and its connection is
+/// closed, even while it waits for a command. Returns false if there is no
+/// such client.
+pub fn kill_client(id: u64) -> bool {
+    let registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    match registry.get(&id) {
+        Some(signal) => {
+            signal.kill();
+            true
+        }
+        None => false,
+    }
+}
+
+/// The classes commands are scheduled by when they wait for a shared
+/// execution slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandClass {
+    /// Applying the writes of a replication source
+    Replication,
+    /// Administrative commands
+    Admin,
+    /// Latency sensitive commands on a few keys
+    Point,
+    /// Scans and other commands that go over many keys
+    Bulk,
+}
+
+impl CommandClass {
+    pub const ALL: [CommandClass; 4] = [
+        CommandClass::Replication,
+        CommandClass::Admin,
+        CommandClass::Point,
+        CommandClass::Bulk,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CommandClass::Replication => "replication",
+            CommandClass::Admin => "admin",
+            CommandClass::Point => "point",
+            CommandClass::Bulk => "bulk",
+        }
+    }
+}
+
+/// Queueing of the commands for the shared execution slots, updated by the
+/// scheduler of the connections and reported by INFO.
+#[derive(Debug, Default)]
+pub struct CommandQueueStats {
+    // Commands waiting for a slot, by class
+    queued: [AtomicU64; CommandClass::ALL.len()],
+    // Commands holding a slot
+    inflight: AtomicU64,
+    // Commands that had to wait for a slot, and their total wait
+    waits: AtomicU64,
+    wait_micros: AtomicU64,
+}
+
+impl CommandQueueStats {
+    /// A command of `class` starts waiting for a slot.
+    pub fn enqueue(&self, class: CommandClass) {
+        self.queued[class as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A command of `class` stopped waiting for a slot after `waited`.
+    pub fn dequeue(&self, class: CommandClass, waited: Duration) {
+        self.queued[class as usize].fetch_sub(1, Ordering::Relaxed);
+        self.waits.fetch_add(1, Ordering::Relaxed);
+        self.wait_micros
+            .fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// A command took a slot.
+    pub fn start(&self) {
+        self.inflight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A command released its slot.
+    pub fn finish(&self) {
+        self.inflight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// The number of commands of `class` waiting for a slot.
+    pub fn class_queue_len(&self, class: CommandClass) -> u64 {
+        self.queued[class as usize].load(Ordering::Relaxed)
+    }
+
+    /// The number of commands waiting for a slot.
+    pub fn queue_len(&self) -> u64 {
+        CommandClass::ALL
+            .into_iter()
+            .map(|class| self.class_queue_len(class))
+            .sum()
+    }
+
+    /// The number of commands holding a slot.
+    pub fn inflight(&self) -> u64 {
+        self.inflight.load(Ordering::Relaxed)
+    }
+
+    /// The INFO section of the stats.
+    pub fn info(&self) -> String {
+        let mut info = format!(
+            "# CommandQueue\r\ncommand_queue_len:{}\r\ncommand_inflight:{}\r\ncommand_queue_waits:{}\r\ncommand_queue_wait_us:{}\r\n",
+            self.queue_len(),
+            self.inflight(),
+            self.waits.load(Ordering::Relaxed),
+            self.wait_micros.load(Ordering::Relaxed),
+        );
+        for class in CommandClass::ALL {
+            info += &format!(
+                "command_queue_len_{}:{}\r\n",
+                class.name(),
+                self.class_queue_len(class)
+            );
+        }
+        info
+    }
+}
+
+/// The command queue stats of the process.
+pub fn command_queue_stats() -> &'static CommandQueueStats {
+    static STATS: OnceLock<CommandQueueStats> = OnceLock::new();
+    STATS.get_or_init(CommandQueueStats::default)
+}
 
 #[async_trait]
 pub trait StreamTrait: Send + Sync {
@@ -27,6 +191,7 @@ pub trait StreamTrait: Send + Sync {
 }
 
 pub struct Client {
+    id: u64,
     stream: Box<dyn StreamTrait>,
     // TODO: use &[Vec<u8>], need lifetime.
     argv: Vec<Vec<u8>>,
@@ -35,20 +200,72 @@ pub struct Client {
     cmd_name: Vec<u8>,
     key: Vec<u8>,
     reply: RespData,
+    // Cancelled by CLIENT KILL.
+    kill_signal: KillSignal,
+    // Token of the running command: kill_token plus the command deadline.
+    cancel_token: CancelToken,
+    // Database selected with SELECT.
+    db_index: usize,
 }
 
 impl Client {
     pub fn new(stream: Box<dyn StreamTrait>) -> Self {
+        let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+        let kill_signal = KillSignal::default();
+        registry()
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, kill_signal.clone());
+
         Self {
+            id,
             stream,
             argv: Vec::default(),
             name: Vec::default(),
             cmd_name: Vec::default(),
             key: Vec::default(),
             reply: RespData::default(),
+            cancel_token: kill_signal.token.clone(),
+            kill_signal,
+            db_index: 0,
         }
     }
 
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Token of the running command, cancelled when this client is killed or
+    /// when the command deadline passes. To be polled by long running commands.
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel_token
+    }
+
+    /// Sets the deadline of the next command, `None` for no deadline.
+    pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+        self.cancel_token = match timeout {
+            Some(timeout) => self.kill_signal.token.with_timeout(timeout),
+            None => self.kill_signal.token.clone(),
+        };
+    }
+
+    pub fn db_index(&self) -> usize {
+        self.db_index
+    }
+
+    pub fn set_db_index(&mut self, db_index: usize) {
+        self.db_index = db_index
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.kill_signal.token.is_cancelled()
+    }
+
+    /// Signal of CLIENT KILL, to wait on while the connection is idle.
+    pub fn kill_signal(&self) -> KillSignal {
+        self.kill_signal.clone()
+    }
+
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
         self.stream.read(buf).await
     }
@@ -97,3 +314,12 @@ impl Client {
         std::mem::take(&mut self.reply)
     }
 }
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        registry()
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
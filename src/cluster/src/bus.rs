@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! TCP transport of the cluster bus.
+//!
+//! Messages are framed with a 4-byte little-endian length. Outgoing messages
+//! are sent over short-lived connections, which keeps the bus stateless: a
+//! node that restarts or changes its address is simply reached on the next
+//! tick.
+
+use crate::error::{InvalidMessageSnafu, IoSnafu, Result};
+use crate::gossip::{now_millis, ClusterState, Outgoing};
+use crate::message::GossipMessage;
+use log::{debug, warn};
+use snafu::{ensure, ResultExt};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const CRON_INTERVAL_MS: u64 = 100;
+const MAX_FRAME_SIZE: usize = 1 << 20;
+
+pub struct ClusterBus {
+    listen_addr: String,
+    state: Arc<Mutex<ClusterState>>,
+}
+
+impl ClusterBus {
+    pub fn new(listen_addr: impl Into<String>, state: ClusterState) -> Self {
+        Self {
+            listen_addr: listen_addr.into(),
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn state(&self) -> Arc<Mutex<ClusterState>> {
+        self.state.clone()
+    }
+
+    /// Sends a MEET to `addr` in the background, the node is added once it
+    /// answers. Must be called within the tokio runtime.
+    pub fn meet(&self, addr: &str) {
+        let outgoing = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.meet(addr, now_millis())
+        };
+        dispatch(vec![outgoing]);
+    }
+
+    /// Accepts bus connections and drives the gossip cron until an IO error
+    /// occurs on the listener.
+    pub async fn run(&self) -> Result<()> {
+        let listener = TcpListener::bind(&self.listen_addr)
+            .await
+            .context(IoSnafu)?;
+
+        let cron_state = self.state.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_millis(CRON_INTERVAL_MS));
+            loop {
+                ticker.tick().await;
+                let outgoing = {
+                    let mut state = cron_state.lock().unwrap_or_else(|e| e.into_inner());
+                    state.cron(now_millis())
+                };
+                dispatch(outgoing);
+            }
+        });
+
+        loop {
+            let (socket, peer) = listener.accept().await.context(IoSnafu)?;
+            let state = self.state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(socket, state).await {
+                    warn!("cluster bus: connection from {peer} closed: {e}");
+                }
+            });
+        }
+    }
+}
+
+/// Makes `bus` the cluster bus of the process, the one the CLUSTER
+/// commands act on. Returns false if there already is one.
+pub fn install_cluster_bus(bus: Arc<ClusterBus>) -> bool {
+    cluster_bus_handle().set(bus).is_ok()
+}
+
+/// The cluster bus of the process, None unless cluster mode is enabled.
+pub fn cluster_bus() -> Option<&'static Arc<ClusterBus>> {
+    cluster_bus_handle().get()
+}
+
+fn cluster_bus_handle() -> &'static OnceLock<Arc<ClusterBus>> {
+    static BUS: OnceLock<Arc<ClusterBus>> = OnceLock::new();
+    &BUS
+}
+
+async fn handle_connection(mut socket: TcpStream, state: Arc<Mutex<ClusterState>>) -> Result<()> {
+    while let Some(frame) = read_frame(&mut socket).await? {
+        let message = GossipMessage::decode(&frame)?;
+        debug!(
+            "cluster bus: received {:?} from {}",
+            message.msg_type, message.sender
+        );
+        let outgoing = {
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            state.handle_message(&message, now_millis())
+        };
+        dispatch(outgoing);
+    }
+    Ok(())
+}
+
+fn dispatch(outgoing: Vec<Outgoing>) {
+    for out in outgoing {
+        tokio::spawn(async move {
+            if let Err(e) = send_message(&out.addr, &out.message).await {
+                debug!("cluster bus: failed to send to {}: {e}", out.addr);
+            }
+        });
+    }
+}
+
+pub async fn send_message(addr: &str, message: &GossipMessage) -> Result<()> {
+    let mut stream = TcpStream::connect(addr).await.context(IoSnafu)?;
+    let payload = message.encode();
+    stream
+        .write_all(&(payload.len() as u32).to_le_bytes())
+        .await
+        .context(IoSnafu)?;
+    stream.write_all(&payload).await.context(IoSnafu)?;
+    stream.shutdown().await.context(IoSnafu)?;
+    Ok(())
+}
+
+/// Reads one frame, returns `None` when the peer closed the connection.
+async fn read_frame(socket: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    match socket.read_exact(&mut len_buf).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).context(IoSnafu),
+    }
+
+    let len = u32::from_le_bytes(len_buf) as usize;
+    ensure!(
+        len <= MAX_FRAME_SIZE,
+        InvalidMessageSnafu {
+            message: format!("frame of {len} bytes exceeds {MAX_FRAME_SIZE}"),
+        }
+    );
+
+    let mut frame = vec![0u8; len];
+    socket.read_exact(&mut frame).await.context(IoSnafu)?;
+    Ok(Some(frame))
+}
@@ -0,0 +1,578 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Replica failover.
+//!
+//! When a master is flagged FAIL its replicas run an election: after a delay
+//! that favours the replica with the most replicated data, a replica bumps the
+//! current epoch and asks every master for its vote. A master grants at most
+//! one vote per epoch, and only to a replica of a master it considers failed.
+//! The replica that collects the votes of a majority of the masters promotes
+//! itself, takes the election epoch as its config epoch and broadcasts the new
+//! configuration with an UPDATE message.
+//!
+//! The old master may come back after the failover, still believing it is a
+//! master. Nodes remember which masters were replaced and at what config
+//! epoch, and answer every message such a stale master sends with an UPDATE
+//! that demotes it to a replica of its successor.
+
+use crate::error::{InvalidOperationSnafu, Result, UnknownNodeSnafu};
+use crate::gossip::{ClusterState, NodeHealth, NodeRole, Outgoing};
+use crate::message::{ConfigUpdate, GossipMessage, MessageType};
+use log::{info, warn};
+use snafu::{ensure, OptionExt};
+use std::collections::{HashMap, HashSet};
+
+/// Fixed delay before an election starts, so the FAIL state can propagate.
+const FAILOVER_DELAY_MS: u64 = 500;
+/// Extra delay per replica of the same master with a better replication offset.
+const FAILOVER_RANK_DELAY_MS: u64 = 1000;
+/// A master doesn't vote twice for the same failed master within
+/// `node_timeout * VOTE_TIMEOUT_MULT`, and an election lasts that long.
+const VOTE_TIMEOUT_MULT: u64 = 2;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Election {
+    /// When the auth request may be sent.
+    scheduled: u64,
+    /// When the auth request was sent, 0 while waiting for `scheduled`.
+    started: u64,
+    epoch: u64,
+    votes: HashSet<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FailoverState {
+    election: Option<Election>,
+    last_vote_epoch: u64,
+    /// Last time we voted for a replica of the given master.
+    voted_time: HashMap<String, u64>,
+    /// Replaced master -> (new master, config epoch of the failover).
+    replaced: HashMap<String, (String, u64)>,
+}
+
+impl ClusterState {
+    /// Makes this node a replica of `master_id` (CLUSTER REPLICATE).
+    pub fn replicate(&mut self, master_id: &str) -> Result<()> {
+        ensure!(
+            master_id != self.myself.id,
+            InvalidOperationSnafu {
+                message: "can't replicate myself".to_string(),
+            }
+        );
+        let master = self
+            .nodes
+            .get(master_id)
+            .context(UnknownNodeSnafu { id: master_id })?;
+        ensure!(
+            master.role == NodeRole::Master,
+            InvalidOperationSnafu {
+                message: format!("node {master_id} is not a master"),
+            }
+        );
+
+        info!("cluster: replicate {master_id}");
+        self.myself.role = NodeRole::Replica;
+        self.myself.master_id = Some(master_id.to_string());
+        self.failover.election = None;
+        Ok(())
+    }
+
+    /// Records how much of the master's data this node replicated.
+    pub fn set_repl_offset(&mut self, offset: u64) {
+        self.myself.repl_offset = offset;
+    }
+
+    /// Returns the node that took over `id` in a failover, if any.
+    pub fn replaced_by(&self, id: &str) -> Option<&str> {
+        self.failover
+            .replaced
+            .get(id)
+            .map(|(master, _)| master.as_str())
+    }
+
+    /// Schedules, starts or retries the election when our master is FAIL.
+    pub(crate) fn failover_cron(&mut self, now: u64) -> Vec<Outgoing> {
+        let master_id = match (&self.myself.role, &self.myself.master_id) {
+            (NodeRole::Replica, Some(master_id)) => master_id.clone(),
+            _ => {
+                self.failover.election = None;
+                return Vec::new();
+            }
+        };
+        let master_failed = self
+            .nodes
+            .get(&master_id)
+            .is_some_and(|master| master.health == NodeHealth::Fail);
+        if !master_failed {
+            self.failover.election = None;
+            return Vec::new();
+        }
+
+        let election_timeout = self.config.node_timeout_ms * VOTE_TIMEOUT_MULT;
+        let expired = match &self.failover.election {
+            None => true,
+            Some(election) => {
+                election.started != 0 && now.saturating_sub(election.started) > election_timeout
+            }
+        };
+        if expired {
+            let rank = self.replica_rank(&master_id);
+            let scheduled = now + FAILOVER_DELAY_MS + rank * FAILOVER_RANK_DELAY_MS;
+            info!("cluster: master {master_id} failed, election scheduled at {scheduled} (rank {rank})");
+            self.failover.election = Some(Election {
+                scheduled,
+                started: 0,
+                epoch: 0,
+                votes: HashSet::new(),
+            });
+            return Vec::new();
+        }
+
+        let Some(election) = self.failover.election.as_mut() else {
+            return Vec::new();
+        };
+        if election.started != 0 || now < election.scheduled {
+            return Vec::new();
+        }
+        self.current_epoch += 1;
+        election.started = now;
+        election.epoch = self.current_epoch;
+        info!(
+            "cluster: start election for master {master_id} at epoch {}",
+            election.epoch
+        );
+
+        let request = self.build_message(MessageType::FailoverAuthRequest, None);
+        self.nodes
+            .values()
+            .filter(|n| n.role == NodeRole::Master && n.id != master_id)
+            .map(|n| Outgoing {
+                addr: n.addr.clone(),
+                message: request.clone(),
+            })
+            .collect()
+    }
+
+    /// Number of replicas of `master_id` that are better candidates than us.
+    fn replica_rank(&self, master_id: &str) -> u64 {
+        let me = &self.myself;
+        self.nodes
+            .values()
+            .filter(|n| n.role == NodeRole::Replica && n.master_id.as_deref() == Some(master_id))
+            .filter(|n| {
+                n.repl_offset > me.repl_offset || (n.repl_offset == me.repl_offset && n.id < me.id)
+            })
+            .count() as u64
+    }
+
+    /// Votes for the replica that sent `msg` if it is allowed to take over its
+    /// master. Denied requests are not answered.
+    pub(crate) fn handle_auth_request(
+        &mut self,
+        msg: &GossipMessage,
+        now: u64,
+    ) -> Option<Outgoing> {
+        if self.myself.role != NodeRole::Master || msg.sender_role != NodeRole::Replica {
+            return None;
+        }
+        let master_id = msg.sender_master.as_deref()?;
+        if msg.current_epoch < self.current_epoch
+            || self.failover.last_vote_epoch == self.current_epoch
+        {
+            return None;
+        }
+        let master_failed = self
+            .nodes
+            .get(master_id)
+            .is_some_and(|master| master.health == NodeHealth::Fail);
+        if !master_failed {
+            return None;
+        }
+        let vote_timeout = self.config.node_timeout_ms * VOTE_TIMEOUT_MULT;
+        if let Some(voted) = self.failover.voted_time.get(master_id) {
+            if now.saturating_sub(*voted) < vote_timeout {
+                return None;
+            }
+        }
+
+        info!(
+            "cluster: vote for {} to replace {master_id} at epoch {}",
+            msg.sender, self.current_epoch
+        );
+        self.failover.last_vote_epoch = self.current_epoch;
+        self.failover.voted_time.insert(master_id.to_string(), now);
+        Some(Outgoing {
+            addr: msg.sender_addr.clone(),
+            message: self.build_message(MessageType::FailoverAuthAck, Some(&msg.sender)),
+        })
+    }
+
+    /// Counts a vote and promotes this node once a majority of masters agreed.
+    pub(crate) fn handle_auth_ack(&mut self, msg: &GossipMessage) -> Vec<Outgoing> {
+        let Some(election) = self.failover.election.as_mut() else {
+            return Vec::new();
+        };
+        if election.started == 0
+            || msg.sender_role != NodeRole::Master
+            || msg.current_epoch < election.epoch
+        {
+            return Vec::new();
+        }
+        election.votes.insert(msg.sender.clone());
+        let votes = election.votes.len();
+        let epoch = election.epoch;
+
+        if votes < self.failure_quorum() {
+            return Vec::new();
+        }
+        self.promote_myself(epoch, votes)
+    }
+
+    fn promote_myself(&mut self, epoch: u64, votes: usize) -> Vec<Outgoing> {
+        let Some(old_master) = self.myself.master_id.take() else {
+            return Vec::new();
+        };
+        warn!("cluster: won the election with {votes} votes, replacing master {old_master} at epoch {epoch}");
+        self.myself.role = NodeRole::Master;
+        self.myself.config_epoch = epoch;
+        self.failover.election = None;
+
+        let update = ConfigUpdate {
+            master: self.myself.id.clone(),
+            replaced: old_master,
+            config_epoch: epoch,
+        };
+        self.apply_update(&update);
+
+        let mut message = self.build_message(MessageType::Update, None);
+        message.update = Some(update);
+        message.gossip.clear();
+        self.nodes
+            .values()
+            .map(|n| Outgoing {
+                addr: n.addr.clone(),
+                message: message.clone(),
+            })
+            .collect()
+    }
+
+    /// Applies the outcome of a failover to the node table.
+    pub(crate) fn apply_update(&mut self, update: &ConfigUpdate) {
+        if let Some((_, epoch)) = self.failover.replaced.get(&update.replaced) {
+            if *epoch >= update.config_epoch {
+                return;
+            }
+        }
+        if update.replaced == self.myself.id {
+            if self.myself.config_epoch >= update.config_epoch {
+                return;
+            }
+            warn!(
+                "cluster: replaced by {} at epoch {}, becoming its replica",
+                update.master, update.config_epoch
+            );
+            self.myself.role = NodeRole::Replica;
+            self.myself.master_id = Some(update.master.clone());
+        } else if let Some(old) = self.nodes.get_mut(&update.replaced) {
+            old.role = NodeRole::Replica;
+            old.master_id = Some(update.master.clone());
+        }
+        self.failover.replaced.insert(
+            update.replaced.clone(),
+            (update.master.clone(), update.config_epoch),
+        );
+
+        if let Some(master) = self.nodes.get_mut(&update.master) {
+            master.role = NodeRole::Master;
+            master.master_id = None;
+            master.config_epoch = master.config_epoch.max(update.config_epoch);
+        }
+        if self.myself.master_id.as_deref() == Some(update.replaced.as_str()) {
+            info!("cluster: follow new master {}", update.master);
+            self.myself.master_id = Some(update.master.clone());
+            self.failover.election = None;
+        }
+        for node in self.nodes.values_mut() {
+            if node.id != update.master
+                && node.master_id.as_deref() == Some(update.replaced.as_str())
+            {
+                node.master_id = Some(update.master.clone());
+            }
+        }
+    }
+
+    /// Answers a master that was replaced by a failover it doesn't know about
+    /// with the UPDATE that demotes it.
+    pub(crate) fn fence_stale_master(&mut self, msg: &GossipMessage) -> Option<Outgoing> {
+        if msg.sender_role != NodeRole::Master {
+            return None;
+        }
+        let (master, epoch) = self.failover.replaced.get(&msg.sender)?;
+        if *epoch <= msg.config_epoch {
+            return None;
+        }
+        let update = ConfigUpdate {
+            master: master.clone(),
+            replaced: msg.sender.clone(),
+            config_epoch: *epoch,
+        };
+
+        let mut message = self.build_message(MessageType::Update, Some(&msg.sender));
+        message.update = Some(update);
+        message.gossip.clear();
+        Some(Outgoing {
+            addr: msg.sender_addr.clone(),
+            message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gossip::ClusterConfig;
+    use std::collections::{BTreeMap, VecDeque};
+
+    const TIMEOUT: u64 = 1000;
+    const STEP: u64 = 100;
+
+    /// A simulated network of nodes that can be partitioned.
+    struct Network {
+        nodes: BTreeMap<String, ClusterState>,
+        blocked: HashSet<(String, String)>,
+        now: u64,
+    }
+
+    fn id_of(addr: &str) -> String {
+        addr.split(':').next().unwrap_or_default().to_string()
+    }
+
+    impl Network {
+        fn new(ids: &[&str]) -> Self {
+            let mut net = Self {
+                nodes: BTreeMap::new(),
+                blocked: HashSet::new(),
+                now: 1,
+            };
+            for id in ids {
+                let config = ClusterConfig {
+                    node_timeout_ms: TIMEOUT,
+                    gossip_fanout: 3,
+                };
+                net.nodes.insert(
+                    id.to_string(),
+                    ClusterState::new(*id, format!("{id}:17000"), config),
+                );
+            }
+            for (i, a) in ids.iter().enumerate() {
+                for b in &ids[i + 1..] {
+                    let now = net.now;
+                    let meet = net.node_mut(a).meet(&format!("{b}:17000"), now);
+                    net.deliver(a, vec![meet]);
+                }
+            }
+            net
+        }
+
+        fn node(&self, id: &str) -> &ClusterState {
+            &self.nodes[id]
+        }
+
+        fn node_mut(&mut self, id: &str) -> &mut ClusterState {
+            self.nodes.get_mut(id).unwrap()
+        }
+
+        /// Cuts every link between `side` and the rest of the nodes.
+        fn partition(&mut self, side: &[&str]) {
+            let ids: Vec<String> = self.nodes.keys().cloned().collect();
+            for a in side {
+                for b in ids.iter().filter(|b| !side.contains(&b.as_str())) {
+                    self.blocked.insert((a.to_string(), b.clone()));
+                    self.blocked.insert((b.clone(), a.to_string()));
+                }
+            }
+        }
+
+        fn heal(&mut self) {
+            self.blocked.clear();
+        }
+
+        fn deliver(&mut self, from: &str, out: Vec<Outgoing>) {
+            let mut queue: VecDeque<(String, Outgoing)> =
+                out.into_iter().map(|o| (from.to_string(), o)).collect();
+            while let Some((from, o)) = queue.pop_front() {
+                let to = id_of(&o.addr);
+                if self.blocked.contains(&(from.clone(), to.clone())) {
+                    continue;
+                }
+                let now = self.now;
+                let Some(node) = self.nodes.get_mut(&to) else {
+                    continue;
+                };
+                for reply in node.handle_message(&o.message, now) {
+                    queue.push_back((to.clone(), reply));
+                }
+            }
+        }
+
+        fn run_for(&mut self, ms: u64) {
+            for _ in 0..ms / STEP {
+                self.now += STEP;
+                let ids: Vec<String> = self.nodes.keys().cloned().collect();
+                for id in ids {
+                    let now = self.now;
+                    let out = self.node_mut(&id).cron(now);
+                    self.deliver(&id, out);
+                }
+            }
+        }
+
+        /// Masters a, b, c, plus the given replicas of a.
+        fn with_replicas(replicas: &[&str]) -> Self {
+            let mut ids = vec!["a", "b", "c"];
+            ids.extend_from_slice(replicas);
+            let mut net = Self::new(&ids);
+            for r in replicas {
+                net.node_mut(r).replicate("a").unwrap();
+            }
+            net.run_for(TIMEOUT);
+            net
+        }
+    }
+
+    #[test]
+    fn test_replicate_validation() {
+        let mut net = Network::new(&["a", "b"]);
+        assert!(net.node_mut("a").replicate("a").is_err());
+        assert!(net.node_mut("a").replicate("nope").is_err());
+        net.node_mut("b").replicate("a").unwrap();
+        net.run_for(TIMEOUT);
+        assert_eq!(net.node("a").node("b").unwrap().role, NodeRole::Replica);
+        assert!(net.node_mut("a").replicate("b").is_err());
+    }
+
+    #[test]
+    fn test_replica_promoted_when_master_fails() {
+        let mut net = Network::with_replicas(&["r"]);
+        net.partition(&["a"]);
+        net.run_for(10 * TIMEOUT);
+
+        let r = net.node("r").myself();
+        assert_eq!(r.role, NodeRole::Master);
+        assert!(r.config_epoch > 0);
+        assert!(r.master_id.is_none());
+        for id in ["b", "c"] {
+            let view = net.node(id);
+            assert_eq!(view.node("r").unwrap().role, NodeRole::Master);
+            assert_eq!(view.node("a").unwrap().role, NodeRole::Replica);
+            assert_eq!(view.replaced_by("a"), Some("r"));
+        }
+    }
+
+    #[test]
+    fn test_old_master_fenced_on_rejoin() {
+        let mut net = Network::with_replicas(&["r"]);
+        net.partition(&["a"]);
+        net.run_for(10 * TIMEOUT);
+        assert_eq!(net.node("a").myself().role, NodeRole::Master);
+
+        net.heal();
+        net.run_for(5 * TIMEOUT);
+
+        let a = net.node("a").myself();
+        assert_eq!(a.role, NodeRole::Replica);
+        assert_eq!(a.master_id.as_deref(), Some("r"));
+        for id in ["b", "c", "r"] {
+            let old = net.node(id).node("a").unwrap();
+            assert_eq!(old.role, NodeRole::Replica);
+            assert_eq!(old.health, NodeHealth::Online);
+        }
+        assert_eq!(net.node("r").myself().role, NodeRole::Master);
+    }
+
+    #[test]
+    fn test_no_failover_without_majority() {
+        let mut net = Network::with_replicas(&["r"]);
+        // c and r only see one master out of three: a and b are never FAIL.
+        net.partition(&["a", "b"]);
+        net.run_for(10 * TIMEOUT);
+
+        assert_eq!(net.node("r").myself().role, NodeRole::Replica);
+        assert_eq!(net.node("r").myself().master_id.as_deref(), Some("a"));
+        assert_ne!(net.node("r").node("a").unwrap().health, NodeHealth::Fail);
+        assert_eq!(net.node("a").myself().role, NodeRole::Master);
+        assert_eq!(net.node("a").current_epoch(), 0);
+    }
+
+    #[test]
+    fn test_best_replica_wins_and_others_follow() {
+        let mut net = Network::new(&["a", "b", "c", "r1", "r2"]);
+        for r in ["r1", "r2"] {
+            net.node_mut(r).replicate("a").unwrap();
+        }
+        net.node_mut("r1").set_repl_offset(50);
+        net.node_mut("r2").set_repl_offset(100);
+        net.run_for(TIMEOUT);
+
+        net.partition(&["a"]);
+        net.run_for(10 * TIMEOUT);
+
+        assert_eq!(net.node("r2").myself().role, NodeRole::Master);
+        let r1 = net.node("r1").myself();
+        assert_eq!(r1.role, NodeRole::Replica);
+        assert_eq!(r1.master_id.as_deref(), Some("r2"));
+        // Exactly one election took place.
+        assert_eq!(net.node("b").current_epoch(), 1);
+    }
+
+    #[test]
+    fn test_one_vote_per_epoch() {
+        let mut net = Network::with_replicas(&["r1", "r2"]);
+        net.partition(&["a", "r1", "r2"]);
+        // Let b and c agree that a failed without running any election.
+        net.partition(&["r1"]);
+        net.partition(&["r2"]);
+        net.run_for(5 * TIMEOUT);
+        assert_eq!(net.node("b").node("a").unwrap().health, NodeHealth::Fail);
+
+        let now = net.now;
+        let b = net.node_mut("b");
+        let epoch = b.current_epoch() + 1;
+        let mut request_1 = b.build_message(MessageType::FailoverAuthRequest, None);
+        request_1.sender = "r1".to_string();
+        request_1.sender_addr = "r1:17000".to_string();
+        request_1.sender_role = NodeRole::Replica;
+        request_1.sender_master = Some("a".to_string());
+        request_1.current_epoch = epoch;
+        request_1.gossip.clear();
+        let mut request_2 = request_1.clone();
+        request_2.sender = "r2".to_string();
+        request_2.sender_addr = "r2:17000".to_string();
+
+        let granted_1 = b.handle_message(&request_1, now);
+        assert!(granted_1
+            .iter()
+            .any(|o| o.message.msg_type == MessageType::FailoverAuthAck && o.addr == "r1:17000"));
+        let granted_2 = b.handle_message(&request_2, now);
+        assert!(!granted_2
+            .iter()
+            .any(|o| o.message.msg_type == MessageType::FailoverAuthAck));
+    }
+}
@@ -0,0 +1,840 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Gossip based node table and failure detection.
+//!
+//! Every node periodically pings the other nodes it knows and piggybacks a
+//! few entries of its own node table on each message. A node that doesn't
+//! answer a ping within `node_timeout_ms` is flagged PFAIL (possibly failing)
+//! locally. PFAIL flags are gossiped, and once a majority of the masters
+//! report the same node as failing it is promoted to FAIL and the decision is
+//! broadcast to the whole cluster.
+//!
+//! All methods take the current time in milliseconds explicitly so that the
+//! state machine is deterministic and can be driven by tests.
+
+use crate::error::{Error, InvalidMessageSnafu, Result};
+use crate::failover::FailoverState;
+use crate::message::{GossipEntry, GossipMessage, MessageType};
+use log::{info, warn};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Failure reports older than `node_timeout * FAIL_REPORT_VALIDITY_MULT` are discarded.
+const FAIL_REPORT_VALIDITY_MULT: u64 = 2;
+/// A failed master is cleared after `node_timeout * FAIL_UNDO_TIME_MULT` if it is reachable again.
+const FAIL_UNDO_TIME_MULT: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeRole {
+    Master = 0,
+    Replica = 1,
+}
+
+impl TryFrom<u8> for NodeRole {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(NodeRole::Master),
+            1 => Ok(NodeRole::Replica),
+            _ => InvalidMessageSnafu {
+                message: format!("Invalid node role byte: {value}"),
+            }
+            .fail(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeHealth {
+    Online = 0,
+    /// Unreachable from this node's point of view.
+    PFail = 1,
+    /// Unreachable according to a majority of the masters.
+    Fail = 2,
+}
+
+impl TryFrom<u8> for NodeHealth {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(NodeHealth::Online),
+            1 => Ok(NodeHealth::PFail),
+            2 => Ok(NodeHealth::Fail),
+            _ => InvalidMessageSnafu {
+                message: format!("Invalid node health byte: {value}"),
+            }
+            .fail(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    /// Milliseconds a node may stay unreachable before it is flagged PFAIL.
+    pub node_timeout_ms: u64,
+    /// Number of healthy nodes described in every ping, besides the failing ones.
+    pub gossip_fanout: usize,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            node_timeout_ms: 15_000,
+            gossip_fanout: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterNode {
+    pub id: String,
+    pub addr: String,
+    pub role: NodeRole,
+    pub master_id: Option<String>,
+    pub health: NodeHealth,
+    pub config_epoch: u64,
+    pub repl_offset: u64,
+    /// When the pending ping was sent, 0 if no ping is waiting for a pong.
+    pub ping_sent: u64,
+    pub pong_received: u64,
+    pub fail_time: u64,
+    /// Masters that reported this node as failing, with the report time.
+    fail_reports: HashMap<String, u64>,
+}
+
+impl ClusterNode {
+    pub fn new(id: impl Into<String>, addr: impl Into<String>, role: NodeRole) -> Self {
+        Self {
+            id: id.into(),
+            addr: addr.into(),
+            role,
+            master_id: None,
+            health: NodeHealth::Online,
+            config_epoch: 0,
+            repl_offset: 0,
+            ping_sent: 0,
+            pong_received: 0,
+            fail_time: 0,
+            fail_reports: HashMap::new(),
+        }
+    }
+
+    pub fn fail_report_count(&self) -> usize {
+        self.fail_reports.len()
+    }
+
+    fn clean_fail_reports(&mut self, now: u64, validity: u64) {
+        self.fail_reports
+            .retain(|_, reported| now.saturating_sub(*reported) <= validity);
+    }
+
+    fn to_gossip_entry(&self) -> GossipEntry {
+        GossipEntry {
+            id: self.id.clone(),
+            addr: self.addr.clone(),
+            role: self.role,
+            health: self.health,
+            pong_received: self.pong_received,
+        }
+    }
+}
+
+/// A message produced by the state machine that must be delivered to `addr`.
+#[derive(Debug, Clone)]
+pub struct Outgoing {
+    pub addr: String,
+    pub message: GossipMessage,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Handshake {
+    started: u64,
+    meet_sent: bool,
+}
+
+pub struct ClusterState {
+    pub(crate) config: ClusterConfig,
+    pub(crate) myself: ClusterNode,
+    pub(crate) nodes: HashMap<String, ClusterNode>,
+    /// Addresses we want to MEET but don't know the node id of yet.
+    handshakes: HashMap<String, Handshake>,
+    pub(crate) current_epoch: u64,
+    pub(crate) failover: FailoverState,
+    gossip_cursor: usize,
+}
+
+impl ClusterState {
+    pub fn new(id: impl Into<String>, addr: impl Into<String>, config: ClusterConfig) -> Self {
+        Self {
+            config,
+            myself: ClusterNode::new(id, addr, NodeRole::Master),
+            nodes: HashMap::new(),
+            handshakes: HashMap::new(),
+            current_epoch: 0,
+            failover: FailoverState::default(),
+            gossip_cursor: 0,
+        }
+    }
+
+    pub fn config(&self) -> &ClusterConfig {
+        &self.config
+    }
+
+    pub fn myself(&self) -> &ClusterNode {
+        &self.myself
+    }
+
+    pub fn node(&self, id: &str) -> Option<&ClusterNode> {
+        self.nodes.get(id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &ClusterNode> {
+        self.nodes.values()
+    }
+
+    pub fn current_epoch(&self) -> u64 {
+        self.current_epoch
+    }
+
+    /// The node table in the format of CLUSTER NODES, one line per node,
+    /// this node first:
+    /// `<id> <addr> <flags> <master> <ping sent> <pong received> <config epoch> <link>`
+    pub fn describe_nodes(&self) -> String {
+        let mut nodes: Vec<&ClusterNode> = self.nodes.values().collect();
+        nodes.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+        let mut description = String::new();
+        for node in std::iter::once(&self.myself).chain(nodes) {
+            let mut flags = Vec::new();
+            if node.id == self.myself.id {
+                flags.push("myself");
+            }
+            flags.push(match node.role {
+                NodeRole::Master => "master",
+                NodeRole::Replica => "slave",
+            });
+            match node.health {
+                NodeHealth::Online => {}
+                NodeHealth::PFail => flags.push("fail?"),
+                NodeHealth::Fail => flags.push("fail"),
+            }
+            let link = if node.health == NodeHealth::Online {
+                "connected"
+            } else {
+                "disconnected"
+            };
+            description += &format!(
+                "{} {} {} {} {} {} {} {link}\n",
+                node.id,
+                node.addr,
+                flags.join(","),
+                node.master_id.as_deref().unwrap_or("-"),
+                node.ping_sent,
+                node.pong_received,
+                node.config_epoch,
+            );
+        }
+        description
+    }
+
+    /// Starts a handshake with the node listening on `addr` (CLUSTER MEET).
+    /// Returns the MEET message that must be sent to it.
+    pub fn meet(&mut self, addr: &str, now: u64) -> Outgoing {
+        self.handshakes.insert(
+            addr.to_string(),
+            Handshake {
+                started: now,
+                meet_sent: true,
+            },
+        );
+        Outgoing {
+            addr: addr.to_string(),
+            message: self.build_message(MessageType::Meet, None),
+        }
+    }
+
+    /// Forgets a node (CLUSTER FORGET).
+    pub fn forget(&mut self, id: &str) -> Result<()> {
+        match self.nodes.remove(id) {
+            Some(_) => Ok(()),
+            None => Err(Error::UnknownNode { id: id.to_string() }),
+        }
+    }
+
+    /// Processes a message received from the bus and returns the messages to
+    /// send in response.
+    pub fn handle_message(&mut self, msg: &GossipMessage, now: u64) -> Vec<Outgoing> {
+        let mut outgoing = Vec::new();
+        if msg.sender == self.myself.id {
+            return outgoing;
+        }
+
+        if msg.current_epoch > self.current_epoch {
+            self.current_epoch = msg.current_epoch;
+        }
+
+        if !self.nodes.contains_key(&msg.sender) {
+            let from_handshake = msg.msg_type == MessageType::Pong
+                && self.handshakes.remove(&msg.sender_addr).is_some();
+            if msg.msg_type != MessageType::Meet && !from_handshake {
+                // Only MEET and the PONG answering our own MEET may introduce a node.
+                return outgoing;
+            }
+            info!(
+                "cluster: add node {} ({}) to the node table",
+                msg.sender, msg.sender_addr
+            );
+            let mut node = ClusterNode::new(&msg.sender, &msg.sender_addr, msg.sender_role);
+            node.pong_received = now;
+            self.nodes.insert(msg.sender.clone(), node);
+            self.handshakes.remove(&msg.sender_addr);
+        }
+
+        // A master replaced by a failover keeps the role we gave it until it
+        // learns about the new configuration.
+        let fence = self.fence_stale_master(msg);
+        let fenced = fence.is_some();
+        outgoing.extend(fence);
+
+        if let Some(sender) = self.nodes.get_mut(&msg.sender) {
+            sender.addr = msg.sender_addr.clone();
+            if !fenced {
+                sender.role = msg.sender_role;
+                sender.master_id = msg.sender_master.clone();
+            }
+            sender.repl_offset = msg.repl_offset;
+            if msg.config_epoch > sender.config_epoch {
+                sender.config_epoch = msg.config_epoch;
+            }
+
+            if msg.msg_type == MessageType::Pong {
+                sender.pong_received = now;
+                sender.ping_sent = 0;
+                let fail_undo = self.config.node_timeout_ms * FAIL_UNDO_TIME_MULT;
+                match sender.health {
+                    NodeHealth::PFail => sender.health = NodeHealth::Online,
+                    NodeHealth::Fail
+                        if sender.role == NodeRole::Replica
+                            || now.saturating_sub(sender.fail_time) > fail_undo =>
+                    {
+                        info!("cluster: clear FAIL state of node {}", sender.id);
+                        sender.health = NodeHealth::Online;
+                        sender.fail_time = 0;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        match msg.msg_type {
+            MessageType::Meet | MessageType::Ping => {
+                outgoing.push(Outgoing {
+                    addr: msg.sender_addr.clone(),
+                    message: self.build_message(MessageType::Pong, Some(&msg.sender)),
+                });
+            }
+            MessageType::Pong => {}
+            MessageType::Fail => {
+                if let Some(failed) = &msg.fail_node {
+                    if let Some(node) = self.nodes.get_mut(failed) {
+                        if node.health != NodeHealth::Fail {
+                            warn!("cluster: node {failed} marked FAIL by {}", msg.sender);
+                            node.health = NodeHealth::Fail;
+                            node.fail_time = now;
+                        }
+                    }
+                }
+            }
+            MessageType::FailoverAuthRequest => {
+                outgoing.extend(self.handle_auth_request(msg, now));
+            }
+            MessageType::FailoverAuthAck => {
+                outgoing.extend(self.handle_auth_ack(msg));
+            }
+            MessageType::Update => {
+                if let Some(update) = &msg.update {
+                    self.apply_update(update);
+                }
+            }
+        }
+
+        for failed in self.process_gossip(msg, now) {
+            outgoing.extend(self.broadcast_fail(&failed));
+        }
+
+        outgoing
+    }
+
+    /// Periodic work: detects timed out nodes, promotes PFAIL to FAIL when
+    /// enough reports were collected, drives the failover of our master and
+    /// produces the pings to send.
+    pub fn cron(&mut self, now: u64) -> Vec<Outgoing> {
+        let mut outgoing = Vec::new();
+        let timeout = self.config.node_timeout_ms;
+
+        self.handshakes
+            .retain(|_, hs| now.saturating_sub(hs.started) <= timeout);
+        let mut pending_meets = Vec::new();
+        for (addr, hs) in self.handshakes.iter_mut() {
+            if !hs.meet_sent {
+                hs.meet_sent = true;
+                pending_meets.push(addr.clone());
+            }
+        }
+        for addr in pending_meets {
+            outgoing.push(Outgoing {
+                addr,
+                message: self.build_message(MessageType::Meet, None),
+            });
+        }
+
+        let mut ping_targets = Vec::new();
+        let mut suspected = Vec::new();
+        for node in self.nodes.values_mut() {
+            if node.ping_sent != 0
+                && now.saturating_sub(node.ping_sent) > timeout
+                && node.health == NodeHealth::Online
+            {
+                warn!("cluster: node {} is not reachable, mark PFAIL", node.id);
+                node.health = NodeHealth::PFail;
+            }
+            if node.health == NodeHealth::PFail {
+                suspected.push(node.id.clone());
+            }
+            // Ping nodes we haven't heard from for half the timeout, and retry
+            // unanswered pings without resetting the time of the first attempt.
+            let idle = match node.ping_sent {
+                0 => now.saturating_sub(node.pong_received),
+                sent => now.saturating_sub(sent),
+            };
+            if idle > timeout / 2 {
+                ping_targets.push(node.id.clone());
+            }
+        }
+
+        for id in suspected {
+            if self.mark_failing_if_needed(&id, now) {
+                outgoing.extend(self.broadcast_fail(&id));
+            }
+        }
+
+        for id in ping_targets {
+            let message = self.build_message(MessageType::Ping, Some(&id));
+            if let Some(node) = self.nodes.get_mut(&id) {
+                if node.ping_sent == 0 {
+                    node.ping_sent = now;
+                }
+                outgoing.push(Outgoing {
+                    addr: node.addr.clone(),
+                    message,
+                });
+            }
+        }
+
+        outgoing.extend(self.failover_cron(now));
+
+        outgoing
+    }
+
+    /// Merges the gossip section of `msg`, returns the nodes that became FAIL.
+    fn process_gossip(&mut self, msg: &GossipMessage, now: u64) -> Vec<String> {
+        let mut failed = Vec::new();
+        let sender_is_master = msg.sender_role == NodeRole::Master;
+
+        for entry in &msg.gossip {
+            if entry.id == self.myself.id {
+                continue;
+            }
+
+            match self.nodes.get_mut(&entry.id) {
+                Some(node) => {
+                    if sender_is_master {
+                        match entry.health {
+                            NodeHealth::PFail | NodeHealth::Fail => {
+                                node.fail_reports.insert(msg.sender.clone(), now);
+                            }
+                            NodeHealth::Online => {
+                                node.fail_reports.remove(&msg.sender);
+                            }
+                        }
+                    }
+                    // Somebody else heard from the node recently, no need to suspect it.
+                    if entry.health == NodeHealth::Online
+                        && node.ping_sent == 0
+                        && entry.pong_received > node.pong_received
+                        && entry.pong_received <= now
+                    {
+                        node.pong_received = entry.pong_received;
+                    }
+                    if self.mark_failing_if_needed(&entry.id, now) {
+                        failed.push(entry.id.clone());
+                    }
+                }
+                None => {
+                    let known_addr = entry.addr == self.myself.addr
+                        || self.nodes.values().any(|n| n.addr == entry.addr);
+                    if entry.health != NodeHealth::Fail
+                        && !known_addr
+                        && !self.handshakes.contains_key(&entry.addr)
+                    {
+                        info!(
+                            "cluster: start handshake with {} learned from {}",
+                            entry.addr, msg.sender
+                        );
+                        self.handshakes.insert(
+                            entry.addr.clone(),
+                            Handshake {
+                                started: now,
+                                meet_sent: false,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        failed
+    }
+
+    /// Number of masters that must agree before a node is flagged FAIL, or
+    /// before a replica wins an election.
+    pub(crate) fn failure_quorum(&self) -> usize {
+        let mut masters = self
+            .nodes
+            .values()
+            .filter(|n| n.role == NodeRole::Master)
+            .count();
+        if self.myself.role == NodeRole::Master {
+            masters += 1;
+        }
+        masters / 2 + 1
+    }
+
+    fn mark_failing_if_needed(&mut self, id: &str, now: u64) -> bool {
+        let quorum = self.failure_quorum();
+        let validity = self.config.node_timeout_ms * FAIL_REPORT_VALIDITY_MULT;
+        let myself_is_master = self.myself.role == NodeRole::Master;
+
+        let Some(node) = self.nodes.get_mut(id) else {
+            return false;
+        };
+        if node.health != NodeHealth::PFail {
+            return false;
+        }
+
+        node.clean_fail_reports(now, validity);
+        let mut reports = node.fail_report_count();
+        if myself_is_master {
+            reports += 1;
+        }
+        if reports < quorum {
+            return false;
+        }
+
+        warn!("cluster: marking node {id} as FAIL ({reports} of {quorum} required reports)");
+        node.health = NodeHealth::Fail;
+        node.fail_time = now;
+        true
+    }
+
+    fn broadcast_fail(&mut self, failed: &str) -> Vec<Outgoing> {
+        let mut message = self.build_message(MessageType::Fail, None);
+        message.fail_node = Some(failed.to_string());
+        message.gossip.clear();
+
+        self.nodes
+            .values()
+            .filter(|n| n.id != failed)
+            .map(|n| Outgoing {
+                addr: n.addr.clone(),
+                message: message.clone(),
+            })
+            .collect()
+    }
+
+    pub(crate) fn build_message(
+        &mut self,
+        msg_type: MessageType,
+        target: Option<&str>,
+    ) -> GossipMessage {
+        let mut ids: Vec<&String> = self
+            .nodes
+            .keys()
+            .filter(|id| Some(id.as_str()) != target)
+            .collect();
+        ids.sort();
+
+        // Failing nodes are always gossiped so reports reach the quorum quickly,
+        // the healthy ones are rotated between messages.
+        let mut gossip: Vec<GossipEntry> = ids
+            .iter()
+            .filter_map(|id| self.nodes.get(*id))
+            .filter(|n| n.health != NodeHealth::Online)
+            .map(ClusterNode::to_gossip_entry)
+            .collect();
+
+        let healthy: Vec<&ClusterNode> = ids
+            .iter()
+            .filter_map(|id| self.nodes.get(*id))
+            .filter(|n| n.health == NodeHealth::Online)
+            .collect();
+        let wanted = self.config.gossip_fanout.min(healthy.len());
+        for i in 0..wanted {
+            let node = healthy[(self.gossip_cursor + i) % healthy.len()];
+            gossip.push(node.to_gossip_entry());
+        }
+        let next_cursor = self.gossip_cursor.wrapping_add(wanted);
+        self.gossip_cursor = next_cursor;
+
+        GossipMessage {
+            msg_type,
+            sender: self.myself.id.clone(),
+            sender_addr: self.myself.addr.clone(),
+            sender_role: self.myself.role,
+            sender_master: self.myself.master_id.clone(),
+            current_epoch: self.current_epoch,
+            config_epoch: self.myself.config_epoch,
+            repl_offset: self.myself.repl_offset,
+            fail_node: None,
+            update: None,
+            gossip,
+        }
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A new random node id of 40 hex digits.
+pub fn new_node_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    // Every RandomState is seeded with fresh random keys
+    let random = |seed: u64| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(seed);
+        hasher.finish()
+    };
+    format!(
+        "{:016x}{:016x}{:08x}",
+        random(nanos),
+        random(u64::from(std::process::id())),
+        random(nanos ^ 1) as u32
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: u64 = 1000;
+
+    fn new_node(id: &str) -> ClusterState {
+        ClusterState::new(
+            id,
+            format!("{id}:17000"),
+            ClusterConfig {
+                node_timeout_ms: TIMEOUT,
+                gossip_fanout: 3,
+            },
+        )
+    }
+
+    fn connect(a: &mut ClusterState, b: &mut ClusterState, now: u64) {
+        let meet = a.meet(&b.myself().addr.clone(), now);
+        for reply in b.handle_message(&meet.message, now) {
+            a.handle_message(&reply.message, now);
+        }
+    }
+
+    fn deliver(out: Vec<Outgoing>, target: &mut ClusterState, now: u64) -> Vec<Outgoing> {
+        let addr = target.myself().addr.clone();
+        let mut replies = Vec::new();
+        for o in out.into_iter().filter(|o| o.addr == addr) {
+            replies.extend(target.handle_message(&o.message, now));
+        }
+        replies
+    }
+
+    #[test]
+    fn test_meet_handshake() {
+        let mut a = new_node("a");
+        let mut b = new_node("b");
+        connect(&mut a, &mut b, 10);
+
+        assert!(a.node("b").is_some());
+        assert!(b.node("a").is_some());
+        assert_eq!(a.node("b").unwrap().health, NodeHealth::Online);
+    }
+
+    #[test]
+    fn test_describe_nodes() {
+        let mut a = new_node("a");
+        let mut b = new_node("b");
+        connect(&mut a, &mut b, 10);
+        a.nodes.get_mut("b").unwrap().health = NodeHealth::PFail;
+
+        let description = a.describe_nodes();
+        let lines: Vec<&str> = description.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "a a:17000 myself,master - 0 0 0 connected");
+        assert!(lines[1].starts_with("b b:17000 master,fail? - "));
+        assert!(lines[1].ends_with(" disconnected"));
+
+        let id = new_node_id();
+        assert_eq!(id.len(), 40);
+        assert!(id.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(id, new_node_id());
+    }
+
+    #[test]
+    fn test_ping_from_unknown_node_is_ignored() {
+        let mut a = new_node("a");
+        let mut b = new_node("b");
+        let ping = b.build_message(MessageType::Ping, None);
+        assert!(a.handle_message(&ping, 10).is_empty());
+        assert!(a.node("b").is_none());
+    }
+
+    #[test]
+    fn test_gossip_discovers_new_nodes() {
+        let mut a = new_node("a");
+        let mut b = new_node("b");
+        let mut c = new_node("c");
+        connect(&mut a, &mut b, 10);
+        connect(&mut a, &mut c, 10);
+        assert!(b.node("c").is_none());
+
+        let ping = a.build_message(MessageType::Ping, Some("b"));
+        b.handle_message(&ping, 20);
+
+        let meets: Vec<_> = b
+            .cron(30)
+            .into_iter()
+            .filter(|o| o.message.msg_type == MessageType::Meet)
+            .collect();
+        assert_eq!(meets.len(), 1);
+        assert_eq!(meets[0].addr, c.myself().addr);
+
+        for reply in deliver(meets, &mut c, 30) {
+            b.handle_message(&reply.message, 30);
+        }
+        assert!(b.node("c").is_some());
+        assert!(c.node("b").is_some());
+    }
+
+    #[test]
+    fn test_pfail_after_node_timeout() {
+        let mut a = new_node("a");
+        let mut b = new_node("b");
+        connect(&mut a, &mut b, 0);
+
+        let pings = a.cron(TIMEOUT / 2 + 1);
+        assert!(pings.iter().any(|o| o.addr == b.myself().addr));
+        assert_eq!(a.node("b").unwrap().health, NodeHealth::Online);
+
+        a.cron(TIMEOUT / 2 + 1 + TIMEOUT + 1);
+        // Two masters need two reports, a alone can only flag PFAIL.
+        assert_eq!(a.node("b").unwrap().health, NodeHealth::PFail);
+    }
+
+    #[test]
+    fn test_pong_clears_pfail() {
+        let mut a = new_node("a");
+        let mut b = new_node("b");
+        connect(&mut a, &mut b, 0);
+
+        let t1 = TIMEOUT / 2 + 1;
+        a.cron(t1);
+        let t2 = t1 + TIMEOUT + 1;
+        let pings = a.cron(t2);
+        assert_eq!(a.node("b").unwrap().health, NodeHealth::PFail);
+
+        for reply in deliver(pings, &mut b, t2) {
+            a.handle_message(&reply.message, t2);
+        }
+        assert_eq!(a.node("b").unwrap().health, NodeHealth::Online);
+    }
+
+    #[test]
+    fn test_fail_needs_majority_of_masters() {
+        let mut a = new_node("a");
+        let mut b = new_node("b");
+        let mut c = new_node("c");
+        connect(&mut a, &mut b, 0);
+        connect(&mut a, &mut c, 0);
+        connect(&mut b, &mut c, 0);
+
+        // c goes down: pings to it are never delivered.
+        let t1 = TIMEOUT / 2 + 1;
+        let out_a = a.cron(t1);
+        let out_b = b.cron(t1);
+        for reply in deliver(out_a, &mut b, t1) {
+            a.handle_message(&reply.message, t1);
+        }
+        for reply in deliver(out_b, &mut a, t1) {
+            b.handle_message(&reply.message, t1);
+        }
+
+        let t2 = t1 + TIMEOUT + 1;
+        a.cron(t2);
+        let out_b = b.cron(t2);
+        assert_eq!(a.node("c").unwrap().health, NodeHealth::PFail);
+        assert_eq!(b.node("c").unwrap().health, NodeHealth::PFail);
+
+        // b gossips its PFAIL report about c to a, which now has the quorum.
+        let a_addr = a.myself().addr.clone();
+        let mut fail_broadcast = Vec::new();
+        for o in out_b.into_iter().filter(|o| o.addr == a_addr) {
+            fail_broadcast.extend(a.handle_message(&o.message, t2));
+        }
+        assert_eq!(a.node("c").unwrap().health, NodeHealth::Fail);
+        assert!(fail_broadcast
+            .iter()
+            .any(|o| o.message.msg_type == MessageType::Fail
+                && o.message.fail_node.as_deref() == Some("c")));
+
+        // The FAIL broadcast is applied as is by the other nodes.
+        for o in fail_broadcast
+            .into_iter()
+            .filter(|o| o.message.msg_type == MessageType::Fail)
+        {
+            if o.addr == b.myself().addr {
+                b.handle_message(&o.message, t2);
+            }
+        }
+        assert_eq!(b.node("c").unwrap().health, NodeHealth::Fail);
+    }
+
+    #[test]
+    fn test_forget_unknown_node() {
+        let mut a = new_node("a");
+        assert!(a.forget("nope").is_err());
+    }
+}
@@ -0,0 +1,32 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Cluster support: node tables, gossip based failure detection, replica
+//! failover and the cluster bus used to exchange them between nodes.
+
+pub mod bus;
+pub mod error;
+pub mod failover;
+pub mod gossip;
+pub mod message;
+
+pub use bus::{cluster_bus, install_cluster_bus, ClusterBus};
+pub use error::{Error, Result};
+pub use gossip::{new_node_id, ClusterConfig, ClusterNode, ClusterState, NodeHealth, NodeRole};
+pub use message::{ConfigUpdate, GossipEntry, GossipMessage, MessageType};
@@ -0,0 +1,331 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Wire format of the messages exchanged on the cluster bus.
+//!
+//! Every message starts with a header describing the sender:
+//! | magic | type | current epoch | config epoch | repl offset | sender | sender addr | role | master | fail node |
+//! |  4B   |  1B  |      8B       |      8B      |     8B      |  str   |    str      |  1B  |  opt   |    opt    |
+//!
+//! followed by the configuration update, a 1-byte presence flag and, when
+//! present, the update itself:
+//! | flag | master | replaced | config epoch |
+//! |  1B  |  str   |   str    |      8B      |
+//!
+//! and by the gossip section, a 2-byte count of the entries followed by the
+//! entries:
+//! | count | id  | addr | role | health | pong received | ... |
+//! |  2B   | str | str  |  1B  |   1B   |      8B       |     |
+//!
+//! Integers are little-endian. `str` is a 2-byte length followed by the raw
+//! bytes, `opt` is a 1-byte presence flag followed by a `str` when present.
+
+use crate::error::{Error, InvalidMessageSnafu, Result};
+use crate::gossip::{NodeHealth, NodeRole};
+use bytes::{Buf, BufMut, BytesMut};
+use snafu::ensure;
+
+const GOSSIP_MAGIC: &[u8; 4] = b"KWGS";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Meet = 0,
+    Ping = 1,
+    Pong = 2,
+    Fail = 3,
+    FailoverAuthRequest = 4,
+    FailoverAuthAck = 5,
+    Update = 6,
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(MessageType::Meet),
+            1 => Ok(MessageType::Ping),
+            2 => Ok(MessageType::Pong),
+            3 => Ok(MessageType::Fail),
+            4 => Ok(MessageType::FailoverAuthRequest),
+            5 => Ok(MessageType::FailoverAuthAck),
+            6 => Ok(MessageType::Update),
+            _ => InvalidMessageSnafu {
+                message: format!("Invalid message type byte: {value}"),
+            }
+            .fail(),
+        }
+    }
+}
+
+/// What the sender of a message knows about another node of the cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GossipEntry {
+    pub id: String,
+    pub addr: String,
+    pub role: NodeRole,
+    pub health: NodeHealth,
+    pub pong_received: u64,
+}
+
+/// Announces that `master` took over the role of `replaced` at `config_epoch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigUpdate {
+    pub master: String,
+    pub replaced: String,
+    pub config_epoch: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GossipMessage {
+    pub msg_type: MessageType,
+    pub sender: String,
+    pub sender_addr: String,
+    pub sender_role: NodeRole,
+    pub sender_master: Option<String>,
+    pub current_epoch: u64,
+    pub config_epoch: u64,
+    /// Replication offset of the sender, used to rank replicas in an election.
+    pub repl_offset: u64,
+    /// The node announced as failed, only set for `MessageType::Fail`.
+    pub fail_node: Option<String>,
+    /// The new configuration, only set for `MessageType::Update`.
+    pub update: Option<ConfigUpdate>,
+    pub gossip: Vec<GossipEntry>,
+}
+
+impl GossipMessage {
+    pub fn encode(&self) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(64 + self.gossip.len() * 48);
+
+        buf.put_slice(GOSSIP_MAGIC);
+        buf.put_u8(self.msg_type as u8);
+        buf.put_u64_le(self.current_epoch);
+        buf.put_u64_le(self.config_epoch);
+        buf.put_u64_le(self.repl_offset);
+        put_str(&mut buf, &self.sender);
+        put_str(&mut buf, &self.sender_addr);
+        buf.put_u8(self.sender_role as u8);
+        put_opt_str(&mut buf, self.sender_master.as_deref());
+        put_opt_str(&mut buf, self.fail_node.as_deref());
+        match &self.update {
+            Some(update) => {
+                buf.put_u8(1);
+                put_str(&mut buf, &update.master);
+                put_str(&mut buf, &update.replaced);
+                buf.put_u64_le(update.config_epoch);
+            }
+            None => buf.put_u8(0),
+        }
+
+        buf.put_u16_le(self.gossip.len() as u16);
+        for entry in &self.gossip {
+            put_str(&mut buf, &entry.id);
+            put_str(&mut buf, &entry.addr);
+            buf.put_u8(entry.role as u8);
+            buf.put_u8(entry.health as u8);
+            buf.put_u64_le(entry.pong_received);
+        }
+
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        ensure_remaining(buf, GOSSIP_MAGIC.len() + 1 + 24, "header")?;
+        ensure!(
+            &buf[..GOSSIP_MAGIC.len()] == GOSSIP_MAGIC,
+            InvalidMessageSnafu {
+                message: "bad magic".to_string(),
+            }
+        );
+        buf.advance(GOSSIP_MAGIC.len());
+
+        let msg_type = MessageType::try_from(buf.get_u8())?;
+        let current_epoch = buf.get_u64_le();
+        let config_epoch = buf.get_u64_le();
+        let repl_offset = buf.get_u64_le();
+        let sender = get_str(&mut buf)?;
+        let sender_addr = get_str(&mut buf)?;
+        ensure_remaining(buf, 1, "sender role")?;
+        let sender_role = NodeRole::try_from(buf.get_u8())?;
+        let sender_master = get_opt_str(&mut buf)?;
+        let fail_node = get_opt_str(&mut buf)?;
+        ensure_remaining(buf, 1, "update flag")?;
+        let update = match buf.get_u8() {
+            0 => None,
+            _ => {
+                let master = get_str(&mut buf)?;
+                let replaced = get_str(&mut buf)?;
+                ensure_remaining(buf, 8, "update epoch")?;
+                Some(ConfigUpdate {
+                    master,
+                    replaced,
+                    config_epoch: buf.get_u64_le(),
+                })
+            }
+        };
+
+        ensure_remaining(buf, 2, "gossip count")?;
+        let count = buf.get_u16_le() as usize;
+        let mut gossip = Vec::with_capacity(count);
+        for _ in 0..count {
+            let id = get_str(&mut buf)?;
+            let addr = get_str(&mut buf)?;
+            ensure_remaining(buf, 2 + 8, "gossip entry")?;
+            let role = NodeRole::try_from(buf.get_u8())?;
+            let health = NodeHealth::try_from(buf.get_u8())?;
+            let pong_received = buf.get_u64_le();
+            gossip.push(GossipEntry {
+                id,
+                addr,
+                role,
+                health,
+                pong_received,
+            });
+        }
+
+        Ok(Self {
+            msg_type,
+            sender,
+            sender_addr,
+            sender_role,
+            sender_master,
+            current_epoch,
+            config_epoch,
+            repl_offset,
+            fail_node,
+            update,
+            gossip,
+        })
+    }
+}
+
+fn ensure_remaining(buf: &[u8], needed: usize, field: &str) -> Result<()> {
+    ensure!(
+        buf.remaining() >= needed,
+        InvalidMessageSnafu {
+            message: format!(
+                "truncated {field}: need {needed} bytes, {} left",
+                buf.remaining()
+            ),
+        }
+    );
+    Ok(())
+}
+
+fn put_str(buf: &mut BytesMut, s: &str) {
+    buf.put_u16_le(s.len() as u16);
+    buf.put_slice(s.as_bytes());
+}
+
+fn put_opt_str(buf: &mut BytesMut, s: Option<&str>) {
+    match s {
+        Some(s) => {
+            buf.put_u8(1);
+            put_str(buf, s);
+        }
+        None => buf.put_u8(0),
+    }
+}
+
+fn get_str(buf: &mut &[u8]) -> Result<String> {
+    ensure_remaining(buf, 2, "string length")?;
+    let len = buf.get_u16_le() as usize;
+    ensure_remaining(buf, len, "string")?;
+    let s = String::from_utf8_lossy(&buf[..len]).to_string();
+    buf.advance(len);
+    Ok(s)
+}
+
+fn get_opt_str(buf: &mut &[u8]) -> Result<Option<String>> {
+    ensure_remaining(buf, 1, "optional flag")?;
+    match buf.get_u8() {
+        0 => Ok(None),
+        _ => Ok(Some(get_str(buf)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_message() -> GossipMessage {
+        GossipMessage {
+            msg_type: MessageType::Ping,
+            sender: "node-a".to_string(),
+            sender_addr: "127.0.0.1:17001".to_string(),
+            sender_role: NodeRole::Replica,
+            sender_master: Some("node-b".to_string()),
+            current_epoch: 7,
+            config_epoch: 3,
+            repl_offset: 4096,
+            fail_node: None,
+            update: None,
+            gossip: vec![GossipEntry {
+                id: "node-c".to_string(),
+                addr: "127.0.0.1:17003".to_string(),
+                role: NodeRole::Master,
+                health: NodeHealth::PFail,
+                pong_received: 1_700_000_000_000,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let msg = sample_message();
+        let encoded = msg.encode();
+        let decoded = GossipMessage::decode(&encoded).unwrap();
+        assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn test_encode_decode_update() {
+        let mut msg = sample_message();
+        msg.msg_type = MessageType::Update;
+        msg.update = Some(ConfigUpdate {
+            master: "node-a".to_string(),
+            replaced: "node-b".to_string(),
+            config_epoch: 8,
+        });
+        let encoded = msg.encode();
+        assert_eq!(GossipMessage::decode(&encoded).unwrap(), msg);
+        for len in 0..encoded.len() {
+            assert!(GossipMessage::decode(&encoded[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_bad_magic() {
+        let mut encoded = sample_message().encode();
+        encoded[0] = b'X';
+        assert!(GossipMessage::decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let encoded = sample_message().encode();
+        for len in 0..encoded.len() {
+            assert!(
+                GossipMessage::decode(&encoded[..len]).is_err(),
+                "truncated message of {len} bytes should not decode"
+            );
+        }
+    }
+}
@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::lmpop::{lmpop_reply, parse_lmpop_args, LMPopArgs};
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, BlockedWait, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use std::time::Duration;
+use storage::storage::Storage;
+
+#[derive(Clone, Default)]
+pub struct BLMPopCmd {
+    meta: CmdMeta,
+}
+
+impl BLMPopCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "blmpop".to_string(),
+                arity: -5, // BLMPOP timeout numkeys key [key ...] LEFT|RIGHT [COUNT count]
+                flags: CmdFlags::WRITE | CmdFlags::BLOCKING,
+                acl_category: AclCategory::LIST
+                    | AclCategory::WRITE
+                    | AclCategory::SLOW
+                    | AclCategory::BLOCKING,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Parses the timeout of a blocking command, in seconds with a fraction.
+fn parse_timeout(arg: &[u8]) -> Result<Duration, RespData> {
+    let seconds = std::str::from_utf8(arg)
+        .ok()
+        .and_then(|n| n.parse::<f64>().ok())
+        .filter(|n| n.is_finite())
+        .ok_or_else(|| {
+            RespData::Error(
+                "ERR timeout is not a float or out of range"
+                    .to_string()
+                    .into(),
+            )
+        })?;
+    if seconds < 0.0 {
+        return Err(RespData::Error(
+            "ERR timeout is negative".to_string().into(),
+        ));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parses the timeout and the LMPOP arguments of a BLMPOP argv.
+fn parse_blmpop_args(argv: &[Vec<u8>]) -> Result<(Duration, LMPopArgs), RespData> {
+    let timeout = parse_timeout(&argv[1])?;
+    Ok((timeout, parse_lmpop_args(&argv[2..])?))
+}
+
+impl Cmd for BLMPopCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    /// BLMPOP timeout numkeys key [key ...] LEFT|RIGHT [COUNT count]
+    fn do_initial(&self, client: &mut Client) -> bool {
+        if !self.check_arg(client.argv().len()) {
+            *client.reply_mut() = RespData::Error(
+                "ERR wrong number of arguments for 'blmpop' command"
+                    .to_string()
+                    .into(),
+            );
+            return false;
+        }
+        let key = client.argv()[3].clone();
+        client.set_key(&key);
+        true
+    }
+
+    /// Pops without waiting, as LMPOP. When every list is empty, the
+    /// connection then runs the wait of `blocked_wait`.
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let (_, args) = match parse_blmpop_args(client.argv()) {
+            Ok(parsed) => parsed,
+            Err(reply) => {
+                *client.reply_mut() = reply;
+                return;
+            }
+        };
+
+        *client.reply_mut() = match storage.lmpop(&args.keys, args.end, args.count) {
+            Ok(popped) => lmpop_reply(popped),
+            Err(e) => storage_error_reply(&e),
+        };
+    }
+
+    /// Waits for a push to one of the lists when `do_cmd` popped nothing.
+    fn blocked_wait(&self, client: &mut Client, storage: Arc<Storage>) -> Option<BlockedWait> {
+        if !matches!(client.reply_mut(), RespData::Array(None)) {
+            return None;
+        }
+        let (timeout, args) = parse_blmpop_args(client.argv()).ok()?;
+        Some(Box::pin(async move {
+            match storage
+                .blmpop(&args.keys, args.end, args.count, timeout)
+                .await
+            {
+                Ok(popped) => lmpop_reply(popped),
+                Err(e) => storage_error_reply(&e),
+            }
+        }))
+    }
+}
@@ -36,6 +36,9 @@ impl GetCmd {
                 name: "get".to_string(),
                 arity: 2, // GET key
                 flags: CmdFlags::READONLY,
+                first_key: 1,
+                last_key: 1,
+                key_step: 1,
                 ..Default::default()
             },
         }
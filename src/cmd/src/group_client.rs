@@ -34,6 +34,8 @@ pub fn new_client_group_cmd() -> BaseCmdGroup {
 
     client_cmd.add_sub_cmd(Box::new(CmdClientGetname::new()));
     client_cmd.add_sub_cmd(Box::new(CmdClientSetname::new()));
+    client_cmd.add_sub_cmd(Box::new(CmdClientId::new()));
+    client_cmd.add_sub_cmd(Box::new(CmdClientKill::new()));
 
     client_cmd
 }
@@ -110,3 +112,88 @@ impl Cmd for CmdClientSetname {
         *client.reply_mut() = RespData::SimpleString("OK".to_string().into());
     }
 }
+
+#[derive(Clone, Default)]
+pub struct CmdClientId {
+    meta: CmdMeta,
+}
+
+impl CmdClientId {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "id".to_string(),
+                arity: 2,
+                flags: CmdFlags::ADMIN | CmdFlags::READONLY,
+                acl_category: AclCategory::ADMIN,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdClientId {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, _storage: Arc<Storage>) {
+        *client.reply_mut() = RespData::Integer(client.id() as i64);
+    }
+}
+
+/// CLIENT KILL ID client-id
+#[derive(Clone, Default)]
+pub struct CmdClientKill {
+    meta: CmdMeta,
+}
+
+impl CmdClientKill {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "kill".to_string(),
+                arity: 4,
+                flags: CmdFlags::ADMIN | CmdFlags::WRITE,
+                acl_category: AclCategory::ADMIN | AclCategory::DANGEROUS,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdClientKill {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, _storage: Arc<Storage>) {
+        let argv = client.argv();
+        if argv.len() != 4 {
+            *client.reply_mut() =
+                RespData::Error("ERR wrong number of arguments".to_string().into());
+            return;
+        }
+        if !argv[2].eq_ignore_ascii_case(b"id") {
+            *client.reply_mut() = RespData::Error("ERR syntax error".to_string().into());
+            return;
+        }
+        let Some(id) = std::str::from_utf8(&argv[3])
+            .ok()
+            .and_then(|id| id.parse::<u64>().ok())
+        else {
+            *client.reply_mut() =
+                RespData::Error("ERR client-id should be greater than 0".to_string().into());
+            return;
+        };
+
+        let killed = client::kill_client(id);
+        *client.reply_mut() = RespData::Integer(killed as i64);
+    }
+}
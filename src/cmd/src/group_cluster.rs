@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{AclCategory, BaseCmdGroup, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use cluster::cluster_bus;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+
+const CLUSTER_DISABLED: &str = "ERR This instance has cluster support disabled";
+
+pub fn new_cluster_group_cmd() -> BaseCmdGroup {
+    let mut cluster_cmd = BaseCmdGroup::new(
+        "cluster".to_string(),
+        -2,
+        CmdFlags::ADMIN,
+        AclCategory::ADMIN,
+    );
+
+    cluster_cmd.add_sub_cmd(Box::new(CmdClusterMeet::new()));
+    cluster_cmd.add_sub_cmd(Box::new(CmdClusterNodes::new()));
+
+    cluster_cmd
+}
+
+/// CLUSTER MEET ip port, the port being the one of the cluster bus
+#[derive(Clone, Default)]
+pub struct CmdClusterMeet {
+    meta: CmdMeta,
+}
+
+impl CmdClusterMeet {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "meet".to_string(),
+                arity: 4,
+                flags: CmdFlags::ADMIN | CmdFlags::WRITE,
+                acl_category: AclCategory::ADMIN | AclCategory::DANGEROUS,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdClusterMeet {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, _storage: Arc<Storage>) {
+        let Some(bus) = cluster_bus() else {
+            *client.reply_mut() = RespData::Error(CLUSTER_DISABLED.to_string().into());
+            return;
+        };
+        let argv = client.argv();
+        if argv.len() != 4 {
+            *client.reply_mut() =
+                RespData::Error("ERR wrong number of arguments".to_string().into());
+            return;
+        }
+        let ip = String::from_utf8_lossy(&argv[2]).to_string();
+        let Some(port) = std::str::from_utf8(&argv[3])
+            .ok()
+            .and_then(|port| port.parse::<u16>().ok())
+        else {
+            let port = String::from_utf8_lossy(&argv[3]);
+            *client.reply_mut() = RespData::Error(format!("ERR Invalid port: {port}").into());
+            return;
+        };
+
+        bus.meet(&format!("{ip}:{port}"));
+        *client.reply_mut() = RespData::SimpleString("OK".to_string().into());
+    }
+}
+
+/// CLUSTER NODES
+#[derive(Clone, Default)]
+pub struct CmdClusterNodes {
+    meta: CmdMeta,
+}
+
+impl CmdClusterNodes {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "nodes".to_string(),
+                arity: 2,
+                flags: CmdFlags::ADMIN | CmdFlags::READONLY,
+                acl_category: AclCategory::ADMIN,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdClusterNodes {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, _storage: Arc<Storage>) {
+        let Some(bus) = cluster_bus() else {
+            *client.reply_mut() = RespData::Error(CLUSTER_DISABLED.to_string().into());
+            return;
+        };
+        let nodes = {
+            let state = bus.state();
+            let state = state.lock().unwrap_or_else(|e| e.into_inner());
+            state.describe_nodes()
+        };
+        *client.reply_mut() = RespData::BulkString(Some(nodes.into()));
+    }
+}
@@ -0,0 +1,175 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{AclCategory, BaseCmdGroup, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use conf::active::active_config;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+use storage::string_match;
+
+const NO_CONFIG: &str = "ERR the configuration is not available";
+
+pub fn new_config_group_cmd() -> BaseCmdGroup {
+    let mut config_cmd = BaseCmdGroup::new(
+        "config".to_string(),
+        -2,
+        CmdFlags::ADMIN,
+        AclCategory::ADMIN,
+    );
+
+    config_cmd.add_sub_cmd(Box::new(CmdConfigGet::new()));
+    config_cmd.add_sub_cmd(Box::new(CmdConfigSet::new()));
+    config_cmd.add_sub_cmd(Box::new(CmdConfigRewrite::new()));
+
+    config_cmd
+}
+
+/// CONFIG GET pattern, the options matching the glob-style pattern
+#[derive(Clone, Default)]
+pub struct CmdConfigGet {
+    meta: CmdMeta,
+}
+
+impl CmdConfigGet {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "get".to_string(),
+                arity: 3,
+                flags: CmdFlags::ADMIN | CmdFlags::READONLY,
+                acl_category: AclCategory::ADMIN,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdConfigGet {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, _storage: Arc<Storage>) {
+        let Some(config) = active_config() else {
+            *client.reply_mut() = RespData::Error(NO_CONFIG.to_string().into());
+            return;
+        };
+        let pattern = client.argv()[2].clone();
+        let reply = config
+            .entries()
+            .into_iter()
+            .filter(|(name, _)| string_match(&pattern, name.as_bytes(), true))
+            .flat_map(|(name, value)| {
+                [
+                    RespData::BulkString(Some(name.into())),
+                    RespData::BulkString(Some(value.into())),
+                ]
+            })
+            .collect();
+        *client.reply_mut() = RespData::Array(Some(reply));
+    }
+}
+
+/// CONFIG SET option value
+#[derive(Clone, Default)]
+pub struct CmdConfigSet {
+    meta: CmdMeta,
+}
+
+impl CmdConfigSet {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "set".to_string(),
+                arity: 4,
+                flags: CmdFlags::ADMIN | CmdFlags::WRITE,
+                acl_category: AclCategory::ADMIN | AclCategory::DANGEROUS,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdConfigSet {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, _storage: Arc<Storage>) {
+        let Some(config) = active_config() else {
+            *client.reply_mut() = RespData::Error(NO_CONFIG.to_string().into());
+            return;
+        };
+        let key = String::from_utf8_lossy(&client.argv()[2]).to_lowercase();
+        let value = String::from_utf8_lossy(&client.argv()[3]).to_string();
+        *client.reply_mut() = match config.set(&key, &value) {
+            Ok(()) => RespData::SimpleString("OK".to_string().into()),
+            Err(e) => RespData::Error(format!("ERR CONFIG SET failed: {e}").into()),
+        };
+    }
+}
+
+/// CONFIG REWRITE, writes the configuration back to its file
+#[derive(Clone, Default)]
+pub struct CmdConfigRewrite {
+    meta: CmdMeta,
+}
+
+impl CmdConfigRewrite {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "rewrite".to_string(),
+                arity: 2,
+                flags: CmdFlags::ADMIN,
+                acl_category: AclCategory::ADMIN | AclCategory::DANGEROUS,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdConfigRewrite {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, _storage: Arc<Storage>) {
+        let Some(config) = active_config() else {
+            *client.reply_mut() = RespData::Error(NO_CONFIG.to_string().into());
+            return;
+        };
+        *client.reply_mut() = match config.rewrite() {
+            Ok(()) => RespData::SimpleString("OK".to_string().into()),
+            Err(e) => RespData::Error(format!("ERR {e}").into()),
+        };
+    }
+}
@@ -0,0 +1,117 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, BaseCmdGroup, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+use storage::value_decode::{decode_value, from_hex, DecodeFormat, DecodedField};
+
+pub fn new_debug_group_cmd() -> BaseCmdGroup {
+    let mut debug_cmd = BaseCmdGroup::new(
+        "debug".to_string(),
+        -2,
+        CmdFlags::ADMIN,
+        AclCategory::ADMIN | AclCategory::DANGEROUS,
+    );
+
+    debug_cmd.add_sub_cmd(Box::new(CmdDebugDecode::new()));
+
+    debug_cmd
+}
+
+/// DEBUG DECODE KEY <key>
+/// DEBUG DECODE HEX <string|meta|list|stream|data|hashdata> <hex>
+///
+/// Replies with the decoded fields of the meta value of a key, expired or
+/// not, or of raw value bytes of the given format, as name/value pairs.
+#[derive(Clone, Default)]
+pub struct CmdDebugDecode {
+    meta: CmdMeta,
+}
+
+impl CmdDebugDecode {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "decode".to_string(),
+                arity: -4,
+                flags: CmdFlags::ADMIN | CmdFlags::READONLY,
+                acl_category: AclCategory::ADMIN | AclCategory::DANGEROUS,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdDebugDecode {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let argv = client.argv();
+        let mode = String::from_utf8_lossy(&argv[2]).to_lowercase();
+        let result = match (mode.as_str(), argv.len()) {
+            ("key", 4) => storage.debug_decode_key(&argv[3]),
+            ("hex", 5) => {
+                let format = String::from_utf8_lossy(&argv[3]);
+                let Some(format) = DecodeFormat::from_name(&format) else {
+                    *client.reply_mut() = RespData::Error(
+                        format!(
+                            "ERR unknown format '{format}', expected one of {}",
+                            DecodeFormat::NAMES.join(", ")
+                        )
+                        .into(),
+                    );
+                    return;
+                };
+                let Some(raw) = from_hex(&String::from_utf8_lossy(&argv[4])) else {
+                    *client.reply_mut() =
+                        RespData::Error("ERR invalid hex string".to_string().into());
+                    return;
+                };
+                decode_value(format, &raw)
+            }
+            _ => {
+                *client.reply_mut() = RespData::Error("ERR syntax error".to_string().into());
+                return;
+            }
+        };
+
+        match result {
+            Ok(fields) => *client.reply_mut() = fields_reply(fields),
+            Err(e) => *client.reply_mut() = storage_error_reply(&e),
+        }
+    }
+}
+
+fn fields_reply(fields: Vec<DecodedField>) -> RespData {
+    let mut reply = Vec::with_capacity(fields.len() * 2);
+    for field in fields {
+        reply.push(RespData::BulkString(Some(field.name.into())));
+        reply.push(RespData::BulkString(Some(field.value.into())));
+    }
+    RespData::Array(Some(reply))
+}
@@ -0,0 +1,100 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{AclCategory, BaseCmdGroup, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use kstd::allocator;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+
+pub fn new_memory_group_cmd() -> BaseCmdGroup {
+    let mut memory_cmd = BaseCmdGroup::new(
+        "memory".to_string(),
+        -2,
+        CmdFlags::READONLY,
+        AclCategory::SLOW,
+    );
+
+    memory_cmd.add_sub_cmd(Box::new(CmdMemoryStats::new()));
+
+    memory_cmd
+}
+
+#[derive(Clone, Default)]
+pub struct CmdMemoryStats {
+    meta: CmdMeta,
+}
+
+impl CmdMemoryStats {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "stats".to_string(),
+                arity: 2,
+                flags: CmdFlags::READONLY,
+                acl_category: AclCategory::SLOW,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdMemoryStats {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, _storage: Arc<Storage>) {
+        let stats = allocator::stats();
+
+        // A flat list of name/value pairs, as MEMORY STATS replies in Redis.
+        // Figures the allocator does not report are nil.
+        let bytes =
+            |v: Option<u64>| v.map_or(RespData::BulkString(None), |v| RespData::Integer(v as i64));
+        let pairs = [
+            (
+                "allocator",
+                RespData::BulkString(Some(allocator::name().into())),
+            ),
+            ("allocator.allocated", bytes(stats.allocated)),
+            ("allocator.active", bytes(stats.active)),
+            ("allocator.resident", bytes(stats.resident)),
+            (
+                "fragmentation",
+                stats
+                    .fragmentation_ratio()
+                    .map_or(RespData::BulkString(None), |ratio| {
+                        RespData::BulkString(Some(format!("{ratio:.2}").into()))
+                    }),
+            ),
+        ];
+
+        let mut reply = Vec::with_capacity(pairs.len() * 2);
+        for (name, value) in pairs {
+            reply.push(RespData::BulkString(Some(name.into())));
+            reply.push(value);
+        }
+        *client.reply_mut() = RespData::Array(Some(reply));
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, BaseCmdGroup, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+
+pub fn new_object_group_cmd() -> BaseCmdGroup {
+    let mut object_cmd = BaseCmdGroup::new(
+        "object".to_string(),
+        -2,
+        CmdFlags::READONLY,
+        AclCategory::KEYSPACE | AclCategory::READ | AclCategory::SLOW,
+    );
+
+    object_cmd.add_sub_cmd(Box::new(CmdObjectEncoding::new()));
+
+    object_cmd
+}
+
+/// OBJECT ENCODING <key>
+///
+/// Replies with how the value of the key is stored: "listpack" for a hash,
+/// set or sorted set kept inline in its meta value, "rocksdb-spread" for
+/// one in data keys, nil when the key does not exist.
+#[derive(Clone, Default)]
+pub struct CmdObjectEncoding {
+    meta: CmdMeta,
+}
+
+impl CmdObjectEncoding {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "encoding".to_string(),
+                arity: 3,
+                flags: CmdFlags::READONLY,
+                acl_category: AclCategory::KEYSPACE | AclCategory::READ | AclCategory::SLOW,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for CmdObjectEncoding {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, client: &mut Client) -> bool {
+        if !self.check_arg(client.argv().len()) {
+            *client.reply_mut() = RespData::Error(
+                "ERR wrong number of arguments for 'object|encoding' command"
+                    .to_string()
+                    .into(),
+            );
+            return false;
+        }
+        let key = client.argv()[2].clone();
+        client.set_key(&key);
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        match storage.object_encoding(client.key()) {
+            Ok(encoding) => {
+                *client.reply_mut() = RespData::BulkString(encoding.map(Into::into));
+            }
+            Err(e) => {
+                *client.reply_mut() = storage_error_reply(&e);
+            }
+        }
+    }
+}
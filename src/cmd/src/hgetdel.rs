@@ -0,0 +1,122 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+
+#[derive(Clone, Default)]
+pub struct HGetDelCmd {
+    meta: CmdMeta,
+}
+
+impl HGetDelCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "hgetdel".to_string(),
+                arity: -5, // HGETDEL key FIELDS numfields field [field ...]
+                flags: CmdFlags::WRITE | CmdFlags::FAST,
+                acl_category: AclCategory::HASH | AclCategory::WRITE | AclCategory::FAST,
+                first_key: 1,
+                last_key: 1,
+                key_step: 1,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Parses `FIELDS numfields field [field ...]`, which ends the arguments of
+/// the hash field commands.
+pub(crate) fn parse_fields(args: &[Vec<u8>]) -> Result<&[Vec<u8>], RespData> {
+    if args.len() < 3 || !args[0].eq_ignore_ascii_case(b"fields") {
+        return Err(RespData::Error("ERR syntax error".to_string().into()));
+    }
+    let numfields = std::str::from_utf8(&args[1])
+        .ok()
+        .and_then(|n| n.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .ok_or_else(|| {
+            RespData::Error(
+                "ERR Number of fields must be a positive integer"
+                    .to_string()
+                    .into(),
+            )
+        })?;
+    if numfields != args.len() - 2 {
+        return Err(RespData::Error(
+            "ERR The `numfields` parameter must match the number of arguments"
+                .to_string()
+                .into(),
+        ));
+    }
+    Ok(&args[2..])
+}
+
+/// The reply of HGETDEL and HGETEX, a nil for every missing field.
+pub(crate) fn field_values_reply(values: Vec<Option<Vec<u8>>>) -> RespData {
+    RespData::Array(Some(
+        values
+            .into_iter()
+            .map(|value| RespData::BulkString(value.map(Into::into)))
+            .collect(),
+    ))
+}
+
+impl Cmd for HGetDelCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    /// HGETDEL key FIELDS numfields field [field ...]
+    fn do_initial(&self, client: &mut Client) -> bool {
+        if !self.check_arg(client.argv().len()) {
+            *client.reply_mut() = RespData::Error(
+                "ERR wrong number of arguments for 'hgetdel' command"
+                    .to_string()
+                    .into(),
+            );
+            return false;
+        }
+        let key = client.argv()[1].clone();
+        client.set_key(&key);
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let fields = match parse_fields(&client.argv()[2..]) {
+            Ok(fields) => fields.to_vec(),
+            Err(reply) => {
+                *client.reply_mut() = reply;
+                return;
+            }
+        };
+
+        match storage.hgetdel(client.key(), &fields) {
+            Ok(values) => {
+                *client.reply_mut() = field_values_reply(values);
+            }
+            Err(e) => {
+                *client.reply_mut() = storage_error_reply(&e);
+            }
+        }
+    }
+}
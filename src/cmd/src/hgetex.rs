@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::hgetdel::{field_values_reply, parse_fields};
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+use storage::{clock, FieldExpiry};
+
+#[derive(Clone, Default)]
+pub struct HGetExCmd {
+    meta: CmdMeta,
+}
+
+impl HGetExCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "hgetex".to_string(),
+                // HGETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+                //   PXAT unix-time-milliseconds | PERSIST] FIELDS numfields field [field ...]
+                arity: -5,
+                flags: CmdFlags::WRITE | CmdFlags::FAST,
+                acl_category: AclCategory::HASH | AclCategory::WRITE | AclCategory::FAST,
+                first_key: 1,
+                last_key: 1,
+                key_step: 1,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Parses the expiration option, returns it with the number of arguments it
+/// takes.
+fn parse_expiry(args: &[Vec<u8>]) -> Result<(FieldExpiry, usize), RespData> {
+    let Some(option) = args.first() else {
+        return Err(RespData::Error("ERR syntax error".to_string().into()));
+    };
+    let option = String::from_utf8_lossy(option).to_lowercase();
+    let unit_micros: u64 = match option.as_str() {
+        "fields" => return Ok((FieldExpiry::Keep, 0)),
+        "persist" => return Ok((FieldExpiry::Persist, 1)),
+        "ex" | "exat" => 1_000_000,
+        "px" | "pxat" => 1_000,
+        _ => return Err(RespData::Error("ERR syntax error".to_string().into())),
+    };
+
+    let invalid = || {
+        RespData::Error(
+            "ERR invalid expire time in 'hgetex' command"
+                .to_string()
+                .into(),
+        )
+    };
+    let time = args
+        .get(1)
+        .and_then(|arg| std::str::from_utf8(arg).ok())
+        .and_then(|arg| arg.parse::<u64>().ok())
+        .filter(|&time| time > 0)
+        .and_then(|time| time.checked_mul(unit_micros))
+        .ok_or_else(invalid)?;
+    let etime = if option.ends_with("at") {
+        time
+    } else {
+        clock::now_micros().checked_add(time).ok_or_else(invalid)?
+    };
+    Ok((FieldExpiry::At(etime), 2))
+}
+
+impl Cmd for HGetExCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, client: &mut Client) -> bool {
+        if !self.check_arg(client.argv().len()) {
+            *client.reply_mut() = RespData::Error(
+                "ERR wrong number of arguments for 'hgetex' command"
+                    .to_string()
+                    .into(),
+            );
+            return false;
+        }
+        let key = client.argv()[1].clone();
+        client.set_key(&key);
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let args = &client.argv()[2..];
+        let parsed = parse_expiry(args).and_then(|(expiry, taken)| {
+            parse_fields(&args[taken..]).map(|fields| (expiry, fields.to_vec()))
+        });
+        let (expiry, fields) = match parsed {
+            Ok(parsed) => parsed,
+            Err(reply) => {
+                *client.reply_mut() = reply;
+                return;
+            }
+        };
+
+        match storage.hgetex(client.key(), &fields, expiry) {
+            Ok(values) => {
+                *client.reply_mut() = field_values_reply(values);
+            }
+            Err(e) => {
+                *client.reply_mut() = storage_error_reply(&e);
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{AclCategory, Cmd, CmdFlags, CmdMeta};
+use client::{command_queue_stats, Client};
+use resp::RespData;
+use std::sync::{Arc, Mutex, OnceLock};
+use storage::{
+    active_expire::active_expire_info, encoding_info, filter_stats::filter_stats_info,
+    storage::Storage,
+};
+
+/// Builds an INFO section kept by a crate this one does not depend on,
+/// such as the stats of the network layer.
+pub type InfoSection = fn() -> String;
+
+fn sections() -> &'static Mutex<Vec<(&'static str, InfoSection)>> {
+    static SECTIONS: OnceLock<Mutex<Vec<(&'static str, InfoSection)>>> = OnceLock::new();
+    SECTIONS.get_or_init(Default::default)
+}
+
+/// Adds the section `name` to INFO, also reported by default in the order
+/// of registration. Registering `name` again replaces its section.
+pub fn register_info_section(name: &'static str, section: InfoSection) {
+    let mut sections = sections().lock().unwrap_or_else(|e| e.into_inner());
+    match sections
+        .iter_mut()
+        .find(|(registered, _)| *registered == name)
+    {
+        Some(registered) => registered.1 = section,
+        None => sections.push((name, section)),
+    }
+}
+
+/// The registered sections with their names, in the order of registration.
+fn registered_sections() -> Vec<(&'static str, InfoSection)> {
+    sections().lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+#[derive(Clone, Default)]
+pub struct InfoCmd {
+    meta: CmdMeta,
+}
+
+impl InfoCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "info".to_string(),
+                arity: -1, // INFO [section]
+                flags: CmdFlags::READONLY,
+                acl_category: AclCategory::SLOW | AclCategory::DANGEROUS,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for InfoCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let section = client
+            .argv()
+            .get(1)
+            .map(|s| String::from_utf8_lossy(s).to_lowercase())
+            .unwrap_or_else(|| "default".to_string());
+
+        let info = match section.as_str() {
+            "default" | "all" | "everything" => {
+                storage.perf_stats.info()
+                    + "\r\n"
+                    + &command_queue_stats().info()
+                    + "\r\n"
+                    + &encoding_info()
+                    + "\r\n"
+                    + &filter_stats_info()
+                    + "\r\n"
+                    + &storage.lifecycle_info()
+                    + "\r\n"
+                    + &storage.lazy_delete_info()
+                    + "\r\n"
+                    + &active_expire_info()
+                    + "\r\n"
+                    + &storage.stale_ranges_info()
+                    + "\r\n"
+                    + &registered_sections()
+                        .iter()
+                        .map(|(_, section)| section() + "\r\n")
+                        .collect::<String>()
+                    + &storage.numa_info()
+            }
+            "rocksdbstats" => storage.perf_stats.info(),
+            "encoding" => encoding_info(),
+            "compactionfilter" => filter_stats_info(),
+            "lifecycle" => storage.lifecycle_info(),
+            "lazydelete" => storage.lazy_delete_info(),
+            "activeexpire" => active_expire_info(),
+            "staleranges" => storage.stale_ranges_info(),
+            "commandqueue" => command_queue_stats().info(),
+            "numa" => storage.numa_info(),
+            name => registered_sections()
+                .iter()
+                .find(|(registered, _)| *registered == name)
+                .map_or_else(String::new, |(_, section)| section()),
+        };
+        *client.reply_mut() = RespData::BulkString(Some(info.into()));
+    }
+}
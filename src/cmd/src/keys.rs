@@ -0,0 +1,74 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+
+#[derive(Clone, Default)]
+pub struct KeysCmd {
+    meta: CmdMeta,
+}
+
+impl KeysCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "keys".to_string(),
+                arity: 2, // KEYS pattern
+                flags: CmdFlags::READONLY,
+                acl_category: AclCategory::KEYSPACE
+                    | AclCategory::READ
+                    | AclCategory::SLOW
+                    | AclCategory::DANGEROUS,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for KeysCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, _client: &mut Client) -> bool {
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let pattern = client.argv()[1].clone();
+        let result = storage.keys(&pattern, client.cancel_token());
+
+        match result {
+            Ok(keys) => {
+                let keys = keys
+                    .into_iter()
+                    .map(|key| RespData::BulkString(Some(key.into())))
+                    .collect();
+                *client.reply_mut() = RespData::Array(Some(keys));
+            }
+            Err(e) => {
+                *client.reply_mut() = storage_error_reply(&e);
+            }
+        }
+    }
+}
@@ -17,16 +17,38 @@
  * limitations under the License.
  */
 
+pub mod blmpop;
+pub mod del;
+pub mod exists;
 pub mod get;
 pub mod group_client;
+pub mod group_cluster;
+pub mod group_config;
+pub mod group_debug;
+pub mod group_memory;
+pub mod group_object;
+pub mod hgetdel;
+pub mod hgetex;
+pub mod info;
+pub mod keys;
+pub mod lmove;
+pub mod lmpop;
+pub mod select;
 pub mod set;
+pub mod swapdb;
 pub mod table;
+pub mod timeout;
+pub mod touch;
+pub mod undelete;
 
 use bitflags::bitflags;
-use client::Client;
+use client::{Client, CommandClass};
+use kstd::cancel::AbortReason;
 use log::debug;
 use resp::RespData;
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 use storage::storage::Storage;
 
@@ -88,8 +110,20 @@ pub struct CmdMeta {
     pub flags: CmdFlags,
     pub acl_category: AclCategory,
     pub cmd_id: u32,
+    /// Position in argv of the first key, 0 for a command without keys or
+    /// with keys at positions given by its arguments (LMPOP numkeys)
+    pub first_key: i16,
+    /// Position of the last key, negative to count from the end of argv
+    pub last_key: i16,
+    /// Positions from one key to the next
+    pub key_step: i16,
 }
 
+/// The wait of a blocking command for other clients, resolving to its
+/// reply. It runs on the connection task, which drops it when the client is
+/// killed or disconnects.
+pub type BlockedWait = Pin<Box<dyn Future<Output = RespData> + Send>>;
+
 pub trait Cmd: Send + Sync {
     /// return cmd meta
     fn meta(&self) -> &CmdMeta;
@@ -107,6 +141,12 @@ pub trait Cmd: Send + Sync {
         }
     }
 
+    /// Called after `execute` for a BLOCKING command: the wait to run when
+    /// it found nothing to serve right away, None when it already replied.
+    fn blocked_wait(&self, _client: &mut Client, _storage: Arc<Storage>) -> Option<BlockedWait> {
+        None
+    }
+
     fn name(&self) -> &str {
         &self.meta().name
     }
@@ -120,6 +160,25 @@ pub trait Cmd: Send + Sync {
         }
     }
 
+    /// The positions of the keys in an argv of `argc` arguments, by the
+    /// first_key, last_key and key_step of the meta. Empty for a command
+    /// without keys at fixed positions.
+    fn key_positions(&self, argc: usize) -> Vec<usize> {
+        let meta = self.meta();
+        if meta.first_key <= 0 {
+            return Vec::new();
+        }
+        let last = if meta.last_key < 0 {
+            argc as i64 + meta.last_key as i64
+        } else {
+            (meta.last_key as i64).min(argc as i64 - 1)
+        };
+        (meta.first_key as i64..=last)
+            .step_by(meta.key_step.max(1) as usize)
+            .map(|position| position as usize)
+            .collect()
+    }
+
     fn has_flag(&self, flag: CmdFlags) -> bool {
         self.meta().flags.contains(flag)
     }
@@ -128,6 +187,24 @@ pub trait Cmd: Send + Sync {
         self.meta().acl_category
     }
 
+    /// The class the command waits in for a shared execution slot: raft
+    /// commands apply replicated writes, and the slow read-only commands
+    /// scan many keys.
+    fn class(&self) -> CommandClass {
+        if self.has_flag(CmdFlags::RAFT) {
+            CommandClass::Replication
+        } else if self.has_flag(CmdFlags::ADMIN) {
+            CommandClass::Admin
+        } else if self.has_flag(CmdFlags::READONLY)
+            && !self.has_flag(CmdFlags::FAST)
+            && self.acl_category().contains(AclCategory::SLOW)
+        {
+            CommandClass::Bulk
+        } else {
+            CommandClass::Point
+        }
+    }
+
     fn has_sub_command(&self) -> bool {
         false
     }
@@ -137,6 +214,33 @@ pub trait Cmd: Send + Sync {
     }
 }
 
+/// The reply of a command that ran past its deadline.
+pub fn timeout_reply() -> RespData {
+    RespData::Error(
+        "TIMEOUT command exceeded its execution deadline"
+            .to_string()
+            .into(),
+    )
+}
+
+/// Builds the error reply of a failed storage operation.
+pub fn storage_error_reply(e: &storage::error::Error) -> RespData {
+    match e {
+        storage::error::Error::Aborted {
+            reason: AbortReason::TimedOut,
+            ..
+        } => timeout_reply(),
+        storage::error::Error::InvalidDbIndex { .. } => {
+            RespData::Error("ERR DB index is out of range".to_string().into())
+        }
+        storage::error::Error::WrongType { .. }
+        | storage::error::Error::NoGroup { .. }
+        | storage::error::Error::OffsetNotApplied { .. }
+        | storage::error::Error::CrossSlot { .. } => RespData::Error(e.to_string().into()),
+        _ => RespData::Error(format!("ERR {e}").into()),
+    }
+}
+
 #[macro_export]
 macro_rules! impl_cmd_meta {
     () => {
@@ -194,6 +298,54 @@ impl BaseCmdGroup {
         let name = cmd.name().to_lowercase();
         self.sub_cmds.insert(name, cmd);
     }
+
+    /// The reply of `<group> HELP`, one entry per subcommand in the order of
+    /// their names, built from their metadata. Answered by the groups of the
+    /// command table: CLIENT, CLUSTER, CONFIG, DEBUG, MEMORY and OBJECT.
+    /// There is no XINFO command, so no XINFO HELP either.
+    pub fn help_reply(&self) -> RespData {
+        let group = self.meta.name.to_uppercase();
+        let mut names: Vec<&String> = self.sub_cmds.keys().collect();
+        names.sort();
+
+        let mut lines = vec![format!(
+            "{group} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:"
+        )];
+        for name in names {
+            let meta = self.sub_cmds[name].meta();
+            lines.push(name.to_uppercase());
+            lines.push(format!("    {}", describe_sub_cmd(meta)));
+        }
+        lines.push("HELP".to_string());
+        lines.push("    Print this help.".to_string());
+
+        RespData::Array(Some(
+            lines
+                .into_iter()
+                .map(|line| RespData::SimpleString(line.into()))
+                .collect(),
+        ))
+    }
+}
+
+// Describes a subcommand by the arguments it takes after its name and
+// whether it changes the dataset
+fn describe_sub_cmd(meta: &CmdMeta) -> String {
+    let args = meta.arity.unsigned_abs().saturating_sub(2);
+    let plural = if args == 1 { "" } else { "s" };
+    let takes = match (meta.arity < 0, args) {
+        (false, 0) => "Takes no arguments".to_string(),
+        (false, n) => format!("Takes {n} argument{plural}"),
+        (true, n) => format!("Takes at least {n} argument{plural}"),
+    };
+    let access = if meta.flags.contains(CmdFlags::WRITE) {
+        "write"
+    } else if meta.flags.contains(CmdFlags::ADMIN) {
+        "admin"
+    } else {
+        "read-only"
+    };
+    format!("{takes}, {access}.")
 }
 
 impl Cmd for BaseCmdGroup {
@@ -228,6 +380,8 @@ impl Cmd for BaseCmdGroup {
         let sub_cmd_name = String::from_utf8_lossy(&client.argv()[1]).to_lowercase();
         if let Some(sub_cmd) = self.sub_cmds.get(&sub_cmd_name) {
             sub_cmd.execute(client, storage);
+        } else if sub_cmd_name == "help" && client.argv().len() == 2 {
+            *client.reply_mut() = self.help_reply();
         } else {
             let err_msg = format!("ERR unknown command '{} {}'", self.name(), sub_cmd_name);
             *client.reply_mut() = RespData::Error(err_msg.into());
@@ -0,0 +1,80 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::lmpop::parse_list_end;
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+
+#[derive(Clone, Default)]
+pub struct LMoveCmd {
+    meta: CmdMeta,
+}
+
+impl LMoveCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "lmove".to_string(),
+                arity: 5, // LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+                flags: CmdFlags::WRITE,
+                acl_category: AclCategory::LIST | AclCategory::WRITE | AclCategory::SLOW,
+                first_key: 1,
+                last_key: 2,
+                key_step: 1,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for LMoveCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    /// LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+    fn do_initial(&self, client: &mut Client) -> bool {
+        if !self.check_arg(client.argv().len()) {
+            *client.reply_mut() = RespData::Error(
+                "ERR wrong number of arguments for 'lmove' command"
+                    .to_string()
+                    .into(),
+            );
+            return false;
+        }
+        let key = client.argv()[1].clone();
+        client.set_key(&key);
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let argv = client.argv();
+        let (Some(from), Some(to)) = (parse_list_end(&argv[3]), parse_list_end(&argv[4])) else {
+            *client.reply_mut() = RespData::Error("ERR syntax error".to_string().into());
+            return;
+        };
+
+        *client.reply_mut() = match storage.lmove(&argv[1], &argv[2], from, to) {
+            Ok(value) => RespData::BulkString(value.map(Into::into)),
+            Err(e) => storage_error_reply(&e),
+        };
+    }
+}
@@ -0,0 +1,149 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+use storage::ListEnd;
+
+#[derive(Clone, Default)]
+pub struct LMPopCmd {
+    meta: CmdMeta,
+}
+
+impl LMPopCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "lmpop".to_string(),
+                arity: -4, // LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]
+                flags: CmdFlags::WRITE,
+                acl_category: AclCategory::LIST | AclCategory::WRITE | AclCategory::SLOW,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// The arguments of LMPOP and BLMPOP after the command name and timeout.
+pub(crate) struct LMPopArgs {
+    pub keys: Vec<Vec<u8>>,
+    pub end: ListEnd,
+    pub count: usize,
+}
+
+/// Parses `LEFT` or `RIGHT`.
+pub(crate) fn parse_list_end(arg: &[u8]) -> Option<ListEnd> {
+    if arg.eq_ignore_ascii_case(b"left") {
+        Some(ListEnd::Left)
+    } else if arg.eq_ignore_ascii_case(b"right") {
+        Some(ListEnd::Right)
+    } else {
+        None
+    }
+}
+
+/// Parses `numkeys key [key ...] LEFT|RIGHT [COUNT count]`.
+pub(crate) fn parse_lmpop_args(args: &[Vec<u8>]) -> Result<LMPopArgs, RespData> {
+    let syntax_error = || RespData::Error("ERR syntax error".to_string().into());
+    let numkeys = std::str::from_utf8(&args[0])
+        .ok()
+        .and_then(|n| n.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .ok_or_else(|| {
+            RespData::Error("ERR numkeys should be greater than 0".to_string().into())
+        })?;
+    let Some(keys) = args.get(1..=numkeys) else {
+        return Err(syntax_error());
+    };
+    let end = args
+        .get(numkeys + 1)
+        .and_then(|arg| parse_list_end(arg))
+        .ok_or_else(syntax_error)?;
+
+    let count = match &args[numkeys + 2..] {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"count") => std::str::from_utf8(count)
+            .ok()
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|&n| n > 0)
+            .ok_or_else(|| {
+                RespData::Error("ERR count should be greater than 0".to_string().into())
+            })?,
+        _ => return Err(syntax_error()),
+    };
+    Ok(LMPopArgs {
+        keys: keys.to_vec(),
+        end,
+        count,
+    })
+}
+
+/// The reply of LMPOP and BLMPOP, the key and its elements or a nil.
+pub(crate) fn lmpop_reply(popped: Option<(Vec<u8>, Vec<Vec<u8>>)>) -> RespData {
+    match popped {
+        Some((key, values)) => RespData::Array(Some(vec![
+            RespData::BulkString(Some(key.into())),
+            RespData::Array(Some(
+                values
+                    .into_iter()
+                    .map(|value| RespData::BulkString(Some(value.into())))
+                    .collect(),
+            )),
+        ])),
+        None => RespData::Array(None),
+    }
+}
+
+impl Cmd for LMPopCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    /// LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]
+    fn do_initial(&self, client: &mut Client) -> bool {
+        if !self.check_arg(client.argv().len()) {
+            *client.reply_mut() = RespData::Error(
+                "ERR wrong number of arguments for 'lmpop' command"
+                    .to_string()
+                    .into(),
+            );
+            return false;
+        }
+        let key = client.argv()[2].clone();
+        client.set_key(&key);
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let args = match parse_lmpop_args(&client.argv()[1..]) {
+            Ok(args) => args,
+            Err(reply) => {
+                *client.reply_mut() = reply;
+                return;
+            }
+        };
+
+        *client.reply_mut() = match storage.lmpop(&args.keys, args.end, args.count) {
+            Ok(popped) => lmpop_reply(popped),
+            Err(e) => storage_error_reply(&e),
+        };
+    }
+}
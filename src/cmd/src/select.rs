@@ -0,0 +1,84 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{AclCategory, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+
+#[derive(Clone, Default)]
+pub struct SelectCmd {
+    meta: CmdMeta,
+}
+
+impl SelectCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "select".to_string(),
+                arity: 2, // SELECT index
+                flags: CmdFlags::FAST,
+                acl_category: AclCategory::FAST | AclCategory::CONNECTION,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for SelectCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, client: &mut Client) -> bool {
+        if !self.check_arg(client.argv().len()) {
+            *client.reply_mut() = RespData::Error(
+                "ERR wrong number of arguments for 'select' command"
+                    .to_string()
+                    .into(),
+            );
+            return false;
+        }
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let Some(index) = parse_db_index(&client.argv()[1]) else {
+            *client.reply_mut() = RespData::Error(
+                "ERR value is not an integer or out of range"
+                    .to_string()
+                    .into(),
+            );
+            return;
+        };
+        let databases = storage.databases().map_or(1, |dbs| dbs.len());
+        if index >= databases {
+            *client.reply_mut() =
+                RespData::Error("ERR DB index is out of range".to_string().into());
+            return;
+        }
+
+        client.set_db_index(index);
+        *client.reply_mut() = RespData::SimpleString("OK".to_string().into());
+    }
+}
+
+pub(crate) fn parse_db_index(arg: &[u8]) -> Option<usize> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
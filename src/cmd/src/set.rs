@@ -18,11 +18,12 @@
  */
 
 use crate::{impl_cmd_clone_box, impl_cmd_meta};
-use crate::{Cmd, CmdFlags, CmdMeta};
+use crate::{storage_error_reply, Cmd, CmdFlags, CmdMeta};
 use client::Client;
 use resp::RespData;
 use std::sync::Arc;
 use storage::storage::Storage;
+use storage::{SetCondition, SetOptions};
 
 #[derive(Clone, Default)]
 pub struct SetCmd {
@@ -34,21 +35,46 @@ impl SetCmd {
         Self {
             meta: CmdMeta {
                 name: "set".to_string(),
-                arity: 3, // SET key value
+                arity: -3, // SET key value [NX | XX] [GET]
                 flags: CmdFlags::WRITE,
+                first_key: 1,
+                last_key: 1,
+                key_step: 1,
                 ..Default::default()
             },
         }
     }
 }
 
+/// Parses the options after the value, None on a syntax error.
+fn parse_set_options(args: &[Vec<u8>]) -> Option<SetOptions> {
+    let mut options = SetOptions::default();
+    for arg in args {
+        let condition = if arg.eq_ignore_ascii_case(b"nx") {
+            SetCondition::IfMissing
+        } else if arg.eq_ignore_ascii_case(b"xx") {
+            SetCondition::IfExists
+        } else if arg.eq_ignore_ascii_case(b"get") {
+            options.get = true;
+            continue;
+        } else {
+            return None;
+        };
+        if options.condition != SetCondition::Always && options.condition != condition {
+            return None;
+        }
+        options.condition = condition;
+    }
+    Some(options)
+}
+
 impl Cmd for SetCmd {
     impl_cmd_meta!();
     impl_cmd_clone_box!();
 
-    /// SET key value
+    /// SET key value [NX | XX] [GET]
     fn do_initial(&self, client: &mut Client) -> bool {
-        // TODO: support xx, nx, ex, px
+        // TODO: support ex, px
         let argv = client.argv();
 
         let key = argv[1].clone();
@@ -58,18 +84,18 @@ impl Cmd for SetCmd {
     }
 
     fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let Some(options) = parse_set_options(&client.argv()[3..]) else {
+            *client.reply_mut() = RespData::Error("ERR syntax error".to_string().into());
+            return;
+        };
         let key = client.key();
         let value = &client.argv()[2];
 
-        let result = storage.set(key, value);
-
-        match result {
-            Ok(_) => {
-                *client.reply_mut() = RespData::SimpleString("OK".to_string().into());
-            }
-            Err(e) => {
-                *client.reply_mut() = RespData::Error(format!("ERR {e}").into());
-            }
-        }
+        *client.reply_mut() = match storage.set_with(key, value, options) {
+            Ok(result) if options.get => RespData::BulkString(result.old_value.map(Into::into)),
+            Ok(result) if result.did_set => RespData::SimpleString("OK".to_string().into()),
+            Ok(_) => RespData::BulkString(None),
+            Err(e) => storage_error_reply(&e),
+        };
     }
 }
@@ -0,0 +1,89 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::select::parse_db_index;
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+
+/// SWAPDB index1 index2
+///
+/// Flagged `EXCLUSIVE`: it runs without pinning the databases, because the
+/// swap waits for every command pinned before it.
+#[derive(Clone, Default)]
+pub struct SwapDbCmd {
+    meta: CmdMeta,
+}
+
+impl SwapDbCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "swapdb".to_string(),
+                arity: 3, // SWAPDB index1 index2
+                flags: CmdFlags::WRITE | CmdFlags::FAST | CmdFlags::EXCLUSIVE,
+                acl_category: AclCategory::KEYSPACE
+                    | AclCategory::WRITE
+                    | AclCategory::FAST
+                    | AclCategory::DANGEROUS,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for SwapDbCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, client: &mut Client) -> bool {
+        if !self.check_arg(client.argv().len()) {
+            *client.reply_mut() = RespData::Error(
+                "ERR wrong number of arguments for 'swapdb' command"
+                    .to_string()
+                    .into(),
+            );
+            return false;
+        }
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let Some(a) = parse_db_index(&client.argv()[1]) else {
+            *client.reply_mut() = RespData::Error("ERR invalid first DB index".to_string().into());
+            return;
+        };
+        let Some(b) = parse_db_index(&client.argv()[2]) else {
+            *client.reply_mut() = RespData::Error("ERR invalid second DB index".to_string().into());
+            return;
+        };
+        let Some(databases) = storage.databases() else {
+            *client.reply_mut() =
+                RespData::Error("ERR DB index is out of range".to_string().into());
+            return;
+        };
+
+        *client.reply_mut() = match databases.swap(a, b) {
+            Ok(()) => RespData::SimpleString("OK".to_string().into()),
+            Err(e) => storage_error_reply(&e),
+        };
+    }
+}
@@ -56,12 +56,30 @@ pub fn create_command_table() -> CmdTable {
         cmd_table,
         crate::set::SetCmd,
         crate::get::GetCmd,
+        crate::info::InfoCmd,
+        crate::keys::KeysCmd,
+        crate::select::SelectCmd,
+        crate::swapdb::SwapDbCmd,
+        crate::exists::ExistsCmd,
+        crate::touch::TouchCmd,
+        crate::del::DelCmd,
+        crate::undelete::UndeleteCmd,
+        crate::hgetdel::HGetDelCmd,
+        crate::hgetex::HGetExCmd,
+        crate::lmpop::LMPopCmd,
+        crate::blmpop::BLMPopCmd,
+        crate::lmove::LMoveCmd,
         // TODO: add more commands...
     );
 
     register_group_cmd!(
         cmd_table,
         crate::group_client::new_client_group_cmd,
+        crate::group_cluster::new_cluster_group_cmd,
+        crate::group_config::new_config_group_cmd,
+        crate::group_debug::new_debug_group_cmd,
+        crate::group_memory::new_memory_group_cmd,
+        crate::group_object::new_object_group_cmd,
         // TODO: add more group commands...
     );
 
@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Per command execution deadlines.
+//!
+//! The dispatcher derives a deadline for every command from its class, or
+//! from a per command override, and attaches it to the cancel token of the
+//! client. Long running storage operations poll that token and give up with a
+//! -TIMEOUT error once the deadline passed.
+
+use crate::{Cmd, CmdFlags};
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct CommandTimeouts {
+    /// Commands flagged FAST.
+    pub fast: Option<Duration>,
+    /// Commands that don't modify the dataset.
+    pub read: Option<Duration>,
+    /// Commands that may modify the dataset.
+    pub write: Option<Duration>,
+    /// Administrative commands.
+    pub admin: Option<Duration>,
+    /// Timeouts of specific commands, by lowercase name.
+    overrides: HashMap<String, Option<Duration>>,
+}
+
+impl Default for CommandTimeouts {
+    fn default() -> Self {
+        Self {
+            fast: Some(Duration::from_secs(1)),
+            read: Some(Duration::from_secs(10)),
+            write: Some(Duration::from_secs(10)),
+            admin: None,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl CommandTimeouts {
+    /// No deadline for any command.
+    pub fn unlimited() -> Self {
+        Self {
+            fast: None,
+            read: None,
+            write: None,
+            admin: None,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Overrides the timeout of one command, `None` disables its deadline.
+    pub fn set_timeout(&mut self, name: &str, timeout: Option<Duration>) -> &mut Self {
+        self.overrides.insert(name.to_lowercase(), timeout);
+        self
+    }
+
+    /// Returns the deadline to apply to `cmd`, `None` if it may run forever.
+    pub fn timeout_for(&self, cmd: &dyn Cmd) -> Option<Duration> {
+        if let Some(timeout) = self.overrides.get(&cmd.name().to_lowercase()) {
+            return *timeout;
+        }
+        // Blocking commands wait up to the timeout of their arguments
+        if cmd.has_flag(CmdFlags::BLOCKING) {
+            None
+        } else if cmd.has_flag(CmdFlags::ADMIN) {
+            self.admin
+        } else if cmd.has_flag(CmdFlags::FAST) {
+            self.fast
+        } else if cmd.has_flag(CmdFlags::WRITE) {
+            self.write
+        } else {
+            self.read
+        }
+    }
+}
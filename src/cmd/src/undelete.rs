@@ -0,0 +1,81 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::{impl_cmd_clone_box, impl_cmd_meta};
+use crate::{storage_error_reply, AclCategory, Cmd, CmdFlags, CmdMeta};
+use client::Client;
+use resp::RespData;
+use std::sync::Arc;
+use storage::storage::Storage;
+
+/// Restores a key deleted while soft deletion was enabled, replies 1 when the
+/// key was restored and 0 otherwise.
+#[derive(Clone, Default)]
+pub struct UndeleteCmd {
+    meta: CmdMeta,
+}
+
+impl UndeleteCmd {
+    pub fn new() -> Self {
+        Self {
+            meta: CmdMeta {
+                name: "undelete".to_string(),
+                arity: 2, // UNDELETE key
+                flags: CmdFlags::WRITE | CmdFlags::FAST,
+                acl_category: AclCategory::KEYSPACE | AclCategory::WRITE | AclCategory::FAST,
+                first_key: 1,
+                last_key: 1,
+                key_step: 1,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Cmd for UndeleteCmd {
+    impl_cmd_meta!();
+    impl_cmd_clone_box!();
+
+    fn do_initial(&self, client: &mut Client) -> bool {
+        if !self.check_arg(client.argv().len()) {
+            *client.reply_mut() = RespData::Error(
+                "ERR wrong number of arguments for 'undelete' command"
+                    .to_string()
+                    .into(),
+            );
+            return false;
+        }
+        let key = client.argv()[1].clone();
+        client.set_key(&key);
+        true
+    }
+
+    fn do_cmd(&self, client: &mut Client, storage: Arc<Storage>) {
+        let key = client.key();
+        let result = storage.undelete(key);
+
+        match result {
+            Ok(result) => {
+                *client.reply_mut() = RespData::Integer(result.restored as i64);
+            }
+            Err(e) => {
+                *client.reply_mut() = storage_error_reply(&e);
+            }
+        }
+    }
+}
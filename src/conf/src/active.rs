@@ -0,0 +1,81 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::config::Config;
+use crate::error::Error;
+use snafu::OptionExt;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+//the configuration the server runs with and the file it was loaded from,
+//read by CONFIG GET, changed by CONFIG SET and written by CONFIG REWRITE.
+//An option read at startup takes a value set at runtime at the next start
+pub struct ActiveConfig {
+    config: Mutex<Config>,
+    path: Option<PathBuf>,
+}
+
+impl ActiveConfig {
+    pub fn new(config: Config, path: Option<PathBuf>) -> Self {
+        Self {
+            config: Mutex::new(config),
+            path,
+        }
+    }
+
+    //every option with its value, see Config::entries
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        self.config
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries()
+    }
+
+    //sets an option, the configuration is left as it was if the value is
+    //invalid
+    pub fn set(&self, key: &str, value: &str) -> Result<(), Error> {
+        let mut config = self.config.lock().unwrap_or_else(|e| e.into_inner());
+        *config = config.with_option(key, value)?;
+        Ok(())
+    }
+
+    //writes the configuration back to the file it was loaded from
+    pub fn rewrite(&self) -> Result<(), Error> {
+        let path = self
+            .path
+            .as_ref()
+            .context(crate::error::NoConfigFileSnafu)?;
+        let config = self.config.lock().unwrap_or_else(|e| e.into_inner());
+        config.rewrite(path)
+    }
+}
+
+fn active_config_handle() -> &'static OnceLock<ActiveConfig> {
+    static ACTIVE: OnceLock<ActiveConfig> = OnceLock::new();
+    &ACTIVE
+}
+
+//installs the configuration the CONFIG commands act on, returns false if
+//one was already installed
+pub fn install_active_config(config: ActiveConfig) -> bool {
+    active_config_handle().set(config).is_ok()
+}
+
+pub fn active_config() -> Option<&'static ActiveConfig> {
+    active_config_handle().get()
+}
@@ -16,15 +16,22 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use crate::de_func::{deserialize_bool_from_yes_no, deserialize_memory};
+use crate::de_func::{
+    deserialize_bool_from_yes_no, deserialize_class_weights, deserialize_command_timeouts,
+    deserialize_memory, format_class_weights, format_command_timeouts, format_memory,
+};
 use crate::error::Error;
 use serde::Deserialize;
 use serde_ini;
-use snafu::ResultExt;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use validator::Validate;
 
 //config struct define
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Clone, Deserialize, Validate)]
 #[serde(default)]
 pub struct Config {
     #[validate(range(min = 1024, max = 65535))]
@@ -40,6 +47,52 @@ pub struct Config {
 
     #[serde(deserialize_with = "deserialize_bool_from_yes_no")]
     pub redis_compatible_mode: bool,
+
+    //write guarantees: strict fsyncs every write, normal fsyncs the WAL
+    //every wal_sync_interval milliseconds, relaxed skips the WAL
+    pub durability: String,
+
+    #[validate(range(min = 1))]
+    pub wal_sync_interval: u64,
+
+    //number of databases SELECT can switch between, each one is a storage
+    //of its own
+    #[validate(range(min = 1))]
+    pub databases: usize,
+
+    //deadlines of specific commands over those of their class, in
+    //milliseconds, 0 for none: `keys:0 flushall:60000`
+    #[serde(deserialize_with = "deserialize_command_timeouts")]
+    pub command_timeouts: BTreeMap<String, u64>,
+
+    //commands running at once, the others wait for a slot; 0 for one less
+    //than the CPUs, leaving one to the connections
+    pub command_slots: usize,
+
+    //commands of one connection running at once, so that a pipelining
+    //client cannot take every slot
+    #[validate(range(min = 1))]
+    pub connection_slots: usize,
+
+    //share of the freed slots each class of waiting commands gets per
+    //round, over the defaults: `replication:8 admin:4 point:4 bulk:1`
+    #[serde(deserialize_with = "deserialize_class_weights")]
+    pub command_class_weights: BTreeMap<String, u32>,
+
+    //join a cluster: run the cluster bus and accept the CLUSTER commands
+    #[serde(deserialize_with = "deserialize_bool_from_yes_no")]
+    pub cluster_enabled: bool,
+
+    //address the cluster bus listens on and announces to the other nodes
+    pub cluster_bus_addr: String,
+
+    //milliseconds a node may be unreachable before it is flagged failing
+    #[validate(range(min = 100))]
+    pub cluster_node_timeout: u64,
+
+    //the [rocksdb] section: `<option> = <value>` tunes every column family,
+    //`<cf>.<option> = <value>` one of them over it, checked by the storage
+    pub rocksdb: BTreeMap<String, String>,
 }
 
 //set default value for config
@@ -51,6 +104,17 @@ impl Default for Config {
             memory: 1024 * 1024 * 1024,
             log_dir: "/data/kiwi_rs/logs".to_string(),
             redis_compatible_mode: false,
+            durability: "normal".to_string(),
+            wal_sync_interval: 1000,
+            databases: 1,
+            command_timeouts: BTreeMap::new(),
+            command_slots: 0,
+            connection_slots: 1,
+            command_class_weights: BTreeMap::new(),
+            cluster_enabled: false,
+            cluster_bus_addr: "127.0.0.1:19221".to_string(),
+            cluster_node_timeout: 15000,
+            rocksdb: BTreeMap::new(),
         }
     }
 }
@@ -60,9 +124,13 @@ impl Config {
     pub fn load(path: &str) -> Result<Self, Error> {
         let content =
             std::fs::read_to_string(path).context(crate::error::ConfigFileSnafu { path })?;
+        Self::parse(&content)
+    }
 
+    //parse and validate the content of a config file
+    fn parse(content: &str) -> Result<Self, Error> {
         let config: Config =
-            serde_ini::from_str(&content).context(crate::error::InvalidConfigSnafu {})?;
+            serde_ini::from_str(content).context(crate::error::InvalidConfigSnafu {})?;
 
         config
             .validate()
@@ -70,4 +138,158 @@ impl Config {
 
         Ok(config)
     }
+
+    //CONFIG SET: the configuration with the option key set to value, parsed
+    //and validated as if it was written in the config file
+    pub fn with_option(&self, key: &str, value: &str) -> Result<Self, Error> {
+        let mut entries = self.entries();
+        let entry = entries
+            .iter_mut()
+            .find(|(name, _)| *name == key)
+            .context(crate::error::UnknownOptionSnafu { key })?;
+        ensure!(
+            !value.contains(['\n', '\r']),
+            crate::error::UnwritableValueSnafu { key: entry.0 }
+        );
+        entry.1 = value.to_string();
+
+        let mut content: String = entries
+            .iter()
+            .map(|(key, value)| format!("{key} = {value}\n"))
+            .collect();
+        content += &self.rocksdb_section();
+        Self::parse(&content)
+    }
+
+    //the [rocksdb] section as written in the config file, empty without
+    //options
+    fn rocksdb_section(&self) -> String {
+        if self.rocksdb.is_empty() {
+            return String::new();
+        }
+        let options: String = self
+            .rocksdb
+            .iter()
+            .map(|(key, value)| format!("{key} = {value}\n"))
+            .collect();
+        format!("[rocksdb]\n{options}")
+    }
+
+    //every option with its value, as written in the config file
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+        vec![
+            ("port", self.port.to_string()),
+            ("timeout", self.timeout.to_string()),
+            ("log_dir", self.log_dir.clone()),
+            ("memory", format_memory(self.memory)),
+            ("redis_compatible_mode", yes_no(self.redis_compatible_mode)),
+            ("durability", self.durability.clone()),
+            ("wal_sync_interval", self.wal_sync_interval.to_string()),
+            ("databases", self.databases.to_string()),
+            (
+                "command_timeouts",
+                format_command_timeouts(&self.command_timeouts),
+            ),
+            ("command_slots", self.command_slots.to_string()),
+            ("connection_slots", self.connection_slots.to_string()),
+            (
+                "command_class_weights",
+                format_class_weights(&self.command_class_weights),
+            ),
+            ("cluster_enabled", yes_no(self.cluster_enabled)),
+            ("cluster_bus_addr", self.cluster_bus_addr.clone()),
+            (
+                "cluster_node_timeout",
+                self.cluster_node_timeout.to_string(),
+            ),
+        ]
+    }
+
+    //CONFIG REWRITE: writes the active configuration back to the file at
+    //path, so that the values set at runtime survive a restart. Comments,
+    //unknown lines, sections and the order of the file are kept, the options
+    //already in the file are updated in place and the others are added
+    //before the first section only when they differ from their default. The
+    //file is replaced atomically, the previous one is kept in path.bak
+    pub fn rewrite(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let entries = self.entries();
+        for (key, value) in &entries {
+            ensure!(
+                !value.contains(['\n', '\r']),
+                crate::error::UnwritableValueSnafu { key: *key }
+            );
+        }
+
+        let old = match fs::read_to_string(path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context(crate::error::ConfigFileSnafu { path }),
+        };
+        let content = rewrite_content(old.as_deref().unwrap_or(""), &entries);
+
+        let rewrite_err = || crate::error::RewriteConfigSnafu { path };
+        let tmp_path = with_suffix(path, ".tmp");
+        let mut tmp = File::create(&tmp_path).context(rewrite_err())?;
+        tmp.write_all(content.as_bytes()).context(rewrite_err())?;
+        tmp.sync_all().context(rewrite_err())?;
+        if old.is_some() {
+            fs::copy(path, with_suffix(path, ".bak")).context(rewrite_err())?;
+        }
+        fs::rename(&tmp_path, path).context(rewrite_err())?;
+        Ok(())
+    }
+}
+
+//the lines of content with the options of entries set, see Config::rewrite
+fn rewrite_content(content: &str, entries: &[(&'static str, String)]) -> String {
+    let defaults = Config::default().entries();
+    let mut written = vec![false; entries.len()];
+    let mut lines = Vec::new();
+    //the options are all before the first section, the lines of the
+    //sections are kept as they are
+    let mut first_section = None;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if first_section.is_none() && trimmed.starts_with('[') {
+            first_section = Some(lines.len());
+        }
+        let key = match trimmed.split_once('=') {
+            Some((key, _)) if first_section.is_none() && !trimmed.starts_with([';', '#']) => {
+                key.trim()
+            }
+            _ => {
+                lines.push(line.to_string());
+                continue;
+            }
+        };
+        match entries.iter().position(|(name, _)| *name == key) {
+            // a repeated option is dropped, only the first one is kept
+            Some(i) if written[i] => {}
+            Some(i) => {
+                let indent = &line[..line.len() - trimmed.len()];
+                lines.push(format!("{indent}{key} = {}", entries[i].1));
+                written[i] = true;
+            }
+            None => lines.push(line.to_string()),
+        }
+    }
+    let missing = entries
+        .iter()
+        .enumerate()
+        .filter(|(i, (_, value))| !written[*i] && defaults[*i].1 != *value)
+        .map(|(_, (key, value))| format!("{key} = {value}"));
+    let at = first_section.unwrap_or(lines.len());
+    lines.splice(at..at, missing);
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
 }
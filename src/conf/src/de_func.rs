@@ -18,6 +18,7 @@
  */
 use crate::error::MemoryParseError;
 use serde::{de, Deserialize, Deserializer};
+use std::collections::BTreeMap;
 pub fn deserialize_bool_from_yes_no<'de, D>(deserializer: D) -> Result<bool, D::Error>
 where
     D: Deserializer<'de>,
@@ -43,6 +44,98 @@ where
     }
 }
 
+pub fn deserialize_command_timeouts<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    parse_command_timeouts(s.as_str()).map_err(de::Error::custom)
+}
+
+// parses `name:milliseconds` pairs separated by spaces, such as
+// `keys:0 flushall:60000`, the names lowercased
+pub fn parse_command_timeouts(input: &str) -> Result<BTreeMap<String, u64>, String> {
+    let mut timeouts = BTreeMap::new();
+    for pair in input.split_whitespace() {
+        let (name, millis) = pair
+            .split_once(':')
+            .ok_or_else(|| format!("expected name:milliseconds, got '{pair}'"))?;
+        let millis = millis
+            .parse()
+            .map_err(|e| format!("invalid timeout of '{name}': {e}"))?;
+        timeouts.insert(name.to_lowercase(), millis);
+    }
+    Ok(timeouts)
+}
+
+// formats timeouts as parse_command_timeouts reads them
+pub fn format_command_timeouts(timeouts: &BTreeMap<String, u64>) -> String {
+    timeouts
+        .iter()
+        .map(|(name, millis)| format!("{name}:{millis}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn deserialize_class_weights<'de, D>(deserializer: D) -> Result<BTreeMap<String, u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    parse_class_weights(s.as_str()).map_err(de::Error::custom)
+}
+
+// the classes commands are scheduled by
+const COMMAND_CLASSES: [&str; 4] = ["replication", "admin", "point", "bulk"];
+
+// parses `class:weight` pairs separated by spaces, such as `point:8 bulk:1`,
+// the classes lowercased
+pub fn parse_class_weights(input: &str) -> Result<BTreeMap<String, u32>, String> {
+    let mut weights = BTreeMap::new();
+    for pair in input.split_whitespace() {
+        let (class, weight) = pair
+            .split_once(':')
+            .ok_or_else(|| format!("expected class:weight, got '{pair}'"))?;
+        let class = class.to_lowercase();
+        if !COMMAND_CLASSES.contains(&class.as_str()) {
+            return Err(format!("unknown command class '{class}'"));
+        }
+        let weight = weight
+            .parse()
+            .map_err(|e| format!("invalid weight of '{class}': {e}"))?;
+        weights.insert(class, weight);
+    }
+    Ok(weights)
+}
+
+// formats weights as parse_class_weights reads them
+pub fn format_class_weights(weights: &BTreeMap<String, u32>) -> String {
+    weights
+        .iter()
+        .map(|(class, weight)| format!("{class}:{weight}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// formats bytes with the largest unit dividing it exactly, the reverse of
+// parse_memory
+pub fn format_memory(bytes: u64) -> String {
+    const UNITS: [(&str, u64); 4] = [
+        ("TB", 1 << 40),
+        ("GB", 1 << 30),
+        ("MB", 1 << 20),
+        ("KB", 1 << 10),
+    ];
+    for (unit, size) in UNITS {
+        if bytes != 0 && bytes.is_multiple_of(size) {
+            return format!("{}{}", bytes / size, unit);
+        }
+    }
+    bytes.to_string()
+}
+
 pub fn parse_memory(input: &str) -> Result<u64, MemoryParseError> {
     let cleaned_input = input.trim().replace(',', "").to_uppercase();
 
@@ -36,6 +36,18 @@ pub enum Error {
 
     #[snafu(display("Invalid memory: {}", source))]
     MemoryParse { source: MemoryParseError },
+
+    #[snafu(display("Could not rewrite file {}: {}", path.display(), source))]
+    RewriteConfig { source: io::Error, path: PathBuf },
+
+    #[snafu(display("value of {} can not be written on a single line", key))]
+    UnwritableValue { key: &'static str },
+
+    #[snafu(display("unknown option {}", key))]
+    UnknownOption { key: String },
+
+    #[snafu(display("the server is running without a config file"))]
+    NoConfigFile,
 }
 
 #[derive(Debug, Snafu)]
@@ -16,6 +16,7 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+pub mod active;
 pub mod config;
 pub mod de_func;
 pub mod error;
@@ -42,10 +43,200 @@ mod tests {
             redis_compatible_mode: false,
             log_dir: "".to_string(),
             memory: 1024,
+            ..Config::default()
         };
-        assert_eq!(false, invalid_config.validate().is_ok());
+        assert!(invalid_config.validate().is_err());
 
         invalid_config.port = 8080;
-        assert_eq!(true, invalid_config.validate().is_ok());
+        assert!(invalid_config.validate().is_ok());
+
+        invalid_config.cluster_node_timeout = 10;
+        assert!(invalid_config.validate().is_err());
+
+        invalid_config.cluster_node_timeout = 15000;
+        invalid_config.wal_sync_interval = 0;
+        assert!(invalid_config.validate().is_err());
+
+        invalid_config.wal_sync_interval = 1000;
+        invalid_config.databases = 0;
+        assert!(invalid_config.validate().is_err());
+    }
+
+    #[test]
+    fn test_cluster_options() {
+        let path =
+            std::env::temp_dir().join(format!("kiwi_conf_cluster_{}.ini", std::process::id()));
+        std::fs::write(
+            &path,
+            "cluster_enabled = yes\ncluster_bus_addr = 10.0.0.1:19221\ncluster_node_timeout = 5000\n",
+        )
+        .unwrap();
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+        assert!(config.cluster_enabled);
+        assert_eq!(config.cluster_bus_addr, "10.0.0.1:19221");
+        assert_eq!(config.cluster_node_timeout, 5000);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_command_timeouts() {
+        let path =
+            std::env::temp_dir().join(format!("kiwi_conf_timeouts_{}.ini", std::process::id()));
+        std::fs::write(&path, "command_timeouts = KEYS:0 flushall:60000\n").unwrap();
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(config.command_timeouts.get("keys"), Some(&0));
+        assert_eq!(config.command_timeouts.get("flushall"), Some(&60000));
+        assert_eq!(
+            de_func::format_command_timeouts(&config.command_timeouts),
+            "flushall:60000 keys:0"
+        );
+
+        std::fs::write(&path, "command_timeouts = keys\n").unwrap();
+        assert!(Config::load(path.to_str().unwrap()).is_err());
+        std::fs::write(&path, "command_timeouts = keys:-1\n").unwrap();
+        assert!(Config::load(path.to_str().unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_command_class_weights() {
+        let path =
+            std::env::temp_dir().join(format!("kiwi_conf_weights_{}.ini", std::process::id()));
+        std::fs::write(
+            &path,
+            "command_slots = 4\nconnection_slots = 2\ncommand_class_weights = POINT:8 bulk:2\n",
+        )
+        .unwrap();
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(config.command_slots, 4);
+        assert_eq!(config.connection_slots, 2);
+        assert_eq!(config.command_class_weights.get("point"), Some(&8));
+        assert_eq!(config.command_class_weights.get("bulk"), Some(&2));
+        assert_eq!(config.command_class_weights.get("admin"), None);
+        assert_eq!(
+            de_func::format_class_weights(&config.command_class_weights),
+            "bulk:2 point:8"
+        );
+
+        std::fs::write(&path, "command_class_weights = scan:1\n").unwrap();
+        assert!(Config::load(path.to_str().unwrap()).is_err());
+        std::fs::write(&path, "command_class_weights = bulk:-1\n").unwrap();
+        assert!(Config::load(path.to_str().unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rocksdb_section() {
+        let path =
+            std::env::temp_dir().join(format!("kiwi_conf_rocksdb_{}.ini", std::process::id()));
+        std::fs::write(
+            &path,
+            "port = 9221\n[rocksdb]\nmax_background_jobs = 8\ncompression_per_level = none,lz4\nzset_score_cf.block_size = 32768\n",
+        )
+        .unwrap();
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(config.port, 9221);
+        assert_eq!(config.rocksdb.len(), 3);
+        assert_eq!(config.rocksdb["max_background_jobs"], "8");
+        assert_eq!(config.rocksdb["compression_per_level"], "none,lz4");
+        assert_eq!(config.rocksdb["zset_score_cf.block_size"], "32768");
+
+        // CONFIG SET keeps the section
+        let config = config.with_option("timeout", "100").unwrap();
+        assert_eq!(config.timeout, 100);
+        assert_eq!(config.rocksdb["zset_score_cf.block_size"], "32768");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_format_memory() {
+        assert_eq!(de_func::format_memory(10 * 1024 * 1024), "10MB");
+        assert_eq!(de_func::format_memory(1536), "1536");
+        assert_eq!(de_func::format_memory(0), "0");
+        let memory = de_func::parse_memory(&de_func::format_memory(3 << 30)).unwrap();
+        assert_eq!(memory, 3 << 30);
+    }
+
+    #[test]
+    fn test_config_rewrite() {
+        let dir = std::env::temp_dir().join(format!("kiwi_conf_rewrite_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kiwi.ini");
+        let original =
+            "; kiwi config\n    port = 1430\nmemory = 10M\nunknown = kept\nport = 1431\n";
+        std::fs::write(&path, original).unwrap();
+
+        // the repeated port can not be loaded, the rewrite drops it
+        let mut config = Config {
+            port: 7000,
+            timeout: 99,
+            memory: 10 * 1024 * 1024,
+            ..Config::default()
+        };
+        config.rewrite(&path).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "; kiwi config\n    port = 7000\nmemory = 10MB\nunknown = kept\ntimeout = 99\n"
+        );
+        let backup = dir.join("kiwi.ini.bak");
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), original);
+
+        let reloaded = Config::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(reloaded.entries(), config.entries());
+
+        config.log_dir = "a\nb".to_string();
+        assert!(config.rewrite(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_config_rewrite_keeps_sections() {
+        let dir = std::env::temp_dir().join(format!("kiwi_conf_sections_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kiwi.ini");
+        std::fs::write(&path, "port = 1430\n[other]\ntimeout = 7\n").unwrap();
+
+        let config = Config {
+            port: 7000,
+            timeout: 99,
+            ..Config::default()
+        };
+        config.rewrite(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "port = 7000\ntimeout = 99\n[other]\ntimeout = 7\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_active_config() {
+        let dir = std::env::temp_dir().join(format!("kiwi_conf_active_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kiwi.ini");
+        std::fs::write(&path, "port = 1430\n").unwrap();
+
+        let active = active::ActiveConfig::new(
+            Config::load(path.to_str().unwrap()).unwrap(),
+            Some(path.clone()),
+        );
+        active.set("timeout", "99").unwrap();
+        active.set("memory", "2GB").unwrap();
+        assert!(active.set("timeout", "0").is_err());
+        assert!(active.set("no_such_option", "1").is_err());
+        assert!(active.set("log_dir", "a\nb").is_err());
+        let entries = active.entries();
+        assert!(entries.contains(&("timeout", "99".to_string())));
+        assert!(entries.contains(&("memory", "2GB".to_string())));
+
+        active.rewrite().unwrap();
+        let reloaded = Config::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(reloaded.entries(), entries);
+
+        let without_file = active::ActiveConfig::new(Config::default(), None);
+        assert!(without_file.rewrite().is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
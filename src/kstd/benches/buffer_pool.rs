@@ -0,0 +1,67 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Encoding into a fresh buffer per command against a pooled buffer. The
+//! pooled runs do no allocation once the pool is warm.
+//!
+//! cargo bench -p kstd --bench buffer_pool
+
+use bytes::{BufMut, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kstd::buffer_pool;
+
+// A string value: type, user value, reserve, ctime and etime
+fn encode(buf: &mut BytesMut, value: &[u8]) {
+    buf.put_u8(0);
+    buf.put_slice(value);
+    buf.put_bytes(0, 16);
+    buf.put_u64_le(1);
+    buf.put_u64_le(0);
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(1));
+
+    for size in [16usize, 128, 4096] {
+        let value = vec![b'x'; size];
+        let needed = size + 33;
+
+        group.bench_with_input(BenchmarkId::new("fresh", size), &value, |b, value| {
+            b.iter(|| {
+                let mut buf = BytesMut::with_capacity(needed);
+                encode(&mut buf, value);
+                black_box(&buf);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("pooled", size), &value, |b, value| {
+            b.iter(|| {
+                let mut buf = buffer_pool::acquire(needed);
+                encode(&mut buf, value);
+                black_box(&buf);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
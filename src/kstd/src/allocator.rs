@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Statistics about the global allocator.
+//!
+//! The allocator itself is installed by the server binary behind the
+//! `jemalloc` and `mimalloc` features, which also enable the matching feature
+//! of this crate so the statistics are read from the allocator in use.
+
+/// A snapshot of allocator memory usage. Figures the allocator does not
+/// report are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AllocatorStats {
+    /// Bytes allocated by the application.
+    pub allocated: Option<u64>,
+    /// Bytes in pages the allocator has in use, including the unused space
+    /// inside them.
+    pub active: Option<u64>,
+    /// Bytes of physically resident memory.
+    pub resident: Option<u64>,
+}
+
+impl AllocatorStats {
+    /// Resident memory over allocated memory. Values well above 1.0 mean
+    /// memory is held by the allocator without being used.
+    pub fn fragmentation_ratio(&self) -> Option<f64> {
+        match (self.resident, self.allocated) {
+            (Some(resident), Some(allocated)) if allocated > 0 => {
+                Some(resident as f64 / allocated as f64)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The name of the global allocator the binary is built with.
+pub fn name() -> &'static str {
+    if cfg!(feature = "jemalloc") {
+        "jemalloc"
+    } else if cfg!(feature = "mimalloc") {
+        "mimalloc"
+    } else {
+        "libc"
+    }
+}
+
+#[cfg(feature = "jemalloc")]
+pub fn stats() -> AllocatorStats {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // jemalloc caches its statistics until the epoch is advanced.
+    if epoch::advance().is_err() {
+        return AllocatorStats::default();
+    }
+    AllocatorStats {
+        allocated: stats::allocated::read().ok().map(|v| v as u64),
+        active: stats::active::read().ok().map(|v| v as u64),
+        resident: stats::resident::read().ok().map(|v| v as u64),
+    }
+}
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+pub fn stats() -> AllocatorStats {
+    let (mut elapsed, mut user, mut system) = (0usize, 0usize, 0usize);
+    let (mut rss, mut peak_rss, mut commit, mut peak_commit) = (0usize, 0usize, 0usize, 0usize);
+    let mut page_faults = 0usize;
+    // SAFETY: every out pointer refers to a live local.
+    unsafe {
+        libmimalloc_sys::mi_process_info(
+            &mut elapsed,
+            &mut user,
+            &mut system,
+            &mut rss,
+            &mut peak_rss,
+            &mut commit,
+            &mut peak_commit,
+            &mut page_faults,
+        );
+    }
+    AllocatorStats {
+        allocated: None,
+        active: Some(commit as u64),
+        resident: Some(rss as u64),
+    }
+}
+
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+pub fn stats() -> AllocatorStats {
+    AllocatorStats {
+        allocated: None,
+        active: None,
+        resident: process_resident(),
+    }
+}
+
+/// Resident set size of the process, from `/proc/self/statm`.
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+fn process_resident() -> Option<u64> {
+    // statm reports pages; assume the common 4KiB page size.
+    const PAGE_SIZE: u64 = 4096;
+
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragmentation_ratio() {
+        let stats = AllocatorStats {
+            allocated: Some(100),
+            active: Some(120),
+            resident: Some(150),
+        };
+        assert_eq!(stats.fragmentation_ratio(), Some(1.5));
+
+        let unknown = AllocatorStats {
+            resident: Some(150),
+            ..Default::default()
+        };
+        assert_eq!(unknown.fragmentation_ratio(), None);
+
+        let empty = AllocatorStats {
+            allocated: Some(0),
+            resident: Some(150),
+            ..Default::default()
+        };
+        assert_eq!(empty.fragmentation_ratio(), None);
+    }
+
+    #[test]
+    fn test_stats_snapshot() {
+        let stats = stats();
+        if let (Some(allocated), Some(active)) = (stats.allocated, stats.active) {
+            assert!(active >= allocated);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Thread-local pool of `BytesMut` buffers for the hot paths.
+//!
+//! Every command encodes keys, values and its reply into a fresh buffer.
+//! A `PooledBuf` takes an allocation left by a previous command of the same
+//! thread instead, and gives it back when dropped. Buffers that grew larger
+//! than `MAX_POOLED_CAPACITY` are freed rather than kept, so one large value
+//! does not pin its memory for the lifetime of the thread.
+
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+
+use bytes::BytesMut;
+
+/// Number of buffers kept per thread
+pub const POOL_SIZE: usize = 16;
+/// Largest buffer kept in the pool, in bytes
+pub const MAX_POOLED_CAPACITY: usize = 64 << 10;
+
+thread_local! {
+    static POOL: RefCell<Vec<BytesMut>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An empty buffer with room for at least `capacity` bytes, from the pool of
+/// the current thread when it has one
+pub fn acquire(capacity: usize) -> PooledBuf {
+    let mut buf = POOL
+        .with(|pool| pool.borrow_mut().pop())
+        .unwrap_or_default();
+    buf.clear();
+    buf.reserve(capacity);
+    PooledBuf { buf }
+}
+
+fn release(buf: BytesMut) {
+    if buf.capacity() == 0 || buf.capacity() > MAX_POOLED_CAPACITY {
+        return;
+    }
+    // The pool is gone when the buffer is dropped during thread teardown
+    let _ = POOL.try_with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if pool.len() < POOL_SIZE {
+            pool.push(buf);
+        }
+    });
+}
+
+/// A buffer returned to the pool when dropped
+pub struct PooledBuf {
+    buf: BytesMut,
+}
+
+impl Deref for PooledBuf {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        &mut self.buf
+    }
+}
+
+impl AsRef<[u8]> for PooledBuf {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        release(std::mem::take(&mut self.buf));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BufMut;
+
+    fn pool_len() -> usize {
+        POOL.with(|pool| pool.borrow().len())
+    }
+
+    #[test]
+    fn test_buffer_reuse() {
+        let ptr = {
+            let mut buf = acquire(128);
+            buf.put_slice(b"hello");
+            assert_eq!(&buf[..], b"hello");
+            buf.as_ptr()
+        };
+        assert_eq!(pool_len(), 1);
+
+        let buf = acquire(64);
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(pool_len(), 0);
+    }
+
+    #[test]
+    fn test_large_buffer_not_pooled() {
+        drop(acquire(MAX_POOLED_CAPACITY + 1));
+        assert_eq!(pool_len(), 0);
+
+        // Nothing to give back for a buffer that was never allocated
+        drop(acquire(0));
+        assert_eq!(pool_len(), 0);
+    }
+
+    #[test]
+    fn test_pool_size_bounded() {
+        let bufs: Vec<_> = (0..POOL_SIZE + 4).map(|_| acquire(16)).collect();
+        drop(bufs);
+        assert_eq!(pool_len(), POOL_SIZE);
+    }
+}
@@ -0,0 +1,125 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Cooperative cancellation of long running operations.
+//!
+//! Loops that may run for a long time (full keyspace iterations, big set
+//! operations...) poll a [`CancelToken`] every few iterations and stop early
+//! once it is cancelled, e.g. by CLIENT KILL, or once its deadline passed.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortReason {
+    /// The token was cancelled explicitly.
+    Killed,
+    /// The deadline of the token passed.
+    TimedOut,
+}
+
+impl fmt::Display for AbortReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbortReason::Killed => f.write_str("killed"),
+            AbortReason::TimedOut => f.write_str("max execution time exceeded"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels this token and every token derived from it.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Returns a token sharing the cancellation of this one that also expires
+    /// after `timeout`. An earlier deadline of this token is kept.
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        let deadline = Instant::now() + timeout;
+        Self {
+            cancelled: Arc::clone(&self.cancelled),
+            deadline: Some(self.deadline.map_or(deadline, |d| d.min(deadline))),
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Returns why the operation must stop, `None` if it may go on.
+    pub fn abort_reason(&self) -> Option<AbortReason> {
+        if self.is_cancelled() {
+            return Some(AbortReason::Killed);
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Some(AbortReason::TimedOut),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_is_shared() {
+        let token = CancelToken::new();
+        let derived = token.with_timeout(Duration::from_secs(60));
+        assert_eq!(derived.abort_reason(), None);
+
+        token.cancel();
+        assert!(derived.is_cancelled());
+        assert_eq!(derived.abort_reason(), Some(AbortReason::Killed));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let token = CancelToken::new().with_timeout(Duration::ZERO);
+        assert_eq!(token.abort_reason(), Some(AbortReason::TimedOut));
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn test_keeps_earliest_deadline() {
+        let token = CancelToken::new().with_timeout(Duration::from_secs(1));
+        let later = token.with_timeout(Duration::from_secs(60));
+        assert_eq!(later.deadline(), token.deadline());
+
+        let sooner = token.with_timeout(Duration::ZERO);
+        assert!(sooner.deadline() < token.deadline());
+    }
+}
@@ -17,7 +17,12 @@
  * limitations under the License.
  */
 
+pub mod allocator;
+pub mod buffer_pool;
+pub mod cancel;
 // pub mod env;
 pub mod lock_mgr;
+pub mod numa;
+pub mod resources;
 pub mod slice;
 pub mod status;
@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! NUMA topology and thread placement.
+//!
+//! On a multi-socket host a thread that reads memory of another node pays
+//! for the interconnect, and the scheduler moving threads between nodes
+//! makes the latency uneven. With NUMA placement enabled the server pins
+//! its threads to the CPUs of the nodes, round-robin, and spreads the
+//! storage shards over the nodes, each node with its own block cache. The
+//! topology is read from `/sys/devices/system/node`.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// Where the kernel lists the NUMA nodes.
+const NODE_ROOT: &str = "/sys/devices/system/node";
+
+/// A NUMA node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaNode {
+    /// The id the kernel gives the node.
+    pub id: usize,
+    /// The CPUs of the node.
+    pub cpus: Vec<usize>,
+    /// Memory of the node in bytes, `None` if not reported.
+    pub memory_bytes: Option<u64>,
+}
+
+/// The NUMA nodes of the host that have CPUs, by id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NumaTopology {
+    pub nodes: Vec<NumaNode>,
+}
+
+impl NumaTopology {
+    /// The topology of the host, empty if the kernel does not report one.
+    pub fn detect() -> Self {
+        Self::detect_in(Path::new(NODE_ROOT))
+    }
+
+    fn detect_in(root: &Path) -> Self {
+        let Ok(entries) = fs::read_dir(root) else {
+            return Self::default();
+        };
+        let mut nodes: Vec<NumaNode> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let id = entry
+                    .file_name()
+                    .to_str()?
+                    .strip_prefix("node")?
+                    .parse()
+                    .ok()?;
+                let cpus = parse_cpu_list(&fs::read_to_string(entry.path().join("cpulist")).ok()?)?;
+                let memory_bytes = fs::read_to_string(entry.path().join("meminfo"))
+                    .ok()
+                    .and_then(|meminfo| parse_node_memory(&meminfo));
+                Some(NumaNode {
+                    id,
+                    cpus,
+                    memory_bytes,
+                })
+            })
+            // Memory-only nodes run no thread
+            .filter(|node| !node.cpus.is_empty())
+            .collect();
+        nodes.sort_unstable_by_key(|node| node.id);
+        Self { nodes }
+    }
+
+    /// Whether there is more than one node to place on.
+    pub fn is_multi_node(&self) -> bool {
+        self.nodes.len() > 1
+    }
+
+    /// The position in `nodes` of the node of `shard`, the shards being
+    /// spread round-robin. `None` without nodes.
+    pub fn node_of(&self, shard: usize) -> Option<usize> {
+        (!self.nodes.is_empty()).then(|| shard % self.nodes.len())
+    }
+}
+
+/// The CPUs of a kernel CPU list, such as `0-3,8-11`.
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// Bytes of the `Node <n> MemTotal: <kB> kB` line of a node `meminfo`.
+fn parse_node_memory(meminfo: &str) -> Option<u64> {
+    let line = meminfo.lines().find(|line| line.contains("MemTotal:"))?;
+    let kb: u64 = line.split_whitespace().rev().nth(1)?.parse().ok()?;
+    Some(kb << 10)
+}
+
+/// Pins the calling thread to `cpus`.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+    // SAFETY: the set is a zeroed cpu_set_t, only written by CPU_SET with
+    // the CPUs below CPU_SETSIZE, and read by sched_setaffinity up to its
+    // size.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus.iter().filter(|&&cpu| cpu < libc::CPU_SETSIZE as usize) {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: &[usize]) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+fn placement_handle() -> &'static OnceLock<NumaTopology> {
+    static PLACEMENT: OnceLock<NumaTopology> = OnceLock::new();
+    &PLACEMENT
+}
+
+static NEXT_NODE: AtomicU64 = AtomicU64::new(0);
+static PINNED_THREADS: AtomicU64 = AtomicU64::new(0);
+
+/// Enables the NUMA placement over `topology`. Returns false if it was
+/// already enabled.
+pub fn enable_numa_placement(topology: NumaTopology) -> bool {
+    placement_handle().set(topology).is_ok()
+}
+
+/// The topology threads and shards are placed on, `None` unless
+/// `enable_numa_placement` was called.
+pub fn numa_placement() -> Option<&'static NumaTopology> {
+    placement_handle().get()
+}
+
+/// Pins the calling thread to the CPUs of the next node, round-robin, and
+/// returns the id of the node. Fails without NUMA placement.
+pub fn pin_to_next_node() -> io::Result<usize> {
+    let topology = numa_placement()
+        .filter(|topology| !topology.nodes.is_empty())
+        .ok_or_else(|| io::Error::other("NUMA placement is not enabled"))?;
+    let n = NEXT_NODE.fetch_add(1, Ordering::Relaxed) as usize;
+    let node = &topology.nodes[n % topology.nodes.len()];
+    pin_current_thread(&node.cpus)?;
+    PINNED_THREADS.fetch_add(1, Ordering::Relaxed);
+    Ok(node.id)
+}
+
+/// Threads `pin_to_next_node` pinned.
+pub fn pinned_threads() -> u64 {
+    PINNED_THREADS.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_node_files() {
+        assert_eq!(parse_cpu_list("0-3,8-9\n"), Some(vec![0, 1, 2, 3, 8, 9]));
+        assert_eq!(parse_cpu_list("5\n"), Some(vec![5]));
+        assert_eq!(parse_cpu_list("\n"), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+        assert_eq!(
+            parse_node_memory("Node 1 MemTotal:       32823060 kB\nNode 1 MemFree: 1 kB\n"),
+            Some(32823060 << 10)
+        );
+    }
+
+    #[test]
+    fn test_detect_topology() {
+        let root = std::env::temp_dir().join(format!("kiwi-numa-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (node, cpus) in [("node1", "4-7\n"), ("node0", "0-3\n"), ("node2", "\n")] {
+            fs::create_dir_all(root.join(node)).unwrap();
+            fs::write(root.join(node).join("cpulist"), cpus).unwrap();
+        }
+        fs::write(
+            root.join("node0/meminfo"),
+            "Node 0 MemTotal:       1048576 kB\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("power")).unwrap();
+
+        let topology = NumaTopology::detect_in(&root);
+        // node2 has no CPU
+        assert_eq!(topology.nodes.len(), 2);
+        assert!(topology.is_multi_node());
+        assert_eq!(topology.nodes[0].id, 0);
+        assert_eq!(topology.nodes[0].cpus, vec![0, 1, 2, 3]);
+        assert_eq!(topology.nodes[0].memory_bytes, Some(1 << 30));
+        assert_eq!(topology.nodes[1].id, 1);
+        assert_eq!(topology.nodes[1].memory_bytes, None);
+        assert_eq!(topology.node_of(0), Some(0));
+        assert_eq!(topology.node_of(3), Some(1));
+
+        assert_eq!(NumaTopology::default().node_of(3), None);
+        assert_eq!(
+            NumaTopology::detect_in(&root.join("missing")),
+            NumaTopology::default()
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! CPU and memory the process may use.
+//!
+//! In a container the cgroup of the process caps its CPU time and memory
+//! well below what the host has. Thread pools sized to the host get
+//! throttled, and caches sized to it get the process OOM killed. The limits
+//! are read from cgroup v2, or from the `cpu` and `memory` controllers of
+//! cgroup v1, and fall back to the CPUs of the host and no memory limit.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Where the cgroup hierarchies are mounted.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// cgroup v1 reports no memory limit as a page-aligned `i64::MAX`, anything
+/// from here up is no limit.
+const V1_UNLIMITED_MEMORY: u64 = 1 << 62;
+
+/// The CPUs and the memory of the process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResourceLimits {
+    /// CPUs the process may use, fractional under a CPU quota.
+    pub cpus: f64,
+    /// Memory the process may use in bytes, `None` without a limit.
+    pub memory_bytes: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// The limits of the cgroup of the process, capped at the CPUs of the
+    /// host.
+    pub fn detect() -> Self {
+        let own = fs::read_to_string("/proc/self/cgroup")
+            .ok()
+            .and_then(|cgroups| unified_path(&cgroups));
+        Self::detect_in(Path::new(CGROUP_ROOT), own.as_deref(), num_cpus::get())
+    }
+
+    /// The limits under the cgroup mount `root`, `own` being the cgroup v2
+    /// path of the process. A container only sees its own cgroup, at the
+    /// root, so the root is read when `own` has no limit files.
+    fn detect_in(root: &Path, own: Option<&Path>, host_cpus: usize) -> Self {
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        if let Some(own) = own {
+            dirs.push(root.join(own.strip_prefix("/").unwrap_or(own)));
+        }
+        dirs.push(root.to_path_buf());
+
+        let quota = dirs
+            .iter()
+            .find_map(|dir| fs::read_to_string(dir.join("cpu.max")).ok())
+            .map(|max| parse_cpu_max(&max))
+            .unwrap_or_else(|| read_v1_cpu_quota(&root.join("cpu")));
+        let memory_bytes = dirs
+            .iter()
+            .find_map(|dir| fs::read_to_string(dir.join("memory.max")).ok())
+            .map(|max| parse_memory_max(&max))
+            .unwrap_or_else(|| read_v1_memory_limit(&root.join("memory")));
+
+        let host_cpus = host_cpus.max(1) as f64;
+        Self {
+            cpus: quota.map_or(host_cpus, |quota| quota.min(host_cpus)),
+            memory_bytes,
+        }
+    }
+
+    /// Whole CPUs, at least one: the threads that keep the CPUs busy.
+    pub fn threads(&self) -> usize {
+        (self.cpus.ceil() as usize).max(1)
+    }
+}
+
+/// The cgroup v2 path of the `0::<path>` line of `/proc/self/cgroup`.
+fn unified_path(cgroups: &str) -> Option<PathBuf> {
+    cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(PathBuf::from)
+}
+
+/// CPUs of a cgroup v2 `cpu.max`, `<quota> <period>` or `max <period>`.
+fn parse_cpu_max(max: &str) -> Option<f64> {
+    let mut fields = max.split_whitespace();
+    let quota: f64 = fields.next()?.parse().ok()?;
+    let period: f64 = fields.next().unwrap_or("100000").parse().ok()?;
+    (quota > 0.0 && period > 0.0).then(|| quota / period)
+}
+
+/// Bytes of a cgroup v2 `memory.max`, a number or `max`.
+fn parse_memory_max(max: &str) -> Option<u64> {
+    max.trim().parse().ok()
+}
+
+/// CPUs of the cgroup v1 `cpu` controller at `dir`, the quota being -1
+/// without a limit.
+fn read_v1_cpu_quota(dir: &Path) -> Option<f64> {
+    let read = |name: &str| -> Option<f64> {
+        fs::read_to_string(dir.join(name)).ok()?.trim().parse().ok()
+    };
+    let quota = read("cpu.cfs_quota_us")?;
+    let period = read("cpu.cfs_period_us")?;
+    (quota > 0.0 && period > 0.0).then(|| quota / period)
+}
+
+/// Bytes of the cgroup v1 `memory` controller at `dir`.
+fn read_v1_memory_limit(dir: &Path) -> Option<u64> {
+    let limit: u64 = fs::read_to_string(dir.join("memory.limit_in_bytes"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    (limit < V1_UNLIMITED_MEMORY).then_some(limit)
+}
+
+fn limits_handle() -> &'static OnceLock<ResourceLimits> {
+    static LIMITS: OnceLock<ResourceLimits> = OnceLock::new();
+    &LIMITS
+}
+
+/// Sets the limits `resource_limits` returns, to override the detected
+/// ones. Returns false if they were already set or read.
+pub fn set_resource_limits(limits: ResourceLimits) -> bool {
+    limits_handle().set(limits).is_ok()
+}
+
+/// The limits of the process: those set with `set_resource_limits`, or
+/// detected on the first call.
+pub fn resource_limits() -> &'static ResourceLimits {
+    limits_handle().get_or_init(ResourceLimits::detect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_root(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("kiwi-resources-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_parse_cgroup_files() {
+        assert_eq!(parse_cpu_max("150000 100000\n"), Some(1.5));
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_memory_max("1073741824\n"), Some(1 << 30));
+        assert_eq!(parse_memory_max("max\n"), None);
+        assert_eq!(
+            unified_path("12:cpu,cpuacct:/\n0::/kiwi.slice/kiwi.service\n"),
+            Some(PathBuf::from("/kiwi.slice/kiwi.service"))
+        );
+        assert_eq!(unified_path("4:memory:/docker/abc\n"), None);
+    }
+
+    #[test]
+    fn test_detect_v2() {
+        let root = test_root("v2");
+        let own = root.join("kiwi.slice");
+        fs::create_dir_all(&own).unwrap();
+        fs::write(own.join("cpu.max"), "50000 100000\n").unwrap();
+        fs::write(root.join("memory.max"), "536870912\n").unwrap();
+
+        let limits = ResourceLimits::detect_in(&root, Some(Path::new("/kiwi.slice")), 8);
+        assert_eq!(limits.cpus, 0.5);
+        assert_eq!(limits.threads(), 1);
+        assert_eq!(limits.memory_bytes, Some(512 << 20));
+
+        // A quota above the host is capped at the host
+        fs::write(own.join("cpu.max"), "1600000 100000\n").unwrap();
+        let limits = ResourceLimits::detect_in(&root, Some(Path::new("/kiwi.slice")), 8);
+        assert_eq!(limits.cpus, 8.0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_detect_v1() {
+        let root = test_root("v1");
+        fs::create_dir_all(root.join("cpu")).unwrap();
+        fs::create_dir_all(root.join("memory")).unwrap();
+        fs::write(root.join("cpu/cpu.cfs_quota_us"), "250000\n").unwrap();
+        fs::write(root.join("cpu/cpu.cfs_period_us"), "100000\n").unwrap();
+        fs::write(
+            root.join("memory/memory.limit_in_bytes"),
+            "9223372036854771712\n",
+        )
+        .unwrap();
+
+        let limits = ResourceLimits::detect_in(&root, None, 16);
+        assert_eq!(limits.cpus, 2.5);
+        assert_eq!(limits.threads(), 3);
+        assert_eq!(limits.memory_bytes, None);
+
+        fs::write(root.join("cpu/cpu.cfs_quota_us"), "-1\n").unwrap();
+        let limits = ResourceLimits::detect_in(&root, None, 16);
+        assert_eq!(limits.cpus, 16.0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,511 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! SLO alarms
+//!
+//! A rule compares a metric of the last second against a threshold:
+//!
+//! - `p50 get > 5ms for 1m`: a latency quantile of one command, any of
+//!   `p50`, `p90`, `p99` and `p999`, in `us`, `ms` or `s`
+//! - `qps set < 100 for 5m`: the calls per second of one command
+//! - `queue > 1000 for 30s`: the commands waiting for an execution slot
+//!
+//! A rule fires once its condition held for the whole `for` duration, 0
+//! when omitted, and resolves on the first second it does not hold. A
+//! second without any call of the command has no latency and does not hold.
+//! Every change is logged, published on `ALARM_CHANNEL` of every database
+//! and, with a webhook, posted to it as JSON.
+//!
+//! There is no replication stream yet, so no replication lag to watch.
+
+use client::command_queue_stats;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use storage::databases::Databases;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+/// The pubsub channel the alarms are published on
+pub const ALARM_CHANNEL: &[u8] = b"__alarms__";
+
+/// Interval between two evaluations of the rules
+const EVAL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest wait of a webhook post
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a rule watches
+#[derive(Debug, Clone, PartialEq)]
+pub enum Metric {
+    /// A latency quantile of a command, in microseconds
+    Latency { command: String, quantile: f64 },
+    /// The calls per second of a command
+    Qps { command: String },
+    /// The commands waiting for an execution slot
+    QueueLen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+/// A threshold on a metric, see the module documentation for the syntax
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlarmRule {
+    spec: String,
+    metric: Metric,
+    comparison: Comparison,
+    threshold: f64,
+    duration: Duration,
+}
+
+impl AlarmRule {
+    /// Parses `;` separated rules
+    pub fn parse_list(spec: &str) -> io::Result<Vec<Self>> {
+        spec.split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    fn holds(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Above => value > self.threshold,
+            Comparison::Below => value < self.threshold,
+        }
+    }
+
+    /// The value of the metric over `window`, None without data
+    fn value(&self, window: &Window) -> Option<f64> {
+        match &self.metric {
+            Metric::Latency { command, quantile } => window
+                .latencies
+                .get(command)
+                .and_then(|histogram| histogram.quantile(*quantile))
+                .map(|latency| latency.as_micros() as f64),
+            Metric::Qps { command } => {
+                let calls = window
+                    .latencies
+                    .get(command)
+                    .map_or(0, LatencyHistogram::count);
+                Some(calls as f64 / window.elapsed.as_secs_f64())
+            }
+            Metric::QueueLen => Some(window.queue_len as f64),
+        }
+    }
+
+    /// `value` in the unit of the rule
+    fn format_value(&self, value: f64) -> String {
+        match self.metric {
+            Metric::Latency { .. } => format!("{:?}", Duration::from_micros(value as u64)),
+            Metric::Qps { .. } | Metric::QueueLen => format!("{value:.0}"),
+        }
+    }
+}
+
+impl fmt::Display for AlarmRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+impl FromStr for AlarmRule {
+    type Err = io::Error;
+
+    fn from_str(spec: &str) -> io::Result<Self> {
+        let invalid = |reason: &str| io::Error::other(format!("invalid alarm '{spec}': {reason}"));
+        let words: Vec<&str> = spec.split_whitespace().collect();
+        let (metric, rest) = match words.as_slice() {
+            ["queue", rest @ ..] => (Metric::QueueLen, rest),
+            ["qps", command, rest @ ..] => (
+                Metric::Qps {
+                    command: command.to_lowercase(),
+                },
+                rest,
+            ),
+            [quantile, command, rest @ ..] => {
+                let quantile = match *quantile {
+                    "p50" => 0.5,
+                    "p90" => 0.9,
+                    "p99" => 0.99,
+                    "p999" => 0.999,
+                    _ => return Err(invalid("unknown metric")),
+                };
+                let command = command.to_lowercase();
+                (Metric::Latency { command, quantile }, rest)
+            }
+            _ => return Err(invalid("missing metric")),
+        };
+        let (comparison, threshold, duration) = match rest {
+            [comparison, threshold] => (comparison, threshold, None),
+            [comparison, threshold, "for", duration] => (comparison, threshold, Some(duration)),
+            _ => {
+                return Err(invalid(
+                    "expected <metric> >|< <threshold> [for <duration>]",
+                ))
+            }
+        };
+        let comparison = match *comparison {
+            ">" => Comparison::Above,
+            "<" => Comparison::Below,
+            _ => return Err(invalid("expected > or <")),
+        };
+        let threshold = match metric {
+            Metric::Latency { .. } => parse_duration(threshold).map(|d| d.as_micros() as f64),
+            Metric::Qps { .. } | Metric::QueueLen => threshold.parse().ok(),
+        }
+        .ok_or_else(|| invalid("invalid threshold"))?;
+        let duration = match duration {
+            Some(duration) => {
+                parse_duration(duration).ok_or_else(|| invalid("invalid duration"))?
+            }
+            None => Duration::ZERO,
+        };
+        Ok(Self {
+            spec: words.join(" "),
+            metric,
+            comparison,
+            threshold,
+            duration,
+        })
+    }
+}
+
+/// Parses a number followed by `us`, `ms`, `s`, `m` or `h`
+fn parse_duration(s: &str) -> Option<Duration> {
+    let at = s.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (value, unit) = s.split_at(at);
+    let value: f64 = value.parse().ok()?;
+    let secs = match unit {
+        "us" => value / 1e6,
+        "ms" => value / 1e3,
+        "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// A webhook the alarms are posted to, only plain `http://` is supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    host: String,
+    addr: String,
+    path: String,
+}
+
+impl FromStr for Webhook {
+    type Err = io::Error;
+
+    fn from_str(url: &str) -> io::Result<Self> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            io::Error::other(format!(
+                "invalid webhook '{url}', expected http://host[:port]/path"
+            ))
+        })?;
+        let (host, path) = match rest.find('/') {
+            Some(at) => rest.split_at(at),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(io::Error::other(format!(
+                "invalid webhook '{url}', missing host"
+            )));
+        }
+        let addr = match host.contains(':') {
+            true => host.to_string(),
+            false => format!("{host}:80"),
+        };
+        Ok(Self {
+            host: host.to_string(),
+            addr,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Webhook {
+    async fn post(&self, body: &str) -> io::Result<()> {
+        let mut stream = TcpStream::connect(&self.addr).await?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.path,
+            self.host,
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+/// The rules of the process and where their alarms go
+#[derive(Debug, Clone, Default)]
+pub struct AlarmConfig {
+    pub rules: Vec<AlarmRule>,
+    pub webhook: Option<Webhook>,
+}
+
+/// Sets the alarms `run_alarms` evaluates. Returns false if they were
+/// already set
+pub fn configure_alarms(config: AlarmConfig) -> bool {
+    alarm_config().set(config).is_ok()
+}
+
+fn alarm_config() -> &'static OnceLock<AlarmConfig> {
+    static CONFIG: OnceLock<AlarmConfig> = OnceLock::new();
+    &CONFIG
+}
+
+/// The metrics of one evaluation interval
+struct Window {
+    latencies: HashMap<String, LatencyHistogram>,
+    queue_len: u64,
+    elapsed: Duration,
+}
+
+#[derive(Default)]
+struct RuleState {
+    breached_since: Option<Instant>,
+    firing: bool,
+}
+
+/// Evaluates the configured rules every second and raises their alarms.
+/// Returns right away when no rule is configured.
+///
+/// usage:
+/// tokio::spawn(run_alarms(databases.clone()));
+pub async fn run_alarms(databases: Arc<Databases>) {
+    let Some(config) = alarm_config()
+        .get()
+        .filter(|config| !config.rules.is_empty())
+    else {
+        return;
+    };
+    info!("watching {} alarm rules", config.rules.len());
+    let stats = command_latency_stats();
+    stats.enable();
+
+    let mut states: Vec<RuleState> = config.rules.iter().map(|_| RuleState::default()).collect();
+    let mut interval = tokio::time::interval(EVAL_INTERVAL);
+    interval.tick().await;
+    let mut last = Instant::now();
+    loop {
+        interval.tick().await;
+        let now = Instant::now();
+        let window = Window {
+            latencies: stats.take_window(),
+            queue_len: command_queue_stats().queue_len(),
+            elapsed: now - last,
+        };
+        last = now;
+
+        for (rule, state) in config.rules.iter().zip(states.iter_mut()) {
+            let value = rule.value(&window);
+            match value.filter(|value| rule.holds(*value)) {
+                Some(value) => {
+                    let since = *state.breached_since.get_or_insert(now);
+                    if !state.firing && now - since >= rule.duration {
+                        state.firing = true;
+                        raise(
+                            config,
+                            &databases,
+                            rule,
+                            "firing",
+                            &rule.format_value(value),
+                        )
+                        .await;
+                    }
+                }
+                None => {
+                    state.breached_since = None;
+                    if state.firing {
+                        state.firing = false;
+                        let value =
+                            value.map_or_else(|| "none".to_string(), |v| rule.format_value(v));
+                        raise(config, &databases, rule, "resolved", &value).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Logs, publishes and posts a change of `rule` to `state`
+async fn raise(
+    config: &AlarmConfig,
+    databases: &Databases,
+    rule: &AlarmRule,
+    state: &str,
+    value: &str,
+) {
+    let message = format!("[{state}] {rule}: {value}");
+    match state {
+        "firing" => warn!("alarm {message}"),
+        _ => info!("alarm {message}"),
+    }
+    for storage in databases.all() {
+        storage.publish(ALARM_CHANNEL, message.clone());
+    }
+
+    let Some(webhook) = &config.webhook else {
+        return;
+    };
+    let body = format!(
+        "{{\"alarm\":\"{}\",\"state\":\"{state}\",\"value\":\"{}\"}}",
+        json_escape(&rule.spec),
+        json_escape(value)
+    );
+    match tokio::time::timeout(WEBHOOK_TIMEOUT, webhook.post(&body)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("alarm webhook post failed: {e}"),
+        Err(_) => warn!("alarm webhook post timed out after {WEBHOOK_TIMEOUT:?}"),
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Latencies below this many microseconds get a bucket each, the larger
+/// ones share 4 buckets per power of two, so a bucket is at most 25% wide.
+const LATENCY_SUB_BUCKETS: u64 = 4;
+const LATENCY_BUCKETS: usize = 4 + 62 * 4;
+
+/// A histogram of command latencies, precise to 25%.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS],
+    count: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; LATENCY_BUCKETS],
+            count: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[Self::bucket(micros)] += 1;
+        self.count += 1;
+    }
+
+    /// The number of latencies recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The latency `quantile` (0.99 for the p99) of the recorded ones are
+    /// below, rounded up to the end of its bucket. None when empty.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let rank = ((self.count as f64 * quantile).ceil() as u64).clamp(1, self.count.max(1));
+        let mut seen = 0;
+        for (bucket, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Some(Duration::from_micros(Self::upper_bound(bucket)));
+            }
+        }
+        None
+    }
+
+    fn bucket(micros: u64) -> usize {
+        if micros < LATENCY_SUB_BUCKETS {
+            return micros as usize;
+        }
+        let exp = 63 - micros.leading_zeros() as u64;
+        let sub = (micros >> (exp - 2)) & (LATENCY_SUB_BUCKETS - 1);
+        (LATENCY_SUB_BUCKETS * (exp - 1) + sub) as usize
+    }
+
+    /// The first latency after `bucket`, in microseconds.
+    fn upper_bound(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < LATENCY_SUB_BUCKETS {
+            return bucket + 1;
+        }
+        let exp = bucket / LATENCY_SUB_BUCKETS + 1;
+        let sub = bucket % LATENCY_SUB_BUCKETS;
+        (LATENCY_SUB_BUCKETS + sub + 1).saturating_mul(1 << (exp - 2))
+    }
+}
+
+/// The latency of the commands, by command name, over the window since it
+/// was last taken. Nothing is recorded until it is enabled by a reader.
+#[derive(Debug, Default)]
+pub struct CommandLatencyStats {
+    enabled: AtomicBool,
+    window: Mutex<HashMap<String, LatencyHistogram>>,
+}
+
+impl CommandLatencyStats {
+    /// Starts recording the latencies.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// A `command` took `latency`.
+    pub fn record(&self, command: &str, latency: Duration) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        match window.get_mut(command) {
+            Some(histogram) => histogram.record(latency),
+            None => window
+                .entry(command.to_string())
+                .or_default()
+                .record(latency),
+        }
+    }
+
+    /// The histograms of the window, which starts over.
+    pub fn take_window(&self) -> HashMap<String, LatencyHistogram> {
+        std::mem::take(&mut *self.window.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// The command latency stats of the process.
+pub fn command_latency_stats() -> &'static CommandLatencyStats {
+    static STATS: OnceLock<CommandLatencyStats> = OnceLock::new();
+    STATS.get_or_init(CommandLatencyStats::default)
+}
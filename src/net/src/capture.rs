@@ -0,0 +1,408 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Traffic capture
+//!
+//! With a trace directory configured, the connections record every command
+//! they read and every reply they write, as RESP frames, to trace files for
+//! offline workload analysis and replay. A file is
+//!
+//! | magic | record | record | ... |
+//! |  8B   |
+//!
+//! and a record is
+//!
+//! | timestamp | connection id | direction | length | frame  |
+//! |    8B     |      8B       |    1B     |   4B   | length |
+//!
+//! with the integers little-endian and the timestamp in microseconds since
+//! the epoch. The files are `trace-<n>.ktr`, numbered in the order they were
+//! written: a file is closed once it reaches its size limit, and the oldest
+//! ones are deleted past the file limit.
+//!
+//! A thread writes the records, so a connection never waits for the disk.
+//! When it falls behind the frames are dropped and counted, see the capture
+//! section of INFO. `TraceReader` reads the records of a file back.
+
+use bytes::Bytes;
+use log::{info, warn};
+use resp::encode::RespEncoder;
+use resp::{RespData, RespEncode, RespVersion};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// First bytes of a trace file
+pub const TRACE_MAGIC: &[u8; 8] = b"KIWITRC1";
+
+/// Extension of the trace files
+const TRACE_EXTENSION: &str = "ktr";
+
+/// Bytes of a record before its frame
+const RECORD_HEADER_LEN: usize = 8 + 8 + 1 + 4;
+
+/// Records waiting for the writer thread
+const CAPTURE_QUEUE_LEN: usize = 65536;
+
+/// Longest time a written record stays in the buffer of the writer
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Which way a frame went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// A command read from the client
+    Request = 0,
+    /// A reply written to the client
+    Reply = 1,
+}
+
+impl TryFrom<u8> for Direction {
+    type Error = io::Error;
+
+    fn try_from(value: u8) -> io::Result<Self> {
+        match value {
+            0 => Ok(Direction::Request),
+            1 => Ok(Direction::Reply),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid trace direction {value}"),
+            )),
+        }
+    }
+}
+
+/// A frame of a trace file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// When the frame was read or written, in microseconds since the epoch
+    pub timestamp_micros: u64,
+    /// The id of the client connection
+    pub conn_id: u64,
+    pub direction: Direction,
+    /// The RESP frame
+    pub frame: Bytes,
+}
+
+impl TraceRecord {
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<u64> {
+        writer.write_all(&self.timestamp_micros.to_le_bytes())?;
+        writer.write_all(&self.conn_id.to_le_bytes())?;
+        writer.write_all(&[self.direction as u8])?;
+        writer.write_all(&(self.frame.len() as u32).to_le_bytes())?;
+        writer.write_all(&self.frame)?;
+        Ok((RECORD_HEADER_LEN + self.frame.len()) as u64)
+    }
+}
+
+/// Reads the records of a trace file in the order they were written. A
+/// record cut short, by a process that stopped while writing it, ends the
+/// records with an `UnexpectedEof` error.
+pub struct TraceReader<R> {
+    reader: R,
+}
+
+impl TraceReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Reads the records from `reader`, after checking the magic
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; TRACE_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != TRACE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a trace file",
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let mut header = [0; RECORD_HEADER_LEN];
+        match read_full(&mut self.reader, &mut header)? {
+            0 => return Ok(None),
+            RECORD_HEADER_LEN => {}
+            _ => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+        let timestamp_micros = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let conn_id = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let direction = Direction::try_from(header[16])?;
+        let len = u32::from_le_bytes(header[17..21].try_into().unwrap()) as usize;
+        let mut frame = vec![0; len];
+        self.reader.read_exact(&mut frame)?;
+        Ok(Some(TraceRecord {
+            timestamp_micros,
+            conn_id,
+            direction,
+            frame: frame.into(),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Reads until `buf` is full or the end of the input, returns the bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// The trace files of `dir`, oldest first
+pub fn trace_files(dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<(u64, PathBuf)> = fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            Some((trace_file_number(&path)?, path))
+        })
+        .collect();
+    files.sort_unstable();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// The `n` of `trace-<n>.ktr`
+fn trace_file_number(path: &Path) -> Option<u64> {
+    if path.extension()? != TRACE_EXTENSION {
+        return None;
+    }
+    path.file_stem()?
+        .to_str()?
+        .strip_prefix("trace-")?
+        .parse()
+        .ok()
+}
+
+/// Where the frames are captured
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    /// Directory of the trace files, created if missing
+    pub dir: PathBuf,
+    /// Size a trace file is closed at (in bytes)
+    pub max_file_bytes: u64,
+    /// Trace files kept, the oldest are deleted past it
+    pub max_files: usize,
+}
+
+/// Records the frames of the connections
+pub struct Capture {
+    queue: SyncSender<TraceRecord>,
+}
+
+fn capture_handle() -> &'static OnceLock<Capture> {
+    static CAPTURE: OnceLock<Capture> = OnceLock::new();
+    &CAPTURE
+}
+
+/// The capture of the process, None unless `start_capture` was called
+pub fn capture() -> Option<&'static Capture> {
+    capture_handle().get()
+}
+
+/// Starts capturing to the first trace file of `config` after the existing
+/// ones. Fails if the directory or the file cannot be created, or if the
+/// capture already started.
+pub fn start_capture(config: CaptureConfig) -> io::Result<()> {
+    let mut writer = TraceWriter::open(config)?;
+    let (sender, queue) = mpsc::sync_channel(CAPTURE_QUEUE_LEN);
+    if capture_handle().set(Capture { queue: sender }).is_err() {
+        return Err(io::Error::other("capture already started"));
+    }
+    capture_stats().enable();
+    info!("capturing the traffic to {}", writer.config.dir.display());
+
+    std::thread::Builder::new()
+        .name("capture".to_string())
+        .spawn(move || loop {
+            let result = match queue.recv_timeout(FLUSH_INTERVAL) {
+                Ok(record) => writer.write(&record),
+                Err(RecvTimeoutError::Timeout) => writer.file.flush(),
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            if let Err(e) = result {
+                warn!("capture: writing the trace failed: {e}");
+            }
+        })?;
+    Ok(())
+}
+
+impl Capture {
+    /// Records the command `data` read from the connection `conn_id`
+    pub fn request(&self, conn_id: u64, data: &RespData) {
+        let mut encoder = RespEncoder::new(RespVersion::RESP2);
+        encoder.encode_resp_data(data);
+        self.record(conn_id, Direction::Request, encoder.as_bytes());
+    }
+
+    /// Records the reply `frame` written to the connection `conn_id`
+    pub fn reply(&self, conn_id: u64, frame: &[u8]) {
+        self.record(conn_id, Direction::Reply, frame);
+    }
+
+    fn record(&self, conn_id: u64, direction: Direction, frame: &[u8]) {
+        let timestamp_micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        let record = TraceRecord {
+            timestamp_micros,
+            conn_id,
+            direction,
+            frame: Bytes::copy_from_slice(frame),
+        };
+        if self.queue.try_send(record).is_err() {
+            capture_stats().dropped();
+        }
+    }
+}
+
+/// Writes the records to the current trace file and rotates the files
+struct TraceWriter {
+    config: CaptureConfig,
+    file: BufWriter<File>,
+    file_number: u64,
+    file_bytes: u64,
+}
+
+impl TraceWriter {
+    fn open(config: CaptureConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let last = trace_files(&config.dir)?
+            .last()
+            .and_then(|path| trace_file_number(path));
+        let file_number = last.map_or(0, |n| n + 1);
+        let file = Self::create(&config.dir, file_number)?;
+        Ok(Self {
+            config,
+            file,
+            file_number,
+            file_bytes: TRACE_MAGIC.len() as u64,
+        })
+    }
+
+    fn create(dir: &Path, number: u64) -> io::Result<BufWriter<File>> {
+        let path = dir.join(format!("trace-{number}.{TRACE_EXTENSION}"));
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(TRACE_MAGIC)?;
+        capture_stats().file_started();
+        Ok(file)
+    }
+
+    fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if self.file_bytes >= self.config.max_file_bytes {
+            self.rotate()?;
+        }
+        let bytes = record.write_to(&mut self.file)?;
+        self.file_bytes += bytes;
+        capture_stats().written(bytes);
+        Ok(())
+    }
+
+    /// Closes the current file, starts the next one and deletes the oldest
+    /// past `max_files`
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file = Self::create(&self.config.dir, self.file_number + 1)?;
+        self.file_number += 1;
+        self.file_bytes = TRACE_MAGIC.len() as u64;
+
+        let files = trace_files(&self.config.dir)?;
+        let excess = files.len().saturating_sub(self.config.max_files.max(1));
+        for path in &files[..excess] {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("capture: removing {} failed: {e}", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Frames captured to the trace files, updated by the capture of the
+/// connections and reported by INFO.
+#[derive(Debug, Default)]
+pub struct CaptureStats {
+    enabled: AtomicBool,
+    // Frames written to the trace files, and their bytes
+    records: AtomicU64,
+    bytes: AtomicU64,
+    // Frames dropped, the writer was behind
+    dropped: AtomicU64,
+    // Trace files started
+    files: AtomicU64,
+}
+
+impl CaptureStats {
+    /// Capturing started.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// A frame of `bytes` bytes, headers included, was written.
+    pub fn written(&self, bytes: u64) {
+        self.records.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// A frame was dropped.
+    pub fn dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A trace file was started.
+    pub fn file_started(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The INFO section of the stats.
+    pub fn info(&self) -> String {
+        format!(
+            "# Capture\r\ncapture_enabled:{}\r\ncapture_records:{}\r\ncapture_bytes:{}\r\ncapture_dropped:{}\r\ncapture_files:{}\r\n",
+            self.enabled.load(Ordering::Relaxed) as u8,
+            self.records.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.files.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// The capture stats of the process.
+pub fn capture_stats() -> &'static CaptureStats {
+    static STATS: OnceLock<CaptureStats> = OnceLock::new();
+    STATS.get_or_init(CaptureStats::default)
+}
@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Node lists given by DNS names
+//!
+//! A list is either written inline, `host:port,host:port`, or read from a
+//! file with `@path`, one or more entries per line and `#` comments. Hosts
+//! may be DNS names: a name resolving to several addresses, like the
+//! headless service of a Kubernetes StatefulSet, stands for all of them.
+//!
+//! The list is resolved again, and the file read again, every refresh
+//! interval. A refresh that fails, on a bad entry, a name that does not
+//! resolve or an empty result, keeps the previous nodes, so a DNS hiccup
+//! never empties the list.
+
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::lookup_host;
+use tokio::sync::watch;
+
+/// Where the entries of a node list come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeSource {
+    Static(Vec<String>),
+    /// Read again on every refresh
+    File(PathBuf),
+}
+
+impl NodeSource {
+    /// Parses `@path` or a comma separated list of `host:port`
+    pub fn parse(spec: &str) -> io::Result<Self> {
+        match spec.strip_prefix('@') {
+            Some(path) if path.is_empty() => Err(io::Error::other("missing path after '@'")),
+            Some(path) => Ok(NodeSource::File(PathBuf::from(path))),
+            None => Ok(NodeSource::Static(parse_entries(spec)?)),
+        }
+    }
+
+    /// The current entries, unresolved
+    pub fn entries(&self) -> io::Result<Vec<String>> {
+        match self {
+            NodeSource::Static(entries) => Ok(entries.clone()),
+            NodeSource::File(path) => {
+                let content = std::fs::read_to_string(path).map_err(|e| {
+                    io::Error::new(e.kind(), format!("read {}: {e}", path.display()))
+                })?;
+                let lines: Vec<&str> = content
+                    .lines()
+                    .map(|line| line.split('#').next().unwrap_or_default())
+                    .collect();
+                parse_entries(&lines.join(","))
+            }
+        }
+    }
+
+    /// The addresses of the nodes, as sorted `ip:port` without duplicates
+    pub async fn resolve(&self) -> io::Result<Vec<String>> {
+        let mut nodes = BTreeSet::new();
+        for entry in self.entries()? {
+            let addrs = lookup_host(entry.as_str())
+                .await
+                .map_err(|e| io::Error::new(e.kind(), format!("resolve {entry}: {e}")))?;
+            nodes.extend(addrs.map(|addr| addr.to_string()));
+        }
+        if nodes.is_empty() {
+            return Err(io::Error::other("the node list resolved to no address"));
+        }
+        Ok(nodes.into_iter().collect())
+    }
+}
+
+/// Splits `spec` on commas and whitespace and checks every entry is a
+/// `host:port`
+fn parse_entries(spec: &str) -> io::Result<Vec<String>> {
+    let entries: Vec<String> = spec
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .map(String::from)
+        .collect();
+    for entry in &entries {
+        let valid = entry.rsplit_once(':').is_some_and(|(host, port)| {
+            !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port != 0)
+        });
+        if !valid {
+            return Err(io::Error::other(format!(
+                "invalid node '{entry}', expected host:port"
+            )));
+        }
+    }
+    if entries.is_empty() {
+        return Err(io::Error::other("the node list is empty"));
+    }
+    Ok(entries)
+}
+
+/// Resolves `source` now, then every `interval` in the background, and
+/// publishes the nodes whenever they change. A zero `interval` resolves
+/// only once. The refresh stops when every receiver is dropped.
+pub async fn watch_nodes(
+    source: NodeSource,
+    interval: Duration,
+) -> io::Result<watch::Receiver<Vec<String>>> {
+    let nodes = source.resolve().await?;
+    let (sender, receiver) = watch::channel(nodes);
+    if !interval.is_zero() {
+        tokio::spawn(refresh_nodes(source, interval, sender));
+    }
+    Ok(receiver)
+}
+
+async fn refresh_nodes(source: NodeSource, interval: Duration, sender: watch::Sender<Vec<String>>) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes at once, the list was just resolved
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = sender.closed() => return,
+        }
+        match source.resolve().await {
+            Ok(nodes) => {
+                sender.send_if_modified(|current| {
+                    if *current == nodes {
+                        return false;
+                    }
+                    info!(
+                        "node list changed: {} -> {}",
+                        current.join(","),
+                        nodes.join(",")
+                    );
+                    *current = nodes;
+                    true
+                });
+            }
+            Err(e) => warn!("node list refresh failed, keeping the previous nodes: {e}"),
+        }
+    }
+}
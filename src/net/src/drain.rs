@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Graceful drain for Kubernetes rollouts
+//!
+//! `serve_health` answers plain HTTP on its own port:
+//!
+//! - `/healthz`: 200 while the process runs, for the liveness probe
+//! - `/readyz`: 200, or 503 once a drain started, for the readiness probe
+//! - `/drain?timeout_ms=<n>`: starts a drain and answers when it is over,
+//!   for the PreStop hook
+//!
+//! A drain makes the node unready, so it is taken out of the service,
+//! stops accepting connections and waits up to the timeout (30s by default)
+//! for the open ones to close. Then the TCP server syncs the WAL and
+//! returns, and the process exits. There is no replication stream to wait
+//! for yet, the WAL sync is what makes the last writes durable.
+
+use log::{info, warn};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+/// Default wait of `/drain` for the connections to close
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest request read, the endpoints only need the request line
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// The drain state of the process
+#[derive(Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    finished: AtomicBool,
+    connections: AtomicUsize,
+    changed: Notify,
+}
+
+impl Drain {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Client connections open
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Counts a client connection until the guard is dropped
+    pub fn connection(&'static self) -> ConnectionGuard {
+        self.connections.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard(self)
+    }
+
+    /// Makes the node unready and stops new connections. Returns false if a
+    /// drain already started
+    pub fn start(&self) -> bool {
+        !self.draining.swap(true, Ordering::SeqCst)
+    }
+
+    /// Waits up to `timeout` for every connection to close, returns the
+    /// connections still open
+    pub async fn wait_idle(&self, timeout: Duration) -> usize {
+        let idle = async {
+            loop {
+                // Created before the check, so a close in between wakes it
+                let changed = self.changed.notified();
+                if self.connections() == 0 {
+                    return;
+                }
+                changed.await;
+            }
+        };
+        let _ = tokio::time::timeout(timeout, idle).await;
+        self.connections()
+    }
+
+    /// Tells the server the drain is over and it may return
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+        self.changed.notify_waiters();
+    }
+
+    /// Completes once `finish` was called
+    pub async fn finished(&self) {
+        loop {
+            let changed = self.changed.notified();
+            if self.finished.load(Ordering::SeqCst) {
+                return;
+            }
+            changed.await;
+        }
+    }
+}
+
+/// An open client connection, see `Drain::connection`
+pub struct ConnectionGuard(&'static Drain);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::SeqCst);
+        self.0.changed.notify_waiters();
+    }
+}
+
+/// The drain state of the process.
+pub fn drain() -> &'static Drain {
+    static DRAIN: OnceLock<Drain> = OnceLock::new();
+    DRAIN.get_or_init(Drain::default)
+}
+
+/// Serves the health and drain endpoints on `addr`
+pub async fn serve_health(addr: String) -> io::Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    info!("health endpoints listen on {addr}");
+    loop {
+        let (socket, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(socket).await {
+                warn!("health request error: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_request(mut socket: TcpStream) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(512);
+    let mut chunk = [0; 512];
+    // Only the request line matters, the headers are not waited for
+    while !buf.windows(2).any(|w| w == b"\r\n") && buf.len() < MAX_REQUEST_LEN {
+        let n = socket.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let request = String::from_utf8_lossy(&buf);
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let drain = drain();
+    let (status, body) = match path {
+        "/healthz" => ("200 OK", "ok".to_string()),
+        "/readyz" if drain.is_draining() => ("503 Service Unavailable", "draining".to_string()),
+        "/readyz" => ("200 OK", "ok".to_string()),
+        "/drain" => match drain_timeout(query) {
+            Ok(timeout) => ("200 OK", run_drain(drain, timeout).await),
+            Err(message) => ("400 Bad Request", message),
+        },
+        _ => ("404 Not Found", "not found".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}\n",
+        body.len() + 1
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    if path == "/drain" && drain.is_draining() {
+        drain.finish();
+    }
+    Ok(())
+}
+
+/// The `timeout_ms` of the query, `DEFAULT_DRAIN_TIMEOUT` without it
+fn drain_timeout(query: &str) -> Result<Duration, String> {
+    let Some(value) = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("timeout_ms="))
+    else {
+        return Ok(DEFAULT_DRAIN_TIMEOUT);
+    };
+    value
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|e| format!("invalid timeout_ms: {e}"))
+}
+
+async fn run_drain(drain: &Drain, timeout: Duration) -> String {
+    if drain.start() {
+        info!(
+            "draining {} connections, for up to {timeout:?}",
+            drain.connections()
+        );
+    }
+    match drain.wait_idle(timeout).await {
+        0 => {
+            info!("drained every connection");
+            "drained".to_string()
+        }
+        left => {
+            warn!("drain timed out with {left} connections open");
+            format!("timed out with {left} connections open")
+        }
+    }
+}
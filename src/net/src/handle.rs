@@ -17,72 +17,129 @@
  * limitations under the License.
  */
 
+use crate::alarms::command_latency_stats;
+use crate::capture::capture;
+use crate::mirror::mirror;
+use crate::scheduler::ConnectionSlots;
 use bytes::Bytes;
 use client::Client;
 use cmd::table::CmdTable;
-use log::error;
+use cmd::timeout::CommandTimeouts;
+use cmd::{timeout_reply, BlockedWait, CmdFlags};
+use log::{error, warn};
 use resp::encode::RespEncoder;
 use resp::{Parse, RespData, RespEncode, RespParseResult, RespVersion};
 use std::sync::Arc;
-use storage::storage::Storage;
+use std::time::Instant;
+use storage::databases::Databases;
 use tokio::select;
 
 pub async fn process_connection(
-    client: &mut Client,
-    storage: Arc<Storage>,
+    mut client: Client,
+    databases: Arc<Databases>,
     cmd_table: Arc<CmdTable>,
+    timeouts: Arc<CommandTimeouts>,
+    slots: ConnectionSlots,
 ) -> std::io::Result<()> {
     let mut buf = vec![0; 1024];
     let mut resp_parser = resp::RespParse::new(resp::RespVersion::RESP2);
+    let mut encoder = RespEncoder::new(RespVersion::RESP2);
+    let kill_signal = client.kill_signal();
+    // What the client sent while a command of it waited
+    let mut pending = Vec::new();
 
     loop {
-        select! {
-            result = client.read(&mut buf) => {
-                match result {
-                    Ok(n) => {
-                        if n == 0 { return Ok(()); }
-
-                        match resp_parser.parse(Bytes::copy_from_slice(&buf[..n])) {
-                            RespParseResult::Complete(data) => {
-                                if let RespData::Array(Some(params)) = data {
-                                    if params.is_empty() { continue; }
-
-                                    if let RespData::BulkString(Some(cmd_name)) = &params[0] {
-                                        client.set_cmd_name(cmd_name.as_ref());
-                                    }
-                                    let argv = params.iter().map(|p| if let RespData::BulkString(Some(d)) = p { d.to_vec() } else { vec![] }).collect::<Vec<Vec<u8>>>();
-                                    client.set_argv(&argv);
-                                    handle_command(client, storage.clone(), cmd_table.clone()).await;
-                                    // Extract the reply from the connection and send it
-                                    let response = client.take_reply();
-                                    let mut encoder = RespEncoder::new(RespVersion::RESP2);
-                                    encoder.encode_resp_data(&response);
-                                    match client.write(encoder.get_response().as_ref()).await {
-                                        Ok(_) => (),
-                                        Err(e) => error!("Write error: {e}"),
-                                    }
-                                }
-                            }
-                            RespParseResult::Error(e) => {
-                                error!("Protocol error: {e:?}");
-                                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()));
-                            }
-                            RespParseResult::Incomplete => {
-                                // Not enough data, wait for more
-                            }
-                        }
-                    }
+        let data = if pending.is_empty() {
+            select! {
+                _ = kill_signal.killed() => return Ok(()),
+                result = client.read(&mut buf) => match result {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => Bytes::copy_from_slice(&buf[..n]),
                     Err(e) => {
                         error!("Read error: {e:?}");
                         return Err(e);
                     }
                 }
             }
+        } else {
+            Bytes::from(std::mem::take(&mut pending))
+        };
+
+        match resp_parser.parse(data) {
+            RespParseResult::Complete(data) => {
+                if let Some(capture) = capture() {
+                    capture.request(client.id(), &data);
+                }
+                if let RespData::Array(Some(params)) = data {
+                    if params.is_empty() {
+                        continue;
+                    }
+
+                    if let RespData::BulkString(Some(cmd_name)) = &params[0] {
+                        client.set_cmd_name(cmd_name.as_ref());
+                    }
+                    let argv = params
+                        .iter()
+                        .map(|p| {
+                            if let RespData::BulkString(Some(d)) = p {
+                                d.to_vec()
+                            } else {
+                                vec![]
+                            }
+                        })
+                        .collect::<Vec<Vec<u8>>>();
+                    client.set_argv(&argv);
+                    client = match handle_command(
+                        client,
+                        &databases,
+                        cmd_table.clone(),
+                        &timeouts,
+                        &slots,
+                        &mut pending,
+                    )
+                    .await
+                    {
+                        Some(client) => client,
+                        // Killed or disconnected while the command waited
+                        None => return Ok(()),
+                    };
+                    // Extract the reply from the connection and send it
+                    let response = client.take_reply();
+                    encoder.clear().encode_resp_data(&response);
+                    if let Some(capture) = capture() {
+                        capture.reply(client.id(), encoder.as_bytes());
+                    }
+                    match client.write(encoder.as_bytes()).await {
+                        Ok(_) => (),
+                        Err(e) => error!("Write error: {e}"),
+                    }
+                    if client.is_killed() {
+                        return Ok(());
+                    }
+                }
+            }
+            RespParseResult::Error(e) => {
+                error!("Protocol error: {e:?}");
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    e.to_string(),
+                ));
+            }
+            RespParseResult::Incomplete => {
+                // Not enough data, wait for more
+            }
         }
     }
 }
 
-async fn handle_command(client: &mut Client, storage: Arc<Storage>, cmd_table: Arc<CmdTable>) {
+async fn handle_command(
+    mut client: Client,
+    databases: &Arc<Databases>,
+    cmd_table: Arc<CmdTable>,
+    timeouts: &CommandTimeouts,
+    slots: &ConnectionSlots,
+    pending: &mut Vec<u8>,
+) -> Option<Client> {
     // Convert the command name from &[u8] to a lowercase String for lookup
     let cmd_name = String::from_utf8_lossy(client.cmd_name()).to_lowercase();
 
@@ -90,10 +147,177 @@ async fn handle_command(client: &mut Client, storage: Arc<Storage>, cmd_table: A
         // Clone a command object for this specific request
         let cmd_clone = cmd.clone_box();
 
-        cmd_clone.execute(client, storage);
+        // The slot is released before the reply is written, a slow reader
+        // does not hold it. A blocking command waits for other clients and
+        // would hold a shared slot all along, it only takes the slot of its
+        // connection.
+        let _permit = if cmd.has_flag(CmdFlags::BLOCKING) {
+            slots.acquire_unshared().await
+        } else {
+            slots.acquire(cmd.class()).await
+        };
+
+        // The deadline is enforced by the storage layer through the client's
+        // cancel token, commands without cancellation points are checked
+        // once they return.
+        let timeout = timeouts.timeout_for(cmd.as_ref());
+        client.set_command_timeout(timeout);
+        let start = Instant::now();
+        // Commands block a thread of their own rather than an async worker,
+        // the slots bound how many run at once
+        let pinned = !cmd.has_flag(CmdFlags::EXCLUSIVE);
+        let databases = Arc::clone(databases);
+        let (mut client, wait) = tokio::task::spawn_blocking(move || {
+            if pinned {
+                let guard = databases.pin();
+                let storage = guard
+                    .db(client.db_index())
+                    .expect("selected db index is checked by SELECT");
+                cmd_clone.execute(&mut client, storage);
+            } else {
+                // May wait for all the running commands (SWAPDB), so it
+                // must not pin the databases
+                let storage = Arc::clone(&databases.all()[client.db_index()]);
+                cmd_clone.execute(&mut client, storage);
+            }
+            // A wait for other clients (BLMPOP) holds no pin either, a
+            // SWAPDB does not wait for it
+            let wait = if cmd_clone.has_flag(CmdFlags::BLOCKING) {
+                let storage = Arc::clone(&databases.all()[client.db_index()]);
+                cmd_clone.blocked_wait(&mut client, storage)
+            } else {
+                None
+            };
+            (client, wait)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+        if let Some(wait) = wait {
+            if !wait_blocked(&mut client, wait, pending).await {
+                return None;
+            }
+        }
+        let elapsed = start.elapsed();
+        command_latency_stats().record(&cmd_name, elapsed);
+        if let Some(mirror) = mirror() {
+            mirror.offer(cmd.as_ref(), client.db_index(), client.argv());
+        }
+        if let Some(timeout) = timeout {
+            // A command without cancellation points ran to the end. A write
+            // was applied then, and a TIMEOUT would have the client retry
+            // it, so only a read has its reply replaced.
+            if elapsed > timeout {
+                warn!("command `{cmd_name}` took {elapsed:?}, over its {timeout:?} deadline");
+                if !cmd.has_flag(CmdFlags::WRITE) {
+                    *client.reply_mut() = timeout_reply();
+                }
+            }
+        }
     } else {
         // Command not found, set an error reply
         let err_msg = format!("ERR unknown command `{cmd_name}`");
         *client.reply_mut() = RespData::Error(err_msg.into());
     }
+    Some(client)
+}
+
+/// Runs the wait of a blocking command on the connection task, and sets its
+/// reply. False when the client is killed or disconnects first, the wait is
+/// dropped then. What the client sends meanwhile is kept in `pending`.
+async fn wait_blocked(client: &mut Client, mut wait: BlockedWait, pending: &mut Vec<u8>) -> bool {
+    let kill_signal = client.kill_signal();
+    let mut buf = vec![0; 1024];
+    loop {
+        select! {
+            reply = &mut wait => {
+                *client.reply_mut() = reply;
+                return true;
+            }
+            _ = kill_signal.killed() => return false,
+            result = client.read(&mut buf) => match result {
+                Ok(0) | Err(_) => return false,
+                Ok(n) => pending.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use client::StreamTrait;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    struct DuplexWrapper(DuplexStream);
+
+    #[async_trait]
+    impl StreamTrait for DuplexWrapper {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+            self.0.read(buf).await
+        }
+        async fn write(&mut self, data: &[u8]) -> Result<usize, std::io::Error> {
+            self.0.write(data).await
+        }
+    }
+
+    fn connected_client() -> (Client, DuplexStream) {
+        let (ours, theirs) = tokio::io::duplex(1024);
+        (Client::new(Box::new(DuplexWrapper(ours))), theirs)
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_ends_wait() {
+        let (mut client, peer) = connected_client();
+        let wait: BlockedWait = Box::pin(std::future::pending());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(peer);
+        });
+
+        let mut pending = Vec::new();
+        let waited = tokio::time::timeout(
+            Duration::from_secs(5),
+            wait_blocked(&mut client, wait, &mut pending),
+        )
+        .await
+        .expect("the wait outlived its connection");
+        assert!(!waited);
+    }
+
+    #[tokio::test]
+    async fn test_kill_ends_wait() {
+        let (mut client, _peer) = connected_client();
+        let wait: BlockedWait = Box::pin(std::future::pending());
+        let id = client.id();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            client::kill_client(id);
+        });
+
+        let mut pending = Vec::new();
+        let waited = tokio::time::timeout(
+            Duration::from_secs(5),
+            wait_blocked(&mut client, wait, &mut pending),
+        )
+        .await
+        .expect("the wait outlived CLIENT KILL");
+        assert!(!waited);
+    }
+
+    #[tokio::test]
+    async fn test_input_during_wait_is_kept() {
+        let (mut client, mut peer) = connected_client();
+        peer.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        let wait: BlockedWait = Box::pin(async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            RespData::Integer(1)
+        });
+
+        let mut pending = Vec::new();
+        assert!(wait_blocked(&mut client, wait, &mut pending).await);
+        assert_eq!(client.take_reply(), RespData::Integer(1));
+        assert_eq!(pending, b"*1\r\n$4\r\nPING\r\n");
+    }
 }
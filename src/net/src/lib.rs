@@ -17,33 +17,68 @@
  * limitations under the License.
  */
 
+pub mod alarms;
+pub mod capture;
+pub mod discovery;
+pub mod drain;
 pub mod handle;
+pub mod mirror;
+pub mod proxy;
+pub mod replay;
+pub mod scheduler;
 pub mod tcp;
 
 // TODO: delete this module
 pub mod error;
 pub mod unix;
 
+use crate::capture::capture_stats;
+use crate::mirror::mirror_stats;
+use crate::scheduler::CommandScheduler;
 use crate::tcp::TcpServer;
 use async_trait::async_trait;
+use cmd::info::register_info_section;
+use cmd::timeout::CommandTimeouts;
 use std::error::Error;
+use storage::StorageOptions;
 
 #[async_trait]
 pub trait ServerTrait: Send + Sync + 'static {
     async fn run(&self) -> Result<(), Box<dyn Error>>;
 }
 
+/// What a server is created with.
+#[derive(Default)]
+pub struct ServerOptions {
+    /// Options of the storage of the databases.
+    pub storage: StorageOptions,
+    /// Deadlines of the commands.
+    pub timeouts: CommandTimeouts,
+    /// Slots and class weights the commands are run by.
+    pub scheduler: CommandScheduler,
+}
+
 pub struct ServerFactory;
 
 impl ServerFactory {
-    pub fn create_server(protocol: &str, addr: Option<String>) -> Option<Box<dyn ServerTrait>> {
+    pub fn create_server(
+        protocol: &str,
+        addr: Option<String>,
+        options: ServerOptions,
+    ) -> Option<Box<dyn ServerTrait>> {
         match protocol.to_lowercase().as_str() {
-            "tcp" => Some(Box::new(TcpServer::new(addr))),
+            "tcp" => Some(Box::new(TcpServer::new(addr, options))),
             #[cfg(unix)]
-            "unix" => Some(Box::new(unix::UnixServer::new(addr))),
+            "unix" => Some(Box::new(unix::UnixServer::new(addr, options))),
             #[cfg(not(unix))]
             "unix" => None,
             _ => None,
         }
     }
 }
+
+/// Adds the sections of the stats kept by this crate to INFO.
+pub(crate) fn register_info_sections() {
+    register_info_section("mirror", || mirror_stats().info());
+    register_info_section("capture", || capture_stats().info());
+}
@@ -0,0 +1,341 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Shadow traffic
+//!
+//! With a target configured, the dispatcher copies a sample of the commands
+//! it ran to another kiwi or Redis node, to try a new version on production
+//! traffic. Only the writes are mirrored by default, or the reads too, never
+//! the administrative commands. The sample is spread evenly: at 10%, one
+//! command in ten.
+//!
+//! The copies are queued and written in order on one connection, which
+//! switches the database with SELECT as needed. Mirroring never slows the
+//! clients down: when the queue is full, because the target is slow or
+//! down, the copy is dropped and counted. The replies of the target are
+//! read and only its errors counted, see the mirror section of INFO.
+
+use bytes::{Bytes, BytesMut};
+use cmd::{Cmd, CmdFlags};
+use log::{debug, info, warn};
+use resp::encode::RespEncoder;
+use resp::{Parse, RespData, RespEncode, RespParse, RespParseResult, RespVersion};
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// Copies waiting for the connection to the target
+const MIRROR_QUEUE_LEN: usize = 4096;
+
+/// Wait before connecting again to the target
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The commands mirrored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MirrorMode {
+    /// The commands that may modify the dataset
+    #[default]
+    Writes,
+    /// The reads and the writes
+    All,
+}
+
+impl FromStr for MirrorMode {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "writes" => Ok(Self::Writes),
+            "all" => Ok(Self::All),
+            _ => Err(io::Error::other(format!(
+                "invalid mirror mode '{s}', expected writes or all"
+            ))),
+        }
+    }
+}
+
+/// Where the commands are mirrored and which ones
+#[derive(Debug, Clone)]
+pub struct MirrorConfig {
+    /// `host:port` of the target
+    pub target: String,
+    pub mode: MirrorMode,
+    /// Percent of the commands mirrored, from 1 to 100
+    pub sample_percent: u64,
+}
+
+/// Sets the mirroring `run_mirror` does. Returns false if it was already set
+pub fn configure_mirror(config: MirrorConfig) -> bool {
+    mirror_config().set(config).is_ok()
+}
+
+fn mirror_config() -> &'static OnceLock<MirrorConfig> {
+    static CONFIG: OnceLock<MirrorConfig> = OnceLock::new();
+    &CONFIG
+}
+
+fn mirror_handle() -> &'static OnceLock<Mirror> {
+    static MIRROR: OnceLock<Mirror> = OnceLock::new();
+    &MIRROR
+}
+
+/// The mirror of the process, None until `run_mirror` started
+pub fn mirror() -> Option<&'static Mirror> {
+    mirror_handle().get()
+}
+
+/// A command to mirror, with the database it ran on
+struct MirroredCommand {
+    db_index: usize,
+    frame: Bytes,
+}
+
+/// Samples the commands and queues their copies
+pub struct Mirror {
+    mode: MirrorMode,
+    sample_percent: u64,
+    offered: AtomicU64,
+    queue: mpsc::Sender<MirroredCommand>,
+}
+
+impl Mirror {
+    /// Queues a copy of `cmd`, run with `argv` on `db_index`, if it is
+    /// mirrored and sampled
+    pub fn offer(&self, cmd: &dyn Cmd, db_index: usize, argv: &[Vec<u8>]) {
+        let mirrored = match self.mode {
+            MirrorMode::Writes => cmd.has_flag(CmdFlags::WRITE),
+            MirrorMode::All => cmd.has_flag(CmdFlags::WRITE) || cmd.has_flag(CmdFlags::READONLY),
+        };
+        if !mirrored || cmd.has_flag(CmdFlags::ADMIN) || !self.sampled() {
+            return;
+        }
+        let copy = MirroredCommand {
+            db_index,
+            frame: encode_command(argv),
+        };
+        match self.queue.try_send(copy) {
+            Ok(()) => mirror_stats().queued(),
+            Err(_) => mirror_stats().dropped(),
+        }
+    }
+
+    /// Whether the next offered command is in the sample: the sample grows
+    /// by one every time `offered * sample_percent / 100` does
+    fn sampled(&self) -> bool {
+        let n = self.offered.fetch_add(1, Ordering::Relaxed);
+        (n + 1) * self.sample_percent / 100 != n * self.sample_percent / 100
+    }
+}
+
+fn encode_command(argv: &[Vec<u8>]) -> Bytes {
+    let command = RespData::Array(Some(
+        argv.iter()
+            .map(|arg| RespData::BulkString(Some(Bytes::copy_from_slice(arg))))
+            .collect(),
+    ));
+    let mut encoder = RespEncoder::new(RespVersion::RESP2);
+    encoder.encode_resp_data(&command);
+    Bytes::copy_from_slice(encoder.as_bytes())
+}
+
+/// Mirrors the sampled commands to the configured target, connecting again
+/// whenever the connection fails. Returns right away when no target is
+/// configured.
+///
+/// usage:
+/// tokio::spawn(run_mirror());
+pub async fn run_mirror() {
+    let Some(config) = mirror_config().get() else {
+        return;
+    };
+    let (sender, mut queue) = mpsc::channel(MIRROR_QUEUE_LEN);
+    let mirror = Mirror {
+        mode: config.mode,
+        sample_percent: config.sample_percent.clamp(1, 100),
+        offered: AtomicU64::new(0),
+        queue: sender,
+    };
+    if mirror_handle().set(mirror).is_err() {
+        return;
+    }
+    mirror_stats().enable();
+    info!(
+        "mirroring {}% of the {:?} commands to {}",
+        config.sample_percent, config.mode, config.target
+    );
+
+    loop {
+        match TcpStream::connect(&config.target).await {
+            Ok(stream) => {
+                let (reader, writer) = stream.into_split();
+                let mut reader_task = tokio::spawn(read_replies(reader));
+                // A reader that stopped would leave the replies unread
+                let e = tokio::select! {
+                    e = write_copies(writer, &mut queue) => e,
+                    read = &mut reader_task => read.unwrap_or_else(io::Error::other),
+                };
+                reader_task.abort();
+                warn!("mirror: connection to {} broke: {e}", config.target);
+            }
+            Err(e) => warn!("mirror: connect to {} failed: {e}", config.target),
+        }
+        mirror_stats().disconnect();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Writes the queued copies until the connection fails. Everything queued
+/// while the last write was in flight goes out at once
+async fn write_copies(
+    mut writer: OwnedWriteHalf,
+    queue: &mut mpsc::Receiver<MirroredCommand>,
+) -> io::Error {
+    // A new connection starts on the database 0
+    let mut db_index = 0;
+    let mut out = BytesMut::new();
+    while let Some(first) = queue.recv().await {
+        let mut copy = Some(first);
+        let mut n = 0;
+        while let Some(MirroredCommand {
+            db_index: copy_db,
+            frame,
+        }) = copy.take().or_else(|| queue.try_recv().ok())
+        {
+            if copy_db != db_index {
+                out.extend_from_slice(&encode_command(&[
+                    b"SELECT".to_vec(),
+                    copy_db.to_string().into_bytes(),
+                ]));
+                db_index = copy_db;
+            }
+            out.extend_from_slice(&frame);
+            n += 1;
+        }
+        if let Err(e) = writer.write_all(&out).await {
+            return e;
+        }
+        out.clear();
+        mirror_stats().sent(n);
+    }
+    // The sender lives in a static, the queue is never closed
+    io::Error::new(io::ErrorKind::BrokenPipe, "mirror queue closed")
+}
+
+/// Reads the replies of the target and counts its errors, until the
+/// connection fails
+async fn read_replies(mut reader: OwnedReadHalf) -> io::Error {
+    let mut parser = RespParse::new(RespVersion::RESP2);
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) => return io::ErrorKind::UnexpectedEof.into(),
+            Ok(n) => n,
+            Err(e) => return e,
+        };
+        let mut data = Bytes::copy_from_slice(&buf[..n]);
+        loop {
+            match parser.parse(std::mem::take(&mut data)) {
+                RespParseResult::Complete(RespData::Error(e)) => {
+                    mirror_stats().error();
+                    debug!("mirror: target replied {}", String::from_utf8_lossy(&e));
+                }
+                RespParseResult::Complete(_) => {}
+                RespParseResult::Incomplete => break,
+                RespParseResult::Error(e) => {
+                    return io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+                }
+            }
+        }
+        // Replies are not commands, drop what the parser queued
+        while parser.next_command().is_some() {}
+    }
+}
+
+/// Commands mirrored to a shadow endpoint, updated by the mirror of the
+/// dispatcher and reported by INFO.
+#[derive(Debug, Default)]
+pub struct MirrorStats {
+    enabled: AtomicBool,
+    // Commands sampled and queued for the endpoint
+    queued: AtomicU64,
+    // Commands sampled but dropped, the queue was full
+    dropped: AtomicU64,
+    // Commands written to the endpoint, and the error replies it sent back
+    sent: AtomicU64,
+    errors: AtomicU64,
+    // Connections to the endpoint that failed or broke
+    disconnects: AtomicU64,
+}
+
+impl MirrorStats {
+    /// Mirroring started.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// A sampled command was queued.
+    pub fn queued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A sampled command was dropped.
+    pub fn dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// `n` commands were written to the endpoint.
+    pub fn sent(&self, n: u64) {
+        self.sent.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// The endpoint replied with an error.
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The connection to the endpoint failed or broke.
+    pub fn disconnect(&self) {
+        self.disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The INFO section of the stats.
+    pub fn info(&self) -> String {
+        format!(
+            "# Mirror\r\nmirror_enabled:{}\r\nmirror_queued:{}\r\nmirror_dropped:{}\r\nmirror_sent:{}\r\nmirror_errors:{}\r\nmirror_disconnects:{}\r\n",
+            self.enabled.load(Ordering::Relaxed) as u8,
+            self.queued.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.sent.load(Ordering::Relaxed),
+            self.errors.load(Ordering::Relaxed),
+            self.disconnects.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// The mirror stats of the process.
+pub fn mirror_stats() -> &'static MirrorStats {
+    static STATS: OnceLock<MirrorStats> = OnceLock::new();
+    STATS.get_or_init(MirrorStats::default)
+}
@@ -0,0 +1,519 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Proxy mode
+//!
+//! The node stores nothing and routes every command to one of the backend
+//! nodes, chosen by the key on a consistent hashing ring, so that adding or
+//! removing a backend only moves the keys of its neighbours on the ring.
+//! Keys with a hash tag are placed by the tag, like slots are.
+//!
+//! The backends are a `NodeSource`, resolved again every refresh interval.
+//! When the addresses change the ring is rebuilt, the connections of the
+//! remaining backends are kept and those of the removed ones are closed
+//! once their pending replies arrived.
+//!
+//! All the clients share one connection per backend: the requests of every
+//! client waiting when the connection is free are written at once, and the
+//! replies come back in the same order. The keys of a command are at the
+//! positions its metadata gives. Commands the backends do not know, without
+//! keys at fixed positions or over keys of several backends are refused.
+
+use crate::discovery::{watch_nodes, NodeSource};
+use crate::ServerTrait;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use cmd::table::{create_command_table, CmdTable};
+use log::{error, info, warn};
+use resp::encode::RespEncoder;
+use resp::{Parse, RespData, RespEncode, RespParse, RespParseResult, RespVersion};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use storage::extract_hash_tag;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch};
+
+/// Points of a backend on the ring
+const VIRTUAL_NODES: usize = 160;
+
+/// Requests waiting for the connection of a backend
+const BACKEND_QUEUE_LEN: usize = 4096;
+
+/// Commands that need every key, a connection state or a single node
+const UNSUPPORTED_COMMANDS: &[&str] = &[
+    "select",
+    "swapdb",
+    "move",
+    "keys",
+    "scan",
+    "randomkey",
+    "dbsize",
+    "flushdb",
+    "flushall",
+    "multi",
+    "exec",
+    "discard",
+    "watch",
+    "unwatch",
+    "subscribe",
+    "psubscribe",
+    "unsubscribe",
+    "punsubscribe",
+    "publish",
+    "info",
+    "config",
+    "client",
+    "wait",
+];
+
+/// A consistent hashing ring over the backends
+pub struct HashRing {
+    nodes: Vec<String>,
+    // (point, index in nodes), sorted by point
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    pub fn new(nodes: Vec<String>) -> Self {
+        let mut points = Vec::with_capacity(nodes.len() * VIRTUAL_NODES);
+        for (index, node) in nodes.iter().enumerate() {
+            for vnode in 0..VIRTUAL_NODES {
+                points.push((fnv1a(format!("{node}#{vnode}").as_bytes()), index));
+            }
+        }
+        points.sort_unstable();
+        Self { nodes, points }
+    }
+
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    /// Index of the backend owning `key`: the first point at or after the
+    /// hash of its hash tag, wrapping around
+    pub fn node_for(&self, key: &[u8]) -> usize {
+        let hash = fnv1a(extract_hash_tag(key));
+        let at = self.points.partition_point(|(point, _)| *point < hash);
+        self.points[at % self.points.len()].1
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds and platforms unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Where a command goes
+enum Route {
+    Backend(usize),
+    Reply(RespData),
+}
+
+/// Routes by the keys at the positions of the command metadata. A command
+/// the backends do not know, or without keys at fixed positions, is
+/// refused rather than sent to an arbitrary backend.
+fn route(ring: &HashRing, cmd_table: &CmdTable, argv: &[Bytes]) -> Route {
+    let name = String::from_utf8_lossy(&argv[0]).to_lowercase();
+    let error = |message: String| Route::Reply(RespData::Error(message.into()));
+
+    if name == "ping" {
+        return Route::Reply(match argv.get(1) {
+            Some(message) => RespData::BulkString(Some(message.clone())),
+            None => RespData::SimpleString("PONG".into()),
+        });
+    }
+    if UNSUPPORTED_COMMANDS.contains(&name.as_str()) {
+        return error(format!("ERR '{name}' is not supported in proxy mode"));
+    }
+    let Some(cmd) = cmd_table.get(&name) else {
+        return error(format!("ERR unknown command `{name}`"));
+    };
+    if !cmd.check_arg(argv.len()) {
+        return error(format!(
+            "ERR wrong number of arguments for '{name}' command"
+        ));
+    }
+
+    let keys = cmd.key_positions(argv.len());
+    let Some(&first) = keys.first() else {
+        return error(format!("ERR '{name}' is not supported in proxy mode"));
+    };
+    let backend = ring.node_for(&argv[first]);
+    if keys[1..]
+        .iter()
+        .all(|&position| ring.node_for(&argv[position]) == backend)
+    {
+        Route::Backend(backend)
+    } else {
+        error("CROSSSLOT Keys in request don't hash to the same node".to_string())
+    }
+}
+
+/// A reply to write back to the client, in the order of the commands
+enum PendingReply {
+    Ready(RespData),
+    Backend(usize, oneshot::Receiver<RespData>),
+}
+
+/// A command for a backend and where its reply goes
+struct Request {
+    frame: Bytes,
+    reply: oneshot::Sender<RespData>,
+}
+
+/// Serves the requests for `addr` on one connection, opened on the first
+/// request and again on the first one after it broke
+async fn run_backend(addr: String, mut requests: mpsc::Receiver<Request>) {
+    while let Some(first) = requests.recv().await {
+        let stream = match TcpStream::connect(&addr).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("proxy: connect to backend {addr} failed: {e}");
+                // Dropping the request replies with an error
+                continue;
+            }
+        };
+        let (reader, writer) = stream.into_split();
+        let (pending_tx, pending_rx) = mpsc::unbounded_channel();
+        let reader_task = tokio::spawn(read_replies(reader, pending_rx));
+        match write_requests(writer, first, &mut requests, &pending_tx).await {
+            // The backend was removed, the replies still pending are delivered
+            Ok(()) => {
+                drop(pending_tx);
+                let _ = reader_task.await;
+                info!("proxy: closed the connection to removed backend {addr}");
+                return;
+            }
+            Err(e) => {
+                warn!("proxy: connection to backend {addr} broke: {e}");
+                // Fails the requests still waiting for a reply
+                reader_task.abort();
+            }
+        }
+    }
+}
+
+async fn write_requests(
+    mut writer: OwnedWriteHalf,
+    first: Request,
+    requests: &mut mpsc::Receiver<Request>,
+    pending: &mpsc::UnboundedSender<oneshot::Sender<RespData>>,
+) -> io::Result<()> {
+    let mut out = BytesMut::new();
+    let mut next = Some(first);
+    while let Some(request) = next.take() {
+        let mut request = Some(request);
+        // Everything queued while the last write was in flight goes out at once
+        while let Some(Request { frame, reply }) =
+            request.take().or_else(|| requests.try_recv().ok())
+        {
+            out.extend_from_slice(&frame);
+            pending
+                .send(reply)
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "backend closed"))?;
+        }
+        writer.write_all(&out).await?;
+        out.clear();
+        next = requests.recv().await;
+    }
+    Ok(())
+}
+
+/// Reads a reply for every waiter, until the writer stops sending them
+async fn read_replies(
+    mut reader: OwnedReadHalf,
+    mut pending: mpsc::UnboundedReceiver<oneshot::Sender<RespData>>,
+) -> io::Result<()> {
+    let mut parser = RespParse::new(RespVersion::RESP2);
+    let mut buf = vec![0; 16 * 1024];
+    while let Some(waiter) = pending.recv().await {
+        let mut data = Bytes::new();
+        let reply = loop {
+            match parser.parse(std::mem::take(&mut data)) {
+                RespParseResult::Complete(reply) => break reply,
+                RespParseResult::Incomplete => {
+                    let n = reader.read(&mut buf).await?;
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    data = Bytes::copy_from_slice(&buf[..n]);
+                }
+                RespParseResult::Error(e) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+                }
+            }
+        };
+        // Replies are not commands, drop what the parser queued
+        while parser.next_command().is_some() {}
+        // The client may be gone
+        let _ = waiter.send(reply);
+    }
+    Ok(())
+}
+
+fn encode_command(argv: &[Bytes]) -> Bytes {
+    let command = RespData::Array(Some(
+        argv.iter()
+            .map(|arg| RespData::BulkString(Some(arg.clone())))
+            .collect(),
+    ));
+    let mut encoder = RespEncoder::new(RespVersion::RESP2);
+    encoder.encode_resp_data(&command);
+    Bytes::copy_from_slice(encoder.as_bytes())
+}
+
+/// The ring and the queues of its backends, replaced as a whole when the
+/// backends change
+struct Routing {
+    ring: HashRing,
+    backends: Vec<mpsc::Sender<Request>>,
+}
+
+impl Routing {
+    /// Routes to `nodes`, reusing the connections of `pool` and dropping
+    /// those of the nodes that are gone
+    fn new(nodes: Vec<String>, pool: &mut HashMap<String, mpsc::Sender<Request>>) -> Self {
+        pool.retain(|addr, _| nodes.contains(addr));
+        let backends = nodes
+            .iter()
+            .map(|addr| {
+                pool.entry(addr.clone())
+                    .or_insert_with(|| {
+                        let (sender, receiver) = mpsc::channel(BACKEND_QUEUE_LEN);
+                        tokio::spawn(run_backend(addr.clone(), receiver));
+                        sender
+                    })
+                    .clone()
+            })
+            .collect();
+        Self {
+            ring: HashRing::new(nodes),
+            backends,
+        }
+    }
+}
+
+/// Rebuilds the routing whenever the backends change
+async fn update_routing(
+    mut nodes: watch::Receiver<Vec<String>>,
+    mut pool: HashMap<String, mpsc::Sender<Request>>,
+    routing: watch::Sender<Arc<Routing>>,
+) {
+    while nodes.changed().await.is_ok() {
+        let nodes = nodes.borrow_and_update().clone();
+        info!("proxy: routing to {}", nodes.join(","));
+        routing.send_replace(Arc::new(Routing::new(nodes, &mut pool)));
+    }
+}
+
+pub struct ProxyServer {
+    addr: String,
+    backends: NodeSource,
+    refresh_interval: Duration,
+    cmd_table: Arc<CmdTable>,
+}
+
+impl ProxyServer {
+    /// Proxies `addr` to `backends`, resolved again every
+    /// `refresh_interval`, never if it is zero
+    pub fn new(addr: Option<String>, backends: NodeSource, refresh_interval: Duration) -> Self {
+        Self {
+            addr: addr.unwrap_or("127.0.0.1:9221".to_string()),
+            backends,
+            refresh_interval,
+            cmd_table: Arc::new(create_command_table()),
+        }
+    }
+}
+
+#[async_trait]
+impl ServerTrait for ProxyServer {
+    async fn run(&self) -> Result<(), Box<dyn Error>> {
+        let nodes = watch_nodes(self.backends.clone(), self.refresh_interval).await?;
+        let listener = TcpListener::bind(&self.addr).await?;
+        info!("Proxying TCP {} to {}", self.addr, nodes.borrow().join(","));
+
+        let mut pool = HashMap::new();
+        let initial = Routing::new(nodes.borrow().clone(), &mut pool);
+        let (routing_tx, routing) = watch::channel(Arc::new(initial));
+        tokio::spawn(update_routing(nodes, pool, routing_tx));
+
+        loop {
+            let (socket, _) = listener.accept().await?;
+            let routing = routing.clone();
+            let cmd_table = self.cmd_table.clone();
+            tokio::spawn(async move {
+                if let Err(e) = proxy_connection(socket, routing, cmd_table).await {
+                    error!("proxy connection error: {e:?}");
+                }
+            });
+        }
+    }
+}
+
+/// Routes the commands of a client. The commands of one read are sent
+/// before the first reply is awaited, and their replies written at once.
+/// They are all routed by the backends current when the read completed.
+async fn proxy_connection(
+    mut socket: TcpStream,
+    routing: watch::Receiver<Arc<Routing>>,
+    cmd_table: Arc<CmdTable>,
+) -> io::Result<()> {
+    let mut parser = RespParse::new(RespVersion::RESP2);
+    let mut encoder = RespEncoder::new(RespVersion::RESP2);
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        let current = Arc::clone(&routing.borrow());
+        let Routing { ring, backends } = current.as_ref();
+        let mut replies = Vec::new();
+        let mut data = Bytes::copy_from_slice(&buf[..n]);
+        let mut quit = false;
+        loop {
+            let argv = match parser.parse(std::mem::take(&mut data)) {
+                RespParseResult::Complete(RespData::Array(Some(params))) => params
+                    .into_iter()
+                    .filter_map(|param| match param {
+                        RespData::BulkString(Some(arg)) => Some(arg),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+                RespParseResult::Complete(RespData::Inline(parts)) => parts,
+                RespParseResult::Complete(_) => continue,
+                RespParseResult::Incomplete => break,
+                RespParseResult::Error(e) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+                }
+            };
+            while parser.next_command().is_some() {}
+            if argv.is_empty() {
+                continue;
+            }
+            if argv[0].eq_ignore_ascii_case(b"quit") {
+                replies.push(PendingReply::Ready(RespData::SimpleString("OK".into())));
+                quit = true;
+                break;
+            }
+
+            match route(ring, &cmd_table, &argv) {
+                Route::Reply(reply) => replies.push(PendingReply::Ready(reply)),
+                Route::Backend(backend) => {
+                    let (reply, receiver) = oneshot::channel();
+                    let request = Request {
+                        frame: encode_command(&argv),
+                        reply,
+                    };
+                    // A closed queue drops the request, its receiver errors
+                    let _ = backends[backend].send(request).await;
+                    replies.push(PendingReply::Backend(backend, receiver));
+                }
+            }
+        }
+
+        let mut out = BytesMut::new();
+        for reply in replies {
+            let reply = match reply {
+                PendingReply::Ready(reply) => reply,
+                PendingReply::Backend(backend, receiver) => receiver.await.unwrap_or_else(|_| {
+                    RespData::Error(
+                        format!("ERR backend {} unavailable", ring.nodes()[backend]).into(),
+                    )
+                }),
+            };
+            encoder.clear().encode_resp_data(&reply);
+            out.extend_from_slice(encoder.as_bytes());
+        }
+        socket.write_all(&out).await?;
+        if quit {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &[&str]) -> Vec<Bytes> {
+        args.iter()
+            .map(|arg| Bytes::from(arg.to_string()))
+            .collect()
+    }
+
+    fn routed_to(route: Route) -> Result<usize, String> {
+        match route {
+            Route::Backend(backend) => Ok(backend),
+            Route::Reply(RespData::Error(message)) => {
+                Err(String::from_utf8_lossy(&message).into_owned())
+            }
+            Route::Reply(_) => Err(String::new()),
+        }
+    }
+
+    #[test]
+    fn test_route_by_key_positions() {
+        let ring = HashRing::new(vec!["a:1".to_string(), "b:1".to_string()]);
+        let cmd_table = create_command_table();
+        let routed = |args: &[&str]| routed_to(route(&ring, &cmd_table, &argv(args)));
+        // Two keys of each backend
+        let key_of = |backend: usize| {
+            (0..)
+                .map(|i| format!("key{i}"))
+                .find(|key| ring.node_for(key.as_bytes()) == backend)
+                .unwrap()
+        };
+        let (a, b) = (key_of(0), key_of(1));
+
+        assert_eq!(routed(&["GET", &a]), Ok(0));
+        assert_eq!(routed(&["set", &b, "value"]), Ok(1));
+        assert_eq!(routed(&["del", &b, &b]), Ok(1));
+        // LMOVE has both lists as keys
+        assert!(routed(&["lmove", &a, &b, "left", "right"])
+            .unwrap_err()
+            .starts_with("CROSSSLOT"));
+        assert!(routed(&["exists", &a, &b])
+            .unwrap_err()
+            .starts_with("CROSSSLOT"));
+
+        // Keys named by an argument, no keys, or an unknown command
+        assert!(routed(&["lmpop", "1", &a, "left"])
+            .unwrap_err()
+            .contains("not supported"));
+        assert!(routed(&["keys", "*"])
+            .unwrap_err()
+            .contains("not supported"));
+        assert!(routed(&["nosuchcmd", &a])
+            .unwrap_err()
+            .starts_with("ERR unknown command"));
+        assert!(routed(&["get"])
+            .unwrap_err()
+            .starts_with("ERR wrong number of arguments"));
+    }
+}
@@ -0,0 +1,363 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Trace replay
+//!
+//! `replay` sends the commands of captured trace files to a target, a kiwi
+//! or a Redis node, and compares its replies with the captured ones. Every
+//! captured connection gets its own connection to the target and sends its
+//! commands one at a time, in their order, like the client did. The
+//! commands start at their captured times, scaled by the speed, or as fast
+//! as the target answers.
+//!
+//! The replies are compared as parsed RESP values. Those of the commands in
+//! `UNCHECKED_COMMANDS` depend on the node or on chance and are only
+//! counted. Frames the capture dropped pair the commands with the wrong
+//! replies and show up as divergences.
+
+use crate::capture::{trace_files, Direction, TraceReader, TraceRecord};
+use bytes::Bytes;
+use log::warn;
+use resp::{Parse, RespData, RespParse, RespParseResult, RespVersion};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// Commands whose replies are not compared
+const UNCHECKED_COMMANDS: &[&str] = &[
+    "info",
+    "time",
+    "client",
+    "debug",
+    "memory",
+    "config",
+    "randomkey",
+    "scan",
+    "spop",
+    "srandmember",
+    "hrandfield",
+    "zrandmember",
+];
+
+/// Divergences kept in the report
+const MAX_SAMPLES: usize = 20;
+
+/// Records read ahead of the replay
+const RECORD_QUEUE_LEN: usize = 4096;
+
+/// Where and how fast to replay
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// `host:port` of the target
+    pub target: String,
+    /// Factor the captured pace is sped up by, None to send every command
+    /// as soon as the previous one of its connection got its reply
+    pub speed: Option<f64>,
+}
+
+/// A reply of the target that is not the captured one
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub conn_id: u64,
+    pub command: String,
+    pub expected: RespData,
+    pub actual: RespData,
+}
+
+/// What a replay did
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// Captured connections replayed
+    pub connections: u64,
+    /// Commands sent to the target
+    pub requests: u64,
+    /// Replies compared, and those that differ
+    pub compared: u64,
+    pub divergences: u64,
+    /// Replies of `UNCHECKED_COMMANDS`
+    pub unchecked: u64,
+    /// Commands not replayed, the connection of theirs to the target failed
+    pub failed: u64,
+    /// The first divergences
+    pub samples: Vec<Divergence>,
+    pub elapsed: Duration,
+}
+
+impl ReplayReport {
+    /// Whether every reply compared matched and every command was sent
+    pub fn matched(&self) -> bool {
+        self.divergences == 0 && self.failed == 0
+    }
+
+    fn diverged(&mut self, divergence: Divergence) {
+        self.divergences += 1;
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(divergence);
+        }
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "connections: {}, requests: {}, compared: {}, divergences: {}, unchecked: {}, failed: {}, elapsed: {:?}",
+            self.connections,
+            self.requests,
+            self.compared,
+            self.divergences,
+            self.unchecked,
+            self.failed,
+            self.elapsed
+        )?;
+        for sample in &self.samples {
+            writeln!(
+                f,
+                "connection {} `{}`: expected {:?}, got {:?}",
+                sample.conn_id, sample.command, sample.expected, sample.actual
+            )?;
+        }
+        write!(
+            f,
+            "result: {}",
+            if self.matched() { "MATCH" } else { "DIVERGED" }
+        )
+    }
+}
+
+/// The trace files of `path`: the file itself, or those of the directory
+/// oldest first
+pub fn trace_paths(path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    if path.is_dir() {
+        trace_files(path)
+    } else {
+        Ok(vec![path.to_path_buf()])
+    }
+}
+
+/// Replays the trace files `paths`, in their order, against the target of
+/// `options`. Fails if a file cannot be read, a failed connection to the
+/// target is only counted.
+pub async fn replay(paths: Vec<PathBuf>, options: ReplayOptions) -> io::Result<ReplayReport> {
+    let (records_tx, mut records) = mpsc::channel(RECORD_QUEUE_LEN);
+    let reader = std::thread::spawn(move || read_traces(paths, records_tx));
+
+    let report = Arc::new(Mutex::new(ReplayReport::default()));
+    let target: Arc<str> = options.target.into();
+    let mut connections: HashMap<u64, mpsc::UnboundedSender<TraceRecord>> = HashMap::new();
+    let mut tasks = JoinSet::new();
+    let started = Instant::now();
+    let mut first_timestamp = None;
+
+    while let Some(record) = records.recv().await {
+        if record.direction == Direction::Request {
+            if let Some(speed) = options.speed {
+                let first = *first_timestamp.get_or_insert(record.timestamp_micros);
+                let offset = record.timestamp_micros.saturating_sub(first) as f64 / speed;
+                tokio::time::sleep_until(started + Duration::from_micros(offset as u64)).await;
+            }
+        }
+        let conn_id = record.conn_id;
+        let connection = connections.entry(conn_id).or_insert_with(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tasks.spawn(replay_connection(
+                Arc::clone(&target),
+                conn_id,
+                receiver,
+                Arc::clone(&report),
+            ));
+            sender
+        });
+        // The task only stops once its channel is closed
+        let _ = connection.send(record);
+    }
+    drop(connections);
+    while tasks.join_next().await.is_some() {}
+
+    reader
+        .join()
+        .map_err(|_| io::Error::other("trace reader panicked"))??;
+    let mut report = std::mem::take(&mut *report.lock().unwrap());
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+/// Sends the records of every file to `records`, until it is closed
+fn read_traces(paths: Vec<PathBuf>, records: mpsc::Sender<TraceRecord>) -> io::Result<()> {
+    for path in paths {
+        for record in TraceReader::open(&path)? {
+            let record = match record {
+                Ok(record) => record,
+                // The capture stopped while writing the last record
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    warn!("replay: {} ends with a partial record", path.display());
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            if records.blocking_send(record).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The lowercase name of the command `data`, None if it is not a command
+fn command_name(data: &RespData) -> Option<String> {
+    match data {
+        RespData::Array(Some(params)) => match params.first()? {
+            RespData::BulkString(Some(name)) => Some(String::from_utf8_lossy(name).to_lowercase()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Parses the single RESP value of `frame`
+fn parse_frame(frame: Bytes) -> Option<RespData> {
+    match RespParse::new(RespVersion::RESP2).parse(frame) {
+        RespParseResult::Complete(data) => Some(data),
+        _ => None,
+    }
+}
+
+/// A connection to the target
+struct TargetConnection {
+    stream: TcpStream,
+    parser: RespParse,
+    buf: Vec<u8>,
+}
+
+impl TargetConnection {
+    async fn connect(target: &str) -> io::Result<Self> {
+        Ok(Self {
+            stream: TcpStream::connect(target).await?,
+            parser: RespParse::new(RespVersion::RESP2),
+            buf: vec![0; 16 * 1024],
+        })
+    }
+
+    /// Sends `frame` and reads its reply
+    async fn call(&mut self, frame: &[u8]) -> io::Result<RespData> {
+        self.stream.write_all(frame).await?;
+        let mut data = Bytes::new();
+        loop {
+            match self.parser.parse(std::mem::take(&mut data)) {
+                RespParseResult::Complete(reply) => {
+                    // Replies are not commands, drop what the parser queued
+                    while self.parser.next_command().is_some() {}
+                    return Ok(reply);
+                }
+                RespParseResult::Incomplete => {
+                    let n = self.stream.read(&mut self.buf).await?;
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    data = Bytes::copy_from_slice(&self.buf[..n]);
+                }
+                RespParseResult::Error(e) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+                }
+            }
+        }
+    }
+}
+
+/// Replays the records of the captured connection `conn_id`. A command is
+/// sent once its record comes, and its reply compared once the captured one
+/// comes.
+async fn replay_connection(
+    target: Arc<str>,
+    conn_id: u64,
+    mut records: mpsc::UnboundedReceiver<TraceRecord>,
+    report: Arc<Mutex<ReplayReport>>,
+) {
+    report.lock().unwrap().connections += 1;
+    let mut connection: Option<TargetConnection> = None;
+    let mut broken = false;
+    // Commands sent and the replies of the target, waiting for the
+    // captured replies
+    let mut replies: VecDeque<(String, RespData)> = VecDeque::new();
+
+    while let Some(record) = records.recv().await {
+        match record.direction {
+            Direction::Request => {
+                // The server only answers the commands sent as arrays
+                let Some(command) = parse_frame(record.frame.clone())
+                    .as_ref()
+                    .and_then(command_name)
+                else {
+                    continue;
+                };
+                if broken {
+                    report.lock().unwrap().failed += 1;
+                    continue;
+                }
+                let result = match &mut connection {
+                    Some(connection) => connection.call(&record.frame).await,
+                    None => match TargetConnection::connect(&target).await {
+                        Ok(new) => connection.insert(new).call(&record.frame).await,
+                        Err(e) => Err(e),
+                    },
+                };
+                match result {
+                    Ok(reply) => {
+                        report.lock().unwrap().requests += 1;
+                        replies.push_back((command, reply));
+                    }
+                    Err(e) => {
+                        warn!("replay: connection {conn_id} to {target} failed: {e}");
+                        broken = true;
+                        report.lock().unwrap().failed += 1;
+                    }
+                }
+            }
+            Direction::Reply => {
+                let Some((command, actual)) = replies.pop_front() else {
+                    continue;
+                };
+                let mut report = report.lock().unwrap();
+                if UNCHECKED_COMMANDS.contains(&command.as_str()) {
+                    report.unchecked += 1;
+                    continue;
+                }
+                report.compared += 1;
+                let expected = parse_frame(record.frame).unwrap_or_default();
+                if expected != actual {
+                    report.diverged(Divergence {
+                        conn_id,
+                        command,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,376 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Fair admission of commands to the storage.
+//!
+//! Commands run on the blocking threads of the runtime, the connection
+//! tasks only read them and write their replies, so a client pipelining a
+//! long batch could keep the storage busy at the expense of the others.
+//! Every command first takes one of the `per_connection` slots of its
+//! connection, then one of the `max_inflight` slots shared by all of them.
+//! A connection thus never holds more than `per_connection` shared slots,
+//! and a shared slot is released after every command, so a pipeliner
+//! queues again behind the other clients for each of its commands.
+//!
+//! While the shared slots are all taken the waiting commands are served by
+//! weighted round robin over their `CommandClass`: in every round each
+//! class with waiting commands gets up to its weight of the freed slots, in
+//! the order replication, admin, point, bulk, and commands of one class
+//! are served in FIFO order. Bulk scans thus cannot starve point lookups,
+//! and replication apply and admin commands get ahead of both.
+
+use client::{command_queue_stats, CommandClass};
+use kstd::resources::resource_limits;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+
+/// The share of the freed shared slots each class gets per round while
+/// several classes wait. A weight of 0 counts as 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassWeights {
+    pub replication: u32,
+    pub admin: u32,
+    pub point: u32,
+    pub bulk: u32,
+}
+
+impl Default for ClassWeights {
+    fn default() -> Self {
+        Self {
+            replication: 8,
+            admin: 4,
+            point: 4,
+            bulk: 1,
+        }
+    }
+}
+
+impl ClassWeights {
+    /// Sets the weight of the class named `class`. Returns false if there
+    /// is no such class.
+    pub fn set(&mut self, class: &str, weight: u32) -> bool {
+        let weight_of = match class {
+            "replication" => &mut self.replication,
+            "admin" => &mut self.admin,
+            "point" => &mut self.point,
+            "bulk" => &mut self.bulk,
+            _ => return false,
+        };
+        *weight_of = weight;
+        true
+    }
+
+    fn credits(&self) -> [u32; CommandClass::ALL.len()] {
+        [self.replication, self.admin, self.point, self.bulk].map(|weight| weight.max(1))
+    }
+}
+
+pub struct CommandScheduler {
+    shared: Arc<SharedSlots>,
+    per_connection: usize,
+}
+
+/// One shared slot per CPU of the process but one, left to the connection
+/// tasks, and at least one.
+pub fn default_max_inflight() -> usize {
+    resource_limits().threads().saturating_sub(1).max(1)
+}
+
+impl Default for CommandScheduler {
+    /// One command at a time per connection.
+    fn default() -> Self {
+        Self::new(default_max_inflight(), 1)
+    }
+}
+
+impl CommandScheduler {
+    pub fn new(max_inflight: usize, per_connection: usize) -> Self {
+        Self::with_weights(max_inflight, per_connection, ClassWeights::default())
+    }
+
+    pub fn with_weights(max_inflight: usize, per_connection: usize, weights: ClassWeights) -> Self {
+        Self {
+            shared: Arc::new(SharedSlots {
+                weights,
+                state: Mutex::new(SlotsState {
+                    free: max_inflight.max(1),
+                    waiters: Default::default(),
+                    credits: weights.credits(),
+                }),
+            }),
+            per_connection: per_connection.max(1),
+        }
+    }
+
+    /// The slots of a new connection.
+    pub fn connection(&self) -> ConnectionSlots {
+        ConnectionSlots {
+            shared: Arc::clone(&self.shared),
+            own: Arc::new(Semaphore::new(self.per_connection)),
+        }
+    }
+}
+
+pub struct ConnectionSlots {
+    shared: Arc<SharedSlots>,
+    own: Arc<Semaphore>,
+}
+
+impl ConnectionSlots {
+    /// Waits for a slot of this connection, then a shared one for a command
+    /// of `class`. The command runs while the returned permit is held.
+    pub async fn acquire(&self, class: CommandClass) -> CommandPermit {
+        let own = self.acquire_own().await;
+        SharedSlots::acquire(&self.shared, class).await;
+        command_queue_stats().start();
+        CommandPermit {
+            _own: own,
+            shared: Some(Arc::clone(&self.shared)),
+        }
+    }
+
+    /// Waits for a slot of this connection only, for a command that waits
+    /// for other clients and would hold a shared slot all along.
+    pub async fn acquire_unshared(&self) -> CommandPermit {
+        CommandPermit {
+            _own: self.acquire_own().await,
+            shared: None,
+        }
+    }
+
+    async fn acquire_own(&self) -> OwnedSemaphorePermit {
+        Arc::clone(&self.own)
+            .acquire_owned()
+            .await
+            .expect("connection slots are never closed")
+    }
+}
+
+pub struct CommandPermit {
+    _own: OwnedSemaphorePermit,
+    shared: Option<Arc<SharedSlots>>,
+}
+
+impl Drop for CommandPermit {
+    fn drop(&mut self) {
+        if let Some(shared) = self.shared.take() {
+            command_queue_stats().finish();
+            shared.release();
+        }
+    }
+}
+
+struct SharedSlots {
+    weights: ClassWeights,
+    state: Mutex<SlotsState>,
+}
+
+struct SlotsState {
+    free: usize,
+    // The commands waiting for a slot, by class, woken through their sender
+    waiters: [VecDeque<oneshot::Sender<()>>; CommandClass::ALL.len()],
+    // What is left of the weights of the classes in the current round
+    credits: [u32; CommandClass::ALL.len()],
+}
+
+impl SharedSlots {
+    async fn acquire(shared: &Arc<Self>, class: CommandClass) {
+        let receiver = {
+            let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.free > 0 && state.waiters.iter().all(VecDeque::is_empty) {
+                state.free -= 1;
+                return;
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiters[class as usize].push_back(sender);
+            receiver
+        };
+
+        command_queue_stats().enqueue(class);
+        let mut waiter = Waiter {
+            shared: Arc::clone(shared),
+            class,
+            start: Instant::now(),
+            receiver,
+        };
+        (&mut waiter.receiver)
+            .await
+            .expect("the sender of a waiter is only dropped once it is closed");
+    }
+
+    /// Hands the slot to the next waiting command, or frees it.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some(sender) = state.next_waiter(&self.weights) {
+            // Fails when the waiting command was cancelled
+            if sender.send(()).is_ok() {
+                return;
+            }
+        }
+        state.free += 1;
+    }
+}
+
+impl SlotsState {
+    fn next_waiter(&mut self, weights: &ClassWeights) -> Option<oneshot::Sender<()>> {
+        if self.waiters.iter().all(VecDeque::is_empty) {
+            return None;
+        }
+        loop {
+            let class = (0..self.waiters.len())
+                .find(|&class| self.credits[class] > 0 && !self.waiters[class].is_empty());
+            match class {
+                Some(class) => {
+                    self.credits[class] -= 1;
+                    return self.waiters[class].pop_front();
+                }
+                // Every waiting class used up its weight, start a new round
+                None => self.credits = weights.credits(),
+            }
+        }
+    }
+}
+
+// A command waiting for a shared slot. Dropping it before the slot arrives
+// leaves its sender to be skipped, and a slot that arrived in between is
+// released again.
+struct Waiter {
+    shared: Arc<SharedSlots>,
+    class: CommandClass,
+    start: Instant,
+    receiver: oneshot::Receiver<()>,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        command_queue_stats().dequeue(self.class, self.start.elapsed());
+        self.receiver.close();
+        if self.receiver.try_recv().is_ok() {
+            self.shared.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bulk_cannot_starve_point() {
+        let scheduler = Arc::new(CommandScheduler::new(1, 1));
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let running = scheduler.connection().acquire(CommandClass::Bulk).await;
+
+        // A backlog of bulk commands, then two point commands behind it
+        let mut tasks = Vec::new();
+        for class in [CommandClass::Bulk; 8]
+            .into_iter()
+            .chain([CommandClass::Point; 2])
+        {
+            let scheduler = Arc::clone(&scheduler);
+            let served = Arc::clone(&served);
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler.connection().acquire(class).await;
+                served.lock().unwrap().push(class);
+            }));
+            // Lets the command queue before the next one
+            tokio::task::yield_now().await;
+        }
+
+        drop(running);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let served = served.lock().unwrap();
+        assert_eq!(served.len(), 10);
+        assert_eq!(served[..2], [CommandClass::Point; 2]);
+    }
+
+    #[tokio::test]
+    async fn test_class_weights() {
+        let mut weights = ClassWeights {
+            replication: 1,
+            admin: 1,
+            point: 2,
+            bulk: 2,
+        };
+        assert!(weights.set("bulk", 0));
+        assert!(!weights.set("scan", 1));
+        let scheduler = Arc::new(CommandScheduler::with_weights(1, 1, weights));
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let running = scheduler.connection().acquire(CommandClass::Point).await;
+
+        let mut tasks = Vec::new();
+        for class in [CommandClass::Point; 4]
+            .into_iter()
+            .chain([CommandClass::Bulk; 2])
+        {
+            let scheduler = Arc::clone(&scheduler);
+            let served = Arc::clone(&served);
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler.connection().acquire(class).await;
+                served.lock().unwrap().push(class);
+            }));
+            tokio::task::yield_now().await;
+        }
+
+        drop(running);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        // Two point commands per bulk one, a weight of 0 counting as 1
+        use CommandClass::{Bulk, Point};
+        assert_eq!(
+            *served.lock().unwrap(),
+            [Point, Point, Bulk, Point, Point, Bulk]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_per_connection_cap() {
+        let scheduler = CommandScheduler::new(4, 1);
+        let pipeliner = Arc::new(scheduler.connection());
+        let first = pipeliner.acquire(CommandClass::Point).await;
+
+        // The next command of the connection waits for its first one, even
+        // with shared slots free
+        let acquired = Arc::new(Mutex::new(false));
+        let next = {
+            let pipeliner = Arc::clone(&pipeliner);
+            let acquired = Arc::clone(&acquired);
+            tokio::spawn(async move {
+                let _permit = pipeliner.acquire(CommandClass::Point).await;
+                *acquired.lock().unwrap() = true;
+            })
+        };
+        tokio::task::yield_now().await;
+        assert!(!*acquired.lock().unwrap());
+
+        // Another connection gets a shared slot meanwhile
+        let other = scheduler.connection();
+        let _other = other.acquire(CommandClass::Point).await;
+        let _blocking = other.acquire_unshared().await;
+
+        drop(first);
+        next.await.unwrap();
+        assert!(*acquired.lock().unwrap());
+    }
+}
@@ -17,16 +17,23 @@
  * limitations under the License.
  */
 
+use crate::alarms::run_alarms;
+use crate::drain::drain;
 use crate::handle::process_connection;
-use crate::ServerTrait;
+use crate::mirror::run_mirror;
+use crate::scheduler::CommandScheduler;
+use crate::{register_info_sections, ServerOptions, ServerTrait};
 use async_trait::async_trait;
 use client::{Client, StreamTrait};
 use cmd::table::{create_command_table, CmdTable};
-use log::info;
+use cmd::timeout::CommandTimeouts;
+use kstd::numa::numa_placement;
+use kstd::resources::resource_limits;
+use log::{info, warn};
 use std::error::Error;
 use std::path::PathBuf;
 use std::sync::Arc;
-use storage::options::StorageOptions;
+use storage::databases::Databases;
 use storage::storage::Storage;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
@@ -53,24 +60,32 @@ impl StreamTrait for TcpStreamWrapper {
 
 pub struct TcpServer {
     addr: String,
-    storage: Arc<Storage>,
+    databases: Arc<Databases>,
     cmd_table: Arc<CmdTable>,
+    timeouts: Arc<CommandTimeouts>,
+    scheduler: Arc<CommandScheduler>,
 }
 
 impl TcpServer {
-    pub fn new(addr: Option<String>) -> Self {
-        let storage_options = Arc::new(StorageOptions::default());
+    pub fn new(addr: Option<String>, options: ServerOptions) -> Self {
+        let mut storage_options = options.storage;
+        storage_options
+            .fit_to_limits(resource_limits())
+            .set_numa_topology(numa_placement().cloned());
+        let storage_options = Arc::new(storage_options);
+        register_info_sections();
         let db_path = PathBuf::from("./db");
-        let mut storage = Storage::new(1, 0);
 
-        // Note: Storage::open returns a receiver, and should be called after construction, not in new.
-        // The caller should call storage.open(storage_options, db_path) and spawn the bg_task_worker as needed.
-        storage.open(storage_options, db_path).unwrap();
+        // Note: Storage::open returns a receiver, Databases::open drops it for now.
+        // The caller should spawn the bg_task_worker of every storage as needed.
+        let databases = Databases::open(storage_options, db_path).unwrap();
 
         Self {
             addr: addr.unwrap_or("127.0.0.1:9221".to_string()),
-            storage: Arc::new(storage),
+            databases,
             cmd_table: Arc::new(create_command_table()),
+            timeouts: Arc::new(options.timeouts),
+            scheduler: Arc::new(options.scheduler),
         }
     }
 }
@@ -82,21 +97,53 @@ impl ServerTrait for TcpServer {
 
         info!("Listening on TCP: {}", self.addr);
 
+        for storage in self.databases.all() {
+            tokio::spawn(Storage::wal_sync_worker(storage.clone()));
+            tokio::spawn(Storage::lifecycle_event_worker(storage.clone()));
+            tokio::spawn(Storage::lazy_delete_worker(storage.clone()));
+            tokio::spawn(Storage::active_expire_worker(storage.clone()));
+            tokio::spawn(Storage::stale_compaction_worker(storage));
+        }
+        tokio::spawn(run_alarms(self.databases.clone()));
+        tokio::spawn(run_mirror());
+
+        let drain = drain();
         loop {
-            let (socket, _) = listener.accept().await?;
+            let (socket, _) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = drain.finished() => break,
+            };
+            // The node is leaving, the client reconnects to another one
+            if drain.is_draining() {
+                continue;
+            }
+            let connection = drain.connection();
 
             let s = TcpStreamWrapper::new(socket);
 
-            let mut client = Client::new(Box::new(s));
+            let client = Client::new(Box::new(s));
 
-            let storage = self.storage.clone();
+            let databases = self.databases.clone();
             let cmd_table = self.cmd_table.clone();
+            let timeouts = self.timeouts.clone();
+            let slots = self.scheduler.connection();
 
             tokio::spawn(async move {
-                process_connection(&mut client, storage, cmd_table)
+                let _connection = connection;
+                process_connection(client, databases, cmd_table, timeouts, slots)
                     .await
                     .unwrap();
             });
         }
+
+        for storage in self.databases.all() {
+            for inst in &storage.insts {
+                if let Err(e) = inst.sync_wal() {
+                    warn!("sync WAL of RocksDB{} failed: {e:?}", inst.index);
+                }
+            }
+        }
+        info!("drained, stop listening on TCP: {}", self.addr);
+        Ok(())
     }
 }
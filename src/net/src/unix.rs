@@ -17,31 +17,37 @@
  * limitations under the License.
  */
 
-use crate::ServerTrait;
+use crate::scheduler::CommandScheduler;
+use crate::{register_info_sections, ServerOptions, ServerTrait};
 use async_trait::async_trait;
 use cmd::table::{create_command_table, CmdTable};
+use cmd::timeout::CommandTimeouts;
 use std::{error::Error, path::PathBuf, sync::Arc};
-use storage::{storage::Storage, StorageOptions};
+use storage::databases::Databases;
 
 #[allow(dead_code)]
 pub struct UnixServer {
     path: String,
-    storage: Arc<Storage>,
+    databases: Arc<Databases>,
     cmd_table: Arc<CmdTable>,
+    timeouts: Arc<CommandTimeouts>,
+    scheduler: Arc<CommandScheduler>,
 }
 
 impl UnixServer {
-    pub fn new(path: Option<String>) -> Self {
+    pub fn new(path: Option<String>, options: ServerOptions) -> Self {
         let path = path.unwrap_or_else(|| "/tmp/kiwidb.sock".to_string());
-        let storage_options = Arc::new(StorageOptions::default());
+        let storage_options = Arc::new(options.storage);
+        register_info_sections();
         let db_path = PathBuf::from("./db");
-        let mut storage = Storage::new(1, 0);
-        storage.open(storage_options, db_path).unwrap();
+        let databases = Databases::open(storage_options, db_path).unwrap();
 
         Self {
             path,
-            storage: Arc::new(storage),
+            databases,
             cmd_table: Arc::new(create_command_table()),
+            timeouts: Arc::new(options.timeouts),
+            scheduler: Arc::new(options.scheduler),
         }
     }
 }
@@ -91,12 +97,15 @@ mod unix_impl {
                 match listener.accept().await {
                     Ok((socket, _)) => {
                         let s = UnixStreamWrapper::new(socket);
-                        let mut client = Client::new(Box::new(s));
-                        let storage = self.storage.clone();
+                        let client = Client::new(Box::new(s));
+                        let databases = self.databases.clone();
                         let cmd_table = self.cmd_table.clone();
+                        let timeouts = self.timeouts.clone();
+                        let slots = self.scheduler.connection();
                         tokio::spawn(async move {
                             if let Err(e) =
-                                process_connection(&mut client, storage, cmd_table).await
+                                process_connection(client, databases, cmd_table, timeouts, slots)
+                                    .await
                             {
                                 error!("Connection processing failed: {e:?}");
                             }
@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Pipelined GET/SET parsing with `RespParse` against a naive parser that
+//! scans for CRLF byte pairs, parses integers through `str` and copies
+//! every payload.
+//!
+//! cargo bench -p resp --bench parse
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use resp::{Parse, RespData, RespParse, RespParseResult, RespVersion};
+
+fn pipeline(commands: usize, value_len: usize) -> (Bytes, usize) {
+    let value = "v".repeat(value_len);
+    let mut buf = String::new();
+    for i in 0..commands {
+        let key = format!("key:{i:08}");
+        if i % 2 == 0 {
+            buf.push_str(&format!(
+                "*3\r\n$3\r\nSET\r\n${}\r\n{key}\r\n${}\r\n{value}\r\n",
+                key.len(),
+                value.len()
+            ));
+        } else {
+            buf.push_str(&format!("*2\r\n$3\r\nGET\r\n${}\r\n{key}\r\n", key.len()));
+        }
+    }
+    (Bytes::from(buf), commands)
+}
+
+mod naive {
+    use bytes::Bytes;
+    use resp::RespData;
+
+    fn line(input: &[u8]) -> Option<(&[u8], &[u8])> {
+        let pos = input.windows(2).position(|w| w == b"\r\n")?;
+        Some((&input[..pos], &input[pos + 2..]))
+    }
+
+    fn integer(input: &[u8]) -> Option<(i64, &[u8])> {
+        let (digits, rest) = line(input)?;
+        let num = std::str::from_utf8(digits).ok()?.parse().ok()?;
+        Some((num, rest))
+    }
+
+    pub fn parse(input: &[u8]) -> Option<(RespData, &[u8])> {
+        let (&first, rest) = input.split_first()?;
+        match first {
+            b'$' => {
+                let (len, rest) = integer(rest)?;
+                if len < 0 {
+                    return Some((RespData::BulkString(None), rest));
+                }
+                let len = len as usize;
+                if rest.len() < len + 2 {
+                    return None;
+                }
+                let data = Bytes::copy_from_slice(&rest[..len]);
+                Some((RespData::BulkString(Some(data)), &rest[len + 2..]))
+            }
+            b'*' => {
+                let (len, mut rest) = integer(rest)?;
+                let mut elements = Vec::with_capacity(len.max(0) as usize);
+                for _ in 0..len {
+                    let (element, next) = parse(rest)?;
+                    elements.push(element);
+                    rest = next;
+                }
+                Some((RespData::Array(Some(elements)), rest))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pipeline");
+    for (commands, value_len) in [(16, 16), (128, 16), (128, 1024)] {
+        let (input, count) = pipeline(commands, value_len);
+        let id = format!("{commands}x{value_len}");
+        group.throughput(Throughput::Bytes(input.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("naive", &id), &input, |b, input| {
+            b.iter(|| {
+                let mut rest: &[u8] = input;
+                let mut parsed = 0;
+                while let Some((data, next)) = naive::parse(rest) {
+                    black_box(data);
+                    rest = next;
+                    parsed += 1;
+                }
+                assert_eq!(parsed, count);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("resp_parse", &id), &input, |b, input| {
+            b.iter(|| {
+                let mut parser = RespParse::new(RespVersion::RESP2);
+                let mut res = parser.parse(input.clone());
+                let mut parsed = 0;
+                while let RespParseResult::Complete(data) = res {
+                    black_box::<RespData>(data);
+                    parsed += 1;
+                    res = parser.parse(Bytes::new());
+                }
+                assert_eq!(parsed, count);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_pipeline);
+criterion_main!(benches);
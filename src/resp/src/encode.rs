@@ -27,6 +27,9 @@ use crate::{
     CRLF,
 };
 
+/// Largest buffer an encoder keeps across clear, in bytes
+const MAX_RETAINED_CAPACITY: usize = 64 << 10;
+
 #[repr(i8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CmdRes {
@@ -162,6 +165,12 @@ impl RespEncoder {
         self.version
     }
 
+    /// The encoded response, without the copy made by get_response. The
+    /// encoder of a connection is reused with clear, keeping its buffer
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
     fn append_crlf(&mut self) -> &mut Self {
         self.buffer.extend_from_slice(CRLF.as_bytes());
         self
@@ -330,6 +339,9 @@ impl RespEncode for RespEncoder {
     }
 
     fn clear(&mut self) -> &mut Self {
+        if self.buffer.capacity() > MAX_RETAINED_CAPACITY {
+            self.buffer = BytesMut::new();
+        }
         self.buffer.clear();
         self.res = CmdRes::None;
         self
@@ -17,18 +17,9 @@
  * limitations under the License.
  */
 
-use bytes::{Buf, Bytes, BytesMut};
-use nom::Parser;
-use nom::{
-    bytes::streaming::{take, take_while1},
-    character::streaming::{char, digit1, line_ending, not_line_ending, space1},
-    combinator::{map, map_res, opt, recognize},
-    multi::separated_list0,
-    sequence::terminated,
-    IResult,
-};
+use bytes::{Bytes, BytesMut};
 use std::collections::VecDeque;
-use std::str;
+use std::ops::Range;
 
 use crate::{
     command::{Command, RespCommand},
@@ -36,6 +27,11 @@ use crate::{
     types::{RespData, RespVersion},
 };
 
+/// Upper bound on the element capacity reserved up front for an array, so a
+/// bogus `*<huge>` header cannot make the parser allocate before any element
+/// has arrived.
+const MAX_ARRAY_PREALLOC: usize = 1024;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum RespParseResult {
     Complete(RespData),
@@ -82,155 +78,216 @@ impl RespParse {
         self.version = version;
     }
 
-    fn parse_inline(input: &[u8]) -> IResult<&[u8], RespData> {
-        let mut parse_parts = separated_list0(
-            space1,
-            map(
-                take_while1(|c| c != b' ' && c != b'\r' && c != b'\n'),
-                |s: &[u8]| Bytes::copy_from_slice(s),
-            ),
-        );
+    fn process_buffer(&mut self) -> RespParseResult {
+        if self.buffer.is_empty() {
+            return RespParseResult::Incomplete;
+        }
 
-        let (input, parts) = parse_parts.parse(input)?;
+        match parse_frame(&self.buffer, 0) {
+            Ok((consumed, frame)) => {
+                // Hand the consumed bytes over without copying; the payloads
+                // of the parsed data are slices of this frozen chunk.
+                let frozen = self.buffer.split_to(consumed).freeze();
+                let resp_data = frame.into_resp(&frozen);
 
-        let (input, _) = line_ending(input)?;
+                match resp_data.to_command() {
+                    Ok(mut command) => {
+                        command.is_pipeline = self.is_pipeline;
+                        self.is_pipeline = !self.buffer.is_empty();
 
-        if parts.is_empty() {
-            return Err(nom::Err::Error(nom::error::Error::new(
-                input,
-                nom::error::ErrorKind::Verify,
-            )));
-        }
+                        self.commands.push_back(Ok(command));
+                    }
+                    Err(err) => {
+                        self.commands.push_back(Err(err));
+                    }
+                }
 
-        Ok((input, RespData::Inline(parts)))
+                RespParseResult::Complete(resp_data)
+            }
+            Err(FrameError::Incomplete) => RespParseResult::Incomplete,
+            Err(FrameError::Invalid(reason)) => {
+                let error_msg = format!("Parse error: {reason}");
+                RespParseResult::Error(RespError::ParseError(error_msg))
+            }
+        }
     }
+}
 
-    fn parse_simple_string(input: &[u8]) -> IResult<&[u8], RespData> {
-        let (input, _) = char('+')(input)?;
-        let mut ter_parser = terminated(not_line_ending, line_ending);
-        let (input, data) = ter_parser.parse(input)?;
-        Ok((input, RespData::SimpleString(Bytes::copy_from_slice(data))))
-    }
+/// A parsed value whose payloads are byte ranges into the parse buffer.
+#[derive(Debug)]
+enum Frame {
+    SimpleString(Range<usize>),
+    Error(Range<usize>),
+    Integer(i64),
+    BulkString(Option<Range<usize>>),
+    Array(Option<Vec<Frame>>),
+    Inline(Vec<Range<usize>>),
+}
 
-    fn parse_error(input: &[u8]) -> IResult<&[u8], RespData> {
-        let (input, _) = char('-')(input)?;
-        let mut ter_parser = terminated(not_line_ending, line_ending);
-        let (input, data) = ter_parser.parse(input)?;
-        Ok((input, RespData::Error(Bytes::copy_from_slice(data))))
+impl Frame {
+    fn into_resp(self, buf: &Bytes) -> RespData {
+        match self {
+            Frame::SimpleString(range) => RespData::SimpleString(buf.slice(range)),
+            Frame::Error(range) => RespData::Error(buf.slice(range)),
+            Frame::Integer(num) => RespData::Integer(num),
+            Frame::BulkString(range) => RespData::BulkString(range.map(|r| buf.slice(r))),
+            Frame::Array(elements) => {
+                RespData::Array(elements.map(|e| e.into_iter().map(|f| f.into_resp(buf)).collect()))
+            }
+            Frame::Inline(parts) => {
+                RespData::Inline(parts.into_iter().map(|r| buf.slice(r)).collect())
+            }
+        }
     }
+}
 
-    fn parse_integer(input: &[u8]) -> IResult<&[u8], RespData> {
-        let (input, _) = char(':')(input)?;
-        let mut map_parser = map_res(
-            terminated(recognize((opt(char('-')), digit1)), line_ending),
-            |s: &[u8]| {
-                str::from_utf8(s)
-                    .map_err(|_| ())
-                    .and_then(|s| s.parse::<i64>().map_err(|_| ()))
-            },
-        );
-        let (input, num) = map_parser.parse(input)?;
-        Ok((input, RespData::Integer(num)))
-    }
-
-    fn parse_bulk_string(input: &[u8]) -> IResult<&[u8], RespData> {
-        let (input, _) = char('$')(input)?;
-        let mut map_parser = map_res(
-            terminated(recognize((opt(char('-')), digit1)), line_ending),
-            |s: &[u8]| {
-                str::from_utf8(s)
-                    .map_err(|_| ())
-                    .and_then(|s| s.parse::<i64>().map_err(|_| ()))
-            },
-        );
-        let (input, len) = map_parser.parse(input)?;
+#[derive(Debug, PartialEq, Eq)]
+enum FrameError {
+    Incomplete,
+    Invalid(&'static str),
+}
 
-        if len < 0 {
-            return Ok((input, RespData::BulkString(None)));
-        }
+/// `(end, frame)`: the offset just past the frame, and the frame itself.
+type FrameResult = Result<(usize, Frame), FrameError>;
+
+/// Finds the line starting at `start`, returning its content range (without
+/// the line ending) and the offset of the next line. Both `\r\n` and a bare
+/// `\n` end a line.
+#[inline]
+fn find_line(buf: &[u8], start: usize) -> Result<(Range<usize>, usize), FrameError> {
+    let Some(pos) = memchr::memchr(b'\n', &buf[start..]) else {
+        return Err(FrameError::Incomplete);
+    };
+    let newline = start + pos;
+    let end = if newline > start && buf[newline - 1] == b'\r' {
+        newline - 1
+    } else {
+        newline
+    };
+    Ok((start..end, newline + 1))
+}
 
-        let mut ter_parser = terminated(take(len as usize), line_ending);
-        let (input, data) = ter_parser.parse(input)?;
-        Ok((
-            input,
-            RespData::BulkString(Some(Bytes::copy_from_slice(data))),
-        ))
-    }
-
-    fn parse_array(input: &[u8]) -> IResult<&[u8], RespData> {
-        let (input, _) = char('*')(input)?;
-        let mut mut_parser = map_res(
-            terminated(recognize((opt(char('-')), digit1)), line_ending),
-            |s: &[u8]| {
-                str::from_utf8(s)
-                    .map_err(|_| ())
-                    .and_then(|s| s.parse::<i64>().map_err(|_| ()))
-            },
-        );
-        let (input, len) = mut_parser.parse(input)?;
+/// Parses an optionally negative decimal integer, rejecting empty input,
+/// any non-digit byte and overflow.
+#[inline]
+fn parse_decimal(digits: &[u8]) -> Option<i64> {
+    let (negative, digits) = match digits.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, digits),
+    };
+    if digits.is_empty() {
+        return None;
+    }
 
-        if len < 0 {
-            return Ok((input, RespData::Array(None)));
+    let mut num: i64 = 0;
+    for &b in digits {
+        let d = b.wrapping_sub(b'0');
+        if d > 9 {
+            return None;
         }
+        // Accumulate negatively so that i64::MIN is representable.
+        num = num.checked_mul(10)?.checked_sub(d as i64)?;
+    }
+    if negative {
+        Some(num)
+    } else {
+        num.checked_neg()
+    }
+}
 
-        let mut remaining = input;
-        let mut elements = Vec::with_capacity(len as usize);
-
-        for _ in 0..len {
-            let (new_remaining, element) = Self::parse_resp_data(remaining)?;
-            elements.push(element);
-            remaining = new_remaining;
-        }
+#[inline]
+fn parse_header(buf: &[u8], start: usize) -> Result<(i64, usize), FrameError> {
+    let (line, next) = find_line(buf, start)?;
+    let num = parse_decimal(&buf[line]).ok_or(FrameError::Invalid("invalid integer"))?;
+    Ok((num, next))
+}
 
-        Ok((remaining, RespData::Array(Some(elements))))
-    }
+fn parse_frame(buf: &[u8], start: usize) -> FrameResult {
+    let Some(&first) = buf.get(start) else {
+        return Err(FrameError::Incomplete);
+    };
 
-    fn parse_resp_data(input: &[u8]) -> IResult<&[u8], RespData> {
-        if input.is_empty() {
-            return Err(nom::Err::Incomplete(nom::Needed::Unknown));
+    match first {
+        b'+' => {
+            let (line, next) = find_line(buf, start + 1)?;
+            Ok((next, Frame::SimpleString(line)))
         }
-
-        match input[0] {
-            b'+' => Self::parse_simple_string(input),
-            b'-' => Self::parse_error(input),
-            b':' => Self::parse_integer(input),
-            b'$' => Self::parse_bulk_string(input),
-            b'*' => Self::parse_array(input),
-            _ => Self::parse_inline(input),
+        b'-' => {
+            let (line, next) = find_line(buf, start + 1)?;
+            Ok((next, Frame::Error(line)))
         }
+        b':' => {
+            let (num, next) = parse_header(buf, start + 1)?;
+            Ok((next, Frame::Integer(num)))
+        }
+        b'$' => parse_bulk_string(buf, start + 1),
+        b'*' => parse_array(buf, start + 1),
+        _ => parse_inline(buf, start),
     }
+}
 
-    fn process_buffer(&mut self) -> RespParseResult {
-        if self.buffer.is_empty() {
-            return RespParseResult::Incomplete;
+fn parse_bulk_string(buf: &[u8], start: usize) -> FrameResult {
+    let (len, data_start) = parse_header(buf, start)?;
+    if len < 0 {
+        return Ok((data_start, Frame::BulkString(None)));
+    }
+
+    let data_end = data_start
+        .checked_add(len as usize)
+        .ok_or(FrameError::Invalid("bulk string too long"))?;
+    // The payload is taken by length, only its terminator is checked.
+    let end = match buf.get(data_end..) {
+        None | Some([]) | Some([b'\r']) => return Err(FrameError::Incomplete),
+        Some([b'\n', ..]) => data_end + 1,
+        Some([b'\r', b'\n', ..]) => data_end + 2,
+        Some(_) => {
+            return Err(FrameError::Invalid(
+                "bulk string is not terminated by a line ending",
+            ))
         }
+    };
+    Ok((end, Frame::BulkString(Some(data_start..data_end))))
+}
 
-        match Self::parse_resp_data(&self.buffer) {
-            Ok((remaining, resp_data)) => {
-                let consumed = self.buffer.len() - remaining.len();
-                self.buffer.advance(consumed);
+fn parse_array(buf: &[u8], start: usize) -> FrameResult {
+    let (len, mut pos) = parse_header(buf, start)?;
+    if len < 0 {
+        return Ok((pos, Frame::Array(None)));
+    }
 
-                match resp_data.to_command() {
-                    Ok(mut command) => {
-                        command.is_pipeline = self.is_pipeline;
-                        self.is_pipeline = !self.buffer.is_empty();
+    let len = len as usize;
+    let mut elements = Vec::with_capacity(len.min(MAX_ARRAY_PREALLOC));
+    for _ in 0..len {
+        let (next, element) = parse_frame(buf, pos)?;
+        elements.push(element);
+        pos = next;
+    }
+    Ok((pos, Frame::Array(Some(elements))))
+}
 
-                        self.commands.push_back(Ok(command));
-                    }
-                    Err(err) => {
-                        self.commands.push_back(Err(err));
-                    }
-                }
+fn parse_inline(buf: &[u8], start: usize) -> FrameResult {
+    let (line, next) = find_line(buf, start)?;
 
-                RespParseResult::Complete(resp_data)
-            }
-            Err(nom::Err::Incomplete(_)) => RespParseResult::Incomplete,
-            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
-                let error_msg = format!("Parse error: {e:?}");
-                RespParseResult::Error(RespError::ParseError(error_msg))
-            }
+    let mut parts = Vec::new();
+    let mut pos = line.start;
+    while pos < line.end {
+        let rest = &buf[pos..line.end];
+        match rest.iter().position(|&c| c != b' ' && c != b'\t') {
+            Some(skip) => pos += skip,
+            None => break,
         }
+        let len = buf[pos..line.end]
+            .iter()
+            .position(|&c| c == b' ' || c == b'\t')
+            .unwrap_or(line.end - pos);
+        parts.push(pos..pos + len);
+        pos += len;
+    }
+
+    if parts.is_empty() {
+        return Err(FrameError::Invalid("empty inline command"));
     }
+    Ok((next, Frame::Inline(parts)))
 }
 
 impl Parse for RespParse {
@@ -260,7 +317,9 @@ impl Drop for RespParse {
 #[cfg(test)]
 mod tests {
     use super::Bytes;
-    use super::{Parse, RespData, RespParse, RespParseResult, RespVersion};
+    use super::{
+        parse_decimal, Parse, RespData, RespError, RespParse, RespParseResult, RespVersion,
+    };
 
     #[test]
     fn test_parse_simple_string_ok() {
@@ -435,4 +494,102 @@ mod tests {
         let res = parser.parse(Bytes::from("$10\r\nfoobar"));
         assert_eq!(res, RespParseResult::Incomplete);
     }
+
+    #[test]
+    fn test_parse_decimal() {
+        assert_eq!(parse_decimal(b"0"), Some(0));
+        assert_eq!(parse_decimal(b"-42"), Some(-42));
+        assert_eq!(parse_decimal(b"9223372036854775807"), Some(i64::MAX));
+        assert_eq!(parse_decimal(b"-9223372036854775808"), Some(i64::MIN));
+        assert_eq!(parse_decimal(b"9223372036854775808"), None);
+        assert_eq!(parse_decimal(b""), None);
+        assert_eq!(parse_decimal(b"-"), None);
+        assert_eq!(parse_decimal(b"+1"), None);
+        assert_eq!(parse_decimal(b"12a"), None);
+    }
+
+    #[test]
+    fn test_parse_invalid_integer() {
+        let mut parser = RespParse::new(RespVersion::RESP2);
+        let res = parser.parse(Bytes::from(":12a\r\n"));
+        assert!(matches!(
+            res,
+            RespParseResult::Error(RespError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_bulk_string_bad_terminator() {
+        let mut parser = RespParse::new(RespVersion::RESP2);
+        let res = parser.parse(Bytes::from("$3\r\nfoobar\r\n"));
+        assert!(matches!(
+            res,
+            RespParseResult::Error(RespError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_bulk_string_split() {
+        let mut parser = RespParse::new(RespVersion::RESP2);
+        assert_eq!(
+            parser.parse(Bytes::from("$6\r\nfoo")),
+            RespParseResult::Incomplete
+        );
+        assert_eq!(
+            parser.parse(Bytes::from("bar\r")),
+            RespParseResult::Incomplete
+        );
+        let res = parser.parse(Bytes::from("\n"));
+        assert_eq!(
+            res,
+            RespParseResult::Complete(RespData::BulkString(Some(Bytes::from("foobar"))))
+        );
+    }
+
+    #[test]
+    fn test_parse_bulk_string_binary() {
+        let mut parser = RespParse::new(RespVersion::RESP2);
+        let res = parser.parse(Bytes::from_static(b"$4\r\na\r\nb\r\n"));
+        assert_eq!(
+            res,
+            RespParseResult::Complete(RespData::BulkString(Some(Bytes::from_static(b"a\r\nb"))))
+        );
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let mut parser = RespParse::new(RespVersion::RESP2);
+        let res = parser.parse(Bytes::from(
+            "*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n",
+        ));
+        assert!(
+            matches!(res, RespParseResult::Complete(RespData::Array(Some(ref e))) if e.len() == 3)
+        );
+        let res = parser.parse(Bytes::new());
+        assert_eq!(
+            res,
+            RespParseResult::Complete(RespData::Array(Some(vec![
+                RespData::BulkString(Some(Bytes::from("GET"))),
+                RespData::BulkString(Some(Bytes::from("k"))),
+            ])))
+        );
+        assert_eq!(parser.parse(Bytes::new()), RespParseResult::Incomplete);
+    }
+
+    #[test]
+    fn test_parse_bulk_string_zero_copy() {
+        let mut parser = RespParse::new(RespVersion::RESP2);
+        let res = parser.parse(Bytes::from("*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n"));
+        let RespParseResult::Complete(RespData::Array(Some(elements))) = res else {
+            panic!("unexpected result: {res:?}");
+        };
+        let (RespData::BulkString(Some(foo)), RespData::BulkString(Some(bar))) =
+            (&elements[0], &elements[1])
+        else {
+            panic!("unexpected elements: {elements:?}");
+        };
+        // Both payloads are slices of the same frozen parse buffer.
+        let distance = bar.as_ptr() as usize - foo.as_ptr() as usize;
+        assert_eq!(distance, "foo\r\n$3\r\n".len());
+    }
 }
@@ -17,20 +17,127 @@
  * limitations under the License.
  */
 
-use log::info;
-use net::ServerFactory;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[tokio::main]
-async fn main() -> std::io::Result<()> {
+use cluster::{install_cluster_bus, new_node_id, ClusterBus, ClusterConfig, ClusterState};
+use cmd::timeout::CommandTimeouts;
+use conf::active::{install_active_config, ActiveConfig};
+use conf::config::Config;
+use kstd::numa::{enable_numa_placement, pin_to_next_node, NumaTopology};
+use kstd::resources::{set_resource_limits, ResourceLimits};
+use log::{error, info, warn};
+use net::alarms::{configure_alarms, AlarmConfig, AlarmRule};
+use net::capture::{start_capture, CaptureConfig};
+use net::discovery::NodeSource;
+use net::drain::serve_health;
+use net::mirror::{configure_mirror, MirrorConfig};
+use net::proxy::ProxyServer;
+use net::replay::{replay, trace_paths, ReplayOptions};
+use net::scheduler::{default_max_inflight, ClassWeights, CommandScheduler};
+use net::{ServerFactory, ServerOptions, ServerTrait};
+use storage::{Databases, DurabilityLevel, RocksDbTuning, SelfTestOptions, StorageOptions};
+
+#[cfg(all(feature = "jemalloc", feature = "mimalloc"))]
+compile_error!("features `jemalloc` and `mimalloc` are mutually exclusive");
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+fn main() -> std::io::Result<()> {
     // init logger
     // set env RUST_LOG=level to control
     env_logger::init();
+    info!("global allocator: {}", kstd::allocator::name());
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--test-storage") {
+        return run_storage_self_test(&args);
+    }
+
+    let config = load_config(&args)?;
+
+    // The runtime, the RocksDB threads and the caches are sized to them
+    let limits = resource_limits(&args)?;
+    set_resource_limits(limits);
+    let memory = limits
+        .memory_bytes
+        .map_or("unlimited".to_string(), |bytes| {
+            format!("{}MB", bytes >> 20)
+        });
+    info!("resource limits: {:.2} cpus, memory {memory}", limits.cpus);
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.worker_threads(limits.threads()).enable_all();
+    if args.iter().any(|arg| arg == "--numa") {
+        let topology = NumaTopology::detect();
+        if topology.is_multi_node() {
+            info!("NUMA placement over {} nodes", topology.nodes.len());
+            enable_numa_placement(topology);
+            // The workers are spread over the nodes
+            runtime.on_thread_start(|| {
+                if let Err(e) = pin_to_next_node() {
+                    warn!("pinning a runtime thread failed: {e}");
+                }
+            });
+        } else {
+            info!("single NUMA node, --numa ignored");
+        }
+    }
+    runtime.build()?.block_on(serve(args, config))
+}
+
+async fn serve(args: Vec<String>, config: Config) -> std::io::Result<()> {
+    if let Some(path) = arg_value(&args, "--replay")? {
+        return run_replay(&args, path).await;
+    }
 
     let addr = String::from("127.0.0.1:9221");
     let protocol = "tcp";
 
+    if let Some(backends) = proxy_backends(&args)? {
+        let server = ProxyServer::new(Some(addr), backends, proxy_refresh_interval(&args)?);
+        return server
+            .run()
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()));
+    }
+
+    if let Some(health_addr) = arg_value(&args, "--health-addr")? {
+        let health_addr = health_addr.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = serve_health(health_addr).await {
+                error!("health endpoints stopped: {e}");
+            }
+        });
+    }
+
+    if config.cluster_enabled {
+        start_cluster(&config);
+    }
+
+    configure_alarms(alarm_config(&args)?);
+    if let Some(config) = mirror_config(&args)? {
+        configure_mirror(config);
+    }
+    if let Some(config) = capture_config(&args)? {
+        start_capture(config)?;
+    }
+
+    let options = ServerOptions {
+        storage: storage_options(&args, &config)?,
+        timeouts: command_timeouts(&config),
+        scheduler: command_scheduler(&config),
+    };
+
     info!("tcp listener listen on {addr}");
-    if let Some(server) = ServerFactory::create_server(protocol, Option::from(addr)) {
+    if let Some(server) = ServerFactory::create_server(protocol, Option::from(addr), options) {
         server.run().await.expect("Failed to start the server. Please check the server configuration and ensure the address is available.");
     } else {
         return Err(std::io::Error::other("server unavailable"));
@@ -38,3 +145,313 @@ async fn main() -> std::io::Result<()> {
 
     Ok(())
 }
+
+/// The config file of `--config <file>`, the defaults without it. Installed
+/// as the configuration of the CONFIG commands.
+fn load_config(args: &[String]) -> std::io::Result<Config> {
+    let path = arg_value(args, "--config")?;
+    let config = match path {
+        Some(path) => Config::load(path).map_err(|e| std::io::Error::other(e.to_string()))?,
+        None => Config::default(),
+    };
+    install_active_config(ActiveConfig::new(config.clone(), path.map(PathBuf::from)));
+    Ok(config)
+}
+
+/// The options of the storage from the config file, the durability
+/// overridden by `--durability strict|normal|relaxed`, and the RocksDB
+/// tuning of its `[rocksdb]` section, see `storage::cf_tuning`.
+fn storage_options(args: &[String], config: &Config) -> std::io::Result<StorageOptions> {
+    let (name, durability) = match arg_value(args, "--durability")? {
+        Some(durability) => ("--durability", durability),
+        None => ("durability", config.durability.as_str()),
+    };
+    let durability: DurabilityLevel = durability
+        .parse()
+        .map_err(|e| std::io::Error::other(format!("invalid value for {name}: {e}")))?;
+    info!(
+        "durability {durability}, WAL synced every {}ms",
+        config.wal_sync_interval
+    );
+
+    let tuning = RocksDbTuning::from_options(&config.rocksdb)
+        .map_err(|e| std::io::Error::other(format!("invalid [rocksdb] section: {e}")))?;
+
+    let mut options = StorageOptions::default();
+    options
+        .set_durability(durability)
+        .set_wal_sync_interval_ms(config.wal_sync_interval)
+        .set_databases(config.databases)
+        .set_rocksdb_tuning(tuning);
+    Ok(options)
+}
+
+/// The default deadlines, those of the commands of `command_timeouts`
+/// overridden.
+fn command_timeouts(config: &Config) -> CommandTimeouts {
+    let mut timeouts = CommandTimeouts::default();
+    for (name, &millis) in &config.command_timeouts {
+        timeouts.set_timeout(name, (millis > 0).then(|| Duration::from_millis(millis)));
+    }
+    timeouts
+}
+
+/// The slots of `command_slots`, the default ones for 0, those of
+/// `connection_slots` per connection, and the default weights, those of the
+/// classes of `command_class_weights` overridden.
+fn command_scheduler(config: &Config) -> CommandScheduler {
+    let mut weights = ClassWeights::default();
+    for (class, &weight) in &config.command_class_weights {
+        // The classes are checked by the config
+        weights.set(class, weight);
+    }
+    let slots = match config.command_slots {
+        0 => default_max_inflight(),
+        slots => slots,
+    };
+    CommandScheduler::with_weights(slots, config.connection_slots, weights)
+}
+
+/// Runs the cluster bus on `cluster_bus_addr` under a new node id, the
+/// CLUSTER commands act on it.
+fn start_cluster(config: &Config) {
+    let node_id = new_node_id();
+    let state = ClusterState::new(
+        node_id.as_str(),
+        config.cluster_bus_addr.as_str(),
+        ClusterConfig {
+            node_timeout_ms: config.cluster_node_timeout,
+            ..Default::default()
+        },
+    );
+    let bus = Arc::new(ClusterBus::new(config.cluster_bus_addr.as_str(), state));
+    install_cluster_bus(bus.clone());
+    info!(
+        "cluster node {node_id}, bus listening on {}",
+        config.cluster_bus_addr
+    );
+    tokio::spawn(async move {
+        if let Err(e) = bus.run().await {
+            error!("cluster bus stopped: {e}");
+        }
+    });
+}
+
+/// The detected CPUs and memory of the process, overridden by `--cpus <n>`
+/// and `--memory-mb <n>`.
+fn resource_limits(args: &[String]) -> std::io::Result<ResourceLimits> {
+    let invalid = |name: &str, value: &str| {
+        std::io::Error::other(format!("invalid value for {name}: {value}"))
+    };
+    let mut limits = ResourceLimits::detect();
+    if let Some(cpus) = arg_value(args, "--cpus")? {
+        limits.cpus = cpus
+            .parse()
+            .ok()
+            .filter(|cpus: &f64| *cpus > 0.0)
+            .ok_or_else(|| invalid("--cpus", cpus))?;
+    }
+    if let Some(mb) = arg_value(args, "--memory-mb")? {
+        let mb: u64 = mb
+            .parse()
+            .ok()
+            .filter(|mb| *mb > 0)
+            .ok_or_else(|| invalid("--memory-mb", mb))?;
+        limits.memory_bytes = Some(mb << 20);
+    }
+    Ok(limits)
+}
+
+/// Default of `--proxy-refresh-secs`
+const PROXY_REFRESH_SECS: u64 = 30;
+
+/// The value following `name`, None without the argument.
+fn arg_value<'a>(args: &'a [String], name: &str) -> std::io::Result<Option<&'a str>> {
+    let Some(at) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    args.get(at + 1)
+        .map(|value| Some(value.as_str()))
+        .ok_or_else(|| std::io::Error::other(format!("missing value for {name}")))
+}
+
+/// The backends of `--proxy <host:port>[,<host:port>...]` or
+/// `--proxy @<file>`, None without the argument. Hosts may be DNS names.
+fn proxy_backends(args: &[String]) -> std::io::Result<Option<NodeSource>> {
+    arg_value(args, "--proxy")?
+        .map(|spec| {
+            NodeSource::parse(spec)
+                .map_err(|e| std::io::Error::other(format!("invalid value for --proxy: {e}")))
+        })
+        .transpose()
+}
+
+/// How often the proxy backends are resolved again, from
+/// `--proxy-refresh-secs <n>`, 0 to resolve them only at startup.
+fn proxy_refresh_interval(args: &[String]) -> std::io::Result<Duration> {
+    let secs = match arg_value(args, "--proxy-refresh-secs")? {
+        Some(secs) => secs.parse().map_err(|e| {
+            std::io::Error::other(format!("invalid value for --proxy-refresh-secs: {e}"))
+        })?,
+        None => PROXY_REFRESH_SECS,
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+/// Default of `--mirror-sample`
+const MIRROR_SAMPLE_PERCENT: u64 = 100;
+
+/// The shadow traffic of `--mirror <host:port>`, with `--mirror-commands
+/// writes|all` and `--mirror-sample <percent>`. None without `--mirror`.
+fn mirror_config(args: &[String]) -> std::io::Result<Option<MirrorConfig>> {
+    let Some(target) = arg_value(args, "--mirror")? else {
+        return Ok(None);
+    };
+    let mode = match arg_value(args, "--mirror-commands")? {
+        Some(mode) => mode.parse()?,
+        None => Default::default(),
+    };
+    let sample_percent = match arg_value(args, "--mirror-sample")? {
+        Some(percent) => percent
+            .parse()
+            .ok()
+            .filter(|percent| (1..=100).contains(percent))
+            .ok_or_else(|| {
+                std::io::Error::other(format!(
+                    "invalid value for --mirror-sample: {percent}, expected 1 to 100"
+                ))
+            })?,
+        None => MIRROR_SAMPLE_PERCENT,
+    };
+    Ok(Some(MirrorConfig {
+        target: target.to_string(),
+        mode,
+        sample_percent,
+    }))
+}
+
+/// Defaults of `--trace-file-mb` and `--trace-files`
+const TRACE_FILE_MB: u64 = 64;
+const TRACE_FILES: usize = 8;
+
+/// The capture of `--trace-dir <dir>`, with `--trace-file-mb <n>` and
+/// `--trace-files <n>`. None without `--trace-dir`.
+fn capture_config(args: &[String]) -> std::io::Result<Option<CaptureConfig>> {
+    let Some(dir) = arg_value(args, "--trace-dir")? else {
+        return Ok(None);
+    };
+    let number = |name: &str, value: &str| {
+        value
+            .parse::<u64>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| std::io::Error::other(format!("invalid value for {name}: {value}")))
+    };
+    let file_mb = match arg_value(args, "--trace-file-mb")? {
+        Some(value) => number("--trace-file-mb", value)?,
+        None => TRACE_FILE_MB,
+    };
+    let files = match arg_value(args, "--trace-files")? {
+        Some(value) => number("--trace-files", value)? as usize,
+        None => TRACE_FILES,
+    };
+    Ok(Some(CaptureConfig {
+        dir: dir.into(),
+        max_file_bytes: file_mb << 20,
+        max_files: files,
+    }))
+}
+
+/// The rules of `--alarms "<rule>; <rule>..."` and the webhook of
+/// `--alarm-webhook http://<host>[:<port>]/<path>`, empty without them.
+fn alarm_config(args: &[String]) -> std::io::Result<AlarmConfig> {
+    let invalid = |name: &str, e: std::io::Error| {
+        std::io::Error::other(format!("invalid value for {name}: {e}"))
+    };
+    let rules = match arg_value(args, "--alarms")? {
+        Some(spec) => AlarmRule::parse_list(spec).map_err(|e| invalid("--alarms", e))?,
+        None => Vec::new(),
+    };
+    let webhook = arg_value(args, "--alarm-webhook")?
+        .map(|url| url.parse().map_err(|e| invalid("--alarm-webhook", e)))
+        .transpose()?;
+    Ok(AlarmConfig { rules, webhook })
+}
+
+/// Runs the storage self-test against the data directory and exits.
+///
+/// kiwi --test-storage [--db-path <dir>] [--keys <n>] [--value-len <bytes>]
+fn run_storage_self_test(args: &[String]) -> std::io::Result<()> {
+    let mut db_path = PathBuf::from("./db");
+    let mut options = SelfTestOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .ok_or_else(|| std::io::Error::other(format!("missing value for {arg}")))
+        };
+        let invalid = |e| std::io::Error::other(format!("invalid value for {arg}: {e}"));
+        match arg.as_str() {
+            "--test-storage" => {}
+            "--db-path" => db_path = PathBuf::from(value()?),
+            "--keys" => options.keys = value()?.parse().map_err(invalid)?,
+            "--value-len" => options.value_len = value()?.parse().map_err(invalid)?,
+            _ => return Err(std::io::Error::other(format!("unknown argument: {arg}"))),
+        }
+    }
+
+    info!(
+        "storage self-test on {}: {} keys of {} bytes",
+        db_path.display(),
+        options.keys,
+        options.value_len
+    );
+    let databases = Databases::open(Arc::new(StorageOptions::default()), &db_path)
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let storage = databases
+        .pin()
+        .db(0)
+        .ok_or_else(|| std::io::Error::other("no database to test"))?;
+    let report = storage
+        .self_test(&options)
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    println!("{report}");
+    if !report.passed() {
+        return Err(std::io::Error::other("storage self-test failed"));
+    }
+    Ok(())
+}
+
+/// Replays the trace files of `--replay <file|dir>` against
+/// `--replay-target <host:port>`, at `--replay-speed <factor>` times the
+/// captured pace or `max`, and fails if a reply diverged.
+async fn run_replay(args: &[String], path: &str) -> std::io::Result<()> {
+    let target = arg_value(args, "--replay-target")?
+        .ok_or_else(|| std::io::Error::other("missing --replay-target"))?
+        .to_string();
+    let speed = match arg_value(args, "--replay-speed")? {
+        None => Some(1.0),
+        Some("max") => None,
+        Some(factor) => Some(
+            factor
+                .parse::<f64>()
+                .ok()
+                .filter(|factor| *factor > 0.0)
+                .ok_or_else(|| {
+                    std::io::Error::other(format!("invalid value for --replay-speed: {factor}"))
+                })?,
+        ),
+    };
+
+    let paths = trace_paths(path)?;
+    info!("replaying {} trace files to {target}", paths.len());
+    let report = replay(paths, ReplayOptions { target, speed }).await?;
+
+    println!("{report}");
+    if !report.matched() {
+        return Err(std::io::Error::other("replay diverged"));
+    }
+    Ok(())
+}
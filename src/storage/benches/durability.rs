@@ -0,0 +1,67 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Write throughput of each durability level.
+//!
+//! cargo bench -p storage --bench durability
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use storage::storage::Storage;
+use storage::{unique_test_db_path, DurabilityLevel, StorageOptions};
+
+const VALUE: &[u8] = &[b'x'; 128];
+
+fn bench_set(c: &mut Criterion) {
+    let mut group = c.benchmark_group("set");
+    group.throughput(Throughput::Elements(1));
+
+    for level in [
+        DurabilityLevel::Strict,
+        DurabilityLevel::Normal,
+        DurabilityLevel::Relaxed,
+    ] {
+        let mut options = StorageOptions::default();
+        options.set_durability(level);
+        let path = unique_test_db_path();
+        let mut storage = Storage::new(1, 0);
+        storage.open(Arc::new(options), &path).unwrap();
+
+        let mut i = 0u64;
+        group.bench_with_input(
+            BenchmarkId::from_parameter(level),
+            &storage,
+            |b, storage| {
+                b.iter(|| {
+                    i += 1;
+                    storage.set(format!("key{i}").as_bytes(), VALUE).unwrap();
+                })
+            },
+        );
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_set);
+criterion_main!(benches);
@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Active expiration
+//!
+//! An expired key is hidden from reads but stays on disk until compaction
+//! drops it. Like the active expire cycle of Redis,
+//! `Storage::active_expire_worker` walks the meta column family of every
+//! instance a slice at a time, resuming where the previous cycle stopped,
+//! and deletes the keys whose expiration time has passed. Their data keys
+//! go to the lazy deletion queue.
+//!
+//! A cycle stops once it used its share of the interval, set by
+//! `StorageOptions::active_expire_cpu_percent`, so the walk over a large
+//! keyspace takes several cycles.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use kstd::lock_mgr::ScopeRecordLock;
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    base_key_format::ParsedBaseKey,
+    error::{OptionNoneSnafu, RocksSnafu},
+    lazy_delete::LazyDeleteTask,
+    redis_keys::is_expired_meta_value,
+    ColumnFamilyIndex, Redis, Result,
+};
+
+/// Keys checked between two looks at the clock
+const KEYS_PER_TIME_CHECK: usize = 32;
+
+static CYCLES: AtomicU64 = AtomicU64::new(0);
+static KEYS_SAMPLED: AtomicU64 = AtomicU64::new(0);
+static KEYS_EXPIRED: AtomicU64 = AtomicU64::new(0);
+
+/// What one cycle over an instance did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActiveExpireCycle {
+    /// Where the next cycle starts, None once the walk reached the end
+    pub next: Option<Vec<u8>>,
+    pub sampled: u64,
+    pub expired: u64,
+}
+
+impl Redis {
+    /// Checks the meta keys from `cursor` on until `budget` is used and
+    /// deletes the expired ones
+    pub fn active_expire_cycle(
+        &self,
+        cursor: &[u8],
+        budget: Duration,
+    ) -> Result<ActiveExpireCycle> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let started = Instant::now();
+        let mut cycle = ActiveExpireCycle::default();
+        let mut expired = Vec::new();
+        let iter = db.iterator_cf(&meta_cf, IteratorMode::From(cursor, Direction::Forward));
+        for item in iter {
+            let (meta_key, meta_value) = item.context(RocksSnafu)?;
+            cycle.sampled += 1;
+            if is_expired_meta_value(&meta_value) {
+                expired.push(meta_key.to_vec());
+            }
+            if cycle.sampled as usize % KEYS_PER_TIME_CHECK == 0 && started.elapsed() >= budget {
+                // The smallest key after this one
+                let mut next = meta_key.to_vec();
+                next.push(0);
+                cycle.next = Some(next);
+                break;
+            }
+        }
+
+        for meta_key in expired {
+            if self.delete_expired(&meta_key)? {
+                cycle.expired += 1;
+            }
+        }
+
+        CYCLES.fetch_add(1, Ordering::Relaxed);
+        KEYS_SAMPLED.fetch_add(cycle.sampled, Ordering::Relaxed);
+        KEYS_EXPIRED.fetch_add(cycle.expired, Ordering::Relaxed);
+        Ok(cycle)
+    }
+
+    /// Deletes the key of `meta_key` if it is still expired under its lock,
+    /// and queues its data keys
+    fn delete_expired(&self, meta_key: &[u8]) -> Result<bool> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let parsed = ParsedBaseKey::new(meta_key)?;
+        let key = parsed.key();
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        // The key may have been written again since the scan
+        let Some(meta_value) = db
+            .get_cf_opt(&meta_cf, meta_key, &self.read_options)
+            .context(RocksSnafu)?
+        else {
+            return Ok(false);
+        };
+        if !is_expired_meta_value(&meta_value) {
+            return Ok(false);
+        }
+
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(&meta_cf, meta_key);
+        db.write_opt(batch, &self.write_options)
+            .context(RocksSnafu)?;
+        // An expired key never goes to the trash, its data can go at once
+        if let Some(task) = LazyDeleteTask::from_meta_value(key, &meta_value) {
+            self.lazy_delete.push(task);
+        }
+        Ok(true)
+    }
+}
+
+/// Formats the counters of the active expire cycles as the activeexpire
+/// section of INFO
+pub fn active_expire_info() -> String {
+    let mut info = String::from("# ActiveExpire\r\n");
+    let _ = write!(
+        info,
+        "active_expire_cycles:{}\r\nactive_expire_sampled_keys:{}\r\nexpired_keys:{}\r\n",
+        CYCLES.load(Ordering::Relaxed),
+        KEYS_SAMPLED.load(Ordering::Relaxed),
+        KEYS_EXPIRED.load(Ordering::Relaxed),
+    );
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{self, MockClock},
+        strings_value_format::StringValue,
+        unique_test_db_path, BgTaskHandler, StorageOptions,
+    };
+    use kstd::lock_mgr::LockMgr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_active_expire_cycle() {
+        let test_db_path = unique_test_db_path();
+        let (handler, _receiver) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+
+        let clock = Arc::new(MockClock::new(1_700_000_000_000_000));
+        clock::with_clock(clock.clone(), || {
+            let db = redis.db.as_ref().unwrap();
+            let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+            for i in 0..10 {
+                let mut value = StringValue::new(&b"v"[..]);
+                if i % 2 == 0 {
+                    value.set_relative_etime(1_000_000).unwrap();
+                }
+                let meta_key = redis.base_key(format!("k{i}").as_bytes()).encode();
+                db.put_cf(&meta_cf, meta_key.unwrap(), value.encode())
+                    .unwrap();
+            }
+
+            let cycle = redis
+                .active_expire_cycle(&[], Duration::from_secs(10))
+                .unwrap();
+            assert_eq!((cycle.sampled, cycle.expired), (10, 0));
+
+            clock.advance(Duration::from_secs(2));
+            let cycle = redis
+                .active_expire_cycle(&[], Duration::from_secs(10))
+                .unwrap();
+            assert_eq!(cycle.next, None);
+            assert_eq!((cycle.sampled, cycle.expired), (10, 5));
+            assert_eq!(db.iterator_cf(&meta_cf, IteratorMode::Start).count(), 5);
+        });
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
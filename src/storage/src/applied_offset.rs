@@ -0,0 +1,104 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Read-your-writes session consistency
+//!
+//! With read scaling, a client that writes on the master and then reads
+//! from a replica may not see its own write yet. When enabled, the master
+//! tells the client the binlog offset of every write it replies to, and the
+//! client passes the last offset it got along with its reads. A replica then
+//! serves the read only once it has applied that offset, waiting a bounded
+//! time for the replication to catch up before asking the client to retry.
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The binlog offset applied by this node. Offsets only move forward, an
+/// older offset recorded late is ignored.
+#[derive(Debug, Default)]
+pub struct AppliedOffset {
+    offset: Mutex<u64>,
+    advanced: Condvar,
+}
+
+impl AppliedOffset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The last applied offset, 0 when nothing was applied yet
+    pub fn current(&self) -> u64 {
+        *self.offset.lock().unwrap()
+    }
+
+    /// Records that the binlog was applied up to `offset` and wakes up the
+    /// reads waiting for it
+    pub fn advance(&self, offset: u64) {
+        let mut applied = self.offset.lock().unwrap();
+        if offset > *applied {
+            *applied = offset;
+            self.advanced.notify_all();
+        }
+    }
+
+    /// Waits until `offset` is applied, for at most `timeout`. Returns the
+    /// applied offset when it is reached, None on timeout
+    pub fn wait_for(&self, offset: u64, timeout: Duration) -> Option<u64> {
+        let deadline = Instant::now() + timeout;
+        let mut applied = self.offset.lock().unwrap();
+        while *applied < offset {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            applied = self.advanced.wait_timeout(applied, remaining).unwrap().0;
+        }
+        Some(*applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_advance_only_forward() {
+        let applied = AppliedOffset::new();
+        assert_eq!(applied.current(), 0);
+        applied.advance(10);
+        applied.advance(4);
+        assert_eq!(applied.current(), 10);
+        assert_eq!(applied.wait_for(10, Duration::ZERO), Some(10));
+        assert_eq!(applied.wait_for(11, Duration::from_millis(10)), None);
+    }
+
+    #[test]
+    fn test_wait_for_wakes_up_on_advance() {
+        let applied = Arc::new(AppliedOffset::new());
+        let waiter = {
+            let applied = Arc::clone(&applied);
+            thread::spawn(move || applied.wait_for(5, Duration::from_secs(10)))
+        };
+        applied.advance(3);
+        applied.advance(7);
+        assert_eq!(waiter.join().unwrap(), Some(7));
+    }
+}
@@ -18,20 +18,31 @@
  */
 
 use crate::{
-    base_value_format::{DataType, InternalValue, ParsedInternalValue},
-    delegate_internal_value, delegate_parsed_value,
-    error::{InvalidFormatSnafu, Result},
-    storage_define::{SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH},
+    base_value_format::{DataType, InternalValue, ParsedInternalValue, ValueBuf},
+    checksum, delegate_internal_value, delegate_parsed_value,
+    error::{CorruptValueSnafu, Result},
+    format_version::{FormatMigrator, ValueLayout},
+    impl_value_format,
+    storage_define::{RESERVE_FLAGS_OFFSET, SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH},
 };
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use rocksdb::CompactionDecision;
 use snafu::ensure;
 
 /*
  * hash/set/zset/list data value format
- * | value | reserve | ctime |
- * |       |   16B   |   8B  |
+ * | value | etime | reserve | ctime |
+ * |       |  8B   |   16B   |   8B  |
+ *
+ * The etime of the member is only present when the flags byte of the
+ * reserve has DATA_VALUE_ETIME_FLAG set, so a member without expiration
+ * keeps the layout written before member expiration existed.
  */
 
+/// Set in the flags byte of the reserve of a data value with an etime. The
+/// hash field values share the layout, see `HASH_FIELD_ETIME_FLAG`
+pub const DATA_VALUE_ETIME_FLAG: u8 = 0x01;
+
 /// TODO: remove allow dead code
 #[allow(dead_code)]
 pub struct BaseDataValue {
@@ -39,6 +50,7 @@ pub struct BaseDataValue {
 }
 
 delegate_internal_value!(BaseDataValue);
+impl_value_format!(BaseDataValue, ParsedBaseDataValue);
 #[allow(dead_code)]
 impl BaseDataValue {
     pub fn new<T>(user_value: T) -> Self
@@ -51,16 +63,30 @@ impl BaseDataValue {
     }
 
     pub fn encode(&self) -> BytesMut {
-        let user_value_size = self.inner.user_value.len();
+        let etime_len = if self.inner.etime == 0 {
+            0
+        } else {
+            TIMESTAMP_LENGTH
+        };
         // hash/set/zset/list data value format:
-        //          |     value      |       reserve         |     ctime       |
-        //          |                |         16B           |      8B         |
-        let needed = user_value_size + SUFFIX_RESERVE_LENGTH + TIMESTAMP_LENGTH;
+        //          |     value      |  etime  |    reserve    |  ctime  |
+        //          |                |   8B    |      16B      |    8B   |
+        let needed =
+            self.inner.user_value.len() + etime_len + SUFFIX_RESERVE_LENGTH + TIMESTAMP_LENGTH;
         let mut buf = BytesMut::with_capacity(needed);
 
         buf.put_slice(&self.inner.user_value);
-        buf.put_bytes(0, SUFFIX_RESERVE_LENGTH);
+        let mut reserve = self.inner.reserve;
+        if etime_len > 0 {
+            buf.put_u64_le(self.inner.etime);
+            reserve[RESERVE_FLAGS_OFFSET] |= DATA_VALUE_ETIME_FLAG;
+        } else {
+            reserve[RESERVE_FLAGS_OFFSET] &= !DATA_VALUE_ETIME_FLAG;
+        }
+        let reserve_start = buf.len();
+        buf.put_slice(&reserve);
         buf.put_u64_le(self.inner.ctime);
+        checksum::seal(&mut buf, reserve_start);
 
         buf
     }
@@ -79,38 +105,48 @@ impl ParsedBaseDataValue {
 
     pub fn new<T>(internal_value: T) -> Result<Self>
     where
-        T: Into<BytesMut>,
+        T: Into<ValueBuf>,
     {
-        let value: BytesMut = internal_value.into();
+        let value =
+            FormatMigrator::global().upgrade(ValueLayout::BaseData, internal_value.into())?;
         ensure!(
             value.len() >= Self::BASEDATAVALUESUFFIXLENGTH,
-            InvalidFormatSnafu {
-                message: format!(
-                    "invalid base data value length: {} < {}",
-                    value.len(),
-                    Self::BASEDATAVALUESUFFIXLENGTH
-                )
+            CorruptValueSnafu {
+                data_type: DataType::None,
+                field: "suffix",
+                offset: 0usize,
+                expected: Self::BASEDATAVALUESUFFIXLENGTH,
+                actual: value.len(),
+                key: None::<String>,
             }
         );
 
-        let user_value_len = value.len() - Self::BASEDATAVALUESUFFIXLENGTH;
+        let reserve_start = value.len() - Self::BASEDATAVALUESUFFIXLENGTH;
+        let reserve_end = reserve_start + SUFFIX_RESERVE_LENGTH;
+        let reserve_range = reserve_start..reserve_end;
+        let ctime = (&value[reserve_end..]).get_u64_le();
+
+        let has_etime = value[reserve_start + RESERVE_FLAGS_OFFSET] & DATA_VALUE_ETIME_FLAG != 0;
+        let (user_value_len, etime) = if has_etime {
+            ensure!(
+                reserve_start >= TIMESTAMP_LENGTH,
+                CorruptValueSnafu {
+                    data_type: DataType::None,
+                    field: "etime",
+                    offset: 0usize,
+                    expected: Self::BASEDATAVALUESUFFIXLENGTH + TIMESTAMP_LENGTH,
+                    actual: value.len(),
+                    key: None::<String>,
+                }
+            );
+            let etime_start = reserve_start - TIMESTAMP_LENGTH;
+            let etime = (&value[etime_start..reserve_start]).get_u64_le();
+            (etime_start, etime)
+        } else {
+            (reserve_start, 0)
+        };
         let user_value_range = 0..user_value_len;
-
-        let reserve_end = user_value_len + SUFFIX_RESERVE_LENGTH;
-        let reserve_range = user_value_len..reserve_end;
-
-        let mut time_reader = &value[reserve_end..];
-        ensure!(
-            time_reader.len() >= TIMESTAMP_LENGTH,
-            InvalidFormatSnafu {
-                message: format!(
-                    "invalid base data value length: {} < {}",
-                    time_reader.len(),
-                    TIMESTAMP_LENGTH
-                )
-            }
-        );
-        let ctime = time_reader.get_u64_le();
+        checksum::verify(&value, reserve_start)?;
 
         Ok(Self {
             inner: ParsedInternalValue::new(
@@ -120,11 +156,21 @@ impl ParsedBaseDataValue {
                 reserve_range,
                 0,
                 ctime,
-                0,
+                etime,
             ),
         })
     }
 
+    /// Removes a member whose etime has passed, the members without an
+    /// etime are left to the filter of their collection
+    pub fn filter_decision(&self, cur_time: u64) -> CompactionDecision {
+        if self.inner.etime != 0 && self.inner.etime < cur_time {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+
     pub fn set_ctime(&mut self, ctime: u64) {
         self.inner.ctime = ctime;
         self.set_ctime_to_value();
@@ -142,9 +188,7 @@ impl ParsedBaseDataValue {
         if !self.inner.value.is_empty() {
             let len = self.inner.value.len();
             if len >= Self::BASEDATAVALUESUFFIXLENGTH {
-                self.inner
-                    .value
-                    .truncate(len - Self::BASEDATAVALUESUFFIXLENGTH);
+                self.inner.value.truncate(self.inner.user_value_range.end);
             }
         }
     }
@@ -153,8 +197,11 @@ impl ParsedBaseDataValue {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
+    use crate::hashes_data_value_format::HashesDataValue;
 
     const TEST_CTIME: u64 = 1620000000;
+    const TEST_ETIME: u64 = 1630000000;
     const TEST_VALUE: &[u8] = b"test_data";
     const TEST_VALUE_EMPTY: &[u8] = b"";
     const TEST_VALUE_LARGE: &[u8] = b"this_is_a_very_long_test_value_for_testing_large_data_values";
@@ -266,6 +313,90 @@ mod tests {
         assert_eq!(parsed.inner.ctime, TEST_CTIME);
     }
 
+    #[test]
+    fn test_base_data_value_checksum() {
+        let mut data_value = BaseDataValue::new(TEST_VALUE);
+        data_value.inner.ctime = TEST_CTIME;
+        let mut encoded = crate::checksum::with_checksum(|| data_value.encode());
+
+        let parsed = crate::checksum::with_checksum(|| ParsedBaseDataValue::new(encoded.clone()));
+        assert_eq!(parsed.unwrap().user_value(), TEST_VALUE);
+
+        encoded[0] ^= 0xff;
+        assert!(crate::checksum::with_checksum(|| ParsedBaseDataValue::new(encoded)).is_err());
+    }
+
+    #[test]
+    fn test_base_data_value_etime() {
+        let mut data_value = BaseDataValue::new(TEST_VALUE);
+        data_value.inner.ctime = TEST_CTIME;
+        data_value.inner.etime = TEST_ETIME;
+
+        let encoded = crate::checksum::with_checksum(|| data_value.encode());
+        let reserve_start = TEST_VALUE.len() + TIMESTAMP_LENGTH;
+        assert_eq!(
+            encoded.len(),
+            reserve_start + SUFFIX_RESERVE_LENGTH + TIMESTAMP_LENGTH
+        );
+        assert_eq!(
+            &encoded[TEST_VALUE.len()..reserve_start],
+            &TEST_ETIME.to_le_bytes()
+        );
+        assert_ne!(
+            encoded[reserve_start + RESERVE_FLAGS_OFFSET] & DATA_VALUE_ETIME_FLAG,
+            0
+        );
+
+        let mut parsed =
+            crate::checksum::with_checksum(|| ParsedBaseDataValue::new(encoded.clone())).unwrap();
+        assert_eq!(parsed.user_value(), TEST_VALUE);
+        assert_eq!(parsed.etime(), TEST_ETIME);
+        assert_eq!(parsed.ctime(), TEST_CTIME);
+        parsed.strip_suffix();
+        assert_eq!(&parsed.inner.value[..], TEST_VALUE);
+
+        // the checksum covers the etime
+        let mut corrupted = encoded;
+        corrupted[TEST_VALUE.len()] ^= 0xff;
+        assert!(crate::checksum::with_checksum(|| ParsedBaseDataValue::new(corrupted)).is_err());
+
+        // a hash field value parses as a base data value
+        let mut field_value = HashesDataValue::new(TEST_VALUE);
+        field_value.set_etime(TEST_ETIME);
+        let parsed = ParsedBaseDataValue::new(field_value.encode()).unwrap();
+        assert_eq!(parsed.user_value(), TEST_VALUE);
+        assert_eq!(parsed.etime(), TEST_ETIME);
+    }
+
+    #[test]
+    fn test_base_data_value_stale() {
+        let mut data_value = BaseDataValue::new(TEST_VALUE);
+        data_value.inner.etime = TEST_ETIME;
+        let parsed = ParsedBaseDataValue::new(data_value.encode()).unwrap();
+        let clock = std::sync::Arc::new(MockClock::new(TEST_ETIME));
+        crate::clock::with_clock(clock.clone(), || {
+            assert!(!parsed.is_stale());
+            clock.set(TEST_ETIME + 1);
+            assert!(parsed.is_stale());
+        });
+        assert!(matches!(
+            parsed.filter_decision(TEST_ETIME),
+            CompactionDecision::Keep
+        ));
+        assert!(matches!(
+            parsed.filter_decision(TEST_ETIME + 1),
+            CompactionDecision::Remove
+        ));
+
+        // without an etime a member never expires
+        let parsed = ParsedBaseDataValue::new(BaseDataValue::new(TEST_VALUE).encode()).unwrap();
+        assert_eq!(parsed.etime(), 0);
+        assert!(matches!(
+            parsed.filter_decision(u64::MAX),
+            CompactionDecision::Keep
+        ));
+    }
+
     // ==================== ParsedBaseDataValue Tests ====================
 
     #[test]
@@ -393,7 +524,15 @@ mod tests {
     #[test]
     fn test_parsed_base_data_value_strip_suffix_empty() {
         let mut parsed = ParsedBaseDataValue {
-            inner: ParsedInternalValue::new(BytesMut::new(), DataType::None, 0..0, 0..0, 0, 0, 0),
+            inner: ParsedInternalValue::new(
+                BytesMut::new().into(),
+                DataType::None,
+                0..0,
+                0..0,
+                0,
+                0,
+                0,
+            ),
         };
 
         // Should not panic on empty buffer
@@ -407,7 +546,7 @@ mod tests {
         buf.put_slice(&[0u8; SUFFIX_RESERVE_LENGTH + TIMESTAMP_LENGTH - 1]); // Shorter than suffix length
 
         let mut parsed = ParsedBaseDataValue {
-            inner: ParsedInternalValue::new(buf, DataType::None, 0..0, 0..0, 0, 0, 0),
+            inner: ParsedInternalValue::new(buf.into(), DataType::None, 0..0, 0..0, 0, 0, 0),
         };
 
         // Should not panic on short buffer
@@ -424,7 +563,7 @@ mod tests {
         buf.put_slice(&[0u8; SUFFIX_RESERVE_LENGTH + TIMESTAMP_LENGTH]); // Exact suffix length
 
         let mut parsed = ParsedBaseDataValue {
-            inner: ParsedInternalValue::new(buf, DataType::None, 0..0, 0..0, 0, 0, 0),
+            inner: ParsedInternalValue::new(buf.into(), DataType::None, 0..0, 0..0, 0, 0, 0),
         };
 
         parsed.strip_suffix();
@@ -18,36 +18,100 @@
  */
 
 use crate::{
-    base_key_format::ParsedBaseKey, base_value_format::DataType,
+    base_data_value_format::ParsedBaseDataValue,
+    base_key_format::{BaseKey, ParsedBaseKey},
+    base_meta_value_format::ParsedBaseMetaValue,
+    base_value_format::DataType,
+    clock,
+    error::{Result, RocksSnafu},
+    filter_config::{FilterConfig, FilterPolicy, SharedFilterConfig},
+    filter_stats::{record, FilterKind, FilterOutcome},
+    hashes_data_key_format::ParsedHashesDataKey,
+    hashes_data_value_format::ParsedHashesDataValue,
+    lazy_delete::data_column_families,
+    list_meta_value_format::ParsedListsMetaValue,
+    sets_member_key_format::ParsedSetsMemberKey,
+    stale_ranges::record_filtered,
     strings_value_format::ParsedStringsValue,
+    trash::decode_trash_value,
+    zsets_data_key_format::{ParsedZSetsMemberKey, ParsedZSetsScoreKey},
+    ColumnFamilyIndex,
 };
 use bytes::BytesMut;
-use chrono::Utc;
-use log::debug;
+use log::{debug, warn};
 use rocksdb::{
     compaction_filter::CompactionFilter, compaction_filter_factory::CompactionFilterFactory,
-    ColumnFamily, CompactionDecision, ReadOptions, DB,
+    CompactionDecision, ReadOptions, DB,
 };
-use std::sync::Arc;
+use snafu::ResultExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+
+/// Meta values removed by `BaseMetaFilter`, by data type
+static META_VALUES_REMOVED: [AtomicU64; 8] = [const { AtomicU64::new(0) }; 8];
+
+/// The number of meta values of `data_type` that compaction removed since
+/// the process started.
+pub fn meta_values_removed(data_type: DataType) -> u64 {
+    META_VALUES_REMOVED[data_type as usize].load(Ordering::Relaxed)
+}
+
+/// Empty meta values kept under `FilterPolicy::KeepAndCount`
+static EMPTY_META_VALUES_KEPT: AtomicU64 = AtomicU64::new(0);
+
+/// The number of empty meta values compaction kept and counted since the
+/// process started.
+pub fn empty_meta_values_kept() -> u64 {
+    EMPTY_META_VALUES_KEPT.load(Ordering::Relaxed)
+}
 
 #[derive(Debug, Default)]
-pub struct BaseMetaFilter;
+pub struct BaseMetaFilter {
+    config: FilterConfig,
+}
 
 #[allow(dead_code)]
 #[derive(Debug, Default)]
-pub struct BaseMetaFilterFactory;
+pub struct BaseMetaFilterFactory {
+    config: SharedFilterConfig,
+}
 
-/// TODO: remove allow dead code
-#[allow(dead_code)]
+/// Removes the fields and members of hashes, sets and sorted sets whose
+/// meta value is gone: deleted, overwritten by a newer version or another
+/// type, or expired. Data keys of one collection are adjacent, so the meta
+/// value is read once per collection and kept until the key changes.
 pub struct BaseDataFilter {
-    db: Arc<DB>,
-    cf_handles: Arc<Vec<Arc<ColumnFamily>>>,
+    db: Weak<DB>,
     target_data_type: DataType,
     default_read_opts: ReadOptions,
     cur_key: BytesMut,
     meta_not_found: bool,
     cur_meta_version: u64,
     cur_meta_etime: u64,
+    config: FilterConfig,
+}
+
+/// Removes the score keys of sorted sets on the same decisions
+/// `BaseDataFilter` makes for their member keys, so that compaction of one
+/// column family never leaves the other holding entries of a dead zset.
+pub struct ZSetsScoreFilter {
+    inner: BaseDataFilter,
+}
+
+/// Creates a `ZSetsScoreFilter`, see `BaseDataFilterFactory`.
+pub struct ZSetsScoreFilterFactory {
+    db: Arc<OnceLock<Weak<DB>>>,
+    config: SharedFilterConfig,
+    bottommost_only: bool,
+}
+
+/// Creates a `BaseDataFilter` for the data column family of one type. The
+/// db is filled in once it is open, compactions before that keep everything.
+pub struct BaseDataFilterFactory {
+    db: Arc<OnceLock<Weak<DB>>>,
+    data_type: DataType,
+    config: SharedFilterConfig,
+    bottommost_only: bool,
 }
 
 impl CompactionFilter for BaseMetaFilter {
@@ -56,21 +120,17 @@ impl CompactionFilter for BaseMetaFilter {
     }
 
     fn filter(&mut self, _level: u32, key: &[u8], value: &[u8]) -> CompactionDecision {
-        let current_time = Utc::now().timestamp_micros() as u64;
+        let current_time = self.config.expire_before(clock::now_micros());
 
         let parsed_key_result = ParsedBaseKey::new(key);
         if let Err(e) = parsed_key_result {
             debug!("BaseMetaFilter: Failed to parse key {key:?}: {e}, remove.",);
-            return CompactionDecision::Remove;
+            return self.record_outcome(FilterOutcome::RemovedCorrupt);
         }
         let parsed_key = parsed_key_result.unwrap();
 
         if value.is_empty() {
-            debug!(
-                "BaseMetaFilter: Value for key {:?} is empty, remove.",
-                parsed_key.key()
-            );
-            return CompactionDecision::Remove;
+            return self.empty_value(parsed_key.key());
         }
 
         let data_type = match DataType::try_from(value[0]) {
@@ -81,31 +141,102 @@ impl CompactionFilter for BaseMetaFilter {
                     value[0],
                     parsed_key.key()
                 );
-                return CompactionDecision::Remove;
+                return self.record_outcome(FilterOutcome::RemovedCorrupt);
             }
         };
+        let outcome = Self::decide(parsed_key.key(), data_type, value, current_time);
+        let outcome = self.config.resolve(outcome);
+        if outcome != FilterOutcome::Kept {
+            META_VALUES_REMOVED[data_type as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        record_filtered(ColumnFamilyIndex::MetaCF, outcome);
+        record(FilterKind::Meta, outcome)
+    }
+}
+
+impl BaseMetaFilter {
+    pub fn new(config: FilterConfig) -> Self {
+        Self { config }
+    }
+
+    fn record_outcome(&self, outcome: FilterOutcome) -> CompactionDecision {
+        record(FilterKind::Meta, self.config.resolve(outcome))
+    }
+
+    /// Decides on an empty meta value under `empty_value_policy`. Under
+    /// `FilterPolicy::Remove` it is still kept if `remove_corrupt` is off
+    fn empty_value(&self, key: &[u8]) -> CompactionDecision {
+        match self.config.empty_value_policy {
+            FilterPolicy::Remove if self.config.remove_corrupt => {
+                warn!("BaseMetaFilter: Value for key {key:?} is empty, remove.");
+                record(FilterKind::Meta, FilterOutcome::RemovedCorrupt)
+            }
+            FilterPolicy::Remove => {
+                warn!("BaseMetaFilter: Value for key {key:?} is empty, keep as corrupt.");
+                record(FilterKind::Meta, FilterOutcome::Kept)
+            }
+            FilterPolicy::Keep => {
+                warn!("BaseMetaFilter: Value for key {key:?} is empty, keep.");
+                record(FilterKind::Meta, FilterOutcome::Kept)
+            }
+            FilterPolicy::KeepAndCount => {
+                let kept = EMPTY_META_VALUES_KEPT.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!("BaseMetaFilter: Value for key {key:?} is empty, keep ({kept} kept).");
+                record(FilterKind::Meta, FilterOutcome::Kept)
+            }
+        }
+    }
+
+    fn decide(key: &[u8], data_type: DataType, value: &[u8], current_time: u64) -> FilterOutcome {
         match data_type {
             DataType::String => match ParsedStringsValue::new(value) {
-                Ok(pv) => pv.filter_decision(current_time),
+                Ok(pv) => FilterOutcome::from_expiry(pv.filter_decision(current_time)),
                 Err(e) => {
                     debug!(
-                        "BaseMetaFilter: Failed to parse Strings value for key {:?}: {}, remove.",
-                        parsed_key.key(),
-                        e
+                        "BaseMetaFilter: Failed to parse Strings value for key {key:?}: {e}, remove."
                     );
-                    CompactionDecision::Remove
+                    FilterOutcome::RemovedCorrupt
                 }
             },
-            DataType::List => {
-                todo!()
+            DataType::List => match ParsedListsMetaValue::new(value) {
+                Ok(pv) => FilterOutcome::from_expiry(pv.filter_decision(current_time)),
+                Err(e) => {
+                    debug!(
+                        "BaseMetaFilter: Failed to parse Lists meta value for key {key:?}: {e}, remove."
+                    );
+                    FilterOutcome::RemovedCorrupt
+                }
+            },
+            DataType::Hash | DataType::Set | DataType::ZSet => {
+                match ParsedBaseMetaValue::new(value) {
+                    Ok(pv) => FilterOutcome::from_expiry(pv.filter_decision(current_time)),
+                    Err(e) => {
+                        debug!(
+                            "BaseMetaFilter: Failed to parse {data_type:?} meta value for key {key:?}: {e}, remove."
+                        );
+                        FilterOutcome::RemovedCorrupt
+                    }
+                }
             }
-            _ => {
-                todo!()
+            // Stream entries are reclaimed by trimming, not by compaction
+            DataType::Stream => FilterOutcome::Kept,
+            DataType::None | DataType::All => {
+                debug!(
+                    "BaseMetaFilter: No meta value has type {data_type:?}, key {key:?}, remove."
+                );
+                FilterOutcome::RemovedCorrupt
             }
         }
     }
 }
 
+impl BaseMetaFilterFactory {
+    #[allow(dead_code)]
+    pub fn new(config: SharedFilterConfig) -> Self {
+        Self { config }
+    }
+}
+
 impl CompactionFilterFactory for BaseMetaFilterFactory {
     type Filter = BaseMetaFilter;
 
@@ -113,7 +244,7 @@ impl CompactionFilterFactory for BaseMetaFilterFactory {
         &mut self,
         _context: rocksdb::compaction_filter_factory::CompactionFilterContext,
     ) -> Self::Filter {
-        BaseMetaFilter
+        BaseMetaFilter::new(self.config.get())
     }
 
     fn name(&self) -> &std::ffi::CStr {
@@ -121,46 +252,610 @@ impl CompactionFilterFactory for BaseMetaFilterFactory {
     }
 }
 
-/// TODO: remove allow dead code
-#[allow(dead_code)]
 impl BaseDataFilter {
-    pub fn new(
-        db: Arc<DB>,
-        cf_handles: Arc<Vec<Arc<ColumnFamily>>>,
-        target_data_type: DataType,
-    ) -> Self {
+    pub fn new(db: Weak<DB>, target_data_type: DataType) -> Self {
         Self {
             db,
-            cf_handles,
             target_data_type,
             default_read_opts: ReadOptions::default(),
             cur_key: BytesMut::new(),
             meta_not_found: false,
             cur_meta_version: 0,
             cur_meta_etime: 0,
+            config: FilterConfig::default(),
+        }
+    }
+
+    /// Filters with `config` instead of the default
+    pub fn with_config(mut self, config: FilterConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// The user key, version and meta key of a data key
+    fn parse_key(&self, key: &[u8]) -> Result<(u64, BytesMut)> {
+        let (user_key, version, reserve1) = match self.target_data_type {
+            DataType::Set => {
+                let parsed = ParsedSetsMemberKey::from_slice(key)?;
+                (parsed.key().to_vec(), parsed.version(), *parsed.reserve1())
+            }
+            DataType::ZSet => {
+                let parsed = ParsedZSetsMemberKey::from_slice(key)?;
+                (parsed.key().to_vec(), parsed.version(), *parsed.reserve1())
+            }
+            _ => {
+                let parsed = ParsedHashesDataKey::from_slice(key)?;
+                (parsed.key().to_vec(), parsed.version(), *parsed.reserve1())
+            }
+        };
+        let meta_key = BaseKey::with_reserve1(&user_key, reserve1).encode()?;
+        Ok((version, meta_key))
+    }
+
+    /// Reads the meta value owning the data keys under `meta_key`. A live
+    /// meta value of the target type owns them, otherwise one in the trash
+    /// does, since UNDELETE can still bring its data back.
+    fn load_meta(&mut self, db: &DB, meta_key: BytesMut, now: u64) -> Result<()> {
+        self.meta_not_found = true;
+        self.cur_meta_version = 0;
+        self.cur_meta_etime = 0;
+
+        let live = db
+            .get_opt(&meta_key, &self.default_read_opts)
+            .context(RocksSnafu)?;
+        if let Some(meta) = live.as_deref().and_then(|v| self.owning_meta(v, now)) {
+            self.set_meta(&meta);
+        } else if let Some(trash_cf) = db.cf_handle(ColumnFamilyIndex::TrashCF.name()) {
+            let trashed = db
+                .get_cf_opt(&trash_cf, &meta_key, &self.default_read_opts)
+                .context(RocksSnafu)?;
+            if let Some(meta) = trashed
+                .as_deref()
+                .and_then(decode_trash_value)
+                .and_then(|(_, v)| self.owning_meta(v, now))
+            {
+                self.set_meta(&meta);
+            }
+        }
+        self.cur_key = meta_key;
+        Ok(())
+    }
+
+    /// The meta value if it is of the target type and would survive the
+    /// meta filter
+    fn owning_meta(&self, value: &[u8], now: u64) -> Option<ParsedBaseMetaValue> {
+        if value.first() != Some(&(self.target_data_type as u8)) {
+            return None;
+        }
+        let meta = ParsedBaseMetaValue::new(value).ok()?;
+        matches!(meta.filter_decision(now), CompactionDecision::Keep).then_some(meta)
+    }
+
+    fn set_meta(&mut self, meta: &ParsedBaseMetaValue) {
+        self.meta_not_found = false;
+        self.cur_meta_version = meta.version();
+        self.cur_meta_etime = meta.etime();
+    }
+
+    /// Decides on the data entry `key` of `version`, reading the meta value
+    /// under `meta_key` unless it is the cached one
+    fn filter_entry(
+        &mut self,
+        key: &[u8],
+        version: u64,
+        meta_key: BytesMut,
+        value: &[u8],
+        now: u64,
+    ) -> FilterOutcome {
+        if meta_key != self.cur_key {
+            // The db is closing, leave the entry to a later compaction
+            let Some(db) = self.db.upgrade() else {
+                return FilterOutcome::Kept;
+            };
+            if let Err(e) = self.load_meta(&db, meta_key, now) {
+                debug!("BaseDataFilter: Failed to read meta value for key {key:?}: {e}, keep.");
+                self.cur_key.clear();
+                return FilterOutcome::Kept;
+            }
         }
+        self.decide(version, value, now)
+    }
+
+    /// Decides on a data entry of `version` against the cached meta value
+    fn decide(&self, version: u64, value: &[u8], now: u64) -> FilterOutcome {
+        if self.meta_not_found {
+            return FilterOutcome::RemovedStaleVersion;
+        }
+        if version < self.cur_meta_version {
+            return FilterOutcome::RemovedStaleVersion;
+        }
+        if self.cur_meta_etime != 0 && self.cur_meta_etime < now {
+            return FilterOutcome::RemovedExpired;
+        }
+        let decision = match self.target_data_type {
+            DataType::Hash => ParsedHashesDataValue::new(value).map(|v| v.filter_decision(now)),
+            _ => ParsedBaseDataValue::new(value).map(|v| v.filter_decision(now)),
+        };
+        decision
+            .map(FilterOutcome::from_expiry)
+            .unwrap_or_else(|e| {
+                debug!("BaseDataFilter: Failed to parse data value: {e}, remove.");
+                FilterOutcome::RemovedCorrupt
+            })
+    }
+}
+
+impl CompactionFilter for BaseDataFilter {
+    fn name(&self) -> &std::ffi::CStr {
+        c"BaseDataFilter"
+    }
+
+    fn filter(&mut self, level: u32, key: &[u8], value: &[u8]) -> CompactionDecision {
+        if !self.config.removes_at(level) {
+            return record(FilterKind::Data, FilterOutcome::Kept);
+        }
+        let current_time = self.config.expire_before(clock::now_micros());
+
+        let outcome = match self.parse_key(key) {
+            Ok((version, meta_key)) => {
+                self.filter_entry(key, version, meta_key, value, current_time)
+            }
+            Err(e) => {
+                debug!("BaseDataFilter: Failed to parse key {key:?}: {e}, remove.");
+                FilterOutcome::RemovedCorrupt
+            }
+        };
+        let outcome = self.config.resolve(outcome);
+        if let Some(&cf) = data_column_families(self.target_data_type).first() {
+            record_filtered(cf, outcome);
+        }
+        record(FilterKind::Data, outcome)
+    }
+}
+
+impl CompactionFilter for ZSetsScoreFilter {
+    fn name(&self) -> &std::ffi::CStr {
+        c"ZSetsScoreFilter"
+    }
+
+    fn filter(&mut self, level: u32, key: &[u8], value: &[u8]) -> CompactionDecision {
+        if !self.inner.config.removes_at(level) {
+            return record(FilterKind::ZSetsScore, FilterOutcome::Kept);
+        }
+        let current_time = self.inner.config.expire_before(clock::now_micros());
+
+        let parsed = match ParsedZSetsScoreKey::from_slice(key) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                debug!("ZSetsScoreFilter: Failed to parse key {key:?}: {e}, remove.");
+                let outcome = self.inner.config.resolve(FilterOutcome::RemovedCorrupt);
+                return record(FilterKind::ZSetsScore, outcome);
+            }
+        };
+        let meta_key = match BaseKey::with_reserve1(parsed.key(), *parsed.reserve1()).encode() {
+            Ok(meta_key) => meta_key,
+            Err(e) => {
+                debug!("ZSetsScoreFilter: Failed to encode meta key of {key:?}: {e}, keep.");
+                return record(FilterKind::ZSetsScore, FilterOutcome::Kept);
+            }
+        };
+        let outcome = self
+            .inner
+            .filter_entry(key, parsed.version(), meta_key, value, current_time);
+        let outcome = self.inner.config.resolve(outcome);
+        record_filtered(ColumnFamilyIndex::ZsetsScoreCF, outcome);
+        record(FilterKind::ZSetsScore, outcome)
+    }
+}
+
+impl ZSetsScoreFilter {
+    pub fn new(db: Weak<DB>) -> Self {
+        Self {
+            inner: BaseDataFilter::new(db, DataType::ZSet),
+        }
+    }
+
+    /// Filters with `config` instead of the default
+    pub fn with_config(mut self, config: FilterConfig) -> Self {
+        self.inner.config = config;
+        self
+    }
+}
+
+impl ZSetsScoreFilterFactory {
+    pub fn new(db: Arc<OnceLock<Weak<DB>>>, config: SharedFilterConfig) -> Self {
+        Self {
+            db,
+            config,
+            bottommost_only: false,
+        }
+    }
+
+    /// Makes the filters remove only in the compactions into the
+    /// bottommost level
+    pub fn with_bottommost_only(mut self, bottommost_only: bool) -> Self {
+        self.bottommost_only = bottommost_only;
+        self
+    }
+}
+
+impl CompactionFilterFactory for ZSetsScoreFilterFactory {
+    type Filter = ZSetsScoreFilter;
+
+    fn create(
+        &mut self,
+        context: rocksdb::compaction_filter_factory::CompactionFilterContext,
+    ) -> Self::Filter {
+        ZSetsScoreFilter::new(self.db.get().cloned().unwrap_or_default()).with_config(
+            self.config
+                .get()
+                .for_compaction(self.bottommost_only, &context),
+        )
+    }
+
+    fn name(&self) -> &std::ffi::CStr {
+        c"ZSetsScoreFilterFactory"
+    }
+}
+
+impl BaseDataFilterFactory {
+    pub fn new(
+        db: Arc<OnceLock<Weak<DB>>>,
+        data_type: DataType,
+        config: SharedFilterConfig,
+    ) -> Self {
+        Self {
+            db,
+            data_type,
+            config,
+            bottommost_only: false,
+        }
+    }
+
+    /// Makes the filters remove only in the compactions into the
+    /// bottommost level
+    pub fn with_bottommost_only(mut self, bottommost_only: bool) -> Self {
+        self.bottommost_only = bottommost_only;
+        self
+    }
+}
+
+impl CompactionFilterFactory for BaseDataFilterFactory {
+    type Filter = BaseDataFilter;
+
+    fn create(
+        &mut self,
+        context: rocksdb::compaction_filter_factory::CompactionFilterContext,
+    ) -> Self::Filter {
+        BaseDataFilter::new(self.db.get().cloned().unwrap_or_default(), self.data_type).with_config(
+            self.config
+                .get()
+                .for_compaction(self.bottommost_only, &context),
+        )
+    }
+
+    fn name(&self) -> &std::ffi::CStr {
+        c"BaseDataFilterFactory"
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::base_data_value_format::BaseDataValue;
+    use crate::base_key_format::BaseKey;
+    use crate::base_meta_value_format::BaseMetaValue;
+    use crate::base_value_format::ValueFormat;
+    use crate::checksum;
+    use crate::clock::MockClock;
+    use crate::hashes_data_key_format::HashesDataKey;
+    use crate::hashes_data_value_format::HashesDataValue;
+    use crate::list_meta_value_format::ListsMetaValue;
+    use crate::storage_define::EMPTY_META_GRACE_MICROS;
     use crate::strings_value_format::StringValue;
+    use crate::zsets_data_key_format::{ZSetsMemberKey, ZSetsScoreKey};
+    use crate::{unique_test_db_path, BgTaskHandler, Redis, StorageOptions};
+    use kstd::lock_mgr::LockMgr;
+    use std::time::Duration;
 
     #[test]
     fn test_strings_base_filter() {
         let mut filter = BaseMetaFilter::default();
         let ttl = 1_000_000;
 
-        let string_val: &'static [u8] = b"filter_val";
-        let mut string_val = StringValue::new(string_val);
-        assert!(matches!(string_val.set_relative_etime(ttl), Ok(())));
+        let clock = Arc::new(MockClock::new(1_700_000_000_000_000));
+        clock::with_clock(clock.clone(), || {
+            let string_val: &'static [u8] = b"filter_val";
+            let mut string_val = StringValue::new(string_val);
+            assert!(matches!(string_val.set_relative_etime(ttl), Ok(())));
 
-        let decision = filter.filter(0, string_val.encode().as_ref(), &string_val.encode());
-        assert!(matches!(decision, CompactionDecision::Keep));
+            let decision = filter.filter(0, string_val.encode().as_ref(), &string_val.encode());
+            assert!(matches!(decision, CompactionDecision::Keep));
+
+            clock.advance(Duration::from_secs(2));
+            let decision = filter.filter(0, string_val.encode().as_ref(), &string_val.encode());
+            assert!(matches!(decision, CompactionDecision::Remove));
+        });
+    }
+
+    #[test]
+    fn test_base_filter_config() {
+        let mut filter = BaseMetaFilter::new(FilterConfig {
+            expire_grace_micros: 2_000_000,
+            remove_corrupt: false,
+            ..FilterConfig::default()
+        });
+
+        let clock = Arc::new(MockClock::new(1_700_000_000_000_000));
+        clock::with_clock(clock.clone(), || {
+            let mut string_val = StringValue::new(&b"filter_val"[..]);
+            string_val.set_relative_etime(1_000_000).unwrap();
+            let encoded = string_val.encode();
+
+            // Expired, but within the grace period
+            clock.advance(Duration::from_secs(2));
+            let decision = filter.filter(0, encoded.as_ref(), &encoded);
+            assert!(matches!(decision, CompactionDecision::Keep));
+
+            clock.advance(Duration::from_secs(2));
+            let decision = filter.filter(0, encoded.as_ref(), &encoded);
+            assert!(matches!(decision, CompactionDecision::Remove));
+        });
 
-        std::thread::sleep(std::time::Duration::from_secs(2));
-        let decision = filter.filter(0, string_val.encode().as_ref(), &string_val.encode());
+        let key = BaseKey::new(b"corrupt").encode().unwrap();
+        let decision = filter.filter(0, &key, b"");
+        assert!(matches!(decision, CompactionDecision::Keep));
+        let decision = BaseMetaFilter::default().filter(0, &key, b"");
         assert!(matches!(decision, CompactionDecision::Remove));
     }
+
+    #[test]
+    fn test_base_filter_empty_value_policy() {
+        let key = BaseKey::new(b"empty_value").encode().unwrap();
+
+        let mut keep = BaseMetaFilter::new(FilterConfig {
+            empty_value_policy: FilterPolicy::Keep,
+            ..FilterConfig::default()
+        });
+        let kept = empty_meta_values_kept();
+        let decision = keep.filter(0, &key, b"");
+        assert!(matches!(decision, CompactionDecision::Keep));
+        assert_eq!(empty_meta_values_kept(), kept);
+
+        let mut count = BaseMetaFilter::new(FilterConfig {
+            empty_value_policy: FilterPolicy::KeepAndCount,
+            ..FilterConfig::default()
+        });
+        let decision = count.filter(0, &key, b"");
+        assert!(matches!(decision, CompactionDecision::Keep));
+        assert!(empty_meta_values_kept() > kept);
+    }
+
+    #[test]
+    fn test_empty_collection_base_filter() {
+        let mut filter = BaseMetaFilter::default();
+        let key = BaseKey::new(b"empty").encode().unwrap();
+
+        let clock = Arc::new(MockClock::new(1_700_000_000_000_000));
+        clock::with_clock(clock.clone(), || {
+            // An empty collection is kept through the grace period, as a list
+            for data_type in [DataType::Hash, DataType::Set, DataType::ZSet] {
+                let start = clock::now_micros();
+                let mut meta = BaseMetaValue::new(0);
+                meta.inner.data_type = data_type;
+                let empty = ValueFormat::encode(&meta);
+
+                clock.set(start + EMPTY_META_GRACE_MICROS);
+                assert!(matches!(
+                    filter.filter(0, &key, &empty),
+                    CompactionDecision::Keep
+                ));
+                clock.set(start + EMPTY_META_GRACE_MICROS + 1);
+                assert!(matches!(
+                    filter.filter(0, &key, &empty),
+                    CompactionDecision::Remove
+                ));
+            }
+        });
+    }
+
+    #[test]
+    fn test_collections_base_filter() {
+        let mut filter = BaseMetaFilter::default();
+        let key = BaseKey::new(b"collection").encode().unwrap();
+
+        let clock = Arc::new(MockClock::new(1_700_000_000_000_000));
+        clock::with_clock(clock.clone(), || {
+            for data_type in [DataType::Hash, DataType::Set, DataType::ZSet] {
+                let removed = meta_values_removed(data_type);
+                let mut meta = BaseMetaValue::new(2);
+                meta.inner.data_type = data_type;
+                let persistent = ValueFormat::encode(&meta);
+                meta.set_etime(clock::now_micros() + 1_000_000);
+                let expiring = ValueFormat::encode(&meta);
+                let mut empty = BaseMetaValue::new(0);
+                empty.inner.data_type = data_type;
+                let empty = ValueFormat::encode(&empty);
+
+                assert!(matches!(
+                    filter.filter(0, &key, &persistent),
+                    CompactionDecision::Keep
+                ));
+                assert!(matches!(
+                    filter.filter(0, &key, &expiring),
+                    CompactionDecision::Keep
+                ));
+                assert!(matches!(
+                    filter.filter(0, &key, &empty),
+                    CompactionDecision::Keep
+                ));
+                assert!(matches!(
+                    filter.filter(0, &key, &expiring[..1]),
+                    CompactionDecision::Remove
+                ));
+
+                clock.advance(Duration::from_secs(2));
+                assert!(matches!(
+                    filter.filter(0, &key, &expiring),
+                    CompactionDecision::Remove
+                ));
+                assert!(matches!(
+                    filter.filter(0, &key, &persistent),
+                    CompactionDecision::Keep
+                ));
+                // Other tests may remove meta values concurrently
+                assert!(meta_values_removed(data_type) >= removed + 2);
+            }
+        });
+    }
+
+    #[test]
+    fn test_lists_base_filter() {
+        let mut filter = BaseMetaFilter::default();
+        let key = BaseKey::new(b"list").encode().unwrap();
+
+        let clock = Arc::new(MockClock::new(1_700_000_000_000_000));
+        clock::with_clock(clock.clone(), || {
+            // An empty list is only removed after the grace period
+            let empty = ValueFormat::encode(&ListsMetaValue::new(0u64.to_le_bytes().to_vec()));
+            let mut list = ListsMetaValue::new(3u64.to_le_bytes().to_vec());
+            list.set_etime(clock::now_micros() + 1_000_000);
+            let expiring = ValueFormat::encode(&list);
+            assert!(matches!(
+                filter.filter(0, &key, &empty),
+                CompactionDecision::Keep
+            ));
+            assert!(matches!(
+                filter.filter(0, &key, &expiring),
+                CompactionDecision::Keep
+            ));
+
+            clock.advance(Duration::from_secs(2));
+            assert!(matches!(
+                filter.filter(0, &key, &expiring),
+                CompactionDecision::Remove
+            ));
+            assert!(matches!(
+                filter.filter(0, &key, &empty),
+                CompactionDecision::Keep
+            ));
+
+            clock.advance(Duration::from_micros(EMPTY_META_GRACE_MICROS));
+            assert!(matches!(
+                filter.filter(0, &key, &empty),
+                CompactionDecision::Remove
+            ));
+
+            // A value that fails its checksum
+            checksum::with_checksum(|| {
+                let mut corrupt =
+                    ValueFormat::encode(&ListsMetaValue::new(3u64.to_le_bytes().to_vec()));
+                corrupt[1] ^= 1;
+                assert!(matches!(
+                    filter.filter(0, &key, &corrupt),
+                    CompactionDecision::Remove
+                ));
+            });
+        });
+    }
+
+    #[test]
+    fn test_hashes_base_data_filter() {
+        let test_db_path = unique_test_db_path();
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+        {
+            let db = redis.db.as_ref().unwrap();
+            let mut meta = BaseMetaValue::new(1);
+            meta.inner.data_type = DataType::Hash;
+            meta.inner.version = 5;
+            let meta_key = redis.base_key(b"live").encode().unwrap();
+            db.put(&meta_key, ValueFormat::encode(&meta)).unwrap();
+            meta.inner.etime = 1;
+            let meta_key = redis.base_key(b"expired").encode().unwrap();
+            db.put(&meta_key, ValueFormat::encode(&meta)).unwrap();
+
+            let value = ValueFormat::encode(&HashesDataValue::new("value"));
+            let mut filter = BaseDataFilter::new(Arc::downgrade(db), DataType::Hash);
+            for (key, version, keep) in [
+                (&b"live"[..], 5, true),
+                (b"live", 4, false),
+                (b"expired", 5, false),
+                (b"missing", 5, false),
+                (b"live", 5, true),
+            ] {
+                let data_key = HashesDataKey::new(key, version, b"field").encode().unwrap();
+                let decision = filter.filter(0, &data_key, &value);
+                assert_eq!(matches!(decision, CompactionDecision::Keep), keep);
+            }
+            assert!(matches!(
+                filter.filter(0, b"short", &value),
+                CompactionDecision::Remove
+            ));
+        }
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+
+    #[test]
+    fn test_zsets_score_filter() {
+        let test_db_path = unique_test_db_path();
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut options = StorageOptions::default();
+        options.set_inline_collection_max_entries(0);
+        let mut redis = Redis::new(
+            Arc::new(options),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+        {
+            redis.zset_add(b"z", &[(1.5, b"a")]).unwrap();
+            let db = redis.db.as_ref().unwrap();
+            let meta = redis.live_base_meta(b"z", DataType::ZSet).unwrap().unwrap();
+            let version = meta.version();
+            let mut expired = meta.to_meta_value();
+            expired.inner.etime = 1;
+            let meta_key = redis.base_key(b"expired").encode().unwrap();
+            db.put(&meta_key, ValueFormat::encode(&expired)).unwrap();
+
+            let score = ValueFormat::encode(&BaseDataValue::new(1.5f64.to_le_bytes().to_vec()));
+            let value = ValueFormat::encode(&BaseDataValue::new(""));
+            let mut member_filter = BaseDataFilter::new(Arc::downgrade(db), DataType::ZSet);
+            let mut score_filter = ZSetsScoreFilter::new(Arc::downgrade(db));
+            for (key, version, keep) in [
+                (&b"z"[..], version, true),
+                (b"z", version - 1, false),
+                (b"expired", version, false),
+                (b"missing", version, false),
+            ] {
+                let member_key = ZSetsMemberKey::new(key, version, b"a").encode().unwrap();
+                let decision = member_filter.filter(0, &member_key, &score);
+                assert_eq!(matches!(decision, CompactionDecision::Keep), keep);
+
+                let score_key = ZSetsScoreKey::new(key, version, 1.5, b"a")
+                    .encode()
+                    .unwrap();
+                let decision = score_filter.filter(0, &score_key, &value);
+                assert_eq!(matches!(decision, CompactionDecision::Keep), keep);
+            }
+            assert!(matches!(
+                score_filter.filter(0, b"short", &value),
+                CompactionDecision::Remove
+            ));
+        }
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
 }
@@ -19,6 +19,7 @@
 
 use crate::{
     error::{InvalidFormatSnafu, Result},
+    slot_indexer::key_to_hash_slot,
     storage_define::{
         decode_user_key, encode_user_key, ENCODED_KEY_DELIM_SIZE, PREFIX_RESERVE_LENGTH,
         SUFFIX_RESERVE_LENGTH,
@@ -31,6 +32,23 @@ use snafu::ensure;
 // | reserve1 | key | reserve2 |
 // |    8B    |     |   16B    |
 //
+// With the slot prefix enabled, reserve1 starts with the slot id of the key:
+// | slot id | reserved |
+// |   2B    |    6B    |
+//
+
+/// Length of the slot id at the start of reserve1.
+pub const SLOT_ID_LENGTH: usize = 2;
+
+/// Returns the reserve1 of `key` holding its slot id. The id is big-endian so
+/// the keys of a slot are contiguous and the slots are in order. The slot is
+/// the one of the hash tag, the keys sharing a tag are stored together.
+pub fn slot_reserve(key: &[u8]) -> [u8; PREFIX_RESERVE_LENGTH] {
+    let mut reserve1 = [0; PREFIX_RESERVE_LENGTH];
+    let slot_id = key_to_hash_slot(key) as u16;
+    reserve1[..SLOT_ID_LENGTH].copy_from_slice(&slot_id.to_be_bytes());
+    reserve1
+}
 
 /// TODO: remove allow dead code
 #[allow(dead_code)]
@@ -44,13 +62,22 @@ pub struct BaseKey {
 #[allow(dead_code)]
 impl BaseKey {
     pub fn new(key: &[u8]) -> Self {
+        Self::with_reserve1(key, [0; PREFIX_RESERVE_LENGTH])
+    }
+
+    pub fn with_reserve1(key: &[u8], reserve1: [u8; PREFIX_RESERVE_LENGTH]) -> Self {
         BaseKey {
-            reserve1: [0; PREFIX_RESERVE_LENGTH],
+            reserve1,
             key: Bytes::copy_from_slice(key),
             reserve2: [0; SUFFIX_RESERVE_LENGTH],
         }
     }
 
+    /// A key prefixed with its slot id, see `slot_reserve`.
+    pub fn with_slot_prefix(key: &[u8]) -> Self {
+        Self::with_reserve1(key, slot_reserve(key))
+    }
+
     pub fn encode(&self) -> Result<BytesMut> {
         let estimated_cap = PREFIX_RESERVE_LENGTH
             + self.key.len() * 2
@@ -65,20 +92,34 @@ impl BaseKey {
     }
 }
 
+/// A decoded base key. The user key is unescaped and both reserves are kept,
+/// so that tools reading raw SSTs (dump, check, migrate) recover the user key
+/// and can write the key back unchanged with `to_base_key`.
 pub struct ParsedBaseKey {
     key_str: BytesMut,
+    reserve1: [u8; PREFIX_RESERVE_LENGTH],
+    reserve2: [u8; SUFFIX_RESERVE_LENGTH],
 }
 
 impl ParsedBaseKey {
     pub fn new(encoded_key: &[u8]) -> Result<Self> {
         let mut key_str = BytesMut::new();
         Self::decode(encoded_key, &mut key_str)?;
-        Ok(ParsedBaseKey { key_str })
+        let mut reserve1 = [0; PREFIX_RESERVE_LENGTH];
+        reserve1.copy_from_slice(&encoded_key[..PREFIX_RESERVE_LENGTH]);
+        let mut reserve2 = [0; SUFFIX_RESERVE_LENGTH];
+        reserve2.copy_from_slice(&encoded_key[encoded_key.len() - SUFFIX_RESERVE_LENGTH..]);
+        Ok(ParsedBaseKey {
+            key_str,
+            reserve1,
+            reserve2,
+        })
     }
 
     fn decode(encoded_key: &[u8], key_str: &mut BytesMut) -> Result<()> {
         ensure!(
-            !encoded_key.is_empty(),
+            encoded_key.len()
+                >= PREFIX_RESERVE_LENGTH + ENCODED_KEY_DELIM_SIZE + SUFFIX_RESERVE_LENGTH,
             InvalidFormatSnafu {
                 message: "Encoded key too short to contain prefix, suffix, and data".to_string(),
             }
@@ -87,17 +128,65 @@ impl ParsedBaseKey {
         let start_idx = PREFIX_RESERVE_LENGTH;
         let end_idx = encoded_key.len() - SUFFIX_RESERVE_LENGTH;
         let data_slice = &encoded_key[start_idx..end_idx];
-        decode_user_key(data_slice, key_str)
+        decode_user_key(data_slice, key_str)?;
+
+        // The delimiter must end the encoded user key, anything between it
+        // and reserve2 means the key is not a base key
+        let escaped = key_str.iter().filter(|&&byte| byte == 0x00).count();
+        ensure!(
+            data_slice.len() == key_str.len() + escaped + ENCODED_KEY_DELIM_SIZE,
+            InvalidFormatSnafu {
+                message: "Unexpected bytes after the encoded key delimiter".to_string(),
+            }
+        );
+        Ok(())
     }
 
     pub fn key(&self) -> &[u8] {
         self.key_str.as_ref()
     }
+
+    /// The user key, without copying it.
+    #[allow(dead_code)]
+    pub fn into_key(self) -> Bytes {
+        self.key_str.freeze()
+    }
+
+    /// The slot id of the key, 0 when it was encoded without slot prefix.
+    #[allow(dead_code)]
+    pub fn slot_id(&self) -> u16 {
+        u16::from_be_bytes([self.reserve1[0], self.reserve1[1]])
+    }
+
+    /// The reserved prefix, starting with the slot id with the slot prefix
+    /// enabled.
+    #[allow(dead_code)]
+    pub fn reserve1(&self) -> &[u8; PREFIX_RESERVE_LENGTH] {
+        &self.reserve1
+    }
+
+    /// The reserved suffix.
+    #[allow(dead_code)]
+    pub fn reserve2(&self) -> &[u8; SUFFIX_RESERVE_LENGTH] {
+        &self.reserve2
+    }
+
+    /// The key with the same user key and reserves, which encodes back to
+    /// the bytes it was parsed from.
+    #[allow(dead_code)]
+    pub fn to_base_key(&self) -> BaseKey {
+        BaseKey {
+            reserve1: self.reserve1,
+            key: Bytes::copy_from_slice(&self.key_str),
+            reserve2: self.reserve2,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::slot_indexer::key_to_slot_id;
 
     #[test]
     fn mv_test_base_key_encode_and_decode() {
@@ -127,4 +216,95 @@ mod tests {
 
         assert_eq!(decode_key.key(), test_key);
     }
+
+    #[test]
+    fn test_base_key_embedded_separator() {
+        let test_keys: [&[u8]; 4] = [b"a\x00\x00b", b"\x00", b"a\x00\x01", b""];
+        for test_key in test_keys {
+            let encoded = BaseKey::new(test_key).encode().unwrap();
+            let decoded = ParsedBaseKey::new(&encoded).unwrap();
+            assert_eq!(decoded.key(), test_key);
+        }
+
+        // The encoded user key of "a" must not prefix the one of "a\x00b",
+        // or a prefix scan over the data keys of "a" would visit "a\x00b"
+        let mut short = BytesMut::new();
+        encode_user_key(b"a", &mut short).unwrap();
+        let mut long = BytesMut::new();
+        encode_user_key(b"a\x00b", &mut long).unwrap();
+        assert!(!long.starts_with(&short));
+    }
+
+    #[test]
+    fn test_base_key_slot_prefix() {
+        let test_key = b"{user}:1";
+        let slot_id = key_to_slot_id(b"user") as u16;
+
+        let encoded = BaseKey::with_slot_prefix(test_key).encode().unwrap();
+        assert_eq!(&encoded[..SLOT_ID_LENGTH], &slot_id.to_be_bytes());
+        let parsed = ParsedBaseKey::new(&encoded).unwrap();
+        assert_eq!(parsed.key(), test_key);
+        assert_eq!(parsed.slot_id(), slot_id);
+
+        let plain = BaseKey::new(test_key).encode().unwrap();
+        assert_eq!(ParsedBaseKey::new(&plain).unwrap().slot_id(), 0);
+
+        // The keys of a hash tag share the slot
+        let other = BaseKey::with_slot_prefix(b"{user}:2").encode().unwrap();
+        assert_eq!(&other[..SLOT_ID_LENGTH], &encoded[..SLOT_ID_LENGTH]);
+    }
+
+    #[test]
+    fn test_parsed_base_key_too_short() {
+        assert!(ParsedBaseKey::new(b"").is_err());
+        assert!(ParsedBaseKey::new(&[0u8; PREFIX_RESERVE_LENGTH + SUFFIX_RESERVE_LENGTH]).is_err());
+    }
+
+    #[test]
+    fn test_parsed_base_key_trailing_bytes() {
+        let mut encoded = BytesMut::new();
+        encoded.put_slice(&[0; PREFIX_RESERVE_LENGTH]);
+        encode_user_key(b"key", &mut encoded).unwrap();
+        encoded.put_slice(b"junk");
+        encoded.put_slice(&[0; SUFFIX_RESERVE_LENGTH]);
+        assert!(ParsedBaseKey::new(&encoded).is_err());
+    }
+
+    /// splitmix64, so the random keys are the same on every run
+    fn random_bits(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    #[test]
+    fn test_base_key_round_trip_random_keys() {
+        let mut state = 7;
+        for _ in 0..2000 {
+            let len = (random_bits(&mut state) % 40) as usize;
+            // half of the bytes are 0x00 or 0x01, the ones that get escaped
+            let key: Vec<u8> = (0..len)
+                .map(|_| match random_bits(&mut state) % 4 {
+                    0 => 0x00,
+                    1 => 0x01,
+                    _ => random_bits(&mut state) as u8,
+                })
+                .collect();
+            let reserve1 = random_bits(&mut state).to_be_bytes();
+
+            let encoded = BaseKey::with_reserve1(&key, reserve1).encode().unwrap();
+            let parsed = ParsedBaseKey::new(&encoded).unwrap();
+            assert_eq!(parsed.key(), &key[..]);
+            assert_eq!(parsed.reserve1(), &reserve1);
+            assert_eq!(parsed.reserve2(), &[0; SUFFIX_RESERVE_LENGTH]);
+            assert_eq!(
+                parsed.slot_id(),
+                u16::from_be_bytes([reserve1[0], reserve1[1]])
+            );
+            assert_eq!(parsed.to_base_key().encode().unwrap(), encoded);
+            assert_eq!(parsed.into_key(), key);
+        }
+    }
 }
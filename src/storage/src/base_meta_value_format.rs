@@ -18,18 +18,25 @@
  */
 
 use crate::{
-    base_value_format::{DataType, InternalValue, ParsedInternalValue},
-    delegate_internal_value, delegate_parsed_value,
-    error::{InvalidFormatSnafu, Result},
+    base_value_format::{
+        leading_data_type, DataType, InternalValue, ParsedInternalValue, ValueBuf,
+    },
+    checksum, delegate_internal_value, delegate_parsed_value,
+    error::{CorruptValueSnafu, Result},
+    format_version::{FormatMigrator, ValueLayout},
+    impl_value_format,
+    inline_collection_format::{InlineCollection, RESERVE_INLINE_FLAG},
     storage_define::{
-        BASE_META_VALUE_COUNT_LENGTH, BASE_META_VALUE_LENGTH, SUFFIX_RESERVE_LENGTH,
-        TIMESTAMP_LENGTH, TYPE_LENGTH, VERSION_LENGTH,
+        BASE_META_VALUE_COUNT_LENGTH, BASE_META_VALUE_LENGTH, EMPTY_META_GRACE_MICROS,
+        RESERVE_FLAGS_OFFSET, SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH, TYPE_LENGTH, VERSION_LENGTH,
     },
+    version_seq,
 };
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use chrono::Utc;
+use rocksdb::CompactionDecision;
 use snafu::ensure;
 use std::io::Cursor;
+use std::ops::Range;
 
 #[allow(dead_code)]
 type HashesMetaValue = BaseMetaValue;
@@ -45,50 +52,77 @@ type ZSetsMetaValue = BaseMetaValue;
 type ParsedZSetsMetaValue = ParsedBaseMetaValue;
 
 /*
- * | type | len | version | reserve | cdate | timestamp |
- * |  1B  | 8B  |    8B   |   16B   |   8B  |     8B    |
+ * | type | count | version | inline elements | reserve | cdate | timestamp |
+ * |  1B  |  8B   |    8B   |                 |   16B   |   8B  |     8B    |
+ *
+ * The inline elements are only present when the reserve flags have
+ * RESERVE_INLINE_FLAG, see inline_collection_format.rs
  */
 #[allow(dead_code)]
 pub struct BaseMetaValue {
     pub inner: InternalValue,
+    count: u64,
+    inline: Option<Bytes>,
 }
 
 delegate_internal_value!(BaseMetaValue);
+impl_value_format!(BaseMetaValue, ParsedBaseMetaValue);
 #[allow(dead_code)]
 impl BaseMetaValue {
-    pub fn new<T>(user_value: T) -> Self
-    where
-        T: Into<Bytes>,
-    {
+    pub fn new(count: u64) -> Self {
         Self {
-            inner: InternalValue::new(DataType::None, user_value),
+            inner: InternalValue::new(DataType::None, Bytes::new()),
+            count,
+            inline: None,
         }
     }
 
+    /// Stores the elements inline, or in data keys with `None`. The count
+    /// follows the inline collection
+    pub fn set_inline(&mut self, collection: Option<&InlineCollection>) {
+        self.inline = collection.map(|collection| {
+            self.count = collection.len() as u64;
+            let mut buf = BytesMut::with_capacity(collection.encoded_len());
+            collection.encode_into(&mut buf);
+            buf.freeze()
+        });
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.inline.is_some()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn set_count(&mut self, count: u64) {
+        self.count = count;
+    }
+
     pub fn update_version(&mut self) -> u64 {
-        let now = Utc::now().timestamp_micros() as u64;
-        self.inner.version = match self.inner.version >= now {
-            true => self.inner.version + 1,
-            false => now,
-        };
+        self.inner.version = version_seq::next_version(self.inner.version);
         self.inner.version
     }
 
-    fn encode(&self) -> BytesMut {
-        // type(1) + user_value + version(8) + reserve(16) + ctime(8) + etime(8)
-        let needed = TYPE_LENGTH
-            + self.inner.user_value.len()
-            + VERSION_LENGTH
-            + SUFFIX_RESERVE_LENGTH
-            + 2 * TIMESTAMP_LENGTH;
-        let mut buf = BytesMut::with_capacity(needed);
+    pub fn encode(&self) -> BytesMut {
+        let inline = self.inline.as_deref().unwrap_or_default();
+        let mut buf = BytesMut::with_capacity(BASE_META_VALUE_LENGTH + inline.len());
 
         buf.put_u8(self.inner.data_type as u8);
-        buf.extend_from_slice(&self.inner.user_value);
+        buf.put_u64_le(self.count);
         buf.put_u64_le(self.inner.version);
-        buf.extend_from_slice(&self.inner.reserve);
+        buf.put_slice(inline);
+        let reserve_start = buf.len();
+        let mut reserve = self.inner.reserve;
+        match self.inline {
+            Some(_) => reserve[RESERVE_FLAGS_OFFSET] |= RESERVE_INLINE_FLAG,
+            None => reserve[RESERVE_FLAGS_OFFSET] &= !RESERVE_INLINE_FLAG,
+        }
+        buf.extend_from_slice(&reserve);
         buf.put_u64_le(self.inner.ctime);
         buf.put_u64_le(self.inner.etime);
+        checksum::seal(&mut buf, reserve_start);
 
         buf
     }
@@ -98,6 +132,7 @@ impl BaseMetaValue {
 pub struct ParsedBaseMetaValue {
     inner: ParsedInternalValue,
     count: u64,
+    inline_range: Range<usize>,
 }
 
 delegate_parsed_value! {ParsedBaseMetaValue}
@@ -105,18 +140,20 @@ delegate_parsed_value! {ParsedBaseMetaValue}
 impl ParsedBaseMetaValue {
     pub fn new<T>(internal_value: T) -> Result<Self>
     where
-        T: Into<BytesMut>,
+        T: Into<ValueBuf>,
     {
-        let value: BytesMut = internal_value.into();
+        let value =
+            FormatMigrator::global().upgrade(ValueLayout::BaseMeta, internal_value.into())?;
         let value_len = value.len();
         ensure!(
             value_len >= BASE_META_VALUE_LENGTH,
-            InvalidFormatSnafu {
-                message: format!(
-                    "invalid meta value length: {} < {}",
-                    value.len(),
-                    BASE_META_VALUE_LENGTH,
-                )
+            CorruptValueSnafu {
+                data_type: leading_data_type(&value),
+                field: "meta value",
+                offset: 0usize,
+                expected: BASE_META_VALUE_LENGTH,
+                actual: value_len,
+                key: None::<String>,
             }
         );
 
@@ -128,12 +165,28 @@ impl ParsedBaseMetaValue {
         let count = val_reader.get_u64_le();
         let version = val_reader.get_u64_le();
 
-        let pos = val_reader.position() as usize;
-        let reserve_range = pos..pos + SUFFIX_RESERVE_LENGTH;
-        val_reader.advance(SUFFIX_RESERVE_LENGTH);
+        // The suffix is read from the end, the inline elements sit in between
+        let inline_start = val_reader.position() as usize;
+        let reserve_start = value_len - SUFFIX_RESERVE_LENGTH - 2 * TIMESTAMP_LENGTH;
+        let inline_range = inline_start..reserve_start;
+        let reserve_range = reserve_start..reserve_start + SUFFIX_RESERVE_LENGTH;
+        val_reader.set_position(reserve_range.end as u64);
 
         let ctime = val_reader.get_u64_le();
         let etime = val_reader.get_u64_le();
+        let inline = value[reserve_start + RESERVE_FLAGS_OFFSET] & RESERVE_INLINE_FLAG != 0;
+        ensure!(
+            inline || inline_range.is_empty(),
+            CorruptValueSnafu {
+                data_type,
+                field: "inline elements",
+                offset: inline_start,
+                expected: 0usize,
+                actual: inline_range.len(),
+                key: None::<String>,
+            }
+        );
+        checksum::verify(&value, reserve_range.start)?;
 
         Ok(Self {
             inner: ParsedInternalValue::new(
@@ -146,9 +199,22 @@ impl ParsedBaseMetaValue {
                 etime,
             ),
             count,
+            inline_range,
         })
     }
 
+    pub fn is_inline(&self) -> bool {
+        self.reserve()[RESERVE_FLAGS_OFFSET] & RESERVE_INLINE_FLAG != 0
+    }
+
+    /// The elements stored inline, `None` when they are in data keys
+    pub fn inline_collection(&self) -> Result<Option<InlineCollection>> {
+        if !self.is_inline() {
+            return Ok(None);
+        }
+        InlineCollection::decode(&self.inner.value[self.inline_range.clone()]).map(Some)
+    }
+
     pub fn initial_meta_value(&mut self) -> u64 {
         self.set_count(0);
         self.set_etime(0);
@@ -161,6 +227,7 @@ impl ParsedBaseMetaValue {
         let version_bytes = self.inner.version.to_le_bytes();
         let dst = &mut self.inner.value[suffix_start..suffix_start + VERSION_LENGTH];
         dst.copy_from_slice(&version_bytes);
+        checksum::refresh(&mut self.inner.value, self.inner.reserve_range.start);
     }
 
     fn set_ctime_to_value(&mut self) {
@@ -182,22 +249,20 @@ impl ParsedBaseMetaValue {
         let count_bytes = self.count.to_le_bytes();
         let dst = &mut self.inner.value[suffix_start..suffix_start + BASE_META_VALUE_COUNT_LENGTH];
         dst.copy_from_slice(&count_bytes);
+        checksum::refresh(&mut self.inner.value, self.inner.reserve_range.start);
     }
 
     pub fn is_valid(&self) -> bool {
         !self.inner.is_stale() && self.count != 0
     }
 
-    pub fn check_set_count(&self, count: usize) -> bool {
-        count <= u64::MAX as usize
-    }
-
     pub fn count(&self) -> u64 {
         self.count
     }
 
     pub fn set_count(&mut self, count: u64) {
         self.count = count;
+        self.set_count_to_value();
     }
 
     pub fn set_etime(&mut self, etime: u64) {
@@ -216,21 +281,59 @@ impl ParsedBaseMetaValue {
 
     pub fn modify_count(&mut self, delta: u64) {
         self.count = self.count.saturating_add(delta);
-        let count_bytes = self.count.to_le_bytes();
-        let dst = &mut self.inner.value[TYPE_LENGTH..TYPE_LENGTH + BASE_META_VALUE_COUNT_LENGTH];
-        dst.copy_from_slice(&count_bytes);
+        self.set_count_to_value();
     }
 
     pub fn update_version(&mut self) -> u64 {
-        let now = Utc::now().timestamp_micros() as u64;
-        self.inner.version = match self.inner.version >= now {
-            true => self.inner.version + 1,
-            false => now,
-        };
+        self.inner.version = version_seq::next_version(self.inner.version);
 
         self.set_version_to_value();
         self.inner.version
     }
+
+    pub fn set_data_type(&mut self, data_type: DataType) {
+        self.inner.data_type = data_type;
+        self.inner.value[0] = data_type as u8;
+        checksum::refresh(&mut self.inner.value, self.inner.reserve_range.start);
+    }
+
+    /// Removes a hash, set or sorted set whose etime has passed, or one that
+    /// is empty and was created more than `EMPTY_META_GRACE_MICROS` ago, as
+    /// left by an older version which did not delete the meta value along
+    /// with the last element
+    pub fn filter_decision(&self, cur_time: u64) -> CompactionDecision {
+        let expired = self.inner.etime != 0 && self.inner.etime < cur_time;
+        let empty =
+            self.count == 0 && self.inner.ctime.saturating_add(EMPTY_META_GRACE_MICROS) < cur_time;
+        if expired || empty {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+
+    /// Builds a fresh buffer from the current fields, including a count
+    /// that was only changed through `set_count`, and keeps the reserve.
+    pub fn encode(&self) -> BytesMut {
+        self.to_meta_value().encode()
+    }
+
+    /// A meta value with the current fields, to change what `encode` cannot
+    /// patch in place, such as the inline elements
+    pub fn to_meta_value(&self) -> BaseMetaValue {
+        let mut meta = BaseMetaValue::new(self.count);
+        meta.inner.data_type = self.inner.data_type;
+        meta.inner.version = self.inner.version;
+        meta.inner.ctime = self.inner.ctime;
+        meta.inner.etime = self.inner.etime;
+        meta.inner.reserve.copy_from_slice(self.inner.reserve());
+        if self.is_inline() {
+            meta.inline = Some(Bytes::copy_from_slice(
+                &self.inner.value[self.inline_range.clone()],
+            ));
+        }
+        meta
+    }
 }
 
 #[cfg(test)]
@@ -244,8 +347,7 @@ mod base_meta_value_tests {
     const TEST_ETIME: u64 = 1630000000;
 
     fn create_test_base_meta_value() -> BaseMetaValue {
-        let user_value = TEST_COUNT.to_le_bytes().to_vec();
-        let mut meta = BaseMetaValue::new(Bytes::from(user_value));
+        let mut meta = BaseMetaValue::new(TEST_COUNT);
         meta.inner.version = TEST_VERSION;
         meta.inner.ctime = TEST_CTIME;
         meta.inner.etime = TEST_ETIME;
@@ -256,10 +358,8 @@ mod base_meta_value_tests {
     fn test_base_meta_value_new() {
         let meta = create_test_base_meta_value();
         assert_eq!(meta.inner.data_type, DataType::None);
-
-        let mut buf = &meta.inner.user_value[..]; // &mut &[u8]
-        let user_value = buf.get_u64_le();
-        assert_eq!(user_value, TEST_COUNT);
+        assert_eq!(meta.count(), TEST_COUNT);
+        assert!(meta.inner.user_value.is_empty());
     }
 
     #[test]
@@ -269,7 +369,7 @@ mod base_meta_value_tests {
 
         let mut expected = BytesMut::new();
         expected.put_u8(DataType::None as u8);
-        expected.put_u64_le(TEST_COUNT); // count -> user_value
+        expected.put_u64_le(TEST_COUNT);
         expected.put_u64_le(TEST_VERSION);
         expected.extend_from_slice(&vec![0u8; SUFFIX_RESERVE_LENGTH]); // reserve
         expected.put_u64_le(TEST_CTIME);
@@ -283,11 +383,10 @@ mod base_meta_value_tests {
         let mut meta = create_test_base_meta_value();
         meta.inner.version = 0;
 
-        let now = Utc::now().timestamp_micros() as u64;
         let new_version = meta.update_version();
-
-        assert!(new_version >= now);
+        assert!(new_version > 0);
         assert_eq!(meta.inner.version, new_version);
+        assert!(meta.update_version() > new_version);
     }
 
     #[test]
@@ -297,7 +396,7 @@ mod base_meta_value_tests {
 
         let new_version = meta.update_version();
         assert_eq!(new_version, meta.inner.version);
-        assert_eq!(new_version, 10000000000000001); // version should be +1
+        assert!(new_version > 10000000000000000); // never goes back
     }
 
     #[test]
@@ -317,6 +416,38 @@ mod base_meta_value_tests {
         assert_eq!(parsed.inner.version, TEST_VERSION);
         assert_eq!(parsed.inner.ctime, TEST_CTIME);
         assert_eq!(parsed.inner.etime, TEST_ETIME);
+        assert_eq!(parsed.count(), TEST_COUNT);
+        assert_eq!(parsed.user_value_slice(), TEST_COUNT.to_le_bytes());
+    }
+
+    #[test]
+    fn test_base_meta_value_set_count_roundtrip() {
+        for count in [0, 1, u32::MAX as u64 + 1, u64::MAX] {
+            let mut meta = create_test_base_meta_value();
+            meta.set_count(count);
+            let encoded = meta.encode();
+            assert_eq!(encoded.len(), BASE_META_VALUE_LENGTH);
+
+            let parsed = ParsedBaseMetaValue::new(encoded).unwrap();
+            assert_eq!(parsed.count(), count);
+            assert_eq!(parsed.inner.version, TEST_VERSION);
+            assert_eq!(parsed.inner.ctime, TEST_CTIME);
+            assert_eq!(parsed.inner.etime, TEST_ETIME);
+        }
+    }
+
+    #[test]
+    fn test_base_meta_value_checksum() {
+        checksum::with_checksum(|| {
+            let mut parsed =
+                ParsedBaseMetaValue::new(create_test_base_meta_value().encode()).unwrap();
+            parsed.initial_meta_value();
+            assert!(ParsedBaseMetaValue::new(parsed.inner.value.clone()).is_ok());
+
+            let mut corrupted = parsed.inner.value.clone();
+            corrupted[TYPE_LENGTH] ^= 0xff;
+            assert!(ParsedBaseMetaValue::new(corrupted).is_err());
+        });
     }
 }
 
@@ -362,8 +493,26 @@ mod parsed_base_meta_value_tests {
         buf.put_u8(DataType::Hash as u8);
         buf.put_u64_le(TEST_COUNT);
 
-        let meta = ParsedBaseMetaValue::new(buf);
-        assert!(meta.is_err());
+        let err = ParsedBaseMetaValue::new(buf).err().unwrap();
+        assert!(matches!(
+            err,
+            crate::error::Error::CorruptValue {
+                data_type: DataType::Hash,
+                field: "meta value",
+                offset: 0,
+                expected: BASE_META_VALUE_LENGTH,
+                actual: 9,
+                key: None,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.with_key(b"h").to_string(),
+            format!(
+                "Corrupt hash value of key 'h': meta value at byte 0 needs \
+                 {BASE_META_VALUE_LENGTH} bytes, found 9"
+            )
+        );
     }
 
     #[test]
@@ -371,9 +520,8 @@ mod parsed_base_meta_value_tests {
         let buf = build_test_buffer();
         let mut meta = ParsedBaseMetaValue::new(buf).unwrap();
 
-        let now = Utc::now().timestamp_micros() as u64;
         let new_version = meta.update_version();
-        assert!(new_version >= now);
+        assert!(new_version > 0);
 
         let suffix_start = TYPE_LENGTH + BASE_META_VALUE_COUNT_LENGTH;
         let stored_version =
@@ -429,14 +577,39 @@ mod parsed_base_meta_value_tests {
         assert_eq!(stored_count, TEST_COUNT + delta);
     }
 
+    #[test]
+    fn test_parsed_base_meta_value_modify_count_checksum() {
+        checksum::with_checksum(|| {
+            // Sealed with a checksum, which the in place update must follow
+            let encoded = BaseMetaValue::new(TEST_COUNT).encode();
+            let mut meta = ParsedBaseMetaValue::new(encoded).unwrap();
+            meta.modify_count(10);
+
+            let reparsed = ParsedBaseMetaValue::new(meta.inner.value.clone()).unwrap();
+            assert_eq!(reparsed.count(), TEST_COUNT + 10);
+        });
+    }
+
+    #[test]
+    fn test_parsed_base_meta_value_set_count() {
+        checksum::with_checksum(|| {
+            let encoded = BaseMetaValue::new(TEST_COUNT).encode();
+            let mut meta = ParsedBaseMetaValue::new(encoded).unwrap();
+            meta.set_count(u64::MAX);
+
+            let reparsed = ParsedBaseMetaValue::new(meta.inner.value.clone()).unwrap();
+            assert_eq!(reparsed.count(), u64::MAX);
+        });
+    }
+
     #[test]
     fn test_check_modify_count_overflow() {
         let buf = build_test_buffer();
         let mut meta = ParsedBaseMetaValue::new(buf).unwrap();
 
         meta.count = u64::MAX - 1;
-        assert!(meta.check_modify_count(1)); // 4294967294 + 1 = 4294967295 (u32::MAX)
-        assert!(!meta.check_modify_count(2)); // 4294967294 + 2 = overflow
+        assert!(meta.check_modify_count(1)); // u64::MAX - 1 + 1 = u64::MAX
+        assert!(!meta.check_modify_count(2)); // u64::MAX - 1 + 2 = overflow
     }
 
     #[test]
@@ -447,26 +620,122 @@ mod parsed_base_meta_value_tests {
     }
 
     #[test]
-    fn test_parsed_base_meta_value_check_set_count() {
-        assert!(ParsedBaseMetaValue::new(build_test_buffer())
-            .unwrap()
-            .check_set_count(100));
+    fn test_parsed_base_meta_value_encode() {
+        let buf = build_test_buffer();
+        let meta = ParsedBaseMetaValue::new(buf.clone()).unwrap();
+        assert_eq!(meta.encode(), buf);
 
-        let safe_max = if usize::BITS >= 64 {
-            u64::MAX as usize
-        } else {
-            usize::MAX
-        };
-        assert!(ParsedBaseMetaValue::new(build_test_buffer())
-            .unwrap()
-            .check_set_count(safe_max));
+        let mut meta = ParsedBaseMetaValue::new(buf).unwrap();
+        meta.set_count(7);
+        meta.set_data_type(DataType::Set);
+        let version = meta.update_version();
+
+        let reparsed = ParsedBaseMetaValue::new(meta.encode()).unwrap();
+        assert_eq!(reparsed.inner.data_type, DataType::Set);
+        assert_eq!(reparsed.count(), 7);
+        assert_eq!(reparsed.version(), version);
+        assert_eq!(reparsed.ctime(), TEST_CTIME);
+        assert_eq!(reparsed.etime(), TEST_ETIME);
+    }
+
+    #[test]
+    fn test_parsed_base_meta_value_encode_checksum() {
+        checksum::with_checksum(|| {
+            let mut meta = ParsedBaseMetaValue::new(build_test_buffer()).unwrap();
+            meta.set_count(7);
+            meta.set_data_type(DataType::ZSet);
+            assert!(ParsedBaseMetaValue::new(meta.inner.value.clone()).is_ok());
+
+            let encoded = meta.encode();
+            let reparsed = ParsedBaseMetaValue::new(encoded).unwrap();
+            assert_eq!(reparsed.count(), 7);
+            assert_eq!(reparsed.inner.data_type, DataType::ZSet);
+        });
+    }
 
-        let expected_result = usize::BITS < 64;
+    fn inline_collection() -> InlineCollection {
+        let mut collection = InlineCollection::new();
+        collection.insert(b"field1", b"value1");
+        collection.insert(b"field2", b"");
+        collection
+    }
+
+    #[test]
+    fn test_inline_meta_value_roundtrip() {
+        let mut meta = BaseMetaValue::new(0);
+        meta.inner.data_type = DataType::Hash;
+        meta.inner.version = TEST_VERSION;
+        meta.inner.ctime = TEST_CTIME;
+        meta.set_inline(Some(&inline_collection()));
+        assert!(meta.is_inline());
+
+        let encoded = meta.encode();
+        assert_eq!(
+            encoded.len(),
+            BASE_META_VALUE_LENGTH + inline_collection().encoded_len()
+        );
+
+        let parsed = ParsedBaseMetaValue::new(encoded).unwrap();
+        assert!(parsed.is_inline());
+        assert_eq!(parsed.count(), 2);
+        assert_eq!(parsed.version(), TEST_VERSION);
+        assert_eq!(parsed.ctime(), TEST_CTIME);
+        assert_eq!(parsed.etime(), 0);
+        assert_eq!(
+            parsed.inline_collection().unwrap(),
+            Some(inline_collection())
+        );
+
+        // Spilling to data keys clears the flag and the elements
+        let mut spread = parsed.to_meta_value();
+        spread.set_inline(None);
+        let spread = ParsedBaseMetaValue::new(spread.encode()).unwrap();
+        assert!(!spread.is_inline());
+        assert_eq!(spread.count(), 2);
+        assert_eq!(spread.inline_collection().unwrap(), None);
+        assert_eq!(spread.inner.value.len(), BASE_META_VALUE_LENGTH);
+    }
+
+    #[test]
+    fn test_inline_meta_value_in_place_updates() {
+        let mut meta = BaseMetaValue::new(0);
+        meta.inner.data_type = DataType::Set;
+        meta.set_inline(Some(&inline_collection()));
+
+        let mut parsed = ParsedBaseMetaValue::new(meta.encode()).unwrap();
+        parsed.set_etime(TEST_ETIME);
+        parsed.set_ctime(TEST_CTIME);
+        let version = parsed.update_version();
+
+        let reparsed = ParsedBaseMetaValue::new(parsed.encode()).unwrap();
+        assert_eq!(reparsed.etime(), TEST_ETIME);
+        assert_eq!(reparsed.ctime(), TEST_CTIME);
+        assert_eq!(reparsed.version(), version);
         assert_eq!(
-            ParsedBaseMetaValue::new(build_test_buffer())
-                .unwrap()
-                .check_set_count(usize::MAX),
-            !expected_result
+            reparsed.inline_collection().unwrap(),
+            Some(inline_collection())
         );
     }
+
+    #[test]
+    fn test_inline_meta_value_checksum() {
+        checksum::with_checksum(|| {
+            let mut meta = BaseMetaValue::new(0);
+            meta.inner.data_type = DataType::Hash;
+            meta.set_inline(Some(&inline_collection()));
+            let encoded = meta.encode();
+            assert!(ParsedBaseMetaValue::new(encoded.clone()).is_ok());
+
+            let mut corrupted = encoded.clone();
+            corrupted[BASE_META_VALUE_COUNT_LENGTH + VERSION_LENGTH + 8] ^= 0xff;
+            assert!(ParsedBaseMetaValue::new(corrupted).is_err());
+        });
+    }
+
+    #[test]
+    fn test_parsed_base_meta_value_rejects_trailing_bytes() {
+        let mut buf = build_test_buffer();
+        buf.put_u8(0);
+        assert!(ParsedBaseMetaValue::new(buf).is_err());
+    }
 }
@@ -17,11 +17,15 @@
  * limitations under the License.
  */
 
+use crate::clock;
 use crate::error::{Error, InvalidFormatSnafu, Result};
-use bytes::{BufMut, Bytes, BytesMut};
-use chrono::Utc;
+use crate::format_version::FORMAT_VERSION;
+use crate::storage_define::{RESERVE_VERSION_OFFSET, SUFFIX_RESERVE_LENGTH};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use snafu::OptionExt;
-use std::ops::Range;
+use std::num::NonZeroU64;
+use std::ops::{Deref, DerefMut, Range};
+use std::time::Duration;
 
 /// TODO: remove allow dead code
 #[allow(dead_code)]
@@ -34,6 +38,7 @@ pub enum DataType {
     ZSet = 4,
     None = 5,
     All = 6,
+    Stream = 7,
 }
 
 // TODO: use unified Result
@@ -49,6 +54,7 @@ impl TryFrom<u8> for DataType {
             4 => Ok(DataType::ZSet),
             5 => Ok(DataType::None),
             6 => Ok(DataType::All),
+            7 => Ok(DataType::Stream),
             _ => InvalidFormatSnafu {
                 message: format!("Invalid data type byte: {value}"),
             }
@@ -59,10 +65,12 @@ impl TryFrom<u8> for DataType {
 
 /// TODO: remove allow dead code
 #[allow(dead_code)]
-pub const DATA_TYPE_STRINGS: [&str; 7] = ["string", "hash", "set", "list", "zset", "none", "all"];
+pub const DATA_TYPE_STRINGS: [&str; 8] = [
+    "string", "hash", "set", "list", "zset", "none", "all", "stream",
+];
 /// TODO: remove allow dead code
 #[allow(dead_code)]
-pub const DATA_TYPE_TAG: [char; 7] = ['k', 'h', 's', 'l', 'z', 'n', 'a'];
+pub const DATA_TYPE_TAG: [char; 8] = ['k', 'h', 's', 'l', 'z', 'n', 'a', 'x'];
 
 /// TODO: remove allow dead code
 #[allow(dead_code)]
@@ -70,6 +78,15 @@ pub fn data_type_to_string(data_type: DataType) -> &'static str {
     DATA_TYPE_STRINGS[data_type as usize]
 }
 
+/// The data type a meta value starts with, `DataType::None` when the value
+/// is empty or starts with an unknown type
+pub fn leading_data_type(value: &[u8]) -> DataType {
+    value
+        .first()
+        .and_then(|byte| DataType::try_from(*byte).ok())
+        .unwrap_or(DataType::None)
+}
+
 /// TODO: remove allow dead code
 #[allow(dead_code)]
 pub fn data_type_to_tag(data_type: DataType) -> char {
@@ -85,21 +102,29 @@ pub struct InternalValue {
     pub version: u64,
     pub etime: u64,
     pub ctime: u64,
-    pub reserve: [u8; 16],
+    pub reserve: [u8; SUFFIX_RESERVE_LENGTH],
 }
 
 impl InternalValue {
+    /// A value that never expires, created now
     pub fn new<T>(data_type: DataType, user_value: T) -> Self
     where
         T: Into<Bytes>,
     {
-        Self {
+        Self::builder(data_type, user_value).persistent().build()
+    }
+
+    /// Starts building a value, see `InternalValueBuilder`
+    pub fn builder<T>(data_type: DataType, user_value: T) -> InternalValueBuilder<ExpirationUnset>
+    where
+        T: Into<Bytes>,
+    {
+        InternalValueBuilder {
             data_type,
             user_value: user_value.into(),
             version: 0,
-            etime: 0,
-            ctime: Utc::now().timestamp_micros() as u64,
-            reserve: [0; 16],
+            ctime: None,
+            expiration: ExpirationUnset,
         }
     }
 
@@ -116,7 +141,7 @@ impl InternalValue {
     }
 
     pub fn set_relative_etime(&mut self, ttl: u64) -> Result<()> {
-        let current_micros = Utc::now().timestamp_micros() as u64;
+        let current_micros = clock::now_micros();
         self.etime = current_micros
             .checked_add(ttl)
             .context(InvalidFormatSnafu {
@@ -124,6 +149,96 @@ impl InternalValue {
             })?;
         Ok(())
     }
+
+    pub fn is_stale(&self) -> bool {
+        if self.etime == 0 {
+            return false;
+        }
+        let current_micros = clock::now_micros();
+        self.etime < current_micros
+    }
+}
+
+/// The expiration of an `InternalValueBuilder` is not chosen yet
+#[derive(Debug)]
+pub struct ExpirationUnset;
+
+/// The etime chosen for an `InternalValueBuilder`, 0 for never
+#[derive(Debug)]
+pub struct Expiration(u64);
+
+/// Builds an `InternalValue`. The expiration must be chosen, with
+/// `persistent`, `expire_at` or `expire_in`, before `build` is available,
+/// so no value is built with an etime nobody set. The ctime is the time of
+/// `build` unless given.
+#[derive(Debug)]
+pub struct InternalValueBuilder<E> {
+    data_type: DataType,
+    user_value: Bytes,
+    version: u64,
+    ctime: Option<u64>,
+    expiration: E,
+}
+
+impl<E> InternalValueBuilder<E> {
+    pub fn version(mut self, version: u64) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// The creation time (in microseconds)
+    pub fn ctime(mut self, ctime: u64) -> Self {
+        self.ctime = Some(ctime);
+        self
+    }
+
+    fn with_etime(self, etime: u64) -> InternalValueBuilder<Expiration> {
+        InternalValueBuilder {
+            data_type: self.data_type,
+            user_value: self.user_value,
+            version: self.version,
+            ctime: self.ctime,
+            expiration: Expiration(etime),
+        }
+    }
+}
+
+impl InternalValueBuilder<ExpirationUnset> {
+    /// The value never expires
+    pub fn persistent(self) -> InternalValueBuilder<Expiration> {
+        self.with_etime(0)
+    }
+
+    /// The value expires at `etime` (in microseconds since the epoch)
+    pub fn expire_at(self, etime: NonZeroU64) -> InternalValueBuilder<Expiration> {
+        self.with_etime(etime.get())
+    }
+
+    /// The value expires `ttl` from now
+    pub fn expire_in(self, ttl: Duration) -> Result<InternalValueBuilder<Expiration>> {
+        let etime = u64::try_from(ttl.as_micros())
+            .ok()
+            .and_then(|ttl| clock::now_micros().checked_add(ttl))
+            .context(InvalidFormatSnafu {
+                message: "Timestamp overflow when calculating relative etime".to_string(),
+            })?;
+        Ok(self.with_etime(etime))
+    }
+}
+
+impl InternalValueBuilder<Expiration> {
+    pub fn build(self) -> InternalValue {
+        let mut reserve = [0; SUFFIX_RESERVE_LENGTH];
+        reserve[RESERVE_VERSION_OFFSET] = FORMAT_VERSION;
+        InternalValue {
+            data_type: self.data_type,
+            user_value: self.user_value,
+            version: self.version,
+            etime: self.expiration.0,
+            ctime: self.ctime.unwrap_or_else(clock::now_micros),
+            reserve,
+        }
+    }
 }
 
 /// This macro is used to forward the base function to the structure
@@ -155,10 +270,123 @@ macro_rules! delegate_internal_value {
     };
 }
 
+/// The encoded bytes of a parsed value.
+///
+/// Parsing keeps the bytes it is given as a shared `Bytes`, so a caller that
+/// only reads the value (GET, compaction filters) never copies it. The bytes
+/// are copied into a mutable buffer the first time a setter writes to them,
+/// through `DerefMut`.
+#[derive(Debug, Clone)]
+pub enum ValueBuf {
+    Shared(Bytes),
+    Owned(BytesMut),
+}
+
+#[allow(dead_code)]
+impl ValueBuf {
+    pub fn is_shared(&self) -> bool {
+        matches!(self, ValueBuf::Shared(_))
+    }
+
+    /// The mutable buffer, copying the shared bytes on first use
+    pub fn make_mut(&mut self) -> &mut BytesMut {
+        if let ValueBuf::Shared(bytes) = self {
+            *self = ValueBuf::Owned(BytesMut::from(&bytes[..]));
+        }
+        match self {
+            ValueBuf::Owned(buf) => buf,
+            ValueBuf::Shared(_) => unreachable!(),
+        }
+    }
+
+    /// Drops the first n bytes, without copying shared bytes
+    pub fn advance(&mut self, n: usize) {
+        match self {
+            ValueBuf::Shared(bytes) => bytes.advance(n),
+            ValueBuf::Owned(buf) => buf.advance(n),
+        }
+    }
+
+    /// Keeps the first len bytes, without copying shared bytes
+    pub fn truncate(&mut self, len: usize) {
+        match self {
+            ValueBuf::Shared(bytes) => bytes.truncate(len),
+            ValueBuf::Owned(buf) => buf.truncate(len),
+        }
+    }
+
+    pub fn freeze(self) -> Bytes {
+        match self {
+            ValueBuf::Shared(bytes) => bytes,
+            ValueBuf::Owned(buf) => buf.freeze(),
+        }
+    }
+}
+
+impl Deref for ValueBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ValueBuf::Shared(bytes) => bytes,
+            ValueBuf::Owned(buf) => buf,
+        }
+    }
+}
+
+impl DerefMut for ValueBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.make_mut()
+    }
+}
+
+impl AsRef<[u8]> for ValueBuf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Bytes> for ValueBuf {
+    fn from(bytes: Bytes) -> Self {
+        ValueBuf::Shared(bytes)
+    }
+}
+
+impl From<BytesMut> for ValueBuf {
+    fn from(buf: BytesMut) -> Self {
+        ValueBuf::Shared(buf.freeze())
+    }
+}
+
+impl From<Vec<u8>> for ValueBuf {
+    fn from(vec: Vec<u8>) -> Self {
+        ValueBuf::Shared(Bytes::from(vec))
+    }
+}
+
+/// A borrowed slice cannot be shared, it is copied once
+impl From<&[u8]> for ValueBuf {
+    fn from(slice: &[u8]) -> Self {
+        ValueBuf::Shared(Bytes::copy_from_slice(slice))
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for ValueBuf {
+    fn from(slice: &[u8; N]) -> Self {
+        ValueBuf::Shared(Bytes::copy_from_slice(slice))
+    }
+}
+
+impl<T: AsRef<[u8]>> PartialEq<T> for ValueBuf {
+    fn eq(&self, other: &T) -> bool {
+        self[..] == *other.as_ref()
+    }
+}
+
 /// TODO: remove allow dead code
 #[allow(dead_code)]
 pub struct ParsedInternalValue {
-    pub value: BytesMut,
+    pub value: ValueBuf,
     pub data_type: DataType,
     /// When used to represent MetaValue, the 'user_value' field is decoded to 'count' or 'len'.
     pub user_value_range: Range<usize>,
@@ -171,7 +399,7 @@ pub struct ParsedInternalValue {
 #[allow(dead_code)]
 impl ParsedInternalValue {
     pub fn new(
-        value: BytesMut,
+        value: ValueBuf,
         data_type: DataType,
         user_value_range: Range<usize>,
         reserve_range: Range<usize>,
@@ -190,6 +418,11 @@ impl ParsedInternalValue {
         }
     }
 
+    /// The user value, without copying
+    pub fn user_value_slice(&self) -> &[u8] {
+        &self.value[self.user_value_range.clone()]
+    }
+
     /// When used to represent MetaValue, this function will not be called
     pub fn user_value(&self) -> BytesMut {
         let slice = &self.value[self.user_value_range.clone()];
@@ -202,6 +435,10 @@ impl ParsedInternalValue {
         self.version
     }
 
+    pub fn reserve(&self) -> &[u8] {
+        &self.value[self.reserve_range.clone()]
+    }
+
     pub fn ctime(&self) -> u64 {
         self.ctime
     }
@@ -218,7 +455,7 @@ impl ParsedInternalValue {
         if self.etime == 0 {
             return false;
         }
-        let current_micros = Utc::now().timestamp_micros() as u64;
+        let current_micros = clock::now_micros();
         self.etime < current_micros
     }
 
@@ -258,10 +495,108 @@ macro_rules! delegate_parsed_value {
                 self.inner.user_value()
             }
 
+            #[allow(dead_code)]
+            pub fn user_value_slice(&self) -> &[u8] {
+                self.inner.user_value_slice()
+            }
+
             #[allow(dead_code)]
             pub fn version(&self) -> u64 {
                 self.inner.version()
             }
+
+            #[allow(dead_code)]
+            pub fn reserve(&self) -> &[u8] {
+                self.inner.reserve()
+            }
+        }
+
+        impl $crate::base_value_format::ParsedValue for $struct_name {
+            fn data_type(&self) -> $crate::base_value_format::DataType {
+                self.inner.data_type
+            }
+
+            fn is_stale(&self) -> bool {
+                self.inner.is_stale()
+            }
+
+            fn etime(&self) -> u64 {
+                self.inner.etime()
+            }
+
+            fn ctime(&self) -> u64 {
+                self.inner.ctime()
+            }
+
+            fn version(&self) -> u64 {
+                self.inner.version()
+            }
+
+            fn user_value_slice(&self) -> &[u8] {
+                self.inner.user_value_slice()
+            }
+        }
+    };
+}
+
+/// A value that can be encoded into its on-disk bytes and parsed back, so that
+/// filters, dump tools and debug commands can handle any value type the same way.
+pub trait ValueFormat {
+    /// The parsed view of the encoded bytes.
+    type Parsed: ParsedValue;
+
+    fn encode(&self) -> BytesMut;
+
+    fn parse<T>(encoded: T) -> Result<Self::Parsed>
+    where
+        T: Into<ValueBuf>;
+
+    fn data_type(&self) -> DataType;
+
+    fn is_stale(&self) -> bool;
+}
+
+/// The common accessors of a parsed value, implemented by `delegate_parsed_value`.
+pub trait ParsedValue {
+    fn data_type(&self) -> DataType;
+
+    fn is_stale(&self) -> bool;
+
+    fn etime(&self) -> u64;
+
+    fn ctime(&self) -> u64;
+
+    fn version(&self) -> u64;
+
+    fn user_value_slice(&self) -> &[u8];
+}
+
+/// This macro implements `ValueFormat` for a value built on `InternalValue`
+/// through its inherent `encode` and the `new` of its parsed type.
+#[macro_export]
+macro_rules! impl_value_format {
+    ($struct_name:ident, $parsed_name:ident) => {
+        impl $crate::base_value_format::ValueFormat for $struct_name {
+            type Parsed = $parsed_name;
+
+            fn encode(&self) -> BytesMut {
+                $struct_name::encode(self)
+            }
+
+            fn parse<T>(encoded: T) -> Result<$parsed_name>
+            where
+                T: Into<$crate::base_value_format::ValueBuf>,
+            {
+                $parsed_name::new(encoded)
+            }
+
+            fn data_type(&self) -> $crate::base_value_format::DataType {
+                self.inner.data_type
+            }
+
+            fn is_stale(&self) -> bool {
+                self.inner.is_stale()
+            }
         }
     };
 }
@@ -270,6 +605,40 @@ macro_rules! delegate_parsed_value {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_internal_value_builder() {
+        let clock = std::sync::Arc::new(crate::clock::MockClock::new(1_000_000));
+        crate::clock::with_clock(clock, || {
+            let value = InternalValue::builder(DataType::String, "v")
+                .version(7)
+                .persistent()
+                .build();
+            assert_eq!(value.version, 7);
+            assert_eq!(value.etime, 0);
+            assert_eq!(value.ctime, 1_000_000);
+            assert_eq!(value.reserve[RESERVE_VERSION_OFFSET], FORMAT_VERSION);
+            assert!(!value.is_stale());
+
+            let value = InternalValue::builder(DataType::String, "v")
+                .expire_in(Duration::from_secs(2))
+                .unwrap()
+                .ctime(5)
+                .build();
+            assert_eq!(value.etime, 3_000_000);
+            assert_eq!(value.ctime, 5);
+
+            let value = InternalValue::builder(DataType::Hash, "v")
+                .expire_at(NonZeroU64::new(500_000).unwrap())
+                .build();
+            assert_eq!(value.etime, 500_000);
+            assert!(value.is_stale());
+
+            assert!(InternalValue::builder(DataType::String, "v")
+                .expire_in(Duration::MAX)
+                .is_err());
+        });
+    }
+
     #[test]
     fn test_data_type_to_string() {
         assert_eq!(data_type_to_string(DataType::String), "string");
@@ -279,6 +648,7 @@ mod tests {
         assert_eq!(data_type_to_string(DataType::ZSet), "zset");
         assert_eq!(data_type_to_string(DataType::None), "none");
         assert_eq!(data_type_to_string(DataType::All), "all");
+        assert_eq!(data_type_to_string(DataType::Stream), "stream");
     }
 
     #[test]
@@ -290,5 +660,66 @@ mod tests {
         assert_eq!(data_type_to_tag(DataType::ZSet), 'z');
         assert_eq!(data_type_to_tag(DataType::None), 'n');
         assert_eq!(data_type_to_tag(DataType::All), 'a');
+        assert_eq!(data_type_to_tag(DataType::Stream), 'x');
+    }
+
+    #[test]
+    fn test_value_buf_copy_on_write() {
+        let bytes = Bytes::from(b"kiwi-rs".to_vec());
+        let mut value = ValueBuf::from(bytes.clone());
+        assert!(value.is_shared());
+        assert_eq!(value.as_ptr(), bytes.as_ptr());
+
+        value.advance(1);
+        value.truncate(3);
+        assert!(value.is_shared());
+        assert_eq!(value, b"iwi");
+
+        value[0] = b'I';
+        assert!(!value.is_shared());
+        assert_eq!(value, b"Iwi");
+        assert_eq!(&bytes[..], b"kiwi-rs");
+    }
+
+    #[test]
+    fn test_value_buf_from_vec_is_shared() {
+        let vec = b"kiwi-rs".to_vec();
+        let ptr = vec.as_ptr();
+        let value = ValueBuf::from(vec);
+        assert!(value.is_shared());
+        assert_eq!(value.as_ptr(), ptr);
+        assert_eq!(value.freeze(), Bytes::from_static(b"kiwi-rs"));
+    }
+
+    fn roundtrip<V: ValueFormat>(value: V) -> V::Parsed {
+        let parsed = V::parse(value.encode()).unwrap();
+        assert_eq!(parsed.data_type(), value.data_type());
+        assert_eq!(parsed.is_stale(), value.is_stale());
+        parsed
+    }
+
+    #[test]
+    fn test_value_format_roundtrip() {
+        use crate::base_data_value_format::BaseDataValue;
+        use crate::base_meta_value_format::BaseMetaValue;
+        use crate::list_meta_value_format::ListsMetaValue;
+        use crate::strings_value_format::StringValue;
+
+        let parsed = roundtrip(StringValue::new("value"));
+        assert_eq!(parsed.user_value_slice(), b"value");
+
+        let mut meta = BaseMetaValue::new(3);
+        meta.inner.data_type = DataType::Hash;
+        meta.set_version(7);
+        let parsed = roundtrip(meta);
+        assert_eq!(ParsedValue::version(&parsed), 7);
+
+        let mut list = ListsMetaValue::new(Bytes::copy_from_slice(&0u64.to_le_bytes()));
+        list.set_etime(1);
+        assert!(list.is_stale());
+        roundtrip(list);
+
+        let parsed = roundtrip(BaseDataValue::new("field-value"));
+        assert_eq!(ParsedValue::etime(&parsed), 0);
     }
 }
@@ -0,0 +1,247 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![cfg_attr(not(test), allow(dead_code))]
+
+use crate::{
+    error::{InvalidFormatSnafu, Result},
+    hashes_data_key_format::{HashesDataKey, ParsedHashesDataKey},
+};
+use bytes::{Buf, BufMut};
+use snafu::ensure;
+
+/// Bytes of a bitmap per segment, the last segment may be shorter
+pub const BITMAP_SEGMENT_BYTES: u64 = 1024;
+
+const U64_LEN: usize = 8;
+const HEADER_LENGTH: usize = 2 * U64_LEN;
+
+/*
+ * A bitmap written by SETBIT is a string value with STRING_SEGMENTED_FLAG
+ * whose value is the header
+ * | length | version |
+ * |   8B   |    8B   |
+ * where the length is the byte length of the whole bitmap. Its bytes are
+ * cut in segments of BITMAP_SEGMENT_BYTES, segment i is stored in
+ * bitmap_data_cf under the hash data key layout with the big-endian index as
+ * the field
+ * | reserve1 | key | version | index | reserve2 |
+ * |    8B    |     |    8B   |   8B  |   16B    |
+ * and its bytes as a base data value. A missing segment is all zeros, so
+ * SETBIT and BITCOUNT only read and write the segments they cover.
+ */
+
+/// The value of the meta key of a bitmap stored in segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentedBitmapHeader {
+    /// The byte length of the bitmap, as returned by STRLEN and GET
+    pub len: u64,
+    /// The version of the segments, a new bitmap gets a new version so the
+    /// segments of a deleted one are never read
+    pub version: u64,
+}
+
+impl SegmentedBitmapHeader {
+    pub fn new(version: u64) -> Self {
+        Self { len: 0, version }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut dst = Vec::with_capacity(HEADER_LENGTH);
+        dst.put_u64_le(self.len);
+        dst.put_u64_le(self.version);
+        dst
+    }
+
+    pub fn decode(mut src: &[u8]) -> Result<Self> {
+        ensure!(
+            src.len() == HEADER_LENGTH,
+            InvalidFormatSnafu {
+                message: format!("invalid segmented bitmap header length: {}", src.len()),
+            }
+        );
+        Ok(Self {
+            len: src.get_u64_le(),
+            version: src.get_u64_le(),
+        })
+    }
+
+    /// The number of segments covering the bitmap
+    pub fn segment_count(&self) -> u64 {
+        self.len.div_ceil(BITMAP_SEGMENT_BYTES)
+    }
+
+    /// Grows the bitmap to hold the byte at `pos`
+    pub fn extend_to(&mut self, pos: BitPosition) {
+        self.len = self.len.max(pos.byte_offset() + 1);
+    }
+}
+
+/// The key of one segment of a bitmap.
+pub struct BitmapSegmentKey(HashesDataKey);
+
+impl BitmapSegmentKey {
+    pub fn new(key: &[u8], version: u64, index: u64) -> Self {
+        Self(HashesDataKey::new(key, version, &index.to_be_bytes()))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        self.0.encode()
+    }
+
+    /// Encodes the key without reserve2, to seek the first segment at or
+    /// after `index`
+    pub fn encode_seek_key(&self) -> Result<Vec<u8>> {
+        self.0.encode_seek_key()
+    }
+}
+
+/// Decodes the index of a segment key.
+pub fn decode_segment_index(key: &[u8]) -> Result<u64> {
+    let parsed = ParsedHashesDataKey::decode(key)?;
+    let index: [u8; U64_LEN] =
+        parsed
+            .field()
+            .try_into()
+            .map_err(|_| crate::error::Error::InvalidFormat {
+                message: format!(
+                    "invalid bitmap segment index length: {}",
+                    parsed.field().len()
+                ),
+                location: snafu::location!(),
+            })?;
+    Ok(u64::from_be_bytes(index))
+}
+
+/// Where a bit offset of SETBIT and GETBIT falls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitPosition {
+    pub segment: u64,
+    /// The byte within the segment
+    pub byte: usize,
+    /// The bit within the byte, bit 0 is the most significant as in Redis
+    pub mask: u8,
+}
+
+impl BitPosition {
+    pub fn of_bit(offset: u64) -> Self {
+        let byte_offset = offset / 8;
+        Self {
+            segment: byte_offset / BITMAP_SEGMENT_BYTES,
+            byte: (byte_offset % BITMAP_SEGMENT_BYTES) as usize,
+            mask: 0x80 >> (offset % 8),
+        }
+    }
+
+    /// The offset of the byte in the whole bitmap
+    pub fn byte_offset(&self) -> u64 {
+        self.segment * BITMAP_SEGMENT_BYTES + self.byte as u64
+    }
+
+    pub fn get(&self, segment: &[u8]) -> bool {
+        segment
+            .get(self.byte)
+            .is_some_and(|byte| byte & self.mask != 0)
+    }
+
+    /// Sets the bit in the segment, growing it with zeros as needed.
+    /// Returns the previous bit
+    pub fn set(&self, segment: &mut Vec<u8>, on: bool) -> bool {
+        if segment.len() <= self.byte {
+            segment.resize(self.byte + 1, 0);
+        }
+        let old = segment[self.byte] & self.mask != 0;
+        if on {
+            segment[self.byte] |= self.mask;
+        } else {
+            segment[self.byte] &= !self.mask;
+        }
+        old
+    }
+}
+
+/// The part of the inclusive byte range `start..=end` of the bitmap that
+/// falls in a segment, as a range of the segment bytes.
+pub fn segment_byte_range(index: u64, start: u64, end: u64) -> std::ops::Range<usize> {
+    let first = index * BITMAP_SEGMENT_BYTES;
+    let from = start.saturating_sub(first).min(BITMAP_SEGMENT_BYTES);
+    let to = (end + 1).saturating_sub(first).min(BITMAP_SEGMENT_BYTES);
+    from as usize..to.max(from) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let mut header = SegmentedBitmapHeader::new(42);
+        assert_eq!(header.segment_count(), 0);
+        header.extend_to(BitPosition::of_bit(8 * BITMAP_SEGMENT_BYTES));
+        assert_eq!(header.len, BITMAP_SEGMENT_BYTES + 1);
+        assert_eq!(header.segment_count(), 2);
+        assert_eq!(
+            SegmentedBitmapHeader::decode(&header.encode()).unwrap(),
+            header
+        );
+        assert!(SegmentedBitmapHeader::decode(b"short").is_err());
+    }
+
+    #[test]
+    fn test_segment_key_order() {
+        let keys: Vec<_> = [0, 1, 255, 256, u64::MAX]
+            .iter()
+            .map(|&index| {
+                BitmapSegmentKey::new(b"bit\x00map", 7, index)
+                    .encode()
+                    .unwrap()
+            })
+            .collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(decode_segment_index(&keys[3]).unwrap(), 256);
+        assert_eq!(decode_segment_index(&keys[4]).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn test_bit_position() {
+        let pos = BitPosition::of_bit(0);
+        assert_eq!((pos.segment, pos.byte, pos.mask), (0, 0, 0x80));
+        let pos = BitPosition::of_bit(8 * BITMAP_SEGMENT_BYTES + 15);
+        assert_eq!((pos.segment, pos.byte, pos.mask), (1, 1, 0x01));
+        assert_eq!(pos.byte_offset(), BITMAP_SEGMENT_BYTES + 1);
+
+        let mut segment = Vec::new();
+        assert!(!pos.get(&segment));
+        assert!(!pos.set(&mut segment, true));
+        assert_eq!(segment, vec![0, 1]);
+        assert!(pos.get(&segment));
+        assert!(pos.set(&mut segment, false));
+        assert_eq!(segment, vec![0, 0]);
+    }
+
+    #[test]
+    fn test_segment_byte_range() {
+        let n = BITMAP_SEGMENT_BYTES;
+        assert_eq!(segment_byte_range(0, 0, 10), 0..11);
+        assert_eq!(segment_byte_range(0, 5, 3 * n), 5..n as usize);
+        assert_eq!(segment_byte_range(1, 5, 3 * n), 0..n as usize);
+        assert_eq!(segment_byte_range(3, 5, 3 * n), 0..1);
+        assert_eq!(segment_byte_range(1, 0, 10), 0..0);
+        assert_eq!(segment_byte_range(0, n + 1, n + 2), n as usize..n as usize);
+    }
+}
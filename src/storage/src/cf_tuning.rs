@@ -0,0 +1,370 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! RocksDB tuning per column family
+//!
+//! The column families hold very different data: small meta values read
+//! by every command, hash fields, sorted set scores walked in order. A
+//! `CfTuning` sets the memtables, the SST files, the compression of the
+//! levels and the blocks of one of them, or of all of them, over
+//! `StorageOptions::options`. An option left unset keeps what the column
+//! family gets without tuning, so the default `RocksDbTuning` changes
+//! nothing.
+//!
+//! The tuning comes from the `[rocksdb]` section of the config file, an
+//! option of every column family by its name and one of a column family
+//! prefixed with the name of the column family:
+//!
+//! ```ini
+//! [rocksdb]
+//! max_background_jobs = 8
+//! compression_per_level = none,none,lz4,zstd
+//! zset_score_cf.block_size = 32768
+//! zset_score_cf.write_buffer_size = 134217728
+//! ```
+
+use crate::redis::ColumnFamilyIndex;
+use rocksdb::{BlockBasedOptions, DBCompressionType, Options};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Codec of the SST files of a level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelCompression {
+    None,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl FromStr for LevelCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(LevelCompression::None),
+            "snappy" => Ok(LevelCompression::Snappy),
+            "lz4" => Ok(LevelCompression::Lz4),
+            "zstd" => Ok(LevelCompression::Zstd),
+            _ => Err(format!("unknown compression '{s}'")),
+        }
+    }
+}
+
+impl From<LevelCompression> for DBCompressionType {
+    fn from(compression: LevelCompression) -> Self {
+        match compression {
+            LevelCompression::None => DBCompressionType::None,
+            LevelCompression::Snappy => DBCompressionType::Snappy,
+            LevelCompression::Lz4 => DBCompressionType::Lz4,
+            LevelCompression::Zstd => DBCompressionType::Zstd,
+        }
+    }
+}
+
+/// RocksDB options of a column family, None or empty to keep its own
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CfTuning {
+    /// Size of a memtable (in bytes)
+    pub write_buffer_size: Option<usize>,
+    /// Memtables kept in memory, the one written included
+    pub max_write_buffer_number: Option<i32>,
+    /// Size of the SST files of level 1, multiplied down the levels (in bytes)
+    pub target_file_size_base: Option<u64>,
+    /// Codec of each level from level 0, the last one repeated down to the
+    /// bottommost level
+    pub compression_per_level: Vec<LevelCompression>,
+    /// Bits per key of the bloom filter, 0 for none
+    pub bloom_filter_bits: Option<f64>,
+    /// Size of an uncompressed data block (in bytes)
+    pub block_size: Option<usize>,
+}
+
+impl CfTuning {
+    /// Sets the option `name` to `value`, the levels of
+    /// `compression_per_level` separated by commas
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid {name} '{value}'"))
+        }
+        match name {
+            "write_buffer_size" => self.write_buffer_size = Some(parse(name, value)?),
+            "max_write_buffer_number" => self.max_write_buffer_number = Some(parse(name, value)?),
+            "target_file_size_base" => self.target_file_size_base = Some(parse(name, value)?),
+            "compression_per_level" => {
+                self.compression_per_level = value
+                    .split(',')
+                    .map(|level| level.trim().parse())
+                    .collect::<Result<_, _>>()?
+            }
+            "bloom_filter_bits" => self.bloom_filter_bits = Some(parse(name, value)?),
+            "block_size" => self.block_size = Some(parse(name, value)?),
+            _ => return Err(format!("unknown rocksdb option '{name}'")),
+        }
+        Ok(())
+    }
+
+    /// The options of `self`, those unset taken from `fallback`
+    fn or(&self, fallback: &CfTuning) -> CfTuning {
+        CfTuning {
+            write_buffer_size: self.write_buffer_size.or(fallback.write_buffer_size),
+            max_write_buffer_number: self
+                .max_write_buffer_number
+                .or(fallback.max_write_buffer_number),
+            target_file_size_base: self
+                .target_file_size_base
+                .or(fallback.target_file_size_base),
+            compression_per_level: if self.compression_per_level.is_empty() {
+                fallback.compression_per_level.clone()
+            } else {
+                self.compression_per_level.clone()
+            },
+            bloom_filter_bits: self.bloom_filter_bits.or(fallback.bloom_filter_bits),
+            block_size: self.block_size.or(fallback.block_size),
+        }
+    }
+
+    /// Sets the memtable, SST file and compression options of `self` on
+    /// `cf_opts`
+    pub(crate) fn apply(&self, cf_opts: &mut Options) {
+        if let Some(size) = self.write_buffer_size {
+            cf_opts.set_write_buffer_size(size);
+        }
+        if let Some(number) = self.max_write_buffer_number {
+            cf_opts.set_max_write_buffer_number(number);
+        }
+        if let Some(size) = self.target_file_size_base {
+            cf_opts.set_target_file_size_base(size);
+        }
+        if !self.compression_per_level.is_empty() {
+            let levels: Vec<DBCompressionType> = self
+                .compression_per_level
+                .iter()
+                .map(|&compression| compression.into())
+                .collect();
+            cf_opts.set_compression_per_level(&levels);
+        }
+    }
+
+    /// Sets the block size and the bloom filter of `self` on `table_opts`,
+    /// or `block_size` and `bloom_filter_bits`, those the column family
+    /// gets without tuning
+    pub(crate) fn apply_table(
+        &self,
+        table_opts: &mut BlockBasedOptions,
+        block_size: Option<usize>,
+        bloom_filter_bits: f64,
+    ) {
+        let bits = self.bloom_filter_bits.unwrap_or(bloom_filter_bits);
+        if bits > 0.0 {
+            table_opts.set_bloom_filter(bits, true);
+        }
+        if let Some(size) = self.block_size.or(block_size) {
+            table_opts.set_block_size(size);
+        }
+    }
+}
+
+/// RocksDB tuning of the instances
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RocksDbTuning {
+    /// Flushes and compactions an instance runs at once
+    pub max_background_jobs: Option<i32>,
+    /// Tuning of every column family
+    pub all: CfTuning,
+    /// Tuning of a column family by name, over `all`
+    pub column_families: BTreeMap<String, CfTuning>,
+}
+
+impl RocksDbTuning {
+    /// Reads the options of the `[rocksdb]` section of the config file,
+    /// `<option>` or `<cf>.<option>` by value, and checks the tuning
+    pub fn from_options(options: &BTreeMap<String, String>) -> Result<Self, String> {
+        let mut tuning = Self::default();
+        for (key, value) in options {
+            match key.split_once('.') {
+                Some((cf_name, name)) => tuning
+                    .column_families
+                    .entry(cf_name.to_string())
+                    .or_default()
+                    .set(name, value)?,
+                None if key == "max_background_jobs" => {
+                    tuning.max_background_jobs = Some(
+                        value
+                            .trim()
+                            .parse()
+                            .map_err(|_| format!("invalid max_background_jobs '{value}'"))?,
+                    )
+                }
+                None => tuning.all.set(key, value)?,
+            }
+        }
+        tuning.validate()?;
+        Ok(tuning)
+    }
+
+    /// Fails on an unknown column family or an option out of range
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(name) = self.column_families.keys().find(|name| {
+            !ColumnFamilyIndex::ALL
+                .iter()
+                .any(|cf| cf.name() == name.as_str())
+        }) {
+            return Err(format!("unknown column family '{name}'"));
+        }
+        if self.max_background_jobs.is_some_and(|jobs| jobs < 1) {
+            return Err("max_background_jobs must be at least 1".to_string());
+        }
+        let tunings = std::iter::once(("all", &self.all)).chain(
+            self.column_families
+                .iter()
+                .map(|(name, tuning)| (name.as_str(), tuning)),
+        );
+        for (name, tuning) in tunings {
+            let invalid = |option: &str| format!("invalid {option} of '{name}'");
+            if tuning.write_buffer_size == Some(0) {
+                return Err(invalid("write_buffer_size"));
+            }
+            if tuning.max_write_buffer_number.is_some_and(|n| n < 1) {
+                return Err(invalid("max_write_buffer_number"));
+            }
+            if tuning.target_file_size_base == Some(0) {
+                return Err(invalid("target_file_size_base"));
+            }
+            if tuning
+                .bloom_filter_bits
+                .is_some_and(|bits| !(0.0..=64.0).contains(&bits))
+            {
+                return Err(invalid("bloom_filter_bits"));
+            }
+            if tuning.block_size == Some(0) {
+                return Err(invalid("block_size"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The tuning of the column family `cf_name`, its own over `all`
+    pub fn for_cf(&self, cf_name: &str) -> CfTuning {
+        match self.column_families.get(cf_name) {
+            Some(tuning) => tuning.or(&self.all),
+            None => self.all.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{unique_test_db_path, BgTaskHandler, Redis, StorageOptions};
+    use kstd::lock_mgr::LockMgr;
+    use std::sync::Arc;
+
+    fn options(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_tuning_from_options() {
+        let tuning = RocksDbTuning::from_options(&options(&[
+            ("max_background_jobs", "8"),
+            ("compression_per_level", "none, lz4,zstd"),
+            ("block_size", "8192"),
+            ("zset_score_cf.block_size", "32768"),
+            ("zset_score_cf.bloom_filter_bits", "0"),
+        ]))
+        .unwrap();
+        assert_eq!(tuning.max_background_jobs, Some(8));
+
+        let score = tuning.for_cf("zset_score_cf");
+        assert_eq!(score.block_size, Some(32768));
+        assert_eq!(score.bloom_filter_bits, Some(0.0));
+        assert_eq!(
+            score.compression_per_level,
+            vec![
+                LevelCompression::None,
+                LevelCompression::Lz4,
+                LevelCompression::Zstd
+            ]
+        );
+        let meta = tuning.for_cf("default");
+        assert_eq!(meta.block_size, Some(8192));
+        assert_eq!(meta.write_buffer_size, None);
+
+        assert_eq!(
+            RocksDbTuning::from_options(&BTreeMap::new()).unwrap(),
+            RocksDbTuning::default()
+        );
+    }
+
+    #[test]
+    fn test_invalid_tuning() {
+        for (key, value) in [
+            ("string_cf.block_size", "4096"),
+            ("max_background_jobs", "0"),
+            ("write_buffer_size", "0"),
+            ("default.bloom_filter_bits", "-1"),
+            ("compression_per_level", "none,gzip"),
+            ("block_sizes", "4096"),
+            ("block_size", "4k"),
+        ] {
+            assert!(
+                RocksDbTuning::from_options(&options(&[(key, value)])).is_err(),
+                "{key} = {value}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_open_tuned() {
+        let test_db_path = unique_test_db_path();
+        let tuning = RocksDbTuning::from_options(&options(&[
+            ("max_background_jobs", "4"),
+            ("compression_per_level", "none,lz4"),
+            ("write_buffer_size", "8388608"),
+            ("default.bloom_filter_bits", "0"),
+            ("default.block_size", "4096"),
+            ("zset_score_cf.target_file_size_base", "16777216"),
+        ]))
+        .unwrap();
+        let mut options = StorageOptions::default();
+        options.set_rocksdb_tuning(tuning);
+        let (handler, _receiver) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(options),
+            1,
+            Arc::new(handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+
+        redis.set(b"key", b"value").unwrap();
+        assert_eq!(redis.get(b"key").unwrap(), "value");
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
@@ -0,0 +1,217 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! CRC32 of the encoded values
+//!
+//! With `StorageOptions::value_checksum`, every value format writes a CRC32
+//! of the bytes before its suffix reserve (the type, the user value and the
+//! fixed fields such as count and version) into the first bytes of the
+//! reserve, and sets `RESERVE_CHECKSUM_FLAG` in the flags byte. Parsing a
+//! value with the flag verifies it and fails with
+//! `Error::ChecksumMismatch`, so a corrupted value is caught before it is
+//! served or written back. Values written without the option carry no
+//! checksum and are never verified, the option can be switched on an
+//! existing database.
+//!
+//! The timestamps after the reserve are not covered, they are rewritten in
+//! place on every expiration change.
+
+use std::sync::OnceLock;
+
+use snafu::ensure;
+
+use crate::error::{ChecksumMismatchSnafu, ConfigSnafu, Result};
+use crate::storage_define::{RESERVE_CHECKSUM_LENGTH, RESERVE_FLAGS_OFFSET};
+
+/// Set in the flags byte of the reserve when the value has a checksum
+pub const RESERVE_CHECKSUM_FLAG: u8 = 0x02;
+
+// The values are encoded without access to the options, the switch is
+// process wide: set by the first instance opened, the others must agree
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    static TEST_ENABLED: std::cell::Cell<Option<bool>> = const { std::cell::Cell::new(None) };
+}
+
+/// Sets the switch when the first instance opens, fails if an instance was
+/// opened with the other one
+pub fn set_enabled(enabled: bool) -> Result<()> {
+    let current = *ENABLED.get_or_init(|| enabled);
+    ensure!(
+        current == enabled,
+        ConfigSnafu {
+            message: format!(
+                "value_checksum is {current} in this process, an instance can not be opened with {enabled}"
+            ),
+        }
+    );
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    #[cfg(test)]
+    if let Some(enabled) = TEST_ENABLED.with(|e| e.get()) {
+        return enabled;
+    }
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+/// Runs f with checksums enabled on the current thread only, so tests do
+/// not change the encoding seen by the tests running in parallel
+#[cfg(test)]
+pub(crate) fn with_checksum<R>(f: impl FnOnce() -> R) -> R {
+    TEST_ENABLED.with(|e| e.set(Some(true)));
+    let result = f();
+    TEST_ENABLED.with(|e| e.set(None));
+    result
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE), as zlib crc32
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Writes the checksum of value[..reserve_start] into the reserve when
+/// checksums are enabled, clears it otherwise. Called by every encode
+pub(crate) fn seal(value: &mut [u8], reserve_start: usize) {
+    let (covered, reserve) = value.split_at_mut(reserve_start);
+    if enabled() {
+        reserve[..RESERVE_CHECKSUM_LENGTH].copy_from_slice(&crc32(covered).to_le_bytes());
+        reserve[RESERVE_FLAGS_OFFSET] |= RESERVE_CHECKSUM_FLAG;
+    } else {
+        reserve[..RESERVE_CHECKSUM_LENGTH].fill(0);
+        reserve[RESERVE_FLAGS_OFFSET] &= !RESERVE_CHECKSUM_FLAG;
+    }
+}
+
+/// Updates the checksum of a value that has one, after a field before the
+/// reserve was rewritten in place
+pub(crate) fn refresh(value: &mut [u8], reserve_start: usize) {
+    if value[reserve_start + RESERVE_FLAGS_OFFSET] & RESERVE_CHECKSUM_FLAG != 0 {
+        let (covered, reserve) = value.split_at_mut(reserve_start);
+        reserve[..RESERVE_CHECKSUM_LENGTH].copy_from_slice(&crc32(covered).to_le_bytes());
+    }
+}
+
+/// Verifies the checksum of a value that has one, when checksums are
+/// enabled. Called by every parse
+pub(crate) fn verify(value: &[u8], reserve_start: usize) -> Result<()> {
+    let reserve = &value[reserve_start..];
+    if !enabled() || reserve[RESERVE_FLAGS_OFFSET] & RESERVE_CHECKSUM_FLAG == 0 {
+        return Ok(());
+    }
+
+    let mut stored = [0u8; RESERVE_CHECKSUM_LENGTH];
+    stored.copy_from_slice(&reserve[..RESERVE_CHECKSUM_LENGTH]);
+    let expected = u32::from_le_bytes(stored);
+    let actual = crc32(&value[..reserve_start]);
+    if expected != actual {
+        return ChecksumMismatchSnafu { expected, actual }.fail();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage_define::SUFFIX_RESERVE_LENGTH;
+
+    fn value(user_value: &[u8]) -> Vec<u8> {
+        let mut value = user_value.to_vec();
+        value.extend_from_slice(&[0; SUFFIX_RESERVE_LENGTH]);
+        value
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414f_a339
+        );
+    }
+
+    #[test]
+    fn test_seal_and_verify() {
+        let mut sealed = value(b"hello");
+        with_checksum(|| seal(&mut sealed, 5));
+        assert_eq!(&sealed[5..9], &crc32(b"hello").to_le_bytes());
+        assert_eq!(sealed[5 + RESERVE_FLAGS_OFFSET], RESERVE_CHECKSUM_FLAG);
+        assert!(with_checksum(|| verify(&sealed, 5)).is_ok());
+
+        let mut corrupted = sealed.clone();
+        corrupted[0] = b'j';
+        assert!(with_checksum(|| verify(&corrupted, 5)).is_err());
+        // Not verified while checksums are disabled
+        assert!(verify(&corrupted, 5).is_ok());
+
+        // Values without a checksum are not verified
+        assert!(with_checksum(|| verify(&value(b"hello"), 5)).is_ok());
+
+        // Sealing with checksums disabled removes the checksum
+        seal(&mut sealed, 5);
+        assert_eq!(sealed, value(b"hello"));
+    }
+
+    #[test]
+    fn test_conflicting_switch() {
+        // The default of every instance opened by the other tests
+        set_enabled(false).unwrap();
+        assert!(set_enabled(true).is_err());
+        set_enabled(false).unwrap();
+    }
+
+    #[test]
+    fn test_refresh() {
+        let mut sealed = value(b"hello");
+        with_checksum(|| seal(&mut sealed, 5));
+        sealed[0] = b'j';
+        refresh(&mut sealed, 5);
+        assert!(with_checksum(|| verify(&sealed, 5)).is_ok());
+
+        // Nothing to refresh without a checksum
+        let mut plain = value(b"hello");
+        refresh(&mut plain, 5);
+        assert_eq!(plain, value(b"hello"));
+    }
+}
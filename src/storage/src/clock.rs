@@ -0,0 +1,160 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The wall clock of the storage layer
+//!
+//! Versions, ctime and etime, TTL checks and the compaction filters read the
+//! time through `now_micros`, which asks the clock of
+//! `StorageOptions::clock` (`SystemClock` unless replaced). A `MockClock`
+//! lets tests move time forward without sleeping.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use snafu::ensure;
+
+use crate::error::{ConfigSnafu, Result};
+
+/// A source of the current time
+pub trait Clock: Send + Sync {
+    /// Microseconds since the Unix epoch
+    fn now_micros(&self) -> u64;
+}
+
+/// The system wall clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_micros(&self) -> u64 {
+        Utc::now().timestamp_micros() as u64
+    }
+}
+
+/// The system clock shared by the default options, so that instances opened
+/// with them have the same clock
+pub fn system_clock() -> Arc<dyn Clock> {
+    static SYSTEM_CLOCK: OnceLock<Arc<dyn Clock>> = OnceLock::new();
+    SYSTEM_CLOCK.get_or_init(|| Arc::new(SystemClock)).clone()
+}
+
+/// A clock that only moves when told to
+#[derive(Debug, Default)]
+pub struct MockClock {
+    micros: AtomicU64,
+}
+
+impl MockClock {
+    pub fn new(micros: u64) -> Self {
+        Self {
+            micros: AtomicU64::new(micros),
+        }
+    }
+
+    pub fn set(&self, micros: u64) {
+        self.micros.store(micros, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.micros
+            .fetch_add(by.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_micros(&self) -> u64 {
+        self.micros.load(Ordering::Relaxed)
+    }
+}
+
+// The values are encoded without access to the options, the clock is
+// process wide: set by the first instance opened, the others must share it
+static CLOCK: OnceLock<Arc<dyn Clock>> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    static TEST_CLOCK: std::cell::RefCell<Option<Arc<dyn Clock>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Sets the clock when the first instance opens, fails if an instance was
+/// opened with another clock
+pub fn set_clock(clock: Arc<dyn Clock>) -> Result<()> {
+    let current = CLOCK.get_or_init(|| clock.clone());
+    ensure!(
+        Arc::ptr_eq(current, &clock),
+        ConfigSnafu {
+            message:
+                "an instance can not be opened with another clock than the one of this process"
+                    .to_string(),
+        }
+    );
+    Ok(())
+}
+
+/// Microseconds since the Unix epoch, by the current clock
+pub fn now_micros() -> u64 {
+    #[cfg(test)]
+    if let Some(micros) = TEST_CLOCK.with(|c| c.borrow().as_ref().map(|c| c.now_micros())) {
+        return micros;
+    }
+    match CLOCK.get() {
+        Some(clock) => clock.now_micros(),
+        None => SystemClock.now_micros(),
+    }
+}
+
+/// Runs f with clock on the current thread only, so tests do not change the
+/// time seen by the tests running in parallel
+#[cfg(test)]
+pub(crate) fn with_clock<R>(clock: Arc<dyn Clock>, f: impl FnOnce() -> R) -> R {
+    TEST_CLOCK.with(|c| *c.borrow_mut() = Some(clock));
+    let result = f();
+    TEST_CLOCK.with(|c| *c.borrow_mut() = None);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = Arc::new(MockClock::new(1_000));
+        with_clock(clock.clone(), || {
+            assert_eq!(now_micros(), 1_000);
+            clock.advance(Duration::from_millis(2));
+            assert_eq!(now_micros(), 3_000);
+            clock.set(42);
+            assert_eq!(now_micros(), 42);
+        });
+
+        // Back to the system clock outside with_clock
+        assert!(now_micros() > 1_600_000_000_000_000);
+    }
+
+    #[test]
+    fn test_conflicting_clock() {
+        set_clock(system_clock()).unwrap();
+        assert!(set_clock(Arc::new(MockClock::new(0))).is_err());
+        set_clock(system_clock()).unwrap();
+    }
+}
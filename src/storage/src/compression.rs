@@ -0,0 +1,191 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Compression of large string values
+//!
+//! With `StorageOptions::value_compression`, a string user value of at
+//! least `compression_threshold` bytes is compressed when it is encoded and
+//! the codec is recorded by a flag in the flags byte of the reserve.
+//! `ParsedStringsValue` decompresses it, so readers only ever see the
+//! original bytes. A value that does not shrink is stored as is. Values are
+//! decompressed whatever the current option, it can be changed or switched
+//! off on an existing database.
+
+use std::sync::OnceLock;
+
+use snafu::ensure;
+
+use crate::error::{ConfigSnafu, InvalidFormatSnafu, Result};
+use crate::options::ValueCompression;
+
+/// Set in the flags byte of the reserve of a string value compressed with
+/// LZ4, the original length is prepended to the compressed bytes
+pub const STRING_LZ4_FLAG: u8 = 0x20;
+
+/// Set in the flags byte of the reserve of a string value compressed with
+/// Zstd
+pub const STRING_ZSTD_FLAG: u8 = 0x40;
+
+/// Every codec flag
+pub const STRING_COMPRESSION_FLAGS: u8 = STRING_LZ4_FLAG | STRING_ZSTD_FLAG;
+
+const ZSTD_LEVEL: i32 = 3;
+
+// Same as the checksum switch, the values are encoded without access to the
+// options and the settings are process wide, set by the first instance opened
+static SETTINGS: OnceLock<(ValueCompression, usize)> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    static TEST_SETTINGS: std::cell::Cell<Option<(ValueCompression, usize)>> =
+        const { std::cell::Cell::new(None) };
+}
+
+/// Sets the codec and the threshold when the first instance opens, fails if
+/// an instance was opened with other ones
+pub fn configure(codec: ValueCompression, threshold: usize) -> Result<()> {
+    let current = *SETTINGS.get_or_init(|| (codec, threshold));
+    ensure!(
+        current == (codec, threshold),
+        ConfigSnafu {
+            message: format!(
+                "value compression is {current:?} in this process, an instance can not be opened with {:?}",
+                (codec, threshold)
+            ),
+        }
+    );
+    Ok(())
+}
+
+fn settings() -> (ValueCompression, usize) {
+    #[cfg(test)]
+    if let Some(settings) = TEST_SETTINGS.with(|s| s.get()) {
+        return settings;
+    }
+    SETTINGS
+        .get()
+        .copied()
+        .unwrap_or((ValueCompression::None, usize::MAX))
+}
+
+/// Runs f with the given settings on the current thread only, so tests do
+/// not change the encoding seen by the tests running in parallel
+#[cfg(test)]
+pub(crate) fn with_compression<R>(
+    codec: ValueCompression,
+    threshold: usize,
+    f: impl FnOnce() -> R,
+) -> R {
+    TEST_SETTINGS.with(|s| s.set(Some((codec, threshold))));
+    let result = f();
+    TEST_SETTINGS.with(|s| s.set(None));
+    result
+}
+
+/// The flag of the codec and the compressed value. None when compression
+/// is off, the value is under the threshold or does not shrink
+pub(crate) fn compress(value: &[u8]) -> Option<(u8, Vec<u8>)> {
+    let (codec, threshold) = settings();
+    if value.len() < threshold {
+        return None;
+    }
+    let (flag, compressed) = match codec {
+        ValueCompression::None => return None,
+        ValueCompression::Lz4 => (STRING_LZ4_FLAG, lz4_flex::compress_prepend_size(value)),
+        ValueCompression::Zstd => (
+            STRING_ZSTD_FLAG,
+            zstd::bulk::compress(value, ZSTD_LEVEL).ok()?,
+        ),
+    };
+    (compressed.len() < value.len()).then_some((flag, compressed))
+}
+
+/// The original bytes of a value compressed with the codec of flags
+pub(crate) fn decompress(flags: u8, compressed: &[u8]) -> Result<Vec<u8>> {
+    let decompressed = match flags & STRING_COMPRESSION_FLAGS {
+        STRING_LZ4_FLAG => {
+            lz4_flex::decompress_size_prepended(compressed).map_err(|e| e.to_string())
+        }
+        STRING_ZSTD_FLAG => zstd::decode_all(compressed).map_err(|e| e.to_string()),
+        flags => Err(format!("unknown compression flags {flags:#04x}")),
+    };
+    match decompressed {
+        Ok(value) => Ok(value),
+        Err(message) => InvalidFormatSnafu {
+            message: format!("invalid compressed string value: {message}"),
+        }
+        .fail(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_blob() -> Vec<u8> {
+        (0..64)
+            .flat_map(|i| format!(r#"{{"id":{i},"name":"kiwi","tags":["a","b"]}},"#).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let value = json_blob();
+        for (codec, flag) in [
+            (ValueCompression::Lz4, STRING_LZ4_FLAG),
+            (ValueCompression::Zstd, STRING_ZSTD_FLAG),
+        ] {
+            let (compressed_flag, compressed) =
+                with_compression(codec, 64, || compress(&value)).unwrap();
+            assert_eq!(compressed_flag, flag);
+            assert!(compressed.len() < value.len());
+            assert_eq!(decompress(flag, &compressed).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_compress_skips() {
+        let value = json_blob();
+        // Off by default
+        assert!(compress(&value).is_none());
+        assert!(with_compression(ValueCompression::None, 0, || compress(&value)).is_none());
+        // Under the threshold
+        let threshold = value.len() + 1;
+        assert!(with_compression(ValueCompression::Lz4, threshold, || compress(&value)).is_none());
+        // Not shrinking
+        assert!(with_compression(ValueCompression::Lz4, 0, || compress(b"ab")).is_none());
+    }
+
+    #[test]
+    fn test_conflicting_settings() {
+        let defaults = crate::StorageOptions::default();
+        let (codec, threshold) = (defaults.value_compression, defaults.compression_threshold);
+        configure(codec, threshold).unwrap();
+        assert!(configure(ValueCompression::Zstd, threshold).is_err());
+        assert!(configure(codec, threshold + 1).is_err());
+        configure(codec, threshold).unwrap();
+    }
+
+    #[test]
+    fn test_decompress_invalid() {
+        assert!(decompress(STRING_LZ4_FLAG, b"\x10\x00\x00\x00junk").is_err());
+        assert!(decompress(STRING_ZSTD_FLAG, b"junk").is_err());
+        assert!(decompress(0, b"junk").is_err());
+    }
+}
@@ -0,0 +1,274 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! The logical databases selected with SELECT.
+//!
+//! Every database is an independent `Storage`. A command resolves the
+//! database of its client through a [`DbGuard`], which pins the current
+//! mapping from index to `Storage` for as long as it lives, so a command or a
+//! transaction sees one consistent mapping from start to end.
+//!
+//! SWAPDB publishes a new mapping with the two entries exchanged, which costs
+//! the same whatever the size of the databases, then waits for the guards
+//! pinned before the swap to be dropped (epoch based quiescence). Once swap
+//! returns no command is still running against the old mapping.
+
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+
+use crate::error::{InvalidDbIndexSnafu, Result};
+use crate::storage::Storage;
+use crate::StorageOptions;
+
+#[derive(Debug, Default)]
+struct EpochState {
+    epoch: u64,
+    // Number of live guards pinned in an even and in an odd epoch.
+    active: [usize; 2],
+}
+
+pub struct Databases {
+    mapping: RwLock<Arc<Vec<Arc<Storage>>>>,
+    state: Mutex<EpochState>,
+    drained: Condvar,
+    // Serializes swaps, so that a swap only waits for the epoch it closed.
+    swap_lock: Mutex<()>,
+}
+
+impl Databases {
+    /// Creates the databases from their storages, the index of a storage
+    /// in `dbs` is its database index.
+    pub fn new(dbs: Vec<Arc<Storage>>) -> Arc<Self> {
+        Arc::new_cyclic(|weak: &Weak<Databases>| {
+            for storage in &dbs {
+                let _ = storage.databases.set(weak.clone());
+            }
+            Self {
+                mapping: RwLock::new(Arc::new(dbs)),
+                state: Mutex::new(EpochState::default()),
+                drained: Condvar::new(),
+                swap_lock: Mutex::new(()),
+            }
+        })
+    }
+
+    /// Opens `options.databases` databases under `db_path`: database 0 uses
+    /// `db_path` itself, database `n` uses `db_path/db<n>`.
+    pub fn open(options: Arc<StorageOptions>, db_path: impl AsRef<Path>) -> Result<Arc<Self>> {
+        let db_path = db_path.as_ref();
+        let mut dbs = Vec::with_capacity(options.databases);
+        for index in 0..options.databases.max(1) {
+            let path = match index {
+                0 => db_path.to_path_buf(),
+                n => db_path.join(format!("db{n}")),
+            };
+            let mut storage = Storage::new(options.db_instance_num, index);
+            // Background tasks are not consumed yet, the receiver is dropped.
+            storage.open(Arc::clone(&options), path)?;
+            dbs.push(Arc::new(storage));
+        }
+        Ok(Self::new(dbs))
+    }
+
+    /// Number of databases.
+    pub fn len(&self) -> usize {
+        self.current().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The storages of all databases, by database index.
+    pub fn all(&self) -> Vec<Arc<Storage>> {
+        self.current().as_ref().clone()
+    }
+
+    /// Pins the current mapping. Commands hold the guard while they execute,
+    /// a transaction holds one guard for all its commands.
+    pub fn pin(&self) -> DbGuard<'_> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let epoch = state.epoch;
+        state.active[(epoch % 2) as usize] += 1;
+        // Read the mapping while holding the state lock so that it belongs
+        // to the pinned epoch.
+        let mapping = self.current();
+        drop(state);
+
+        DbGuard {
+            databases: self,
+            epoch,
+            mapping,
+        }
+    }
+
+    /// Exchanges the contents of databases `a` and `b` (SWAPDB), waiting for
+    /// the commands that started before the swap. Must not be called while
+    /// holding a guard of these databases, it would wait for itself. Blocks
+    /// the calling thread, async callers run it with `spawn_blocking`.
+    pub fn swap(&self, a: usize, b: usize) -> Result<()> {
+        let _swap = self.swap_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        {
+            let mut mapping = self.mapping.write().unwrap_or_else(|e| e.into_inner());
+            for index in [a, b] {
+                snafu::ensure!(index < mapping.len(), InvalidDbIndexSnafu { index });
+            }
+            if a != b {
+                let mut swapped = mapping.as_ref().clone();
+                swapped.swap(a, b);
+                *mapping = Arc::new(swapped);
+            }
+        }
+        let closed = (state.epoch % 2) as usize;
+        state.epoch += 1;
+
+        while state.active[closed] > 0 {
+            state = self.drained.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        Ok(())
+    }
+
+    fn current(&self) -> Arc<Vec<Arc<Storage>>> {
+        Arc::clone(&self.mapping.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn unpin(&self, epoch: u64) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let slot = (epoch % 2) as usize;
+        state.active[slot] -= 1;
+        if state.active[slot] == 0 {
+            self.drained.notify_all();
+        }
+    }
+}
+
+/// A pinned mapping from database index to `Storage`, see [`Databases::pin`].
+pub struct DbGuard<'a> {
+    databases: &'a Databases,
+    epoch: u64,
+    mapping: Arc<Vec<Arc<Storage>>>,
+}
+
+impl DbGuard<'_> {
+    /// The storage of database `index`, `None` if out of range.
+    pub fn db(&self, index: usize) -> Option<Arc<Storage>> {
+        self.mapping.get(index).cloned()
+    }
+}
+
+impl Drop for DbGuard<'_> {
+    fn drop(&mut self) {
+        self.databases.unpin(self.epoch);
+    }
+}
+
+impl Storage {
+    /// The databases this storage belongs to, if any.
+    pub fn databases(&self) -> Option<Arc<Databases>> {
+        self.databases.get().and_then(Weak::upgrade)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn databases(num: usize) -> Arc<Databases> {
+        Databases::new((0..num).map(|i| Arc::new(Storage::new(1, i))).collect())
+    }
+
+    fn db_id(guard: &DbGuard<'_>, index: usize) -> usize {
+        guard.db(index).unwrap().db_id
+    }
+
+    #[test]
+    fn test_swap_exchanges_mapping() {
+        let dbs = databases(3);
+        dbs.swap(0, 2).unwrap();
+
+        let guard = dbs.pin();
+        assert_eq!(db_id(&guard, 0), 2);
+        assert_eq!(db_id(&guard, 1), 1);
+        assert_eq!(db_id(&guard, 2), 0);
+        assert!(guard.db(3).is_none());
+        drop(guard);
+
+        assert!(dbs.swap(0, 3).is_err());
+        dbs.swap(1, 1).unwrap();
+        assert!(Arc::ptr_eq(&dbs.all()[0].databases().unwrap(), &dbs));
+    }
+
+    #[test]
+    fn test_swap_waits_for_transaction_in_progress() {
+        let dbs = databases(2);
+
+        // A client in the middle of a transaction keeps its mapping.
+        let guard = dbs.pin();
+        assert_eq!(db_id(&guard, 0), 0);
+
+        let (done_tx, done_rx) = mpsc::channel();
+        let swapper = {
+            let dbs = Arc::clone(&dbs);
+            thread::spawn(move || {
+                dbs.swap(0, 1).unwrap();
+                done_tx.send(()).unwrap();
+            })
+        };
+
+        // The swap is published but does not complete while the transaction
+        // is running, and the transaction still sees the old mapping.
+        assert!(done_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(db_id(&guard, 0), 0);
+        assert_eq!(db_id(&dbs.pin(), 0), 1);
+
+        drop(guard);
+        done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        swapper.join().unwrap();
+        assert_eq!(db_id(&dbs.pin(), 0), 1);
+    }
+
+    #[test]
+    fn test_concurrent_swaps() {
+        let dbs = databases(2);
+        let swappers: Vec<_> = (0..4)
+            .map(|_| {
+                let dbs = Arc::clone(&dbs);
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        let guard = dbs.pin();
+                        let (a, b) = (db_id(&guard, 0), db_id(&guard, 1));
+                        assert_ne!(a, b);
+                        drop(guard);
+                        dbs.swap(0, 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for swapper in swappers {
+            swapper.join().unwrap();
+        }
+
+        // an even number of swaps restores the original mapping
+        assert_eq!(db_id(&dbs.pin(), 0), 0);
+    }
+}
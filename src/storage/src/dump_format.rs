@@ -0,0 +1,727 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Redis DUMP payloads, for DUMP, RESTORE and MIGRATE with real Redis
+//!
+//! A payload is one RDB object followed by a footer:
+//!
+//! | type | object | rdb version | crc64 |
+//! |  1B  |        |    2B LE    | 8B LE |
+//!
+//! The crc64 (Jones polynomial, the one of Redis) covers everything before
+//! it. Values are dumped with the plain object types of RDB version 9, which
+//! every Redis since 5.0 restores. Payloads are read with those types and
+//! with the compact encodings Redis 7 dumps small values with: integer and
+//! LZF strings, intsets, listpacks and quicklists of listpacks.
+
+use crate::base_value_format::DataType;
+use crate::error::{InvalidFormatSnafu, Result};
+use bytes::{BufMut, BytesMut};
+use snafu::{ensure, OptionExt};
+
+/// RDB version written in the footer of the dumped payloads
+pub const DUMP_RDB_VERSION: u16 = 9;
+/// Newest RDB version accepted, the one of Redis 7.4
+const MAX_RDB_VERSION: u16 = 12;
+const FOOTER_LENGTH: usize = 2 + 8;
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+
+/// Special string encodings, flagged by the two high bits of the length
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+/// Container of a quicklist node
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+const LISTPACK_HEADER_LENGTH: usize = 6;
+const LISTPACK_EOF: u8 = 0xff;
+
+/// A value in the form it is exchanged with Redis
+#[derive(Debug, Clone, PartialEq)]
+pub enum DumpValue {
+    String(Vec<u8>),
+    Hash(Vec<(Vec<u8>, Vec<u8>)>),
+    List(Vec<Vec<u8>>),
+    Set(Vec<Vec<u8>>),
+    ZSet(Vec<(Vec<u8>, f64)>),
+}
+
+impl DumpValue {
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Self::String(_) => DataType::String,
+            Self::Hash(_) => DataType::Hash,
+            Self::List(_) => DataType::List,
+            Self::Set(_) => DataType::Set,
+            Self::ZSet(_) => DataType::ZSet,
+        }
+    }
+
+    /// The DUMP payload of the value
+    pub fn dump(&self) -> Vec<u8> {
+        let mut dst = BytesMut::new();
+        match self {
+            Self::String(value) => {
+                dst.put_u8(RDB_TYPE_STRING);
+                put_string(&mut dst, value);
+            }
+            Self::Hash(fields) => {
+                dst.put_u8(RDB_TYPE_HASH);
+                put_len(&mut dst, fields.len() as u64);
+                for (field, value) in fields {
+                    put_string(&mut dst, field);
+                    put_string(&mut dst, value);
+                }
+            }
+            Self::List(elements) | Self::Set(elements) => {
+                let rdb_type = if matches!(self, Self::List(_)) {
+                    RDB_TYPE_LIST
+                } else {
+                    RDB_TYPE_SET
+                };
+                dst.put_u8(rdb_type);
+                put_len(&mut dst, elements.len() as u64);
+                for element in elements {
+                    put_string(&mut dst, element);
+                }
+            }
+            Self::ZSet(members) => {
+                dst.put_u8(RDB_TYPE_ZSET_2);
+                put_len(&mut dst, members.len() as u64);
+                for (member, score) in members {
+                    put_string(&mut dst, member);
+                    dst.put_f64_le(*score);
+                }
+            }
+        }
+        dst.put_u16_le(DUMP_RDB_VERSION);
+        let crc = crc64(0, &dst);
+        dst.put_u64_le(crc);
+        dst.to_vec()
+    }
+
+    /// Parses a DUMP payload, as RESTORE does. Fails when the footer is
+    /// wrong or the object is not a string, hash, list, set or sorted set
+    pub fn restore(payload: &[u8]) -> Result<Self> {
+        ensure!(
+            payload.len() > FOOTER_LENGTH,
+            InvalidFormatSnafu {
+                message: "DUMP payload version or checksum are wrong".to_string(),
+            }
+        );
+        let (body, crc) = payload.split_at(payload.len() - 8);
+        let (object, version) = body.split_at(body.len() - 2);
+        let version = u16::from_le_bytes([version[0], version[1]]);
+        let crc = u64::from_le_bytes(crc.try_into().unwrap());
+        ensure!(
+            version <= MAX_RDB_VERSION && crc64(0, body) == crc,
+            InvalidFormatSnafu {
+                message: "DUMP payload version or checksum are wrong".to_string(),
+            }
+        );
+
+        let mut reader = Reader { src: object };
+        let value = reader.object()?;
+        ensure!(
+            reader.src.is_empty(),
+            InvalidFormatSnafu {
+                message: format!("{} trailing bytes in DUMP payload", reader.src.len()),
+            }
+        );
+        Ok(value)
+    }
+}
+
+fn put_len(dst: &mut BytesMut, len: u64) {
+    if len < 1 << 6 {
+        dst.put_u8(len as u8);
+    } else if len < 1 << 14 {
+        dst.put_u8(0x40 | (len >> 8) as u8);
+        dst.put_u8(len as u8);
+    } else if len <= u32::MAX as u64 {
+        dst.put_u8(0x80);
+        dst.put_u32(len as u32);
+    } else {
+        dst.put_u8(0x81);
+        dst.put_u64(len);
+    }
+}
+
+fn put_string(dst: &mut BytesMut, value: &[u8]) {
+    put_len(dst, value.len() as u64);
+    dst.put_slice(value);
+}
+
+/// A length, or the encoding of a string that is not stored as is
+enum Len {
+    Plain(u64),
+    Encoded(u8),
+}
+
+struct Reader<'a> {
+    src: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(
+            len <= self.src.len(),
+            InvalidFormatSnafu {
+                message: "DUMP payload truncated".to_string(),
+            }
+        );
+        let (head, tail) = self.src.split_at(len);
+        self.src = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn len(&mut self) -> Result<Len> {
+        let first = self.u8()?;
+        let len = match first >> 6 {
+            0 => Len::Plain((first & 0x3f) as u64),
+            1 => Len::Plain(((first & 0x3f) as u64) << 8 | self.u8()? as u64),
+            2 if first == 0x80 => {
+                Len::Plain(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64)
+            }
+            2 if first == 0x81 => Len::Plain(u64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+            3 => Len::Encoded(first & 0x3f),
+            _ => {
+                return InvalidFormatSnafu {
+                    message: format!("invalid RDB length byte: {first:#04x}"),
+                }
+                .fail()
+            }
+        };
+        Ok(len)
+    }
+
+    fn plain_len(&mut self) -> Result<usize> {
+        match self.len()? {
+            Len::Plain(len) => Ok(len as usize),
+            Len::Encoded(_) => InvalidFormatSnafu {
+                message: "encoded string where a length is expected".to_string(),
+            }
+            .fail(),
+        }
+    }
+
+    /// A count of elements, which can not exceed the bytes left so that a
+    /// corrupt count does not reserve a huge vector
+    fn count(&mut self) -> Result<usize> {
+        let count = self.plain_len()?;
+        ensure!(
+            count <= self.src.len(),
+            InvalidFormatSnafu {
+                message: format!("RDB element count {count} exceeds the payload"),
+            }
+        );
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        match self.len()? {
+            Len::Plain(len) => Ok(self.take(len as usize)?.to_vec()),
+            Len::Encoded(RDB_ENC_INT8) => Ok((self.u8()? as i8).to_string().into_bytes()),
+            Len::Encoded(RDB_ENC_INT16) => {
+                let int = i16::from_le_bytes(self.take(2)?.try_into().unwrap());
+                Ok(int.to_string().into_bytes())
+            }
+            Len::Encoded(RDB_ENC_INT32) => {
+                let int = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+                Ok(int.to_string().into_bytes())
+            }
+            Len::Encoded(RDB_ENC_LZF) => {
+                let compressed_len = self.plain_len()?;
+                let len = self.plain_len()?;
+                lzf_decompress(self.take(compressed_len)?, len)
+            }
+            Len::Encoded(encoding) => InvalidFormatSnafu {
+                message: format!("unknown RDB string encoding: {encoding}"),
+            }
+            .fail(),
+        }
+    }
+
+    /// A score of a RDB_TYPE_ZSET, as a length prefixed decimal
+    fn string_double(&mut self) -> Result<f64> {
+        match self.u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => parse_double(self.take(len as usize)?),
+        }
+    }
+
+    fn object(&mut self) -> Result<DumpValue> {
+        let rdb_type = self.u8()?;
+        let value = match rdb_type {
+            RDB_TYPE_STRING => DumpValue::String(self.string()?),
+            RDB_TYPE_LIST | RDB_TYPE_SET => {
+                let count = self.count()?;
+                let elements = (0..count)
+                    .map(|_| self.string())
+                    .collect::<Result<Vec<_>>>()?;
+                if rdb_type == RDB_TYPE_LIST {
+                    DumpValue::List(elements)
+                } else {
+                    DumpValue::Set(elements)
+                }
+            }
+            RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+                let count = self.count()?;
+                let mut members = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let member = self.string()?;
+                    let score = if rdb_type == RDB_TYPE_ZSET {
+                        self.string_double()?
+                    } else {
+                        f64::from_le_bytes(self.take(8)?.try_into().unwrap())
+                    };
+                    members.push((member, score));
+                }
+                DumpValue::ZSet(members)
+            }
+            RDB_TYPE_HASH => {
+                let count = self.count()?;
+                let mut fields = Vec::with_capacity(count);
+                for _ in 0..count {
+                    fields.push((self.string()?, self.string()?));
+                }
+                DumpValue::Hash(fields)
+            }
+            RDB_TYPE_SET_INTSET => DumpValue::Set(intset_entries(&self.string()?)?),
+            RDB_TYPE_SET_LISTPACK => DumpValue::Set(listpack_entries(&self.string()?)?),
+            RDB_TYPE_HASH_LISTPACK => DumpValue::Hash(pairs(listpack_entries(&self.string()?)?)?),
+            RDB_TYPE_ZSET_LISTPACK => {
+                let members = pairs(listpack_entries(&self.string()?)?)?
+                    .into_iter()
+                    .map(|(member, score)| Ok((member, parse_double(&score)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                DumpValue::ZSet(members)
+            }
+            RDB_TYPE_LIST_QUICKLIST_2 => {
+                let nodes = self.count()?;
+                let mut elements = Vec::new();
+                for _ in 0..nodes {
+                    let container = self.plain_len()? as u64;
+                    let node = self.string()?;
+                    match container {
+                        QUICKLIST_NODE_PLAIN => elements.push(node),
+                        QUICKLIST_NODE_PACKED => elements.extend(listpack_entries(&node)?),
+                        _ => {
+                            return InvalidFormatSnafu {
+                                message: format!("unknown quicklist container: {container}"),
+                            }
+                            .fail()
+                        }
+                    }
+                }
+                DumpValue::List(elements)
+            }
+            _ => {
+                return InvalidFormatSnafu {
+                    message: format!("unsupported RDB object type: {rdb_type}"),
+                }
+                .fail()
+            }
+        };
+        Ok(value)
+    }
+}
+
+fn parse_double(src: &[u8]) -> Result<f64> {
+    std::str::from_utf8(src)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .context(InvalidFormatSnafu {
+            message: format!("invalid RDB double: {}", String::from_utf8_lossy(src)),
+        })
+}
+
+/// Groups the entries of a listpack holding field value pairs
+fn pairs(entries: Vec<Vec<u8>>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut pairs = Vec::with_capacity(entries.len() / 2);
+    let mut entries = entries.into_iter();
+    while let Some(first) = entries.next() {
+        let second = entries.next().context(InvalidFormatSnafu {
+            message: "odd number of listpack entries".to_string(),
+        })?;
+        pairs.push((first, second));
+    }
+    Ok(pairs)
+}
+
+fn slice(src: &[u8], start: usize, len: usize) -> Result<&[u8]> {
+    start
+        .checked_add(len)
+        .and_then(|end| src.get(start..end))
+        .context(InvalidFormatSnafu {
+            message: "listpack or intset truncated".to_string(),
+        })
+}
+
+/// The members of an intset, as decimals
+fn intset_entries(intset: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let header = slice(intset, 0, 8)?;
+    let width = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let count = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    ensure!(
+        matches!(width, 2 | 4 | 8) && intset.len() == 8 + width * count,
+        InvalidFormatSnafu {
+            message: "invalid intset layout".to_string(),
+        }
+    );
+    Ok(intset[8..]
+        .chunks_exact(width)
+        .map(|int| {
+            let int = match width {
+                2 => i16::from_le_bytes(int.try_into().unwrap()) as i64,
+                4 => i32::from_le_bytes(int.try_into().unwrap()) as i64,
+                _ => i64::from_le_bytes(int.try_into().unwrap()),
+            };
+            int.to_string().into_bytes()
+        })
+        .collect())
+}
+
+/// The entries of a listpack, the integers as decimals
+fn listpack_entries(listpack: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let header = slice(listpack, 0, LISTPACK_HEADER_LENGTH)?;
+    let total = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    ensure!(
+        total == listpack.len(),
+        InvalidFormatSnafu {
+            message: format!("listpack length {total} != {}", listpack.len()),
+        }
+    );
+
+    let mut entries = Vec::new();
+    let mut pos = LISTPACK_HEADER_LENGTH;
+    loop {
+        let encoding = slice(listpack, pos, 1)?[0];
+        if encoding == LISTPACK_EOF {
+            break;
+        }
+        let (entry, entry_len) = listpack_entry(listpack, pos)?;
+        entries.push(entry);
+        pos += entry_len + backlen_size(entry_len);
+    }
+    ensure!(
+        pos + 1 == listpack.len(),
+        InvalidFormatSnafu {
+            message: "bytes after listpack end".to_string(),
+        }
+    );
+    Ok(entries)
+}
+
+/// The entry at `pos` and the length of its encoding and data
+fn listpack_entry(listpack: &[u8], pos: usize) -> Result<(Vec<u8>, usize)> {
+    let encoding = listpack[pos];
+    let int = |len: usize| -> Result<(Vec<u8>, usize)> {
+        let mut bytes = [0u8; 8];
+        bytes[..len].copy_from_slice(slice(listpack, pos + 1, len)?);
+        // sign extension from the high bit of the last byte
+        if bytes[len - 1] & 0x80 != 0 {
+            bytes[len..].fill(0xff);
+        }
+        Ok((i64::from_le_bytes(bytes).to_string().into_bytes(), 1 + len))
+    };
+    let string = |header_len: usize, len: usize| -> Result<(Vec<u8>, usize)> {
+        let data = slice(listpack, pos + header_len, len)?;
+        Ok((data.to_vec(), header_len + len))
+    };
+
+    if encoding & 0x80 == 0 {
+        // 7 bit unsigned int
+        Ok(((encoding & 0x7f).to_string().into_bytes(), 1))
+    } else if encoding & 0xc0 == 0x80 {
+        // 6 bit string length
+        string(1, (encoding & 0x3f) as usize)
+    } else if encoding & 0xe0 == 0xc0 {
+        // 13 bit signed int
+        let low = slice(listpack, pos + 1, 1)?[0];
+        let uint = ((encoding & 0x1f) as i64) << 8 | low as i64;
+        let int = if uint >= 1 << 12 {
+            uint - (1 << 13)
+        } else {
+            uint
+        };
+        Ok((int.to_string().into_bytes(), 2))
+    } else if encoding & 0xf0 == 0xe0 {
+        // 12 bit string length
+        let low = slice(listpack, pos + 1, 1)?[0];
+        string(2, ((encoding & 0x0f) as usize) << 8 | low as usize)
+    } else {
+        match encoding {
+            0xf0 => {
+                let len = slice(listpack, pos + 1, 4)?;
+                string(5, u32::from_le_bytes(len.try_into().unwrap()) as usize)
+            }
+            0xf1 => int(2),
+            0xf2 => int(3),
+            0xf3 => int(4),
+            0xf4 => int(8),
+            _ => InvalidFormatSnafu {
+                message: format!("invalid listpack encoding: {encoding:#04x}"),
+            }
+            .fail(),
+        }
+    }
+}
+
+/// Size of the back length following an entry of `entry_len` bytes
+fn backlen_size(entry_len: usize) -> usize {
+    match entry_len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Decompresses a LZF string of `len` bytes
+fn lzf_decompress(src: &[u8], len: usize) -> Result<Vec<u8>> {
+    let invalid = || InvalidFormatSnafu {
+        message: "invalid LZF compressed string".to_string(),
+    };
+    let mut out = Vec::with_capacity(len.min(src.len() * 256));
+    let mut pos = 0;
+    while pos < src.len() {
+        let ctrl = src[pos] as usize;
+        pos += 1;
+        if ctrl < 1 << 5 {
+            // literal run of ctrl + 1 bytes
+            let literal = src.get(pos..pos + ctrl + 1).context(invalid())?;
+            out.extend_from_slice(literal);
+            pos += ctrl + 1;
+            continue;
+        }
+        // back reference of run + 2 bytes
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += *src.get(pos).context(invalid())? as usize;
+            pos += 1;
+        }
+        let distance = ((ctrl & 0x1f) << 8) + *src.get(pos).context(invalid())? as usize + 1;
+        pos += 1;
+        ensure!(distance <= out.len(), invalid());
+        let start = out.len() - distance;
+        for i in 0..run + 2 {
+            out.push(out[start + i]);
+        }
+    }
+    ensure!(out.len() == len, invalid());
+    Ok(out)
+}
+
+/// crc64 with the Jones polynomial, reflected, as the one of Redis
+fn crc64(mut crc: u64, src: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+    const TABLE: [u64; 256] = {
+        let mut table = [0u64; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u64;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ POLY
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    for byte in src {
+        crc = TABLE[((crc ^ *byte as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Appends the footer of a payload to an RDB object
+    fn with_footer(object: &[u8], version: u16) -> Vec<u8> {
+        let mut payload = object.to_vec();
+        payload.extend_from_slice(&version.to_le_bytes());
+        let crc = crc64(0, &payload);
+        payload.extend_from_slice(&crc.to_le_bytes());
+        payload
+    }
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn test_restore_redis_payload() {
+        // DUMP of a key holding "10", as documented by Redis
+        let payload = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+        let value = DumpValue::restore(payload).unwrap();
+        assert_eq!(value, DumpValue::String(b"10".to_vec()));
+
+        let mut corrupt = payload.to_vec();
+        corrupt[2] = b'9';
+        assert!(DumpValue::restore(&corrupt).is_err());
+        assert!(DumpValue::restore(&with_footer(b"\x00\x0210", 13)).is_err());
+    }
+
+    #[test]
+    fn test_dump_restore_round_trip() {
+        let long = vec![b'x'; 20000];
+        let values = [
+            DumpValue::String(b"value".to_vec()),
+            DumpValue::String(long.clone()),
+            DumpValue::Hash(vec![
+                (b"f1".to_vec(), b"v1".to_vec()),
+                (long.clone(), vec![]),
+            ]),
+            DumpValue::List(vec![b"a".to_vec(), b"b".to_vec(), b"a".to_vec()]),
+            DumpValue::Set((0..100).map(|i| format!("m{i}").into_bytes()).collect()),
+            DumpValue::ZSet(vec![
+                (b"a".to_vec(), 1.5),
+                (b"b".to_vec(), f64::NEG_INFINITY),
+                (b"c".to_vec(), -0.0),
+            ]),
+        ];
+        for value in values {
+            let payload = value.dump();
+            assert_eq!(
+                &payload[payload.len() - FOOTER_LENGTH..][..2],
+                &DUMP_RDB_VERSION.to_le_bytes()
+            );
+            assert_eq!(DumpValue::restore(&payload).unwrap(), value);
+        }
+        assert_eq!(DumpValue::ZSet(Vec::new()).data_type(), DataType::ZSet);
+    }
+
+    #[test]
+    fn test_restore_encoded_strings() {
+        let int16 = with_footer(b"\x00\xc1\x39\x30", 9);
+        assert_eq!(
+            DumpValue::restore(&int16).unwrap(),
+            DumpValue::String(b"12345".to_vec())
+        );
+        let int32 = with_footer(b"\x00\xc2\xff\xff\xff\xff", 9);
+        assert_eq!(
+            DumpValue::restore(&int32).unwrap(),
+            DumpValue::String(b"-1".to_vec())
+        );
+        // "abc" then a back reference of 9 bytes at distance 3
+        let lzf = with_footer(b"\x00\xc3\x07\x0c\x02abc\xe0\x00\x02", 9);
+        assert_eq!(
+            DumpValue::restore(&lzf).unwrap(),
+            DumpValue::String(b"abcabcabcabc".to_vec())
+        );
+    }
+
+    /// A listpack holding `entries`, each one given as its encoding and data
+    fn listpack(entries: &[&[u8]]) -> Vec<u8> {
+        let mut listpack = vec![0; LISTPACK_HEADER_LENGTH];
+        for entry in entries {
+            listpack.extend_from_slice(entry);
+            listpack.push(entry.len() as u8);
+        }
+        listpack.push(LISTPACK_EOF);
+        let total = listpack.len() as u32;
+        listpack[..4].copy_from_slice(&total.to_le_bytes());
+        listpack[4..6].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+        listpack
+    }
+
+    fn rdb_string(value: &[u8]) -> Vec<u8> {
+        let mut dst = BytesMut::new();
+        put_string(&mut dst, value);
+        dst.to_vec()
+    }
+
+    #[test]
+    fn test_restore_listpacks() {
+        // "f1" => 7, "f2" => -2 as a 13 bit int
+        let hash = listpack(&[b"\x82f1", b"\x07", b"\x82f2", b"\xdf\xfe"]);
+        let mut object = vec![RDB_TYPE_HASH_LISTPACK];
+        object.extend(rdb_string(&hash));
+        assert_eq!(
+            DumpValue::restore(&with_footer(&object, 11)).unwrap(),
+            DumpValue::Hash(vec![
+                (b"f1".to_vec(), b"7".to_vec()),
+                (b"f2".to_vec(), b"-2".to_vec())
+            ])
+        );
+
+        let zset = listpack(&[b"\x81a", b"\x833.5", b"\x81b", b"\xf1\x00\x80"]);
+        let mut object = vec![RDB_TYPE_ZSET_LISTPACK];
+        object.extend(rdb_string(&zset));
+        assert_eq!(
+            DumpValue::restore(&with_footer(&object, 11)).unwrap(),
+            DumpValue::ZSet(vec![(b"a".to_vec(), 3.5), (b"b".to_vec(), -32768.0)])
+        );
+
+        // a packed node then a plain one
+        let mut object = vec![RDB_TYPE_LIST_QUICKLIST_2, 2, QUICKLIST_NODE_PACKED as u8];
+        object.extend(rdb_string(&listpack(&[b"\x81x", b"\x01"])));
+        object.push(QUICKLIST_NODE_PLAIN as u8);
+        object.extend(rdb_string(b"plain"));
+        assert_eq!(
+            DumpValue::restore(&with_footer(&object, 11)).unwrap(),
+            DumpValue::List(vec![b"x".to_vec(), b"1".to_vec(), b"plain".to_vec()])
+        );
+
+        let mut intset = vec![2, 0, 0, 0, 2, 0, 0, 0];
+        intset.extend_from_slice(&(-5i16).to_le_bytes());
+        intset.extend_from_slice(&300i16.to_le_bytes());
+        let mut object = vec![RDB_TYPE_SET_INTSET];
+        object.extend(rdb_string(&intset));
+        assert_eq!(
+            DumpValue::restore(&with_footer(&object, 9)).unwrap(),
+            DumpValue::Set(vec![b"-5".to_vec(), b"300".to_vec()])
+        );
+
+        let mut truncated = vec![RDB_TYPE_SET_LISTPACK];
+        truncated.extend(rdb_string(&listpack(&[b"\x85ab"])));
+        assert!(DumpValue::restore(&with_footer(&truncated, 11)).is_err());
+    }
+}
@@ -19,8 +19,10 @@
 
 //! Error types for the storage engine
 
+use crate::base_value_format::{data_type_to_string, DataType};
 use crate::storage::BgTask;
 use common_macro::stack_trace_debug;
+use kstd::cancel::AbortReason;
 use snafu::{Location, Snafu};
 use std::io;
 
@@ -69,6 +71,48 @@ pub enum Error {
         location: Location,
     },
 
+    #[snafu(display("WRONGTYPE Operation against a key holding the wrong kind of value"))]
+    WrongType {
+        key: String,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("NOGROUP No such key '{}' or consumer group '{}'", key, group))]
+    NoGroup {
+        key: String,
+        group: String,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display(
+        "TRYAGAIN offset {} is not applied yet, applied offset is {}",
+        offset,
+        applied
+    ))]
+    OffsetNotApplied {
+        offset: u64,
+        applied: u64,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("CROSSSLOT Keys in request don't hash to the same slot"))]
+    CrossSlot {
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("{} of {} bytes exceeds the limit of {}", what, size, limit))]
+    ValueTooLarge {
+        what: &'static str,
+        size: u64,
+        limit: u64,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
     #[snafu(display("Invalid format: {}", message))]
     InvalidFormat {
         message: String,
@@ -76,6 +120,29 @@ pub enum Error {
         location: Location,
     },
 
+    /// A stored value too short for one of its fields. `offset` is where the
+    /// field starts, `expected` the length the value needs from there and
+    /// `actual` the length it has. Data values have type `DataType::None`.
+    #[snafu(display(
+        "Corrupt {} value{}: {} at byte {} needs {} bytes, found {}",
+        value_kind(*data_type),
+        key.as_deref().map(|key| format!(" of key '{key}'")).unwrap_or_default(),
+        field,
+        offset,
+        expected,
+        actual
+    ))]
+    CorruptValue {
+        data_type: DataType,
+        field: &'static str,
+        offset: usize,
+        expected: usize,
+        actual: usize,
+        key: Option<String>,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
     #[snafu(display("Transaction error: {}", message))]
     Transaction {
         message: String,
@@ -124,4 +191,49 @@ pub enum Error {
         #[snafu(implicit)]
         location: Location,
     },
+
+    #[snafu(display("DB index is out of range: {}", index))]
+    InvalidDbIndex {
+        index: usize,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display(
+        "Checksum mismatch: expected {:#010x}, actual {:#010x}",
+        expected,
+        actual
+    ))]
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Command aborted: {}", reason))]
+    Aborted {
+        reason: AbortReason,
+        #[snafu(implicit)]
+        location: Location,
+    },
+}
+
+/// Data values have no type of their own
+fn value_kind(data_type: DataType) -> &'static str {
+    match data_type {
+        DataType::None => "data",
+        data_type => data_type_to_string(data_type),
+    }
+}
+
+impl Error {
+    /// Adds the user key to a `CorruptValue` error, for the callers that
+    /// know which key the value belongs to. Other errors are unchanged.
+    pub fn with_key(mut self, user_key: &[u8]) -> Self {
+        if let Error::CorruptValue { key, .. } = &mut self {
+            *key = Some(String::from_utf8_lossy(user_key).into_owned());
+        }
+        self
+    }
 }
@@ -0,0 +1,307 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Behavior of the compaction filters
+//!
+//! The filter factories share one `SharedFilterConfig`, from
+//! `StorageOptions::filter_config`, and clone its current value into every
+//! filter they create. A change applies to the compactions started after it.
+//!
+//! Replication holds the filters back with a reclaim fence: the time from
+//! which a lagging replica or a running full sync may still read the data.
+//! An entry that expired before the fence is removed as usual, one expired
+//! after it is kept. An entry orphaned by a delete, an overwrite or a trim
+//! has no removal time, so none is removed while a fence is set. Moving the
+//! fence forward as the replicas catch up, and clearing it once they did,
+//! is up to replication.
+//!
+//! A removal at an upper level writes a tombstone, which every compaction
+//! below carries down. The data filters of a column family listed in
+//! `StorageOptions::filter_bottommost_only` only remove in the compactions
+//! into the bottommost level, where nothing is left to shadow.
+
+use parking_lot::RwLock;
+use rocksdb::compaction_filter_factory::CompactionFilterContext;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::filter_stats::FilterOutcome;
+
+/// What `BaseMetaFilter` does with a meta value that is empty, which no
+/// write produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterPolicy {
+    #[default]
+    Remove,
+    Keep,
+    /// Keep it and count it in `empty_meta_values_kept`, to measure how
+    /// much of a suspect data directory is affected
+    KeepAndCount,
+}
+
+impl FromStr for FilterPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "remove" => Ok(FilterPolicy::Remove),
+            "keep" => Ok(FilterPolicy::Keep),
+            "keep-and-count" => Ok(FilterPolicy::KeepAndCount),
+            _ => Err(format!(
+                "invalid filter policy '{s}', expected remove, keep or keep-and-count"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterConfig {
+    /// Time an expired value is kept past its expiration time before
+    /// compaction drops it (in microseconds)
+    pub expire_grace_micros: u64,
+    /// Whether entries whose key or value cannot be parsed are removed,
+    /// they are kept for inspection otherwise
+    pub remove_corrupt: bool,
+    /// What to do with an empty meta value
+    pub empty_value_policy: FilterPolicy,
+    /// Time from which the removed data may still be read (in
+    /// microseconds), None without a fence. Only set through
+    /// `SharedFilterConfig::set_reclaim_fence`
+    pub reclaim_fence: Option<u64>,
+    /// Whether the filter only removes in a compaction into the bottommost
+    /// level. Set per column family by the filter factories
+    pub bottommost_only: bool,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            expire_grace_micros: 0,
+            remove_corrupt: true,
+            empty_value_policy: FilterPolicy::default(),
+            reclaim_fence: None,
+            bottommost_only: false,
+        }
+    }
+}
+
+impl FilterConfig {
+    /// The time to check expiration times against at `now`, no later than
+    /// the reclaim fence
+    pub fn expire_before(&self, now: u64) -> u64 {
+        let before = now.saturating_sub(self.expire_grace_micros);
+        self.reclaim_fence.map_or(before, |fence| before.min(fence))
+    }
+
+    /// The config of a filter created for `context`, on a column family
+    /// whose filters only remove at the bottommost level if
+    /// `bottommost_only`. A full or manual compaction goes down to the
+    /// bottommost level, its filters remove at every level.
+    pub fn for_compaction(
+        mut self,
+        bottommost_only: bool,
+        context: &CompactionFilterContext,
+    ) -> Self {
+        self.bottommost_only =
+            bottommost_only && !context.is_full_compaction && !context.is_manual_compaction;
+        self
+    }
+
+    /// Whether the filter removes anything in a compaction from `level`.
+    /// The compactions from the last two levels write to the last one
+    pub fn removes_at(&self, level: u32) -> bool {
+        !self.bottommost_only || level + 2 >= NUM_LEVELS
+    }
+
+    /// `outcome`, with corrupt entries kept unless they are removed and
+    /// orphaned entries kept behind a reclaim fence
+    pub fn resolve(&self, outcome: FilterOutcome) -> FilterOutcome {
+        match outcome {
+            FilterOutcome::RemovedCorrupt if !self.remove_corrupt => FilterOutcome::Kept,
+            FilterOutcome::RemovedStaleVersion if self.reclaim_fence.is_some() => {
+                FENCED.fetch_add(1, Ordering::Relaxed);
+                FilterOutcome::Kept
+            }
+            outcome => outcome,
+        }
+    }
+}
+
+/// Orphaned entries kept behind a reclaim fence
+static FENCED: AtomicU64 = AtomicU64::new(0);
+
+/// The orphaned entries the filters kept behind a reclaim fence since the
+/// process started
+pub fn fenced_entries() -> u64 {
+    FENCED.load(Ordering::Relaxed)
+}
+
+/// Levels of the LSM tree, the RocksDB default the options keep
+const NUM_LEVELS: u32 = 7;
+
+/// No reclaim fence in `SharedFilterConfig`
+const NO_FENCE: u64 = u64::MAX;
+
+/// A `FilterConfig` shared by the filter factories. Clones share the value
+#[derive(Debug, Clone)]
+pub struct SharedFilterConfig {
+    config: Arc<RwLock<FilterConfig>>,
+    // Kept apart so that replication moves it without a lock
+    reclaim_fence: Arc<AtomicU64>,
+}
+
+impl Default for SharedFilterConfig {
+    fn default() -> Self {
+        Self::new(FilterConfig::default())
+    }
+}
+
+impl SharedFilterConfig {
+    pub fn new(config: FilterConfig) -> Self {
+        let shared = Self {
+            config: Arc::new(RwLock::new(FilterConfig::default())),
+            reclaim_fence: Arc::new(AtomicU64::new(NO_FENCE)),
+        };
+        shared.set(config);
+        shared
+    }
+
+    /// The current config, with the current reclaim fence
+    pub fn get(&self) -> FilterConfig {
+        FilterConfig {
+            reclaim_fence: self.reclaim_fence(),
+            ..self.config.read().clone()
+        }
+    }
+
+    /// Replaces the config of the filters created from now on. The reclaim
+    /// fence of `config` is ignored, see `set_reclaim_fence`
+    pub fn set(&self, config: FilterConfig) {
+        *self.config.write() = config;
+    }
+
+    /// The reclaim fence, None without one
+    pub fn reclaim_fence(&self) -> Option<u64> {
+        let fence = self.reclaim_fence.load(Ordering::Acquire);
+        (fence != NO_FENCE).then_some(fence)
+    }
+
+    /// Sets the time from which the compactions started from now on keep
+    /// the removed data (in microseconds), or clears it with None. A fence
+    /// is set before the replica or the full sync starts reading, and a
+    /// compaction already running when it is set does not honor it.
+    pub fn set_reclaim_fence(&self, fence: Option<u64>) {
+        self.reclaim_fence
+            .store(fence.unwrap_or(NO_FENCE), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_filter_config() {
+        let shared = SharedFilterConfig::default();
+        let clone = shared.clone();
+        assert_eq!(clone.get(), FilterConfig::default());
+
+        shared.set(FilterConfig {
+            expire_grace_micros: 10,
+            remove_corrupt: false,
+            ..FilterConfig::default()
+        });
+        let config = clone.get();
+        assert_eq!(config.expire_before(100), 90);
+        assert_eq!(config.expire_before(5), 0);
+        assert_eq!(
+            config.resolve(FilterOutcome::RemovedCorrupt),
+            FilterOutcome::Kept
+        );
+        assert_eq!(
+            config.resolve(FilterOutcome::RemovedExpired),
+            FilterOutcome::RemovedExpired
+        );
+    }
+
+    #[test]
+    fn test_reclaim_fence() {
+        let shared = SharedFilterConfig::default();
+        shared.set_reclaim_fence(Some(50));
+        // A new config leaves the fence alone
+        shared.set(FilterConfig {
+            expire_grace_micros: 10,
+            ..FilterConfig::default()
+        });
+        let config = shared.get();
+        assert_eq!(config.reclaim_fence, Some(50));
+        assert_eq!(config.expire_before(100), 50);
+        assert_eq!(config.expire_before(40), 30);
+
+        let fenced = fenced_entries();
+        assert_eq!(
+            config.resolve(FilterOutcome::RemovedStaleVersion),
+            FilterOutcome::Kept
+        );
+        assert!(fenced_entries() > fenced);
+        assert_eq!(
+            config.resolve(FilterOutcome::RemovedExpired),
+            FilterOutcome::RemovedExpired
+        );
+
+        shared.set_reclaim_fence(None);
+        let config = shared.get();
+        assert_eq!(config.expire_before(100), 90);
+        assert_eq!(
+            config.resolve(FilterOutcome::RemovedStaleVersion),
+            FilterOutcome::RemovedStaleVersion
+        );
+    }
+
+    #[test]
+    fn test_bottommost_only() {
+        let automatic = CompactionFilterContext {
+            is_full_compaction: false,
+            is_manual_compaction: false,
+        };
+        let config = FilterConfig::default().for_compaction(true, &automatic);
+        assert!(!config.removes_at(0));
+        assert!(!config.removes_at(4));
+        assert!(config.removes_at(5));
+        assert!(config.removes_at(6));
+        assert!(FilterConfig::default()
+            .for_compaction(false, &automatic)
+            .removes_at(0));
+
+        let manual = CompactionFilterContext {
+            is_full_compaction: false,
+            is_manual_compaction: true,
+        };
+        let config = FilterConfig::default().for_compaction(true, &manual);
+        assert!(config.removes_at(0));
+    }
+
+    #[test]
+    fn test_filter_policy_from_str() {
+        assert_eq!("remove".parse(), Ok(FilterPolicy::Remove));
+        assert_eq!("Keep".parse(), Ok(FilterPolicy::Keep));
+        assert_eq!("keep-and-count".parse(), Ok(FilterPolicy::KeepAndCount));
+        assert!("drop".parse::<FilterPolicy>().is_err());
+    }
+}
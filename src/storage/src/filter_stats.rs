@@ -0,0 +1,224 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Decisions of the compaction filters
+//!
+//! Every filter counts the entries it kept and the ones it removed, by
+//! reason, in process wide counters. A removed count that stays flat while
+//! keys are deleted means compaction is not keeping up.
+
+use rocksdb::CompactionDecision;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The compaction filters of the storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    /// `BaseMetaFilter`, on the meta column family
+    Meta = 0,
+    /// `BaseDataFilter`, on the hash, set and zset data column families
+    Data = 1,
+    /// `ZSetsScoreFilter`, on the zset score column family
+    ZSetsScore = 2,
+    /// `ListsDataFilter`, on the list data column family
+    ListsData = 3,
+    /// `TrashFilter`, on the trash column family
+    Trash = 4,
+    /// `StreamsDataFilter`, on the stream data column family
+    StreamsData = 5,
+}
+
+pub const FILTER_KINDS: [FilterKind; 6] = [
+    FilterKind::Meta,
+    FilterKind::Data,
+    FilterKind::ZSetsScore,
+    FilterKind::ListsData,
+    FilterKind::Trash,
+    FilterKind::StreamsData,
+];
+
+impl FilterKind {
+    /// The name of the filter in INFO
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Meta => "meta",
+            FilterKind::Data => "data",
+            FilterKind::ZSetsScore => "zset_score",
+            FilterKind::ListsData => "list_data",
+            FilterKind::Trash => "trash",
+            FilterKind::StreamsData => "stream_data",
+        }
+    }
+}
+
+/// What a filter did with one entry, and why
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOutcome {
+    Kept,
+    /// The entry or its owner expired, or its retention passed
+    RemovedExpired,
+    /// The entry belongs to a deleted or overwritten key, or lies outside
+    /// the live range of a list
+    RemovedStaleVersion,
+    /// The key or value could not be parsed
+    RemovedCorrupt,
+}
+
+impl FilterOutcome {
+    pub fn decision(self) -> CompactionDecision {
+        match self {
+            FilterOutcome::Kept => CompactionDecision::Keep,
+            _ => CompactionDecision::Remove,
+        }
+    }
+
+    /// The outcome of a decision taken on the entry's own expiration time
+    pub fn from_expiry(decision: CompactionDecision) -> Self {
+        match decision {
+            CompactionDecision::Remove => FilterOutcome::RemovedExpired,
+            _ => FilterOutcome::Kept,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FilterStatsSnapshot {
+    pub kept: u64,
+    pub removed_expired: u64,
+    pub removed_stale_version: u64,
+    pub removed_corrupt: u64,
+}
+
+impl FilterStatsSnapshot {
+    pub fn removed(&self) -> u64 {
+        self.removed_expired + self.removed_stale_version + self.removed_corrupt
+    }
+}
+
+struct FilterCounters {
+    kept: AtomicU64,
+    removed_expired: AtomicU64,
+    removed_stale_version: AtomicU64,
+    removed_corrupt: AtomicU64,
+}
+
+impl FilterCounters {
+    const fn new() -> Self {
+        Self {
+            kept: AtomicU64::new(0),
+            removed_expired: AtomicU64::new(0),
+            removed_stale_version: AtomicU64::new(0),
+            removed_corrupt: AtomicU64::new(0),
+        }
+    }
+}
+
+static FILTER_COUNTERS: [FilterCounters; FILTER_KINDS.len()] =
+    [const { FilterCounters::new() }; FILTER_KINDS.len()];
+
+/// Counts `outcome` for `kind` and returns its decision
+pub(crate) fn record(kind: FilterKind, outcome: FilterOutcome) -> CompactionDecision {
+    let counters = &FILTER_COUNTERS[kind as usize];
+    let counter = match outcome {
+        FilterOutcome::Kept => &counters.kept,
+        FilterOutcome::RemovedExpired => &counters.removed_expired,
+        FilterOutcome::RemovedStaleVersion => &counters.removed_stale_version,
+        FilterOutcome::RemovedCorrupt => &counters.removed_corrupt,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    outcome.decision()
+}
+
+/// The decisions of `kind` since the process started
+pub fn filter_stats(kind: FilterKind) -> FilterStatsSnapshot {
+    let counters = &FILTER_COUNTERS[kind as usize];
+    FilterStatsSnapshot {
+        kept: counters.kept.load(Ordering::Relaxed),
+        removed_expired: counters.removed_expired.load(Ordering::Relaxed),
+        removed_stale_version: counters.removed_stale_version.load(Ordering::Relaxed),
+        removed_corrupt: counters.removed_corrupt.load(Ordering::Relaxed),
+    }
+}
+
+/// The decisions of all filters together
+pub fn total_filter_stats() -> FilterStatsSnapshot {
+    FILTER_KINDS.iter().map(|kind| filter_stats(*kind)).fold(
+        FilterStatsSnapshot::default(),
+        |total, stats| FilterStatsSnapshot {
+            kept: total.kept + stats.kept,
+            removed_expired: total.removed_expired + stats.removed_expired,
+            removed_stale_version: total.removed_stale_version + stats.removed_stale_version,
+            removed_corrupt: total.removed_corrupt + stats.removed_corrupt,
+        },
+    )
+}
+
+/// Formats the counters as the compactionfilter section of INFO.
+pub fn filter_stats_info() -> String {
+    let mut info = String::from("# CompactionFilter\r\n");
+    for kind in FILTER_KINDS {
+        let name = kind.name();
+        let stats = filter_stats(kind);
+        let _ = write!(
+            info,
+            "{name}_kept:{}\r\n{name}_removed_expired:{}\r\n{name}_removed_stale_version:{}\r\n{name}_removed_corrupt:{}\r\n",
+            stats.kept, stats.removed_expired, stats.removed_stale_version, stats.removed_corrupt,
+        );
+    }
+    let _ = write!(
+        info,
+        "meta_empty_values_kept:{}\r\nfenced_entries_kept:{}\r\n",
+        crate::base_filter::empty_meta_values_kept(),
+        crate::filter_config::fenced_entries()
+    );
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_by_outcome() {
+        // Filters of tests running concurrently count too, only compare
+        // lower bounds
+        let before = filter_stats(FilterKind::Trash);
+        assert!(matches!(
+            record(FilterKind::Trash, FilterOutcome::Kept),
+            CompactionDecision::Keep
+        ));
+        for outcome in [
+            FilterOutcome::RemovedExpired,
+            FilterOutcome::RemovedStaleVersion,
+            FilterOutcome::RemovedCorrupt,
+        ] {
+            assert!(matches!(
+                record(FilterKind::Trash, outcome),
+                CompactionDecision::Remove
+            ));
+        }
+
+        let after = filter_stats(FilterKind::Trash);
+        assert!(after.kept > before.kept);
+        assert!(after.removed_expired > before.removed_expired);
+        assert!(after.removed_stale_version > before.removed_stale_version);
+        assert!(after.removed_corrupt > before.removed_corrupt);
+        assert!(total_filter_stats().removed() >= after.removed());
+        assert!(filter_stats_info().contains("trash_removed_corrupt:"));
+    }
+}
@@ -0,0 +1,262 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![cfg_attr(not(test), allow(dead_code))]
+
+//! Format version of the encoded values
+//!
+//! Every value format keeps a format version in its suffix reserve, at
+//! `RESERVE_VERSION_OFFSET`. Values written before the version byte existed
+//! have a zeroed reserve, so version 0 is the original layout of every
+//! format. When a layout changes, `FORMAT_VERSION` is bumped and a
+//! `Migration` from the previous version is added to `MIGRATIONS`; the
+//! parsers pass every value through `FormatMigrator::upgrade` first, so old
+//! values are upgraded lazily as they are read and the data directory never
+//! needs an offline rewrite. A value with a version newer than
+//! `FORMAT_VERSION` was written by a newer build and is rejected.
+
+use std::sync::OnceLock;
+
+use bytes::BytesMut;
+use snafu::ensure;
+
+use crate::base_value_format::ValueBuf;
+use crate::error::{InvalidFormatSnafu, Result};
+use crate::storage_define::{RESERVE_VERSION_OFFSET, SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH};
+
+/// The format version written by this build
+pub const FORMAT_VERSION: u8 = 0;
+
+/// The value formats, each versioned on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueLayout {
+    String,
+    BaseMeta,
+    ListsMeta,
+    StreamsMeta,
+    BaseData,
+    HashesData,
+}
+
+impl ValueLayout {
+    /// The length from the start of the reserve to the end of the value,
+    /// which is fixed for every format
+    pub fn reserve_suffix_len(self) -> usize {
+        match self {
+            ValueLayout::String
+            | ValueLayout::BaseMeta
+            | ValueLayout::ListsMeta
+            | ValueLayout::StreamsMeta => SUFFIX_RESERVE_LENGTH + 2 * TIMESTAMP_LENGTH,
+            ValueLayout::BaseData | ValueLayout::HashesData => {
+                SUFFIX_RESERVE_LENGTH + TIMESTAMP_LENGTH
+            }
+        }
+    }
+
+    /// The format version of value, None when it is too short to have a
+    /// reserve
+    pub fn version_of(self, value: &[u8]) -> Option<u8> {
+        let reserve_start = value.len().checked_sub(self.reserve_suffix_len())?;
+        Some(value[reserve_start + RESERVE_VERSION_OFFSET])
+    }
+}
+
+/// Writes the format version into value, whose reserve starts at
+/// reserve_start. Migrations call it on the values they produce
+pub fn stamp(value: &mut [u8], reserve_start: usize, version: u8) {
+    value[reserve_start + RESERVE_VERSION_OFFSET] = version;
+}
+
+/// Rewrites a value of layout from version `from` into version `from + 1`,
+/// including the new version byte
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub layout: ValueLayout,
+    pub from: u8,
+    pub migrate: fn(&[u8]) -> Result<BytesMut>,
+}
+
+/// The migrations known to this build, one per layout change
+const MIGRATIONS: &[Migration] = &[];
+
+pub struct FormatMigrator {
+    current: u8,
+    migrations: Vec<Migration>,
+}
+
+impl FormatMigrator {
+    pub fn new(current: u8) -> Self {
+        Self {
+            current,
+            migrations: Vec::new(),
+        }
+    }
+
+    /// The migrator used by the parsers, with every migration of this build
+    pub fn global() -> &'static FormatMigrator {
+        static GLOBAL: OnceLock<FormatMigrator> = OnceLock::new();
+        GLOBAL.get_or_init(|| {
+            let mut migrator = FormatMigrator::new(FORMAT_VERSION);
+            for migration in MIGRATIONS {
+                migrator.register(*migration);
+            }
+            migrator
+        })
+    }
+
+    pub fn register(&mut self, migration: Migration) -> &mut Self {
+        self.migrations.push(migration);
+        self
+    }
+
+    /// Upgrades value to the current version of its layout, returning it
+    /// unchanged when it already is. A value too short to have a reserve is
+    /// returned as is for the parser to reject.
+    pub fn upgrade(&self, layout: ValueLayout, mut value: ValueBuf) -> Result<ValueBuf> {
+        let Some(mut version) = layout.version_of(&value) else {
+            return Ok(value);
+        };
+        ensure!(
+            version <= self.current,
+            InvalidFormatSnafu {
+                message: format!(
+                    "{layout:?} value has format version {version}, newer than {}",
+                    self.current
+                )
+            }
+        );
+
+        while version < self.current {
+            let migration = self
+                .migrations
+                .iter()
+                .find(|m| m.layout == layout && m.from == version);
+            let Some(migration) = migration else {
+                return InvalidFormatSnafu {
+                    message: format!("no migration for {layout:?} value format version {version}"),
+                }
+                .fail();
+            };
+
+            value = (migration.migrate)(&value)?.into();
+            let migrated = layout.version_of(&value);
+            ensure!(
+                migrated == Some(version + 1),
+                InvalidFormatSnafu {
+                    message: format!(
+                        "{layout:?} migration from version {version} produced version {migrated:?}"
+                    )
+                }
+            );
+            version += 1;
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_data_value_format::{BaseDataValue, ParsedBaseDataValue};
+    use crate::error::Error;
+    use bytes::BufMut;
+
+    // Version 1 of a made up base data layout appends a marker byte to the
+    // user value
+    fn add_marker(value: &[u8]) -> Result<BytesMut> {
+        let suffix_len = ValueLayout::BaseData.reserve_suffix_len();
+        let reserve_start = value.len() - suffix_len;
+        let mut upgraded = BytesMut::with_capacity(value.len() + 1);
+        upgraded.put_slice(&value[..reserve_start]);
+        upgraded.put_u8(b'!');
+        upgraded.put_slice(&value[reserve_start..]);
+        stamp(&mut upgraded, reserve_start + 1, 1);
+        Ok(upgraded)
+    }
+
+    fn marker_migration() -> Migration {
+        Migration {
+            layout: ValueLayout::BaseData,
+            from: 0,
+            migrate: add_marker,
+        }
+    }
+
+    #[test]
+    fn test_current_version_unchanged() {
+        let encoded = BaseDataValue::new(&b"value"[..]).encode();
+        assert_eq!(
+            ValueLayout::BaseData.version_of(&encoded),
+            Some(FORMAT_VERSION)
+        );
+
+        let upgraded = FormatMigrator::global()
+            .upgrade(ValueLayout::BaseData, encoded.clone().into())
+            .unwrap();
+        assert_eq!(upgraded, encoded);
+    }
+
+    #[test]
+    fn test_upgrade_old_version() {
+        let mut migrator = FormatMigrator::new(1);
+        migrator.register(marker_migration());
+
+        let encoded = BaseDataValue::new(&b"value"[..]).encode();
+        let upgraded = migrator
+            .upgrade(ValueLayout::BaseData, encoded.into())
+            .unwrap();
+        assert_eq!(ValueLayout::BaseData.version_of(&upgraded), Some(1));
+
+        assert_eq!(&upgraded[..6], b"value!");
+
+        // Already current, the migration does not run again
+        let again = migrator
+            .upgrade(ValueLayout::BaseData, upgraded.clone())
+            .unwrap();
+        assert_eq!(again, upgraded);
+    }
+
+    #[test]
+    fn test_newer_version_rejected() {
+        let mut encoded = BaseDataValue::new(&b"value"[..]).encode();
+        let reserve_start = encoded.len() - ValueLayout::BaseData.reserve_suffix_len();
+        stamp(&mut encoded, reserve_start, FORMAT_VERSION + 1);
+
+        let result =
+            FormatMigrator::global().upgrade(ValueLayout::BaseData, encoded.clone().into());
+        assert!(matches!(result, Err(Error::InvalidFormat { .. })));
+        assert!(ParsedBaseDataValue::new(&encoded[..]).is_err());
+    }
+
+    #[test]
+    fn test_missing_migration() {
+        let migrator = FormatMigrator::new(1);
+        let encoded = BaseDataValue::new(&b"value"[..]).encode();
+        let result = migrator.upgrade(ValueLayout::BaseData, encoded.into());
+        assert!(matches!(result, Err(Error::InvalidFormat { .. })));
+    }
+
+    #[test]
+    fn test_short_value_left_to_parser() {
+        let short = BytesMut::from(&b"abc"[..]);
+        let result = FormatMigrator::global()
+            .upgrade(ValueLayout::BaseData, short.clone().into())
+            .unwrap();
+        assert_eq!(result, short);
+    }
+}
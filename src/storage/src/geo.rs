@@ -0,0 +1,486 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Geohash encoding for the GEO commands.
+//!
+//! A location is stored as a member of a sorted set whose score is the 52 bit
+//! interleaved geohash of its longitude and latitude, as in Redis. A search
+//! area maps to at most nine score ranges, the geohash box around the center
+//! and its neighbors, which are scanned on the zset score keys.
+
+pub const GEO_STEP_MAX: u8 = 26;
+pub const GEO_LAT_MIN: f64 = -85.05112878;
+pub const GEO_LAT_MAX: f64 = 85.05112878;
+pub const GEO_LONG_MIN: f64 = -180.0;
+pub const GEO_LONG_MAX: f64 = 180.0;
+
+/// Earth's quadratic mean radius for WGS-84, the value Redis uses
+pub const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+
+const GEO_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// The geohash of a box, `step` bits for each of longitude and latitude.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GeoHashBits {
+    pub bits: u64,
+    pub step: u8,
+}
+
+impl GeoHashBits {
+    /// The range of the scores of the locations in the box, min inclusive
+    /// and max exclusive
+    pub fn score_range(&self) -> (f64, f64) {
+        let shift = 2 * (GEO_STEP_MAX - self.step) as u32;
+        (
+            (self.bits << shift) as f64,
+            ((self.bits + 1) << shift) as f64,
+        )
+    }
+
+    fn move_x(mut self, d: i8) -> Self {
+        if d == 0 {
+            return self;
+        }
+        let mut x = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let y = self.bits & 0x5555555555555555;
+        let zz = 0x5555555555555555u64 >> (64 - 2 * self.step as u32);
+        if d > 0 {
+            x = x.wrapping_add(zz + 1);
+        } else {
+            x |= zz;
+            x = x.wrapping_sub(zz + 1);
+        }
+        x &= 0xaaaaaaaaaaaaaaaau64 >> (64 - 2 * self.step as u32);
+        self.bits = x | y;
+        self
+    }
+
+    fn move_y(mut self, d: i8) -> Self {
+        if d == 0 {
+            return self;
+        }
+        let x = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let mut y = self.bits & 0x5555555555555555;
+        let zz = 0xaaaaaaaaaaaaaaaau64 >> (64 - 2 * self.step as u32);
+        if d > 0 {
+            y = y.wrapping_add(zz + 1);
+        } else {
+            y |= zz;
+            y = y.wrapping_sub(zz + 1);
+        }
+        y &= 0x5555555555555555u64 >> (64 - 2 * self.step as u32);
+        self.bits = x | y;
+        self
+    }
+
+    /// The eight boxes of the same step around this one
+    pub fn neighbors(&self) -> GeoHashNeighbors {
+        GeoHashNeighbors {
+            north: self.move_y(1),
+            south: self.move_y(-1),
+            east: self.move_x(1),
+            west: self.move_x(-1),
+            north_east: self.move_x(1).move_y(1),
+            north_west: self.move_x(-1).move_y(1),
+            south_east: self.move_x(1).move_y(-1),
+            south_west: self.move_x(-1).move_y(-1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeoHashNeighbors {
+    pub north: GeoHashBits,
+    pub south: GeoHashBits,
+    pub east: GeoHashBits,
+    pub west: GeoHashBits,
+    pub north_east: GeoHashBits,
+    pub north_west: GeoHashBits,
+    pub south_east: GeoHashBits,
+    pub south_west: GeoHashBits,
+}
+
+/// The longitude and latitude ranges of a geohash box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoHashArea {
+    pub hash: GeoHashBits,
+    pub longitude: (f64, f64),
+    pub latitude: (f64, f64),
+}
+
+// Spreads the 32 bits of x over the even bits and those of y over the odd
+// bits of the result
+fn interleave64(x: u32, y: u32) -> u64 {
+    const B: [u64; 5] = [
+        0x5555555555555555,
+        0x3333333333333333,
+        0x0F0F0F0F0F0F0F0F,
+        0x00FF00FF00FF00FF,
+        0x0000FFFF0000FFFF,
+    ];
+    const S: [u32; 5] = [1, 2, 4, 8, 16];
+
+    let mut x = x as u64;
+    let mut y = y as u64;
+    for (b, s) in B.iter().zip(S).rev() {
+        x = (x | (x << s)) & b;
+        y = (y | (y << s)) & b;
+    }
+    x | (y << 1)
+}
+
+// The reverse of interleave64, the even bits in the low and the odd bits in
+// the high 32 bits
+fn deinterleave64(interleaved: u64) -> u64 {
+    const B: [u64; 6] = [
+        0x5555555555555555,
+        0x3333333333333333,
+        0x0F0F0F0F0F0F0F0F,
+        0x00FF00FF00FF00FF,
+        0x0000FFFF0000FFFF,
+        0x00000000FFFFFFFF,
+    ];
+    const S: [u32; 6] = [0, 1, 2, 4, 8, 16];
+
+    let mut x = interleaved;
+    let mut y = interleaved >> 1;
+    for (b, s) in B.iter().zip(S) {
+        x = (x | (x >> s)) & b;
+        y = (y | (y >> s)) & b;
+    }
+    x | (y << 32)
+}
+
+fn encode_in(
+    longitude: f64,
+    latitude: f64,
+    long_range: (f64, f64),
+    lat_range: (f64, f64),
+    step: u8,
+) -> Option<GeoHashBits> {
+    if step == 0
+        || step > 32
+        || !(long_range.0..=long_range.1).contains(&longitude)
+        || !(lat_range.0..=lat_range.1).contains(&latitude)
+    {
+        return None;
+    }
+    let cells = (1u64 << step) as f64;
+    let lat_offset = (latitude - lat_range.0) / (lat_range.1 - lat_range.0) * cells;
+    let long_offset = (longitude - long_range.0) / (long_range.1 - long_range.0) * cells;
+    Some(GeoHashBits {
+        bits: interleave64(lat_offset as u32, long_offset as u32),
+        step,
+    })
+}
+
+/// The geohash of a location, None when it is out of the ranges GEOADD
+/// accepts
+pub fn encode(longitude: f64, latitude: f64, step: u8) -> Option<GeoHashBits> {
+    encode_in(
+        longitude,
+        latitude,
+        (GEO_LONG_MIN, GEO_LONG_MAX),
+        (GEO_LAT_MIN, GEO_LAT_MAX),
+        step,
+    )
+}
+
+/// The zset score of a location
+pub fn score(longitude: f64, latitude: f64) -> Option<f64> {
+    encode(longitude, latitude, GEO_STEP_MAX).map(|hash| hash.bits as f64)
+}
+
+/// The box of a geohash
+pub fn decode(hash: GeoHashBits) -> GeoHashArea {
+    let separated = deinterleave64(hash.bits);
+    let cells = (1u64 << hash.step) as f64;
+    let lat_cell = separated as u32 as f64;
+    let long_cell = (separated >> 32) as u32 as f64;
+    let lat_scale = GEO_LAT_MAX - GEO_LAT_MIN;
+    let long_scale = GEO_LONG_MAX - GEO_LONG_MIN;
+    GeoHashArea {
+        hash,
+        latitude: (
+            GEO_LAT_MIN + lat_cell / cells * lat_scale,
+            GEO_LAT_MIN + (lat_cell + 1.0) / cells * lat_scale,
+        ),
+        longitude: (
+            GEO_LONG_MIN + long_cell / cells * long_scale,
+            GEO_LONG_MIN + (long_cell + 1.0) / cells * long_scale,
+        ),
+    }
+}
+
+/// The longitude and latitude of the center of a geohash box
+pub fn decode_to_lonlat(hash: GeoHashBits) -> (f64, f64) {
+    let area = decode(hash);
+    let longitude = ((area.longitude.0 + area.longitude.1) / 2.0).clamp(GEO_LONG_MIN, GEO_LONG_MAX);
+    let latitude = ((area.latitude.0 + area.latitude.1) / 2.0).clamp(GEO_LAT_MIN, GEO_LAT_MAX);
+    (longitude, latitude)
+}
+
+/// The longitude and latitude stored as a zset score (GEOPOS)
+pub fn score_to_lonlat(score: f64) -> (f64, f64) {
+    decode_to_lonlat(GeoHashBits {
+        bits: score as u64,
+        step: GEO_STEP_MAX,
+    })
+}
+
+/// The standard 11 character geohash of a zset score (GEOHASH). The
+/// standard geohash uses latitudes in [-90, 90] rather than the Mercator
+/// range of the score
+pub fn score_to_geohash_string(score: f64) -> String {
+    let (longitude, latitude) = score_to_lonlat(score);
+    let bits = encode_in(
+        longitude,
+        latitude,
+        (-180.0, 180.0),
+        (-90.0, 90.0),
+        GEO_STEP_MAX,
+    )
+    .map_or(0, |hash| hash.bits);
+    (0..11)
+        .map(|i| {
+            // 52 bits give 10 full characters, the last one is padded
+            let index = if i == 10 {
+                0
+            } else {
+                (bits >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            GEO_ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+fn deg_rad(deg: f64) -> f64 {
+    deg.to_radians()
+}
+
+fn rad_deg(rad: f64) -> f64 {
+    rad.to_degrees()
+}
+
+/// The great-circle distance in meters between two locations (GEODIST)
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let lat1r = deg_rad(lat1);
+    let lat2r = deg_rad(lat2);
+    let u = ((lat2r - lat1r) / 2.0).sin();
+    let v = (deg_rad(lon2 - lon1) / 2.0).sin();
+    2.0 * EARTH_RADIUS_IN_METERS * (u * u + lat1r.cos() * lat2r.cos() * v * v).sqrt().asin()
+}
+
+/// The geohash step whose boxes are about the size of the radius, smaller
+/// near the poles where boxes get narrow
+pub fn estimate_steps_by_radius(mut range_meters: f64, latitude: f64) -> u8 {
+    if range_meters == 0.0 {
+        return GEO_STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    while range_meters < MERCATOR_MAX {
+        range_meters *= 2.0;
+        step += 1;
+    }
+    step -= 2;
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, GEO_STEP_MAX as i32) as u8
+}
+
+/// The `[min_lon, min_lat, max_lon, max_lat]` box around the circle of
+/// `radius_meters` around a location
+pub fn bounding_box(longitude: f64, latitude: f64, radius_meters: f64) -> [f64; 4] {
+    let lat_delta = rad_deg(radius_meters / EARTH_RADIUS_IN_METERS);
+    let long_delta_top =
+        rad_deg(radius_meters / EARTH_RADIUS_IN_METERS / deg_rad(latitude + lat_delta).cos());
+    let long_delta_bottom =
+        rad_deg(radius_meters / EARTH_RADIUS_IN_METERS / deg_rad(latitude - lat_delta).cos());
+    // the circle is widest on the side closer to the equator
+    let long_delta = if latitude < 0.0 {
+        long_delta_bottom
+    } else {
+        long_delta_top
+    };
+    [
+        longitude - long_delta,
+        latitude - lat_delta,
+        longitude + long_delta,
+        latitude + lat_delta,
+    ]
+}
+
+/// The score ranges to scan for the locations within `radius_meters` of a
+/// location (GEOSEARCH BYRADIUS): the box of the center and those of its
+/// neighbors that reach the bounding box. Every range is min inclusive and
+/// max exclusive, the locations found still have to be checked with
+/// `distance`. None when the center is out of range
+pub fn search_ranges(longitude: f64, latitude: f64, radius_meters: f64) -> Option<Vec<(f64, f64)>> {
+    let [min_lon, min_lat, max_lon, max_lat] = bounding_box(longitude, latitude, radius_meters);
+    let mut step = estimate_steps_by_radius(radius_meters, latitude);
+    let mut hash = encode(longitude, latitude, step)?;
+    let mut neighbors = hash.neighbors();
+
+    // The estimate can leave part of the circle out of the nine boxes, one
+    // step up makes every box twice as large
+    let too_small = decode(neighbors.north).latitude.1 < max_lat
+        || decode(neighbors.south).latitude.0 > min_lat
+        || decode(neighbors.east).longitude.1 < max_lon
+        || decode(neighbors.west).longitude.0 > min_lon;
+    if step > 1 && too_small {
+        step -= 1;
+        hash = encode(longitude, latitude, step)?;
+        neighbors = hash.neighbors();
+    }
+
+    let area = decode(hash);
+    let mut boxes = vec![hash];
+    let (reaches_south, reaches_north, reaches_west, reaches_east) = if step >= 2 {
+        (
+            area.latitude.0 >= min_lat,
+            area.latitude.1 <= max_lat,
+            area.longitude.0 >= min_lon,
+            area.longitude.1 <= max_lon,
+        )
+    } else {
+        (true, true, true, true)
+    };
+    let candidates = [
+        (neighbors.north, reaches_north),
+        (neighbors.south, reaches_south),
+        (neighbors.east, reaches_east),
+        (neighbors.west, reaches_west),
+        (neighbors.north_east, reaches_north && reaches_east),
+        (neighbors.north_west, reaches_north && reaches_west),
+        (neighbors.south_east, reaches_south && reaches_east),
+        (neighbors.south_west, reaches_south && reaches_west),
+    ];
+    for (neighbor, reaches) in candidates {
+        // near the poles and the antimeridian neighbors can repeat
+        if reaches && !boxes.contains(&neighbor) {
+            boxes.push(neighbor);
+        }
+    }
+    Some(boxes.iter().map(GeoHashBits::score_range).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // GEOADD Sicily 13.361389 38.115556 "Palermo" 15.087269 37.502669 "Catania"
+    const PALERMO: (f64, f64) = (13.361389, 38.115556);
+    const CATANIA: (f64, f64) = (15.087269, 37.502669);
+
+    #[test]
+    fn test_interleave() {
+        for (x, y) in [
+            (0, 0),
+            (1, 0),
+            (0, 1),
+            (u32::MAX, 0),
+            (0x1234_5678, 0x9abc_def0),
+        ] {
+            let interleaved = interleave64(x, y);
+            assert_eq!(deinterleave64(interleaved), ((y as u64) << 32) | x as u64);
+        }
+        assert_eq!(interleave64(1, 0), 1);
+        assert_eq!(interleave64(0, 1), 2);
+    }
+
+    #[test]
+    fn test_score_matches_redis() {
+        assert_eq!(score(PALERMO.0, PALERMO.1), Some(3479099956230698.0));
+        assert_eq!(score(CATANIA.0, CATANIA.1), Some(3479447370796909.0));
+        assert_eq!(score(0.0, 86.0), None);
+        assert_eq!(score(181.0, 0.0), None);
+
+        let (lon, lat) = score_to_lonlat(3479099956230698.0);
+        assert!((lon - PALERMO.0).abs() < 1e-5);
+        assert!((lat - PALERMO.1).abs() < 1e-5);
+
+        assert_eq!(score_to_geohash_string(3479099956230698.0), "sqc8b49rny0");
+        assert_eq!(score_to_geohash_string(3479447370796909.0), "sqdtr74hyu0");
+    }
+
+    #[test]
+    fn test_distance() {
+        let d = distance(PALERMO.0, PALERMO.1, CATANIA.0, CATANIA.1);
+        assert!((d - 166274.15).abs() < 1.0, "{d}");
+        assert_eq!(distance(PALERMO.0, PALERMO.1, PALERMO.0, PALERMO.1), 0.0);
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let hash = encode(PALERMO.0, PALERMO.1, 10).unwrap();
+        let area = decode(hash);
+        let neighbors = hash.neighbors();
+
+        let north = decode(neighbors.north);
+        assert_eq!(north.longitude, area.longitude);
+        assert!((north.latitude.0 - area.latitude.1).abs() < 1e-9);
+        let west = decode(neighbors.west);
+        assert_eq!(west.latitude, area.latitude);
+        assert!((west.longitude.1 - area.longitude.0).abs() < 1e-9);
+        let south_east = decode(neighbors.south_east);
+        assert!((south_east.latitude.1 - area.latitude.0).abs() < 1e-9);
+        assert!((south_east.longitude.0 - area.longitude.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_search_ranges_cover_the_circle() {
+        let radius = 200_000.0;
+        let ranges = search_ranges(PALERMO.0, PALERMO.1, radius).unwrap();
+        assert!(!ranges.is_empty() && ranges.len() <= 9);
+
+        let catania = score(CATANIA.0, CATANIA.1).unwrap();
+        assert!(ranges
+            .iter()
+            .any(|&(min, max)| min <= catania && catania < max));
+
+        // every point of a ring just inside the circle is in a range
+        for i in 0..36 {
+            let angle = (i as f64 * 10.0).to_radians();
+            let delta = rad_deg(radius * 0.99 / EARTH_RADIUS_IN_METERS);
+            let lat = PALERMO.1 + delta * angle.sin();
+            let lon = PALERMO.0 + delta * angle.cos() / deg_rad(lat).cos();
+            if distance(PALERMO.0, PALERMO.1, lon, lat) > radius {
+                continue;
+            }
+            let s = score(lon, lat).unwrap();
+            assert!(
+                ranges.iter().any(|&(min, max)| min <= s && s < max),
+                "{lon} {lat}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_estimate_steps() {
+        assert_eq!(estimate_steps_by_radius(0.0, 0.0), GEO_STEP_MAX);
+        let step = estimate_steps_by_radius(1000.0, 0.0);
+        assert!(estimate_steps_by_radius(1000.0, 70.0) < step);
+        assert!(estimate_steps_by_radius(100_000.0, 0.0) < step);
+        assert_eq!(estimate_steps_by_radius(f64::MAX, 0.0), 1);
+    }
+}
@@ -0,0 +1,384 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![cfg_attr(not(test), allow(dead_code))]
+
+use crate::coding::{decode_fixed, encode_fixed};
+use crate::error::Result;
+use crate::storage_define::{
+    decode_user_key, encode_user_key, ENCODED_KEY_DELIM_SIZE, NEED_TRANSFORM_CHARACTER,
+};
+use bytes::{Bytes, BytesMut};
+
+// Constants for fixed-length fields
+const RESERVE1_LEN: usize = 8;
+const RESERVE2_LEN: usize = 16;
+const U64_LEN: usize = 8;
+
+/*
+ * Format for Hash data key
+ * | reserve1 | key | version | field | reserve2 |
+ * |    8B    |     |    8B   |       |   16B    |
+ *
+ * The version ties every field to the meta value stored under the same user
+ * key, so bumping the version in `BaseMetaValue` invalidates all old fields.
+ *
+ * The field needs no escaping or length: zero bytes of the key are escaped
+ * and the key ends with "\x00\x00", the version has a fixed length and the
+ * field runs up to the fixed-length reserve2. So no two (key, version, field)
+ * share an encoding, and the fields of a hash sort in byte order. Set member
+ * keys share this layout (see sets_member_key_format).
+ */
+pub struct HashesDataKey {
+    reserve1: [u8; 8],
+    key: Vec<u8>,
+    version: u64,
+    field: Vec<u8>,
+    reserve2: [u8; 16],
+}
+
+impl HashesDataKey {
+    pub fn new(key: &[u8], version: u64, field: &[u8]) -> Self {
+        Self::with_reserves(key, version, field, [0; 8], [0; 16])
+    }
+
+    pub fn with_reserves(
+        key: &[u8],
+        version: u64,
+        field: &[u8],
+        reserve1: [u8; 8],
+        reserve2: [u8; 16],
+    ) -> Self {
+        Self {
+            reserve1,
+            key: key.to_vec(),
+            version,
+            field: field.to_vec(),
+            reserve2,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut dst = self.encode_seek_key()?;
+        dst.extend_from_slice(&self.reserve2);
+        Ok(dst)
+    }
+
+    /// Encodes the key without the trailing reserve2, used as the seek target
+    /// when iterating from a given field.
+    pub fn encode_seek_key(&self) -> Result<Vec<u8>> {
+        let mut dst = self.encode_prefix()?;
+        dst.reserve(self.field.len() + RESERVE2_LEN);
+        dst.extend_from_slice(&self.field);
+        Ok(dst)
+    }
+
+    /// Encodes `| reserve1 | key | version |`, which is shared by every field
+    /// of the same hash and therefore usable as a prefix for scans.
+    pub fn encode_prefix(&self) -> Result<Vec<u8>> {
+        // Each NEED_TRANSFORM_CHARACTER (0) is escaped as two bytes, so we need to
+        // account for the extra space.
+        let nzero = self
+            .key
+            .iter()
+            .filter(|&&c| c == NEED_TRANSFORM_CHARACTER as u8)
+            .count();
+        let encoded_key_len = self.key.len() + nzero + ENCODED_KEY_DELIM_SIZE;
+
+        let mut dst = Vec::with_capacity(
+            RESERVE1_LEN + encoded_key_len + U64_LEN + self.field.len() + RESERVE2_LEN,
+        );
+
+        // 1. reserve1 (8 bytes)
+        dst.extend_from_slice(&self.reserve1);
+
+        // 2. encoded user key
+        let mut temp_buf = BytesMut::with_capacity(encoded_key_len);
+        encode_user_key(&self.key, &mut temp_buf)?;
+        dst.extend_from_slice(&temp_buf);
+
+        // 3. version (8 bytes)
+        let mut version_buf = [0u8; U64_LEN];
+        encode_fixed(&mut version_buf, self.version);
+        dst.extend_from_slice(&version_buf);
+
+        Ok(dst)
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        &self.reserve1
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        &self.reserve2
+    }
+}
+
+pub struct ParsedHashesDataKey {
+    key_str: Vec<u8>,
+    reserve1: [u8; 8],
+    version: u64,
+    field: Bytes,
+    reserve2: [u8; 16],
+}
+
+impl ParsedHashesDataKey {
+    pub fn from_string(key: &str) -> Result<Self> {
+        Self::decode(key.as_bytes())
+    }
+
+    pub fn from_slice(key: &[u8]) -> Result<Self> {
+        Self::decode(key)
+    }
+
+    /// Parses a key the caller owns, the field then shares its memory
+    pub fn from_bytes(key: Bytes) -> Result<Self> {
+        let mut parsed = Self::decode(&key)?;
+        let field_len = parsed.field.len();
+        let field_end = key.len() - RESERVE2_LEN;
+        parsed.field = key.slice(field_end - field_len..field_end);
+        Ok(parsed)
+    }
+
+    pub fn decode(key: &[u8]) -> Result<Self> {
+        let min_len = RESERVE1_LEN + ENCODED_KEY_DELIM_SIZE + U64_LEN + RESERVE2_LEN;
+        if key.len() < min_len {
+            return Err(crate::error::Error::InvalidFormat {
+                message: "Key too short for hash data key".to_string(),
+                location: snafu::location!(),
+            });
+        }
+
+        // skip head reserve1 and tail reserve2
+        let encoded_key_start = RESERVE1_LEN;
+        let encoded_key_end = key.len() - RESERVE2_LEN;
+        let encoded_key_slice = &key[encoded_key_start..encoded_key_end];
+
+        // Zero bytes of the user key are escaped as "\x00\x01", so the first
+        // "\x00\x00" is always the delimiter even if the field contains zeros.
+        let pos = encoded_key_slice
+            .windows(ENCODED_KEY_DELIM_SIZE)
+            .position(|window| window == b"\x00\x00")
+            .map(|p| p + ENCODED_KEY_DELIM_SIZE)
+            .ok_or_else(|| crate::error::Error::InvalidFormat {
+                message: "Encoded key delimiter not found".to_string(),
+                location: snafu::location!(),
+            })?;
+
+        let mut key_str_buf = BytesMut::with_capacity(pos);
+        decode_user_key(&encoded_key_slice[..pos], &mut key_str_buf)?;
+        let key_str = key_str_buf.to_vec();
+
+        // version follows the encoded key, the field takes the rest up to reserve2
+        let version_offset = encoded_key_start + pos;
+        let field_offset = version_offset + U64_LEN;
+        if field_offset > encoded_key_end {
+            return Err(crate::error::Error::InvalidFormat {
+                message: "Key too short for version field".to_string(),
+                location: snafu::location!(),
+            });
+        }
+
+        let version = decode_fixed(&key[version_offset..field_offset]);
+        let field = Bytes::copy_from_slice(&key[field_offset..encoded_key_end]);
+
+        let reserve1 =
+            key[..RESERVE1_LEN]
+                .try_into()
+                .map_err(|_| crate::error::Error::InvalidFormat {
+                    message: "Failed to read reserve1 field".to_string(),
+                    location: snafu::location!(),
+                })?;
+
+        let reserve2 =
+            key[encoded_key_end..]
+                .try_into()
+                .map_err(|_| crate::error::Error::InvalidFormat {
+                    message: "Failed to read reserve2 field".to_string(),
+                    location: snafu::location!(),
+                })?;
+
+        Ok(Self {
+            key_str,
+            reserve1,
+            version,
+            field,
+            reserve2,
+        })
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key_str
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn field(&self) -> &[u8] {
+        &self.field
+    }
+
+    pub fn into_field(self) -> Bytes {
+        self.field
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        &self.reserve1
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        &self.reserve2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn test_encode_decode() -> Result<()> {
+        let data_key = HashesDataKey::new(b"test\x00key", 123, b"field");
+        let encoded = data_key.encode()?;
+
+        let parsed = ParsedHashesDataKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), b"test\x00key");
+        assert_eq!(parsed.version(), 123);
+        assert_eq!(parsed.field(), b"field");
+        Ok(())
+    }
+
+    #[test]
+    fn test_special_characters_in_field() -> Result<()> {
+        let key = b"special\x00\x01\x00chars";
+        let field = b"\x00\x00field\x00\x01";
+
+        let encoded = HashesDataKey::new(key, 999, field).encode()?;
+        let parsed = ParsedHashesDataKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), key);
+        assert_eq!(parsed.version(), 999);
+        assert_eq!(parsed.field(), field);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_key_and_field() -> Result<()> {
+        let encoded = HashesDataKey::new(b"", 0, b"").encode()?;
+        let parsed = ParsedHashesDataKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), b"");
+        assert_eq!(parsed.version(), 0);
+        assert_eq!(parsed.field(), b"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix_matches_all_fields() -> Result<()> {
+        let prefix = HashesDataKey::new(b"hash", 7, b"").encode_prefix()?;
+        for field in [&b"a"[..], b"b\x00c", b""] {
+            let encoded = HashesDataKey::new(b"hash", 7, field).encode()?;
+            assert!(encoded.starts_with(&prefix));
+        }
+
+        let other_version = HashesDataKey::new(b"hash", 8, b"a").encode()?;
+        assert!(!other_version.starts_with(&prefix));
+        let other_key = HashesDataKey::new(b"hash2", 7, b"a").encode()?;
+        assert!(!other_key.starts_with(&prefix));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_encoding() {
+        let result = ParsedHashesDataKey::from_slice(b"invalid\x00\x02data");
+        assert!(matches!(result, Err(Error::InvalidFormat { .. })));
+
+        // delimiter present but no room left for the version
+        let mut truncated = vec![0u8; RESERVE1_LEN];
+        truncated.extend_from_slice(b"key\x00\x00");
+        truncated.extend_from_slice(&[0u8; RESERVE2_LEN]);
+        let result = ParsedHashesDataKey::from_slice(&truncated);
+        assert!(matches!(result, Err(Error::InvalidFormat { .. })));
+    }
+
+    #[test]
+    fn test_no_collisions() -> Result<()> {
+        // the same bytes split differently between key and field
+        let cases: [(&[u8], &[u8]); 5] = [
+            (b"a", b"\x00\x00b"),
+            (b"a\x00", b"\x00b"),
+            (b"a\x00\x00", b"b"),
+            (b"a\x00\x00b", b""),
+            (b"a\x00\x01", b"b"),
+        ];
+        let mut encoded = Vec::new();
+        for (key, field) in cases {
+            let data_key = HashesDataKey::new(key, 1, field).encode()?;
+            let parsed = ParsedHashesDataKey::from_slice(&data_key)?;
+            assert_eq!(parsed.key(), key);
+            assert_eq!(parsed.field(), field);
+            encoded.push(data_key);
+        }
+        encoded.sort();
+        encoded.dedup();
+        assert_eq!(encoded.len(), cases.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_field_order() -> Result<()> {
+        let fields = [&b""[..], b"\x00", b"\x00\x00", b"a", b"a\x00", b"ab", b"b"];
+        let encoded = fields
+            .iter()
+            .map(|field| HashesDataKey::new(b"hash", 1, field).encode())
+            .collect::<Result<Vec<_>>>()?;
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_bytes_shares_the_field() -> Result<()> {
+        let encoded = Bytes::from(HashesDataKey::new(b"hash", 1, b"field").encode()?);
+        let field = ParsedHashesDataKey::from_bytes(encoded.clone())?.into_field();
+        assert_eq!(field, &b"field"[..]);
+        let field_start = encoded.len() - RESERVE2_LEN - field.len();
+        assert_eq!(field.as_ptr(), encoded[field_start..].as_ptr());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserve_fields_round_trip() -> Result<()> {
+        let reserve1 = [1, 2, 3, 4, 5, 6, 7, 8];
+        let reserve2 = [
+            9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        ];
+
+        let data_key = HashesDataKey::with_reserves(b"test_key", 456, b"f", reserve1, reserve2);
+        let encoded = data_key.encode()?;
+        let parsed = ParsedHashesDataKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), b"test_key");
+        assert_eq!(parsed.version(), 456);
+        assert_eq!(parsed.field(), b"f");
+        assert_eq!(parsed.reserve1(), &reserve1);
+        assert_eq!(parsed.reserve2(), &reserve2);
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![cfg_attr(not(test), allow(dead_code))]
+
+use crate::{
+    base_data_value_format::DATA_VALUE_ETIME_FLAG,
+    base_value_format::{DataType, InternalValue, ParsedInternalValue, ValueBuf},
+    checksum, clock, delegate_internal_value, delegate_parsed_value,
+    error::{CorruptValueSnafu, Result},
+    format_version::{FormatMigrator, ValueLayout},
+    impl_value_format,
+    storage_define::{RESERVE_FLAGS_OFFSET, SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH},
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use rocksdb::CompactionDecision;
+use snafu::ensure;
+
+/*
+ * hash data value format
+ * | value | etime | reserve | ctime |
+ * |       |  8B   |   16B   |   8B  |
+ *
+ * The etime of the field (HEXPIRE) is only present when the flags byte of
+ * the reserve has HASH_FIELD_ETIME_FLAG set, so a field without expiration
+ * keeps the layout written before field expiration existed. This is the
+ * base data value layout, which the data filter relies on.
+ */
+pub const HASH_FIELD_ETIME_FLAG: u8 = DATA_VALUE_ETIME_FLAG;
+
+const SUFFIX_LENGTH: usize = SUFFIX_RESERVE_LENGTH + TIMESTAMP_LENGTH;
+
+pub struct HashesDataValue {
+    inner: InternalValue,
+}
+
+delegate_internal_value!(HashesDataValue);
+impl_value_format!(HashesDataValue, ParsedHashesDataValue);
+impl HashesDataValue {
+    pub fn new<T>(user_value: T) -> Self
+    where
+        T: Into<Bytes>,
+    {
+        Self {
+            inner: InternalValue::new(DataType::None, user_value),
+        }
+    }
+
+    pub fn encode(&self) -> BytesMut {
+        let etime_len = if self.inner.etime == 0 {
+            0
+        } else {
+            TIMESTAMP_LENGTH
+        };
+        let needed = self.inner.user_value.len() + etime_len + SUFFIX_LENGTH;
+        let mut buf = BytesMut::with_capacity(needed);
+
+        buf.put_slice(&self.inner.user_value);
+        let mut reserve = self.inner.reserve;
+        if etime_len > 0 {
+            buf.put_u64_le(self.inner.etime);
+            reserve[RESERVE_FLAGS_OFFSET] |= HASH_FIELD_ETIME_FLAG;
+        }
+        let reserve_start = buf.len();
+        buf.put_slice(&reserve);
+        buf.put_u64_le(self.inner.ctime);
+        checksum::seal(&mut buf, reserve_start);
+
+        buf
+    }
+}
+
+delegate_parsed_value!(ParsedHashesDataValue);
+pub struct ParsedHashesDataValue {
+    inner: ParsedInternalValue,
+}
+
+impl ParsedHashesDataValue {
+    pub fn new<T>(internal_value: T) -> Result<Self>
+    where
+        T: Into<ValueBuf>,
+    {
+        let value =
+            FormatMigrator::global().upgrade(ValueLayout::HashesData, internal_value.into())?;
+        ensure!(
+            value.len() >= SUFFIX_LENGTH,
+            CorruptValueSnafu {
+                data_type: DataType::None,
+                field: "suffix",
+                offset: 0usize,
+                expected: SUFFIX_LENGTH,
+                actual: value.len(),
+                key: None::<String>,
+            }
+        );
+
+        let reserve_start = value.len() - SUFFIX_LENGTH;
+        let reserve_range = reserve_start..reserve_start + SUFFIX_RESERVE_LENGTH;
+        let ctime = (&value[reserve_range.end..]).get_u64_le();
+
+        let has_etime = value[reserve_start + RESERVE_FLAGS_OFFSET] & HASH_FIELD_ETIME_FLAG != 0;
+        let (user_value_len, etime) = if has_etime {
+            ensure!(
+                reserve_start >= TIMESTAMP_LENGTH,
+                CorruptValueSnafu {
+                    data_type: DataType::None,
+                    field: "field etime",
+                    offset: 0usize,
+                    expected: SUFFIX_LENGTH + TIMESTAMP_LENGTH,
+                    actual: value.len(),
+                    key: None::<String>,
+                }
+            );
+            let etime_start = reserve_start - TIMESTAMP_LENGTH;
+            let etime = (&value[etime_start..reserve_start]).get_u64_le();
+            (etime_start, etime)
+        } else {
+            (reserve_start, 0)
+        };
+        checksum::verify(&value, reserve_start)?;
+
+        Ok(Self {
+            inner: ParsedInternalValue::new(
+                value,
+                DataType::None,
+                0..user_value_len,
+                reserve_range,
+                0,
+                ctime,
+                etime,
+            ),
+        })
+    }
+
+    /// Whether the field has an expiration time that has passed. An expired
+    /// field is treated as missing by reads until compaction removes it
+    pub fn is_field_stale(&self) -> bool {
+        self.inner.is_stale()
+    }
+
+    pub fn filter_decision(&self, cur_time: u64) -> CompactionDecision {
+        if self.inner.etime != 0 && self.inner.etime < cur_time {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+
+    /// Sets the expiration time of the field, 0 to persist it (HPERSIST).
+    /// Adding or removing the etime changes the length of the value
+    pub fn set_etime(&mut self, etime: u64) {
+        let had_etime = self.inner.etime != 0;
+        self.inner.etime = etime;
+        if had_etime && etime != 0 {
+            let etime_start = self.inner.user_value_range.end;
+            self.inner.value[etime_start..etime_start + TIMESTAMP_LENGTH]
+                .copy_from_slice(&etime.to_le_bytes());
+            checksum::refresh(&mut self.inner.value, self.inner.reserve_range.start);
+            return;
+        }
+
+        let mut reserve = [0u8; SUFFIX_RESERVE_LENGTH];
+        reserve.copy_from_slice(&self.inner.value[self.inner.reserve_range.clone()]);
+        let mut value = HashesDataValue::new(Bytes::copy_from_slice(
+            &self.inner.value[self.inner.user_value_range.clone()],
+        ));
+        reserve[RESERVE_FLAGS_OFFSET] &= !HASH_FIELD_ETIME_FLAG;
+        value.inner.reserve = reserve;
+        value.set_ctime(self.inner.ctime);
+        value.set_etime(etime);
+
+        self.inner.value = value.encode().into();
+        let reserve_start = self.inner.value.len() - SUFFIX_LENGTH;
+        self.inner.reserve_range = reserve_start..reserve_start + SUFFIX_RESERVE_LENGTH;
+    }
+
+    /// Sets a relative expiration time of the field (in microseconds)
+    pub fn set_relative_etime(&mut self, ttl: u64) {
+        let now = clock::now_micros();
+        self.set_etime(now.saturating_add(ttl));
+    }
+
+    /// The encoded value, to write back after set_etime
+    pub fn encoded(&self) -> &[u8] {
+        &self.inner.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_CTIME: u64 = 1620000000;
+    const TEST_ETIME: u64 = 1630000000;
+    const TEST_VALUE: &[u8] = b"field_value";
+
+    #[test]
+    fn test_encode_without_etime() {
+        let mut value = HashesDataValue::new(TEST_VALUE);
+        value.set_ctime(TEST_CTIME);
+
+        let mut expected = BytesMut::new();
+        expected.put_slice(TEST_VALUE);
+        expected.put_bytes(0, SUFFIX_RESERVE_LENGTH);
+        expected.put_u64_le(TEST_CTIME);
+        // Same layout as a base data value
+        assert_eq!(value.encode(), expected);
+
+        let parsed = ParsedHashesDataValue::new(value.encode()).unwrap();
+        assert_eq!(parsed.user_value(), TEST_VALUE);
+        assert_eq!(parsed.ctime(), TEST_CTIME);
+        assert_eq!(parsed.etime(), 0);
+        assert!(!parsed.is_field_stale());
+    }
+
+    #[test]
+    fn test_encode_with_etime() {
+        let mut value = HashesDataValue::new(TEST_VALUE);
+        value.set_ctime(TEST_CTIME);
+        value.set_etime(TEST_ETIME);
+
+        let encoded = value.encode();
+        let mut expected = BytesMut::new();
+        expected.put_slice(TEST_VALUE);
+        expected.put_u64_le(TEST_ETIME);
+        expected.put_bytes(0, RESERVE_FLAGS_OFFSET);
+        expected.put_u8(HASH_FIELD_ETIME_FLAG);
+        expected.put_bytes(0, SUFFIX_RESERVE_LENGTH - RESERVE_FLAGS_OFFSET - 1);
+        expected.put_u64_le(TEST_CTIME);
+        assert_eq!(encoded, expected);
+
+        let parsed = ParsedHashesDataValue::new(encoded).unwrap();
+        assert_eq!(parsed.user_value(), TEST_VALUE);
+        assert_eq!(parsed.ctime(), TEST_CTIME);
+        assert_eq!(parsed.etime(), TEST_ETIME);
+        // TEST_ETIME is long past
+        assert!(parsed.is_field_stale());
+    }
+
+    #[test]
+    fn test_empty_value_with_etime() {
+        let mut value = HashesDataValue::new(Bytes::new());
+        value.set_etime(TEST_ETIME);
+        let parsed = ParsedHashesDataValue::new(value.encode()).unwrap();
+        assert!(parsed.user_value().is_empty());
+        assert_eq!(parsed.etime(), TEST_ETIME);
+    }
+
+    #[test]
+    fn test_filter_decision() {
+        let mut value = HashesDataValue::new(TEST_VALUE);
+        value.set_etime(TEST_ETIME);
+        let parsed = ParsedHashesDataValue::new(value.encode()).unwrap();
+        assert!(matches!(
+            parsed.filter_decision(TEST_ETIME + 1),
+            CompactionDecision::Remove
+        ));
+        assert!(matches!(
+            parsed.filter_decision(TEST_ETIME - 1),
+            CompactionDecision::Keep
+        ));
+
+        let parsed = ParsedHashesDataValue::new(HashesDataValue::new(TEST_VALUE).encode()).unwrap();
+        assert!(matches!(
+            parsed.filter_decision(u64::MAX),
+            CompactionDecision::Keep
+        ));
+    }
+
+    #[test]
+    fn test_set_etime() {
+        let mut value = HashesDataValue::new(TEST_VALUE);
+        value.set_ctime(TEST_CTIME);
+        let mut parsed = ParsedHashesDataValue::new(value.encode()).unwrap();
+
+        // Add an etime
+        parsed.set_etime(TEST_ETIME);
+        let reparsed = ParsedHashesDataValue::new(parsed.encoded()).unwrap();
+        assert_eq!(reparsed.etime(), TEST_ETIME);
+        assert_eq!(reparsed.user_value(), TEST_VALUE);
+        assert_eq!(reparsed.ctime(), TEST_CTIME);
+
+        // Update it in place
+        let len = parsed.encoded().len();
+        parsed.set_etime(TEST_ETIME + 1);
+        assert_eq!(parsed.encoded().len(), len);
+        let reparsed = ParsedHashesDataValue::new(parsed.encoded()).unwrap();
+        assert_eq!(reparsed.etime(), TEST_ETIME + 1);
+
+        // Persist the field
+        parsed.set_etime(0);
+        let mut base = HashesDataValue::new(TEST_VALUE);
+        base.set_ctime(TEST_CTIME);
+        assert_eq!(parsed.encoded(), &base.encode()[..]);
+        assert_eq!(parsed.user_value(), TEST_VALUE);
+
+        parsed.set_relative_etime(60_000_000);
+        assert!(!parsed.is_field_stale());
+    }
+
+    #[test]
+    fn test_checksum() {
+        checksum::with_checksum(|| {
+            let mut value = HashesDataValue::new(TEST_VALUE);
+            value.set_etime(TEST_ETIME);
+            let mut parsed = ParsedHashesDataValue::new(value.encode()).unwrap();
+
+            // The etime is covered by the checksum
+            parsed.set_etime(TEST_ETIME + 1);
+            assert!(ParsedHashesDataValue::new(parsed.encoded()).is_ok());
+            parsed.set_etime(0);
+            assert!(ParsedHashesDataValue::new(parsed.encoded()).is_ok());
+
+            let mut corrupted = parsed.encoded().to_vec();
+            corrupted[0] ^= 0xff;
+            assert!(ParsedHashesDataValue::new(&corrupted[..]).is_err());
+        });
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(ParsedHashesDataValue::new(&[0u8; SUFFIX_LENGTH - 1][..]).is_err());
+
+        // The flag is set but there is no room for the etime
+        let mut buf = [0u8; SUFFIX_LENGTH + TIMESTAMP_LENGTH - 1];
+        buf[TIMESTAMP_LENGTH - 1 + RESERVE_FLAGS_OFFSET] = HASH_FIELD_ETIME_FLAG;
+        assert!(ParsedHashesDataValue::new(&buf[..]).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Approximate detection of the most frequently read keys.
+//!
+//! Every read bumps a counter of its key. The counters live in a few shards
+//! of bounded size that follow the space-saving algorithm: when a shard is
+//! full, the key with the lowest count is replaced by the new key, which
+//! inherits that count plus one. Keys read more often than once every
+//! `capacity` reads are never evicted. The result is a good enough top-N for
+//! warming the block cache after a restart, not an exact ranking.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+const SHARD_NUM: usize = 16;
+
+#[derive(Debug)]
+pub struct HotKeyDetector {
+    shards: Vec<Mutex<HashMap<Vec<u8>, u64>>>,
+    shard_capacity: usize,
+}
+
+impl HotKeyDetector {
+    /// Creates a detector tracking about `capacity` distinct keys.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            shards: (0..SHARD_NUM).map(|_| Mutex::new(HashMap::new())).collect(),
+            shard_capacity: capacity.div_ceil(SHARD_NUM).max(1),
+        }
+    }
+
+    /// Counts one read of `key`.
+    pub fn record(&self, key: &[u8]) {
+        let mut shard = self.shards[Self::shard_index(key)]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = shard.get_mut(key) {
+            *count += 1;
+            return;
+        }
+        let mut count = 1;
+        if shard.len() >= self.shard_capacity {
+            let coldest = shard
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(key, count)| (key.clone(), *count));
+            if let Some((coldest_key, coldest_count)) = coldest {
+                shard.remove(&coldest_key);
+                count += coldest_count;
+            }
+        }
+        shard.insert(key.to_vec(), count);
+    }
+
+    /// Returns up to `n` keys, the most read first.
+    pub fn top(&self, n: usize) -> Vec<Vec<u8>> {
+        let mut counts: Vec<(Vec<u8>, u64)> = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            counts.extend(shard.iter().map(|(key, count)| (key.clone(), *count)));
+        }
+        counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts.truncate(n);
+        counts.into_iter().map(|(key, _)| key).collect()
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+
+    fn shard_index(key: &[u8]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % SHARD_NUM
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_keys() {
+        let detector = HotKeyDetector::new(1024);
+        for i in 0..10u8 {
+            for _ in 0..=i {
+                detector.record(&[i]);
+            }
+        }
+
+        assert_eq!(detector.top(3), vec![vec![9], vec![8], vec![7]]);
+        assert_eq!(detector.top(100).len(), 10);
+
+        detector.clear();
+        assert!(detector.top(3).is_empty());
+    }
+
+    #[test]
+    fn test_bounded_memory_keeps_hot_keys() {
+        let detector = HotKeyDetector::new(SHARD_NUM * 8);
+        for _ in 0..100 {
+            detector.record(b"hot");
+        }
+        for i in 0..10_000u32 {
+            detector.record(&i.to_le_bytes());
+        }
+
+        let tracked: usize = detector
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum();
+        assert!(tracked <= SHARD_NUM * 8);
+        assert_eq!(detector.top(1), vec![b"hot".to_vec()]);
+    }
+}
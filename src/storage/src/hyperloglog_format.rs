@@ -0,0 +1,541 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::error::{InvalidFormatSnafu, Result};
+use crate::storage_murmur3::murmur64a;
+use bytes::{BufMut, BytesMut};
+use snafu::ensure;
+
+/*
+ * HyperLogLog payload, the user value of a string, layout compatible with
+ * Redis
+ *
+ * | magic | encoding | unused | cardinality |  registers  |
+ * |  4B   |    1B    |   3B   |     8B      |             |
+ *
+ * The cardinality is the cached PFCOUNT result, little endian, stale when
+ * the most significant bit of its last byte is set.
+ *
+ * dense registers: 16384 registers of 6 bits, least significant bits first
+ * sparse registers: run length encoded opcodes
+ *   ZERO  | 00xxxxxx |          | xxxxxx + 1 zero registers
+ *   XZERO | 01xxxxxx | yyyyyyyy | xxxxxxyyyyyyyy + 1 zero registers
+ *   VAL   | 1vvvvvxx |          | xx + 1 registers set to vvvvv + 1
+ */
+pub const HLL_MAGIC: &[u8; 4] = b"HYLL";
+pub const HLL_HEADER_LENGTH: usize = 16;
+
+const HLL_P: u32 = 14;
+const HLL_Q: u32 = 64 - HLL_P;
+pub const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_BITS: usize = 6;
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+pub const HLL_DENSE_LENGTH: usize = HLL_HEADER_LENGTH + (HLL_REGISTERS * HLL_BITS).div_ceil(8);
+
+const HLL_HASH_SEED: u64 = 0xadc83b19;
+const HLL_ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+const HLL_SPARSE_VAL_MAX_VALUE: u8 = 32;
+const HLL_SPARSE_VAL_MAX_LEN: usize = 4;
+const HLL_SPARSE_ZERO_MAX_LEN: usize = 64;
+const HLL_SPARSE_XZERO_MAX_LEN: usize = 16384;
+/// Largest sparse payload before converting to dense, as hll-sparse-max-bytes
+pub const HLL_SPARSE_MAX_BYTES: usize = 3000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HllEncoding {
+    Dense = 0,
+    Sparse = 1,
+}
+
+impl TryFrom<u8> for HllEncoding {
+    type Error = crate::error::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Dense),
+            1 => Ok(Self::Sparse),
+            _ => InvalidFormatSnafu {
+                message: format!("invalid hyperloglog encoding: {value}"),
+            }
+            .fail(),
+        }
+    }
+}
+
+/// Whether a string value holds a HyperLogLog, as checked before PFADD and
+/// PFCOUNT reject a plain string with WRONGTYPE
+pub fn is_hyperloglog(user_value: &[u8]) -> bool {
+    user_value.len() >= HLL_HEADER_LENGTH && user_value.starts_with(HLL_MAGIC)
+}
+
+/// The registers of a HyperLogLog, one byte each, decoded from and encoded
+/// to the sparse or dense payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+    cached_cardinality: Option<u64>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self {
+            registers: vec![0; HLL_REGISTERS],
+            cached_cardinality: Some(0),
+        }
+    }
+
+    pub fn decode(user_value: &[u8]) -> Result<Self> {
+        ensure!(
+            is_hyperloglog(user_value),
+            InvalidFormatSnafu {
+                message: "invalid hyperloglog header".to_string(),
+            }
+        );
+
+        let encoding = HllEncoding::try_from(user_value[4])?;
+        let card = &user_value[8..HLL_HEADER_LENGTH];
+        let cached_cardinality = match card[7] & 0x80 {
+            0 => Some(u64::from_le_bytes(card.try_into().unwrap())),
+            _ => None,
+        };
+
+        let payload = &user_value[HLL_HEADER_LENGTH..];
+        let registers = match encoding {
+            HllEncoding::Dense => decode_dense(payload)?,
+            HllEncoding::Sparse => decode_sparse(payload)?,
+        };
+
+        Ok(Self {
+            registers,
+            cached_cardinality,
+        })
+    }
+
+    /// Encodes sparse while the result fits in HLL_SPARSE_MAX_BYTES and
+    /// every register fits in a VAL opcode, dense otherwise
+    pub fn encode(&self) -> BytesMut {
+        match encode_sparse(&self.registers) {
+            Some(payload) if HLL_HEADER_LENGTH + payload.len() <= HLL_SPARSE_MAX_BYTES => {
+                self.encode_with(HllEncoding::Sparse, &payload)
+            }
+            _ => self.encode_with(HllEncoding::Dense, &encode_dense(&self.registers)),
+        }
+    }
+
+    /// Encodes dense whatever the registers
+    pub fn encode_dense(&self) -> BytesMut {
+        self.encode_with(HllEncoding::Dense, &encode_dense(&self.registers))
+    }
+
+    fn encode_with(&self, encoding: HllEncoding, payload: &[u8]) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(HLL_HEADER_LENGTH + payload.len());
+        buf.put_slice(HLL_MAGIC);
+        buf.put_u8(encoding as u8);
+        buf.put_bytes(0, 3);
+        match self.cached_cardinality {
+            Some(card) => buf.put_u64_le(card),
+            None => buf.put_u64_le(1 << 63),
+        }
+        buf.put_slice(payload);
+        buf
+    }
+
+    /// Adds element, returns whether a register changed, which is PFADD's
+    /// reply for the key
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let (index, count) = pattern_len(element);
+        if self.registers[index] >= count {
+            return false;
+        }
+        self.registers[index] = count;
+        self.cached_cardinality = None;
+        true
+    }
+
+    /// Merges other into self, keeping the larger register of each
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        let mut changed = false;
+        for (reg, other) in self.registers.iter_mut().zip(&other.registers) {
+            if *other > *reg {
+                *reg = *other;
+                changed = true;
+            }
+        }
+        if changed {
+            self.cached_cardinality = None;
+        }
+    }
+
+    /// Estimated cardinality, cached until the registers change
+    pub fn count(&mut self) -> u64 {
+        if let Some(card) = self.cached_cardinality {
+            return card;
+        }
+        let card = estimate(&self.registers);
+        self.cached_cardinality = Some(card);
+        card
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+}
+
+/// Estimated cardinality of the union of hlls, as PFCOUNT of several keys
+pub fn count_union<'a>(hlls: impl IntoIterator<Item = &'a HyperLogLog>) -> u64 {
+    let mut union = HyperLogLog::new();
+    for hll in hlls {
+        union.merge(hll);
+    }
+    estimate(&union.registers)
+}
+
+// The register of element and the position of the first set bit of the rest
+// of its hash, counting from 1
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmur64a(element, HLL_HASH_SEED);
+    let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+    let hash = (hash >> HLL_P) | (1 << HLL_Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+// The estimator of Ertl, "New cardinality estimation algorithms for
+// HyperLogLog sketches", as Redis hllCount
+fn estimate(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for reg in registers {
+        histogram[*reg as usize] += 1;
+    }
+
+    let m = HLL_REGISTERS as f64;
+    let q = HLL_Q as usize;
+    let mut z = m * tau((m - histogram[q + 1] as f64) / m);
+    for j in (1..=q).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (HLL_ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let prev = z;
+        z += x * y;
+        y += y;
+        if prev == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let prev = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if prev == z {
+            return z / 3.0;
+        }
+    }
+}
+
+fn decode_dense(payload: &[u8]) -> Result<Vec<u8>> {
+    ensure!(
+        payload.len() == HLL_DENSE_LENGTH - HLL_HEADER_LENGTH,
+        InvalidFormatSnafu {
+            message: format!(
+                "invalid dense hyperloglog length: {} != {}",
+                payload.len(),
+                HLL_DENSE_LENGTH - HLL_HEADER_LENGTH
+            ),
+        }
+    );
+
+    let registers = (0..HLL_REGISTERS)
+        .map(|index| {
+            let bit = index * HLL_BITS;
+            let (byte, shift) = (bit / 8, bit % 8);
+            let low = payload[byte] as u16;
+            let high = payload.get(byte + 1).copied().unwrap_or(0) as u16;
+            ((((high << 8) | low) >> shift) as u8) & HLL_REGISTER_MAX
+        })
+        .collect();
+    Ok(registers)
+}
+
+fn encode_dense(registers: &[u8]) -> Vec<u8> {
+    let mut payload = vec![0u8; HLL_DENSE_LENGTH - HLL_HEADER_LENGTH];
+    for (index, reg) in registers.iter().enumerate() {
+        let bit = index * HLL_BITS;
+        let (byte, shift) = (bit / 8, bit % 8);
+        let value = ((*reg).min(HLL_REGISTER_MAX) as u16) << shift;
+        payload[byte] |= value as u8;
+        if value > 0xff {
+            payload[byte + 1] |= (value >> 8) as u8;
+        }
+    }
+    payload
+}
+
+fn decode_sparse(payload: &[u8]) -> Result<Vec<u8>> {
+    let mut registers = Vec::with_capacity(HLL_REGISTERS);
+    let mut pos = 0;
+    while pos < payload.len() {
+        let op = payload[pos];
+        let (value, len) = match op >> 6 {
+            0b00 => (0, (op & 0x3f) as usize + 1),
+            0b01 => {
+                let Some(next) = payload.get(pos + 1) else {
+                    return InvalidFormatSnafu {
+                        message: "truncated sparse hyperloglog XZERO opcode".to_string(),
+                    }
+                    .fail();
+                };
+                pos += 1;
+                (0, ((((op & 0x3f) as usize) << 8) | *next as usize) + 1)
+            }
+            _ => (((op >> 2) & 0x1f) + 1, (op & 0x03) as usize + 1),
+        };
+        pos += 1;
+
+        ensure!(
+            registers.len() + len <= HLL_REGISTERS,
+            InvalidFormatSnafu {
+                message: "sparse hyperloglog covers too many registers".to_string(),
+            }
+        );
+        registers.resize(registers.len() + len, value);
+    }
+
+    ensure!(
+        registers.len() == HLL_REGISTERS,
+        InvalidFormatSnafu {
+            message: format!(
+                "sparse hyperloglog covers {} registers, expected {HLL_REGISTERS}",
+                registers.len()
+            ),
+        }
+    );
+    Ok(registers)
+}
+
+// None when a register is too large for a VAL opcode
+fn encode_sparse(registers: &[u8]) -> Option<Vec<u8>> {
+    let mut payload = Vec::new();
+    let mut index = 0;
+    while index < registers.len() {
+        let value = registers[index];
+        let run = registers[index..]
+            .iter()
+            .take_while(|reg| **reg == value)
+            .count();
+        index += run;
+
+        if value == 0 {
+            let mut run = run;
+            while run > 0 {
+                let len = run.min(HLL_SPARSE_XZERO_MAX_LEN);
+                if len <= HLL_SPARSE_ZERO_MAX_LEN {
+                    payload.push((len - 1) as u8);
+                } else {
+                    payload.push(0x40 | ((len - 1) >> 8) as u8);
+                    payload.push(((len - 1) & 0xff) as u8);
+                }
+                run -= len;
+            }
+            continue;
+        }
+
+        if value > HLL_SPARSE_VAL_MAX_VALUE {
+            return None;
+        }
+        let mut run = run;
+        while run > 0 {
+            let len = run.min(HLL_SPARSE_VAL_MAX_LEN);
+            payload.push(0x80 | ((value - 1) << 2) | (len - 1) as u8);
+            run -= len;
+        }
+    }
+    Some(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(range: std::ops::Range<u32>) -> HyperLogLog {
+        let mut hll = HyperLogLog::new();
+        for i in range {
+            hll.add(format!("element:{i}").as_bytes());
+        }
+        hll
+    }
+
+    #[test]
+    fn test_empty_hyperloglog() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.count(), 0);
+
+        let encoded = hll.encode();
+        assert!(is_hyperloglog(&encoded));
+        assert_eq!(encoded[4], HllEncoding::Sparse as u8);
+        // XZERO covering the 16384 registers
+        assert_eq!(&encoded[HLL_HEADER_LENGTH..], &[0x7f, 0xff]);
+
+        let mut decoded = HyperLogLog::decode(&encoded).unwrap();
+        assert_eq!(decoded, hll);
+        assert_eq!(decoded.count(), 0);
+    }
+
+    #[test]
+    fn test_add_and_count() {
+        let mut hll = HyperLogLog::new();
+        assert!(hll.add(b"a"));
+        assert!(!hll.add(b"a"));
+        assert!(hll.add(b"b"));
+        assert_eq!(hll.count(), 2);
+
+        let mut hll = filled(0..10000);
+        let count = hll.count() as f64;
+        assert!((count - 10000.0).abs() / 10000.0 < 0.02, "count {count}");
+    }
+
+    #[test]
+    fn test_sparse_round_trip() {
+        let mut hll = filled(0..100);
+        let card = hll.count();
+
+        let encoded = hll.encode();
+        assert_eq!(encoded[4], HllEncoding::Sparse as u8);
+        assert!(encoded.len() < HLL_SPARSE_MAX_BYTES);
+
+        let mut decoded = HyperLogLog::decode(&encoded).unwrap();
+        assert_eq!(decoded.registers(), hll.registers());
+        assert_eq!(decoded.count(), card);
+    }
+
+    #[test]
+    fn test_dense_round_trip() {
+        let hll = filled(0..20000);
+        let encoded = hll.encode();
+        assert_eq!(encoded[4], HllEncoding::Dense as u8);
+        assert_eq!(encoded.len(), HLL_DENSE_LENGTH);
+
+        let decoded = HyperLogLog::decode(&encoded).unwrap();
+        assert_eq!(decoded.registers(), hll.registers());
+
+        let small = filled(0..10);
+        let decoded = HyperLogLog::decode(&small.encode_dense()).unwrap();
+        assert_eq!(decoded.registers(), small.registers());
+    }
+
+    #[test]
+    fn test_dense_register_packing() {
+        let mut hll = HyperLogLog::new();
+        hll.registers[0] = 63;
+        hll.registers[1] = 1;
+        hll.registers[HLL_REGISTERS - 1] = 40;
+
+        let encoded = hll.encode();
+        assert_eq!(encoded[4], HllEncoding::Dense as u8);
+        let payload = &encoded[HLL_HEADER_LENGTH..];
+        assert_eq!(payload[0], 0x7f);
+        assert_eq!(payload[1], 0x00);
+        assert_eq!(payload[payload.len() - 1], 40 << 2);
+
+        let decoded = HyperLogLog::decode(&encoded).unwrap();
+        assert_eq!(decoded.registers(), hll.registers());
+    }
+
+    #[test]
+    fn test_cached_cardinality() {
+        let mut hll = filled(0..50);
+        let stale = HyperLogLog::decode(&hll.encode()).unwrap();
+        assert_eq!(stale.cached_cardinality, None);
+
+        let card = hll.count();
+        let cached = HyperLogLog::decode(&hll.encode()).unwrap();
+        assert_eq!(cached.cached_cardinality, Some(card));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut left = filled(0..5000);
+        let right = filled(2500..7500);
+        let union = count_union([&left, &right]) as f64;
+        assert!((union - 7500.0).abs() / 7500.0 < 0.02, "union {union}");
+
+        left.merge(&right);
+        assert_eq!(left.count(), union as u64);
+        assert_eq!(left, {
+            let mut all = filled(0..7500);
+            all.count();
+            all
+        });
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(HyperLogLog::decode(b"HYLL").is_err());
+        assert!(
+            HyperLogLog::decode(b"NOPE\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00").is_err()
+        );
+
+        let mut encoded = HyperLogLog::new().encode();
+        encoded[4] = 2;
+        assert!(HyperLogLog::decode(&encoded).is_err());
+
+        // sparse opcodes covering too few registers
+        let mut encoded = HyperLogLog::new().encode();
+        encoded.truncate(HLL_HEADER_LENGTH);
+        encoded.put_u8(0x3f);
+        assert!(HyperLogLog::decode(&encoded).is_err());
+
+        // truncated XZERO
+        encoded.truncate(HLL_HEADER_LENGTH);
+        encoded.put_u8(0x7f);
+        assert!(HyperLogLog::decode(&encoded).is_err());
+
+        // dense payload of the wrong length
+        let mut encoded = filled(0..20000).encode();
+        encoded.truncate(encoded.len() - 1);
+        assert!(HyperLogLog::decode(&encoded).is_err());
+    }
+}
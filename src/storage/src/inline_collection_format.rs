@@ -0,0 +1,339 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg_attr(not(test), allow(dead_code))]
+
+//! Inline encoding of small collections
+//!
+//! A hash, set or sorted set with few and short elements is stored whole in
+//! its meta value, between the version and the reserve, instead of one data
+//! key per element. Reading or updating it then costs a single RocksDB read.
+//! `RESERVE_INLINE_FLAG` marks such a meta value. Once the collection grows
+//! past `InlineLimits` it is spilled to data keys and the flag is cleared.
+
+use bytes::{Buf, BufMut, BytesMut};
+use snafu::ensure;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::base_value_format::{DataType, DATA_TYPE_STRINGS};
+use crate::error::{InvalidFormatSnafu, Result};
+use crate::options::StorageOptions;
+
+/// Set in the reserve flags of a meta value that holds its elements inline
+pub const RESERVE_INLINE_FLAG: u8 = 0x04;
+
+/// OBJECT ENCODING of a collection stored inline
+pub const ENCODING_INLINE: &str = "listpack";
+/// OBJECT ENCODING of a collection stored in data keys
+pub const ENCODING_SPREAD: &str = "rocksdb-spread";
+
+/// Inline collections spilled to data keys, by data type
+static INLINE_SPILLS: [AtomicU64; DATA_TYPE_STRINGS.len()] =
+    [const { AtomicU64::new(0) }; DATA_TYPE_STRINGS.len()];
+
+/// Counts an inline collection of `data_type` spilled to data keys
+pub(crate) fn record_spill(data_type: DataType) {
+    INLINE_SPILLS[data_type as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of inline collections of `data_type` spilled to data keys
+/// since the process started.
+pub fn inline_spills(data_type: DataType) -> u64 {
+    INLINE_SPILLS[data_type as usize].load(Ordering::Relaxed)
+}
+
+/// Formats the spill counters as the encoding section of INFO.
+pub fn encoding_info() -> String {
+    let mut info = String::from("# Encoding\r\n");
+    for dtype in [DataType::Hash, DataType::Set, DataType::ZSet] {
+        let name = DATA_TYPE_STRINGS[dtype as usize];
+        let _ = write!(info, "{name}_inline_spills:{}\r\n", inline_spills(dtype));
+    }
+    info
+}
+
+const LEN_LENGTH: usize = 4;
+
+/*
+ * Inline collection payload, sorted by key without duplicates. A set member
+ * has an empty value
+ * | entry count | key len | key | value len | value | ... |
+ * |     4B      |    4B   |     |    4B     |       |     |
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InlineCollection {
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl InlineCollection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn position(&self, key: &[u8]) -> std::result::Result<usize, usize> {
+        self.entries
+            .binary_search_by(|(k, _)| k.as_slice().cmp(key))
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.position(key)
+            .ok()
+            .map(|i| self.entries[i].1.as_slice())
+    }
+
+    /// Inserts or overwrites key, returns true when key is new
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> bool {
+        match self.position(key) {
+            Ok(i) => {
+                self.entries[i].1 = value.to_vec();
+                false
+            }
+            Err(i) => {
+                self.entries.insert(i, (key.to_vec(), value.to_vec()));
+                true
+            }
+        }
+    }
+
+    /// Removes key, returns true when it was present
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        match self.position(key) {
+            Ok(i) => {
+                self.entries.remove(i);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// The entries in key order
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries
+            .iter()
+            .map(|(k, v)| (k.as_slice(), v.as_slice()))
+    }
+
+    pub fn into_entries(self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.entries
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        LEN_LENGTH
+            + self
+                .entries
+                .iter()
+                .map(|(k, v)| 2 * LEN_LENGTH + k.len() + v.len())
+                .sum::<usize>()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.reserve(self.encoded_len());
+        buf.put_u32_le(self.entries.len() as u32);
+        for (key, value) in &self.entries {
+            buf.put_u32_le(key.len() as u32);
+            buf.put_slice(key);
+            buf.put_u32_le(value.len() as u32);
+            buf.put_slice(value);
+        }
+    }
+
+    pub fn decode(mut src: &[u8]) -> Result<Self> {
+        let count = read_len(&mut src)?;
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> =
+            Vec::with_capacity(count.min(src.len() / (2 * LEN_LENGTH)));
+        for _ in 0..count {
+            let key = read_bytes(&mut src)?;
+            let value = read_bytes(&mut src)?;
+            ensure!(
+                entries.last().is_none_or(|(last, _)| *last < key),
+                InvalidFormatSnafu {
+                    message: "inline collection keys are not sorted".to_string(),
+                }
+            );
+            entries.push((key, value));
+        }
+        ensure!(
+            src.is_empty(),
+            InvalidFormatSnafu {
+                message: "trailing bytes after inline collection".to_string(),
+            }
+        );
+        Ok(Self { entries })
+    }
+}
+
+fn read_len(src: &mut &[u8]) -> Result<usize> {
+    ensure!(
+        src.len() >= LEN_LENGTH,
+        InvalidFormatSnafu {
+            message: "inline collection truncated".to_string(),
+        }
+    );
+    Ok(src.get_u32_le() as usize)
+}
+
+fn read_bytes(src: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = read_len(src)?;
+    ensure!(
+        src.len() >= len,
+        InvalidFormatSnafu {
+            message: "inline collection truncated".to_string(),
+        }
+    );
+    let bytes = src[..len].to_vec();
+    src.advance(len);
+    Ok(bytes)
+}
+
+/// The largest collection kept inline, see `StorageOptions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InlineLimits {
+    /// 0 disables the inline encoding
+    pub max_entries: usize,
+    pub max_entry_len: usize,
+}
+
+impl InlineLimits {
+    pub fn from_options(options: &StorageOptions) -> Self {
+        Self {
+            max_entries: options.inline_collection_max_entries,
+            max_entry_len: options.inline_collection_max_entry_len,
+        }
+    }
+
+    /// Whether an entry of key and value may be stored inline
+    pub fn allows_entry(&self, key: &[u8], value: &[u8]) -> bool {
+        key.len() <= self.max_entry_len && value.len() <= self.max_entry_len
+    }
+
+    /// Whether collection can stay inline, false once it has to be spilled
+    /// to data keys
+    pub fn allows(&self, collection: &InlineCollection) -> bool {
+        collection.len() <= self.max_entries
+            && collection.iter().all(|(k, v)| self.allows_entry(k, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection() -> InlineCollection {
+        let mut collection = InlineCollection::new();
+        assert!(collection.insert(b"b", b"2"));
+        assert!(collection.insert(b"a", b"1"));
+        assert!(collection.insert(b"c\x00", b""));
+        collection
+    }
+
+    #[test]
+    fn test_inline_collection_operations() {
+        let mut collection = collection();
+        assert_eq!(collection.len(), 3);
+        assert_eq!(collection.get(b"a"), Some(&b"1"[..]));
+        assert_eq!(collection.get(b"c\x00"), Some(&b""[..]));
+        assert_eq!(collection.get(b"c"), None);
+
+        assert!(!collection.insert(b"a", b"one"));
+        assert_eq!(collection.get(b"a"), Some(&b"one"[..]));
+        assert_eq!(collection.len(), 3);
+
+        assert!(collection.remove(b"b"));
+        assert!(!collection.remove(b"b"));
+        let keys: Vec<&[u8]> = collection.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![&b"a"[..], &b"c\x00"[..]]);
+        assert_eq!(
+            collection.into_entries(),
+            vec![
+                (b"a".to_vec(), b"one".to_vec()),
+                (b"c\x00".to_vec(), Vec::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_collection_roundtrip() {
+        let collection = collection();
+        let mut buf = BytesMut::new();
+        collection.encode_into(&mut buf);
+        assert_eq!(buf.len(), collection.encoded_len());
+        assert_eq!(InlineCollection::decode(&buf).unwrap(), collection);
+
+        let mut empty = BytesMut::new();
+        InlineCollection::new().encode_into(&mut empty);
+        assert!(InlineCollection::decode(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_inline_collection_decode_invalid() {
+        let mut buf = BytesMut::new();
+        collection().encode_into(&mut buf);
+        assert!(InlineCollection::decode(&buf[..buf.len() - 1]).is_err());
+
+        let mut trailing = buf.clone();
+        trailing.put_u8(0);
+        assert!(InlineCollection::decode(&trailing).is_err());
+
+        let mut unsorted = BytesMut::new();
+        unsorted.put_u32_le(2);
+        for key in [b"b", b"a"] {
+            unsorted.put_u32_le(1);
+            unsorted.put_slice(key);
+            unsorted.put_u32_le(0);
+        }
+        assert!(InlineCollection::decode(&unsorted).is_err());
+    }
+
+    #[test]
+    fn test_inline_limits() {
+        let defaults = InlineLimits::from_options(&StorageOptions::default());
+        assert_eq!(defaults.max_entries, 128);
+        assert_eq!(defaults.max_entry_len, 64);
+
+        let limits = InlineLimits {
+            max_entries: 3,
+            max_entry_len: 2,
+        };
+        let mut collection = collection();
+        assert!(limits.allows(&collection));
+        assert!(!limits.allows_entry(b"abc", b""));
+
+        collection.insert(b"d", b"4");
+        assert!(!limits.allows(&collection));
+        collection.remove(b"d");
+        collection.insert(b"a", b"123");
+        assert!(!limits.allows(&collection));
+
+        let disabled = InlineLimits {
+            max_entries: 0,
+            max_entry_len: 64,
+        };
+        assert!(!disabled.allows(&collection));
+        assert!(disabled.allows(&InlineCollection::new()));
+    }
+}
@@ -0,0 +1,433 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Intent log of the operations spanning several write batches
+//!
+//! A command writes meta and data keys in one `WriteBatch`, which is atomic.
+//! An operation too large for one batch, such as deleting the data keys of
+//! a big collection chunk by chunk, first records its intent in the intent
+//! log column family, then deletes the intent in the batch of its last
+//! step. An intent still there when an instance opens belongs to an
+//! operation a crash interrupted, and is completed or rolled back before
+//! any command runs.
+//!
+//! Key: the intent id, 8 bytes big-endian, from the version counter.
+//! Value:
+//! | kind | payload |
+//! |  1B  |         |
+//!
+//! DeleteRange payload: | cf | start len | start | end |
+//!                      | 1B |    4B     |       |     |
+//! RestoreMeta payload: | key len | key | value |
+//!                      |   4B    |     |       |
+
+#![cfg_attr(not(test), allow(dead_code))]
+
+use bytes::{BufMut, BytesMut};
+use log::warn;
+use rocksdb::{Direction, IteratorMode, WriteBatch, WriteOptions};
+use snafu::{ensure, OptionExt, ResultExt};
+
+use crate::{
+    coding::decode_fixed,
+    error::{InvalidFormatSnafu, OptionNoneSnafu, RocksSnafu},
+    version_seq::next_version,
+    ColumnFamilyIndex, Redis, Result,
+};
+
+/// Data keys deleted per batch by a logged range deletion
+const DELETE_RANGE_CHUNK: usize = 1024;
+
+const KIND_DELETE_RANGE: u8 = 1;
+const KIND_RESTORE_META: u8 = 2;
+const LEN_LENGTH: usize = 4;
+
+/// An operation recorded in the intent log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Intent {
+    /// Deletes every key of `cf` in `[start, end)`. Deleting is idempotent,
+    /// so recovery completes it.
+    DeleteRange {
+        cf: ColumnFamilyIndex,
+        start: Vec<u8>,
+        end: Vec<u8>,
+    },
+    /// The operation replaced the meta value under `key`, which was
+    /// `value`, empty when there was none. Recovery rolls it back by putting
+    /// `value` back.
+    RestoreMeta { key: Vec<u8>, value: Vec<u8> },
+}
+
+impl Intent {
+    pub(crate) fn encode(&self) -> BytesMut {
+        let mut buf = BytesMut::new();
+        match self {
+            Intent::DeleteRange { cf, start, end } => {
+                buf.put_u8(KIND_DELETE_RANGE);
+                buf.put_u8(*cf as u8);
+                buf.put_u32_le(start.len() as u32);
+                buf.put_slice(start);
+                buf.put_slice(end);
+            }
+            Intent::RestoreMeta { key, value } => {
+                buf.put_u8(KIND_RESTORE_META);
+                buf.put_u32_le(key.len() as u32);
+                buf.put_slice(key);
+                buf.put_slice(value);
+            }
+        }
+        buf
+    }
+
+    pub(crate) fn decode(value: &[u8]) -> Result<Self> {
+        let Some((&kind, payload)) = value.split_first() else {
+            return InvalidFormatSnafu {
+                message: "empty intent".to_string(),
+            }
+            .fail();
+        };
+        match kind {
+            KIND_DELETE_RANGE => {
+                let (&cf, rest) = payload.split_first().context(InvalidFormatSnafu {
+                    message: "intent too short for the column family".to_string(),
+                })?;
+                let cf = cf_from_index(cf).context(InvalidFormatSnafu {
+                    message: format!("unknown column family {cf} in intent"),
+                })?;
+                let (start, end) = split_prefixed(rest)?;
+                Ok(Intent::DeleteRange {
+                    cf,
+                    start: start.to_vec(),
+                    end: end.to_vec(),
+                })
+            }
+            KIND_RESTORE_META => {
+                let (key, value) = split_prefixed(payload)?;
+                Ok(Intent::RestoreMeta {
+                    key: key.to_vec(),
+                    value: value.to_vec(),
+                })
+            }
+            _ => InvalidFormatSnafu {
+                message: format!("unknown intent kind {kind}"),
+            }
+            .fail(),
+        }
+    }
+}
+
+/// Splits a length prefixed field off the front of `buf`
+fn split_prefixed(buf: &[u8]) -> Result<(&[u8], &[u8])> {
+    ensure!(
+        buf.len() >= LEN_LENGTH,
+        InvalidFormatSnafu {
+            message: "intent too short for a length".to_string(),
+        }
+    );
+    let len = decode_fixed::<u32>(&buf[..LEN_LENGTH]) as usize;
+    let rest = &buf[LEN_LENGTH..];
+    ensure!(
+        rest.len() >= len,
+        InvalidFormatSnafu {
+            message: "intent too short for its field".to_string(),
+        }
+    );
+    Ok(rest.split_at(len))
+}
+
+fn cf_from_index(index: u8) -> Option<ColumnFamilyIndex> {
+    [
+        ColumnFamilyIndex::MetaCF,
+        ColumnFamilyIndex::HashesDataCF,
+        ColumnFamilyIndex::SetsDataCF,
+        ColumnFamilyIndex::ListsDataCF,
+        ColumnFamilyIndex::ZsetsDataCF,
+        ColumnFamilyIndex::ZsetsScoreCF,
+        ColumnFamilyIndex::SystemCF,
+        ColumnFamilyIndex::TrashCF,
+        ColumnFamilyIndex::StreamsGroupCF,
+        ColumnFamilyIndex::BitmapDataCF,
+    ]
+    .into_iter()
+    .find(|cf| *cf as u8 == index)
+}
+
+impl Redis {
+    /// Records `intent` before the first step of the operation and returns
+    /// its id. The write is synced, a step must never reach the disk
+    /// before its intent.
+    pub(crate) fn log_intent(&self, intent: &Intent) -> Result<u64> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::IntentLogCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let id = next_version(0);
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(true);
+        db.put_cf_opt(&cf, id.to_be_bytes(), intent.encode(), &write_options)
+            .context(RocksSnafu)?;
+        Ok(id)
+    }
+
+    /// Deletes the intent `id` in `batch`, the last step of its operation
+    pub(crate) fn stage_intent_done(&self, batch: &mut WriteBatch, id: u64) -> Result<()> {
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::IntentLogCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        batch.delete_cf(&cf, id.to_be_bytes());
+        Ok(())
+    }
+
+    /// Deletes every key of `cf` in `[start, end)`, in batches of
+    /// `DELETE_RANGE_CHUNK` keys. A crash in between leaves the intent for
+    /// `recover_intents` to finish. Returns the number of keys deleted
+    pub(crate) fn delete_range_logged(
+        &self,
+        cf: ColumnFamilyIndex,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<u64> {
+        let id = self.log_intent(&Intent::DeleteRange {
+            cf,
+            start: start.to_vec(),
+            end: end.to_vec(),
+        })?;
+        self.run_delete_range(id, cf, start, end)
+    }
+
+    fn run_delete_range(
+        &self,
+        id: u64,
+        cf_index: ColumnFamilyIndex,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<u64> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self.get_cf_handle(cf_index).context(OptionNoneSnafu {
+            message: "cf is not initialized".to_string(),
+        })?;
+
+        let mut deleted = 0;
+        loop {
+            let mut batch = WriteBatch::default();
+            let mut chunk = 0;
+            let iter = db.iterator_cf(&cf, IteratorMode::From(start, Direction::Forward));
+            for item in iter {
+                let (key, _) = item.context(RocksSnafu)?;
+                if key.as_ref() >= end || chunk == DELETE_RANGE_CHUNK {
+                    break;
+                }
+                batch.delete_cf(&cf, &key);
+                chunk += 1;
+            }
+            deleted += chunk as u64;
+            let last = chunk < DELETE_RANGE_CHUNK;
+            if last {
+                self.stage_intent_done(&mut batch, id)?;
+            }
+            db.write_opt(batch, &self.write_options)
+                .context(RocksSnafu)?;
+            if last {
+                return Ok(deleted);
+            }
+        }
+    }
+
+    /// Completes or rolls back the operations interrupted by a crash.
+    /// Called when opening, before any command runs. Returns the number of
+    /// intents handled
+    pub(crate) fn recover_intents(&self) -> Result<usize> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::IntentLogCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let mut pending = Vec::new();
+        for item in db.iterator_cf(&cf, IteratorMode::Start) {
+            let (key, value) = item.context(RocksSnafu)?;
+            if key.len() != 8 {
+                warn!("intent log: ignoring the entry under the invalid id {key:?}");
+                continue;
+            }
+            match Intent::decode(&value) {
+                Ok(intent) => {
+                    let mut id = [0u8; 8];
+                    id.copy_from_slice(&key);
+                    pending.push((u64::from_be_bytes(id), intent));
+                }
+                Err(e) => warn!("intent log: ignoring the invalid intent {key:?}: {e}"),
+            }
+        }
+
+        for (id, intent) in &pending {
+            match intent {
+                Intent::DeleteRange { cf, start, end } => {
+                    self.run_delete_range(*id, *cf, start, end)?;
+                }
+                Intent::RestoreMeta { key, value } => {
+                    let mut batch = WriteBatch::default();
+                    if value.is_empty() {
+                        batch.delete(key);
+                    } else {
+                        batch.put(key, value);
+                    }
+                    self.stage_intent_done(&mut batch, *id)?;
+                    db.write_opt(batch, &self.write_options)
+                        .context(RocksSnafu)?;
+                }
+            }
+        }
+        Ok(pending.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{unique_test_db_path, BgTaskHandler, StorageOptions};
+    use kstd::lock_mgr::LockMgr;
+    use std::sync::Arc;
+
+    fn open(path: &std::path::Path) -> Redis {
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(path.to_str().unwrap()).unwrap();
+        redis
+    }
+
+    fn intents(redis: &Redis) -> usize {
+        let db = redis.db.as_ref().unwrap();
+        let cf = redis.get_cf_handle(ColumnFamilyIndex::IntentLogCF).unwrap();
+        db.iterator_cf(&cf, IteratorMode::Start).count()
+    }
+
+    #[test]
+    fn test_intent_round_trip() {
+        for intent in [
+            Intent::DeleteRange {
+                cf: ColumnFamilyIndex::SetsDataCF,
+                start: b"a".to_vec(),
+                end: b"b".to_vec(),
+            },
+            Intent::RestoreMeta {
+                key: b"key".to_vec(),
+                value: Vec::new(),
+            },
+        ] {
+            assert_eq!(Intent::decode(&intent.encode()).unwrap(), intent);
+        }
+        assert!(Intent::decode(b"").is_err());
+        assert!(Intent::decode(&[KIND_DELETE_RANGE, 42, 0, 0, 0, 0]).is_err());
+        assert!(Intent::decode(&[KIND_RESTORE_META, 9, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_delete_range_logged() {
+        let test_db_path = unique_test_db_path();
+        let redis = open(&test_db_path);
+        {
+            let db = redis.db.as_ref().unwrap();
+            let cf = redis.get_cf_handle(ColumnFamilyIndex::SetsDataCF).unwrap();
+            for i in 0..(DELETE_RANGE_CHUNK as u32 + 10) {
+                db.put_cf(&cf, [b"r".as_slice(), &i.to_be_bytes()].concat(), b"")
+                    .unwrap();
+            }
+            db.put_cf(&cf, b"s", b"").unwrap();
+
+            let deleted = redis
+                .delete_range_logged(ColumnFamilyIndex::SetsDataCF, b"r", b"s")
+                .unwrap();
+            assert_eq!(deleted, DELETE_RANGE_CHUNK as u64 + 10);
+            assert_eq!(db.iterator_cf(&cf, IteratorMode::Start).count(), 1);
+            assert_eq!(intents(&redis), 0);
+        }
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+
+    #[test]
+    fn test_recover_intents_on_open() {
+        let test_db_path = unique_test_db_path();
+        let redis = open(&test_db_path);
+        {
+            // A range deletion interrupted before its first batch, and an
+            // operation that replaced a meta value
+            let db = redis.db.as_ref().unwrap();
+            let cf = redis.get_cf_handle(ColumnFamilyIndex::SetsDataCF).unwrap();
+            db.put_cf(&cf, b"r1", b"").unwrap();
+            db.put_cf(&cf, b"r2", b"").unwrap();
+            redis
+                .log_intent(&Intent::DeleteRange {
+                    cf: ColumnFamilyIndex::SetsDataCF,
+                    start: b"r".to_vec(),
+                    end: b"s".to_vec(),
+                })
+                .unwrap();
+            db.put(b"meta", b"new").unwrap();
+            redis
+                .log_intent(&Intent::RestoreMeta {
+                    key: b"meta".to_vec(),
+                    value: b"old".to_vec(),
+                })
+                .unwrap();
+            db.put(b"created", b"new").unwrap();
+            redis
+                .log_intent(&Intent::RestoreMeta {
+                    key: b"created".to_vec(),
+                    value: Vec::new(),
+                })
+                .unwrap();
+            assert_eq!(intents(&redis), 3);
+        }
+        redis.set_need_close(true);
+        drop(redis);
+
+        let redis = open(&test_db_path);
+        {
+            let db = redis.db.as_ref().unwrap();
+            let cf = redis.get_cf_handle(ColumnFamilyIndex::SetsDataCF).unwrap();
+            assert_eq!(db.iterator_cf(&cf, IteratorMode::Start).count(), 0);
+            assert_eq!(db.get(b"meta").unwrap().as_deref(), Some(&b"old"[..]));
+            assert_eq!(db.get(b"created").unwrap(), None);
+            assert_eq!(intents(&redis), 0);
+            assert_eq!(redis.recover_intents().unwrap(), 0);
+        }
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
@@ -0,0 +1,211 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Options that change where and how keys are written
+//!
+//! An instance opened with another key placement or key encoding than the
+//! one its keys were written with would no longer find them. These options
+//! are thus persisted in the system column family of each instance the first
+//! time it is opened, and every later open with different options is
+//! refused. An instance written before the options were persisted holds keys
+//! in the default layout.
+
+use rocksdb::IteratorMode;
+use snafu::{ensure, OptionExt, ResultExt};
+
+use crate::{
+    error::{ConfigSnafu, OptionNoneSnafu, RocksSnafu},
+    ColumnFamilyIndex, Redis, Result,
+};
+
+/// Key of the key layout in the system column family
+pub(crate) const KEY_LAYOUT_KEY: &[u8] = b"key_layout";
+
+/// Flag of `StorageOptions::hash_tag_placement`
+const HASH_TAG_PLACEMENT: u8 = 1 << 0;
+/// Flag of `StorageOptions::slot_prefix`
+const SLOT_PREFIX: u8 = 1 << 1;
+
+/// Placement and encoding options the keys of an instance are written with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct KeyLayout {
+    pub(crate) hash_tag_placement: bool,
+    pub(crate) slot_prefix: bool,
+}
+
+impl KeyLayout {
+    fn encode(self) -> [u8; 1] {
+        let mut flags = 0;
+        if self.hash_tag_placement {
+            flags |= HASH_TAG_PLACEMENT;
+        }
+        if self.slot_prefix {
+            flags |= SLOT_PREFIX;
+        }
+        [flags]
+    }
+
+    fn decode(value: &[u8]) -> Self {
+        let flags = value.first().copied().unwrap_or(0);
+        Self {
+            hash_tag_placement: flags & HASH_TAG_PLACEMENT != 0,
+            slot_prefix: flags & SLOT_PREFIX != 0,
+        }
+    }
+}
+
+impl Redis {
+    /// Persists the key layout of the options on an instance without one,
+    /// and fails if the instance was written with another layout. Called
+    /// when opening
+    pub(crate) fn check_key_layout(&self) -> Result<()> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::SystemCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let layout = KeyLayout {
+            hash_tag_placement: self.storage.hash_tag_placement,
+            slot_prefix: self.storage.slot_prefix,
+        };
+
+        let stored = match db
+            .get_cf_opt(&cf, KEY_LAYOUT_KEY, &self.read_options)
+            .context(RocksSnafu)?
+        {
+            Some(value) => Some(KeyLayout::decode(&value)),
+            None => {
+                let meta_cf =
+                    self.get_cf_handle(ColumnFamilyIndex::MetaCF)
+                        .context(OptionNoneSnafu {
+                            message: "cf is not initialized".to_string(),
+                        })?;
+                let empty = db
+                    .iterator_cf(&meta_cf, IteratorMode::Start)
+                    .next()
+                    .is_none();
+                (!empty).then(KeyLayout::default)
+            }
+        };
+        if let Some(stored) = stored {
+            ensure!(
+                stored == layout,
+                ConfigSnafu {
+                    message: format!(
+                        "RocksDB{} was written with {stored:?}, it can not be opened with {layout:?}",
+                        self.index
+                    ),
+                }
+            );
+        }
+
+        db.put_cf_opt(&cf, KEY_LAYOUT_KEY, layout.encode(), &self.write_options)
+            .context(RocksSnafu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{unique_test_db_path, BgTaskHandler, StorageOptions};
+    use kstd::lock_mgr::LockMgr;
+    use std::sync::Arc;
+
+    fn open(path: &std::path::Path, hash_tag_placement: bool) -> Result<Redis> {
+        let mut options = StorageOptions::default();
+        options.set_hash_tag_placement(hash_tag_placement);
+        open_with(path, options)
+    }
+
+    fn open_with(path: &std::path::Path, options: StorageOptions) -> Result<Redis> {
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(options),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.set_need_close(true);
+        redis.open(path.to_str().unwrap())?;
+        Ok(redis)
+    }
+
+    #[test]
+    fn test_key_layout_is_persisted() {
+        let path = unique_test_db_path();
+
+        let redis = open(&path, true).unwrap();
+        redis.set(b"{user}:name", b"value").unwrap();
+        drop(redis);
+
+        assert!(open(&path, false).is_err());
+        let redis = open(&path, true).unwrap();
+        assert_eq!(redis.get(b"{user}:name").unwrap(), "value");
+        drop(redis);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_slot_prefix_is_persisted() {
+        let path = unique_test_db_path();
+        let slot_prefix = || {
+            let mut options = StorageOptions::default();
+            options.set_slot_prefix(true);
+            options
+        };
+
+        let redis = open_with(&path, slot_prefix()).unwrap();
+        redis.set(b"key", b"value").unwrap();
+        drop(redis);
+
+        assert!(open(&path, false).is_err());
+        let redis = open_with(&path, slot_prefix()).unwrap();
+        assert_eq!(redis.get(b"key").unwrap(), "value");
+        drop(redis);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_keys_without_layout_use_the_default() {
+        let path = unique_test_db_path();
+
+        // Keys written before the layout was persisted
+        let redis = open(&path, false).unwrap();
+        redis.set(b"key", b"value").unwrap();
+        let cf = redis.get_cf_handle(ColumnFamilyIndex::SystemCF).unwrap();
+        redis
+            .db
+            .as_ref()
+            .unwrap()
+            .delete_cf(&cf, KEY_LAYOUT_KEY)
+            .unwrap();
+        drop(cf);
+        drop(redis);
+
+        assert!(open(&path, true).is_err());
+        assert!(open(&path, false).is_ok());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,162 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Iterator bounds of the data keys of one version of a key
+//!
+//! Every data key format starts with `| reserve1 | key | version |`: the
+//! fields of a hash, the members of a set, both keys of a zset member, the
+//! elements of a list, the entries and consumer groups of a stream and the
+//! segments of a bitmap. `KeyScope::version` builds that prefix and the
+//! exclusive upper bound of the keys starting with it, so a scan over
+//! HGETALL, SMEMBERS or ZRANGE sets `iterate_upper_bound` and stops at the
+//! last key of the version instead of comparing prefixes by hand.
+
+#![cfg_attr(not(test), allow(dead_code))]
+
+use rocksdb::ReadOptions;
+
+use crate::{error::Result, hashes_data_key_format::HashesDataKey};
+
+/// The keys starting with a prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyScope {
+    prefix: Vec<u8>,
+    upper_bound: Option<Vec<u8>>,
+}
+
+impl KeyScope {
+    /// Every data key of version of key, whatever its type
+    pub fn version(key: &[u8], version: u64) -> Result<Self> {
+        Ok(Self::prefix(
+            HashesDataKey::new(key, version, &[]).encode_prefix()?,
+        ))
+    }
+
+    /// Every data key of every version of key, whatever its type
+    pub fn key(key: &[u8]) -> Result<Self> {
+        let mut prefix = HashesDataKey::new(key, 0, &[]).encode_prefix()?;
+        // The encoded key ends with a delimiter, so no other key extends it
+        prefix.truncate(prefix.len() - std::mem::size_of::<u64>());
+        Ok(Self::prefix(prefix))
+    }
+
+    /// Every key starting with prefix
+    pub fn prefix(prefix: Vec<u8>) -> Self {
+        let upper_bound = prefix_successor(&prefix);
+        Self {
+            prefix,
+            upper_bound,
+        }
+    }
+
+    /// Where a forward scan of the scope starts
+    pub fn start(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// The first key after the scope, None when no key sorts after it
+    pub fn upper_bound(&self) -> Option<&[u8]> {
+        self.upper_bound.as_deref()
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key.starts_with(&self.prefix)
+    }
+
+    /// Read options bounding an iterator to the scope
+    pub fn read_options(&self) -> ReadOptions {
+        let mut read_options = ReadOptions::default();
+        read_options.set_iterate_lower_bound(self.prefix.clone());
+        if let Some(upper_bound) = &self.upper_bound {
+            read_options.set_iterate_upper_bound(upper_bound.clone());
+        }
+        read_options
+    }
+}
+
+/// The smallest key greater than every key starting with prefix, None if
+/// prefix is empty or only made of 0xff
+pub fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|byte| *byte != 0xff)?;
+    let mut successor = prefix[..=last].to_vec();
+    successor[last] += 1;
+    Some(successor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bitmap_segment_format::BitmapSegmentKey,
+        lists_data_key_format::ListsDataKey,
+        sets_member_key_format::SetsMemberKey,
+        streams_data_key_format::StreamId,
+        streams_data_key_format::StreamsDataKey,
+        streams_group_format::StreamsGroupKey,
+        zsets_data_key_format::{ZSetsMemberKey, ZSetsScoreKey},
+    };
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(prefix_successor(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_successor(b"a\xff\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_successor(b"\xff\xff"), None);
+        assert_eq!(prefix_successor(b""), None);
+    }
+
+    #[test]
+    fn test_version_scope_covers_every_data_key() -> Result<()> {
+        for key in [&b"k"[..], b"k\x00ey", b"\xff\xff"] {
+            let version = 7;
+            let scope = KeyScope::version(key, version)?;
+            let upper_bound = scope.upper_bound().unwrap();
+            let data_keys = [
+                HashesDataKey::new(key, version, b"field").encode()?,
+                SetsMemberKey::new(key, version, b"member").encode()?,
+                ZSetsMemberKey::new(key, version, b"member").encode()?,
+                ZSetsScoreKey::new(key, version, f64::MAX, b"member").encode()?,
+                ListsDataKey::new(key, version, u64::MAX).encode()?,
+                StreamsDataKey::new(key, version, StreamId::MAX).encode()?,
+                StreamsGroupKey::group(key, version, b"group").encode()?,
+                BitmapSegmentKey::new(key, version, u64::MAX).encode()?,
+            ];
+            for data_key in data_keys {
+                assert!(scope.contains(&data_key));
+                assert!(scope.start() <= &data_key[..]);
+                assert!(&data_key[..] < upper_bound);
+            }
+
+            // neither the next version nor a key extending this one
+            for other in [
+                HashesDataKey::new(key, version + 1, b"field").encode()?,
+                HashesDataKey::new(&[key, b"2"].concat(), version, b"field").encode()?,
+            ] {
+                assert!(!scope.contains(&other));
+                assert!(other[..] < *scope.start() || other[..] >= *upper_bound);
+            }
+
+            // every version, but still not a key extending this one
+            let scope = KeyScope::key(key)?;
+            assert!(scope.contains(&HashesDataKey::new(key, 0, b"field").encode()?));
+            assert!(scope.contains(&HashesDataKey::new(key, u64::MAX, b"field").encode()?));
+            assert!(!scope.contains(&HashesDataKey::new(&[key, b"2"].concat(), 0, b"f").encode()?));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Keyspace notifications and their replay buffer
+//!
+//! Every write that Storage notifies is published on the
+//! `__keyspace@<db>__:<key>` and `__keyevent@<db>__:<event>` channels, as
+//! Redis does. A notification is lost for a subscriber that is not connected
+//! when it is published, so a cache-invalidation consumer would have to
+//! resync everything after a reconnect. With the replay buffer enabled, the
+//! last notifications are also kept with increasing sequence numbers, and a
+//! consumer that reconnects asks for the events after the last sequence it
+//! saw, resyncing only when the buffer no longer reaches back that far.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A recorded keyspace notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyspaceEvent {
+    /// Increasing sequence number, starting at 1
+    pub seq: u64,
+    /// Event name, such as "del"
+    pub event: &'static str,
+    pub key: Vec<u8>,
+}
+
+#[derive(Default)]
+struct EventRing {
+    events: VecDeque<KeyspaceEvent>,
+    last_seq: u64,
+}
+
+/// The last `capacity` keyspace notifications of a database
+pub struct KeyspaceEventLog {
+    ring: Mutex<EventRing>,
+    capacity: usize,
+}
+
+impl KeyspaceEventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ring: Mutex::new(EventRing::default()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Records an event and returns its sequence number, evicting the oldest
+    /// event when the buffer is full
+    pub fn record(&self, event: &'static str, key: &[u8]) -> u64 {
+        let mut ring = self.ring.lock().unwrap();
+        ring.last_seq += 1;
+        let seq = ring.last_seq;
+        if ring.events.len() == self.capacity {
+            ring.events.pop_front();
+        }
+        ring.events.push_back(KeyspaceEvent {
+            seq,
+            event,
+            key: key.to_vec(),
+        });
+        seq
+    }
+
+    /// Sequence number of the last recorded event, 0 when there is none
+    pub fn last_seq(&self) -> u64 {
+        self.ring.lock().unwrap().last_seq
+    }
+
+    /// The last `count` events, oldest first
+    pub fn last(&self, count: usize) -> Vec<KeyspaceEvent> {
+        let ring = self.ring.lock().unwrap();
+        let skip = ring.events.len().saturating_sub(count);
+        ring.events.iter().skip(skip).cloned().collect()
+    }
+
+    /// The events after `seq`, oldest first. None when some of them were
+    /// already evicted, the caller has missed events and must resync
+    pub fn since(&self, seq: u64) -> Option<Vec<KeyspaceEvent>> {
+        let ring = self.ring.lock().unwrap();
+        let oldest = ring.events.front().map_or(ring.last_seq + 1, |e| e.seq);
+        if seq >= ring.last_seq {
+            return Some(Vec::new());
+        }
+        if seq + 1 < oldest {
+            return None;
+        }
+        let skip = (seq + 1 - oldest) as usize;
+        Some(ring.events.iter().skip(skip).cloned().collect())
+    }
+}
+
+/// Channel of the events of `key`, the payload is the event name
+pub fn keyspace_channel(db_id: usize, key: &[u8]) -> Vec<u8> {
+    let mut channel = format!("__keyspace@{db_id}__:").into_bytes();
+    channel.extend_from_slice(key);
+    channel
+}
+
+/// Channel of the `event` events, the payload is the key
+pub fn keyevent_channel(db_id: usize, event: &str) -> Vec<u8> {
+    format!("__keyevent@{db_id}__:{event}").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_last() {
+        let log = KeyspaceEventLog::new(3);
+        assert_eq!(log.last_seq(), 0);
+        assert!(log.last(10).is_empty());
+
+        assert_eq!(log.record("del", b"a"), 1);
+        assert_eq!(log.record("del", b"b"), 2);
+        assert_eq!(log.record("del", b"c"), 3);
+        assert_eq!(log.record("del", b"d"), 4);
+        assert_eq!(log.last_seq(), 4);
+
+        let last = log.last(2);
+        assert_eq!(last.len(), 2);
+        assert_eq!((last[0].seq, &last[0].key[..]), (3, &b"c"[..]));
+        assert_eq!((last[1].seq, &last[1].key[..]), (4, &b"d"[..]));
+        // The first event was evicted
+        assert_eq!(log.last(10).len(), 3);
+    }
+
+    #[test]
+    fn test_since() {
+        let log = KeyspaceEventLog::new(3);
+        assert_eq!(log.since(0), Some(Vec::new()));
+
+        for key in [b"a", b"b", b"c", b"d"] {
+            log.record("del", key);
+        }
+
+        let seqs = |events: Option<Vec<KeyspaceEvent>>| {
+            events.map(|events| events.iter().map(|e| e.seq).collect::<Vec<_>>())
+        };
+        assert_eq!(seqs(log.since(1)), Some(vec![2, 3, 4]));
+        assert_eq!(seqs(log.since(3)), Some(vec![4]));
+        assert_eq!(seqs(log.since(4)), Some(vec![]));
+        assert_eq!(seqs(log.since(10)), Some(vec![]));
+        // Event 1 is gone, a consumer that only saw event 0 must resync
+        assert_eq!(seqs(log.since(0)), None);
+    }
+
+    #[test]
+    fn test_channels() {
+        assert_eq!(keyspace_channel(2, b"user:1"), b"__keyspace@2__:user:1");
+        assert_eq!(keyevent_channel(0, "del"), b"__keyevent@0__:del");
+    }
+}
@@ -0,0 +1,338 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Lazy deletion of the data keys of deleted collections
+//!
+//! DEL only drops the meta value, the data keys of the version it pointed
+//! to stay until a compaction happens to visit them, which for a huge hash
+//! can take long. Once the deletion is written, DEL queues the key and the
+//! old version, and `Storage::lazy_delete_worker` drops every data key of
+//! that version with one `delete_range` per data column family.
+//!
+//! Versions are never reused, so the range cannot hold keys of a newer
+//! collection of the same name. The queue is in memory: the tasks lost on
+//! a crash, or dropped when the queue is full, are left to the compaction
+//! filters as before. Keys moved to the trash are not queued, UNDELETE
+//! still needs their data.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use rocksdb::WriteBatch;
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    base_meta_value_format::ParsedBaseMetaValue,
+    bitmap_segment_format::SegmentedBitmapHeader,
+    error::{OptionNoneSnafu, RocksSnafu},
+    key_scope::KeyScope,
+    list_meta_value_format::ParsedListsMetaValue,
+    streams_meta_value_format::ParsedStreamsMetaValue,
+    strings_value_format::ParsedStringsValue,
+    ColumnFamilyIndex, DataType, Redis, Result,
+};
+
+/// The data keys of one version of a deleted key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LazyDeleteTask {
+    pub key: Vec<u8>,
+    pub version: u64,
+    pub data_type: DataType,
+}
+
+impl LazyDeleteTask {
+    /// The task dropping the data keys the meta value `meta_value` of `key`
+    /// points to, None for the values without data keys
+    pub fn from_meta_value(key: &[u8], meta_value: &[u8]) -> Option<Self> {
+        let data_type = meta_value
+            .first()
+            .and_then(|t| DataType::try_from(*t).ok())?;
+        let version = match data_type {
+            DataType::Hash | DataType::Set | DataType::ZSet => {
+                let meta = ParsedBaseMetaValue::new(meta_value).ok()?;
+                // An inline collection keeps its elements in the meta value
+                if meta.is_inline() {
+                    return None;
+                }
+                meta.version()
+            }
+            DataType::List => ParsedListsMetaValue::new(meta_value).ok()?.version(),
+            DataType::Stream => ParsedStreamsMetaValue::new(meta_value).ok()?.version(),
+            DataType::String => {
+                let value = ParsedStringsValue::new(meta_value).ok()?;
+                if !value.is_segmented() {
+                    return None;
+                }
+                SegmentedBitmapHeader::decode(value.user_value_slice())
+                    .ok()?
+                    .version
+            }
+            DataType::None | DataType::All => return None,
+        };
+        Some(Self {
+            key: key.to_vec(),
+            version,
+            data_type,
+        })
+    }
+
+    /// The column families holding the data keys of the task
+    pub fn column_families(&self) -> &'static [ColumnFamilyIndex] {
+        data_column_families(self.data_type)
+    }
+}
+
+/// The column families holding the data keys of the values of `data_type`
+pub(crate) fn data_column_families(data_type: DataType) -> &'static [ColumnFamilyIndex] {
+    match data_type {
+        DataType::Hash => &[ColumnFamilyIndex::HashesDataCF],
+        DataType::Set => &[ColumnFamilyIndex::SetsDataCF],
+        DataType::ZSet => &[
+            ColumnFamilyIndex::ZsetsDataCF,
+            ColumnFamilyIndex::ZsetsScoreCF,
+        ],
+        DataType::List => &[ColumnFamilyIndex::ListsDataCF],
+        DataType::Stream => &[
+            ColumnFamilyIndex::StreamsDataCF,
+            ColumnFamilyIndex::StreamsGroupCF,
+        ],
+        DataType::String => &[ColumnFamilyIndex::BitmapDataCF],
+        DataType::None | DataType::All => &[],
+    }
+}
+
+/// The lazy deletion tasks of an instance, bounded
+#[derive(Debug)]
+pub struct LazyDeleteQueue {
+    tasks: Mutex<VecDeque<LazyDeleteTask>>,
+    capacity: usize,
+    enqueued: AtomicU64,
+    dropped: AtomicU64,
+    done: AtomicU64,
+}
+
+impl LazyDeleteQueue {
+    /// A queue of at most `capacity` tasks, 0 to queue none
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tasks: Mutex::new(VecDeque::new()),
+            capacity,
+            enqueued: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            done: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues `task`, false when the queue is full and it is dropped
+    pub fn push(&self, task: LazyDeleteTask) -> bool {
+        let mut tasks = self.tasks.lock().unwrap();
+        if tasks.len() >= self.capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        tasks.push_back(task);
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Takes up to `max` tasks, oldest first
+    pub fn pop_batch(&self, max: usize) -> Vec<LazyDeleteTask> {
+        let mut tasks = self.tasks.lock().unwrap();
+        let n = max.min(tasks.len());
+        tasks.drain(..n).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Tasks queued since the start
+    pub fn enqueued(&self) -> u64 {
+        self.enqueued.load(Ordering::Relaxed)
+    }
+
+    /// Tasks dropped on a full queue since the start
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Tasks whose data keys were deleted since the start
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+}
+
+impl Redis {
+    /// Queues the deletion of the data keys of `meta_value`, the meta value
+    /// of `key` a deletion just removed
+    pub(crate) fn enqueue_lazy_delete(&self, key: &[u8], meta_value: &[u8]) {
+        if self.storage.trash_retention_s > 0 {
+            return;
+        }
+        if let Some(task) = LazyDeleteTask::from_meta_value(key, meta_value) {
+            self.lazy_delete.push(task);
+        }
+    }
+
+    /// Deletes the data keys of up to `max` queued tasks in one batch and
+    /// returns how many tasks were done
+    pub fn run_lazy_deletes(&self, max: usize) -> Result<usize> {
+        let tasks = self.lazy_delete.pop_batch(max);
+        if tasks.is_empty() {
+            return Ok(0);
+        }
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+
+        let mut batch = WriteBatch::default();
+        for task in &tasks {
+            let scope = KeyScope::version(&task.key, task.version)?;
+            // A prefix always ends with a version and has a successor
+            let Some(end) = scope.upper_bound() else {
+                continue;
+            };
+            for &cf_index in task.column_families() {
+                let cf = self.get_cf_handle(cf_index).context(OptionNoneSnafu {
+                    message: "cf is not initialized".to_string(),
+                })?;
+                batch.delete_range_cf(&cf, scope.start(), end);
+            }
+        }
+        db.write_opt(batch, &self.write_options)
+            .context(RocksSnafu)?;
+
+        self.lazy_delete
+            .done
+            .fetch_add(tasks.len() as u64, Ordering::Relaxed);
+        Ok(tasks.len())
+    }
+
+    /// Formats the queue as the lazy deletion lines of INFO
+    pub(crate) fn lazy_delete_info(&self, info: &mut String) {
+        let queue = &self.lazy_delete;
+        let _ = write!(
+            info,
+            "instance{}_lazy_delete:pending={},enqueued={},dropped={},done={}\r\n",
+            self.index,
+            queue.len(),
+            queue.enqueued(),
+            queue.dropped(),
+            queue.done(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base_meta_value_format::BaseMetaValue, hashes_data_key_format::HashesDataKey,
+        hashes_data_value_format::HashesDataValue, unique_test_db_path, BgTaskHandler,
+        StorageOptions, ValueFormat,
+    };
+    use kstd::lock_mgr::LockMgr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_lazy_delete_queue() {
+        let queue = LazyDeleteQueue::new(2);
+        let task = |version| LazyDeleteTask {
+            key: b"k".to_vec(),
+            version,
+            data_type: DataType::Hash,
+        };
+        assert!(queue.push(task(1)));
+        assert!(queue.push(task(2)));
+        assert!(!queue.push(task(3)));
+        assert_eq!((queue.enqueued(), queue.dropped()), (2, 1));
+
+        assert_eq!(queue.pop_batch(1), vec![task(1)]);
+        assert_eq!(queue.pop_batch(10), vec![task(2)]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_run_lazy_deletes() {
+        let test_db_path = unique_test_db_path();
+        let (handler, _receiver) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+
+        let db = redis.db.as_ref().unwrap();
+        let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+        let data_cf = redis
+            .get_cf_handle(ColumnFamilyIndex::HashesDataCF)
+            .unwrap();
+        let mut meta = BaseMetaValue::new(100);
+        meta.inner.data_type = DataType::Hash;
+        let version = meta.update_version();
+        for i in 0..100u32 {
+            let data_key = HashesDataKey::new(b"big", version, &i.to_be_bytes());
+            db.put_cf(
+                &data_cf,
+                data_key.encode().unwrap(),
+                HashesDataValue::new(b"v".to_vec()).encode(),
+            )
+            .unwrap();
+        }
+        // Another hash sorting right after, which must survive
+        let other = HashesDataKey::new(b"big", version + 1, b"f")
+            .encode()
+            .unwrap();
+        db.put_cf(
+            &data_cf,
+            &other,
+            HashesDataValue::new(b"v".to_vec()).encode(),
+        )
+        .unwrap();
+        let meta_value = ValueFormat::encode(&meta);
+        db.put_cf(
+            &meta_cf,
+            redis.base_key(b"big").encode().unwrap(),
+            &meta_value,
+        )
+        .unwrap();
+
+        assert_eq!(redis.del(&[b"big"]).unwrap(), 1);
+        assert_eq!(redis.lazy_delete.len(), 1);
+        assert_eq!(redis.run_lazy_deletes(16).unwrap(), 1);
+        assert_eq!(redis.lazy_delete.done(), 1);
+
+        let remaining: Vec<_> = db
+            .iterator_cf(&data_cf, rocksdb::IteratorMode::Start)
+            .map(|item| item.unwrap().0.to_vec())
+            .collect();
+        assert_eq!(remaining, vec![other]);
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
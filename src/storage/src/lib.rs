@@ -17,35 +17,115 @@
  * limitations under the License.
  */
 
+pub mod active_expire;
+pub mod applied_offset;
 mod base_data_value_format;
 // mod base_data_key_format;
 mod base_filter;
 mod base_key_format;
 mod base_meta_value_format;
 mod base_value_format;
+mod bitmap_segment_format;
+pub mod cf_tuning;
+mod checksum;
+pub mod clock;
 mod coding;
+mod compression;
+pub mod databases;
+pub mod dump_format;
 pub mod error;
+pub mod filter_config;
+pub mod filter_stats;
+mod format_version;
+pub mod geo;
+mod hashes_data_key_format;
+mod hashes_data_value_format;
+pub mod hot_key_detector;
+pub mod hyperloglog_format;
+mod inline_collection_format;
+mod intent_log;
+mod key_layout;
+mod key_scope;
+pub mod keyspace_events;
+pub mod lazy_delete;
+pub mod lifecycle_events;
 mod list_meta_value_format;
+mod list_recenter;
 mod lists_data_key_format;
+mod lists_filter;
 // mod lru_cache;
 pub mod options;
+pub mod perf_stats;
+pub mod pipeline;
+pub mod pubsub;
 mod redis;
-mod slot_indexer;
+pub mod replication_filter;
+pub mod results;
+pub mod self_test;
+mod sets_member_key_format;
+pub mod slot_indexer;
+pub mod stale_ranges;
 mod statistics;
 pub mod storage;
 mod storage_define;
 mod storage_impl;
 mod storage_murmur3;
+mod streams_data_key_format;
+mod streams_data_value_format;
+mod streams_filter;
+mod streams_group_format;
+mod streams_meta_value_format;
+mod streams_trim;
 mod strings_value_format;
+pub mod trash;
 mod util;
+pub mod value_decode;
+mod version_seq;
+pub mod warmup;
+mod zsets_data_key_format;
 
 // commands
+mod redis_bitmaps;
+mod redis_hash_fields;
+mod redis_hash_values;
+mod redis_keys;
+mod redis_list_queue;
+mod redis_streams;
 mod redis_strings;
+mod redis_zset_members;
 
+pub use applied_offset::AppliedOffset;
+pub use base_filter::{empty_meta_values_kept, meta_values_removed};
 pub use base_value_format::*;
+pub use cf_tuning::{CfTuning, LevelCompression, RocksDbTuning};
+pub use databases::{Databases, DbGuard};
+pub use dump_format::DumpValue;
 pub use error::Result;
-pub use options::StorageOptions;
+pub use filter_config::{FilterConfig, FilterPolicy, SharedFilterConfig};
+pub use filter_stats::{FilterKind, FilterOutcome, FilterStatsSnapshot};
+pub use hot_key_detector::HotKeyDetector;
+pub use hyperloglog_format::HyperLogLog;
+pub use inline_collection_format::{encoding_info, inline_spills};
+pub use keyspace_events::{KeyspaceEvent, KeyspaceEventLog};
+pub use lifecycle_events::{LifecycleEvent, LifecycleEventKind, StallCondition};
+pub use options::{DurabilityLevel, StorageOptions, ValueCompression};
+pub use perf_stats::{ReadPerfSnapshot, ReadPerfStats};
+pub use pipeline::{Pipeline, PipelineOp, PipelineResult};
+pub use pubsub::{Message, PubSub, Subscription};
 pub use redis::{ColumnFamilyIndex, Redis};
+pub use redis_hash_fields::FieldExpiry;
+pub use redis_list_queue::ListEnd;
+pub use redis_streams::{AutoClaim, AutoClaimOptions};
+pub use redis_strings::{SetCondition, SetOptions};
+pub use replication_filter::ReplicationFilter;
+pub use results::{DelResult, ExistsResult, PushResult, SetResult, UndeleteResult};
+pub use self_test::{SelfTestOptions, SelfTestReport};
+pub use slot_indexer::{extract_hash_tag, key_to_hash_slot, key_to_slot_id, SlotIndexer};
 pub use statistics::KeyStatistics;
 pub use storage::{BgTask, BgTaskHandler};
-pub use util::unique_test_db_path;
+pub use streams_data_key_format::StreamId;
+#[cfg(test)]
+pub(crate) use util::with_redis;
+pub use util::{string_match, unique_test_db_path};
+pub use value_decode::{DecodeFormat, DecodedField};
+pub use warmup::{WarmupStats, WarmupTarget};
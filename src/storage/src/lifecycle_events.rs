@@ -0,0 +1,248 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Flush, compaction and write stall events of the instances
+//!
+//! The rocksdb crate does not expose the EventListener of RocksDB, so
+//! `Storage::lifecycle_event_worker` samples the running flushes and
+//! compactions and the write stall state of every instance, and turns the
+//! changes between two samples into events. A flush or compaction that
+//! starts and ends between two samples is not seen, the interval bounds
+//! how late an event is and how short a job can be missed.
+//!
+//! Events go to the subscribers of `LifecycleEvents::subscribe` and to
+//! per-kind counters reported in INFO.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::broadcast;
+
+use crate::{Redis, Result};
+
+/// Events kept for a subscriber that lags behind, older ones are dropped
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// How writes are throttled by RocksDB
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StallCondition {
+    #[default]
+    Normal,
+    /// Writes are slowed down
+    Delayed,
+    /// Writes wait until flushes or compactions catch up
+    Stopped,
+}
+
+impl StallCondition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StallCondition::Normal => "normal",
+            StallCondition::Delayed => "delayed",
+            StallCondition::Stopped => "stopped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleEventKind {
+    FlushBegin,
+    FlushCompleted,
+    CompactionCompleted,
+    StallChanged(StallCondition),
+}
+
+/// An event of the instance `instance`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LifecycleEvent {
+    pub instance: i32,
+    pub kind: LifecycleEventKind,
+}
+
+/// The state of an instance the events are derived from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LifecycleSample {
+    pub running_flushes: u64,
+    pub running_compactions: u64,
+    pub stall: StallCondition,
+}
+
+impl LifecycleSample {
+    /// The events between the `prev` sample and this one
+    pub fn events_since(&self, prev: &LifecycleSample) -> Vec<LifecycleEventKind> {
+        let mut events = Vec::new();
+        for _ in prev.running_flushes..self.running_flushes {
+            events.push(LifecycleEventKind::FlushBegin);
+        }
+        for _ in self.running_flushes..prev.running_flushes {
+            events.push(LifecycleEventKind::FlushCompleted);
+        }
+        for _ in self.running_compactions..prev.running_compactions {
+            events.push(LifecycleEventKind::CompactionCompleted);
+        }
+        if self.stall != prev.stall {
+            events.push(LifecycleEventKind::StallChanged(self.stall));
+        }
+        events
+    }
+}
+
+impl Redis {
+    /// Samples the running flushes and compactions and the write stall
+    /// state of the instance
+    pub fn lifecycle_sample(&self) -> Result<LifecycleSample> {
+        let stall = if self.get_property("rocksdb.is-write-stopped")? != 0 {
+            StallCondition::Stopped
+        } else if self.get_property("rocksdb.actual-delayed-write-rate")? != 0 {
+            StallCondition::Delayed
+        } else {
+            StallCondition::Normal
+        };
+        Ok(LifecycleSample {
+            running_flushes: self.get_property("rocksdb.num-running-flushes")?,
+            running_compactions: self.get_property("rocksdb.num-running-compactions")?,
+            stall,
+        })
+    }
+}
+
+/// The lifecycle events of the instances of a storage
+pub struct LifecycleEvents {
+    sender: broadcast::Sender<LifecycleEvent>,
+    flushes_begun: AtomicU64,
+    flushes_completed: AtomicU64,
+    compactions_completed: AtomicU64,
+    stall_changes: AtomicU64,
+}
+
+impl Default for LifecycleEvents {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            flushes_begun: AtomicU64::new(0),
+            flushes_completed: AtomicU64::new(0),
+            compactions_completed: AtomicU64::new(0),
+            stall_changes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LifecycleEvents {
+    /// A stream of the events from now on. A subscriber too slow to keep up
+    /// gets `RecvError::Lagged` and then the newest events
+    pub fn subscribe(&self) -> broadcast::Receiver<LifecycleEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Counts `event` and sends it to the subscribers
+    pub fn publish(&self, event: LifecycleEvent) {
+        let counter = match event.kind {
+            LifecycleEventKind::FlushBegin => &self.flushes_begun,
+            LifecycleEventKind::FlushCompleted => &self.flushes_completed,
+            LifecycleEventKind::CompactionCompleted => &self.compactions_completed,
+            LifecycleEventKind::StallChanged(_) => &self.stall_changes,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        // No subscriber is not an error
+        let _ = self.sender.send(event);
+    }
+
+    pub fn flushes_completed(&self) -> u64 {
+        self.flushes_completed.load(Ordering::Relaxed)
+    }
+
+    pub fn compactions_completed(&self) -> u64 {
+        self.compactions_completed.load(Ordering::Relaxed)
+    }
+
+    /// Formats the counters as the lifecycle section of INFO, with the
+    /// current stall condition of every instance.
+    pub fn info(&self, stalls: &[(i32, StallCondition)]) -> String {
+        let mut info = String::from("# Lifecycle\r\n");
+        let _ = write!(
+            info,
+            "flushes_begun:{}\r\nflushes_completed:{}\r\ncompactions_completed:{}\r\nstall_changes:{}\r\n",
+            self.flushes_begun.load(Ordering::Relaxed),
+            self.flushes_completed.load(Ordering::Relaxed),
+            self.compactions_completed.load(Ordering::Relaxed),
+            self.stall_changes.load(Ordering::Relaxed),
+        );
+        for (instance, stall) in stalls {
+            let _ = write!(info, "instance{instance}_stall:{}\r\n", stall.as_str());
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_since() {
+        let idle = LifecycleSample::default();
+        let busy = LifecycleSample {
+            running_flushes: 2,
+            running_compactions: 1,
+            stall: StallCondition::Delayed,
+        };
+        assert!(idle.events_since(&idle).is_empty());
+        assert_eq!(
+            busy.events_since(&idle),
+            vec![
+                LifecycleEventKind::FlushBegin,
+                LifecycleEventKind::FlushBegin,
+                LifecycleEventKind::StallChanged(StallCondition::Delayed),
+            ]
+        );
+        assert_eq!(
+            idle.events_since(&busy),
+            vec![
+                LifecycleEventKind::FlushCompleted,
+                LifecycleEventKind::FlushCompleted,
+                LifecycleEventKind::CompactionCompleted,
+                LifecycleEventKind::StallChanged(StallCondition::Normal),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_publish_and_subscribe() {
+        let events = LifecycleEvents::default();
+        // Publishing without subscribers only counts
+        events.publish(LifecycleEvent {
+            instance: 0,
+            kind: LifecycleEventKind::FlushCompleted,
+        });
+
+        let mut receiver = events.subscribe();
+        let stopped = LifecycleEvent {
+            instance: 1,
+            kind: LifecycleEventKind::StallChanged(StallCondition::Stopped),
+        };
+        events.publish(stopped);
+        assert_eq!(receiver.try_recv().unwrap(), stopped);
+        assert!(receiver.try_recv().is_err());
+
+        assert_eq!(events.flushes_completed(), 1);
+        assert_eq!(events.compactions_completed(), 0);
+        let info = events.info(&[(1, StallCondition::Stopped)]);
+        assert!(info.contains("flushes_completed:1\r\n"));
+        assert!(info.contains("stall_changes:1\r\n"));
+        assert!(info.contains("instance1_stall:stopped\r\n"));
+    }
+}
@@ -18,28 +18,57 @@
  */
 
 use crate::{
-    base_value_format::{DataType, InternalValue, ParsedInternalValue},
-    delegate_internal_value, delegate_parsed_value,
-    error::{InvalidFormatSnafu, Result},
+    base_value_format::{DataType, InternalValue, ParsedInternalValue, ValueBuf},
+    checksum, delegate_internal_value, delegate_parsed_value,
+    error::{CorruptValueSnafu, InvalidFormatSnafu, Result},
+    format_version::{FormatMigrator, ValueLayout},
+    impl_value_format,
     storage_define::{
-        BASE_META_VALUE_COUNT_LENGTH, SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH, TYPE_LENGTH,
+        BASE_META_VALUE_COUNT_LENGTH, EMPTY_META_GRACE_MICROS, LISTS_META_VALUE_LENGTH,
+        LIST_VALUE_INDEX_LENGTH, SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH, TYPE_LENGTH,
         VERSION_LENGTH,
     },
+    version_seq,
 };
-use bytes::{Buf, BufMut, Bytes, BytesMut};
-use chrono::Utc;
-use snafu::ensure;
-use std::io::Cursor;
-
-// Constants from C++ version
-const INITIAL_LEFT_INDEX: u64 = 9223372036854775807;
-const INITIAL_RIGHT_INDEX: u64 = 9223372036854775808;
-const LIST_VALUE_INDEX_LENGTH: usize = 8;
+use bytes::{BufMut, Bytes, BytesMut};
+use rocksdb::CompactionDecision;
+use snafu::{ensure, OptionExt};
+
+// Constants from C++ version. The indexes are exclusive: the elements of a
+// list are the data keys in left_index + 1..right_index
+pub(crate) const INITIAL_LEFT_INDEX: u64 = 9223372036854775807;
+pub(crate) const INITIAL_RIGHT_INDEX: u64 = 9223372036854775808;
+/// Room kept between the indexes of a list and the ends of the index space,
+/// see `ParsedListsMetaValue::needs_recenter`
+const LIST_INDEX_MARGIN: u64 = 1 << 20;
 
 /*
  * | type  | list_size | version | left index | right index | reserve |  cdate | timestamp |
  * |  1B   |     8B    |    8B   |     8B     |      8B     |   16B   |    8B  |     8B    |
  */
+// The offset of every field, both encode and parse go through these
+const COUNT_OFFSET: usize = TYPE_LENGTH;
+const VERSION_OFFSET: usize = COUNT_OFFSET + BASE_META_VALUE_COUNT_LENGTH;
+const LEFT_INDEX_OFFSET: usize = VERSION_OFFSET + VERSION_LENGTH;
+const RIGHT_INDEX_OFFSET: usize = LEFT_INDEX_OFFSET + LIST_VALUE_INDEX_LENGTH;
+const RESERVE_OFFSET: usize = RIGHT_INDEX_OFFSET + LIST_VALUE_INDEX_LENGTH;
+const CTIME_OFFSET: usize = RESERVE_OFFSET + SUFFIX_RESERVE_LENGTH;
+const ETIME_OFFSET: usize = CTIME_OFFSET + TIMESTAMP_LENGTH;
+/// Everything after the count
+const LISTS_META_VALUE_SUFFIX_LENGTH: usize = LISTS_META_VALUE_LENGTH - VERSION_OFFSET;
+
+const _: () = assert!(ETIME_OFFSET + TIMESTAMP_LENGTH == LISTS_META_VALUE_LENGTH);
+
+fn read_u64(value: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&value[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn write_u64(value: &mut [u8], offset: usize, field: u64) {
+    value[offset..offset + 8].copy_from_slice(&field.to_le_bytes());
+}
+
 #[allow(dead_code)]
 pub struct ListsMetaValue {
     pub inner: InternalValue,
@@ -48,6 +77,7 @@ pub struct ListsMetaValue {
 }
 
 delegate_internal_value!(ListsMetaValue);
+impl_value_format!(ListsMetaValue, ParsedListsMetaValue);
 #[allow(dead_code)]
 impl ListsMetaValue {
     pub fn new<T>(list_size: T) -> Self
@@ -62,11 +92,7 @@ impl ListsMetaValue {
     }
 
     pub fn update_version(&mut self) -> u64 {
-        let now = Utc::now().timestamp_micros() as u64;
-        self.inner.version = match self.inner.version >= now {
-            true => self.inner.version + 1,
-            false => now,
-        };
+        self.inner.version = version_seq::next_version(self.inner.version);
         self.inner.version
     }
 
@@ -74,8 +100,10 @@ impl ListsMetaValue {
         self.left_index
     }
 
+    /// `needs_recenter` keeps the indexes away from the ends of the index
+    /// space, as for `ParsedListsMetaValue::modify_left_index`
     pub fn modify_left_index(&mut self, index: u64) {
-        self.left_index -= index;
+        self.left_index = self.left_index.wrapping_sub(index);
     }
 
     pub fn right_index(&self) -> u64 {
@@ -83,27 +111,24 @@ impl ListsMetaValue {
     }
 
     pub fn modify_right_index(&mut self, index: u64) {
-        self.right_index += index;
+        self.right_index = self.right_index.wrapping_add(index);
     }
 
     fn encode(&self) -> BytesMut {
-        // type(1) + user_value + version(8) + left_index(8) + right_index(8) + reserve(16) + ctime(8) + etime(8)
-        let needed = TYPE_LENGTH
-            + self.inner.user_value.len()
-            + VERSION_LENGTH
-            + 2 * LIST_VALUE_INDEX_LENGTH
-            + SUFFIX_RESERVE_LENGTH
-            + 2 * TIMESTAMP_LENGTH;
-        let mut buf = BytesMut::with_capacity(needed);
+        debug_assert_eq!(self.inner.user_value.len(), BASE_META_VALUE_COUNT_LENGTH);
+        let mut buf = BytesMut::with_capacity(LISTS_META_VALUE_LENGTH);
 
         buf.put_u8(self.inner.data_type as u8);
         buf.extend_from_slice(&self.inner.user_value);
         buf.put_u64_le(self.inner.version);
         buf.put_u64_le(self.left_index);
         buf.put_u64_le(self.right_index);
+        debug_assert_eq!(buf.len(), RESERVE_OFFSET);
         buf.extend_from_slice(&self.inner.reserve);
         buf.put_u64_le(self.inner.ctime);
         buf.put_u64_le(self.inner.etime);
+        debug_assert_eq!(buf.len(), LISTS_META_VALUE_LENGTH);
+        checksum::seal(&mut buf, RESERVE_OFFSET);
 
         buf
     }
@@ -120,44 +145,34 @@ pub struct ParsedListsMetaValue {
 delegate_parsed_value! {ParsedListsMetaValue}
 #[allow(dead_code)]
 impl ParsedListsMetaValue {
-    const LISTS_META_VALUE_SUFFIX_LENGTH: usize =
-        VERSION_LENGTH + 2 * LIST_VALUE_INDEX_LENGTH + SUFFIX_RESERVE_LENGTH + 2 * TIMESTAMP_LENGTH;
-    const LISTS_META_VALUE_LENGTH: usize =
-        TYPE_LENGTH + BASE_META_VALUE_COUNT_LENGTH + Self::LISTS_META_VALUE_SUFFIX_LENGTH;
-
     pub fn new<T>(internal_value: T) -> Result<Self>
     where
-        T: Into<BytesMut>,
+        T: Into<ValueBuf>,
     {
-        let value: BytesMut = internal_value.into();
-        let value_len = value.len();
+        let value =
+            FormatMigrator::global().upgrade(ValueLayout::ListsMeta, internal_value.into())?;
         ensure!(
-            value_len >= Self::LISTS_META_VALUE_LENGTH,
-            InvalidFormatSnafu {
-                message: format!(
-                    "invalid lists meta value length: {} < {}",
-                    value.len(),
-                    Self::LISTS_META_VALUE_LENGTH,
-                )
+            value.len() == LISTS_META_VALUE_LENGTH,
+            CorruptValueSnafu {
+                data_type: DataType::List,
+                field: "list meta value",
+                offset: 0usize,
+                expected: LISTS_META_VALUE_LENGTH,
+                actual: value.len(),
+                key: None::<String>,
             }
         );
 
-        let mut val_reader = Cursor::new(&value[..]);
-        let data_type: DataType = val_reader.get_u8().try_into()?;
-        let pos = val_reader.position() as usize;
-
-        let count_range = pos..pos + BASE_META_VALUE_COUNT_LENGTH;
-        let count = val_reader.get_u64_le();
-
-        let version = val_reader.get_u64_le();
-        let left_index = val_reader.get_u64_le();
-        let right_index = val_reader.get_u64_le();
-        let pos = val_reader.position() as usize;
-
-        let reserve_range = pos..pos + SUFFIX_RESERVE_LENGTH;
-        val_reader.advance(SUFFIX_RESERVE_LENGTH);
-        let ctime = val_reader.get_u64_le();
-        let etime = val_reader.get_u64_le();
+        let data_type = DataType::try_from(value[0])?;
+        let count_range = COUNT_OFFSET..VERSION_OFFSET;
+        let count = read_u64(&value, COUNT_OFFSET);
+        let version = read_u64(&value, VERSION_OFFSET);
+        let left_index = read_u64(&value, LEFT_INDEX_OFFSET);
+        let right_index = read_u64(&value, RIGHT_INDEX_OFFSET);
+        let reserve_range = RESERVE_OFFSET..CTIME_OFFSET;
+        let ctime = read_u64(&value, CTIME_OFFSET);
+        let etime = read_u64(&value, ETIME_OFFSET);
+        checksum::verify(&value, RESERVE_OFFSET)?;
 
         Ok(Self {
             inner: ParsedInternalValue::new(
@@ -185,46 +200,27 @@ impl ParsedListsMetaValue {
     }
 
     fn set_version_to_value(&mut self) {
-        let suffix_start = TYPE_LENGTH + BASE_META_VALUE_COUNT_LENGTH;
-        let version_bytes = self.inner.version.to_le_bytes();
-        let dst = &mut self.inner.value[suffix_start..suffix_start + VERSION_LENGTH];
-        dst.copy_from_slice(&version_bytes);
+        write_u64(&mut self.inner.value, VERSION_OFFSET, self.inner.version);
+        checksum::refresh(&mut self.inner.value, RESERVE_OFFSET);
     }
 
     fn set_ctime_to_value(&mut self) {
-        let suffix_start = self.inner.value.len() - 2 * TIMESTAMP_LENGTH;
-        let ctime_bytes = self.inner.ctime.to_le_bytes();
-        let dst = &mut self.inner.value[suffix_start..suffix_start + TIMESTAMP_LENGTH];
-        dst.copy_from_slice(&ctime_bytes)
+        write_u64(&mut self.inner.value, CTIME_OFFSET, self.inner.ctime);
     }
 
     fn set_etime_to_value(&mut self) {
-        let suffix_start = self.inner.value.len() - TIMESTAMP_LENGTH;
-        let etime_bytes = self.inner.etime.to_le_bytes();
-        let dst = &mut self.inner.value[suffix_start..suffix_start + TIMESTAMP_LENGTH];
-        dst.copy_from_slice(&etime_bytes)
+        write_u64(&mut self.inner.value, ETIME_OFFSET, self.inner.etime);
     }
 
     fn set_count_to_value(&mut self) {
-        let suffix_start = TYPE_LENGTH;
-        let count_bytes = self.count.to_le_bytes();
-        let dst = &mut self.inner.value[suffix_start..suffix_start + BASE_META_VALUE_COUNT_LENGTH];
-        dst.copy_from_slice(&count_bytes);
+        write_u64(&mut self.inner.value, COUNT_OFFSET, self.count);
+        checksum::refresh(&mut self.inner.value, RESERVE_OFFSET);
     }
 
     fn set_index_to_value(&mut self) {
-        let suffix_start = TYPE_LENGTH + BASE_META_VALUE_COUNT_LENGTH + VERSION_LENGTH;
-
-        // Set left_index
-        let left_index_bytes = self.left_index.to_le_bytes();
-        let dst = &mut self.inner.value[suffix_start..suffix_start + LIST_VALUE_INDEX_LENGTH];
-        dst.copy_from_slice(&left_index_bytes);
-
-        // Set right_index
-        let right_index_bytes = self.right_index.to_le_bytes();
-        let dst = &mut self.inner.value
-            [suffix_start + LIST_VALUE_INDEX_LENGTH..suffix_start + 2 * LIST_VALUE_INDEX_LENGTH];
-        dst.copy_from_slice(&right_index_bytes);
+        write_u64(&mut self.inner.value, LEFT_INDEX_OFFSET, self.left_index);
+        write_u64(&mut self.inner.value, RIGHT_INDEX_OFFSET, self.right_index);
+        checksum::refresh(&mut self.inner.value, RESERVE_OFFSET);
     }
 
     pub fn is_valid(&self) -> bool {
@@ -240,9 +236,17 @@ impl ParsedListsMetaValue {
         self.set_count_to_value();
     }
 
-    pub fn modify_count(&mut self, delta: u64) {
-        self.count += delta;
+    /// Adds `delta` to the count, negative to remove elements. Fails and
+    /// leaves the count unchanged when it would leave the range of u64
+    pub fn modify_count(&mut self, delta: i64) -> Result<()> {
+        self.count = self
+            .count
+            .checked_add_signed(delta)
+            .with_context(|| InvalidFormatSnafu {
+                message: format!("list count {} {delta:+} is out of range", self.count),
+            })?;
         self.set_count_to_value();
+        Ok(())
     }
 
     pub fn set_etime(&mut self, etime: u64) {
@@ -256,11 +260,7 @@ impl ParsedListsMetaValue {
     }
 
     pub fn update_version(&mut self) -> u64 {
-        let now = Utc::now().timestamp_micros() as u64;
-        self.inner.version = match self.inner.version >= now {
-            true => self.inner.version + 1,
-            false => now,
-        };
+        self.inner.version = version_seq::next_version(self.inner.version);
 
         self.set_version_to_value();
         self.inner.version
@@ -276,7 +276,7 @@ impl ParsedListsMetaValue {
     }
 
     pub fn modify_left_index(&mut self, index: u64) {
-        self.left_index -= index;
+        self.left_index = self.left_index.wrapping_sub(index);
         self.set_index_to_value();
     }
 
@@ -290,17 +290,54 @@ impl ParsedListsMetaValue {
     }
 
     pub fn modify_right_index(&mut self, index: u64) {
-        self.right_index += index;
+        self.right_index = self.right_index.wrapping_add(index);
         self.set_index_to_value();
     }
 
+    /// Whether pushing `left` elements at the head and `right` at the tail
+    /// would bring an index within `LIST_INDEX_MARGIN` of the ends of the
+    /// index space, so the list has to be re-centered first
+    pub fn needs_recenter(&self, left: u64, right: u64) -> bool {
+        self.left_index < left.saturating_add(LIST_INDEX_MARGIN)
+            || u64::MAX - self.right_index < right.saturating_add(LIST_INDEX_MARGIN)
+    }
+
+    /// The left and right index of the list once its elements are moved
+    /// around the initial midpoint, keeping their distance to each other
+    pub fn centered_indexes(&self) -> (u64, u64) {
+        let span = self
+            .right_index
+            .saturating_sub(self.left_index)
+            .saturating_sub(1);
+        let left_index = INITIAL_LEFT_INDEX - span / 2;
+        (left_index, left_index + span + 1)
+    }
+
+    /// Removes a list whose etime has passed, or one that is empty and was
+    /// created more than `EMPTY_META_GRACE_MICROS` ago
+    pub fn filter_decision(&self, cur_time: u64) -> CompactionDecision {
+        let expired = self.inner.etime != 0 && self.inner.etime < cur_time;
+        let empty =
+            self.count == 0 && self.inner.ctime.saturating_add(EMPTY_META_GRACE_MICROS) < cur_time;
+        if expired || empty {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+
+    /// The encoded value, to write back after changing the count or indexes
+    pub fn encoded(&self) -> &[u8] {
+        &self.inner.value
+    }
+
     pub fn strip_suffix(&mut self) {
         if !self.inner.value.is_empty() {
             let len = self.inner.value.len();
-            if len >= Self::LISTS_META_VALUE_SUFFIX_LENGTH {
+            if len >= LISTS_META_VALUE_SUFFIX_LENGTH {
                 self.inner
                     .value
-                    .truncate(len - Self::LISTS_META_VALUE_SUFFIX_LENGTH);
+                    .truncate(len - LISTS_META_VALUE_SUFFIX_LENGTH);
             }
         }
     }
@@ -367,16 +404,45 @@ mod tests {
         assert_eq!(encoded, expected);
     }
 
+    #[test]
+    fn test_needs_recenter() {
+        let encoded = ListsMetaValue::new(3u64.to_le_bytes().to_vec()).encode();
+        let mut parsed = ParsedListsMetaValue::new(encoded).unwrap();
+        assert!(!parsed.needs_recenter(1000, 1000));
+        assert_eq!(
+            parsed.centered_indexes(),
+            (INITIAL_LEFT_INDEX, INITIAL_RIGHT_INDEX)
+        );
+
+        // Three elements pushed down to the left end of the index space
+        parsed.set_left_index(LIST_INDEX_MARGIN + 10);
+        parsed.set_right_index(LIST_INDEX_MARGIN + 14);
+        assert!(!parsed.needs_recenter(10, 0));
+        assert!(parsed.needs_recenter(11, 0));
+        assert_eq!(
+            parsed.centered_indexes(),
+            (INITIAL_LEFT_INDEX - 1, INITIAL_LEFT_INDEX + 3)
+        );
+
+        parsed.set_left_index(u64::MAX - LIST_INDEX_MARGIN - 10);
+        parsed.set_right_index(u64::MAX - LIST_INDEX_MARGIN);
+        assert!(!parsed.needs_recenter(0, 0));
+        assert!(parsed.needs_recenter(0, 1));
+        assert!(parsed.needs_recenter(0, u64::MAX));
+        let (left, right) = parsed.centered_indexes();
+        assert_eq!(right - left, 10);
+        assert!(left < INITIAL_LEFT_INDEX && right > INITIAL_RIGHT_INDEX);
+    }
+
     #[test]
     fn test_lists_meta_value_update_version() {
         let mut meta = create_test_lists_meta_value();
         meta.inner.version = 0;
 
-        let now = Utc::now().timestamp_micros() as u64;
         let new_version = meta.update_version();
-
-        assert!(new_version >= now);
+        assert!(new_version > 0);
         assert_eq!(meta.inner.version, new_version);
+        assert!(meta.update_version() > new_version);
     }
 
     #[test]
@@ -437,8 +503,13 @@ mod tests {
         let mut parsed = ParsedListsMetaValue::new(buf).unwrap();
 
         let delta = 5;
-        parsed.modify_count(delta);
-        assert_eq!(parsed.count, TEST_COUNT + delta);
+        parsed.modify_count(delta).unwrap();
+        assert_eq!(parsed.count, TEST_COUNT + delta as u64);
+
+        parsed.modify_count(-delta).unwrap();
+        assert_eq!(parsed.count, TEST_COUNT);
+        assert!(parsed.modify_count(-(TEST_COUNT as i64) - 1).is_err());
+        assert_eq!(parsed.count, TEST_COUNT);
     }
 
     #[test]
@@ -468,6 +539,49 @@ mod tests {
         assert_eq!(parsed.right_index, TEST_RIGHT_INDEX + 200);
     }
 
+    #[test]
+    fn test_parsed_lists_meta_value_write_back() {
+        let buf = build_test_buffer();
+        let mut parsed = ParsedListsMetaValue::new(buf).unwrap();
+
+        parsed.set_count(7);
+        parsed.modify_count(3).unwrap();
+        parsed.set_left_index(500);
+        parsed.modify_left_index(100);
+        parsed.set_right_index(1500);
+        parsed.modify_right_index(200);
+        parsed.set_ctime(TEST_CTIME + 1);
+        parsed.set_etime(TEST_ETIME + 1);
+        let version = parsed.update_version();
+
+        let reparsed = ParsedListsMetaValue::new(parsed.inner.value.clone()).unwrap();
+        assert_eq!(reparsed.inner.data_type, DataType::List);
+        assert_eq!(reparsed.count(), 10);
+        assert_eq!(reparsed.inner.version, version);
+        assert_eq!(reparsed.left_index(), 400);
+        assert_eq!(reparsed.right_index(), 1700);
+        assert_eq!(reparsed.inner.ctime, TEST_CTIME + 1);
+        assert_eq!(reparsed.inner.etime, TEST_ETIME + 1);
+    }
+
+    #[test]
+    fn test_parsed_lists_meta_value_checksum() {
+        checksum::with_checksum(|| {
+            let encoded = create_test_lists_meta_value().encode();
+            let mut parsed = ParsedListsMetaValue::new(encoded).unwrap();
+
+            // In place updates keep the checksum valid
+            parsed.modify_count(3).unwrap();
+            parsed.modify_left_index(100);
+            parsed.update_version();
+            assert!(ParsedListsMetaValue::new(parsed.inner.value.clone()).is_ok());
+
+            let mut corrupted = parsed.inner.value.clone();
+            corrupted[TYPE_LENGTH] ^= 0xff;
+            assert!(ParsedListsMetaValue::new(corrupted).is_err());
+        });
+    }
+
     #[test]
     fn test_parsed_lists_meta_value_is_valid() {
         let buf = build_test_buffer();
@@ -483,7 +597,7 @@ mod tests {
 
         parsed.strip_suffix();
 
-        let expected_len = buf.len() - ParsedListsMetaValue::LISTS_META_VALUE_SUFFIX_LENGTH;
+        let expected_len = buf.len() - LISTS_META_VALUE_SUFFIX_LENGTH;
         assert_eq!(parsed.inner.value.len(), expected_len);
     }
 
@@ -500,5 +614,54 @@ mod tests {
         assert_eq!(parsed.right_index, TEST_RIGHT_INDEX);
         assert_eq!(parsed.inner.ctime, TEST_CTIME);
         assert_eq!(parsed.inner.etime, TEST_ETIME);
+        assert_eq!(parsed.user_value_slice(), TEST_COUNT.to_le_bytes());
+        assert_eq!(parsed.reserve(), meta.inner.reserve);
+    }
+
+    #[test]
+    fn test_lists_meta_value_layout() {
+        let encoded = create_test_lists_meta_value().encode();
+        assert_eq!(encoded.len(), LISTS_META_VALUE_LENGTH);
+        assert_eq!(encoded.len(), build_test_buffer().len());
+
+        assert_eq!(encoded[0], DataType::List as u8);
+        assert_eq!(read_u64(&encoded, COUNT_OFFSET), TEST_COUNT);
+        assert_eq!(read_u64(&encoded, VERSION_OFFSET), TEST_VERSION);
+        assert_eq!(read_u64(&encoded, LEFT_INDEX_OFFSET), TEST_LEFT_INDEX);
+        assert_eq!(read_u64(&encoded, RIGHT_INDEX_OFFSET), TEST_RIGHT_INDEX);
+        assert_eq!(read_u64(&encoded, CTIME_OFFSET), TEST_CTIME);
+        assert_eq!(read_u64(&encoded, ETIME_OFFSET), TEST_ETIME);
+    }
+
+    #[test]
+    fn test_parsed_lists_meta_value_rejects_wrong_length() {
+        // A 4-byte count shifts every later field
+        let mut short = BytesMut::new();
+        short.put_u8(DataType::List as u8);
+        short.put_u32_le(TEST_COUNT as u32);
+        short.extend_from_slice(&build_test_buffer()[VERSION_OFFSET..]);
+        assert!(ParsedListsMetaValue::new(short).is_err());
+
+        let mut long = build_test_buffer();
+        long.put_u8(0);
+        assert!(ParsedListsMetaValue::new(long).is_err());
+    }
+
+    #[test]
+    fn test_parsed_lists_meta_value_roundtrip_after_setters() {
+        let mut parsed =
+            ParsedListsMetaValue::new(create_test_lists_meta_value().encode()).unwrap();
+        parsed.set_count(3);
+        parsed.set_left_index(INITIAL_LEFT_INDEX);
+        parsed.set_right_index(INITIAL_RIGHT_INDEX);
+        parsed.set_ctime(1);
+        parsed.set_etime(2);
+        let version = parsed.update_version();
+
+        let mut meta = ListsMetaValue::new(3u64.to_le_bytes().to_vec());
+        meta.inner.version = version;
+        meta.inner.ctime = 1;
+        meta.inner.etime = 2;
+        assert_eq!(parsed.inner.value, meta.encode());
     }
 }
@@ -0,0 +1,183 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Re-centering of list indexes
+//!
+//! The elements of a list are the data keys between the left and right
+//! index of its meta value, which start around the midpoint of the u64
+//! space. Pushes on one side only move one index toward an end of the
+//! space, and once it comes close `stage_list_push_room` moves every
+//! element back around the midpoint.
+
+use rocksdb::{Direction, IteratorMode, ReadOptions, WriteBatch};
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    error::{OptionNoneSnafu, RocksSnafu},
+    list_meta_value_format::ParsedListsMetaValue,
+    lists_data_key_format::{ListsDataKey, ParsedListsDataKey},
+    ColumnFamilyIndex, Redis, Result,
+};
+
+impl Redis {
+    /// Makes room for `left` pushes at the head and `right` pushes at the
+    /// tail of the list `key`, re-centering it first when
+    /// `ParsedListsMetaValue::needs_recenter`. The moved data keys go to
+    /// `batch` and the new indexes to `meta`, whose write is left to the
+    /// caller. Returns whether the list was re-centered.
+    pub(crate) fn stage_list_push_room(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        meta: &mut ParsedListsMetaValue,
+        left: u64,
+        right: u64,
+    ) -> Result<bool> {
+        if !meta.needs_recenter(left, right) {
+            return Ok(false);
+        }
+
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::ListsDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let reserve1 = self.key_reserve1(key);
+        let data_key =
+            |index| ListsDataKey::with_reserves(key, meta.version(), index, reserve1, [0; 16]);
+        let start = data_key(meta.left_index() + 1).encode_seek_key()?;
+        let end = data_key(meta.right_index()).encode_seek_key()?;
+
+        let mut read_options = ReadOptions::default();
+        read_options.set_iterate_upper_bound(end);
+        let iter = db.iterator_cf_opt(
+            &cf,
+            read_options,
+            IteratorMode::From(&start, Direction::Forward),
+        );
+
+        let (left_index, right_index) = meta.centered_indexes();
+        let mut moved = Vec::new();
+        for item in iter {
+            let (old_key, value) = item.context(RocksSnafu)?;
+            let parsed = ParsedListsDataKey::from_slice(&old_key)?;
+            let offset = parsed.index() - meta.left_index();
+            let new_key = ListsDataKey::with_reserves(
+                key,
+                meta.version(),
+                left_index + offset,
+                *parsed.reserve1(),
+                *parsed.reserve2(),
+            )
+            .encode()?;
+            moved.push((old_key, new_key, value));
+        }
+
+        // The old and new index ranges may overlap, every delete goes first
+        for (old_key, _, _) in &moved {
+            batch.delete_cf(&cf, old_key);
+        }
+        for (_, new_key, value) in &moved {
+            batch.put_cf(&cf, new_key, value);
+        }
+        meta.set_left_index(left_index);
+        meta.set_right_index(right_index);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base_data_value_format::BaseDataValue,
+        list_meta_value_format::{ListsMetaValue, INITIAL_LEFT_INDEX},
+        unique_test_db_path, BgTaskHandler, StorageOptions, ValueFormat,
+    };
+    use kstd::lock_mgr::LockMgr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_stage_list_push_room() {
+        let test_db_path = unique_test_db_path();
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+        {
+            let db = redis.db.as_ref().unwrap();
+            let cf = redis.get_cf_handle(ColumnFamilyIndex::ListsDataCF).unwrap();
+            let encoded = ValueFormat::encode(&ListsMetaValue::new(3u64.to_le_bytes().to_vec()));
+            let mut meta = ParsedListsMetaValue::new(encoded).unwrap();
+            let version = meta.update_version();
+
+            // Room is left for pushes on a new list
+            let mut batch = WriteBatch::default();
+            assert!(!redis
+                .stage_list_push_room(&mut batch, b"l", &mut meta, 100, 100)
+                .unwrap());
+            assert!(batch.is_empty());
+
+            // Three elements pushed down to index 6, with a data key of an
+            // older version at index 7
+            meta.set_left_index(5);
+            meta.set_right_index(9);
+            for (index, value) in [(6, "a"), (7, "b"), (8, "c")] {
+                let key = ListsDataKey::new(b"l", version, index).encode().unwrap();
+                db.put_cf(&cf, key, BaseDataValue::new(value).encode())
+                    .unwrap();
+            }
+            let stale = ListsDataKey::new(b"l", version - 1, 7).encode().unwrap();
+            db.put_cf(&cf, &stale, b"stale").unwrap();
+
+            let mut batch = WriteBatch::default();
+            assert!(redis
+                .stage_list_push_room(&mut batch, b"l", &mut meta, 1, 0)
+                .unwrap());
+            db.write(batch).unwrap();
+
+            assert_eq!(meta.left_index(), INITIAL_LEFT_INDEX - 1);
+            assert_eq!(meta.right_index(), INITIAL_LEFT_INDEX + 3);
+            for index in 6..9 {
+                let key = ListsDataKey::new(b"l", version, index).encode().unwrap();
+                assert!(db.get_cf(&cf, key).unwrap().is_none());
+            }
+            for (index, value) in [(0, "a"), (1, "b"), (2, "c")] {
+                let key = ListsDataKey::new(b"l", version, INITIAL_LEFT_INDEX + index)
+                    .encode()
+                    .unwrap();
+                let expected = BaseDataValue::new(value).encode();
+                assert_eq!(db.get_cf(&cf, key).unwrap().unwrap(), expected.to_vec());
+            }
+            assert_eq!(db.get_cf(&cf, &stale).unwrap().unwrap(), b"stale");
+        }
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
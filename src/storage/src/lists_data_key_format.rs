@@ -26,6 +26,7 @@ use crate::storage_define::{
 };
 use bytes::BytesMut;
 use std::mem;
+use std::ops::Range;
 
 // Constants for fixed-length fields
 const RESERVE1_LEN: usize = 8;
@@ -36,6 +37,9 @@ const U64_LEN: usize = 8;
  * Format for List data key
  * | reserve1 | key | version | index | reserve2 |
  * |    8B    |     |    8B   |   8B  |   16B    |
+ *
+ * The index is stored big-endian, so the data keys of one list version sort
+ * by index and LRANGE is a forward scan from left_index + 1 to right_index.
  */
 pub struct ListsDataKey {
     reserve1: [u8; 8],
@@ -100,8 +104,8 @@ impl ListsDataKey {
         encode_fixed(&mut dst[offset..offset + U64_LEN], self.version);
         offset += U64_LEN;
 
-        // 4. index (8 bytes)
-        encode_fixed(&mut dst[offset..offset + U64_LEN], self.index);
+        // 4. index (8 bytes, big-endian to keep the index order)
+        dst[offset..offset + U64_LEN].copy_from_slice(&self.index.to_be_bytes());
         offset += U64_LEN;
 
         // 5. reserve2 (16 bytes)
@@ -110,6 +114,35 @@ impl ListsDataKey {
         Ok(dst)
     }
 
+    /// Encodes the key without the trailing reserve2. Used as the start (or
+    /// exclusive end) of an index range scan: it sorts before the data key of
+    /// `index` and after the data keys of all the smaller indexes.
+    pub fn encode_seek_key(&self) -> Result<Vec<u8>> {
+        let mut dst = self.encode()?;
+        dst.truncate(dst.len() - RESERVE2_LEN);
+        Ok(dst)
+    }
+
+    /// Encodes the bounds of a forward scan over the data keys of `indexes`
+    /// of one list version: the first key to read and the exclusive end.
+    pub fn encode_range(
+        key: &[u8],
+        version: u64,
+        indexes: Range<u64>,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let start = Self::new(key, version, indexes.start).encode_seek_key()?;
+        let end = Self::new(key, version, indexes.end).encode_seek_key()?;
+        Ok((start, end))
+    }
+
+    /// Encodes `| reserve1 | key | version |`, the prefix shared by all the
+    /// data keys of one list version.
+    pub fn encode_prefix(&self) -> Result<Vec<u8>> {
+        let mut dst = self.encode()?;
+        dst.truncate(dst.len() - U64_LEN - RESERVE2_LEN);
+        Ok(dst)
+    }
+
     pub fn reserve1(&self) -> &[u8; 8] {
         &self.reserve1
     }
@@ -119,6 +152,35 @@ impl ListsDataKey {
     }
 }
 
+/// The data key indexes of the elements start..=stop of a list, given the
+/// left and right index of its meta value. As in LRANGE, a negative position
+/// counts from the tail and the range is clamped to the list. None if the
+/// range holds no element
+pub fn list_index_range(
+    left_index: u64,
+    right_index: u64,
+    start: i64,
+    stop: i64,
+) -> Option<Range<u64>> {
+    // i128 holds every length and position without overflow
+    let len = right_index.saturating_sub(left_index).saturating_sub(1) as i128;
+    let normalize = |pos: i64| {
+        if pos < 0 {
+            pos as i128 + len
+        } else {
+            pos as i128
+        }
+    };
+    let start = normalize(start).max(0);
+    let stop = normalize(stop).min(len - 1);
+    if start > stop {
+        return None;
+    }
+
+    let first = left_index + 1;
+    Some(first + start as u64..first + stop as u64 + 1)
+}
+
 pub struct ParsedListsDataKey {
     key_str: Vec<u8>,
     reserve1: [u8; 8],
@@ -179,7 +241,9 @@ impl ParsedListsDataKey {
         }
 
         let version = decode_fixed(&key[version_offset..version_offset + U64_LEN]);
-        let index = decode_fixed(&key[index_offset..index_offset + U64_LEN]);
+        let mut index_bytes = [0u8; U64_LEN];
+        index_bytes.copy_from_slice(&key[index_offset..index_offset + U64_LEN]);
+        let index = u64::from_be_bytes(index_bytes);
 
         // sanity check: we should end exactly before RESERVE2
         if index_offset + U64_LEN != encoded_key_end {
@@ -241,6 +305,7 @@ impl ParsedListsDataKey {
 mod tests {
     use super::*;
     use crate::error::Error;
+    use crate::list_meta_value_format::{INITIAL_LEFT_INDEX, INITIAL_RIGHT_INDEX};
 
     #[test]
     fn test_encode_decode() -> Result<()> {
@@ -341,4 +406,105 @@ mod tests {
         assert_eq!(parsed.reserve2(), &reserve2);
         Ok(())
     }
+
+    #[test]
+    fn test_index_order() -> Result<()> {
+        let indexes = [
+            0,
+            1,
+            255,
+            256,
+            1 << 32,
+            u64::MAX / 2,
+            u64::MAX / 2 + 1,
+            u64::MAX,
+        ];
+        let encoded = indexes
+            .iter()
+            .map(|&index| ListsDataKey::new(b"list", 3, index).encode())
+            .collect::<Result<Vec<_>>>()?;
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+
+        for (index, key) in indexes.iter().zip(&encoded) {
+            assert_eq!(ParsedListsDataKey::from_slice(key)?.index(), *index);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_scan_bounds() -> Result<()> {
+        let list = |index| ListsDataKey::new(b"list", 3, index);
+        let prefix = list(0).encode_prefix()?;
+        let start = list(10).encode_seek_key()?;
+        let end = list(20).encode_seek_key()?;
+
+        for index in [9, 10, 19, 20] {
+            let key = list(index).encode()?;
+            assert!(key.starts_with(&prefix));
+            assert_eq!(key >= start && key < end, (10..20).contains(&index));
+        }
+
+        let other_version = ListsDataKey::new(b"list", 4, 15).encode()?;
+        assert!(!other_version.starts_with(&prefix));
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_index_range() {
+        // an empty list
+        assert_eq!(
+            list_index_range(INITIAL_LEFT_INDEX, INITIAL_RIGHT_INDEX, 0, -1),
+            None
+        );
+
+        // three LPUSH and two RPUSH: the elements straddle the midpoint
+        let left = INITIAL_LEFT_INDEX - 3;
+        let right = INITIAL_RIGHT_INDEX + 2;
+        let first = INITIAL_LEFT_INDEX - 2;
+        assert_eq!(list_index_range(left, right, 0, -1), Some(first..right));
+        assert_eq!(list_index_range(left, right, 0, 0), Some(first..first + 1));
+        assert_eq!(
+            list_index_range(left, right, 2, 3),
+            Some(INITIAL_LEFT_INDEX..INITIAL_RIGHT_INDEX + 1)
+        );
+        assert_eq!(
+            list_index_range(left, right, -1, -1),
+            Some(right - 1..right)
+        );
+        assert_eq!(list_index_range(left, right, -100, 100), Some(first..right));
+        assert_eq!(list_index_range(left, right, 5, 10), None);
+        assert_eq!(list_index_range(left, right, 3, 2), None);
+        assert_eq!(list_index_range(left, right, -100, -6), None);
+        assert_eq!(
+            list_index_range(left, right, i64::MIN, i64::MAX),
+            Some(first..right)
+        );
+
+        // a list grown to the ends of the index space
+        assert_eq!(
+            list_index_range(0, u64::MAX, -1, -1),
+            Some(u64::MAX - 1..u64::MAX)
+        );
+        assert_eq!(list_index_range(0, u64::MAX, 0, 0), Some(1..2));
+    }
+
+    #[test]
+    fn test_encode_range_across_midpoint() -> Result<()> {
+        let left = INITIAL_LEFT_INDEX - 3;
+        let right = INITIAL_RIGHT_INDEX + 2;
+        let indexes = list_index_range(left, right, 0, -1).unwrap();
+        let (start, end) = ListsDataKey::encode_range(b"list", 3, indexes.clone())?;
+
+        // every element is in the range, in list order, and the keys just
+        // outside the list are not
+        let keys = (left..=right)
+            .map(|index| ListsDataKey::new(b"list", 3, index).encode())
+            .collect::<Result<Vec<_>>>()?;
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        for (index, key) in (left..=right).zip(&keys) {
+            let in_range = *key >= start && *key < end;
+            assert_eq!(in_range, indexes.contains(&index));
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,287 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Compaction filter of the list data column family
+//!
+//! The nodes of a list live between the left and right index of its meta
+//! value, both excluded. Nodes of an older version are left behind by a
+//! DEL or an overwrite, nodes outside the indexes by LTRIM or a crash
+//! between writes; this filter drops both.
+
+use bytes::BytesMut;
+use log::debug;
+use rocksdb::{
+    compaction_filter::CompactionFilter, compaction_filter_factory::CompactionFilterFactory,
+    CompactionDecision, ReadOptions, DB,
+};
+use snafu::ResultExt;
+use std::sync::{Arc, OnceLock, Weak};
+
+use crate::{
+    base_key_format::BaseKey,
+    base_value_format::DataType,
+    clock,
+    error::{Result, RocksSnafu},
+    filter_config::{FilterConfig, SharedFilterConfig},
+    filter_stats::{record, FilterKind, FilterOutcome},
+    list_meta_value_format::ParsedListsMetaValue,
+    lists_data_key_format::ParsedListsDataKey,
+    stale_ranges::record_filtered,
+    trash::decode_trash_value,
+    ColumnFamilyIndex,
+};
+
+/// Removes the nodes of lists whose meta value is gone, of an older
+/// version than it, or outside its indexes. Nodes of one list are adjacent,
+/// so the meta value is read once per list.
+pub struct ListsDataFilter {
+    db: Weak<DB>,
+    default_read_opts: ReadOptions,
+    cur_key: BytesMut,
+    meta_not_found: bool,
+    cur_meta_version: u64,
+    cur_meta_etime: u64,
+    cur_left_index: u64,
+    cur_right_index: u64,
+    config: FilterConfig,
+}
+
+/// Creates a `ListsDataFilter`. The db is filled in once it is open,
+/// compactions before that keep everything.
+pub struct ListsDataFilterFactory {
+    db: Arc<OnceLock<Weak<DB>>>,
+    config: SharedFilterConfig,
+    bottommost_only: bool,
+}
+
+impl ListsDataFilter {
+    pub fn new(db: Weak<DB>) -> Self {
+        Self {
+            db,
+            default_read_opts: ReadOptions::default(),
+            cur_key: BytesMut::new(),
+            meta_not_found: false,
+            cur_meta_version: 0,
+            cur_meta_etime: 0,
+            cur_left_index: 0,
+            cur_right_index: 0,
+            config: FilterConfig::default(),
+        }
+    }
+
+    /// Filters with `config` instead of the default
+    pub fn with_config(mut self, config: FilterConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Reads the meta value owning the nodes under `meta_key`, a live list
+    /// or one in the trash, which UNDELETE can still bring back.
+    fn load_meta(&mut self, db: &DB, meta_key: BytesMut, now: u64) -> Result<()> {
+        self.meta_not_found = true;
+
+        let live = db
+            .get_opt(&meta_key, &self.default_read_opts)
+            .context(RocksSnafu)?;
+        if let Some(meta) = live.as_deref().and_then(|v| owning_meta(v, now)) {
+            self.set_meta(&meta);
+        } else if let Some(trash_cf) = db.cf_handle(ColumnFamilyIndex::TrashCF.name()) {
+            let trashed = db
+                .get_cf_opt(&trash_cf, &meta_key, &self.default_read_opts)
+                .context(RocksSnafu)?;
+            if let Some(meta) = trashed
+                .as_deref()
+                .and_then(decode_trash_value)
+                .and_then(|(_, v)| owning_meta(v, now))
+            {
+                self.set_meta(&meta);
+            }
+        }
+        self.cur_key = meta_key;
+        Ok(())
+    }
+
+    fn set_meta(&mut self, meta: &ParsedListsMetaValue) {
+        self.meta_not_found = false;
+        self.cur_meta_version = meta.version();
+        self.cur_meta_etime = meta.etime();
+        self.cur_left_index = meta.left_index();
+        self.cur_right_index = meta.right_index();
+    }
+
+    /// Decides on the node at `index` of `version` against the cached meta
+    /// value. A node of a newer version than the meta value is kept, it
+    /// can only come from a write this filter has not seen.
+    fn decide(&self, version: u64, index: u64, now: u64) -> FilterOutcome {
+        if self.meta_not_found || version < self.cur_meta_version {
+            return FilterOutcome::RemovedStaleVersion;
+        }
+        if self.cur_meta_etime != 0 && self.cur_meta_etime < now {
+            return FilterOutcome::RemovedExpired;
+        }
+        if version == self.cur_meta_version
+            && (index <= self.cur_left_index || index >= self.cur_right_index)
+        {
+            return FilterOutcome::RemovedStaleVersion;
+        }
+        FilterOutcome::Kept
+    }
+}
+
+/// The list meta value in `value` if it would survive the meta filter
+fn owning_meta(value: &[u8], now: u64) -> Option<ParsedListsMetaValue> {
+    if value.first() != Some(&(DataType::List as u8)) {
+        return None;
+    }
+    let meta = ParsedListsMetaValue::new(value).ok()?;
+    matches!(meta.filter_decision(now), CompactionDecision::Keep).then_some(meta)
+}
+
+impl CompactionFilter for ListsDataFilter {
+    fn name(&self) -> &std::ffi::CStr {
+        c"ListsDataFilter"
+    }
+
+    fn filter(&mut self, level: u32, key: &[u8], _value: &[u8]) -> CompactionDecision {
+        if !self.config.removes_at(level) {
+            return record(FilterKind::ListsData, FilterOutcome::Kept);
+        }
+        let current_time = self.config.expire_before(clock::now_micros());
+
+        let parsed = match ParsedListsDataKey::from_slice(key) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                debug!("ListsDataFilter: Failed to parse key {key:?}: {e}, remove.");
+                let outcome = self.config.resolve(FilterOutcome::RemovedCorrupt);
+                return record(FilterKind::ListsData, outcome);
+            }
+        };
+        let meta_key = match BaseKey::with_reserve1(parsed.key(), *parsed.reserve1()).encode() {
+            Ok(meta_key) => meta_key,
+            Err(e) => {
+                debug!("ListsDataFilter: Failed to encode meta key of {key:?}: {e}, keep.");
+                return record(FilterKind::ListsData, FilterOutcome::Kept);
+            }
+        };
+
+        if meta_key != self.cur_key {
+            // The db is closing, leave the node to a later compaction
+            let Some(db) = self.db.upgrade() else {
+                return record(FilterKind::ListsData, FilterOutcome::Kept);
+            };
+            if let Err(e) = self.load_meta(&db, meta_key, current_time) {
+                debug!("ListsDataFilter: Failed to read meta value for key {key:?}: {e}, keep.");
+                self.cur_key.clear();
+                return record(FilterKind::ListsData, FilterOutcome::Kept);
+            }
+        }
+
+        let outcome =
+            self.config
+                .resolve(self.decide(parsed.version(), parsed.index(), current_time));
+        record_filtered(ColumnFamilyIndex::ListsDataCF, outcome);
+        record(FilterKind::ListsData, outcome)
+    }
+}
+
+impl ListsDataFilterFactory {
+    pub fn new(db: Arc<OnceLock<Weak<DB>>>, config: SharedFilterConfig) -> Self {
+        Self {
+            db,
+            config,
+            bottommost_only: false,
+        }
+    }
+
+    /// Makes the filters remove only in the compactions into the
+    /// bottommost level
+    pub fn with_bottommost_only(mut self, bottommost_only: bool) -> Self {
+        self.bottommost_only = bottommost_only;
+        self
+    }
+}
+
+impl CompactionFilterFactory for ListsDataFilterFactory {
+    type Filter = ListsDataFilter;
+
+    fn create(
+        &mut self,
+        context: rocksdb::compaction_filter_factory::CompactionFilterContext,
+    ) -> Self::Filter {
+        ListsDataFilter::new(self.db.get().cloned().unwrap_or_default()).with_config(
+            self.config
+                .get()
+                .for_compaction(self.bottommost_only, &context),
+        )
+    }
+
+    fn name(&self) -> &std::ffi::CStr {
+        c"ListsDataFilterFactory"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lists_data_key_format::ListsDataKey, unique_test_db_path, BgTaskHandler, ListEnd, Redis,
+        StorageOptions,
+    };
+    use kstd::lock_mgr::LockMgr;
+
+    #[test]
+    fn test_lists_data_filter() {
+        let test_db_path = unique_test_db_path();
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+        {
+            redis
+                .push(b"l", ListEnd::Right, &[b"a", b"b", b"c"])
+                .unwrap();
+            let db = redis.db.as_ref().unwrap();
+            let meta_key = redis.base_key(b"l").encode().unwrap();
+            let meta = ParsedListsMetaValue::new(db.get(&meta_key).unwrap().unwrap()).unwrap();
+            let (left, right, version) = (meta.left_index(), meta.right_index(), meta.version());
+
+            let mut filter = ListsDataFilter::new(Arc::downgrade(db));
+            for (key, version, index, keep) in [
+                (&b"l"[..], version, left + 1, true),
+                (b"l", version, right - 1, true),
+                (b"l", version, left, false),
+                (b"l", version, right, false),
+                (b"l", version - 1, left + 1, false),
+                (b"l", version + 1, right, true),
+                (b"missing", version, left + 1, false),
+            ] {
+                let data_key = ListsDataKey::new(key, version, index).encode().unwrap();
+                let decision = filter.filter(0, &data_key, b"");
+                assert_eq!(matches!(decision, CompactionDecision::Keep), keep);
+            }
+        }
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
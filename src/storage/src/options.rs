@@ -19,7 +19,121 @@
 
 //! Storage engine options and configurations
 
-use rocksdb::Options;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use kstd::numa::NumaTopology;
+use kstd::resources::ResourceLimits;
+use rocksdb::{Cache, Options, WriteOptions};
+
+use crate::cf_tuning::RocksDbTuning;
+use crate::clock::{system_clock, Clock};
+use crate::error::ValueTooLargeSnafu;
+use crate::filter_config::{FilterConfig, SharedFilterConfig};
+use crate::redis::ColumnFamilyIndex;
+use crate::stale_ranges::MaintenanceWindow;
+use snafu::ensure;
+
+/// How hard a write tries to reach the disk before it is acknowledged.
+///
+/// Run `cargo bench -p storage --bench durability` to measure the throughput
+/// of each level on the target hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DurabilityLevel {
+    /// Every write fsyncs the WAL before it returns. No acknowledged write is
+    /// lost, even on power failure, at the price of one fsync per write.
+    Strict,
+    /// Writes go to the WAL without fsync, and the WAL is fsynced in the
+    /// background every `wal_sync_interval_ms`. A process crash loses
+    /// nothing, a machine crash loses at most one interval of writes.
+    #[default]
+    Normal,
+    /// Writes skip the WAL entirely and are only durable once the memtable
+    /// is flushed. A process crash loses every unflushed write.
+    Relaxed,
+}
+
+impl DurabilityLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DurabilityLevel::Strict => "strict",
+            DurabilityLevel::Normal => "normal",
+            DurabilityLevel::Relaxed => "relaxed",
+        }
+    }
+}
+
+impl fmt::Display for DurabilityLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DurabilityLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(DurabilityLevel::Strict),
+            "normal" => Ok(DurabilityLevel::Normal),
+            "relaxed" => Ok(DurabilityLevel::Relaxed),
+            _ => Err(format!(
+                "invalid durability level '{s}', expected strict, normal or relaxed"
+            )),
+        }
+    }
+}
+
+/// Codec of the compressed string values, see `compression`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueCompression {
+    #[default]
+    None,
+    /// Fast, for values read often
+    Lz4,
+    /// Smaller than LZ4 at a higher CPU cost
+    Zstd,
+}
+
+impl ValueCompression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValueCompression::None => "none",
+            ValueCompression::Lz4 => "lz4",
+            ValueCompression::Zstd => "zstd",
+        }
+    }
+}
+
+impl fmt::Display for ValueCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ValueCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(ValueCompression::None),
+            "lz4" => Ok(ValueCompression::Lz4),
+            "zstd" => Ok(ValueCompression::Zstd),
+            _ => Err(format!(
+                "invalid value compression '{s}', expected none, lz4 or zstd"
+            )),
+        }
+    }
+}
+
+/// Percent of a CPU, per CPU of the process, the active expiration of all
+/// the databases may use with `StorageOptions::fit_to_limits`
+const BACKGROUND_CPU_PERCENT: u64 = 25;
+
+/// Part of the memory of the process the block cache may take with
+/// `StorageOptions::fit_to_limits`, one in four
+const BLOCK_CACHE_MEMORY_SHARE: u64 = 4;
 
 /// TODO: remove allow dead code
 #[allow(dead_code)]
@@ -47,6 +161,130 @@ pub struct StorageOptions {
     pub max_gap: i64,
     /// Memory manager size
     pub mem_manager_size: usize,
+    /// Write guarantees applied to every write
+    pub durability: DurabilityLevel,
+    /// Interval of the background WAL fsync with `DurabilityLevel::Normal` (in milliseconds)
+    pub wal_sync_interval_ms: u64,
+    /// Maximum execution time of a full keyspace scan, 0 for no limit (in milliseconds)
+    pub max_scan_time_ms: u64,
+    /// Number of hot keys saved at shutdown and warmed at startup, 0 to disable
+    pub hot_key_snapshot_size: usize,
+    /// Number of logical databases (SELECT index range)
+    pub databases: usize,
+    /// Retention of deleted keys in the trash, 0 to delete immediately (in seconds)
+    pub trash_retention_s: u64,
+    /// Whether keys are prefixed with their slot id. Changes the key layout,
+    /// so it can only be set on an empty database
+    pub slot_prefix: bool,
+    /// Whether keys sharing a `{tag}` are placed on the same instance, see
+    /// `SlotIndexer`. Changes where keys are, so it can only be set on an
+    /// empty database
+    pub hash_tag_placement: bool,
+    /// Number of keyspace notifications kept for replay, 0 to disable
+    pub keyspace_events_replay_len: usize,
+    /// Whether values are written with a CRC32 that is verified on read.
+    /// Applies to the whole process, every instance is opened with the same
+    pub value_checksum: bool,
+    /// Maximum number of elements of a hash, set or sorted set stored inline
+    /// in its meta value, 0 to always use data keys
+    pub inline_collection_max_entries: usize,
+    /// Maximum length of a field, member or value stored inline (in bytes)
+    pub inline_collection_max_entry_len: usize,
+    /// Source of versions, ctime, etime and expiration checks. Applies to
+    /// the whole process, every instance is opened with the same
+    pub clock: Arc<dyn Clock>,
+    /// Maximum length of a string value, 0 for no limit (in bytes)
+    pub max_value_size: usize,
+    /// Maximum length of a field, member or element of a collection and of
+    /// a hash field value, 0 for no limit (in bytes)
+    pub max_member_size: usize,
+    /// Maximum number of elements of a collection, 0 for no limit
+    pub max_collection_len: u64,
+    /// Codec of the string values of at least `compression_threshold` bytes.
+    /// Applies to the whole process, every instance is opened with the same
+    pub value_compression: ValueCompression,
+    /// Minimum length of a string value to compress (in bytes). Applies to
+    /// the whole process as `value_compression`
+    pub compression_threshold: usize,
+    /// Longest wait of a read for the binlog offset its client passed to be
+    /// applied, 0 to disable read-your-writes (in milliseconds)
+    pub read_your_writes_wait_ms: u64,
+    /// Behavior of the compaction filters, shared with their factories so
+    /// that a change applies without reopening
+    pub filter_config: SharedFilterConfig,
+    /// Interval between two samples of the flush, compaction and stall
+    /// state, 0 to disable the lifecycle events (in milliseconds)
+    pub lifecycle_poll_interval_ms: u64,
+    /// Deletions whose data keys wait for the lazy deletion worker, per
+    /// instance, 0 to leave them all to compaction
+    pub lazy_delete_queue_len: usize,
+    /// Interval between two runs of the lazy deletion worker (in milliseconds)
+    pub lazy_delete_interval_ms: u64,
+    /// Interval between two active expire cycles, 0 to leave expired keys
+    /// to compaction (in milliseconds)
+    pub active_expire_interval_ms: u64,
+    /// Share of the interval an active expire cycle may run (in percent)
+    pub active_expire_cpu_percent: u64,
+    /// Daily UTC window in which the key ranges reads found stale are
+    /// compacted, None to leave them to compaction
+    pub stale_compaction_window: Option<MaintenanceWindow>,
+    /// Keys whose stale reads are counted, per instance
+    pub stale_keys_tracked: usize,
+    /// Keys compacted per instance and check of the window
+    pub stale_compaction_ranges: usize,
+    /// Stale reads of a key before it is compacted
+    pub stale_compaction_min_reads: u64,
+    /// Column families whose data filters only remove in the compactions
+    /// into the bottommost level, so no tombstone is carried down from the
+    /// upper ones. Orphaned data stays longer on disk
+    pub filter_bottommost_only: Vec<ColumnFamilyIndex>,
+    /// NUMA nodes the instances are spread over, each node with its own
+    /// block cache, None to leave the placement to the OS
+    pub numa_placement: Option<NumaPlacement>,
+    /// RocksDB options of the instances and of each column family, over
+    /// `options`
+    pub rocksdb_tuning: RocksDbTuning,
+}
+
+/// The NUMA nodes of `StorageOptions::set_numa_topology` and their block
+/// caches. The instances are spread over the nodes round-robin, so those
+/// of a node only evict each other's blocks.
+pub struct NumaPlacement {
+    pub topology: NumaTopology,
+    /// Capacity of the cache of a node (in bytes)
+    pub node_cache_size: usize,
+    /// A cache per node, none with a `block_cache_size` of 0
+    block_caches: Vec<Cache>,
+}
+
+impl NumaPlacement {
+    /// Places on `topology`, splitting `block_cache_size` evenly between
+    /// the nodes
+    pub fn new(topology: NumaTopology, block_cache_size: usize) -> Self {
+        let node_cache_size = block_cache_size / topology.nodes.len().max(1);
+        let block_caches = match node_cache_size {
+            0 => Vec::new(),
+            size => (0..topology.nodes.len())
+                .map(|_| Cache::new_lru_cache(size))
+                .collect(),
+        };
+        Self {
+            topology,
+            node_cache_size,
+            block_caches,
+        }
+    }
+
+    /// The position in the nodes of the instance `index` of the database
+    /// `db_id`, out of `db_instance_num` per database
+    pub fn node_of(&self, db_id: usize, index: usize, db_instance_num: usize) -> Option<usize> {
+        self.topology.node_of(db_id * db_instance_num + index)
+    }
+
+    /// The block cache of the node at `node` in the nodes
+    pub fn block_cache(&self, node: usize) -> Option<&Cache> {
+        self.block_caches.get(node)
+    }
 }
 
 impl Default for StorageOptions {
@@ -72,6 +310,38 @@ impl Default for StorageOptions {
             raft_timeout_s: u32::MAX,
             max_gap: 1000,
             mem_manager_size: 100_000_000,
+            durability: DurabilityLevel::default(),
+            wal_sync_interval_ms: 1000,
+            max_scan_time_ms: 0,
+            hot_key_snapshot_size: 1000,
+            databases: 1,
+            trash_retention_s: 0,
+            slot_prefix: false,
+            hash_tag_placement: false,
+            keyspace_events_replay_len: 0,
+            value_checksum: false,
+            inline_collection_max_entries: 128,
+            inline_collection_max_entry_len: 64,
+            clock: system_clock(),
+            max_value_size: 512 << 20, // 512MB, the largest bulk string of Redis
+            max_member_size: 512 << 20,
+            max_collection_len: 0,
+            value_compression: ValueCompression::default(),
+            compression_threshold: 4096,
+            read_your_writes_wait_ms: 0,
+            filter_config: SharedFilterConfig::default(),
+            lifecycle_poll_interval_ms: 1000,
+            lazy_delete_queue_len: 65536,
+            lazy_delete_interval_ms: 100,
+            active_expire_interval_ms: 100,
+            active_expire_cpu_percent: 25,
+            stale_compaction_window: None,
+            stale_keys_tracked: 4096,
+            stale_compaction_ranges: 16,
+            stale_compaction_min_reads: 64,
+            filter_bottommost_only: Vec::new(),
+            numa_placement: None,
+            rocksdb_tuning: RocksDbTuning::default(),
         }
     }
 }
@@ -141,6 +411,268 @@ impl StorageOptions {
         self.mem_manager_size = size;
         self
     }
+
+    /// Set durability level
+    pub fn set_durability(&mut self, durability: DurabilityLevel) -> &mut Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Set background WAL fsync interval
+    pub fn set_wal_sync_interval_ms(&mut self, interval_ms: u64) -> &mut Self {
+        self.wal_sync_interval_ms = interval_ms;
+        self
+    }
+
+    /// Set maximum execution time of full keyspace scans
+    pub fn set_max_scan_time_ms(&mut self, max_scan_time_ms: u64) -> &mut Self {
+        self.max_scan_time_ms = max_scan_time_ms;
+        self
+    }
+
+    /// Set number of hot keys kept across restarts
+    pub fn set_hot_key_snapshot_size(&mut self, size: usize) -> &mut Self {
+        self.hot_key_snapshot_size = size;
+        self
+    }
+
+    /// Set number of logical databases
+    pub fn set_databases(&mut self, databases: usize) -> &mut Self {
+        self.databases = databases;
+        self
+    }
+
+    /// Set retention of deleted keys in the trash
+    pub fn set_trash_retention_s(&mut self, retention_s: u64) -> &mut Self {
+        self.trash_retention_s = retention_s;
+        self
+    }
+
+    /// Set whether keys are prefixed with their slot id
+    pub fn set_slot_prefix(&mut self, slot_prefix: bool) -> &mut Self {
+        self.slot_prefix = slot_prefix;
+        self
+    }
+
+    /// Set whether keys sharing a hash tag are placed on the same instance
+    pub fn set_hash_tag_placement(&mut self, hash_tag_placement: bool) -> &mut Self {
+        self.hash_tag_placement = hash_tag_placement;
+        self
+    }
+
+    /// Set the number of keyspace notifications kept for replay
+    pub fn set_keyspace_events_replay_len(&mut self, len: usize) -> &mut Self {
+        self.keyspace_events_replay_len = len;
+        self
+    }
+
+    /// Set whether values are written with a checksum verified on read
+    pub fn set_value_checksum(&mut self, value_checksum: bool) -> &mut Self {
+        self.value_checksum = value_checksum;
+        self
+    }
+
+    /// Set maximum number of elements of a collection stored inline
+    pub fn set_inline_collection_max_entries(&mut self, max_entries: usize) -> &mut Self {
+        self.inline_collection_max_entries = max_entries;
+        self
+    }
+
+    /// Set maximum length of an element stored inline
+    pub fn set_inline_collection_max_entry_len(&mut self, max_entry_len: usize) -> &mut Self {
+        self.inline_collection_max_entry_len = max_entry_len;
+        self
+    }
+
+    /// Set the clock the storage reads the time from
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Set maximum length of a string value
+    pub fn set_max_value_size(&mut self, max_value_size: usize) -> &mut Self {
+        self.max_value_size = max_value_size;
+        self
+    }
+
+    /// Set maximum length of an element of a collection
+    pub fn set_max_member_size(&mut self, max_member_size: usize) -> &mut Self {
+        self.max_member_size = max_member_size;
+        self
+    }
+
+    /// Set maximum number of elements of a collection
+    pub fn set_max_collection_len(&mut self, max_collection_len: u64) -> &mut Self {
+        self.max_collection_len = max_collection_len;
+        self
+    }
+
+    /// Set codec of large string values
+    pub fn set_value_compression(&mut self, value_compression: ValueCompression) -> &mut Self {
+        self.value_compression = value_compression;
+        self
+    }
+
+    /// Set minimum length of a string value to compress
+    pub fn set_compression_threshold(&mut self, compression_threshold: usize) -> &mut Self {
+        self.compression_threshold = compression_threshold;
+        self
+    }
+
+    /// Set longest wait of a read for its client's binlog offset
+    pub fn set_read_your_writes_wait_ms(&mut self, wait_ms: u64) -> &mut Self {
+        self.read_your_writes_wait_ms = wait_ms;
+        self
+    }
+
+    /// Set interval between two samples of the lifecycle events
+    pub fn set_lifecycle_poll_interval_ms(&mut self, interval_ms: u64) -> &mut Self {
+        self.lifecycle_poll_interval_ms = interval_ms;
+        self
+    }
+
+    /// Set deletions queued for the lazy deletion worker per instance
+    pub fn set_lazy_delete_queue_len(&mut self, len: usize) -> &mut Self {
+        self.lazy_delete_queue_len = len;
+        self
+    }
+
+    /// Set interval between two runs of the lazy deletion worker
+    pub fn set_lazy_delete_interval_ms(&mut self, interval_ms: u64) -> &mut Self {
+        self.lazy_delete_interval_ms = interval_ms;
+        self
+    }
+
+    /// Set interval between two active expire cycles
+    pub fn set_active_expire_interval_ms(&mut self, interval_ms: u64) -> &mut Self {
+        self.active_expire_interval_ms = interval_ms;
+        self
+    }
+
+    /// Set share of the interval an active expire cycle may run, capped at 100
+    pub fn set_active_expire_cpu_percent(&mut self, percent: u64) -> &mut Self {
+        self.active_expire_cpu_percent = percent.min(100);
+        self
+    }
+
+    /// Set daily window of the stale range compactions
+    pub fn set_stale_compaction_window(&mut self, window: Option<MaintenanceWindow>) -> &mut Self {
+        self.stale_compaction_window = window;
+        self
+    }
+
+    /// Set keys whose stale reads are counted per instance
+    pub fn set_stale_keys_tracked(&mut self, keys: usize) -> &mut Self {
+        self.stale_keys_tracked = keys;
+        self
+    }
+
+    /// Set keys compacted per instance and check of the window
+    pub fn set_stale_compaction_ranges(&mut self, ranges: usize) -> &mut Self {
+        self.stale_compaction_ranges = ranges;
+        self
+    }
+
+    /// Set stale reads of a key before it is compacted
+    pub fn set_stale_compaction_min_reads(&mut self, reads: u64) -> &mut Self {
+        self.stale_compaction_min_reads = reads;
+        self
+    }
+
+    /// Set whether the data filters of `cf` only remove in the compactions
+    /// into the bottommost level
+    pub fn set_filter_bottommost_only(
+        &mut self,
+        cf: ColumnFamilyIndex,
+        bottommost_only: bool,
+    ) -> &mut Self {
+        self.filter_bottommost_only.retain(|other| *other != cf);
+        if bottommost_only {
+            self.filter_bottommost_only.push(cf);
+        }
+        self
+    }
+
+    /// Set NUMA nodes the instances are spread over, None or no node to
+    /// leave the placement to the OS. Splits `block_cache_size` between the
+    /// nodes, so it is set first
+    pub fn set_numa_topology(&mut self, topology: Option<NumaTopology>) -> &mut Self {
+        self.numa_placement = topology
+            .filter(|topology| !topology.nodes.is_empty())
+            .map(|topology| NumaPlacement::new(topology, self.block_cache_size));
+        self
+    }
+
+    /// Set RocksDB options of the instances and of each column family
+    pub fn set_rocksdb_tuning(&mut self, tuning: RocksDbTuning) -> &mut Self {
+        self.rocksdb_tuning = tuning;
+        self
+    }
+
+    /// Set behavior of the compaction filters
+    pub fn set_filter_config(&mut self, config: FilterConfig) -> &mut Self {
+        self.filter_config.set(config);
+        self
+    }
+
+    /// Size the caches and the background work to `limits`, the CPUs and
+    /// the memory of the process: a flush or compaction job per CPU, at most
+    /// a quarter of the CPUs for the active expiration of every database
+    /// together, and at most a quarter of the memory for the block cache.
+    /// Only lowers the budgets already set.
+    pub fn fit_to_limits(&mut self, limits: &ResourceLimits) -> &mut Self {
+        let threads = limits.threads();
+        self.options.increase_parallelism(threads as i32);
+        self.options.set_max_background_jobs(threads.max(2) as i32);
+
+        let databases = self.databases.max(1) as f64;
+        let expire_percent = (limits.cpus * BACKGROUND_CPU_PERCENT as f64 / databases) as u64;
+        self.active_expire_cpu_percent = self.active_expire_cpu_percent.min(expire_percent.max(1));
+
+        if let Some(memory_bytes) = limits.memory_bytes {
+            let cache_bytes = memory_bytes / BLOCK_CACHE_MEMORY_SHARE;
+            self.block_cache_size = self.block_cache_size.min(cache_bytes as usize);
+        }
+        self
+    }
+
+    /// Fails with `Error::ValueTooLarge` when a string value of `len` bytes
+    /// is over `max_value_size`, checked before the value is encoded.
+    pub fn check_value_size(&self, len: usize) -> crate::error::Result<()> {
+        check_limit("string value", len as u64, self.max_value_size as u64)
+    }
+
+    /// Same as `check_value_size` for an element of a collection.
+    pub fn check_member_size(&self, len: usize) -> crate::error::Result<()> {
+        check_limit("collection member", len as u64, self.max_member_size as u64)
+    }
+
+    /// Same as `check_value_size` for the number of elements a collection
+    /// grows to.
+    pub fn check_collection_len(&self, len: u64) -> crate::error::Result<()> {
+        check_limit("collection length", len, self.max_collection_len)
+    }
+
+    /// Build the write options matching the durability level, shared by all
+    /// write paths.
+    pub fn write_options(&self) -> WriteOptions {
+        let mut write_options = WriteOptions::default();
+        match self.durability {
+            DurabilityLevel::Strict => write_options.set_sync(true),
+            DurabilityLevel::Normal => write_options.set_sync(false),
+            DurabilityLevel::Relaxed => {
+                write_options.set_sync(false);
+                write_options.disable_wal(true);
+            }
+        }
+        write_options
+    }
+
+    /// Whether the WAL must be fsynced in the background.
+    pub fn needs_wal_sync(&self) -> bool {
+        self.durability == DurabilityLevel::Normal && self.wal_sync_interval_ms > 0
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -148,3 +680,152 @@ pub enum OptionType {
     DB,
     ColumnFamily,
 }
+
+fn check_limit(what: &'static str, size: u64, limit: u64) -> crate::error::Result<()> {
+    ensure!(
+        limit == 0 || size <= limit,
+        ValueTooLargeSnafu { what, size, limit }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durability_level_from_str() {
+        assert_eq!(
+            "strict".parse::<DurabilityLevel>(),
+            Ok(DurabilityLevel::Strict)
+        );
+        assert_eq!(
+            "Normal".parse::<DurabilityLevel>(),
+            Ok(DurabilityLevel::Normal)
+        );
+        assert_eq!(
+            "RELAXED".parse::<DurabilityLevel>(),
+            Ok(DurabilityLevel::Relaxed)
+        );
+        assert!("always".parse::<DurabilityLevel>().is_err());
+        assert_eq!(DurabilityLevel::Relaxed.to_string(), "relaxed");
+    }
+
+    #[test]
+    fn test_value_compression_from_str() {
+        assert_eq!("LZ4".parse::<ValueCompression>(), Ok(ValueCompression::Lz4));
+        assert_eq!(
+            "zstd".parse::<ValueCompression>(),
+            Ok(ValueCompression::Zstd)
+        );
+        assert_eq!(
+            "none".parse::<ValueCompression>(),
+            Ok(ValueCompression::None)
+        );
+        assert!("gzip".parse::<ValueCompression>().is_err());
+        assert_eq!(ValueCompression::Zstd.to_string(), "zstd");
+        assert_eq!(
+            StorageOptions::default().value_compression,
+            ValueCompression::None
+        );
+    }
+
+    #[test]
+    fn test_wal_sync_only_for_normal() {
+        let mut options = StorageOptions::default();
+        assert_eq!(options.durability, DurabilityLevel::Normal);
+        assert!(options.needs_wal_sync());
+
+        options.set_wal_sync_interval_ms(0);
+        assert!(!options.needs_wal_sync());
+
+        options.set_wal_sync_interval_ms(1000);
+        for level in [DurabilityLevel::Strict, DurabilityLevel::Relaxed] {
+            options.set_durability(level);
+            assert!(!options.needs_wal_sync());
+        }
+    }
+
+    #[test]
+    fn test_fit_to_limits() {
+        let mut options = StorageOptions::default();
+        options.set_databases(16).fit_to_limits(&ResourceLimits {
+            cpus: 2.0,
+            memory_bytes: Some(4 << 30),
+        });
+        // 2 CPUs at 25% shared by 16 databases
+        assert_eq!(options.active_expire_cpu_percent, 3);
+        assert_eq!(options.block_cache_size, 1 << 30);
+
+        // Budgets below the limits are kept
+        let mut options = StorageOptions::default();
+        options
+            .set_active_expire_cpu_percent(1)
+            .set_block_cache_size(64 << 20)
+            .fit_to_limits(&ResourceLimits {
+                cpus: 64.0,
+                memory_bytes: None,
+            });
+        assert_eq!(options.active_expire_cpu_percent, 1);
+        assert_eq!(options.block_cache_size, 64 << 20);
+    }
+
+    #[test]
+    fn test_numa_placement() {
+        let node = |id, cpus: Vec<usize>| kstd::numa::NumaNode {
+            id,
+            cpus,
+            memory_bytes: None,
+        };
+        let topology = NumaTopology {
+            nodes: vec![node(0, vec![0, 1]), node(1, vec![2, 3])],
+        };
+
+        let mut options = StorageOptions::default();
+        options
+            .set_block_cache_size(64 << 20)
+            .set_numa_topology(Some(topology.clone()));
+        let placement = options.numa_placement.as_ref().unwrap();
+        // 3 instances per database, spread over both nodes
+        assert_eq!(placement.node_of(0, 0, 3), Some(0));
+        assert_eq!(placement.node_of(0, 1, 3), Some(1));
+        assert_eq!(placement.node_of(1, 0, 3), Some(1));
+        assert_eq!(placement.node_cache_size, 32 << 20);
+        assert!(placement.block_cache(1).is_some());
+        assert!(placement.block_cache(2).is_none());
+
+        options
+            .set_block_cache_size(0)
+            .set_numa_topology(Some(topology));
+        assert!(options
+            .numa_placement
+            .as_ref()
+            .unwrap()
+            .block_cache(0)
+            .is_none());
+
+        options.set_numa_topology(Some(NumaTopology::default()));
+        assert!(options.numa_placement.is_none());
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let mut options = StorageOptions::default();
+        assert!(options.check_value_size(512 << 20).is_ok());
+        assert!(matches!(
+            options.check_value_size((512 << 20) + 1),
+            Err(crate::error::Error::ValueTooLarge { .. })
+        ));
+        assert!(options.check_collection_len(u64::MAX).is_ok());
+
+        options
+            .set_max_value_size(0)
+            .set_max_member_size(4)
+            .set_max_collection_len(2);
+        assert!(options.check_value_size(usize::MAX).is_ok());
+        assert!(options.check_member_size(4).is_ok());
+        assert!(options.check_member_size(5).is_err());
+        assert!(options.check_collection_len(2).is_ok());
+        assert!(options.check_collection_len(3).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Read path IO statistics per command family.
+//!
+//! Reads are wrapped with [`ReadPerfStats::measure`], which collects the
+//! RocksDB perf context counters of the calling thread (block reads, bytes
+//! read from disk and block cache hits) and adds them to the counters of the
+//! data type the command works on. Reads that are not tied to one type, such
+//! as EXISTS, KEYS or OBJECT ENCODING, are counted in the keyspace family.
+//! They are reported by INFO rocksdbstats to help sizing the block cache.
+//!
+//! Type filtered scans (SCAN ... TYPE) also count the meta entries they
+//! skipped by the type of the entry, telling how much of the keyspace such
+//! a scan walks through for nothing.
+
+use crate::base_value_format::{DataType, DATA_TYPE_STRINGS};
+use rocksdb::perf::{set_perf_stats, PerfContext, PerfMetric, PerfStatsLevel};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Data types that have their own counters, `DataType::All` standing for
+/// the keyspace family.
+const FAMILIES: [DataType; 6] = [
+    DataType::String,
+    DataType::Hash,
+    DataType::Set,
+    DataType::List,
+    DataType::ZSet,
+    DataType::All,
+];
+
+/// The name of the `dtype` family in INFO.
+fn family_name(dtype: DataType) -> &'static str {
+    match dtype {
+        DataType::All => "keyspace",
+        dtype => DATA_TYPE_STRINGS[dtype as usize],
+    }
+}
+
+#[derive(Debug, Default)]
+struct FamilyCounters {
+    reads: AtomicU64,
+    block_read_count: AtomicU64,
+    block_read_bytes: AtomicU64,
+    block_cache_hits: AtomicU64,
+}
+
+/// Read statistics of one command family at a point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadPerfSnapshot {
+    pub reads: u64,
+    pub block_read_count: u64,
+    pub block_read_bytes: u64,
+    pub block_cache_hits: u64,
+}
+
+impl ReadPerfSnapshot {
+    /// Fraction of the block lookups served by the block cache.
+    pub fn cache_hit_ratio(&self) -> f64 {
+        let lookups = self.block_cache_hits + self.block_read_count;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.block_cache_hits as f64 / lookups as f64
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReadPerfStats {
+    families: [FamilyCounters; FAMILIES.len()],
+    /// Indexed by `DataType as usize`
+    scan_type_skipped: [AtomicU64; DATA_TYPE_STRINGS.len()],
+}
+
+impl ReadPerfStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `read` and accounts the IO it did to the `dtype` family.
+    pub fn measure<R>(&self, dtype: DataType, read: impl FnOnce() -> R) -> R {
+        if self.counters(dtype).is_none() {
+            return read();
+        }
+
+        set_perf_stats(PerfStatsLevel::EnableCount);
+        let mut ctx = PerfContext::default();
+        ctx.reset();
+        let result = read();
+        self.record(
+            dtype,
+            ReadPerfSnapshot {
+                reads: 1,
+                block_read_count: ctx.metric(PerfMetric::BlockReadCount),
+                block_read_bytes: ctx.metric(PerfMetric::BlockReadByte),
+                block_cache_hits: ctx.metric(PerfMetric::BlockCacheHitCount),
+            },
+        );
+        result
+    }
+
+    /// Adds `delta` to the counters of the `dtype` family.
+    pub fn record(&self, dtype: DataType, delta: ReadPerfSnapshot) {
+        let Some(counters) = self.counters(dtype) else {
+            return;
+        };
+        counters.reads.fetch_add(delta.reads, Ordering::Relaxed);
+        counters
+            .block_read_count
+            .fetch_add(delta.block_read_count, Ordering::Relaxed);
+        counters
+            .block_read_bytes
+            .fetch_add(delta.block_read_bytes, Ordering::Relaxed);
+        counters
+            .block_cache_hits
+            .fetch_add(delta.block_cache_hits, Ordering::Relaxed);
+    }
+
+    /// Returns the counters of the `dtype` family, `None` for the data types
+    /// that aren't tracked.
+    pub fn snapshot(&self, dtype: DataType) -> Option<ReadPerfSnapshot> {
+        self.counters(dtype).map(|counters| ReadPerfSnapshot {
+            reads: counters.reads.load(Ordering::Relaxed),
+            block_read_count: counters.block_read_count.load(Ordering::Relaxed),
+            block_read_bytes: counters.block_read_bytes.load(Ordering::Relaxed),
+            block_cache_hits: counters.block_cache_hits.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Adds `skipped[t]` to the entries of type `t` a type filtered scan
+    /// skipped, `skipped` being indexed by `DataType as usize`.
+    pub fn record_scan_type_skipped(&self, skipped: &[u64; DATA_TYPE_STRINGS.len()]) {
+        for (counter, skipped) in self.scan_type_skipped.iter().zip(skipped) {
+            if *skipped > 0 {
+                counter.fetch_add(*skipped, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The entries of type `dtype` skipped by type filtered scans.
+    pub fn scan_type_skipped(&self, dtype: DataType) -> u64 {
+        self.scan_type_skipped[dtype as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        for counters in &self.families {
+            counters.reads.store(0, Ordering::Relaxed);
+            counters.block_read_count.store(0, Ordering::Relaxed);
+            counters.block_read_bytes.store(0, Ordering::Relaxed);
+            counters.block_cache_hits.store(0, Ordering::Relaxed);
+        }
+        for counter in &self.scan_type_skipped {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Formats the counters as the rocksdbstats section of INFO.
+    pub fn info(&self) -> String {
+        let mut info = String::from("# RocksDBStats\r\n");
+        for dtype in FAMILIES {
+            let Some(stats) = self.snapshot(dtype) else {
+                continue;
+            };
+            let name = family_name(dtype);
+            let _ = write!(
+                info,
+                "{name}_reads:{}\r\n{name}_block_read_count:{}\r\n{name}_block_read_bytes:{}\r\n{name}_block_cache_hits:{}\r\n{name}_block_cache_hit_ratio:{:.4}\r\n",
+                stats.reads,
+                stats.block_read_count,
+                stats.block_read_bytes,
+                stats.block_cache_hits,
+                stats.cache_hit_ratio(),
+            );
+        }
+        let scanned = FAMILIES
+            .iter()
+            .copied()
+            .filter(|dtype| *dtype != DataType::All);
+        for dtype in scanned.chain([DataType::Stream]) {
+            let name = DATA_TYPE_STRINGS[dtype as usize];
+            let _ = write!(
+                info,
+                "{name}_scan_type_skipped:{}\r\n",
+                self.scan_type_skipped(dtype)
+            );
+        }
+        info
+    }
+
+    fn counters(&self, dtype: DataType) -> Option<&FamilyCounters> {
+        FAMILIES
+            .iter()
+            .position(|family| *family == dtype)
+            .map(|i| &self.families[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_per_family() {
+        let stats = ReadPerfStats::new();
+        let delta = ReadPerfSnapshot {
+            reads: 1,
+            block_read_count: 1,
+            block_read_bytes: 4096,
+            block_cache_hits: 3,
+        };
+        stats.record(DataType::Hash, delta);
+        stats.record(DataType::Hash, delta);
+        stats.record(DataType::All, delta);
+        stats.record(DataType::None, delta);
+
+        let hash = stats.snapshot(DataType::Hash).unwrap();
+        assert_eq!(hash.reads, 2);
+        assert_eq!(hash.block_read_bytes, 8192);
+        assert_eq!(hash.cache_hit_ratio(), 0.75);
+        assert_eq!(
+            stats.snapshot(DataType::String),
+            Some(ReadPerfSnapshot::default())
+        );
+        assert_eq!(stats.snapshot(DataType::All).unwrap().reads, 1);
+        assert!(stats.snapshot(DataType::None).is_none());
+
+        let info = stats.info();
+        assert!(info.starts_with("# RocksDBStats\r\n"));
+        assert!(info.contains("hash_reads:2\r\n"));
+        assert!(info.contains("keyspace_reads:1\r\n"));
+        assert!(!info.contains("all_"));
+        assert!(info.contains("hash_block_cache_hit_ratio:0.7500\r\n"));
+        assert!(info.contains("string_reads:0\r\n"));
+
+        stats.reset();
+        assert_eq!(
+            stats.snapshot(DataType::Hash),
+            Some(ReadPerfSnapshot::default())
+        );
+    }
+
+    #[test]
+    fn test_scan_type_skipped() {
+        let stats = ReadPerfStats::new();
+        let mut skipped = [0u64; DATA_TYPE_STRINGS.len()];
+        skipped[DataType::Hash as usize] = 3;
+        skipped[DataType::Stream as usize] = 1;
+        stats.record_scan_type_skipped(&skipped);
+        stats.record_scan_type_skipped(&skipped);
+
+        assert_eq!(stats.scan_type_skipped(DataType::Hash), 6);
+        assert_eq!(stats.scan_type_skipped(DataType::Stream), 2);
+        assert_eq!(stats.scan_type_skipped(DataType::ZSet), 0);
+        let info = stats.info();
+        assert!(info.contains("hash_scan_type_skipped:6\r\n"));
+        assert!(info.contains("stream_scan_type_skipped:2\r\n"));
+
+        stats.reset();
+        assert_eq!(stats.scan_type_skipped(DataType::Hash), 0);
+    }
+}
@@ -0,0 +1,278 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Publish/subscribe for embedders
+//!
+//! In library mode there is no connection to push messages to, so a
+//! subscription is a `Subscription` handle: an async stream of the messages
+//! published to its channels, or to the channels matching its patterns. A
+//! subscriber that falls `capacity` messages behind loses the newer ones,
+//! like a Redis client hitting its output buffer limit. Dropping the handle
+//! unsubscribes.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+use crate::util::string_match;
+
+/// Default number of messages buffered per subscription.
+pub const DEFAULT_SUBSCRIPTION_CAPACITY: usize = 1024;
+
+/// A published message, as received by a subscription.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// The pattern that matched the channel, `None` for a channel subscription
+    pub pattern: Option<Vec<u8>>,
+    pub channel: Vec<u8>,
+    pub payload: Bytes,
+}
+
+struct Subscriber {
+    id: u64,
+    sender: mpsc::Sender<Message>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    channels: HashMap<Vec<u8>, Vec<Subscriber>>,
+    patterns: HashMap<Vec<u8>, Vec<Subscriber>>,
+}
+
+impl Registry {
+    fn remove(&mut self, id: u64) {
+        for subscribers in [&mut self.channels, &mut self.patterns] {
+            subscribers.retain(|_, subs| {
+                subs.retain(|sub| sub.id != id);
+                !subs.is_empty()
+            });
+        }
+    }
+}
+
+/// The channels of a storage and their subscribers.
+pub struct PubSub {
+    registry: Mutex<Registry>,
+    capacity: usize,
+}
+
+impl Default for PubSub {
+    fn default() -> Self {
+        Self::new(DEFAULT_SUBSCRIPTION_CAPACITY)
+    }
+}
+
+impl PubSub {
+    /// `capacity` is the number of messages buffered per subscription.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            registry: Mutex::new(Registry::default()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Subscribes to `channels`.
+    pub fn subscribe(self: &Arc<Self>, channels: &[&[u8]]) -> Subscription {
+        self.register(channels, false)
+    }
+
+    /// Subscribes to the channels matching the glob `patterns`.
+    pub fn psubscribe(self: &Arc<Self>, patterns: &[&[u8]]) -> Subscription {
+        self.register(patterns, true)
+    }
+
+    /// Sends `payload` to the subscribers of `channel` and returns how many
+    /// received it. A subscriber subscribed to the channel and to a matching
+    /// pattern receives the message twice, as in Redis.
+    pub fn publish(&self, channel: &[u8], payload: impl Into<Bytes>) -> usize {
+        let payload = payload.into();
+        let registry = self.registry.lock().unwrap();
+
+        let mut received = 0;
+        if let Some(subs) = registry.channels.get(channel) {
+            let message = Message {
+                pattern: None,
+                channel: channel.to_vec(),
+                payload: payload.clone(),
+            };
+            received += subs
+                .iter()
+                .filter(|sub| sub.sender.try_send(message.clone()).is_ok())
+                .count();
+        }
+        for (pattern, subs) in &registry.patterns {
+            if !string_match(pattern, channel, false) {
+                continue;
+            }
+            let message = Message {
+                pattern: Some(pattern.clone()),
+                channel: channel.to_vec(),
+                payload: payload.clone(),
+            };
+            received += subs
+                .iter()
+                .filter(|sub| sub.sender.try_send(message.clone()).is_ok())
+                .count();
+        }
+        received
+    }
+
+    /// Number of subscribers of `channel`, patterns excluded (PUBSUB NUMSUB).
+    pub fn num_sub(&self, channel: &[u8]) -> usize {
+        let registry = self.registry.lock().unwrap();
+        registry.channels.get(channel).map_or(0, Vec::len)
+    }
+
+    /// Number of pattern subscriptions (PUBSUB NUMPAT).
+    pub fn num_pat(&self) -> usize {
+        let registry = self.registry.lock().unwrap();
+        registry.patterns.values().map(Vec::len).sum()
+    }
+
+    fn register(self: &Arc<Self>, names: &[&[u8]], pattern: bool) -> Subscription {
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        let mut registry = self.registry.lock().unwrap();
+        let id = registry.next_id;
+        registry.next_id += 1;
+
+        let subscribers = match pattern {
+            true => &mut registry.patterns,
+            false => &mut registry.channels,
+        };
+        let mut names = names.to_vec();
+        names.sort_unstable();
+        names.dedup();
+        for name in names {
+            subscribers
+                .entry(name.to_vec())
+                .or_default()
+                .push(Subscriber {
+                    id,
+                    sender: sender.clone(),
+                });
+        }
+
+        Subscription {
+            id,
+            receiver,
+            pubsub: Arc::downgrade(self),
+        }
+    }
+}
+
+/// The messages of a subscription. Unsubscribes when dropped.
+pub struct Subscription {
+    id: u64,
+    receiver: mpsc::Receiver<Message>,
+    pubsub: Weak<PubSub>,
+}
+
+impl Subscription {
+    /// Waits for the next message, `None` once the `PubSub` is dropped.
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.receiver.recv().await
+    }
+
+    /// Returns the next message if one is buffered.
+    pub fn try_recv(&mut self) -> Option<Message> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(pubsub) = self.pubsub.upgrade() {
+            pubsub.registry.lock().unwrap().remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_subscribe() {
+        let pubsub = Arc::new(PubSub::default());
+        let mut news = pubsub.subscribe(&[b"news", b"news"]);
+        let mut all = pubsub.psubscribe(&[b"n*"]);
+
+        assert_eq!(pubsub.publish(b"news", "hello"), 2);
+        assert_eq!(pubsub.publish(b"other", "ignored"), 0);
+        assert_eq!(pubsub.num_sub(b"news"), 1);
+        assert_eq!(pubsub.num_pat(), 1);
+
+        let message = news.try_recv().unwrap();
+        assert_eq!(message.pattern, None);
+        assert_eq!(message.channel, b"news");
+        assert_eq!(message.payload, "hello");
+        assert!(news.try_recv().is_none());
+
+        let message = all.try_recv().unwrap();
+        assert_eq!(message.pattern.as_deref(), Some(&b"n*"[..]));
+        assert_eq!(message.channel, b"news");
+
+        drop(news);
+        assert_eq!(pubsub.num_sub(b"news"), 0);
+        assert_eq!(pubsub.publish(b"news", "again"), 1);
+    }
+
+    #[test]
+    fn test_slow_subscriber_drops_messages() {
+        let pubsub = Arc::new(PubSub::new(2));
+        let mut sub = pubsub.subscribe(&[b"c"]);
+
+        assert_eq!(pubsub.publish(b"c", "1"), 1);
+        assert_eq!(pubsub.publish(b"c", "2"), 1);
+        assert_eq!(pubsub.publish(b"c", "3"), 0);
+
+        assert_eq!(sub.try_recv().unwrap().payload, "1");
+        assert_eq!(sub.try_recv().unwrap().payload, "2");
+        assert!(sub.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscription_recv() {
+        let pubsub = Arc::new(PubSub::default());
+        let mut sub = pubsub.subscribe(&[b"c"]);
+
+        let publisher = pubsub.clone();
+        tokio::spawn(async move {
+            publisher.publish(b"c", "async");
+        });
+        assert_eq!(sub.recv().await.unwrap().payload, "async");
+
+        drop(pubsub);
+        assert!(sub.recv().await.is_none());
+    }
+}
@@ -17,11 +17,22 @@
  * limitations under the License.
  */
 
+use crate::base_filter::{BaseDataFilterFactory, ZSetsScoreFilterFactory};
+use crate::base_key_format::{slot_reserve, BaseKey};
 use crate::base_value_format::{DataType, DATA_TYPE_TAG};
+use crate::checksum;
+use crate::clock;
+use crate::compression;
 use crate::error::{OptionNoneSnafu, Result, RocksSnafu};
+use crate::lazy_delete::LazyDeleteQueue;
+use crate::lists_filter::ListsDataFilterFactory;
 use crate::options::{OptionType, StorageOptions};
+use crate::stale_ranges::StaleRanges;
 use crate::statistics::KeyStatistics;
 use crate::storage::BgTaskHandler;
+use crate::storage_define::PREFIX_RESERVE_LENGTH;
+use crate::streams_filter::StreamsDataFilterFactory;
+use crate::trash::TrashFilterFactory;
 use foyer::{Cache, CacheBuilder};
 use kstd::lock_mgr::LockMgr;
 use rocksdb::{
@@ -30,20 +41,42 @@ use rocksdb::{
 use snafu::{OptionExt, ResultExt};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::{Arc, OnceLock, Weak};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ColumnFamilyIndex {
-    MetaCF = 0,       // meta & string
-    HashesDataCF = 1, // hash data
-    SetsDataCF = 2,   // set data
-    ListsDataCF = 3,  // list data
-    ZsetsDataCF = 4,  // zset data
-    ZsetsScoreCF = 5, // zset score
+    MetaCF = 0,         // meta & string
+    HashesDataCF = 1,   // hash data
+    SetsDataCF = 2,     // set data
+    ListsDataCF = 3,    // list data
+    ZsetsDataCF = 4,    // zset data
+    ZsetsScoreCF = 5,   // zset score
+    SystemCF = 6,       // server internal state
+    TrashCF = 7,        // soft deleted meta values
+    StreamsGroupCF = 8, // stream consumer groups & pending entries
+    BitmapDataCF = 9,   // segments of bitmaps written by SETBIT
+    IntentLogCF = 10,   // intents of operations spanning several batches
+    StreamsDataCF = 11, // stream entries
 }
 
 impl ColumnFamilyIndex {
+    /// Every column family, in the order of their indexes
+    pub const ALL: [ColumnFamilyIndex; 12] = [
+        ColumnFamilyIndex::MetaCF,
+        ColumnFamilyIndex::HashesDataCF,
+        ColumnFamilyIndex::SetsDataCF,
+        ColumnFamilyIndex::ListsDataCF,
+        ColumnFamilyIndex::ZsetsDataCF,
+        ColumnFamilyIndex::ZsetsScoreCF,
+        ColumnFamilyIndex::SystemCF,
+        ColumnFamilyIndex::TrashCF,
+        ColumnFamilyIndex::StreamsGroupCF,
+        ColumnFamilyIndex::BitmapDataCF,
+        ColumnFamilyIndex::IntentLogCF,
+        ColumnFamilyIndex::StreamsDataCF,
+    ];
+
     pub fn name(&self) -> &'static str {
         match self {
             ColumnFamilyIndex::MetaCF => "default",
@@ -52,6 +85,12 @@ impl ColumnFamilyIndex {
             ColumnFamilyIndex::ListsDataCF => "list_data_cf",
             ColumnFamilyIndex::ZsetsDataCF => "zset_data_cf",
             ColumnFamilyIndex::ZsetsScoreCF => "zset_score_cf",
+            ColumnFamilyIndex::SystemCF => "system_cf",
+            ColumnFamilyIndex::TrashCF => "trash_cf",
+            ColumnFamilyIndex::StreamsGroupCF => "stream_group_cf",
+            ColumnFamilyIndex::BitmapDataCF => "bitmap_data_cf",
+            ColumnFamilyIndex::IntentLogCF => "intent_log_cf",
+            ColumnFamilyIndex::StreamsDataCF => "stream_data_cf",
         }
     }
 }
@@ -59,6 +98,8 @@ impl ColumnFamilyIndex {
 #[repr(C, align(64))]
 pub struct Redis {
     pub index: i32,
+    // The position of the NUMA node of the instance, see NumaPlacement
+    pub numa_node: Option<usize>,
     pub need_close: std::sync::atomic::AtomicBool,
     pub lock_mgr: Arc<LockMgr>,
 
@@ -67,7 +108,9 @@ pub struct Redis {
     pub write_options: WriteOptions,
     pub read_options: ReadOptions,
     pub compact_options: CompactOptions,
-    pub db: Option<DB>,
+    pub db: Option<Arc<DB>>,
+    // Handed to the data compaction filters once the db is open
+    pub filter_db: Arc<OnceLock<Weak<DB>>>,
 
     // For background task
     pub storage: Arc<StorageOptions>,
@@ -82,6 +125,11 @@ pub struct Redis {
     pub scan_cursors_store: Mutex<Cache<String, u64>>,
     pub spop_counts_store: Mutex<Cache<String, u64>>,
 
+    // For the data keys of deleted collections, see lazy_delete
+    pub lazy_delete: LazyDeleteQueue,
+    // For the keys reads found expired, see stale_ranges
+    pub stale_ranges: StaleRanges,
+
     // For raft
     pub is_starting: AtomicBool,
 }
@@ -99,18 +147,29 @@ impl Redis {
 
         let statistics_store: Cache<String, KeyStatistics> =
             CacheBuilder::new(storage.statistics_max_size).build();
+        let write_options = storage.write_options();
+        let lazy_delete = LazyDeleteQueue::new(storage.lazy_delete_queue_len);
+        // Nothing drains the tracked keys without a maintenance window
+        let stale_ranges = match storage.stale_compaction_window {
+            Some(_) => StaleRanges::new(storage.stale_keys_tracked),
+            None => StaleRanges::new(0),
+        };
 
         Self {
             index,
+            numa_node: None,
             need_close: std::sync::atomic::AtomicBool::new(false),
             is_starting: AtomicBool::new(true),
 
             storage,
             db: None,
+            filter_db: Arc::new(OnceLock::new()),
+            lazy_delete,
+            stale_ranges,
             bg_task_handler,
             lock_mgr,
             handles: Vec::new(),
-            write_options: WriteOptions::default(),
+            write_options,
             read_options: ReadOptions::default(),
             compact_options,
 
@@ -129,6 +188,13 @@ impl Redis {
             self.storage.small_compaction_threshold as u64,
             std::sync::atomic::Ordering::SeqCst,
         );
+        // Process wide, an instance with conflicting options is refused
+        checksum::set_enabled(self.storage.value_checksum)?;
+        compression::configure(
+            self.storage.value_compression,
+            self.storage.compression_threshold,
+        )?;
+        clock::set_clock(self.storage.clock.clone())?;
 
         const CF_CONFIGS: &[(&str, bool, Option<usize>)] = &[
             ("default", true, None),                   // meta & string: bloom filter
@@ -137,19 +203,45 @@ impl Redis {
             ("list_data_cf", true, None),              // list: bloom filter
             ("zset_data_cf", false, Some(16 * 1024)),  // zset data: 16KB block size
             ("zset_score_cf", false, Some(16 * 1024)), // zset score: 16KB block size
+            ("system_cf", false, None),                // server internal state
+            ("trash_cf", false, None),                 // soft deleted meta values
+            ("stream_group_cf", true, None),           // stream consumer groups
+            ("bitmap_data_cf", true, None),            // bitmap segments
+            ("intent_log_cf", false, None),            // multi-batch intents
+            ("stream_data_cf", true, None),            // stream entries
         ];
 
+        // The column families of an instance share the cache of its node
+        let block_cache = self.numa_node.and_then(|node| {
+            self.storage
+                .numa_placement
+                .as_ref()?
+                .block_cache(node)
+                .cloned()
+        });
         let column_families: Vec<ColumnFamilyDescriptor> = CF_CONFIGS
             .iter()
             .map(|(name, use_bloom, block_size)| {
-                Self::create_cf_options(&self.storage, name, *use_bloom, *block_size)
+                Self::create_cf_options(
+                    &self.storage,
+                    &self.filter_db,
+                    name,
+                    *use_bloom,
+                    *block_size,
+                    block_cache.as_ref(),
+                )
             })
             .collect();
 
-        self.db = Some(
-            DB::open_cf_descriptors(&self.storage.options, db_path, column_families)
-                .context(RocksSnafu)?,
+        let mut db_options = self.storage.options.clone();
+        if let Some(jobs) = self.storage.rocksdb_tuning.max_background_jobs {
+            db_options.set_max_background_jobs(jobs);
+        }
+        let db = Arc::new(
+            DB::open_cf_descriptors(&db_options, db_path, column_families).context(RocksSnafu)?,
         );
+        let _ = self.filter_db.set(Arc::downgrade(&db));
+        self.db = Some(db);
 
         if let Some(db) = &self.db {
             let mut handles = Vec::new();
@@ -162,6 +254,9 @@ impl Redis {
             self.handles = handles;
         }
 
+        self.check_key_layout()?;
+        self.restore_version_sequence()?;
+        self.recover_intents()?;
         self.is_starting.store(false, Ordering::SeqCst);
 
         Ok(())
@@ -170,30 +265,91 @@ impl Redis {
     // Helper function: create column-family options
     fn create_cf_options(
         storage_options: &StorageOptions,
+        filter_db: &Arc<OnceLock<Weak<DB>>>,
         cf_name: &str,
         use_bloom_filter: bool,
         block_size: Option<usize>,
+        block_cache: Option<&rocksdb::Cache>,
     ) -> ColumnFamilyDescriptor {
+        let tuning = storage_options.rocksdb_tuning.for_cf(cf_name);
         let mut cf_opts = storage_options.options.clone();
+        tuning.apply(&mut cf_opts);
         let mut table_opts = BlockBasedOptions::default();
 
-        // Set bloom filter
-        if use_bloom_filter {
-            table_opts.set_bloom_filter(10.0, true);
-        }
-
-        // Set block size
-        if let Some(size) = block_size {
-            table_opts.set_block_size(size);
-        }
+        // Set bloom filter and block size, the tuning over those of CF_CONFIGS
+        let bloom_filter_bits = if use_bloom_filter { 10.0 } else { 0.0 };
+        tuning.apply_table(&mut table_opts, block_size, bloom_filter_bits);
 
         // Set block cache
-        if !storage_options.share_block_cache && storage_options.block_cache_size > 0 {
+        if let Some(cache) = block_cache {
+            table_opts.set_block_cache(cache);
+        } else if !storage_options.share_block_cache && storage_options.block_cache_size > 0 {
             let cache = rocksdb::Cache::new_lru_cache(storage_options.block_cache_size);
             table_opts.set_block_cache(&cache);
         }
 
         cf_opts.set_block_based_table_factory(&table_opts);
+
+        // Purge the soft deleted keys whose retention has passed
+        if cf_name == ColumnFamilyIndex::TrashCF.name() {
+            cf_opts.set_compaction_filter_factory(TrashFilterFactory::new(
+                storage_options.trash_retention_s,
+            ));
+        }
+
+        let bottommost_only = storage_options
+            .filter_bottommost_only
+            .iter()
+            .any(|cf| cf.name() == cf_name);
+
+        // Drop the fields and members of deleted or overwritten collections
+        let data_type = match cf_name {
+            name if name == ColumnFamilyIndex::HashesDataCF.name() => Some(DataType::Hash),
+            name if name == ColumnFamilyIndex::SetsDataCF.name() => Some(DataType::Set),
+            name if name == ColumnFamilyIndex::ZsetsDataCF.name() => Some(DataType::ZSet),
+            _ => None,
+        };
+        if let Some(data_type) = data_type {
+            cf_opts.set_compaction_filter_factory(
+                BaseDataFilterFactory::new(
+                    filter_db.clone(),
+                    data_type,
+                    storage_options.filter_config.clone(),
+                )
+                .with_bottommost_only(bottommost_only),
+            );
+        }
+        // Drop the score keys along with the member keys of the same zsets
+        if cf_name == ColumnFamilyIndex::ZsetsScoreCF.name() {
+            cf_opts.set_compaction_filter_factory(
+                ZSetsScoreFilterFactory::new(
+                    filter_db.clone(),
+                    storage_options.filter_config.clone(),
+                )
+                .with_bottommost_only(bottommost_only),
+            );
+        }
+        // Drop the nodes of deleted lists and those left outside their indexes
+        if cf_name == ColumnFamilyIndex::ListsDataCF.name() {
+            cf_opts.set_compaction_filter_factory(
+                ListsDataFilterFactory::new(
+                    filter_db.clone(),
+                    storage_options.filter_config.clone(),
+                )
+                .with_bottommost_only(bottommost_only),
+            );
+        }
+        // Drop the trimmed entries and those of deleted streams
+        if cf_name == ColumnFamilyIndex::StreamsDataCF.name() {
+            cf_opts.set_compaction_filter_factory(
+                StreamsDataFilterFactory::new(
+                    filter_db.clone(),
+                    storage_options.filter_config.clone(),
+                )
+                .with_bottommost_only(bottommost_only),
+            );
+        }
+
         ColumnFamilyDescriptor::new(cf_name, cf_opts)
     }
 
@@ -228,6 +384,14 @@ impl Redis {
         Ok(())
     }
 
+    /// Fsync the WAL, making every write acknowledged so far durable.
+    pub fn sync_wal(&self) -> Result<()> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        db.flush_wal(true).context(RocksSnafu)
+    }
+
     pub fn get_property(&self, property: &str) -> Result<u64> {
         if let Some(db) = &self.db {
             if let Some(value) = db.property_int_value(property).context(RocksSnafu)? {
@@ -254,6 +418,20 @@ impl Redis {
         None
     }
 
+    /// Returns the reserve1 of the keys of `key`, carrying its slot id when
+    /// the slot prefix is enabled.
+    pub fn key_reserve1(&self, key: &[u8]) -> [u8; PREFIX_RESERVE_LENGTH] {
+        match self.storage.slot_prefix {
+            true => slot_reserve(key),
+            false => [0; PREFIX_RESERVE_LENGTH],
+        }
+    }
+
+    /// Returns the meta key of `key`.
+    pub fn base_key(&self, key: &[u8]) -> BaseKey {
+        BaseKey::with_reserve1(key, self.key_reserve1(key))
+    }
+
     pub fn update_specific_key_duration(
         &self,
         dtype: DataType,
@@ -0,0 +1,478 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Bitmap operations on string values
+//!
+//! SETBIT stores the bitmap in segments (see bitmap_segment_format), so
+//! every later SETBIT, GETBIT and BITCOUNT only reads the segments it needs.
+//! A plain string is moved to segments by its first SETBIT.
+
+use kstd::lock_mgr::ScopeRecordLock;
+use rocksdb::{Direction, IteratorMode, ReadOptions, WriteBatch};
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    base_data_value_format::{BaseDataValue, ParsedBaseDataValue},
+    bitmap_segment_format::{
+        decode_segment_index, segment_byte_range, BitPosition, BitmapSegmentKey,
+        SegmentedBitmapHeader, BITMAP_SEGMENT_BYTES,
+    },
+    error::{OptionNoneSnafu, RocksSnafu, WrongTypeSnafu},
+    redis_keys::is_live_meta_value,
+    strings_value_format::{ParsedStringsValue, StringValue},
+    version_seq, ColumnFamilyIndex, DataType, Redis, Result,
+};
+
+/// The bitmap stored under a key, as read from its meta value.
+enum Bitmap {
+    Missing,
+    Plain(ParsedStringsValue),
+    Segmented(ParsedStringsValue, SegmentedBitmapHeader),
+}
+
+impl Redis {
+    /// Sets or clears the bit at offset (SETBIT) and returns its old value
+    pub fn setbit(&self, key: &[u8], offset: u64, on: bool) -> Result<bool> {
+        let pos = BitPosition::of_bit(offset);
+        self.storage
+            .check_value_size(pos.byte_offset().saturating_add(1) as usize)?;
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::BitmapDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let mut batch = WriteBatch::default();
+        let (times, mut header, mut segment) = match self.read_bitmap(key)? {
+            Bitmap::Segmented(value, header) => {
+                let segment_key = BitmapSegmentKey::new(key, header.version, pos.segment);
+                let segment = self.read_segment(&segment_key.encode()?)?;
+                (Some((value.ctime(), value.etime())), header, segment)
+            }
+            Bitmap::Plain(value) => {
+                let bytes = value.user_value_slice();
+                let header = self.stage_bitmap_segments(&mut batch, key, bytes)?;
+                let segment = bytes
+                    .chunks(BITMAP_SEGMENT_BYTES as usize)
+                    .nth(pos.segment as usize)
+                    .map(<[u8]>::to_vec)
+                    .unwrap_or_default();
+                (Some((value.ctime(), value.etime())), header, segment)
+            }
+            Bitmap::Missing => {
+                let header = SegmentedBitmapHeader::new(version_seq::next_version(0));
+                (None, header, Vec::new())
+            }
+        };
+
+        // a later put of the same key wins within the batch
+        let old = pos.set(&mut segment, on);
+        let segment_key = BitmapSegmentKey::new(key, header.version, pos.segment);
+        batch.put_cf(
+            &cf,
+            segment_key.encode()?,
+            BaseDataValue::new(segment).encode(),
+        );
+
+        header.extend_to(pos);
+        let mut meta = StringValue::segmented(header.encode());
+        if let Some((ctime, etime)) = times {
+            meta.set_ctime(ctime);
+            meta.set_etime(etime);
+        }
+        let meta_key = self.base_key(key).encode()?;
+        self.stage_meta_update(&mut batch, &meta_key, &meta.encode())?;
+
+        db.write_opt(batch, &self.write_options)
+            .context(RocksSnafu)?;
+        Ok(old)
+    }
+
+    /// The bit at offset (GETBIT), false past the end of the bitmap
+    pub fn getbit(&self, key: &[u8], offset: u64) -> Result<bool> {
+        let pos = BitPosition::of_bit(offset);
+        match self.read_bitmap(key)? {
+            Bitmap::Missing => Ok(false),
+            Bitmap::Plain(value) => {
+                let byte = pos.byte_offset();
+                Ok(value
+                    .user_value_slice()
+                    .get(byte as usize)
+                    .is_some_and(|b| b & pos.mask != 0))
+            }
+            Bitmap::Segmented(_, header) => {
+                if pos.byte_offset() >= header.len {
+                    return Ok(false);
+                }
+                let segment_key = BitmapSegmentKey::new(key, header.version, pos.segment);
+                Ok(pos.get(&self.read_segment(&segment_key.encode()?)?))
+            }
+        }
+    }
+
+    /// Counts the set bits (BITCOUNT), in the inclusive byte range
+    /// `start..=end` when given. Negative offsets count from the end
+    pub fn bitcount(&self, key: &[u8], range: Option<(i64, i64)>) -> Result<u64> {
+        let bitmap = self.read_bitmap(key)?;
+        let len = match &bitmap {
+            Bitmap::Missing => return Ok(0),
+            Bitmap::Plain(value) => value.user_value_slice().len() as u64,
+            Bitmap::Segmented(_, header) => header.len,
+        };
+        let Some((start, end)) = byte_range(len, range) else {
+            return Ok(0);
+        };
+
+        match bitmap {
+            Bitmap::Missing => Ok(0),
+            Bitmap::Plain(value) => Ok(count_ones(
+                &value.user_value_slice()[start as usize..=end as usize],
+            )),
+            Bitmap::Segmented(_, header) => {
+                let first = start / BITMAP_SEGMENT_BYTES;
+                let last = end / BITMAP_SEGMENT_BYTES;
+                let mut count = 0;
+                for (index, segment) in self.read_segments(key, header.version, first..last + 1)? {
+                    let bytes = segment_byte_range(index, start, end);
+                    let bytes = bytes.start.min(segment.len())..bytes.end.min(segment.len());
+                    count += count_ones(&segment[bytes]);
+                }
+                Ok(count)
+            }
+        }
+    }
+
+    /// The bytes of a bitmap stored in segments, for GET
+    pub(crate) fn segmented_bitmap_value(
+        &self,
+        key: &[u8],
+        header: SegmentedBitmapHeader,
+    ) -> Result<Vec<u8>> {
+        let mut bitmap = vec![0; header.len as usize];
+        for (index, segment) in
+            self.read_segments(key, header.version, 0..header.segment_count())?
+        {
+            let start = (index * BITMAP_SEGMENT_BYTES) as usize;
+            let end = (start + segment.len()).min(bitmap.len());
+            if start < end {
+                bitmap[start..end].copy_from_slice(&segment[..end - start]);
+            }
+        }
+        Ok(bitmap)
+    }
+
+    fn read_bitmap(&self, key: &[u8]) -> Result<Bitmap> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let Some(value) = db
+            .get_cf_opt(&meta_cf, self.base_key(key).encode()?, &self.read_options)
+            .context(RocksSnafu)?
+        else {
+            return Ok(Bitmap::Missing);
+        };
+        if !is_live_meta_value(&value) {
+            self.note_stale_read(key, &value);
+            return Ok(Bitmap::Missing);
+        }
+        if value[0] != DataType::String as u8 {
+            return WrongTypeSnafu {
+                key: String::from_utf8_lossy(key).to_string(),
+            }
+            .fail();
+        }
+
+        let value = ParsedStringsValue::new(value)?;
+        if !value.is_segmented() {
+            return Ok(Bitmap::Plain(value));
+        }
+        let header = SegmentedBitmapHeader::decode(value.user_value_slice())?;
+        Ok(Bitmap::Segmented(value, header))
+    }
+
+    // Writes the bytes of a plain string as the segments of a new bitmap
+    fn stage_bitmap_segments(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        bytes: &[u8],
+    ) -> Result<SegmentedBitmapHeader> {
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::BitmapDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let mut header = SegmentedBitmapHeader::new(version_seq::next_version(0));
+        for (index, chunk) in bytes.chunks(BITMAP_SEGMENT_BYTES as usize).enumerate() {
+            let segment_key = BitmapSegmentKey::new(key, header.version, index as u64);
+            batch.put_cf(
+                &cf,
+                segment_key.encode()?,
+                BaseDataValue::new(chunk.to_vec()).encode(),
+            );
+        }
+        header.len = bytes.len() as u64;
+        Ok(header)
+    }
+
+    // Deletes the segments of a bitmap whose meta value is being overwritten
+    pub(crate) fn stage_bitmap_segments_delete(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        header: &SegmentedBitmapHeader,
+    ) -> Result<()> {
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::BitmapDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let start = BitmapSegmentKey::new(key, header.version, 0).encode_seek_key()?;
+        let end =
+            BitmapSegmentKey::new(key, header.version, header.segment_count()).encode_seek_key()?;
+        batch.delete_range_cf(&cf, start, end);
+        Ok(())
+    }
+
+    fn read_segment(&self, segment_key: &[u8]) -> Result<Vec<u8>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::BitmapDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        match db
+            .get_cf_opt(&cf, segment_key, &self.read_options)
+            .context(RocksSnafu)?
+        {
+            Some(value) => Ok(ParsedBaseDataValue::new(value)?.user_value().to_vec()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // The stored segments whose index is in `indexes`, missing ones are zeros
+    fn read_segments(
+        &self,
+        key: &[u8],
+        version: u64,
+        indexes: std::ops::Range<u64>,
+    ) -> Result<Vec<(u64, Vec<u8>)>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::BitmapDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        if indexes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let start = BitmapSegmentKey::new(key, version, indexes.start).encode_seek_key()?;
+        let end = BitmapSegmentKey::new(key, version, indexes.end).encode_seek_key()?;
+        let mut read_options = ReadOptions::default();
+        read_options.set_iterate_upper_bound(end);
+        let iter = db.iterator_cf_opt(
+            &cf,
+            read_options,
+            IteratorMode::From(&start, Direction::Forward),
+        );
+
+        let mut segments = Vec::new();
+        for item in iter {
+            let (segment_key, value) = item.context(RocksSnafu)?;
+            let index = decode_segment_index(&segment_key)?;
+            segments.push((
+                index,
+                ParsedBaseDataValue::new(value)?.user_value().to_vec(),
+            ));
+        }
+        Ok(segments)
+    }
+}
+
+/// The bytes of the inclusive range of BITCOUNT, clamped to a bitmap of len
+/// bytes. None when the range is empty
+fn byte_range(len: u64, range: Option<(i64, i64)>) -> Option<(u64, u64)> {
+    if len == 0 {
+        return None;
+    }
+    let (start, end) = range.unwrap_or((0, -1));
+    let resolve = |offset: i64| {
+        if offset < 0 {
+            (len as i64 + offset).max(0)
+        } else {
+            offset
+        }
+    };
+    let start = resolve(start);
+    let end = resolve(end).min(len as i64 - 1);
+    (start <= end).then_some((start as u64, end as u64))
+}
+
+fn count_ones(bytes: &[u8]) -> u64 {
+    bytes.iter().map(|byte| byte.count_ones() as u64).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base_meta_value_format::BaseMetaValue, error::Error, with_redis, StorageOptions,
+        ValueFormat,
+    };
+
+    fn stored_segments(redis: &Redis, key: &[u8]) -> Vec<u64> {
+        let Bitmap::Segmented(_, header) = redis.read_bitmap(key).unwrap() else {
+            panic!("bitmap is not segmented");
+        };
+        redis
+            .read_segments(key, header.version, 0..header.segment_count())
+            .unwrap()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    #[test]
+    fn test_setbit_writes_only_the_touched_segment() {
+        with_redis(StorageOptions::default(), |redis| {
+            let far = 8 * 10 * BITMAP_SEGMENT_BYTES + 3;
+            assert!(!redis.setbit(b"bm", 7, true).unwrap());
+            assert!(!redis.setbit(b"bm", far, true).unwrap());
+            assert!(redis.setbit(b"bm", far, true).unwrap());
+            assert_eq!(stored_segments(redis, b"bm"), vec![0, 10]);
+
+            assert!(redis.getbit(b"bm", 7).unwrap());
+            assert!(redis.getbit(b"bm", far).unwrap());
+            assert!(!redis.getbit(b"bm", far + 1).unwrap());
+            assert!(!redis.getbit(b"bm", u64::MAX).unwrap());
+
+            let value = redis.get(b"bm").unwrap();
+            assert_eq!(value.len() as u64, 10 * BITMAP_SEGMENT_BYTES + 1);
+
+            assert_eq!(redis.bitcount(b"bm", None).unwrap(), 2);
+            assert_eq!(redis.bitcount(b"bm", Some((1, -1))).unwrap(), 1);
+            assert_eq!(redis.bitcount(b"bm", Some((0, 0))).unwrap(), 1);
+            assert_eq!(redis.bitcount(b"bm", Some((-1, -1))).unwrap(), 1);
+            assert_eq!(redis.bitcount(b"bm", Some((5, 2))).unwrap(), 0);
+            assert_eq!(redis.bitcount(b"missing", None).unwrap(), 0);
+
+            assert!(redis.setbit(b"bm", 7, false).unwrap());
+            assert_eq!(redis.bitcount(b"bm", None).unwrap(), 1);
+
+            // The bitmap may not grow past the string value limit
+            let limit = redis.storage.max_value_size as u64;
+            assert!(matches!(
+                redis.setbit(b"bm", 8 * limit, true),
+                Err(Error::ValueTooLarge { .. })
+            ));
+            assert!(!redis.setbit(b"bm", 8 * limit - 1, true).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_set_deletes_the_segments_of_a_bitmap() {
+        with_redis(StorageOptions::default(), |redis| {
+            let far = 8 * 3 * BITMAP_SEGMENT_BYTES;
+            assert!(!redis.setbit(b"bm", 0, true).unwrap());
+            assert!(!redis.setbit(b"bm", far, true).unwrap());
+            let Bitmap::Segmented(_, header) = redis.read_bitmap(b"bm").unwrap() else {
+                panic!("bitmap is not segmented");
+            };
+            assert_eq!(stored_segments(redis, b"bm"), vec![0, 3]);
+
+            redis.set(b"bm", b"plain").unwrap();
+            assert_eq!(redis.get(b"bm").unwrap(), "plain");
+            assert!(redis
+                .read_segments(b"bm", header.version, 0..header.segment_count())
+                .unwrap()
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn test_setbit_moves_a_plain_string_to_segments() {
+        with_redis(StorageOptions::default(), |redis| {
+            let mut bytes = vec![b'a'; BITMAP_SEGMENT_BYTES as usize + 2];
+            redis.set(b"s", &bytes).unwrap();
+            assert_eq!(redis.bitcount(b"s", None).unwrap(), 3 * bytes.len() as u64);
+            assert!(redis.getbit(b"s", 1).unwrap());
+
+            // 'a' is 0b0110_0001, set the most significant bit of the last byte
+            let last = bytes.len() as u64 - 1;
+            assert!(!redis.setbit(b"s", 8 * last, true).unwrap());
+            bytes[last as usize] |= 0x80;
+            assert_eq!(stored_segments(redis, b"s"), vec![0, 1]);
+            assert_eq!(
+                redis.get(b"s").unwrap(),
+                String::from_utf8_lossy(&bytes).to_string()
+            );
+            assert_eq!(
+                redis.bitcount(b"s", None).unwrap(),
+                3 * bytes.len() as u64 + 1
+            );
+        });
+    }
+
+    #[test]
+    fn test_bitmap_of_another_type() {
+        with_redis(StorageOptions::default(), |redis| {
+            let db = redis.db.as_ref().unwrap();
+            let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+            let mut meta = BaseMetaValue::new(1);
+            meta.inner.data_type = DataType::Hash;
+            let meta_key = redis.base_key(b"h").encode().unwrap();
+            db.put_cf(&meta_cf, meta_key, ValueFormat::encode(&meta))
+                .unwrap();
+
+            assert!(matches!(
+                redis.setbit(b"h", 0, true),
+                Err(Error::WrongType { .. })
+            ));
+            assert!(matches!(
+                redis.bitcount(b"h", None),
+                Err(Error::WrongType { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn test_byte_range() {
+        assert_eq!(byte_range(0, None), None);
+        assert_eq!(byte_range(10, None), Some((0, 9)));
+        assert_eq!(byte_range(10, Some((-3, -1))), Some((7, 9)));
+        assert_eq!(byte_range(10, Some((-100, 100))), Some((0, 9)));
+        assert_eq!(byte_range(10, Some((10, 20))), None);
+        assert_eq!(byte_range(10, Some((3, 2))), None);
+    }
+}
@@ -0,0 +1,367 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Hash commands that read fields and change them in the same write:
+//! HGETDEL and HGETEX
+
+use std::collections::HashSet;
+
+use kstd::lock_mgr::ScopeRecordLock;
+use rocksdb::WriteBatch;
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    base_meta_value_format::ParsedBaseMetaValue,
+    clock,
+    error::{OptionNoneSnafu, RocksSnafu, WrongTypeSnafu},
+    hashes_data_key_format::HashesDataKey,
+    hashes_data_value_format::{HashesDataValue, ParsedHashesDataValue},
+    inline_collection_format::record_spill,
+    redis_keys::is_live_meta_value,
+    ColumnFamilyIndex, DataType, Redis, Result,
+};
+
+/// What HGETEX does to the expiration time of the fields it returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldExpiry {
+    /// Leaves the expiration time unchanged
+    Keep,
+    /// Removes the expiration time (PERSIST)
+    Persist,
+    /// Expires the fields at a unix time in microseconds (EX, PX, EXAT, PXAT).
+    /// A time in the past deletes the fields
+    At(u64),
+}
+
+#[derive(Clone, Copy)]
+enum FieldUpdate {
+    Delete,
+    Expire(FieldExpiry),
+}
+
+impl Redis {
+    /// Returns the values of the fields and deletes them (HGETDEL). The hash
+    /// is deleted with its last field
+    pub fn hgetdel(&self, key: &[u8], fields: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.hget_and_update(key, fields, FieldUpdate::Delete)
+    }
+
+    /// Returns the values of the fields and sets or clears their expiration
+    /// time (HGETEX)
+    pub fn hgetex(
+        &self,
+        key: &[u8],
+        fields: &[&[u8]],
+        expiry: FieldExpiry,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let update = match expiry {
+            FieldExpiry::At(etime) if etime <= clock::now_micros() => FieldUpdate::Delete,
+            _ => FieldUpdate::Expire(expiry),
+        };
+        self.hget_and_update(key, fields, update)
+    }
+
+    // Reads every field first, so a repeated field gets its value each time,
+    // then stages the update of the existing fields and the meta value in one
+    // batch under the record lock of the key
+    fn hget_and_update(
+        &self,
+        key: &[u8],
+        fields: &[&[u8]],
+        update: FieldUpdate,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let data_cf = self
+            .get_cf_handle(ColumnFamilyIndex::HashesDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let meta_key = self.base_key(key).encode()?;
+        let Some(meta_value) = db
+            .get_cf_opt(&meta_cf, &meta_key, &self.read_options)
+            .context(RocksSnafu)?
+        else {
+            return Ok(vec![None; fields.len()]);
+        };
+        if !is_live_meta_value(&meta_value) {
+            self.note_stale_read(key, &meta_value);
+            return Ok(vec![None; fields.len()]);
+        }
+        if meta_value[0] != DataType::Hash as u8 {
+            return WrongTypeSnafu { key: key_str }.fail();
+        }
+        let meta = ParsedBaseMetaValue::new(&meta_value[..])?;
+
+        let mut batch = WriteBatch::default();
+        let mut values = Vec::with_capacity(fields.len());
+
+        if let Some(mut collection) = meta.inline_collection()? {
+            for field in fields {
+                values.push(collection.get(field).map(<[u8]>::to_vec));
+            }
+            if values.iter().all(Option::is_none) {
+                return Ok(values);
+            }
+
+            let mut new_meta = meta.to_meta_value();
+            match update {
+                FieldUpdate::Delete => {
+                    for field in fields {
+                        collection.remove(field);
+                    }
+                    new_meta.set_inline(Some(&collection));
+                }
+                // inline fields never expire
+                FieldUpdate::Expire(FieldExpiry::Keep | FieldExpiry::Persist) => return Ok(values),
+                // only data keys carry a field etime, so move the fields there
+                FieldUpdate::Expire(FieldExpiry::At(etime)) => {
+                    let expiring: HashSet<&[u8]> = fields.iter().copied().collect();
+                    for (field, value) in collection.iter() {
+                        let mut data_value = HashesDataValue::new(value.to_vec());
+                        if expiring.contains(field) {
+                            data_value.set_etime(etime);
+                        }
+                        let data_key = HashesDataKey::new(key, meta.version(), field);
+                        batch.put_cf(&data_cf, data_key.encode()?, data_value.encode());
+                    }
+                    new_meta.set_inline(None);
+                    record_spill(DataType::Hash);
+                }
+            }
+            self.stage_meta_update(&mut batch, &meta_key, &new_meta.encode())?;
+        } else {
+            let mut found = Vec::with_capacity(fields.len());
+            for field in fields {
+                let data_key = HashesDataKey::new(key, meta.version(), field).encode()?;
+                let data_value = db
+                    .get_cf_opt(&data_cf, &data_key, &self.read_options)
+                    .context(RocksSnafu)?
+                    .map(ParsedHashesDataValue::new)
+                    .transpose()?
+                    .filter(|value| !value.is_field_stale());
+                values.push(data_value.as_ref().map(|v| v.user_value_slice().to_vec()));
+                found.push((data_key, data_value));
+            }
+
+            let mut seen = HashSet::with_capacity(found.len());
+            let mut deleted = 0;
+            for (data_key, data_value) in found {
+                let Some(mut data_value) = data_value else {
+                    continue;
+                };
+                if !seen.insert(data_key.clone()) {
+                    continue;
+                }
+                match update {
+                    FieldUpdate::Delete => {
+                        batch.delete_cf(&data_cf, &data_key);
+                        deleted += 1;
+                    }
+                    FieldUpdate::Expire(FieldExpiry::Keep) => {}
+                    FieldUpdate::Expire(FieldExpiry::Persist) => {
+                        if data_value.etime() != 0 {
+                            data_value.set_etime(0);
+                            batch.put_cf(&data_cf, &data_key, data_value.encoded());
+                        }
+                    }
+                    FieldUpdate::Expire(FieldExpiry::At(etime)) => {
+                        data_value.set_etime(etime);
+                        batch.put_cf(&data_cf, &data_key, data_value.encoded());
+                    }
+                }
+            }
+
+            if deleted > 0 {
+                let mut new_meta = meta.to_meta_value();
+                new_meta.set_count(meta.count().saturating_sub(deleted));
+                self.stage_meta_update(&mut batch, &meta_key, &new_meta.encode())?;
+            }
+        }
+
+        if !batch.is_empty() {
+            db.write_opt(batch, &self.write_options)
+                .context(RocksSnafu)?;
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base_meta_value_format::BaseMetaValue, clock::MockClock, error::Error,
+        inline_collection_format::InlineCollection, with_redis, StorageOptions, ValueFormat,
+    };
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const FIELDS: [(&[u8], &[u8]); 3] = [(b"f1", b"v1"), (b"f2", b"v2"), (b"f3", b"v3")];
+
+    fn put_hash(redis: &Redis, key: &[u8], inline: bool) {
+        let db = redis.db.as_ref().unwrap();
+        let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+        let data_cf = redis
+            .get_cf_handle(ColumnFamilyIndex::HashesDataCF)
+            .unwrap();
+
+        let mut meta = BaseMetaValue::new(FIELDS.len() as u64);
+        meta.inner.data_type = DataType::Hash;
+        let version = meta.update_version();
+        if inline {
+            let mut collection = InlineCollection::new();
+            for (field, value) in FIELDS {
+                collection.insert(field, value);
+            }
+            meta.set_inline(Some(&collection));
+        } else {
+            for (field, value) in FIELDS {
+                let data_key = HashesDataKey::new(key, version, field).encode().unwrap();
+                let data_value = HashesDataValue::new(value.to_vec());
+                db.put_cf(&data_cf, data_key, data_value.encode()).unwrap();
+            }
+        }
+        let meta_key = redis.base_key(key).encode().unwrap();
+        db.put_cf(&meta_cf, meta_key, ValueFormat::encode(&meta))
+            .unwrap();
+    }
+
+    fn get_meta(redis: &Redis, key: &[u8]) -> Option<ParsedBaseMetaValue> {
+        let db = redis.db.as_ref().unwrap();
+        let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+        let meta_key = redis.base_key(key).encode().unwrap();
+        db.get_cf(&meta_cf, meta_key)
+            .unwrap()
+            .map(|value| ParsedBaseMetaValue::new(value).unwrap())
+    }
+
+    fn get_field(redis: &Redis, key: &[u8], field: &[u8]) -> Option<ParsedHashesDataValue> {
+        let db = redis.db.as_ref().unwrap();
+        let data_cf = redis
+            .get_cf_handle(ColumnFamilyIndex::HashesDataCF)
+            .unwrap();
+        let version = get_meta(redis, key)?.version();
+        let data_key = HashesDataKey::new(key, version, field).encode().unwrap();
+        db.get_cf(&data_cf, data_key)
+            .unwrap()
+            .map(|value| ParsedHashesDataValue::new(value).unwrap())
+    }
+
+    #[test]
+    fn test_hgetdel() {
+        with_redis(StorageOptions::default(), |redis| {
+            for inline in [false, true] {
+                put_hash(redis, b"h", inline);
+
+                let values = redis.hgetdel(b"h", &[b"f1", b"nope", b"f1"]).unwrap();
+                assert_eq!(
+                    values,
+                    vec![Some(b"v1".to_vec()), None, Some(b"v1".to_vec())]
+                );
+                assert_eq!(get_meta(redis, b"h").unwrap().count(), 2);
+                assert!(get_field(redis, b"h", b"f1").is_none());
+
+                let values = redis.hgetdel(b"h", &[b"f2", b"f3"]).unwrap();
+                assert_eq!(values, vec![Some(b"v2".to_vec()), Some(b"v3".to_vec())]);
+                assert!(get_meta(redis, b"h").is_none());
+
+                let values = redis.hgetdel(b"h", &[b"f1"]).unwrap();
+                assert_eq!(values, vec![None]);
+            }
+        });
+    }
+
+    #[test]
+    fn test_hgetex_sets_and_clears_field_etime() {
+        with_redis(StorageOptions::default(), |redis| {
+            put_hash(redis, b"h", false);
+            let clock = Arc::new(MockClock::new(1_700_000_000_000_000));
+            clock::with_clock(clock.clone(), || {
+                let etime = clock::now_micros() + 60_000_000;
+                let values = redis
+                    .hgetex(b"h", &[b"f1", b"f2"], FieldExpiry::At(etime))
+                    .unwrap();
+                assert_eq!(values, vec![Some(b"v1".to_vec()), Some(b"v2".to_vec())]);
+                assert_eq!(get_field(redis, b"h", b"f1").unwrap().etime(), etime);
+
+                let values = redis.hgetex(b"h", &[b"f2"], FieldExpiry::Persist).unwrap();
+                assert_eq!(values, vec![Some(b"v2".to_vec())]);
+                assert_eq!(get_field(redis, b"h", b"f2").unwrap().etime(), 0);
+
+                clock.advance(Duration::from_secs(120));
+                let values = redis
+                    .hgetex(b"h", &[b"f1", b"f2"], FieldExpiry::Keep)
+                    .unwrap();
+                assert_eq!(values, vec![None, Some(b"v2".to_vec())]);
+
+                // an expiration time in the past deletes the fields
+                let values = redis.hgetex(b"h", &[b"f3"], FieldExpiry::At(1)).unwrap();
+                assert_eq!(values, vec![Some(b"v3".to_vec())]);
+                assert!(get_field(redis, b"h", b"f3").is_none());
+            });
+        });
+    }
+
+    #[test]
+    fn test_hgetex_moves_inline_fields_to_data_keys() {
+        with_redis(StorageOptions::default(), |redis| {
+            put_hash(redis, b"h", true);
+            let values = redis.hgetex(b"h", &[b"f1"], FieldExpiry::Persist).unwrap();
+            assert_eq!(values, vec![Some(b"v1".to_vec())]);
+            assert!(get_meta(redis, b"h").unwrap().is_inline());
+
+            let etime = clock::now_micros() + 60_000_000;
+            redis
+                .hgetex(b"h", &[b"f1"], FieldExpiry::At(etime))
+                .unwrap();
+            let meta = get_meta(redis, b"h").unwrap();
+            assert!(!meta.is_inline());
+            assert_eq!(meta.count(), 3);
+            assert_eq!(get_field(redis, b"h", b"f1").unwrap().etime(), etime);
+            let f2 = get_field(redis, b"h", b"f2").unwrap();
+            assert_eq!(f2.user_value_slice(), b"v2");
+            assert_eq!(f2.etime(), 0);
+        });
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        with_redis(StorageOptions::default(), |redis| {
+            redis.set(b"s", b"value").unwrap();
+            assert!(matches!(
+                redis.hgetdel(b"s", &[b"f"]),
+                Err(Error::WrongType { .. })
+            ));
+            assert!(matches!(
+                redis.hgetex(b"s", &[b"f"], FieldExpiry::Keep),
+                Err(Error::WrongType { .. })
+            ));
+        });
+    }
+}
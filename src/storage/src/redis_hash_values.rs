@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Hash commands on field values: HSET and HGET.
+//!
+//! A hash within `InlineLimits` keeps its fields in its meta value. The
+//! first write that takes it past the limits, by field count or by the
+//! length of a field or value, moves every field to data keys in the same
+//! batch. Shrinking does not move it back.
+
+use std::collections::HashMap;
+
+use kstd::lock_mgr::ScopeRecordLock;
+use rocksdb::WriteBatch;
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    base_meta_value_format::BaseMetaValue,
+    error::{OptionNoneSnafu, RocksSnafu},
+    hashes_data_key_format::HashesDataKey,
+    hashes_data_value_format::{HashesDataValue, ParsedHashesDataValue},
+    inline_collection_format::{record_spill, InlineCollection, InlineLimits},
+    ColumnFamilyIndex, DataType, Redis, Result,
+};
+
+impl Redis {
+    /// Sets the fields to their values, creating the hash when it does not
+    /// exist (HSET). The last value of a repeated field wins and a field
+    /// loses its expiration time. Returns the number of fields added
+    pub fn hash_set(&self, key: &[u8], fields: &[(&[u8], &[u8])]) -> Result<u64> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let data_cf = self
+            .get_cf_handle(ColumnFamilyIndex::HashesDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let meta_key = self.base_key(key).encode()?;
+        let (mut meta, collection) = match self.live_base_meta(key, DataType::Hash)? {
+            Some(meta) => (meta.to_meta_value(), meta.inline_collection()?),
+            None => {
+                let mut meta = BaseMetaValue::new(0);
+                meta.inner.data_type = DataType::Hash;
+                meta.update_version();
+                (meta, Some(InlineCollection::new()))
+            }
+        };
+        let version = meta.inner.version;
+
+        let mut batch = WriteBatch::default();
+        let added = match collection {
+            Some(mut collection) => {
+                // a new collection starts empty, a live inline one never is
+                let was_inline = !collection.is_empty();
+                let mut added = 0;
+                for (field, value) in fields {
+                    if collection.insert(field, value) {
+                        added += 1;
+                    }
+                }
+                if InlineLimits::from_options(&self.storage).allows(&collection) {
+                    meta.set_inline(Some(&collection));
+                } else {
+                    for (field, value) in collection.iter() {
+                        let data_key = HashesDataKey::new(key, version, field);
+                        let data_value = HashesDataValue::new(value.to_vec());
+                        batch.put_cf(&data_cf, data_key.encode()?, data_value.encode());
+                    }
+                    meta.set_inline(None);
+                    meta.set_count(collection.len() as u64);
+                    if was_inline {
+                        record_spill(DataType::Hash);
+                    }
+                }
+                added
+            }
+            None => {
+                let latest: HashMap<&[u8], &[u8]> = fields.iter().copied().collect();
+                let mut added = 0;
+                for (field, value) in latest {
+                    let data_key = HashesDataKey::new(key, version, field).encode()?;
+                    let exists = db
+                        .get_cf_opt(&data_cf, &data_key, &self.read_options)
+                        .context(RocksSnafu)?
+                        .map(ParsedHashesDataValue::new)
+                        .transpose()?
+                        .is_some_and(|value| !value.is_field_stale());
+                    if !exists {
+                        added += 1;
+                    }
+                    let data_value = HashesDataValue::new(value.to_vec());
+                    batch.put_cf(&data_cf, data_key, data_value.encode());
+                }
+                meta.set_count(meta.count() + added);
+                added
+            }
+        };
+        self.stage_meta_update(&mut batch, &meta_key, &meta.encode())?;
+        db.write_opt(batch, &self.write_options)
+            .context(RocksSnafu)?;
+        Ok(added)
+    }
+
+    /// The value of `field`, `None` when the hash or the field does not
+    /// exist (HGET)
+    pub fn hash_get(&self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(meta) = self.live_base_meta(key, DataType::Hash)? else {
+            return Ok(None);
+        };
+        if let Some(collection) = meta.inline_collection()? {
+            return Ok(collection.get(field).map(<[u8]>::to_vec));
+        }
+
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let data_cf = self
+            .get_cf_handle(ColumnFamilyIndex::HashesDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let data_key = HashesDataKey::new(key, meta.version(), field).encode()?;
+        Ok(db
+            .get_cf_opt(&data_cf, &data_key, &self.read_options)
+            .context(RocksSnafu)?
+            .map(ParsedHashesDataValue::new)
+            .transpose()?
+            .filter(|value| !value.is_field_stale())
+            .map(|value| value.user_value_slice().to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base_meta_value_format::ParsedBaseMetaValue, error::Error, inline_spills, with_redis,
+        StorageOptions,
+    };
+
+    // At most 3 fields of up to 4 bytes inline
+    fn small_inline_options() -> StorageOptions {
+        let mut options = StorageOptions::default();
+        options
+            .set_inline_collection_max_entries(3)
+            .set_inline_collection_max_entry_len(4);
+        options
+    }
+
+    fn meta(redis: &Redis, key: &[u8]) -> ParsedBaseMetaValue {
+        redis.live_base_meta(key, DataType::Hash).unwrap().unwrap()
+    }
+
+    fn assert_fields(redis: &Redis, key: &[u8], fields: &[(&[u8], &[u8])]) {
+        assert_eq!(meta(redis, key).count(), fields.len() as u64);
+        for (field, value) in fields {
+            assert_eq!(redis.hash_get(key, field).unwrap().as_deref(), Some(*value));
+        }
+    }
+
+    #[test]
+    fn test_hash_stays_inline_up_to_the_entry_limit() {
+        with_redis(small_inline_options(), |redis| {
+            assert_eq!(
+                redis
+                    .hash_set(b"h", &[(b"f1", b"v1"), (b"f2", b"v2")])
+                    .unwrap(),
+                2
+            );
+            assert_eq!(
+                redis
+                    .hash_set(b"h", &[(b"f3", b"v3"), (b"f1", b"x")])
+                    .unwrap(),
+                1
+            );
+            assert!(meta(redis, b"h").is_inline());
+            assert_fields(
+                redis,
+                b"h",
+                &[(b"f1", b"x"), (b"f2", b"v2"), (b"f3", b"v3")],
+            );
+            assert_eq!(redis.hash_get(b"h", b"f4").unwrap(), None);
+            assert_eq!(redis.hash_get(b"nope", b"f1").unwrap(), None);
+
+            assert_eq!(redis.object_encoding(b"h").unwrap(), Some("listpack"));
+
+            // the fourth field spills every field to data keys
+            let spills = inline_spills(DataType::Hash);
+            assert_eq!(redis.hash_set(b"h", &[(b"f4", b"v4")]).unwrap(), 1);
+            assert!(!meta(redis, b"h").is_inline());
+            assert_eq!(redis.object_encoding(b"h").unwrap(), Some("rocksdb-spread"));
+            // Other tests may spill hashes concurrently
+            assert!(inline_spills(DataType::Hash) > spills);
+            assert_fields(
+                redis,
+                b"h",
+                &[
+                    (b"f1", b"x"),
+                    (b"f2", b"v2"),
+                    (b"f3", b"v3"),
+                    (b"f4", b"v4"),
+                ],
+            );
+
+            // a spilled hash stays in data keys once small again
+            redis.hgetdel(b"h", &[b"f1", b"f2", b"f3"]).unwrap();
+            assert_eq!(
+                redis
+                    .hash_set(b"h", &[(b"f4", b"y"), (b"f4", b"z")])
+                    .unwrap(),
+                0
+            );
+            assert!(!meta(redis, b"h").is_inline());
+            assert_fields(redis, b"h", &[(b"f4", b"z")]);
+        });
+    }
+
+    #[test]
+    fn test_hash_spills_on_long_field_or_value() {
+        with_redis(small_inline_options(), |redis| {
+            redis.hash_set(b"v", &[(b"f", b"1234")]).unwrap();
+            assert!(meta(redis, b"v").is_inline());
+            redis.hash_set(b"v", &[(b"f", b"12345")]).unwrap();
+            assert!(!meta(redis, b"v").is_inline());
+            assert_fields(redis, b"v", &[(b"f", b"12345")]);
+
+            redis.hash_set(b"f", &[(b"1234", b"v")]).unwrap();
+            assert!(meta(redis, b"f").is_inline());
+            redis.hash_set(b"f", &[(b"12345", b"v")]).unwrap();
+            assert!(!meta(redis, b"f").is_inline());
+            assert_fields(redis, b"f", &[(b"1234", b"v"), (b"12345", b"v")]);
+
+            // a new hash too big to start inline
+            redis.hash_set(b"n", &[(b"f", b"12345")]).unwrap();
+            assert!(!meta(redis, b"n").is_inline());
+            assert_fields(redis, b"n", &[(b"f", b"12345")]);
+        });
+    }
+
+    #[test]
+    fn test_hash_with_inline_disabled() {
+        let mut options = StorageOptions::default();
+        options.set_inline_collection_max_entries(0);
+        with_redis(options, |redis| {
+            assert_eq!(redis.hash_set(b"h", &[(b"f", b"v")]).unwrap(), 1);
+            assert!(!meta(redis, b"h").is_inline());
+            assert_fields(redis, b"h", &[(b"f", b"v")]);
+        });
+    }
+
+    #[test]
+    fn test_hash_wrong_type() {
+        with_redis(StorageOptions::default(), |redis| {
+            redis.set(b"s", b"value").unwrap();
+            assert!(matches!(
+                redis.hash_set(b"s", &[(b"f", b"v")]),
+                Err(Error::WrongType { .. })
+            ));
+            assert!(matches!(
+                redis.hash_get(b"s", b"f"),
+                Err(Error::WrongType { .. })
+            ));
+        });
+    }
+}
@@ -0,0 +1,419 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Keyspace wide operations
+
+use kstd::cancel::CancelToken;
+use rocksdb::{IteratorMode, WriteBatch};
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    base_key_format::ParsedBaseKey,
+    base_meta_value_format::ParsedBaseMetaValue,
+    base_value_format::DATA_TYPE_STRINGS,
+    error::{OptionNoneSnafu, RocksSnafu, WrongTypeSnafu},
+    inline_collection_format::{ENCODING_INLINE, ENCODING_SPREAD},
+    list_meta_value_format::ParsedListsMetaValue,
+    perf_stats::ReadPerfStats,
+    streams_meta_value_format::ParsedStreamsMetaValue,
+    strings_value_format::ParsedStringsValue,
+    util::{check_abort, string_match},
+    ColumnFamilyIndex, DataType, Redis, Result,
+};
+
+/// Number of keys visited between two checks of the cancel token.
+const ABORT_CHECK_INTERVAL: usize = 256;
+
+impl Redis {
+    /// Returns the live keys matching the glob `pattern`, in key order.
+    /// Gives up with `Error::Aborted` once `token` is cancelled or expired.
+    pub fn scan_keys(&self, pattern: &[u8], token: &CancelToken) -> Result<Vec<Vec<u8>>> {
+        self.scan_meta(pattern, None, token)
+    }
+
+    /// Like `scan_keys`, keeping only the keys of type `data_type`. The type
+    /// byte leading every meta value is checked before anything is parsed,
+    /// the entries of other types are only counted in `stats`.
+    pub fn scan_keys_of_type(
+        &self,
+        pattern: &[u8],
+        data_type: DataType,
+        token: &CancelToken,
+        stats: &ReadPerfStats,
+    ) -> Result<Vec<Vec<u8>>> {
+        let mut skipped = [0u64; DATA_TYPE_STRINGS.len()];
+        let keys = self.scan_meta(pattern, Some((data_type, &mut skipped)), token);
+        stats.record_scan_type_skipped(&skipped);
+        keys
+    }
+
+    fn scan_meta(
+        &self,
+        pattern: &[u8],
+        mut type_filter: Option<(DataType, &mut [u64; DATA_TYPE_STRINGS.len()])>,
+        token: &CancelToken,
+    ) -> Result<Vec<Vec<u8>>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let mut keys = Vec::new();
+        for (i, item) in db.iterator_cf(&cf, IteratorMode::Start).enumerate() {
+            if i % ABORT_CHECK_INTERVAL == 0 {
+                check_abort(token)?;
+            }
+            let (key, value) = item.context(RocksSnafu)?;
+            if let Some((data_type, skipped)) = type_filter.as_mut() {
+                let Some(&type_byte) = value.first() else {
+                    continue;
+                };
+                if type_byte != *data_type as u8 {
+                    if let Some(count) = skipped.get_mut(type_byte as usize) {
+                        *count += 1;
+                    }
+                    continue;
+                }
+            }
+            if !is_live_meta_value(&value) {
+                continue;
+            }
+            let parsed_key = ParsedBaseKey::new(&key)?;
+            if string_match(pattern, parsed_key.key(), false) {
+                keys.push(parsed_key.key().to_vec());
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+impl Redis {
+    /// Returns the type of every key of `keys`, in the same order, `None` for
+    /// the keys that do not exist. The meta values are read with a single
+    /// batched MultiGet, a key repeated in `keys` is reported every time.
+    pub fn key_types(&self, keys: &[&[u8]]) -> Result<Vec<Option<DataType>>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let meta_keys = keys
+            .iter()
+            .map(|key| self.base_key(key).encode())
+            .collect::<Result<Vec<_>>>()?;
+        db.batched_multi_get_cf_opt(&cf, &meta_keys, false, &self.read_options)
+            .into_iter()
+            .map(|value| {
+                let value = value.context(RocksSnafu)?;
+                Ok(value
+                    .filter(|value| is_live_meta_value(value))
+                    .and_then(|value| DataType::try_from(value[0]).ok()))
+            })
+            .collect()
+    }
+
+    /// Returns how the value of `key` is stored, as OBJECT ENCODING reports
+    /// it, `None` when the key does not exist.
+    pub fn object_encoding(&self, key: &[u8]) -> Result<Option<&'static str>> {
+        let Some(value) = self.raw_meta_value(key)? else {
+            return Ok(None);
+        };
+        if !is_live_meta_value(&value) {
+            self.note_stale_read(key, &value);
+            return Ok(None);
+        }
+        let encoding = match DataType::try_from(value[0])? {
+            DataType::String => "raw",
+            DataType::Hash | DataType::Set | DataType::ZSet => {
+                match ParsedBaseMetaValue::new(&value[..])?.is_inline() {
+                    true => ENCODING_INLINE,
+                    false => ENCODING_SPREAD,
+                }
+            }
+            DataType::List => ENCODING_SPREAD,
+            DataType::Stream => "stream",
+            DataType::None | DataType::All => return Ok(None),
+        };
+        Ok(Some(encoding))
+    }
+}
+
+impl Redis {
+    /// Adds the write of `meta_value`, the updated meta value of the key
+    /// `meta_key`, to `batch`. A collection whose last element was removed is
+    /// deleted instead of being kept with a count of 0, so that it no longer
+    /// exists for TYPE, EXISTS and SCAN. Returns whether the key was deleted.
+    pub(crate) fn stage_meta_update(
+        &self,
+        batch: &mut WriteBatch,
+        meta_key: &[u8],
+        meta_value: &[u8],
+    ) -> Result<bool> {
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        if collection_count(meta_value) == Some(0) {
+            batch.delete_cf(&meta_cf, meta_key);
+            return Ok(true);
+        }
+        batch.put_cf(&meta_cf, meta_key, meta_value);
+        Ok(false)
+    }
+
+    /// The meta value of the hash, set or sorted set `key`, `None` when the
+    /// key does not exist. Fails for a key of another type than `data_type`.
+    pub(crate) fn live_base_meta(
+        &self,
+        key: &[u8],
+        data_type: DataType,
+    ) -> Result<Option<ParsedBaseMetaValue>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let meta_key = self.base_key(key).encode()?;
+        let Some(meta_value) = db
+            .get_cf_opt(&meta_cf, &meta_key, &self.read_options)
+            .context(RocksSnafu)?
+        else {
+            return Ok(None);
+        };
+        if !is_live_meta_value(&meta_value) {
+            self.note_stale_read(key, &meta_value);
+            return Ok(None);
+        }
+        if meta_value[0] != data_type as u8 {
+            return WrongTypeSnafu {
+                key: String::from_utf8_lossy(key).to_string(),
+            }
+            .fail();
+        }
+        ParsedBaseMetaValue::new(&meta_value[..]).map(Some)
+    }
+}
+
+/// The element count of the meta value of a hash, set, sorted set or list,
+/// `None` for the other types and for values that fail to parse.
+pub(crate) fn collection_count(value: &[u8]) -> Option<u64> {
+    let data_type = value.first().and_then(|t| DataType::try_from(*t).ok())?;
+    match data_type {
+        DataType::List => ParsedListsMetaValue::new(value).ok().map(|v| v.count()),
+        DataType::Hash | DataType::Set | DataType::ZSet => {
+            ParsedBaseMetaValue::new(value).ok().map(|v| v.count())
+        }
+        _ => None,
+    }
+}
+
+/// Whether a value of the meta column family describes a key that exists:
+/// not expired and, for collections, not empty.
+pub(crate) fn is_live_meta_value(value: &[u8]) -> bool {
+    let Some(data_type) = value.first().and_then(|t| DataType::try_from(*t).ok()) else {
+        return false;
+    };
+    match data_type {
+        DataType::String => ParsedStringsValue::new(value).is_ok_and(|v| !v.is_stale()),
+        DataType::List => ParsedListsMetaValue::new(value).is_ok_and(|v| v.is_valid()),
+        DataType::Hash | DataType::Set | DataType::ZSet => {
+            ParsedBaseMetaValue::new(value).is_ok_and(|v| v.is_valid())
+        }
+        DataType::Stream => ParsedStreamsMetaValue::new(value).is_ok_and(|v| v.is_valid()),
+        DataType::None | DataType::All => false,
+    }
+}
+
+/// Whether a value of the meta column family has passed its expiration
+/// time. Unlike `is_live_meta_value`, an empty collection is not expired.
+pub(crate) fn is_expired_meta_value(value: &[u8]) -> bool {
+    let Some(data_type) = value.first().and_then(|t| DataType::try_from(*t).ok()) else {
+        return false;
+    };
+    match data_type {
+        DataType::String => ParsedStringsValue::new(value).is_ok_and(|v| v.is_stale()),
+        DataType::List => ParsedListsMetaValue::new(value).is_ok_and(|v| v.is_stale()),
+        DataType::Hash | DataType::Set | DataType::ZSet => {
+            ParsedBaseMetaValue::new(value).is_ok_and(|v| v.is_stale())
+        }
+        DataType::Stream => ParsedStreamsMetaValue::new(value).is_ok_and(|v| v.is_stale()),
+        DataType::None | DataType::All => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base_meta_value_format::BaseMetaValue, list_meta_value_format::ListsMetaValue,
+        strings_value_format::StringValue, unique_test_db_path, BgTaskHandler, ListEnd,
+        StorageOptions, ValueFormat,
+    };
+    use kstd::lock_mgr::LockMgr;
+    use std::sync::Arc;
+
+    fn hash_meta_value(count: u64) -> Vec<u8> {
+        let mut meta = BaseMetaValue::new(count);
+        meta.inner.data_type = DataType::Hash;
+        ValueFormat::encode(&meta).to_vec()
+    }
+
+    #[test]
+    fn test_collection_count() {
+        assert_eq!(collection_count(&hash_meta_value(3)), Some(3));
+        assert_eq!(collection_count(&hash_meta_value(0)), Some(0));
+
+        let list = ListsMetaValue::new(0u64.to_le_bytes().to_vec());
+        assert_eq!(collection_count(&ValueFormat::encode(&list)), Some(0));
+
+        let string = StringValue::new("value");
+        assert_eq!(collection_count(&string.encode()), None);
+        assert_eq!(collection_count(b""), None);
+    }
+
+    #[test]
+    fn test_stage_meta_update_deletes_empty_collection() {
+        let test_db_path = unique_test_db_path();
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+        {
+            let db = redis.db.as_ref().unwrap();
+            let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+            let meta_key = redis.base_key(b"h").encode().unwrap();
+            let keys: [&[u8]; 1] = [b"h"];
+
+            let mut batch = WriteBatch::default();
+            assert!(!redis
+                .stage_meta_update(&mut batch, &meta_key, &hash_meta_value(1))
+                .unwrap());
+            db.write(batch).unwrap();
+            assert_eq!(redis.key_types(&keys).unwrap(), vec![Some(DataType::Hash)]);
+
+            let mut batch = WriteBatch::default();
+            assert!(redis
+                .stage_meta_update(&mut batch, &meta_key, &hash_meta_value(0))
+                .unwrap());
+            db.write(batch).unwrap();
+            assert_eq!(redis.key_types(&keys).unwrap(), vec![None]);
+            assert!(db.get_cf(&meta_cf, &meta_key).unwrap().is_none());
+        }
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+
+    #[test]
+    fn test_scan_keys_of_type() {
+        let test_db_path = unique_test_db_path();
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+        {
+            let db = redis.db.as_ref().unwrap();
+            let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+            let string = StringValue::new("value").encode();
+            for (key, value) in [
+                (&b"h1"[..], hash_meta_value(1)),
+                (b"h2", hash_meta_value(2)),
+                (b"s1", string.to_vec()),
+                (b"s2", string.to_vec()),
+                (b"s3", string.to_vec()),
+            ] {
+                let meta_key = redis.base_key(key).encode().unwrap();
+                db.put_cf(&meta_cf, &meta_key, value).unwrap();
+            }
+
+            let stats = ReadPerfStats::new();
+            let token = CancelToken::new();
+            let keys = redis
+                .scan_keys_of_type(b"*", DataType::Hash, &token, &stats)
+                .unwrap();
+            assert_eq!(keys, vec![b"h1".to_vec(), b"h2".to_vec()]);
+            assert_eq!(stats.scan_type_skipped(DataType::String), 3);
+            assert_eq!(stats.scan_type_skipped(DataType::Hash), 0);
+
+            let keys = redis
+                .scan_keys_of_type(b"s[12]", DataType::String, &token, &stats)
+                .unwrap();
+            assert_eq!(keys, vec![b"s1".to_vec(), b"s2".to_vec()]);
+            assert_eq!(stats.scan_type_skipped(DataType::Hash), 2);
+            assert_eq!(redis.scan_keys(b"*", &token).unwrap().len(), 5);
+        }
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+
+    #[test]
+    fn test_object_encoding() {
+        let test_db_path = unique_test_db_path();
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+        {
+            redis.set(b"s", b"value").unwrap();
+            redis.push(b"l", ListEnd::Left, &[b"a"]).unwrap();
+            assert_eq!(redis.object_encoding(b"s").unwrap(), Some("raw"));
+            assert_eq!(redis.object_encoding(b"l").unwrap(), Some("rocksdb-spread"));
+            assert_eq!(redis.object_encoding(b"missing").unwrap(), None);
+
+            // a hash written straight to data keys
+            let db = redis.db.as_ref().unwrap();
+            let meta_key = redis.base_key(b"h").encode().unwrap();
+            db.put(&meta_key, hash_meta_value(1)).unwrap();
+            assert_eq!(redis.object_encoding(b"h").unwrap(), Some("rocksdb-spread"));
+        }
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
@@ -0,0 +1,618 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! List commands for job queues: LPUSH, RPUSH, LPOP, RPOP, LMPOP, LMOVE
+//! (RPOPLPUSH) and LREM.
+//!
+//! A reliable queue claims a job with `lmove(queue, processing, Right,
+//! Left)`, which moves it in a single write so a crashed worker leaves it in
+//! `processing` instead of losing it, and acks it with
+//! `lrem(processing, 1, job)` once done.
+
+use kstd::lock_mgr::ScopeRecordLock;
+use rocksdb::{BoundColumnFamily, Direction, IteratorMode, ReadOptions, WriteBatch};
+use snafu::{OptionExt, ResultExt};
+use std::sync::Arc;
+
+use crate::{
+    base_data_value_format::{BaseDataValue, ParsedBaseDataValue},
+    error::{OptionNoneSnafu, RocksSnafu, WrongTypeSnafu},
+    list_meta_value_format::{ListsMetaValue, ParsedListsMetaValue},
+    lists_data_key_format::ListsDataKey,
+    redis_keys::is_live_meta_value,
+    ColumnFamilyIndex, DataType, Redis, Result, ValueFormat,
+};
+
+/// The end of a list elements are pushed to and popped from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    /// The head of the list
+    Left,
+    /// The tail of the list
+    Right,
+}
+
+impl ListEnd {
+    /// Keyspace event of a push at this end
+    pub(crate) fn push_event(self) -> &'static str {
+        match self {
+            ListEnd::Left => "lpush",
+            ListEnd::Right => "rpush",
+        }
+    }
+
+    /// Keyspace event of a pop at this end
+    pub(crate) fn pop_event(self) -> &'static str {
+        match self {
+            ListEnd::Left => "lpop",
+            ListEnd::Right => "rpop",
+        }
+    }
+}
+
+impl Redis {
+    /// Inserts `values` one after the other at `end` of the list `key`,
+    /// creating it when it does not exist (LPUSH, RPUSH). Returns the length
+    /// of the list
+    pub fn push(&self, key: &[u8], end: ListEnd, values: &[&[u8]]) -> Result<u64> {
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let meta_key = self.base_key(key).encode()?;
+        let mut meta = match self.live_list_meta(key)? {
+            Some(meta) => meta,
+            None => new_list_meta()?,
+        };
+        let mut batch = WriteBatch::default();
+        self.stage_push(&mut batch, key, &mut meta, end, values)?;
+        self.stage_meta_update(&mut batch, &meta_key, meta.encoded())?;
+        self.write_batch(batch)?;
+        Ok(meta.count())
+    }
+
+    /// Removes and returns up to `count` elements from `end` of the list
+    /// `key` (LPOP, RPOP). The list is deleted with its last element
+    pub fn pop(&self, key: &[u8], end: ListEnd, count: usize) -> Result<Vec<Vec<u8>>> {
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let Some(mut meta) = self.live_list_meta(key)? else {
+            return Ok(Vec::new());
+        };
+        let mut batch = WriteBatch::default();
+        let mut values = Vec::new();
+        while values.len() < count {
+            match self.stage_pop(&mut batch, key, &mut meta, end)? {
+                Some(value) => values.push(value),
+                None => break,
+            }
+        }
+        if !values.is_empty() {
+            let meta_key = self.base_key(key).encode()?;
+            self.stage_meta_update(&mut batch, &meta_key, meta.encoded())?;
+            self.write_batch(batch)?;
+        }
+        Ok(values)
+    }
+
+    /// Pops up to `count` elements from `end` of the first non-empty list of
+    /// `keys` (LMPOP). Returns that key with its elements, `None` when every
+    /// list is empty
+    pub fn lmpop(
+        &self,
+        keys: &[&[u8]],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<(Vec<u8>, Vec<Vec<u8>>)>> {
+        for key in keys {
+            let values = self.pop(key, end, count)?;
+            if !values.is_empty() {
+                return Ok(Some((key.to_vec(), values)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Pops the element at `from` of `source` and pushes it at `to` of
+    /// `destination` in a single write (LMOVE, RPOPLPUSH moves it from
+    /// `Right` to `Left`). Moving within one list rotates it. Returns the
+    /// element, `None` when `source` is empty
+    pub fn lmove(
+        &self,
+        source: &[u8],
+        destination: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Vec<u8>>> {
+        // Lock both keys in sorted order, so two opposite moves cannot
+        // deadlock
+        let mut lock_keys = vec![
+            String::from_utf8_lossy(source).to_string(),
+            String::from_utf8_lossy(destination).to_string(),
+        ];
+        lock_keys.sort_unstable();
+        lock_keys.dedup();
+        let _locks: Vec<ScopeRecordLock> = lock_keys
+            .iter()
+            .map(|key| ScopeRecordLock::new(self.lock_mgr.as_ref(), key))
+            .collect();
+
+        let Some(mut source_meta) = self.live_list_meta(source)? else {
+            return Ok(None);
+        };
+        let mut batch = WriteBatch::default();
+        let source_key = self.base_key(source).encode()?;
+
+        if source == destination {
+            let value = self
+                .stage_pop(&mut batch, source, &mut source_meta, from)?
+                .context(OptionNoneSnafu {
+                    message: "list element is missing".to_string(),
+                })?;
+            self.stage_push(&mut batch, source, &mut source_meta, to, &[&value])?;
+            self.stage_meta_update(&mut batch, &source_key, source_meta.encoded())?;
+            self.write_batch(batch)?;
+            return Ok(Some(value));
+        }
+
+        // A destination of another type fails the move before the pop
+        let mut destination_meta = match self.live_list_meta(destination)? {
+            Some(meta) => meta,
+            None => new_list_meta()?,
+        };
+        let value = self
+            .stage_pop(&mut batch, source, &mut source_meta, from)?
+            .context(OptionNoneSnafu {
+                message: "list element is missing".to_string(),
+            })?;
+        self.stage_push(
+            &mut batch,
+            destination,
+            &mut destination_meta,
+            to,
+            &[&value],
+        )?;
+        self.stage_meta_update(&mut batch, &source_key, source_meta.encoded())?;
+        let destination_key = self.base_key(destination).encode()?;
+        self.stage_meta_update(&mut batch, &destination_key, destination_meta.encoded())?;
+        self.write_batch(batch)?;
+        Ok(Some(value))
+    }
+
+    /// Removes the first `count` elements equal to `element`, the last
+    /// `-count` of them when `count` is negative, or all of them when it is
+    /// zero (LREM). The elements after the first removed one move up to
+    /// close the gap. Returns the number of removed elements
+    pub fn lrem(&self, key: &[u8], count: i64, element: &[u8]) -> Result<u64> {
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let Some(mut meta) = self.live_list_meta(key)? else {
+            return Ok(0);
+        };
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let data_cf = self.lists_data_cf()?;
+
+        let reserve1 = self.key_reserve1(key);
+        let data_key =
+            |index| ListsDataKey::with_reserves(key, meta.version(), index, reserve1, [0; 16]);
+        let start = data_key(meta.left_index() + 1).encode_seek_key()?;
+        let end = data_key(meta.right_index()).encode_seek_key()?;
+        let mut read_options = ReadOptions::default();
+        read_options.set_iterate_upper_bound(end);
+        let iter = db.iterator_cf_opt(
+            &data_cf,
+            read_options,
+            IteratorMode::From(&start, Direction::Forward),
+        );
+        let mut elements = Vec::new();
+        for item in iter {
+            let (_, value) = item.context(RocksSnafu)?;
+            let matches = ParsedBaseDataValue::new(&value[..])?.user_value_slice() == element;
+            elements.push((value, matches));
+        }
+
+        let limit = match count {
+            0 => usize::MAX,
+            count => usize::try_from(count.unsigned_abs()).unwrap_or(usize::MAX),
+        };
+        let mut removed = vec![false; elements.len()];
+        let matching = elements
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, matches))| *matches)
+            .map(|(pos, _)| pos);
+        if count < 0 {
+            matching
+                .rev()
+                .take(limit)
+                .for_each(|pos| removed[pos] = true);
+        } else {
+            matching.take(limit).for_each(|pos| removed[pos] = true);
+        }
+        let Some(first) = removed.iter().position(|removed| *removed) else {
+            return Ok(0);
+        };
+
+        let mut batch = WriteBatch::default();
+        let mut next = first;
+        for (pos, (value, _)) in elements.iter().enumerate().skip(first) {
+            if !removed[pos] {
+                let index = meta.left_index() + 1 + next as u64;
+                batch.put_cf(&data_cf, data_key(index).encode()?, value);
+                next += 1;
+            }
+        }
+        for pos in next..elements.len() {
+            let index = meta.left_index() + 1 + pos as u64;
+            batch.delete_cf(&data_cf, data_key(index).encode()?);
+        }
+        let removed_count = (elements.len() - next) as u64;
+        let right_index = meta.left_index() + 1 + next as u64;
+        meta.set_right_index(right_index);
+        meta.set_count(next as u64);
+        let meta_key = self.base_key(key).encode()?;
+        self.stage_meta_update(&mut batch, &meta_key, meta.encoded())?;
+        self.write_batch(batch)?;
+        Ok(removed_count)
+    }
+
+    // The meta value of the list key, None when key does not exist or is
+    // expired, and a WRONGTYPE error when it holds another type
+    fn live_list_meta(&self, key: &[u8]) -> Result<Option<ParsedListsMetaValue>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let meta_key = self.base_key(key).encode()?;
+        let Some(meta_value) = db
+            .get_cf_opt(&meta_cf, &meta_key, &self.read_options)
+            .context(RocksSnafu)?
+        else {
+            return Ok(None);
+        };
+        if !is_live_meta_value(&meta_value) {
+            self.note_stale_read(key, &meta_value);
+            return Ok(None);
+        }
+        if meta_value[0] != DataType::List as u8 {
+            return WrongTypeSnafu {
+                key: String::from_utf8_lossy(key).to_string(),
+            }
+            .fail();
+        }
+        ParsedListsMetaValue::new(meta_value).map(Some)
+    }
+
+    // Stages the data keys of values pushed at end, making room for them
+    // first, and updates the indexes and count of meta. Fails before staging
+    // anything when a value or the grown list is over its limit
+    fn stage_push(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        meta: &mut ParsedListsMetaValue,
+        end: ListEnd,
+        values: &[&[u8]],
+    ) -> Result<()> {
+        for value in values {
+            self.storage.check_member_size(value.len())?;
+        }
+        let pushed = values.len() as u64;
+        self.storage
+            .check_collection_len(meta.count().saturating_add(pushed))?;
+
+        let data_cf = self.lists_data_cf()?;
+        match end {
+            ListEnd::Left => self.stage_list_push_room(batch, key, meta, pushed, 0)?,
+            ListEnd::Right => self.stage_list_push_room(batch, key, meta, 0, pushed)?,
+        };
+
+        let reserve1 = self.key_reserve1(key);
+        for value in values {
+            let index = match end {
+                ListEnd::Left => meta.left_index(),
+                ListEnd::Right => meta.right_index(),
+            };
+            let data_key =
+                ListsDataKey::with_reserves(key, meta.version(), index, reserve1, [0; 16]);
+            batch.put_cf(
+                &data_cf,
+                data_key.encode()?,
+                BaseDataValue::new(value.to_vec()).encode(),
+            );
+            match end {
+                ListEnd::Left => meta.modify_left_index(1),
+                ListEnd::Right => meta.modify_right_index(1),
+            }
+        }
+        meta.modify_count(pushed as i64)?;
+        Ok(())
+    }
+
+    // Stages the delete of the element at end and updates the indexes and
+    // count of meta. Returns the element, None when the list is empty
+    fn stage_pop(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        meta: &mut ParsedListsMetaValue,
+        end: ListEnd,
+    ) -> Result<Option<Vec<u8>>> {
+        if meta.count() == 0 {
+            return Ok(None);
+        }
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let data_cf = self.lists_data_cf()?;
+
+        let index = match end {
+            ListEnd::Left => meta.left_index() + 1,
+            ListEnd::Right => meta.right_index() - 1,
+        };
+        let data_key = ListsDataKey::with_reserves(
+            key,
+            meta.version(),
+            index,
+            self.key_reserve1(key),
+            [0; 16],
+        )
+        .encode()?;
+        let value = db
+            .get_cf_opt(&data_cf, &data_key, &self.read_options)
+            .context(RocksSnafu)?
+            .context(OptionNoneSnafu {
+                message: "list element is missing".to_string(),
+            })?;
+        let value = ParsedBaseDataValue::new(value)?.user_value_slice().to_vec();
+
+        batch.delete_cf(&data_cf, &data_key);
+        match end {
+            ListEnd::Left => meta.set_left_index(index),
+            ListEnd::Right => meta.set_right_index(index),
+        }
+        meta.modify_count(-1)?;
+        Ok(Some(value))
+    }
+
+    fn lists_data_cf(&self) -> Result<Arc<BoundColumnFamily<'_>>> {
+        self.get_cf_handle(ColumnFamilyIndex::ListsDataCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        db.write_opt(batch, &self.write_options).context(RocksSnafu)
+    }
+}
+
+// The meta value of a new, empty list with a fresh version
+fn new_list_meta() -> Result<ParsedListsMetaValue> {
+    let mut meta = ParsedListsMetaValue::new(ValueFormat::encode(&ListsMetaValue::new(
+        0u64.to_le_bytes().to_vec(),
+    )))?;
+    meta.update_version();
+    Ok(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, with_redis, StorageOptions};
+
+    fn range(redis: &Redis, key: &[u8]) -> Vec<Vec<u8>> {
+        let Some(meta) = redis.live_list_meta(key).unwrap() else {
+            return Vec::new();
+        };
+        let db = redis.db.as_ref().unwrap();
+        let data_cf = redis.lists_data_cf().unwrap();
+        (meta.left_index() + 1..meta.right_index())
+            .map(|index| {
+                let data_key = ListsDataKey::with_reserves(
+                    key,
+                    meta.version(),
+                    index,
+                    redis.key_reserve1(key),
+                    [0; 16],
+                );
+                let value = db.get_cf(&data_cf, data_key.encode().unwrap()).unwrap();
+                ParsedBaseDataValue::new(value.unwrap())
+                    .unwrap()
+                    .user_value_slice()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    fn list(values: &[&str]) -> Vec<Vec<u8>> {
+        values.iter().map(|v| v.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        with_redis(StorageOptions::default(), |redis| {
+            assert_eq!(redis.push(b"l", ListEnd::Left, &[b"b", b"a"]).unwrap(), 2);
+            assert_eq!(redis.push(b"l", ListEnd::Right, &[b"c", b"d"]).unwrap(), 4);
+            assert_eq!(range(redis, b"l"), list(&["a", "b", "c", "d"]));
+
+            assert_eq!(redis.pop(b"l", ListEnd::Right, 1).unwrap(), list(&["d"]));
+            assert_eq!(
+                redis.pop(b"l", ListEnd::Left, 2).unwrap(),
+                list(&["a", "b"])
+            );
+            assert_eq!(redis.pop(b"l", ListEnd::Left, 5).unwrap(), list(&["c"]));
+            assert!(redis.live_list_meta(b"l").unwrap().is_none());
+            assert!(redis.pop(b"l", ListEnd::Left, 1).unwrap().is_empty());
+
+            redis.set(b"s", b"v").unwrap();
+            let err = redis.push(b"s", ListEnd::Left, &[b"a"]).unwrap_err();
+            assert!(matches!(err, Error::WrongType { .. }));
+        });
+    }
+
+    #[test]
+    fn test_lmpop() {
+        with_redis(StorageOptions::default(), |redis| {
+            assert!(redis
+                .lmpop(&[b"q1", b"q2"], ListEnd::Left, 2)
+                .unwrap()
+                .is_none());
+            redis
+                .push(b"q2", ListEnd::Right, &[b"a", b"b", b"c"])
+                .unwrap();
+            let (key, values) = redis
+                .lmpop(&[b"q1", b"q2"], ListEnd::Right, 2)
+                .unwrap()
+                .unwrap();
+            assert_eq!(key, b"q2");
+            assert_eq!(values, list(&["c", "b"]));
+        });
+    }
+
+    #[test]
+    fn test_lmove_claims_and_lrem_acks() {
+        with_redis(StorageOptions::default(), |redis| {
+            redis
+                .push(b"queue", ListEnd::Left, &[b"job1", b"job2", b"job3"])
+                .unwrap();
+
+            // RPOPLPUSH claims the oldest job
+            let job = redis
+                .lmove(b"queue", b"processing", ListEnd::Right, ListEnd::Left)
+                .unwrap();
+            assert_eq!(job.as_deref(), Some(&b"job1"[..]));
+            redis
+                .lmove(b"queue", b"processing", ListEnd::Right, ListEnd::Left)
+                .unwrap();
+            assert_eq!(range(redis, b"queue"), list(&["job3"]));
+            assert_eq!(range(redis, b"processing"), list(&["job2", "job1"]));
+
+            assert_eq!(redis.lrem(b"processing", 1, b"job1").unwrap(), 1);
+            assert_eq!(redis.lrem(b"processing", 1, b"job1").unwrap(), 0);
+            assert_eq!(range(redis, b"processing"), list(&["job2"]));
+
+            // The last job empties the queue
+            redis
+                .lmove(b"queue", b"processing", ListEnd::Right, ListEnd::Left)
+                .unwrap();
+            assert!(redis.live_list_meta(b"queue").unwrap().is_none());
+            assert!(redis
+                .lmove(b"queue", b"processing", ListEnd::Right, ListEnd::Left)
+                .unwrap()
+                .is_none());
+
+            // A destination of another type leaves the source untouched
+            redis.set(b"s", b"v").unwrap();
+            let err = redis
+                .lmove(b"processing", b"s", ListEnd::Right, ListEnd::Left)
+                .unwrap_err();
+            assert!(matches!(err, Error::WrongType { .. }));
+            assert_eq!(range(redis, b"processing"), list(&["job3", "job2"]));
+        });
+    }
+
+    #[test]
+    fn test_lmove_rotates_one_list() {
+        with_redis(StorageOptions::default(), |redis| {
+            redis
+                .push(b"l", ListEnd::Right, &[b"a", b"b", b"c"])
+                .unwrap();
+            let value = redis
+                .lmove(b"l", b"l", ListEnd::Left, ListEnd::Right)
+                .unwrap();
+            assert_eq!(value.as_deref(), Some(&b"a"[..]));
+            assert_eq!(range(redis, b"l"), list(&["b", "c", "a"]));
+
+            redis
+                .lmove(b"l", b"l", ListEnd::Left, ListEnd::Left)
+                .unwrap();
+            assert_eq!(range(redis, b"l"), list(&["b", "c", "a"]));
+        });
+    }
+
+    #[test]
+    fn test_push_limits() {
+        let mut options = StorageOptions::default();
+        options.set_max_member_size(4).set_max_collection_len(3);
+        with_redis(options, |redis| {
+            let err = redis.push(b"l", ListEnd::Left, &[b"toolong"]).unwrap_err();
+            assert!(matches!(err, Error::ValueTooLarge { .. }));
+            assert!(redis.live_list_meta(b"l").unwrap().is_none());
+
+            redis.push(b"l", ListEnd::Right, &[b"a", b"b"]).unwrap();
+            let err = redis.push(b"l", ListEnd::Right, &[b"c", b"d"]).unwrap_err();
+            assert!(matches!(err, Error::ValueTooLarge { .. }));
+            assert_eq!(range(redis, b"l"), list(&["a", "b"]));
+            assert_eq!(redis.push(b"l", ListEnd::Right, &[b"c"]).unwrap(), 3);
+
+            // A move into a full list leaves both lists untouched
+            redis.push(b"src", ListEnd::Left, &[b"x"]).unwrap();
+            let err = redis
+                .lmove(b"src", b"l", ListEnd::Right, ListEnd::Left)
+                .unwrap_err();
+            assert!(matches!(err, Error::ValueTooLarge { .. }));
+            assert_eq!(range(redis, b"src"), list(&["x"]));
+            assert_eq!(range(redis, b"l"), list(&["a", "b", "c"]));
+
+            // Rotating a full list does not grow it
+            redis
+                .lmove(b"l", b"l", ListEnd::Left, ListEnd::Right)
+                .unwrap();
+            assert_eq!(range(redis, b"l"), list(&["b", "c", "a"]));
+        });
+    }
+
+    #[test]
+    fn test_lrem() {
+        with_redis(StorageOptions::default(), |redis| {
+            let values: [&[u8]; 6] = [b"x", b"a", b"x", b"b", b"x", b"c"];
+            for (count, expected) in [
+                (1, list(&["a", "x", "b", "x", "c"])),
+                (-2, list(&["x", "a", "b", "c"])),
+                (0, list(&["a", "b", "c"])),
+            ] {
+                redis.push(b"l", ListEnd::Right, &values).unwrap();
+                let removed = redis.lrem(b"l", count, b"x").unwrap();
+                assert_eq!(removed, 6 - expected.len() as u64);
+                assert_eq!(range(redis, b"l"), expected);
+                assert_eq!(
+                    redis.live_list_meta(b"l").unwrap().unwrap().count(),
+                    expected.len() as u64
+                );
+                redis.pop(b"l", ListEnd::Left, 6).unwrap();
+            }
+
+            redis.push(b"l", ListEnd::Right, &[b"x", b"x"]).unwrap();
+            assert_eq!(redis.lrem(b"l", 0, b"x").unwrap(), 2);
+            assert!(redis.live_list_meta(b"l").unwrap().is_none());
+            assert_eq!(redis.lrem(b"l", 0, b"x").unwrap(), 0);
+        });
+    }
+}
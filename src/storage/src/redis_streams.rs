@@ -0,0 +1,352 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Consumer group commands over the pending entries of stream_group_cf
+
+use kstd::lock_mgr::ScopeRecordLock;
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    clock,
+    error::{NoGroupSnafu, OptionNoneSnafu, RocksSnafu, WrongTypeSnafu},
+    key_scope::KeyScope,
+    redis_keys::is_live_meta_value,
+    streams_data_key_format::StreamId,
+    streams_group_format::{ParsedStreamsGroupKey, StreamsGroupKey, StreamsPendingValue},
+    streams_meta_value_format::ParsedStreamsMetaValue,
+    ColumnFamilyIndex, DataType, Redis, Result,
+};
+
+/// How many pending entries XAUTOCLAIM looks at for each entry it may
+/// claim, as in Redis
+const AUTOCLAIM_ATTEMPTS_FACTOR: usize = 10;
+
+/// The arguments of XAUTOCLAIM after the key, group and consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoClaimOptions {
+    /// Only entries delivered at least this long ago are claimed
+    pub min_idle_ms: u64,
+    /// The pending entry to start from, the cursor of the previous call
+    pub start: StreamId,
+    /// The most entries claimed by one call
+    pub count: usize,
+    /// Leaves the delivery count unchanged (JUSTID)
+    pub justid: bool,
+}
+
+impl Default for AutoClaimOptions {
+    fn default() -> Self {
+        Self {
+            min_idle_ms: 0,
+            start: StreamId::MIN,
+            count: 100,
+            justid: false,
+        }
+    }
+}
+
+/// What XAUTOCLAIM did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AutoClaim {
+    /// Where the next call starts, `StreamId::MIN` once the scan reached the
+    /// end of the pending entries
+    pub next_cursor: StreamId,
+    /// The entries now owned by the consumer, in ID order
+    pub claimed: Vec<StreamId>,
+}
+
+impl Redis {
+    /// Moves the pending entries of a group idle for at least
+    /// `min_idle_ms` to consumer (XAUTOCLAIM). Looks at no more than
+    /// `AUTOCLAIM_ATTEMPTS_FACTOR * count` entries from `start`, so a long
+    /// pending list is reclaimed over several calls following
+    /// `AutoClaim::next_cursor`. Every claimed entry is delivered again
+    /// now, in one batch under the record lock of the key
+    pub fn xautoclaim(
+        &self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+        options: &AutoClaimOptions,
+    ) -> Result<AutoClaim> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let group_cf = self
+            .get_cf_handle(ColumnFamilyIndex::StreamsGroupCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let version = self.stream_group_version(key, group)?;
+        let now_ms = clock::now_micros() / 1000;
+        let scope =
+            KeyScope::prefix(StreamsGroupKey::group(key, version, group).encode_seek_key()?);
+        let start =
+            StreamsGroupKey::pending(key, version, group, options.start).encode_seek_key()?;
+        let iter = db.iterator_cf_opt(
+            &group_cf,
+            scope.read_options(),
+            IteratorMode::From(&start, Direction::Forward),
+        );
+
+        let mut attempts = options.count.saturating_mul(AUTOCLAIM_ATTEMPTS_FACTOR);
+        let mut batch = WriteBatch::default();
+        let mut result = AutoClaim::default();
+        for item in iter {
+            let (pending_key, value) = item.context(RocksSnafu)?;
+            // The group record sorts at the seek key of StreamId::MIN
+            let Some(id) = ParsedStreamsGroupKey::decode(&pending_key)?.id() else {
+                continue;
+            };
+            if attempts == 0 || result.claimed.len() == options.count {
+                result.next_cursor = id;
+                break;
+            }
+            attempts -= 1;
+
+            let mut pending = StreamsPendingValue::decode(&value)?;
+            if now_ms.saturating_sub(pending.delivery_time) < options.min_idle_ms {
+                continue;
+            }
+            if options.justid {
+                pending.consumer = consumer.to_vec();
+                pending.delivery_time = now_ms;
+            } else {
+                pending.redeliver(consumer, now_ms);
+            }
+            batch.put_cf(&group_cf, &pending_key, pending.encode());
+            result.claimed.push(id);
+        }
+
+        if !batch.is_empty() {
+            db.write_opt(batch, &self.write_options)
+                .context(RocksSnafu)?;
+        }
+        Ok(result)
+    }
+
+    /// The version of a live stream having the group, fails with
+    /// `Error::NoGroup` otherwise, like every consumer group command
+    fn stream_group_version(&self, key: &[u8], group: &[u8]) -> Result<u64> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let group_cf = self
+            .get_cf_handle(ColumnFamilyIndex::StreamsGroupCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let no_group = || NoGroupSnafu {
+            key: String::from_utf8_lossy(key).to_string(),
+            group: String::from_utf8_lossy(group).to_string(),
+        };
+        let meta_key = self.base_key(key).encode()?;
+        let meta_value = db
+            .get_cf_opt(&meta_cf, &meta_key, &self.read_options)
+            .context(RocksSnafu)?
+            .filter(|value| is_live_meta_value(value))
+            .with_context(no_group)?;
+        if meta_value[0] != DataType::Stream as u8 {
+            return WrongTypeSnafu {
+                key: String::from_utf8_lossy(key).to_string(),
+            }
+            .fail();
+        }
+        let version = ParsedStreamsMetaValue::new(&meta_value[..])?.version();
+
+        let group_key = StreamsGroupKey::group(key, version, group).encode()?;
+        db.get_cf_opt(&group_cf, &group_key, &self.read_options)
+            .context(RocksSnafu)?
+            .with_context(no_group)?;
+        Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::MockClock, error::Error, streams_group_format::StreamsGroupValue,
+        streams_meta_value_format::StreamsMetaValue, strings_value_format::StringValue, with_redis,
+        StorageOptions,
+    };
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const NOW_MS: u64 = 1_700_000_000_000;
+
+    // A stream with group "g" whose pending entries 1-0..=n-0 were delivered
+    // to "alice" at NOW_MS - 1000 * id, so entry i is idle for i seconds
+    fn put_group(redis: &Redis, key: &[u8], n: u64) {
+        let db = redis.db.as_ref().unwrap();
+        let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+        let group_cf = redis
+            .get_cf_handle(ColumnFamilyIndex::StreamsGroupCF)
+            .unwrap();
+
+        let mut meta = StreamsMetaValue::new(n.to_le_bytes().to_vec());
+        let version = meta.update_version();
+        meta.set_groups_count(1);
+        let meta_key = redis.base_key(key).encode().unwrap();
+        db.put_cf(&meta_cf, meta_key, meta.encode()).unwrap();
+
+        let group_key = StreamsGroupKey::group(key, version, b"g").encode().unwrap();
+        let group = StreamsGroupValue::new(StreamId::new(n, 0));
+        db.put_cf(&group_cf, group_key, group.encode()).unwrap();
+        for ms in 1..=n {
+            let id = StreamId::new(ms, 0);
+            let pending_key = StreamsGroupKey::pending(key, version, b"g", id)
+                .encode()
+                .unwrap();
+            let pending = StreamsPendingValue::new(b"alice", NOW_MS - 1000 * ms);
+            db.put_cf(&group_cf, pending_key, pending.encode()).unwrap();
+        }
+    }
+
+    fn pending(redis: &Redis, key: &[u8], ms: u64) -> StreamsPendingValue {
+        let db = redis.db.as_ref().unwrap();
+        let group_cf = redis
+            .get_cf_handle(ColumnFamilyIndex::StreamsGroupCF)
+            .unwrap();
+        let version = redis.stream_group_version(key, b"g").unwrap();
+        let pending_key = StreamsGroupKey::pending(key, version, b"g", StreamId::new(ms, 0))
+            .encode()
+            .unwrap();
+        let value = db.get_cf(&group_cf, pending_key).unwrap().unwrap();
+        StreamsPendingValue::decode(&value).unwrap()
+    }
+
+    fn ids(ms: &[u64]) -> Vec<StreamId> {
+        ms.iter().map(|ms| StreamId::new(*ms, 0)).collect()
+    }
+
+    #[test]
+    fn test_xautoclaim_claims_idle_entries() {
+        with_redis(StorageOptions::default(), |redis| {
+            put_group(redis, b"s", 6);
+            let clock = Arc::new(MockClock::new(NOW_MS * 1000));
+            clock::with_clock(clock.clone(), || {
+                // Entries 3-0 to 6-0 are idle for at least 3s
+                let options = AutoClaimOptions {
+                    min_idle_ms: 3000,
+                    count: 3,
+                    ..Default::default()
+                };
+                let claim = redis.xautoclaim(b"s", b"g", b"bob", &options).unwrap();
+                assert_eq!(claim.claimed, ids(&[3, 4, 5]));
+                assert_eq!(claim.next_cursor, StreamId::new(6, 0));
+
+                let claimed = pending(redis, b"s", 3);
+                assert_eq!(claimed.consumer, b"bob");
+                assert_eq!(claimed.delivery_time, NOW_MS);
+                assert_eq!(claimed.delivery_count, 2);
+                assert_eq!(pending(redis, b"s", 2).consumer, b"alice");
+
+                // The cursor resumes the scan, the end of the list resets it
+                let options = AutoClaimOptions {
+                    start: claim.next_cursor,
+                    justid: true,
+                    ..options
+                };
+                let claim = redis.xautoclaim(b"s", b"g", b"carol", &options).unwrap();
+                assert_eq!(claim.claimed, ids(&[6]));
+                assert_eq!(claim.next_cursor, StreamId::MIN);
+                assert_eq!(pending(redis, b"s", 6).consumer, b"carol");
+                assert_eq!(pending(redis, b"s", 6).delivery_count, 1);
+
+                // Claimed entries are idle again only after min_idle_ms
+                clock.advance(Duration::from_secs(2));
+                let claim = redis
+                    .xautoclaim(b"s", b"g", b"dave", &AutoClaimOptions::default())
+                    .unwrap();
+                assert_eq!(claim.claimed, ids(&[1, 2, 3, 4, 5, 6]));
+                let options = AutoClaimOptions {
+                    min_idle_ms: 1000,
+                    ..Default::default()
+                };
+                let claim = redis.xautoclaim(b"s", b"g", b"erin", &options).unwrap();
+                assert!(claim.claimed.is_empty());
+            });
+        });
+    }
+
+    #[test]
+    fn test_xautoclaim_bounds_the_scan() {
+        with_redis(StorageOptions::default(), |redis| {
+            put_group(redis, b"s", 30);
+            let clock = Arc::new(MockClock::new(NOW_MS * 1000));
+            clock::with_clock(clock, || {
+                // Only the entries from 20-0 are idle enough and COUNT 1 looks
+                // at 10 entries
+                let options = AutoClaimOptions {
+                    min_idle_ms: 20_000,
+                    count: 1,
+                    ..Default::default()
+                };
+                let claim = redis.xautoclaim(b"s", b"g", b"bob", &options).unwrap();
+                assert!(claim.claimed.is_empty());
+                assert_eq!(claim.next_cursor, StreamId::new(11, 0));
+
+                let options = AutoClaimOptions {
+                    start: StreamId::new(11, 0),
+                    ..options
+                };
+                let claim = redis.xautoclaim(b"s", b"g", b"bob", &options).unwrap();
+                assert_eq!(claim.claimed, ids(&[20]));
+                assert_eq!(claim.next_cursor, StreamId::new(21, 0));
+            });
+        });
+    }
+
+    #[test]
+    fn test_xautoclaim_without_group() {
+        with_redis(StorageOptions::default(), |redis| {
+            put_group(redis, b"s", 1);
+            let options = AutoClaimOptions::default();
+            let missing: [(&[u8], &[u8]); 2] = [(b"s", b"other"), (b"missing", b"g")];
+            for (key, group) in missing {
+                assert!(matches!(
+                    redis.xautoclaim(key, group, b"bob", &options),
+                    Err(Error::NoGroup { .. })
+                ));
+            }
+
+            let db = redis.db.as_ref().unwrap();
+            let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+            let meta_key = redis.base_key(b"str").encode().unwrap();
+            db.put_cf(&meta_cf, meta_key, StringValue::new("v").encode())
+                .unwrap();
+            assert!(matches!(
+                redis.xautoclaim(b"str", b"g", b"bob", &options),
+                Err(Error::WrongType { .. })
+            ));
+        });
+    }
+}
@@ -28,16 +28,37 @@
 
 // use crate::types::KeyValue;
 
+use kstd::buffer_pool;
 use kstd::lock_mgr::ScopeRecordLock;
 use snafu::{OptionExt, ResultExt};
 
 use crate::{
-    base_key_format::BaseKey,
-    error::{KeyNotFoundSnafu, OptionNoneSnafu, RocksSnafu},
+    bitmap_segment_format::SegmentedBitmapHeader,
+    error::{KeyNotFoundSnafu, OptionNoneSnafu, RocksSnafu, WrongTypeSnafu},
+    redis_keys::is_live_meta_value,
     strings_value_format::{ParsedStringsValue, StringValue},
-    ColumnFamilyIndex, Redis, Result,
+    ColumnFamilyIndex, DataType, Redis, Result, SetResult,
 };
 
+/// When SET writes the value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SetCondition {
+    #[default]
+    Always,
+    /// NX, only when the key does not exist
+    IfMissing,
+    /// XX, only when the key exists
+    IfExists,
+}
+
+/// The options of SET
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SetOptions {
+    pub condition: SetCondition,
+    /// GET, return the string the key held
+    pub get: bool,
+}
+
 impl Redis {
     // /// Append a value to the string stored at key
     // pub fn append(&self, key: &[u8], value: &[u8], ret: &mut i32) -> Result<()> {
@@ -103,16 +124,20 @@ impl Redis {
         let db = self.db.as_ref().context(OptionNoneSnafu {
             message: "db is not initialized".to_string(),
         })?;
-        let string_key = BaseKey::new(key);
+        let string_key = self.base_key(key);
 
         match db
             .get_opt(string_key.encode()?, &self.read_options)
             .context(RocksSnafu)?
         {
             Some(val) => {
-                let string_value = ParsedStringsValue::new(&val[..])?;
-                let user_value = string_value.user_value();
-                Ok(String::from_utf8_lossy(&user_value).to_string())
+                let string_value = ParsedStringsValue::new(val).map_err(|e| e.with_key(key))?;
+                if string_value.is_segmented() {
+                    let header = SegmentedBitmapHeader::decode(string_value.user_value_slice())?;
+                    let bitmap = self.segmented_bitmap_value(key, header)?;
+                    return Ok(String::from_utf8_lossy(&bitmap).to_string());
+                }
+                Ok(String::from_utf8_lossy(string_value.user_value_slice()).to_string())
             }
             None => KeyNotFoundSnafu {
                 key: String::from_utf8_lossy(key).to_string(),
@@ -163,29 +188,75 @@ impl Redis {
     // }
 
     /// Set key to hold the string value
-    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        let string_key = BaseKey::new(key);
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<SetResult> {
+        self.set_with(key, value, SetOptions::default())
+    }
+
+    /// SET with its NX, XX and GET options. A key of any type counts as
+    /// existing for NX and XX, GET fails on a key that is not a string
+    pub fn set_with(&self, key: &[u8], value: &[u8], options: SetOptions) -> Result<SetResult> {
+        self.storage.check_value_size(value.len())?;
+        let string_key = self.base_key(key).encode()?;
         let string_value = StringValue::new(value.to_owned());
 
         // Get lock for the key
         let key_str = String::from_utf8_lossy(key).to_string();
         let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
 
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
         let cf = self
             .get_cf_handle(ColumnFamilyIndex::MetaCF)
             .context(OptionNoneSnafu {
                 message: "cf is not initialized".to_string(),
             })?;
-        let mut batch = rocksdb::WriteBatch::default();
-        batch.put_cf(&cf, string_key.encode()?, string_value.encode());
 
-        let db = self.db.as_ref().context(OptionNoneSnafu {
-            message: "db is not initialized".to_string(),
-        })?;
+        let existing = db
+            .get_cf_opt(&cf, &string_key, &self.read_options)
+            .context(RocksSnafu)?
+            .filter(|value| is_live_meta_value(value));
+        let old_value = match &existing {
+            Some(existing) if options.get => Some(self.string_bytes(key, existing)?),
+            _ => None,
+        };
+        let did_set = match options.condition {
+            SetCondition::Always => true,
+            SetCondition::IfMissing => existing.is_none(),
+            SetCondition::IfExists => existing.is_some(),
+        };
+        if !did_set {
+            return Ok(SetResult::new(false, old_value));
+        }
+
+        let mut value_buf = buffer_pool::acquire(string_value.encoded_len());
+        string_value.encode_into(&mut value_buf);
+        let mut batch = rocksdb::WriteBatch::default();
+        // The segments of a bitmap would outlive the meta value pointing at them
+        if let Some(header) = existing.as_deref().and_then(segmented_header) {
+            self.stage_bitmap_segments_delete(&mut batch, key, &header)?;
+        }
+        batch.put_cf(&cf, string_key, &value_buf);
         db.write_opt(batch, &self.write_options)
             .context(RocksSnafu)?;
 
-        Ok(())
+        Ok(SetResult::new(true, old_value))
+    }
+
+    /// The bytes of the live string meta value `value` of key, for GET
+    fn string_bytes(&self, key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        if value[0] != DataType::String as u8 {
+            return WrongTypeSnafu {
+                key: String::from_utf8_lossy(key).to_string(),
+            }
+            .fail();
+        }
+        let value = ParsedStringsValue::new(value).map_err(|e| e.with_key(key))?;
+        if value.is_segmented() {
+            let header = SegmentedBitmapHeader::decode(value.user_value_slice())?;
+            return self.segmented_bitmap_value(key, header);
+        }
+        Ok(value.user_value_slice().to_vec())
     }
 
     // /// Set key to hold string value and expiration time
@@ -387,3 +458,15 @@ impl Redis {
     //     }
     // }
 }
+
+/// The bitmap header of a live meta value holding a segmented string
+fn segmented_header(value: &[u8]) -> Option<SegmentedBitmapHeader> {
+    if value[0] != DataType::String as u8 {
+        return None;
+    }
+    let value = ParsedStringsValue::new(value).ok()?;
+    if !value.is_segmented() {
+        return None;
+    }
+    SegmentedBitmapHeader::decode(value.user_value_slice()).ok()
+}
@@ -0,0 +1,359 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Sorted set commands on the score of members: ZADD, ZSCORE and ZREM.
+//!
+//! A sorted set within `InlineLimits` keeps its members and scores in its
+//! meta value, so these commands read and write a single key. It is spilled
+//! to member and score keys once it outgrows the limits and stays there.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use kstd::lock_mgr::ScopeRecordLock;
+use rocksdb::{BoundColumnFamily, WriteBatch};
+use snafu::{ensure, OptionExt, ResultExt};
+
+use crate::{
+    base_data_value_format::{BaseDataValue, ParsedBaseDataValue},
+    base_meta_value_format::BaseMetaValue,
+    error::{InvalidFormatSnafu, OptionNoneSnafu, RocksSnafu},
+    inline_collection_format::{record_spill, InlineCollection, InlineLimits},
+    zsets_data_key_format::{ZSetsMemberKey, ZSetsScoreKey},
+    ColumnFamilyIndex, DataType, Redis, Result, ValueFormat,
+};
+
+const SCORE_VALUE_LENGTH: usize = 8;
+
+impl Redis {
+    /// Adds the members with their scores, or updates the score of existing
+    /// ones (ZADD). The last score of a repeated member wins. Returns the
+    /// number of members added
+    pub fn zset_add(&self, key: &[u8], members: &[(f64, &[u8])]) -> Result<u64> {
+        ensure!(
+            members.iter().all(|(score, _)| !score.is_nan()),
+            InvalidFormatSnafu {
+                message: "score is not a number".to_string(),
+            }
+        );
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let meta_key = self.base_key(key).encode()?;
+        let (mut meta, collection) = match self.live_base_meta(key, DataType::ZSet)? {
+            Some(meta) => (meta.to_meta_value(), meta.inline_collection()?),
+            None => {
+                let mut meta = BaseMetaValue::new(0);
+                meta.inner.data_type = DataType::ZSet;
+                meta.update_version();
+                (meta, Some(InlineCollection::new()))
+            }
+        };
+
+        let version = meta.inner.version;
+        let mut batch = WriteBatch::default();
+        let added = match collection {
+            Some(mut collection) => {
+                // a new collection starts empty, a live inline one never is
+                let was_inline = !collection.is_empty();
+                let mut added = 0;
+                for (score, member) in members {
+                    if collection.insert(member, &score.to_le_bytes()) {
+                        added += 1;
+                    }
+                }
+                if InlineLimits::from_options(&self.storage).allows(&collection) {
+                    meta.set_inline(Some(&collection));
+                } else {
+                    self.stage_zset_spill(&mut batch, key, version, &collection)?;
+                    meta.set_inline(None);
+                    meta.set_count(collection.len() as u64);
+                    if was_inline {
+                        record_spill(DataType::ZSet);
+                    }
+                }
+                added
+            }
+            None => {
+                let latest: HashMap<&[u8], f64> = members
+                    .iter()
+                    .map(|(score, member)| (*member, *score))
+                    .collect();
+                let mut added = 0;
+                for (member, score) in latest {
+                    match self.zset_data_score(key, version, member)? {
+                        Some(old) if old == score => {}
+                        Some(old) => {
+                            self.stage_zset_delete(&mut batch, key, version, old, member)?;
+                            self.stage_zset_put(&mut batch, key, version, score, member)?;
+                        }
+                        None => {
+                            self.stage_zset_put(&mut batch, key, version, score, member)?;
+                            added += 1;
+                        }
+                    }
+                }
+                meta.set_count(meta.count() + added);
+                added
+            }
+        };
+        self.stage_meta_update(&mut batch, &meta_key, &meta.encode())?;
+        self.write_zset_batch(batch)?;
+        Ok(added)
+    }
+
+    /// The score of `member`, `None` when it is not in the sorted set (ZSCORE)
+    pub fn zset_score(&self, key: &[u8], member: &[u8]) -> Result<Option<f64>> {
+        let Some(meta) = self.live_base_meta(key, DataType::ZSet)? else {
+            return Ok(None);
+        };
+        match meta.inline_collection()? {
+            Some(collection) => collection.get(member).map(parse_score).transpose(),
+            None => self.zset_data_score(key, meta.version(), member),
+        }
+    }
+
+    /// Removes the members (ZREM), returns how many of them existed. The
+    /// sorted set is deleted with its last member
+    pub fn zset_rem(&self, key: &[u8], members: &[&[u8]]) -> Result<u64> {
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let Some(parsed) = self.live_base_meta(key, DataType::ZSet)? else {
+            return Ok(0);
+        };
+        let version = parsed.version();
+        let mut meta = parsed.to_meta_value();
+        let mut batch = WriteBatch::default();
+        let mut removed = 0;
+        match parsed.inline_collection()? {
+            Some(mut collection) => {
+                for member in members {
+                    if collection.remove(member) {
+                        removed += 1;
+                    }
+                }
+                meta.set_inline(Some(&collection));
+            }
+            None => {
+                let unique: HashSet<&[u8]> = members.iter().copied().collect();
+                for member in unique {
+                    if let Some(score) = self.zset_data_score(key, version, member)? {
+                        self.stage_zset_delete(&mut batch, key, version, score, member)?;
+                        removed += 1;
+                    }
+                }
+                meta.set_count(meta.count() - removed);
+            }
+        }
+        if removed > 0 {
+            let meta_key = self.base_key(key).encode()?;
+            self.stage_meta_update(&mut batch, &meta_key, &meta.encode())?;
+            self.write_zset_batch(batch)?;
+        }
+        Ok(removed)
+    }
+
+    // The score in the member key of `member`, for a sorted set in data keys
+    fn zset_data_score(&self, key: &[u8], version: u64, member: &[u8]) -> Result<Option<f64>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let member_cf = self.zset_cf(ColumnFamilyIndex::ZsetsDataCF)?;
+        let member_key = self.zset_member_key(key, version, member).encode()?;
+        db.get_cf_opt(&member_cf, &member_key, &self.read_options)
+            .context(RocksSnafu)?
+            .map(|value| parse_score(ParsedBaseDataValue::new(value)?.user_value_slice()))
+            .transpose()
+    }
+
+    // Moves the members of an inline sorted set to member and score keys
+    fn stage_zset_spill(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        version: u64,
+        collection: &InlineCollection,
+    ) -> Result<()> {
+        for (member, score) in collection.iter() {
+            self.stage_zset_put(batch, key, version, parse_score(score)?, member)?;
+        }
+        Ok(())
+    }
+
+    fn stage_zset_put(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        version: u64,
+        score: f64,
+        member: &[u8],
+    ) -> Result<()> {
+        let member_cf = self.zset_cf(ColumnFamilyIndex::ZsetsDataCF)?;
+        let score_cf = self.zset_cf(ColumnFamilyIndex::ZsetsScoreCF)?;
+        let member_key = self.zset_member_key(key, version, member).encode()?;
+        let score_key = self.zset_score_key(key, version, score, member).encode()?;
+        let member_value = BaseDataValue::new(score.to_le_bytes().to_vec());
+        batch.put_cf(&member_cf, member_key, ValueFormat::encode(&member_value));
+        batch.put_cf(
+            &score_cf,
+            score_key,
+            ValueFormat::encode(&BaseDataValue::new(Vec::new())),
+        );
+        Ok(())
+    }
+
+    fn stage_zset_delete(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        version: u64,
+        score: f64,
+        member: &[u8],
+    ) -> Result<()> {
+        let member_cf = self.zset_cf(ColumnFamilyIndex::ZsetsDataCF)?;
+        let score_cf = self.zset_cf(ColumnFamilyIndex::ZsetsScoreCF)?;
+        let member_key = self.zset_member_key(key, version, member).encode()?;
+        let score_key = self.zset_score_key(key, version, score, member).encode()?;
+        batch.delete_cf(&member_cf, member_key);
+        batch.delete_cf(&score_cf, score_key);
+        Ok(())
+    }
+
+    fn zset_member_key(&self, key: &[u8], version: u64, member: &[u8]) -> ZSetsMemberKey {
+        ZSetsMemberKey::with_reserves(key, version, member, self.key_reserve1(key), [0; 16])
+    }
+
+    fn zset_score_key(&self, key: &[u8], version: u64, score: f64, member: &[u8]) -> ZSetsScoreKey {
+        ZSetsScoreKey::with_reserves(key, version, score, member, self.key_reserve1(key), [0; 16])
+    }
+
+    fn zset_cf(&self, cf_index: ColumnFamilyIndex) -> Result<Arc<BoundColumnFamily<'_>>> {
+        self.get_cf_handle(cf_index).context(OptionNoneSnafu {
+            message: "cf is not initialized".to_string(),
+        })
+    }
+
+    fn write_zset_batch(&self, batch: WriteBatch) -> Result<()> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        db.write_opt(batch, &self.write_options).context(RocksSnafu)
+    }
+}
+
+// A score stored inline or in a member key, a little-endian f64
+fn parse_score(value: &[u8]) -> Result<f64> {
+    let bytes: [u8; SCORE_VALUE_LENGTH] = value.try_into().ok().context(InvalidFormatSnafu {
+        message: format!("score of {} bytes", value.len()),
+    })?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{with_redis, StorageOptions};
+
+    fn is_inline(redis: &Redis, key: &[u8]) -> bool {
+        redis
+            .live_base_meta(key, DataType::ZSet)
+            .unwrap()
+            .unwrap()
+            .is_inline()
+    }
+
+    #[test]
+    fn test_inline_zset() {
+        with_redis(StorageOptions::default(), |redis| {
+            assert_eq!(
+                redis.zset_add(b"z", &[(1.0, b"a"), (2.0, b"b")]).unwrap(),
+                2
+            );
+            assert_eq!(
+                redis.zset_add(b"z", &[(3.0, b"a"), (4.0, b"a")]).unwrap(),
+                0
+            );
+            assert!(is_inline(redis, b"z"));
+            assert_eq!(redis.zset_score(b"z", b"a").unwrap(), Some(4.0));
+            assert_eq!(redis.zset_score(b"z", b"c").unwrap(), None);
+
+            assert_eq!(redis.zset_rem(b"z", &[b"a", b"c"]).unwrap(), 1);
+            assert_eq!(redis.zset_rem(b"z", &[b"b", b"b"]).unwrap(), 1);
+            assert!(redis
+                .live_base_meta(b"z", DataType::ZSet)
+                .unwrap()
+                .is_none());
+            assert!(redis.zset_add(b"z", &[(f64::NAN, b"a")]).is_err());
+        });
+    }
+
+    #[test]
+    fn test_zset_spills_to_data_keys() {
+        let mut options = StorageOptions::default();
+        options.set_inline_collection_max_entries(2);
+        with_redis(options, |redis| {
+            assert_eq!(
+                redis.zset_add(b"z", &[(1.0, b"a"), (2.0, b"b")]).unwrap(),
+                2
+            );
+            assert!(is_inline(redis, b"z"));
+
+            // the third member spills the whole set
+            assert_eq!(
+                redis.zset_add(b"z", &[(3.0, b"c"), (5.0, b"a")]).unwrap(),
+                1
+            );
+            assert!(!is_inline(redis, b"z"));
+            let meta = redis.live_base_meta(b"z", DataType::ZSet).unwrap().unwrap();
+            assert_eq!(meta.count(), 3);
+            assert_eq!(redis.zset_score(b"z", b"a").unwrap(), Some(5.0));
+            assert_eq!(redis.zset_score(b"z", b"c").unwrap(), Some(3.0));
+
+            // the score key follows the score of the member
+            let db = redis.db.as_ref().unwrap();
+            let score_cf = redis.zset_cf(ColumnFamilyIndex::ZsetsScoreCF).unwrap();
+            let score_key = |score| {
+                redis
+                    .zset_score_key(b"z", meta.version(), score, b"a")
+                    .encode()
+                    .unwrap()
+            };
+            assert!(db.get_cf(&score_cf, score_key(5.0)).unwrap().is_some());
+            assert!(db.get_cf(&score_cf, score_key(1.0)).unwrap().is_none());
+
+            // shrinking does not move it back inline
+            assert_eq!(redis.zset_rem(b"z", &[b"a", b"a", b"d"]).unwrap(), 1);
+            assert!(!is_inline(redis, b"z"));
+            assert_eq!(redis.zset_rem(b"z", &[b"b", b"c"]).unwrap(), 2);
+            assert!(redis
+                .live_base_meta(b"z", DataType::ZSet)
+                .unwrap()
+                .is_none());
+        });
+    }
+
+    #[test]
+    fn test_zset_wrong_type() {
+        with_redis(StorageOptions::default(), |redis| {
+            redis.push(b"l", crate::ListEnd::Left, &[b"a"]).unwrap();
+            assert!(redis.zset_add(b"l", &[(1.0, b"a")]).is_err());
+            assert!(redis.zset_score(b"l", b"a").is_err());
+        });
+    }
+}
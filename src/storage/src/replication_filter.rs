@@ -0,0 +1,252 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Selective replication
+//!
+//! A replica may ask for a subset of the master's data: the keys starting
+//! with one of some prefixes, of some data types, or both. The replica sends
+//! its `ReplicationFilter` in the handshake and the master drops the binlog
+//! entries the filter does not match before sending them, so a downstream
+//! replica dedicated to, say, the leaderboards only receives zsets.
+
+use bytes::{Buf, BufMut};
+use snafu::ensure;
+
+use crate::{
+    base_value_format::DATA_TYPE_STRINGS,
+    error::{InvalidFormatSnafu, Result},
+    DataType,
+};
+
+/// The keys a replica subscribes to. An empty prefix list matches every key
+/// and an empty type list every data type, so the default filter passes
+/// everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplicationFilter {
+    prefixes: Vec<Vec<u8>>,
+    data_types: Vec<DataType>,
+}
+
+impl ReplicationFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to the keys starting with `prefix`
+    pub fn add_prefix(&mut self, prefix: impl Into<Vec<u8>>) -> &mut Self {
+        self.prefixes.push(prefix.into());
+        self
+    }
+
+    /// Subscribe to the keys of type `data_type`
+    pub fn add_data_type(&mut self, data_type: DataType) -> &mut Self {
+        if !self.data_types.contains(&data_type) {
+            self.data_types.push(data_type);
+        }
+        self
+    }
+
+    pub fn prefixes(&self) -> &[Vec<u8>] {
+        &self.prefixes
+    }
+
+    pub fn data_types(&self) -> &[DataType] {
+        &self.data_types
+    }
+
+    /// Whether the filter lets every binlog entry through, in which case the
+    /// master can skip filtering altogether.
+    pub fn is_pass_all(&self) -> bool {
+        self.prefixes.is_empty() && self.data_types.is_empty()
+    }
+
+    /// Whether a binlog entry on `key`, of type `data_type`, is sent to the
+    /// replica. Entries on the whole keyspace, such as FLUSHDB, have type
+    /// `DataType::All` and are always sent.
+    pub fn matches(&self, key: &[u8], data_type: DataType) -> bool {
+        if data_type == DataType::All {
+            return true;
+        }
+        let prefix_ok =
+            self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p));
+        let type_ok = self.data_types.is_empty() || self.data_types.contains(&data_type);
+        prefix_ok && type_ok
+    }
+
+    /// Builds a filter from handshake arguments, `PREFIX <prefix>` and
+    /// `TYPE <type>` pairs in any order, each of them may be repeated.
+    pub fn parse_args(args: &[Vec<u8>]) -> Result<Self> {
+        ensure!(
+            args.len() % 2 == 0,
+            InvalidFormatSnafu {
+                message: "replication filter arguments must be pairs".to_string(),
+            }
+        );
+
+        let mut filter = Self::new();
+        for pair in args.chunks(2) {
+            let (name, value) = (&pair[0], &pair[1]);
+            if name.eq_ignore_ascii_case(b"prefix") {
+                filter.add_prefix(value.clone());
+            } else if name.eq_ignore_ascii_case(b"type") {
+                filter.add_data_type(parse_data_type(value)?);
+            } else {
+                return InvalidFormatSnafu {
+                    message: format!(
+                        "unknown replication filter {}",
+                        String::from_utf8_lossy(name)
+                    ),
+                }
+                .fail();
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Serializes the filter for the handshake:
+    /// | prefix count 4 | (prefix len 4 | prefix)* | type count 1 | type 1* |
+    pub fn encode(&self) -> Vec<u8> {
+        let prefixes_len: usize = self.prefixes.iter().map(|p| 4 + p.len()).sum();
+        let mut dst = Vec::with_capacity(4 + prefixes_len + 1 + self.data_types.len());
+        dst.put_u32_le(self.prefixes.len() as u32);
+        for prefix in &self.prefixes {
+            dst.put_u32_le(prefix.len() as u32);
+            dst.put_slice(prefix);
+        }
+        dst.put_u8(self.data_types.len() as u8);
+        for &data_type in &self.data_types {
+            dst.put_u8(data_type as u8);
+        }
+        dst
+    }
+
+    pub fn decode(mut src: &[u8]) -> Result<Self> {
+        let mut filter = Self::new();
+
+        ensure_remaining(src, 4)?;
+        let prefix_count = src.get_u32_le();
+        for _ in 0..prefix_count {
+            ensure_remaining(src, 4)?;
+            let len = src.get_u32_le() as usize;
+            ensure_remaining(src, len)?;
+            filter.add_prefix(&src[..len]);
+            src.advance(len);
+        }
+
+        ensure_remaining(src, 1)?;
+        let type_count = src.get_u8() as usize;
+        ensure_remaining(src, type_count)?;
+        for _ in 0..type_count {
+            filter.add_data_type(DataType::try_from(src.get_u8())?);
+        }
+
+        ensure!(
+            src.is_empty(),
+            InvalidFormatSnafu {
+                message: "trailing bytes after replication filter".to_string(),
+            }
+        );
+        Ok(filter)
+    }
+}
+
+fn ensure_remaining(src: &[u8], len: usize) -> Result<()> {
+    ensure!(
+        src.len() >= len,
+        InvalidFormatSnafu {
+            message: "replication filter truncated".to_string(),
+        }
+    );
+    Ok(())
+}
+
+fn parse_data_type(name: &[u8]) -> Result<DataType> {
+    let data_type = DATA_TYPE_STRINGS
+        .iter()
+        .position(|s| name.eq_ignore_ascii_case(s.as_bytes()))
+        .map(|i| DataType::try_from(i as u8))
+        .transpose()?;
+    match data_type {
+        Some(DataType::None | DataType::All) | None => InvalidFormatSnafu {
+            message: format!("unknown data type {}", String::from_utf8_lossy(name)),
+        }
+        .fail(),
+        Some(data_type) => Ok(data_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(items: &[&str]) -> Vec<Vec<u8>> {
+        items.iter().map(|s| s.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(ReplicationFilter::new().matches(b"any", DataType::Hash));
+
+        let mut filter = ReplicationFilter::new();
+        filter
+            .add_prefix("rank:")
+            .add_prefix("board:")
+            .add_data_type(DataType::ZSet);
+        assert!(filter.matches(b"rank:daily", DataType::ZSet));
+        assert!(filter.matches(b"board:1", DataType::ZSet));
+        assert!(!filter.matches(b"rank:daily", DataType::Hash));
+        assert!(!filter.matches(b"user:1", DataType::ZSet));
+        assert!(filter.matches(b"", DataType::All));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let filter =
+            ReplicationFilter::parse_args(&args(&["PREFIX", "rank:", "type", "zset"])).unwrap();
+        assert_eq!(filter.prefixes(), &[b"rank:".to_vec()]);
+        assert_eq!(filter.data_types(), &[DataType::ZSet]);
+
+        assert!(ReplicationFilter::parse_args(&args(&["PREFIX"])).is_err());
+        assert!(ReplicationFilter::parse_args(&args(&["TYPE", "none"])).is_err());
+        let streams = ReplicationFilter::parse_args(&args(&["TYPE", "Stream"])).unwrap();
+        assert_eq!(streams.data_types(), &[DataType::Stream]);
+        assert!(ReplicationFilter::parse_args(&args(&["SLOT", "1"])).is_err());
+        assert!(ReplicationFilter::parse_args(&[]).unwrap().is_pass_all());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut filter = ReplicationFilter::new();
+        filter
+            .add_prefix(b"a\x00b".to_vec())
+            .add_prefix("")
+            .add_data_type(DataType::Set)
+            .add_data_type(DataType::List);
+        let encoded = filter.encode();
+        assert_eq!(ReplicationFilter::decode(&encoded).unwrap(), filter);
+
+        let empty = ReplicationFilter::new();
+        assert_eq!(ReplicationFilter::decode(&empty.encode()).unwrap(), empty);
+
+        assert!(ReplicationFilter::decode(&encoded[..encoded.len() - 1]).is_err());
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(ReplicationFilter::decode(&trailing).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Results of the storage commands
+//!
+//! Every command of `Storage` that reports more than its value returns a
+//! struct, so the RESP layer and embedders read the named fields instead of
+//! positional primitives. The structs are `#[non_exhaustive]`: a field added
+//! for a new option (SET GET, NX) does not break the callers.
+
+/// The outcome of SET
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SetResult {
+    /// Whether the value was written, false when the NX or XX condition
+    /// did not hold
+    pub did_set: bool,
+    /// The string the key held before, with GET. None when it did not exist
+    pub old_value: Option<Vec<u8>>,
+}
+
+impl SetResult {
+    pub(crate) fn new(did_set: bool, old_value: Option<Vec<u8>>) -> Self {
+        Self { did_set, old_value }
+    }
+}
+
+/// The outcome of LPUSH and RPUSH
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct PushResult {
+    /// Length of the list after the push
+    pub new_len: u64,
+}
+
+impl PushResult {
+    pub(crate) fn new(new_len: u64) -> Self {
+        Self { new_len }
+    }
+}
+
+/// The outcome of DEL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct DelResult {
+    /// Number of keys that existed and were deleted
+    pub deleted: usize,
+}
+
+impl DelResult {
+    pub(crate) fn new(deleted: usize) -> Self {
+        Self { deleted }
+    }
+}
+
+/// The outcome of EXISTS and TOUCH
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct ExistsResult {
+    /// Number of keys that exist, a repeated key is counted every time
+    pub existing: usize,
+}
+
+impl ExistsResult {
+    pub(crate) fn new(existing: usize) -> Self {
+        Self { existing }
+    }
+}
+
+/// The outcome of UNDELETE
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct UndeleteResult {
+    /// Whether the key was restored from the trash
+    pub restored: bool,
+}
+
+impl UndeleteResult {
+    pub(crate) fn new(restored: bool) -> Self {
+        Self { restored }
+    }
+}
@@ -0,0 +1,247 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Storage self-test
+//!
+//! A short benchmark and integrity check for validating the hardware of a
+//! node before it takes traffic: it writes a number of keys, reads every one
+//! back and compares it with what was written, deletes them and checks they
+//! are gone, timing every operation. The keys live under
+//! `SELF_TEST_KEY_PREFIX` and are deleted again, so the test can run against
+//! the data directory of a node. Strings are the only type the storage
+//! serves so far, the other types are not exercised.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::{error::Error, storage::Storage, Result};
+
+/// Prefix of the keys written by the self-test.
+pub const SELF_TEST_KEY_PREFIX: &str = "__kiwi_self_test__:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfTestOptions {
+    /// Number of keys written, read and deleted.
+    pub keys: usize,
+    /// Length of every value in bytes.
+    pub value_len: usize,
+}
+
+impl Default for SelfTestOptions {
+    fn default() -> Self {
+        Self {
+            keys: 10_000,
+            value_len: 128,
+        }
+    }
+}
+
+/// Timings and failures of one phase of the self-test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhaseReport {
+    pub name: &'static str,
+    pub ops: u64,
+    /// Operations that failed or returned unexpected data.
+    pub errors: u64,
+    pub elapsed: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl PhaseReport {
+    fn new(name: &'static str, mut latencies: Vec<Duration>, errors: u64) -> Self {
+        latencies.sort_unstable();
+        let percentile = |p: usize| {
+            latencies
+                .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+                .copied()
+                .unwrap_or_default()
+        };
+        Self {
+            name,
+            ops: latencies.len() as u64,
+            errors,
+            elapsed: latencies.iter().sum(),
+            p50: percentile(50),
+            p99: percentile(99),
+            max: latencies.last().copied().unwrap_or_default(),
+        }
+    }
+
+    pub fn iops(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.ops as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub phases: Vec<PhaseReport>,
+}
+
+impl SelfTestReport {
+    /// Whether every operation succeeded and returned the expected data.
+    pub fn passed(&self) -> bool {
+        self.phases.iter().all(|phase| phase.errors == 0)
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<8} {:>10} {:>8} {:>12} {:>10} {:>10} {:>10}",
+            "phase", "ops", "errors", "iops", "p50(us)", "p99(us)", "max(us)"
+        )?;
+        for phase in &self.phases {
+            writeln!(
+                f,
+                "{:<8} {:>10} {:>8} {:>12.0} {:>10} {:>10} {:>10}",
+                phase.name,
+                phase.ops,
+                phase.errors,
+                phase.iops(),
+                phase.p50.as_micros(),
+                phase.p99.as_micros(),
+                phase.max.as_micros()
+            )?;
+        }
+        write!(f, "result: {}", if self.passed() { "PASS" } else { "FAIL" })
+    }
+}
+
+fn self_test_key(i: usize) -> Vec<u8> {
+    format!("{SELF_TEST_KEY_PREFIX}{i}").into_bytes()
+}
+
+// The value of every key differs, so a value read back from the wrong key
+// is caught
+fn self_test_value(i: usize, len: usize) -> Vec<u8> {
+    let seed = format!("{i}:");
+    seed.bytes().cycle().take(len).collect()
+}
+
+fn timed<T>(latencies: &mut Vec<Duration>, op: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = op();
+    latencies.push(start.elapsed());
+    result
+}
+
+impl Storage {
+    /// Runs the self-test against the opened storage. Errors of single
+    /// operations are counted in the report; only a failure to clean up the
+    /// test keys is returned as an error.
+    pub fn self_test(&self, options: &SelfTestOptions) -> Result<SelfTestReport> {
+        let mut phases = Vec::with_capacity(4);
+
+        let mut latencies = Vec::with_capacity(options.keys);
+        let mut errors = 0;
+        for i in 0..options.keys {
+            let value = self_test_value(i, options.value_len);
+            if timed(&mut latencies, || self.set(&self_test_key(i), &value)).is_err() {
+                errors += 1;
+            }
+        }
+        phases.push(PhaseReport::new("write", latencies, errors));
+
+        let mut latencies = Vec::with_capacity(options.keys);
+        let mut errors = 0;
+        for i in 0..options.keys {
+            let expected = self_test_value(i, options.value_len);
+            match timed(&mut latencies, || self.get(&self_test_key(i))) {
+                Ok(value) if value.as_bytes() == expected => {}
+                _ => errors += 1,
+            }
+        }
+        phases.push(PhaseReport::new("read", latencies, errors));
+
+        let mut latencies = Vec::with_capacity(options.keys);
+        let mut errors = 0;
+        for i in 0..options.keys {
+            match timed(&mut latencies, || self.del(&[self_test_key(i)])) {
+                Ok(result) if result.deleted == 1 => {}
+                _ => errors += 1,
+            }
+        }
+        phases.push(PhaseReport::new("delete", latencies, errors));
+
+        let mut latencies = Vec::with_capacity(options.keys);
+        let mut errors = 0;
+        for i in 0..options.keys {
+            match timed(&mut latencies, || self.get(&self_test_key(i))) {
+                Err(Error::KeyNotFound { .. }) => {}
+                _ => errors += 1,
+            }
+        }
+        phases.push(PhaseReport::new("verify", latencies, errors));
+
+        // Leave no test key behind, even when the delete phase failed
+        if phases[2].errors > 0 {
+            let keys: Vec<_> = (0..options.keys).map(self_test_key).collect();
+            self.del(&keys)?;
+        }
+
+        Ok(SelfTestReport { phases })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_report() {
+        let latencies = (1..=100).rev().map(Duration::from_micros).collect();
+        let phase = PhaseReport::new("read", latencies, 0);
+        assert_eq!(phase.ops, 100);
+        assert_eq!(phase.p50, Duration::from_micros(51));
+        assert_eq!(phase.p99, Duration::from_micros(100));
+        assert_eq!(phase.max, Duration::from_micros(100));
+        assert_eq!(phase.elapsed, Duration::from_micros(5050));
+
+        let empty = PhaseReport::new("read", Vec::new(), 0);
+        assert_eq!(empty.p99, Duration::ZERO);
+        assert_eq!(empty.iops(), 0.0);
+    }
+
+    #[test]
+    fn test_report_passed() {
+        let ok = PhaseReport::new("write", vec![Duration::from_micros(1)], 0);
+        let failed = PhaseReport::new("read", vec![Duration::from_micros(1)], 1);
+        assert!(SelfTestReport {
+            phases: vec![ok.clone()]
+        }
+        .passed());
+
+        let report = SelfTestReport {
+            phases: vec![ok, failed],
+        };
+        assert!(!report.passed());
+        assert!(report.to_string().ends_with("result: FAIL"));
+    }
+
+    #[test]
+    fn test_self_test_values_differ() {
+        assert_eq!(self_test_value(7, 5), b"7:7:7");
+        assert_ne!(self_test_value(1, 8), self_test_value(11, 8));
+    }
+}
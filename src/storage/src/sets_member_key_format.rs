@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![cfg_attr(not(test), allow(dead_code))]
+
+use crate::error::Result;
+use crate::hashes_data_key_format::{HashesDataKey, ParsedHashesDataKey};
+use bytes::Bytes;
+
+/*
+ * Format for Set member key, stored in set_data_cf, value is empty
+ * | reserve1 | key | version | member | reserve2 |
+ * |    8B    |     |    8B   |        |   16B    |
+ *
+ * The layout of a hash data key with the member as the field, so encode and
+ * parse are shared with hashes_data_key_format.
+ */
+pub struct SetsMemberKey(HashesDataKey);
+
+impl SetsMemberKey {
+    pub fn new(key: &[u8], version: u64, member: &[u8]) -> Self {
+        Self(HashesDataKey::new(key, version, member))
+    }
+
+    pub fn with_reserves(
+        key: &[u8],
+        version: u64,
+        member: &[u8],
+        reserve1: [u8; 8],
+        reserve2: [u8; 16],
+    ) -> Self {
+        Self(HashesDataKey::with_reserves(
+            key, version, member, reserve1, reserve2,
+        ))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        self.0.encode()
+    }
+
+    /// Encodes the key without the trailing reserve2, used as the seek target
+    /// when iterating from a given member.
+    pub fn encode_seek_key(&self) -> Result<Vec<u8>> {
+        self.0.encode_seek_key()
+    }
+
+    /// Encodes `| reserve1 | key | version |`, which is shared by every member
+    /// of the same set and therefore usable as a prefix for scans.
+    pub fn encode_prefix(&self) -> Result<Vec<u8>> {
+        self.0.encode_prefix()
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        self.0.reserve1()
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        self.0.reserve2()
+    }
+}
+
+pub struct ParsedSetsMemberKey(ParsedHashesDataKey);
+
+impl ParsedSetsMemberKey {
+    pub fn from_string(key: &str) -> Result<Self> {
+        Self::decode(key.as_bytes())
+    }
+
+    pub fn from_slice(key: &[u8]) -> Result<Self> {
+        Self::decode(key)
+    }
+
+    /// Parses a key the caller owns, the member then shares its memory
+    pub fn from_bytes(key: Bytes) -> Result<Self> {
+        ParsedHashesDataKey::from_bytes(key).map(Self)
+    }
+
+    pub fn decode(key: &[u8]) -> Result<Self> {
+        ParsedHashesDataKey::decode(key).map(Self)
+    }
+
+    pub fn key(&self) -> &[u8] {
+        self.0.key()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.0.version()
+    }
+
+    pub fn member(&self) -> &[u8] {
+        self.0.field()
+    }
+
+    pub fn into_member(self) -> Bytes {
+        self.0.into_field()
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        self.0.reserve1()
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        self.0.reserve2()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn test_encode_decode() -> Result<()> {
+        let member_key = SetsMemberKey::new(b"test\x00key", 123, b"member");
+        let encoded = member_key.encode()?;
+
+        let parsed = ParsedSetsMemberKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), b"test\x00key");
+        assert_eq!(parsed.version(), 123);
+        assert_eq!(parsed.member(), b"member");
+        Ok(())
+    }
+
+    #[test]
+    fn test_special_characters_in_member() -> Result<()> {
+        let key = b"special\x00\x01\x00chars";
+        let member = b"\x00\x00member\x00\x01";
+
+        let encoded = SetsMemberKey::new(key, 999, member).encode()?;
+        let parsed = ParsedSetsMemberKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), key);
+        assert_eq!(parsed.version(), 999);
+        assert_eq!(parsed.member(), member);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_key_and_member() -> Result<()> {
+        let encoded = SetsMemberKey::new(b"", 0, b"").encode()?;
+        let parsed = ParsedSetsMemberKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), b"");
+        assert_eq!(parsed.version(), 0);
+        assert_eq!(parsed.member(), b"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix_matches_all_members() -> Result<()> {
+        let prefix = SetsMemberKey::new(b"set", 7, b"").encode_prefix()?;
+        for member in [&b"a"[..], b"b\x00c", b""] {
+            let encoded = SetsMemberKey::new(b"set", 7, member).encode()?;
+            assert!(encoded.starts_with(&prefix));
+        }
+
+        let other_version = SetsMemberKey::new(b"set", 8, b"a").encode()?;
+        assert!(!other_version.starts_with(&prefix));
+        let other_key = SetsMemberKey::new(b"set2", 7, b"a").encode()?;
+        assert!(!other_key.starts_with(&prefix));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_encoding() {
+        let result = ParsedSetsMemberKey::from_slice(b"invalid\x00\x02data");
+        assert!(matches!(result, Err(Error::InvalidFormat { .. })));
+
+        // delimiter present but no room left for the version
+        let mut truncated = vec![0u8; 8];
+        truncated.extend_from_slice(b"key\x00\x00");
+        truncated.extend_from_slice(&[0u8; 16]);
+        let result = ParsedSetsMemberKey::from_slice(&truncated);
+        assert!(matches!(result, Err(Error::InvalidFormat { .. })));
+    }
+
+    #[test]
+    fn test_same_layout_as_hash_field() -> Result<()> {
+        let encoded = SetsMemberKey::new(b"set", 3, b"m\x00").encode()?;
+        assert_eq!(encoded, HashesDataKey::new(b"set", 3, b"m\x00").encode()?);
+
+        let member = ParsedSetsMemberKey::from_bytes(Bytes::from(encoded))?.into_member();
+        assert_eq!(member, &b"m\x00"[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserve_fields_round_trip() -> Result<()> {
+        let reserve1 = [1, 2, 3, 4, 5, 6, 7, 8];
+        let reserve2 = [
+            9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        ];
+
+        let member_key = SetsMemberKey::with_reserves(b"test_key", 456, b"m", reserve1, reserve2);
+        let encoded = member_key.encode()?;
+        let parsed = ParsedSetsMemberKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), b"test_key");
+        assert_eq!(parsed.version(), 456);
+        assert_eq!(parsed.member(), b"m");
+        assert_eq!(parsed.reserve1(), &reserve1);
+        assert_eq!(parsed.reserve2(), &reserve2);
+        Ok(())
+    }
+}
@@ -17,6 +17,15 @@
  * limitations under the License.
  */
 
+//! Key routing shared by the storage engine, proxies and client libraries.
+//!
+//! A key is mapped to a slot, and the slot to one of the rocksdb instances.
+//! The slot is the CRC16 of the whole key, unless hash tag placement is
+//! enabled: the slot is then the one of its hash tag, as in Redis Cluster,
+//! so keys sharing a `{tag}` land on the same instance and multi-key
+//! commands can be served by a single instance. The placement is persisted
+//! with the keys, see `StorageOptions::hash_tag_placement`.
+
 use crc16::{State, ARC};
 
 pub const SLOT_INDEXER_INSTANCE_NUM: usize = 3;
@@ -26,6 +35,8 @@ pub const SLOT_INDEXER_INSTANCE_NUM: usize = 3;
 pub struct SlotIndexer {
     // Number of instances
     instance_num: usize,
+    // Whether keys are placed by their hash tag
+    hash_tags: bool,
 }
 
 /// NOTE: default instance number is 3.
@@ -33,6 +44,7 @@ impl Default for SlotIndexer {
     fn default() -> Self {
         Self {
             instance_num: SLOT_INDEXER_INSTANCE_NUM,
+            hash_tags: false,
         }
     }
 }
@@ -44,7 +56,16 @@ impl SlotIndexer {
             instance_num > 0,
             "Instance number must be greater than zero."
         );
-        Self { instance_num }
+        Self {
+            instance_num,
+            hash_tags: false,
+        }
+    }
+
+    /// Place the keys by their hash tag instead of the whole key.
+    pub fn with_hash_tags(mut self, hash_tags: bool) -> Self {
+        self.hash_tags = hash_tags;
+        self
     }
 
     /// Calculate the instance ID from given slot ID.
@@ -52,6 +73,20 @@ impl SlotIndexer {
         slot_id % self.instance_num
     }
 
+    /// Calculate the instance ID that stores `key`.
+    pub fn key_to_instance_id(&self, key: &[u8]) -> usize {
+        let slot_id = match self.hash_tags {
+            true => key_to_hash_slot(key),
+            false => key_to_slot_id(key),
+        };
+        self.get_instance_id(slot_id)
+    }
+
+    /// Number of instances.
+    pub fn instance_num(&self) -> usize {
+        self.instance_num
+    }
+
     /// Placeholder for re-sharding slots functionality.
     pub fn reshard_slots(&self, _slots: Vec<usize>) {
         // TODO: Implement the logic for re-sharding slots.
@@ -61,11 +96,30 @@ impl SlotIndexer {
     }
 }
 
-/// Map key to slot ID using CRC16-ARC (compatible with Redis Cluster hash slot)
+/// Returns the part of `key` that is hashed, following the Redis Cluster rules:
+/// if the key contains a `{` followed by a `}` with at least one byte between
+/// them, only the bytes between the first `{` and the next `}` are hashed.
+/// Otherwise the whole key is hashed.
+pub fn extract_hash_tag(key: &[u8]) -> &[u8] {
+    let Some(start) = key.iter().position(|&b| b == b'{') else {
+        return key;
+    };
+    match key[start + 1..].iter().position(|&b| b == b'}') {
+        Some(len) if len > 0 => &key[start + 1..start + 1 + len],
+        _ => key,
+    }
+}
+
+/// Map key to slot ID using CRC16-ARC of the whole key
 pub fn key_to_slot_id(key: &[u8]) -> usize {
     State::<ARC>::calculate(key) as usize
 }
 
+/// Map key to slot ID using CRC16-ARC of its hash tag, see `extract_hash_tag`
+pub fn key_to_hash_slot(key: &[u8]) -> usize {
+    key_to_slot_id(extract_hash_tag(key))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -91,4 +145,41 @@ mod tests {
         assert_eq!(indexer.get_instance_id(8), 8);
         assert_eq!(indexer.get_instance_id(15), 5);
     }
+
+    #[test]
+    fn test_extract_hash_tag() {
+        assert_eq!(extract_hash_tag(b"user:1000"), b"user:1000");
+        assert_eq!(extract_hash_tag(b"{user:1000}.following"), b"user:1000");
+        assert_eq!(extract_hash_tag(b"foo{bar}{zap}"), b"bar");
+        assert_eq!(extract_hash_tag(b"foo{{bar}}"), b"{bar");
+        // Empty or unterminated tags hash the whole key.
+        assert_eq!(extract_hash_tag(b"foo{}{bar}"), b"foo{}{bar}");
+        assert_eq!(extract_hash_tag(b"foo{bar"), b"foo{bar");
+        assert_eq!(extract_hash_tag(b"foo}bar{"), b"foo}bar{");
+        assert_eq!(extract_hash_tag(b""), b"");
+    }
+
+    #[test]
+    fn test_keys_with_same_tag_share_instance() {
+        let indexer = SlotIndexer::default().with_hash_tags(true);
+        assert_eq!(key_to_hash_slot(b"{user}:name"), key_to_slot_id(b"user"));
+        assert_eq!(
+            indexer.key_to_instance_id(b"{user}:name"),
+            indexer.key_to_instance_id(b"{user}:age")
+        );
+        assert_eq!(
+            indexer.key_to_instance_id(b"plain"),
+            indexer.get_instance_id(key_to_slot_id(b"plain"))
+        );
+    }
+
+    #[test]
+    fn test_default_placement_hashes_whole_key() {
+        let indexer = SlotIndexer::default();
+        assert_ne!(key_to_slot_id(b"{user}:name"), key_to_slot_id(b"user"));
+        assert_eq!(
+            indexer.key_to_instance_id(b"{user}:name"),
+            indexer.get_instance_id(key_to_slot_id(b"{user}:name"))
+        );
+    }
 }
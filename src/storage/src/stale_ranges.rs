@@ -0,0 +1,358 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Targeted compaction of the key ranges full of stale entries
+//!
+//! Expired keys and the data keys of old versions take disk space until a
+//! compaction visits them, which on the bottom levels of a large instance
+//! can take days. Reads count the expired meta values they run into, per
+//! key, and the compaction filters count the stale entries they drop, per
+//! column family. Inside `StorageOptions::stale_compaction_window`,
+//! `Storage::stale_compaction_worker` compacts the meta key and the data
+//! keys of the keys reads found stale most often, so the space comes back
+//! at a known time of day instead of whenever compaction gets there.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use snafu::OptionExt;
+
+use crate::{
+    error::OptionNoneSnafu, filter_stats::FilterOutcome, key_scope::KeyScope,
+    lazy_delete::data_column_families, redis_keys::is_expired_meta_value, ColumnFamilyIndex,
+    DataType, Redis, Result,
+};
+
+const CF_COUNT: usize = ColumnFamilyIndex::StreamsDataCF as usize + 1;
+
+/// The column families reported by INFO, the ones holding stale entries
+const STALE_CFS: [ColumnFamilyIndex; 9] = [
+    ColumnFamilyIndex::MetaCF,
+    ColumnFamilyIndex::HashesDataCF,
+    ColumnFamilyIndex::SetsDataCF,
+    ColumnFamilyIndex::ListsDataCF,
+    ColumnFamilyIndex::ZsetsDataCF,
+    ColumnFamilyIndex::ZsetsScoreCF,
+    ColumnFamilyIndex::StreamsDataCF,
+    ColumnFamilyIndex::StreamsGroupCF,
+    ColumnFamilyIndex::BitmapDataCF,
+];
+
+const MICROS_PER_MINUTE: u64 = 60_000_000;
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+static READ: [AtomicU64; CF_COUNT] = [const { AtomicU64::new(0) }; CF_COUNT];
+static FILTERED: [AtomicU64; CF_COUNT] = [const { AtomicU64::new(0) }; CF_COUNT];
+static COMPACTED: [AtomicU64; CF_COUNT] = [const { AtomicU64::new(0) }; CF_COUNT];
+
+/// Counts an entry of `cf` a compaction filter dropped for being stale
+pub(crate) fn record_filtered(cf: ColumnFamilyIndex, outcome: FilterOutcome) {
+    if matches!(
+        outcome,
+        FilterOutcome::RemovedExpired | FilterOutcome::RemovedStaleVersion
+    ) {
+        FILTERED[cf as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A daily time range, in UTC, written `HH:MM-HH:MM`. It wraps past
+/// midnight when the end is before the start, and covers the whole day
+/// when both are equal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    start: u32,
+    end: u32,
+}
+
+impl MaintenanceWindow {
+    /// Whether the time `micros` since the epoch falls in the window
+    pub fn contains(&self, micros: u64) -> bool {
+        let minute = (micros / MICROS_PER_MINUTE % MINUTES_PER_DAY as u64) as u32;
+        match self.start.cmp(&self.end) {
+            std::cmp::Ordering::Equal => true,
+            std::cmp::Ordering::Less => (self.start..self.end).contains(&minute),
+            std::cmp::Ordering::Greater => minute >= self.start || minute < self.end,
+        }
+    }
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let minute = |time: &str| {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+            (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+        };
+        s.split_once('-')
+            .and_then(|(start, end)| Some((minute(start)?, minute(end)?)))
+            .map(|(start, end)| Self { start, end })
+            .ok_or_else(|| format!("invalid maintenance window '{s}', expected HH:MM-HH:MM"))
+    }
+}
+
+/// The keys of an instance reads found stale, with how often, bounded
+#[derive(Debug)]
+pub struct StaleRanges {
+    keys: Mutex<HashMap<Vec<u8>, (DataType, u64)>>,
+    capacity: usize,
+    untracked: AtomicU64,
+}
+
+impl StaleRanges {
+    /// Tracks at most `capacity` keys, 0 to track none
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            keys: Mutex::new(HashMap::new()),
+            capacity,
+            untracked: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts a stale read of `key`. A new key is not tracked once
+    /// `capacity` keys are, the busy ones are already in
+    pub fn record(&self, key: &[u8], data_type: DataType) {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some((_, reads)) = keys.get_mut(key) {
+            *reads += 1;
+        } else if keys.len() < self.capacity {
+            keys.insert(key.to_vec(), (data_type, 1));
+        } else {
+            self.untracked.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Removes and returns up to `max` keys read stale at least `min_reads`
+    /// times, the most read first
+    pub fn take_hottest(&self, max: usize, min_reads: u64) -> Vec<(Vec<u8>, DataType)> {
+        let mut keys = self.keys.lock().unwrap();
+        let mut hottest: Vec<_> = keys
+            .iter()
+            .filter(|(_, (_, reads))| *reads >= min_reads)
+            .map(|(key, (_, reads))| (*reads, key.clone()))
+            .collect();
+        hottest.sort_unstable_by(|a, b| b.cmp(a));
+        hottest.truncate(max);
+        hottest
+            .into_iter()
+            .filter_map(|(_, key)| keys.remove_entry(&key))
+            .map(|(key, (data_type, _))| (key, data_type))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stale reads of keys not tracked for lack of room since the start
+    pub fn untracked(&self) -> u64 {
+        self.untracked.load(Ordering::Relaxed)
+    }
+}
+
+impl Redis {
+    /// Counts a read of `key` that found its meta value `meta_value`, if it
+    /// is expired
+    pub(crate) fn note_stale_read(&self, key: &[u8], meta_value: &[u8]) {
+        if !is_expired_meta_value(meta_value) {
+            return;
+        }
+        let Ok(data_type) = DataType::try_from(meta_value[0]) else {
+            return;
+        };
+        READ[ColumnFamilyIndex::MetaCF as usize].fetch_add(1, Ordering::Relaxed);
+        for &cf in data_column_families(data_type) {
+            READ[cf as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        self.stale_ranges.record(key, data_type);
+    }
+
+    /// Compacts the meta key and every data key of up to `max` keys read
+    /// stale at least `min_reads` times, returns how many keys were done
+    pub fn compact_stale_ranges(&self, max: usize, min_reads: u64) -> Result<usize> {
+        let keys = self.stale_ranges.take_hottest(max, min_reads);
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = |cf_index: ColumnFamilyIndex| {
+            self.get_cf_handle(cf_index).context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })
+        };
+
+        for (key, data_type) in &keys {
+            let meta_key = self.base_key(key).encode()?;
+            db.compact_range_cf(
+                &cf(ColumnFamilyIndex::MetaCF)?,
+                Some(&meta_key),
+                Some(&meta_key),
+            );
+            COMPACTED[ColumnFamilyIndex::MetaCF as usize].fetch_add(1, Ordering::Relaxed);
+
+            let scope = KeyScope::key(key)?;
+            for &cf_index in data_column_families(*data_type) {
+                db.compact_range_cf(&cf(cf_index)?, Some(scope.start()), scope.upper_bound());
+                COMPACTED[cf_index as usize].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(keys.len())
+    }
+
+    /// Formats the tracked keys as the stale range lines of INFO
+    pub(crate) fn stale_ranges_info(&self, info: &mut String) {
+        let _ = write!(
+            info,
+            "instance{}_stale_keys:tracked={},untracked_reads={}\r\n",
+            self.index,
+            self.stale_ranges.len(),
+            self.stale_ranges.untracked(),
+        );
+    }
+}
+
+/// Formats the stale entry counters of every column family, followed by
+/// `instances`, as the staleranges section of INFO
+pub fn stale_ranges_info(instances: &str) -> String {
+    let mut info = String::from("# StaleRanges\r\n");
+    for cf in STALE_CFS {
+        let i = cf as usize;
+        let _ = write!(
+            info,
+            "stale_{}:read={},filtered={},compacted={}\r\n",
+            cf.name(),
+            READ[i].load(Ordering::Relaxed),
+            FILTERED[i].load(Ordering::Relaxed),
+            COMPACTED[i].load(Ordering::Relaxed),
+        );
+    }
+    info.push_str(instances);
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{self, MockClock},
+        strings_value_format::StringValue,
+        unique_test_db_path, BgTaskHandler, StorageOptions,
+    };
+    use kstd::lock_mgr::LockMgr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_maintenance_window() {
+        let at = |hours: u64, minutes: u64| (hours * 60 + minutes) * MICROS_PER_MINUTE;
+
+        let window: MaintenanceWindow = "02:00-04:30".parse().unwrap();
+        assert!(!window.contains(at(1, 59)));
+        assert!(window.contains(at(2, 0)));
+        assert!(window.contains(at(24 * 3 + 4, 29)));
+        assert!(!window.contains(at(4, 30)));
+
+        let window: MaintenanceWindow = "23:00-01:00".parse().unwrap();
+        assert!(window.contains(at(23, 30)));
+        assert!(window.contains(at(0, 30)));
+        assert!(!window.contains(at(12, 0)));
+
+        let window: MaintenanceWindow = "03:00-03:00".parse().unwrap();
+        assert!(window.contains(at(15, 0)));
+
+        for invalid in ["", "02:00", "24:00-01:00", "02:60-03:00", "2-3"] {
+            assert!(invalid.parse::<MaintenanceWindow>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_stale_ranges_take_hottest() {
+        let ranges = StaleRanges::new(3);
+        for (key, reads) in [(&b"a"[..], 5), (b"b", 1), (b"c", 3)] {
+            for _ in 0..reads {
+                ranges.record(key, DataType::Hash);
+            }
+        }
+        ranges.record(b"d", DataType::Set);
+        assert_eq!(ranges.untracked(), 1);
+
+        assert_eq!(
+            ranges.take_hottest(10, 2),
+            vec![
+                (b"a".to_vec(), DataType::Hash),
+                (b"c".to_vec(), DataType::Hash)
+            ]
+        );
+        assert_eq!(ranges.len(), 1);
+        assert!(ranges.take_hottest(10, 2).is_empty());
+    }
+
+    #[test]
+    fn test_compact_stale_ranges() {
+        let test_db_path = unique_test_db_path();
+        let (handler, _receiver) = BgTaskHandler::new();
+        let mut options = StorageOptions::default();
+        options.set_stale_compaction_window(Some("00:00-00:00".parse().unwrap()));
+        let mut redis = Redis::new(
+            Arc::new(options),
+            1,
+            Arc::new(handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+
+        let clock = Arc::new(MockClock::new(1_700_000_000_000_000));
+        clock::with_clock(clock.clone(), || {
+            let db = redis.db.as_ref().unwrap();
+            let meta_cf = redis.get_cf_handle(ColumnFamilyIndex::MetaCF).unwrap();
+            let mut value = StringValue::new(&b"v"[..]);
+            value.set_relative_etime(1_000_000).unwrap();
+            let meta_key = redis.base_key(b"k").encode().unwrap();
+            db.put_cf(&meta_cf, &meta_key, value.encode()).unwrap();
+            db.flush_cf(&meta_cf).unwrap();
+
+            let meta_value = db.get_cf(&meta_cf, &meta_key).unwrap().unwrap();
+            redis.note_stale_read(b"k", &meta_value);
+            assert!(redis.stale_ranges.is_empty());
+
+            clock.advance(Duration::from_secs(2));
+            redis.note_stale_read(b"k", &meta_value);
+            redis.note_stale_read(b"k", &meta_value);
+            assert_eq!(redis.stale_ranges.len(), 1);
+            assert_eq!(redis.compact_stale_ranges(16, 3).unwrap(), 0);
+            assert_eq!(redis.compact_stale_ranges(16, 2).unwrap(), 1);
+            assert!(redis.stale_ranges.is_empty());
+            // The meta filter dropped the expired string
+            assert_eq!(db.get_cf(&meta_cf, &meta_key).unwrap(), None);
+        });
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
@@ -17,20 +17,41 @@
  * limitations under the License.
  */
 
+use crate::applied_offset::AppliedOffset;
 use crate::base_value_format::DataType;
+use crate::clock;
+use crate::databases::Databases;
 use crate::error::{MpscSnafu, Result};
+use crate::hot_key_detector::HotKeyDetector;
+use crate::keyspace_events::KeyspaceEventLog;
+use crate::lifecycle_events::{LifecycleEvent, LifecycleEvents, LifecycleSample};
 use crate::options::OptionType;
+use crate::perf_stats::ReadPerfStats;
+use crate::pubsub::PubSub;
 use crate::slot_indexer::SlotIndexer;
+use crate::stale_ranges::stale_ranges_info;
 use crate::{Redis, StorageOptions};
 use foyer::{Cache, CacheBuilder};
+use kstd::cancel::CancelToken;
 use kstd::lock_mgr::LockMgr;
 use snafu::ResultExt;
 use std::collections::HashMap;
+use std::fmt::Write;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::Duration;
 use tokio::sync::mpsc;
 
+/// Minimum number of keys tracked by the hot-key detector.
+const MIN_HOT_KEY_TRACKED: usize = 4096;
+
+/// Most lazy deletion tasks of an instance done in one batch.
+const LAZY_DELETE_BATCH: usize = 256;
+
+/// Interval between two checks of the stale compaction window.
+const STALE_COMPACTION_CHECK: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Clone)]
 pub enum BgTask {
     CleanAll {
@@ -74,6 +95,28 @@ pub struct Storage {
 
     pub cursors_store: Arc<Cache<String, String>>,
 
+    // For read path IO statistics
+    pub perf_stats: ReadPerfStats,
+
+    // For hot-key snapshot, None when disabled
+    pub hot_keys: Option<HotKeyDetector>,
+
+    // For in-process publish/subscribe, see Storage::subscribe
+    pub pubsub: Arc<PubSub>,
+
+    // For replaying keyspace notifications, None when disabled
+    pub keyspace_events: Option<KeyspaceEventLog>,
+
+    // For flush, compaction and stall events, see lifecycle_event_worker
+    pub lifecycle_events: LifecycleEvents,
+
+    // For read-your-writes, the applied binlog offset and the longest wait
+    // for it. None when disabled
+    pub applied_offset: Option<(AppliedOffset, Duration)>,
+
+    // The databases this storage is registered in, see Storage::databases
+    pub(crate) databases: OnceLock<Weak<Databases>>,
+
     // For scan keys in data base
     pub db_instance_num: usize,
     pub db_id: usize,
@@ -89,6 +132,13 @@ impl Storage {
             is_opened: AtomicBool::new(false),
             lock_mgr: Arc::new(LockMgr::new(1000)),
             cursors_store: Arc::new(CacheBuilder::new(1000).build()),
+            perf_stats: ReadPerfStats::new(),
+            hot_keys: None,
+            pubsub: Arc::new(PubSub::default()),
+            keyspace_events: None,
+            lifecycle_events: LifecycleEvents::default(),
+            applied_offset: None,
+            databases: OnceLock::new(),
             db_instance_num,
             db_id,
             bg_task_handler: None,
@@ -107,6 +157,8 @@ impl Storage {
         self.bg_task_handler = Some(Arc::clone(&handler_arc));
 
         let db_path = db_path.as_ref();
+        self.slot_indexer =
+            SlotIndexer::new(self.db_instance_num).with_hash_tags(options.hash_tag_placement);
         let handler_for_redis = Arc::clone(&handler_arc);
         self.insts.clear();
         for i in 0..self.db_instance_num {
@@ -126,6 +178,10 @@ impl Storage {
                 Arc::clone(&handler_for_redis),
                 Arc::clone(&self.lock_mgr),
             );
+            inst.numa_node = options
+                .numa_placement
+                .as_ref()
+                .and_then(|placement| placement.node_of(self.db_id, i, self.db_instance_num));
             if let Err(e) = inst.open(sub_path_str) {
                 log::error!("open RocksDB{i} failed: {e:?}");
                 self.insts.clear();
@@ -137,10 +193,42 @@ impl Storage {
         }
         self.is_opened.store(true, Ordering::SeqCst);
 
+        if options.keyspace_events_replay_len > 0 {
+            self.keyspace_events = Some(KeyspaceEventLog::new(options.keyspace_events_replay_len));
+        }
+
+        if options.read_your_writes_wait_ms > 0 {
+            self.applied_offset = Some((
+                AppliedOffset::new(),
+                Duration::from_millis(options.read_your_writes_wait_ms),
+            ));
+        }
+
+        if options.hot_key_snapshot_size > 0 {
+            self.hot_keys = Some(HotKeyDetector::new(
+                (options.hot_key_snapshot_size * 4).max(MIN_HOT_KEY_TRACKED),
+            ));
+            match self.warmup_from_snapshot(&CancelToken::new()) {
+                Ok(stats) => log::info!(
+                    "warmed up {} hot keys ({} missing, {} data entries)",
+                    stats.keys,
+                    stats.missing,
+                    stats.data_entries
+                ),
+                Err(e) => log::warn!("warm up hot keys failed: {e:?}"),
+            }
+        }
+
         Ok(receiver)
     }
 
     pub async fn shutdown(&mut self) {
+        if self.is_opened.load(Ordering::SeqCst) {
+            if let Err(e) = self.save_hot_keys_snapshot() {
+                log::warn!("save hot keys snapshot failed: {e:?}");
+            }
+        }
+        self.is_opened.store(false, Ordering::SeqCst);
         if let Some(bg_task_handler) = self.bg_task_handler.as_ref() {
             let _ = bg_task_handler.send(BgTask::Shutdown).await;
         }
@@ -176,6 +264,267 @@ impl Storage {
         }
     }
 
+    /// Fsync the WAL of every instance periodically when the durability level
+    /// is `DurabilityLevel::Normal`, returns right away for the other levels.
+    ///
+    /// usage:
+    /// tokio::spawn(Storage::wal_sync_worker(storage.clone()));
+    pub async fn wal_sync_worker(storage: Arc<Storage>) {
+        let Some(options) = storage.insts.first().map(|inst| Arc::clone(&inst.storage)) else {
+            return;
+        };
+        if !options.needs_wal_sync() {
+            return;
+        }
+
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(options.wal_sync_interval_ms));
+        while storage.is_opened.load(Ordering::SeqCst) {
+            interval.tick().await;
+            for inst in &storage.insts {
+                if let Err(e) = inst.sync_wal() {
+                    log::warn!("sync WAL of RocksDB{} failed: {e:?}", inst.index);
+                }
+            }
+        }
+    }
+
+    /// Samples every instance each `lifecycle_poll_interval_ms` and
+    /// publishes their flush, compaction and stall events on
+    /// `lifecycle_events`, returns right away when the interval is 0.
+    ///
+    /// usage:
+    /// tokio::spawn(Storage::lifecycle_event_worker(storage.clone()));
+    pub async fn lifecycle_event_worker(storage: Arc<Storage>) {
+        let Some(options) = storage.insts.first().map(|inst| Arc::clone(&inst.storage)) else {
+            return;
+        };
+        if options.lifecycle_poll_interval_ms == 0 {
+            return;
+        }
+
+        let mut samples = vec![LifecycleSample::default(); storage.insts.len()];
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(options.lifecycle_poll_interval_ms));
+        while storage.is_opened.load(Ordering::SeqCst) {
+            interval.tick().await;
+            for (inst, prev) in storage.insts.iter().zip(samples.iter_mut()) {
+                let sample = match inst.lifecycle_sample() {
+                    Ok(sample) => sample,
+                    Err(e) => {
+                        log::warn!("sample lifecycle of RocksDB{} failed: {e:?}", inst.index);
+                        continue;
+                    }
+                };
+                for kind in sample.events_since(prev) {
+                    storage.lifecycle_events.publish(LifecycleEvent {
+                        instance: inst.index,
+                        kind,
+                    });
+                }
+                *prev = sample;
+            }
+        }
+    }
+
+    /// Deletes the data keys queued by DEL on every instance each
+    /// `lazy_delete_interval_ms`, returns right away when lazy deletion is
+    /// disabled.
+    ///
+    /// usage:
+    /// tokio::spawn(Storage::lazy_delete_worker(storage.clone()));
+    pub async fn lazy_delete_worker(storage: Arc<Storage>) {
+        let Some(options) = storage.insts.first().map(|inst| Arc::clone(&inst.storage)) else {
+            return;
+        };
+        if options.lazy_delete_queue_len == 0 || options.lazy_delete_interval_ms == 0 {
+            return;
+        }
+
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(options.lazy_delete_interval_ms));
+        while storage.is_opened.load(Ordering::SeqCst) {
+            interval.tick().await;
+            for inst in &storage.insts {
+                // One batch per instance and tick, the foreground writes go first
+                if let Err(e) = inst.run_lazy_deletes(LAZY_DELETE_BATCH) {
+                    log::warn!("lazy deletion on RocksDB{} failed: {e:?}", inst.index);
+                }
+            }
+        }
+    }
+
+    /// Runs an active expire cycle on every instance each
+    /// `active_expire_interval_ms`, the instances sharing the time budget of
+    /// the cycle. Returns right away when active expiration is disabled.
+    ///
+    /// usage:
+    /// tokio::spawn(Storage::active_expire_worker(storage.clone()));
+    pub async fn active_expire_worker(storage: Arc<Storage>) {
+        let Some(options) = storage.insts.first().map(|inst| Arc::clone(&inst.storage)) else {
+            return;
+        };
+        if options.active_expire_interval_ms == 0 || options.active_expire_cpu_percent == 0 {
+            return;
+        }
+
+        let period = Duration::from_millis(options.active_expire_interval_ms);
+        let budget = period * options.active_expire_cpu_percent.min(100) as u32
+            / 100
+            / storage.insts.len() as u32;
+        let mut cursors = vec![Vec::new(); storage.insts.len()];
+        let mut interval = tokio::time::interval(period);
+        while storage.is_opened.load(Ordering::SeqCst) {
+            interval.tick().await;
+            for (inst, cursor) in storage.insts.iter().zip(cursors.iter_mut()) {
+                match inst.active_expire_cycle(cursor, budget) {
+                    // The walk starts over once it reached the end
+                    Ok(cycle) => *cursor = cycle.next.unwrap_or_default(),
+                    Err(e) => {
+                        log::warn!("active expire on RocksDB{} failed: {e:?}", inst.index)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compacts the key ranges reads found stale most often on every
+    /// instance, each `STALE_COMPACTION_CHECK` spent inside
+    /// `stale_compaction_window`. Returns right away without a window.
+    ///
+    /// usage:
+    /// tokio::spawn(Storage::stale_compaction_worker(storage.clone()));
+    pub async fn stale_compaction_worker(storage: Arc<Storage>) {
+        let Some(options) = storage.insts.first().map(|inst| Arc::clone(&inst.storage)) else {
+            return;
+        };
+        let Some(window) = options.stale_compaction_window else {
+            return;
+        };
+        if options.stale_compaction_ranges == 0 {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(STALE_COMPACTION_CHECK);
+        while storage.is_opened.load(Ordering::SeqCst) {
+            interval.tick().await;
+            if !window.contains(clock::now_micros()) {
+                continue;
+            }
+            let storage = Arc::clone(&storage);
+            let options = Arc::clone(&options);
+            // A manual compaction may run for minutes, keep it off the runtime
+            let compacted = tokio::task::spawn_blocking(move || {
+                for inst in &storage.insts {
+                    match inst.compact_stale_ranges(
+                        options.stale_compaction_ranges,
+                        options.stale_compaction_min_reads,
+                    ) {
+                        Ok(0) => {}
+                        Ok(n) => {
+                            log::info!("compacted {n} stale key ranges on RocksDB{}", inst.index)
+                        }
+                        Err(e) => {
+                            log::warn!(
+                                "stale range compaction on RocksDB{} failed: {e:?}",
+                                inst.index
+                            )
+                        }
+                    }
+                }
+            })
+            .await;
+            if let Err(e) = compacted {
+                log::warn!("stale range compaction panicked: {e}");
+            }
+        }
+    }
+
+    /// Sets the time from which the compaction filters of every instance
+    /// keep the removed data, for a lagging replica or a full sync, or
+    /// clears it with None. See `SharedFilterConfig::set_reclaim_fence`.
+    pub fn set_reclaim_fence(&self, fence: Option<u64>) {
+        // The instances share the options, and so the filter config
+        if let Some(inst) = self.insts.first() {
+            inst.storage.filter_config.set_reclaim_fence(fence);
+        }
+    }
+
+    /// Formats the stale entry counters and the keys tracked on every
+    /// instance as the staleranges section of INFO.
+    pub fn stale_ranges_info(&self) -> String {
+        let mut instances = String::new();
+        for inst in &self.insts {
+            inst.stale_ranges_info(&mut instances);
+        }
+        stale_ranges_info(&instances)
+    }
+
+    /// Formats the lazy deletion queue of every instance as the lazydelete
+    /// section of INFO.
+    pub fn lazy_delete_info(&self) -> String {
+        let mut info = String::from("# LazyDelete\r\n");
+        for inst in &self.insts {
+            inst.lazy_delete_info(&mut info);
+        }
+        info
+    }
+
+    /// Formats the NUMA nodes and the node of every instance as the numa
+    /// section of INFO.
+    pub fn numa_info(&self) -> String {
+        let mut info = String::from("# Numa\r\n");
+        let placement = self
+            .insts
+            .first()
+            .and_then(|inst| inst.storage.numa_placement.as_ref());
+        let Some(placement) = placement else {
+            info.push_str("numa_enabled:0\r\n");
+            return info;
+        };
+        let _ = write!(
+            info,
+            "numa_enabled:1\r\nnuma_nodes:{}\r\nnuma_pinned_threads:{}\r\n",
+            placement.topology.nodes.len(),
+            kstd::numa::pinned_threads()
+        );
+        for (i, node) in placement.topology.nodes.iter().enumerate() {
+            let (cache_mb, cache_used_mb) = placement.block_cache(i).map_or((0, 0), |cache| {
+                (placement.node_cache_size >> 20, cache.get_usage() >> 20)
+            });
+            let _ = write!(
+                info,
+                "node{}:cpus={},memory_mb={},block_cache_mb={},block_cache_used_mb={}\r\n",
+                node.id,
+                node.cpus.len(),
+                node.memory_bytes.map_or(0, |bytes| bytes >> 20),
+                cache_mb,
+                cache_used_mb,
+            );
+        }
+        for inst in &self.insts {
+            if let Some(node) = inst.numa_node {
+                let _ = write!(
+                    info,
+                    "instance{}_numa_node:{}\r\n",
+                    inst.index, placement.topology.nodes[node].id
+                );
+            }
+        }
+        info
+    }
+
+    /// Formats the lifecycle event counters and the current write stall of
+    /// every instance as the lifecycle section of INFO.
+    pub fn lifecycle_info(&self) -> String {
+        let stalls: Vec<_> = self
+            .insts
+            .iter()
+            .filter_map(|inst| Some((inst.index, inst.lifecycle_sample().ok()?.stall)))
+            .collect();
+        self.lifecycle_events.info(&stalls)
+    }
+
     fn set_option(&self, option_type: OptionType, options: &HashMap<String, String>) -> Result<()> {
         for inst in &self.insts {
             inst.set_option(option_type, options)?;
@@ -21,7 +21,16 @@ pub const PREFIX_RESERVE_LENGTH: usize = 8;
 pub const VERSION_LENGTH: usize = 8;
 // const SCORE_LENGTH: usize = 8;
 pub const SUFFIX_RESERVE_LENGTH: usize = 16;
-// const LIST_VALUE_INDEX_LENGTH: usize = 16;
+// The value checksum takes the first bytes of the suffix reserve, followed
+// by a byte of flags, see checksum.rs
+pub const RESERVE_CHECKSUM_LENGTH: usize = 4;
+pub const RESERVE_FLAGS_OFFSET: usize = RESERVE_CHECKSUM_LENGTH;
+// The format version byte follows the flags, see format_version.rs
+pub const RESERVE_VERSION_OFFSET: usize = RESERVE_FLAGS_OFFSET + 1;
+pub const LIST_VALUE_INDEX_LENGTH: usize = 8;
+// How long the compaction filter keeps an empty collection meta value after
+// its ctime, in microseconds
+pub const EMPTY_META_GRACE_MICROS: u64 = 60_000_000;
 
 // used to store a fixed-size value for the Type field.
 pub const TYPE_LENGTH: usize = 1;
@@ -36,12 +45,15 @@ pub const ENCODED_KEY_DELIM_SIZE: usize = 2;
 
 pub const STRING_VALUE_SUFFIXLENGTH: usize = 2 * TIMESTAMP_LENGTH + SUFFIX_RESERVE_LENGTH;
 pub const BASE_META_VALUE_COUNT_LENGTH: usize = 8;
-/// type(1B) + len(4B) + version(8B) + reserve(16B) + cdata(8B) + timestamp(8B)
+/// type(1B) + count(8B) + version(8B) + reserve(16B) + cdata(8B) + timestamp(8B)
 pub const BASE_META_VALUE_LENGTH: usize = TYPE_LENGTH
     + BASE_META_VALUE_COUNT_LENGTH
     + VERSION_LENGTH
     + SUFFIX_RESERVE_LENGTH
     + 2 * TIMESTAMP_LENGTH;
+/// type(1B) + count(8B) + version(8B) + left index(8B) + right index(8B) + reserve(16B)
+/// + cdata(8B) + timestamp(8B)
+pub const LISTS_META_VALUE_LENGTH: usize = BASE_META_VALUE_LENGTH + 2 * LIST_VALUE_INDEX_LENGTH;
 
 use crate::error::{InvalidFormatSnafu, Result};
 use bytes::{BufMut, BytesMut};
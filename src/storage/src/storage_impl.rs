@@ -17,9 +17,21 @@
  * limitations under the License.
  */
 
-use crate::error::Result;
-use crate::slot_indexer::key_to_slot_id;
+use crate::base_value_format::DataType;
+use crate::error::{CrossSlotSnafu, KeyNotFoundSnafu, OffsetNotAppliedSnafu, Result};
+use crate::keyspace_events::{keyevent_channel, keyspace_channel, KeyspaceEvent};
+use crate::pipeline::{Pipeline, PipelineOp, PipelineResult};
+use crate::pubsub::Subscription;
+use crate::redis_hash_fields::FieldExpiry;
+use crate::redis_list_queue::ListEnd;
+use crate::redis_strings::SetOptions;
+use crate::results::{DelResult, ExistsResult, PushResult, SetResult, UndeleteResult};
 use crate::storage::Storage;
+use crate::value_decode::{decode_meta_value, DecodedField};
+use crate::warmup::{load_hot_keys, WarmupStats, WarmupTarget};
+use kstd::cancel::CancelToken;
+use std::path::Path;
+use std::time::Duration;
 
 // use crate::base_data_value_format::DataType;
 // use crate::storage::{Storage, Status, KeyValue, ValueStatus, FieldValue, ScoreMember, BitOpType, BeforeOrAfter, BGTask, Operation, AGGREGATE};
@@ -30,16 +42,487 @@ impl Storage {
 
     // Set key to hold the string value. if key
     // already holds a value, it is overwritten
-    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        let slot_id = key_to_slot_id(key);
-        let instance_id = self.slot_indexer.get_instance_id(slot_id);
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<SetResult> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
         self.insts[instance_id].set(key, value)
     }
 
+    // SET with the NX or XX condition and the GET option
+    pub fn set_with(&self, key: &[u8], value: &[u8], options: SetOptions) -> Result<SetResult> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        self.insts[instance_id].set_with(key, value, options)
+    }
+
     pub fn get(&self, key: &[u8]) -> Result<String> {
-        let slot_id = key_to_slot_id(key);
-        let instance_id = self.slot_indexer.get_instance_id(slot_id);
-        self.insts[instance_id].get(key)
+        if let Some(hot_keys) = &self.hot_keys {
+            hot_keys.record(key);
+        }
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        self.perf_stats
+            .measure(DataType::String, || self.insts[instance_id].get(key))
+    }
+
+    // Hashes Commands Implementation
+
+    // Returns the values of the fields and deletes them, None for the fields
+    // that do not exist. The hash is deleted with its last field
+    pub fn hgetdel(&self, key: &[u8], fields: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        let fields: Vec<&[u8]> = fields.iter().map(|field| &field[..]).collect();
+        let values = self.perf_stats.measure(DataType::Hash, || {
+            self.insts[instance_id].hgetdel(key, &fields)
+        })?;
+        if values.iter().any(Option::is_some) {
+            self.notify_keyspace_event("hdel", key);
+        }
+        Ok(values)
+    }
+
+    // Returns the values of the fields and sets or clears their expiration
+    // time, None for the fields that do not exist
+    pub fn hgetex(
+        &self,
+        key: &[u8],
+        fields: &[Vec<u8>],
+        expiry: FieldExpiry,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        let fields: Vec<&[u8]> = fields.iter().map(|field| &field[..]).collect();
+        let values = self.perf_stats.measure(DataType::Hash, || {
+            self.insts[instance_id].hgetex(key, &fields, expiry)
+        })?;
+        if values.iter().any(Option::is_some) {
+            match expiry {
+                FieldExpiry::Keep => {}
+                FieldExpiry::Persist => self.notify_keyspace_event("hpersist", key),
+                FieldExpiry::At(_) => self.notify_keyspace_event("hexpire", key),
+            }
+        }
+        Ok(values)
+    }
+
+    // Lists Commands Implementation
+
+    // Inserts the values one after the other at end of the list key, creating
+    // it when it does not exist. Returns the length of the list
+    pub fn push(&self, key: &[u8], end: ListEnd, values: &[Vec<u8>]) -> Result<PushResult> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        let values: Vec<&[u8]> = values.iter().map(|value| &value[..]).collect();
+        let len = self.insts[instance_id].push(key, end, &values)?;
+        self.notify_keyspace_event(end.push_event(), key);
+        Ok(PushResult::new(len))
+    }
+
+    // Removes and returns up to count elements from end of the list key
+    pub fn pop(&self, key: &[u8], end: ListEnd, count: usize) -> Result<Vec<Vec<u8>>> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        let values = self.perf_stats.measure(DataType::List, || {
+            self.insts[instance_id].pop(key, end, count)
+        })?;
+        if !values.is_empty() {
+            self.notify_keyspace_event(end.pop_event(), key);
+        }
+        Ok(values)
+    }
+
+    // Pops up to count elements from end of the first non-empty list of keys.
+    // Returns that key with its elements, None when every list is empty
+    pub fn lmpop(
+        &self,
+        keys: &[Vec<u8>],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<(Vec<u8>, Vec<Vec<u8>>)>> {
+        for key in keys {
+            let values = self.pop(key, end, count)?;
+            if !values.is_empty() {
+                return Ok(Some((key.clone(), values)));
+            }
+        }
+        Ok(None)
+    }
+
+    // Pops like lmpop, and when every list is empty waits up to timeout for a
+    // push to one of them, forever when timeout is zero. None when the
+    // timeout elapses first. The keyspace channels are subscribed before the
+    // first try, so a push in between still wakes the wait
+    pub async fn blmpop(
+        &self,
+        keys: &[Vec<u8>],
+        end: ListEnd,
+        count: usize,
+        timeout: Duration,
+    ) -> Result<Option<(Vec<u8>, Vec<Vec<u8>>)>> {
+        let channels: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|key| keyspace_channel(self.db_id, key))
+            .collect();
+        let channels: Vec<&[u8]> = channels.iter().map(|channel| &channel[..]).collect();
+        let mut subscription = self.subscribe(&channels);
+        let deadline = (!timeout.is_zero()).then(|| tokio::time::Instant::now() + timeout);
+        loop {
+            if let Some(popped) = self.lmpop(keys, end, count)? {
+                return Ok(Some(popped));
+            }
+            let message = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, subscription.recv())
+                    .await
+                    .ok()
+                    .flatten(),
+                None => subscription.recv().await,
+            };
+            if message.is_none() {
+                return Ok(None);
+            }
+        }
+    }
+
+    // Atomically pops the element at from of source and pushes it at to of
+    // destination, both lists have to be on the same instance. Claiming a job
+    // of a reliable queue is lmove(queue, processing, Right, Left). Returns
+    // the element, None when source is empty
+    pub fn lmove(
+        &self,
+        source: &[u8],
+        destination: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Vec<u8>>> {
+        let instance_id = self.slot_indexer.key_to_instance_id(source);
+        if self.slot_indexer.key_to_instance_id(destination) != instance_id {
+            return CrossSlotSnafu.fail();
+        }
+        let value = self.perf_stats.measure(DataType::List, || {
+            self.insts[instance_id].lmove(source, destination, from, to)
+        })?;
+        if value.is_some() {
+            self.notify_keyspace_event(from.pop_event(), source);
+            self.notify_keyspace_event(to.push_event(), destination);
+        }
+        Ok(value)
+    }
+
+    // Removes count elements equal to element from the head of the list key,
+    // -count from the tail when negative, or all of them when zero. Acking a
+    // claimed job is lrem(processing, 1, job). Returns the number removed
+    pub fn lrem(&self, key: &[u8], count: i64, element: &[u8]) -> Result<u64> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        let removed = self.perf_stats.measure(DataType::List, || {
+            self.insts[instance_id].lrem(key, count, element)
+        })?;
+        if removed > 0 {
+            self.notify_keyspace_event("lrem", key);
+        }
+        Ok(removed)
+    }
+
+    // Keyspace Commands Implementation
+
+    // Returns all the keys matching pattern. The scan stops early when token
+    // is cancelled or when it runs longer than max_scan_time_ms
+    pub fn keys(&self, pattern: &[u8], token: &CancelToken) -> Result<Vec<Vec<u8>>> {
+        let token = self.scan_token(token);
+
+        self.perf_stats.measure(DataType::All, || {
+            let mut keys = Vec::new();
+            for inst in &self.insts {
+                keys.extend(inst.scan_keys(pattern, &token)?);
+            }
+            Ok(keys)
+        })
+    }
+
+    // Like keys, for the keys of type data_type only (SCAN ... TYPE). The
+    // entries of other types are counted in perf_stats
+    pub fn keys_of_type(
+        &self,
+        pattern: &[u8],
+        data_type: DataType,
+        token: &CancelToken,
+    ) -> Result<Vec<Vec<u8>>> {
+        let token = self.scan_token(token);
+
+        self.perf_stats.measure(DataType::All, || {
+            let mut keys = Vec::new();
+            for inst in &self.insts {
+                keys.extend(inst.scan_keys_of_type(
+                    pattern,
+                    data_type,
+                    &token,
+                    &self.perf_stats,
+                )?);
+            }
+            Ok(keys)
+        })
+    }
+
+    // The token a keyspace scan runs with, expiring after max_scan_time_ms
+    fn scan_token(&self, token: &CancelToken) -> CancelToken {
+        match self.insts.first().map(|inst| inst.storage.max_scan_time_ms) {
+            Some(max_ms) if max_ms > 0 => token.with_timeout(Duration::from_millis(max_ms)),
+            _ => token.clone(),
+        }
+    }
+
+    // Returns the type of every key, None for the keys that do not exist.
+    // Every instance reads its keys with one MultiGet
+    pub fn key_types(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<DataType>>> {
+        let mut per_instance: Vec<Vec<usize>> = vec![Vec::new(); self.insts.len()];
+        for (pos, key) in keys.iter().enumerate() {
+            per_instance[self.slot_indexer.key_to_instance_id(key)].push(pos);
+        }
+
+        self.perf_stats.measure(DataType::All, || {
+            let mut types = vec![None; keys.len()];
+            for (inst, positions) in self.insts.iter().zip(&per_instance) {
+                if positions.is_empty() {
+                    continue;
+                }
+                let inst_keys: Vec<&[u8]> = positions.iter().map(|&pos| &keys[pos][..]).collect();
+                for (&pos, data_type) in positions.iter().zip(inst.key_types(&inst_keys)?) {
+                    types[pos] = data_type;
+                }
+            }
+            Ok(types)
+        })
+    }
+
+    // Returns how the value of key is stored, None when it does not exist
+    pub fn object_encoding(&self, key: &[u8]) -> Result<Option<&'static str>> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        self.perf_stats.measure(DataType::All, || {
+            self.insts[instance_id].object_encoding(key)
+        })
+    }
+
+    // Returns the number of keys that exist, a key is counted as many times
+    // as it is repeated
+    pub fn exists(&self, keys: &[Vec<u8>]) -> Result<ExistsResult> {
+        let existing = self.key_types(keys)?.iter().flatten().count();
+        Ok(ExistsResult::new(existing))
+    }
+
+    // Same as exists, and counts an access to every existing key for the
+    // hot-key detector
+    pub fn touch(&self, keys: &[Vec<u8>]) -> Result<ExistsResult> {
+        let types = self.key_types(keys)?;
+        if let Some(hot_keys) = &self.hot_keys {
+            for (key, _) in keys.iter().zip(&types).filter(|(_, t)| t.is_some()) {
+                hot_keys.record(key);
+            }
+        }
+        Ok(ExistsResult::new(types.iter().flatten().count()))
+    }
+
+    // Deletes the existing keys and returns how many were deleted. Deleted
+    // keys go to the trash when soft deletion is enabled
+    pub fn del(&self, keys: &[Vec<u8>]) -> Result<DelResult> {
+        let mut deleted = 0;
+        for key in keys {
+            let instance_id = self.slot_indexer.key_to_instance_id(key);
+            if self.insts[instance_id].del(&[&key[..]])? > 0 {
+                self.notify_keyspace_event("del", key);
+                deleted += 1;
+            }
+        }
+        Ok(DelResult::new(deleted))
+    }
+
+    // Restores a key from the trash, false when there is nothing to restore
+    pub fn undelete(&self, key: &[u8]) -> Result<UndeleteResult> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        let restored = self.insts[instance_id].undelete(key)?;
+        if restored {
+            self.notify_keyspace_event("undelete", key);
+        }
+        Ok(UndeleteResult::new(restored))
+    }
+
+    // Publishes a keyspace notification and records it for replay when the
+    // replay buffer is enabled
+    pub fn notify_keyspace_event(&self, event: &'static str, key: &[u8]) {
+        if let Some(log) = &self.keyspace_events {
+            log.record(event, key);
+        }
+        self.pubsub
+            .publish(&keyspace_channel(self.db_id, key), event.as_bytes());
+        self.pubsub
+            .publish(&keyevent_channel(self.db_id, event), key.to_vec());
+    }
+
+    // The keyspace notifications after seq, for a subscriber that reconnects.
+    // None when the replay buffer is disabled or no longer holds them all
+    pub fn keyspace_events_since(&self, seq: u64) -> Option<Vec<KeyspaceEvent>> {
+        self.keyspace_events.as_ref()?.since(seq)
+    }
+
+    // The last count keyspace notifications, empty when the replay buffer is
+    // disabled
+    pub fn last_keyspace_events(&self, count: usize) -> Vec<KeyspaceEvent> {
+        self.keyspace_events
+            .as_ref()
+            .map_or_else(Vec::new, |log| log.last(count))
+    }
+
+    // Records that the binlog was applied up to offset, waking up the reads
+    // waiting for it. Does nothing when read-your-writes is disabled
+    pub fn advance_applied_offset(&self, offset: u64) {
+        if let Some((applied, _)) = &self.applied_offset {
+            applied.advance(offset);
+        }
+    }
+
+    // The applied binlog offset, the one write replies carry. None when
+    // read-your-writes is disabled
+    pub fn applied_offset(&self) -> Option<u64> {
+        self.applied_offset
+            .as_ref()
+            .map(|(applied, _)| applied.current())
+    }
+
+    // Waits until the binlog offset a client passed is applied, so that its
+    // read sees its own writes. Fails with Error::OffsetNotApplied once
+    // read_your_writes_wait_ms has passed. Returns right away when
+    // read-your-writes is disabled
+    pub fn wait_for_offset(&self, offset: u64) -> Result<()> {
+        let Some((applied, max_wait)) = &self.applied_offset else {
+            return Ok(());
+        };
+        match applied.wait_for(offset, *max_wait) {
+            Some(_) => Ok(()),
+            None => OffsetNotAppliedSnafu {
+                offset,
+                applied: applied.current(),
+            }
+            .fail(),
+        }
+    }
+
+    // Subscribes to channels of this storage, the subscription is an async
+    // stream of the published messages
+    pub fn subscribe(&self, channels: &[&[u8]]) -> Subscription {
+        self.pubsub.subscribe(channels)
+    }
+
+    // Subscribes to the channels of this storage matching the glob patterns
+    pub fn psubscribe(&self, patterns: &[&[u8]]) -> Subscription {
+        self.pubsub.psubscribe(patterns)
+    }
+
+    // Publishes payload to channel, returns the number of receivers
+    pub fn publish(&self, channel: &[u8], payload: impl Into<bytes::Bytes>) -> usize {
+        self.pubsub.publish(channel, payload)
+    }
+
+    // Applies the writes of pipeline and returns their results in order. The
+    // writes of every instance are committed atomically, so a pipeline is
+    // atomic when its keys share a hash tag
+    pub fn execute_pipeline(&self, pipeline: &Pipeline) -> Result<Vec<PipelineResult>> {
+        let mut per_instance: Vec<Vec<usize>> = vec![Vec::new(); self.insts.len()];
+        for (pos, op) in pipeline.ops().iter().enumerate() {
+            per_instance[self.slot_indexer.key_to_instance_id(op.key())].push(pos);
+        }
+
+        let mut results = vec![PipelineResult::Set; pipeline.len()];
+        for (inst, positions) in self.insts.iter().zip(&per_instance) {
+            if positions.is_empty() {
+                continue;
+            }
+            let ops: Vec<&PipelineOp> = positions.iter().map(|&pos| &pipeline.ops()[pos]).collect();
+            for (&pos, result) in positions.iter().zip(inst.execute_pipeline(&ops)?) {
+                results[pos] = result;
+            }
+        }
+        Ok(results)
+    }
+
+    // Warm-up Implementation
+
+    // Loads the index and data blocks of the target keys into the block cache.
+    // Keys are routed to the instance owning them, a prefix is warmed on
+    // every instance
+    pub fn warmup(&self, target: &WarmupTarget, token: &CancelToken) -> Result<WarmupStats> {
+        let mut stats = WarmupStats::default();
+        match target {
+            WarmupTarget::Keys(keys) => {
+                let per_instance = self.group_keys_by_instance(keys.iter().cloned());
+                for (inst, keys) in self.insts.iter().zip(&per_instance) {
+                    if !keys.is_empty() {
+                        stats.merge(&inst.warmup_keys(keys, token)?);
+                    }
+                }
+            }
+            WarmupTarget::Prefix(prefix) => {
+                for inst in &self.insts {
+                    stats.merge(&inst.warmup_prefix(prefix, token)?);
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    // Warms the keys of a hot-key list saved by warmup::save_hot_keys. A
+    // missing list warms nothing
+    pub fn warmup_from_file(
+        &self,
+        path: impl AsRef<Path>,
+        token: &CancelToken,
+    ) -> Result<WarmupStats> {
+        let keys = load_hot_keys(path)?;
+        self.warmup(&WarmupTarget::Keys(keys), token)
+    }
+
+    // Warms the keys saved by save_hot_keys_snapshot at the last shutdown,
+    // every instance reads its own snapshot
+    pub fn warmup_from_snapshot(&self, token: &CancelToken) -> Result<WarmupStats> {
+        let mut stats = WarmupStats::default();
+        for inst in &self.insts {
+            let keys = inst.load_hot_keys_snapshot()?;
+            if !keys.is_empty() {
+                stats.merge(&inst.warmup_keys(&keys, token)?);
+            }
+        }
+        Ok(stats)
+    }
+
+    // Saves the top hot_key_snapshot_size keys of the hot-key detector to the
+    // system column family of the instance owning them
+    pub fn save_hot_keys_snapshot(&self) -> Result<()> {
+        let Some(detector) = &self.hot_keys else {
+            return Ok(());
+        };
+        let size = self
+            .insts
+            .first()
+            .map_or(0, |inst| inst.storage.hot_key_snapshot_size);
+        let per_instance = self.group_keys_by_instance(detector.top(size));
+        for (inst, keys) in self.insts.iter().zip(&per_instance) {
+            inst.save_hot_keys_snapshot(keys)?;
+        }
+        Ok(())
+    }
+
+    // Debug Commands Implementation
+
+    // Decodes every field of the meta value of key, expired or not
+    pub fn debug_decode_key(&self, key: &[u8]) -> Result<Vec<DecodedField>> {
+        let instance_id = self.slot_indexer.key_to_instance_id(key);
+        match self.insts[instance_id].raw_meta_value(key)? {
+            Some(raw) => decode_meta_value(&raw).map_err(|e| e.with_key(key)),
+            None => KeyNotFoundSnafu {
+                key: String::from_utf8_lossy(key).to_string(),
+            }
+            .fail(),
+        }
+    }
+
+    fn group_keys_by_instance(&self, keys: impl IntoIterator<Item = Vec<u8>>) -> Vec<Vec<Vec<u8>>> {
+        let mut per_instance = vec![Vec::new(); self.insts.len()];
+        for key in keys {
+            let instance_id = self.slot_indexer.key_to_instance_id(&key);
+            per_instance[instance_id].push(key);
+        }
+        per_instance
     }
 
     // // Atomically sets key to value and returns the old value stored at key
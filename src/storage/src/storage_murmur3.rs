@@ -75,6 +75,41 @@ pub fn murmur3_32<T: AsRef<[u8]>>(data: T, seed: u32) -> u32 {
     hash
 }
 
+// MurmurHash64A, the hash Redis uses for HyperLogLog elements
+pub fn murmur64a<T: AsRef<[u8]>>(data: T, seed: u64) -> u64 {
+    let data = data.as_ref();
+    let m: u64 = 0xc6a4a7935bd1e995;
+    let r = 47;
+    let mut hash = seed ^ (data.len() as u64).wrapping_mul(m);
+
+    let chunks = data.chunks_exact(8);
+    let remainder = chunks.remainder();
+
+    for chunk in chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+
+        k = k.wrapping_mul(m);
+        k ^= k >> r;
+        k = k.wrapping_mul(m);
+
+        hash ^= k;
+        hash = hash.wrapping_mul(m);
+    }
+
+    if !remainder.is_empty() {
+        for (i, byte) in remainder.iter().enumerate() {
+            hash ^= (*byte as u64) << (8 * i);
+        }
+        hash = hash.wrapping_mul(m);
+    }
+
+    hash ^= hash >> r;
+    hash = hash.wrapping_mul(m);
+    hash ^= hash >> r;
+
+    hash
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -120,4 +155,18 @@ mod tests {
     fn test_unicode() {
         assert_eq!(murmur3_32("€", 0), 0x5b43fca5);
     }
+
+    #[test]
+    fn test_murmur64a() {
+        assert_eq!(murmur64a("", 0), 0);
+        assert_eq!(murmur64a("hello", 0), 0x1e68d17c457bf117);
+        assert_eq!(murmur64a("hello, world", 0), 0x9659ad0699a8465f);
+        assert_eq!(
+            murmur64a("The quick brown fox jumps over the lazy dog.", 0),
+            0x8adb11747aa7b565
+        );
+
+        assert_eq!(murmur64a("", 0xadc83b19), 0xd8dfea6585bc9732);
+        assert_eq!(murmur64a("hello", 0xadc83b19), 0x0f656f01eecfe400);
+    }
 }
@@ -0,0 +1,376 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg_attr(not(test), allow(dead_code))]
+
+use crate::coding::{decode_fixed, encode_fixed};
+use crate::error::Result;
+use crate::storage_define::{
+    decode_user_key, encode_user_key, ENCODED_KEY_DELIM_SIZE, NEED_TRANSFORM_CHARACTER,
+};
+use bytes::BytesMut;
+use std::fmt;
+use std::str::FromStr;
+
+// Constants for fixed-length fields
+const RESERVE1_LEN: usize = 8;
+const RESERVE2_LEN: usize = 16;
+const U64_LEN: usize = 8;
+pub const STREAM_ID_LENGTH: usize = 16;
+
+/// The ID of a stream entry, `<ms>-<seq>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Encodes the ID big-endian, so encoded IDs sort like the IDs.
+    pub fn encode(&self) -> [u8; STREAM_ID_LENGTH] {
+        let mut dst = [0u8; STREAM_ID_LENGTH];
+        dst[..U64_LEN].copy_from_slice(&self.ms.to_be_bytes());
+        dst[U64_LEN..].copy_from_slice(&self.seq.to_be_bytes());
+        dst
+    }
+
+    pub fn decode(src: &[u8]) -> Result<Self> {
+        if src.len() != STREAM_ID_LENGTH {
+            return Err(crate::error::Error::InvalidFormat {
+                message: format!("invalid stream id length: {}", src.len()),
+                location: snafu::location!(),
+            });
+        }
+        let mut ms = [0u8; U64_LEN];
+        ms.copy_from_slice(&src[..U64_LEN]);
+        let mut seq = [0u8; U64_LEN];
+        seq.copy_from_slice(&src[U64_LEN..]);
+        Ok(Self {
+            ms: u64::from_be_bytes(ms),
+            seq: u64::from_be_bytes(seq),
+        })
+    }
+
+    /// The smallest ID greater than this one, `None` for `StreamId::MAX`.
+    pub fn next(&self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self::new(self.ms, seq)),
+            None => self.ms.checked_add(1).map(|ms| Self::new(ms, 0)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Parses `<ms>-<seq>`, or `<ms>` alone with a sequence number of 0.
+impl FromStr for StreamId {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || crate::error::Error::InvalidFormat {
+            message: format!("invalid stream id: {s}"),
+            location: snafu::location!(),
+        };
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse().map_err(|_| invalid())?),
+            None => (s, 0),
+        };
+        Ok(Self::new(ms.parse().map_err(|_| invalid())?, seq))
+    }
+}
+
+/*
+ * Format for Stream data key
+ * | reserve1 | key | version | id  | reserve2 |
+ * |    8B    |     |    8B   | 16B |   16B    |
+ *
+ * The id is stored big-endian, so the entries of one stream version sort by
+ * id and XRANGE is a forward scan from the start id to the end id.
+ */
+pub struct StreamsDataKey {
+    reserve1: [u8; 8],
+    key: Vec<u8>,
+    version: u64,
+    id: StreamId,
+    reserve2: [u8; 16],
+}
+
+impl StreamsDataKey {
+    pub fn new(key: &[u8], version: u64, id: StreamId) -> Self {
+        Self::with_reserves(key, version, id, [0; 8], [0; 16])
+    }
+
+    pub fn with_reserves(
+        key: &[u8],
+        version: u64,
+        id: StreamId,
+        reserve1: [u8; 8],
+        reserve2: [u8; 16],
+    ) -> Self {
+        Self {
+            reserve1,
+            key: key.to_vec(),
+            version,
+            id,
+            reserve2,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let nzero = self
+            .key
+            .iter()
+            .filter(|&&c| c == NEED_TRANSFORM_CHARACTER as u8)
+            .count();
+        let encoded_key_len = self.key.len() + nzero + ENCODED_KEY_DELIM_SIZE;
+        let needed = RESERVE1_LEN + encoded_key_len + U64_LEN + STREAM_ID_LENGTH + RESERVE2_LEN;
+        let mut dst = Vec::with_capacity(needed);
+
+        // 1. reserve1 (8 bytes)
+        dst.extend_from_slice(&self.reserve1);
+
+        // 2. encoded user key
+        let mut temp_buf = BytesMut::with_capacity(encoded_key_len);
+        encode_user_key(&self.key, &mut temp_buf)?;
+        dst.extend_from_slice(&temp_buf);
+
+        // 3. version (8 bytes)
+        let mut version_buf = [0u8; U64_LEN];
+        encode_fixed(&mut version_buf, self.version);
+        dst.extend_from_slice(&version_buf);
+
+        // 4. id (16 bytes, big-endian to keep the id order)
+        dst.extend_from_slice(&self.id.encode());
+
+        // 5. reserve2 (16 bytes)
+        dst.extend_from_slice(&self.reserve2);
+
+        Ok(dst)
+    }
+
+    /// Encodes the key without the trailing reserve2. Used as the start (or
+    /// exclusive end) of an id range scan: it sorts before the data key of
+    /// `id` and after the data keys of all the smaller ids.
+    pub fn encode_seek_key(&self) -> Result<Vec<u8>> {
+        let mut dst = self.encode()?;
+        dst.truncate(dst.len() - RESERVE2_LEN);
+        Ok(dst)
+    }
+
+    /// Encodes `| reserve1 | key | version |`, the prefix shared by all the
+    /// entries of one stream version.
+    pub fn encode_prefix(&self) -> Result<Vec<u8>> {
+        let mut dst = self.encode()?;
+        dst.truncate(dst.len() - STREAM_ID_LENGTH - RESERVE2_LEN);
+        Ok(dst)
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        &self.reserve1
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        &self.reserve2
+    }
+}
+
+pub struct ParsedStreamsDataKey {
+    key_str: Vec<u8>,
+    reserve1: [u8; 8],
+    version: u64,
+    id: StreamId,
+    reserve2: [u8; 16],
+}
+
+impl ParsedStreamsDataKey {
+    pub fn from_slice(key: &[u8]) -> Result<Self> {
+        Self::decode(key)
+    }
+
+    pub fn decode(key: &[u8]) -> Result<Self> {
+        let min_len = RESERVE1_LEN + RESERVE2_LEN;
+        if key.len() < min_len {
+            return Err(crate::error::Error::InvalidFormat {
+                message: "Key too short for reserve fields".to_string(),
+                location: snafu::location!(),
+            });
+        }
+
+        // skip head reserve1 and tail reserve2
+        let encoded_key_start = RESERVE1_LEN;
+        let encoded_key_end = key.len() - RESERVE2_LEN;
+        let encoded_key_slice = &key[encoded_key_start..encoded_key_end];
+
+        // user key zeros are escaped, so the first "\x00\x00" is the delimiter
+        let pos = encoded_key_slice
+            .windows(ENCODED_KEY_DELIM_SIZE)
+            .position(|window| window == b"\x00\x00")
+            .map(|p| p + ENCODED_KEY_DELIM_SIZE)
+            .ok_or_else(|| crate::error::Error::InvalidFormat {
+                message: "Encoded key delimiter not found".to_string(),
+                location: snafu::location!(),
+            })?;
+
+        let mut key_str_buf = BytesMut::with_capacity(pos);
+        decode_user_key(&encoded_key_slice[..pos], &mut key_str_buf)?;
+        let key_str = key_str_buf.to_vec();
+
+        // version & id follow immediately after the encoded key and must end
+        // exactly before reserve2
+        let version_offset = encoded_key_start + pos;
+        let id_offset = version_offset + U64_LEN;
+        if id_offset + STREAM_ID_LENGTH != encoded_key_end {
+            return Err(crate::error::Error::InvalidFormat {
+                message: "Invalid length of version/id fields".to_string(),
+                location: snafu::location!(),
+            });
+        }
+
+        let version = decode_fixed(&key[version_offset..id_offset]);
+        let id = StreamId::decode(&key[id_offset..encoded_key_end])?;
+
+        let mut reserve1 = [0u8; RESERVE1_LEN];
+        reserve1.copy_from_slice(&key[..RESERVE1_LEN]);
+        let mut reserve2 = [0u8; RESERVE2_LEN];
+        reserve2.copy_from_slice(&key[encoded_key_end..]);
+
+        Ok(Self {
+            key_str,
+            reserve1,
+            version,
+            id,
+            reserve2,
+        })
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key_str
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        &self.reserve1
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        &self.reserve2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_id_parse_and_display() {
+        let id: StreamId = "1526919030474-55".parse().unwrap();
+        assert_eq!(id, StreamId::new(1526919030474, 55));
+        assert_eq!(id.to_string(), "1526919030474-55");
+
+        assert_eq!("42".parse::<StreamId>().unwrap(), StreamId::new(42, 0));
+        assert!("".parse::<StreamId>().is_err());
+        assert!("1-".parse::<StreamId>().is_err());
+        assert!("a-1".parse::<StreamId>().is_err());
+        assert!("-1".parse::<StreamId>().is_err());
+    }
+
+    #[test]
+    fn test_stream_id_order() {
+        let ids = [
+            StreamId::MIN,
+            StreamId::new(0, 1),
+            StreamId::new(1, 0),
+            StreamId::new(1, u64::MAX),
+            StreamId::new(256, 0),
+            StreamId::MAX,
+        ];
+        for pair in ids.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert!(pair[0].encode() < pair[1].encode());
+            assert_eq!(StreamId::decode(&pair[1].encode()).unwrap(), pair[1]);
+        }
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(StreamId::MAX.next(), None);
+    }
+
+    #[test]
+    fn test_encode_decode() -> Result<()> {
+        let key = b"test\x00key";
+        let id = StreamId::new(1000, 7);
+
+        let encoded = StreamsDataKey::new(key, 123, id).encode()?;
+        let parsed = ParsedStreamsDataKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), key);
+        assert_eq!(parsed.version(), 123);
+        assert_eq!(parsed.id(), id);
+        assert_eq!(parsed.reserve1(), &[0; 8]);
+        assert_eq!(parsed.reserve2(), &[0; 16]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_scan_bounds() -> Result<()> {
+        let key = b"stream";
+        let prefix = StreamsDataKey::new(key, 1, StreamId::MIN).encode_prefix()?;
+        let start = StreamsDataKey::new(key, 1, StreamId::new(5, 0)).encode_seek_key()?;
+
+        let before = StreamsDataKey::new(key, 1, StreamId::new(4, 9)).encode()?;
+        let first = StreamsDataKey::new(key, 1, StreamId::new(5, 0)).encode()?;
+        let other_version = StreamsDataKey::new(key, 2, StreamId::new(5, 0)).encode()?;
+
+        assert!(before < start);
+        assert!(first > start);
+        assert!(first.starts_with(&prefix));
+        assert!(!other_version.starts_with(&prefix));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(ParsedStreamsDataKey::decode(&[0u8; 10]).is_err());
+
+        let mut encoded = StreamsDataKey::new(b"k", 1, StreamId::new(1, 1))
+            .encode()
+            .unwrap();
+        encoded.remove(encoded.len() - RESERVE2_LEN - 1);
+        assert!(ParsedStreamsDataKey::decode(&encoded).is_err());
+    }
+}
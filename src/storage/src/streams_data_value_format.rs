@@ -0,0 +1,149 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{
+    base_data_value_format::{BaseDataValue, ParsedBaseDataValue},
+    error::{InvalidFormatSnafu, Result},
+};
+use bytes::{Buf, BufMut, BytesMut};
+use snafu::ensure;
+
+const LEN_LENGTH: usize = 4;
+
+/*
+ * Stream entry data value format, the user value of a base data value
+ * | field count | field len | field | value len | value | ... | reserve | ctime |
+ * |     4B      |    4B     |       |    4B     |       |     |   16B   |   8B  |
+ */
+
+/// The field-value pairs of a stream entry, in insertion order.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamsDataValue {
+    fields: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[allow(dead_code)]
+impl StreamsDataValue {
+    pub fn new(fields: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        Self { fields }
+    }
+
+    pub fn fields(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.fields
+    }
+
+    pub fn into_fields(self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.fields
+    }
+
+    pub fn encode(&self) -> BytesMut {
+        let needed = LEN_LENGTH
+            + self
+                .fields
+                .iter()
+                .map(|(f, v)| 2 * LEN_LENGTH + f.len() + v.len())
+                .sum::<usize>();
+        let mut user_value = BytesMut::with_capacity(needed);
+        user_value.put_u32_le(self.fields.len() as u32);
+        for (field, value) in &self.fields {
+            user_value.put_u32_le(field.len() as u32);
+            user_value.put_slice(field);
+            user_value.put_u32_le(value.len() as u32);
+            user_value.put_slice(value);
+        }
+        BaseDataValue::new(user_value.freeze()).encode()
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self> {
+        let user_value = ParsedBaseDataValue::new(value)?.user_value();
+        let mut src = &user_value[..];
+
+        let count = read_len(&mut src)?;
+        let mut fields = Vec::with_capacity(count.min(src.len() / (2 * LEN_LENGTH)));
+        for _ in 0..count {
+            let field = read_bytes(&mut src)?;
+            let value = read_bytes(&mut src)?;
+            fields.push((field, value));
+        }
+        ensure!(
+            src.is_empty(),
+            InvalidFormatSnafu {
+                message: "trailing bytes after stream entry fields".to_string(),
+            }
+        );
+        Ok(Self { fields })
+    }
+}
+
+fn read_len(src: &mut &[u8]) -> Result<usize> {
+    ensure!(
+        src.len() >= LEN_LENGTH,
+        InvalidFormatSnafu {
+            message: "stream entry value truncated".to_string(),
+        }
+    );
+    Ok(src.get_u32_le() as usize)
+}
+
+fn read_bytes(src: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = read_len(src)?;
+    ensure!(
+        src.len() >= len,
+        InvalidFormatSnafu {
+            message: "stream entry value truncated".to_string(),
+        }
+    );
+    let bytes = src[..len].to_vec();
+    src.advance(len);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage_define::{SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH};
+
+    #[test]
+    fn test_streams_data_value_roundtrip() {
+        let value = StreamsDataValue::new(vec![
+            (b"sensor".to_vec(), b"1234".to_vec()),
+            (b"temp\x00".to_vec(), Vec::new()),
+        ]);
+        let encoded = value.encode();
+        assert_eq!(StreamsDataValue::decode(&encoded).unwrap(), value);
+
+        let empty = StreamsDataValue::default();
+        assert_eq!(StreamsDataValue::decode(&empty.encode()).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_streams_data_value_invalid() {
+        let value = StreamsDataValue::new(vec![(b"f".to_vec(), b"v".to_vec())]);
+        let encoded = value.encode();
+
+        // drop the last byte of the value, keeping the base data suffix
+        let suffix = encoded.len() - SUFFIX_RESERVE_LENGTH - TIMESTAMP_LENGTH;
+        let mut truncated = encoded[..suffix - 1].to_vec();
+        truncated.extend_from_slice(&encoded[suffix..]);
+        assert!(StreamsDataValue::decode(&truncated).is_err());
+
+        assert!(StreamsDataValue::decode(b"short").is_err());
+    }
+}
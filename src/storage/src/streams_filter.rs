@@ -0,0 +1,295 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Compaction filter of the stream data column family
+//!
+//! A trim only has to update the meta value: it raises the max deleted ID
+//! to the last entry it removed, and the entries up to it are trimmed
+//! whether their keys are deleted or not. This filter drops them, along
+//! with the entries of older versions and of expired streams, so XTRIM
+//! does not need to delete every entry it removes.
+
+use bytes::BytesMut;
+use log::debug;
+use rocksdb::{
+    compaction_filter::CompactionFilter, compaction_filter_factory::CompactionFilterFactory,
+    CompactionDecision, ReadOptions, DB,
+};
+use snafu::ResultExt;
+use std::sync::{Arc, OnceLock, Weak};
+
+use crate::{
+    base_key_format::BaseKey,
+    base_value_format::DataType,
+    clock,
+    error::{Result, RocksSnafu},
+    filter_config::{FilterConfig, SharedFilterConfig},
+    filter_stats::{record, FilterKind, FilterOutcome},
+    stale_ranges::record_filtered,
+    streams_data_key_format::{ParsedStreamsDataKey, StreamId},
+    streams_meta_value_format::ParsedStreamsMetaValue,
+    trash::decode_trash_value,
+    ColumnFamilyIndex,
+};
+
+/// Removes the entries of streams whose meta value is gone or expired, of
+/// an older version than it, or trimmed. Entries of one stream are
+/// adjacent, so the meta value is read once per stream.
+pub struct StreamsDataFilter {
+    db: Weak<DB>,
+    default_read_opts: ReadOptions,
+    cur_key: BytesMut,
+    meta_not_found: bool,
+    cur_meta_version: u64,
+    cur_meta_etime: u64,
+    cur_max_deleted_id: StreamId,
+    config: FilterConfig,
+}
+
+/// Creates a `StreamsDataFilter`. The db is filled in once it is open,
+/// compactions before that keep everything.
+pub struct StreamsDataFilterFactory {
+    db: Arc<OnceLock<Weak<DB>>>,
+    config: SharedFilterConfig,
+    bottommost_only: bool,
+}
+
+impl StreamsDataFilter {
+    pub fn new(db: Weak<DB>) -> Self {
+        Self {
+            db,
+            default_read_opts: ReadOptions::default(),
+            cur_key: BytesMut::new(),
+            meta_not_found: false,
+            cur_meta_version: 0,
+            cur_meta_etime: 0,
+            cur_max_deleted_id: StreamId::MIN,
+            config: FilterConfig::default(),
+        }
+    }
+
+    /// Filters with `config` instead of the default
+    pub fn with_config(mut self, config: FilterConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Reads the meta value owning the entries under `meta_key`, a live
+    /// stream or one in the trash, which UNDELETE can still bring back.
+    fn load_meta(&mut self, db: &DB, meta_key: BytesMut) -> Result<()> {
+        self.meta_not_found = true;
+
+        let live = db
+            .get_opt(&meta_key, &self.default_read_opts)
+            .context(RocksSnafu)?;
+        if let Some(meta) = live.as_deref().and_then(owning_meta) {
+            self.set_meta(&meta);
+        } else if let Some(trash_cf) = db.cf_handle(ColumnFamilyIndex::TrashCF.name()) {
+            let trashed = db
+                .get_cf_opt(&trash_cf, &meta_key, &self.default_read_opts)
+                .context(RocksSnafu)?;
+            if let Some(meta) = trashed
+                .as_deref()
+                .and_then(decode_trash_value)
+                .and_then(|(_, v)| owning_meta(v))
+            {
+                self.set_meta(&meta);
+            }
+        }
+        self.cur_key = meta_key;
+        Ok(())
+    }
+
+    fn set_meta(&mut self, meta: &ParsedStreamsMetaValue) {
+        self.meta_not_found = false;
+        self.cur_meta_version = meta.version();
+        self.cur_meta_etime = meta.etime();
+        self.cur_max_deleted_id = meta.max_deleted_id();
+    }
+
+    /// Decides on the entry `id` of `version` against the cached meta
+    /// value. An entry of a newer version than the meta value is kept, it
+    /// can only come from a write this filter has not seen.
+    fn decide(&self, version: u64, id: StreamId, now: u64) -> FilterOutcome {
+        if self.meta_not_found || version < self.cur_meta_version {
+            return FilterOutcome::RemovedStaleVersion;
+        }
+        if self.cur_meta_etime != 0 && self.cur_meta_etime < now {
+            return FilterOutcome::RemovedExpired;
+        }
+        if version == self.cur_meta_version && id <= self.cur_max_deleted_id {
+            return FilterOutcome::RemovedStaleVersion;
+        }
+        FilterOutcome::Kept
+    }
+}
+
+/// The stream meta value in `value`. The meta filter keeps every stream,
+/// an expired one is only recognized by its etime
+fn owning_meta(value: &[u8]) -> Option<ParsedStreamsMetaValue> {
+    if value.first() != Some(&(DataType::Stream as u8)) {
+        return None;
+    }
+    ParsedStreamsMetaValue::new(value).ok()
+}
+
+impl CompactionFilter for StreamsDataFilter {
+    fn name(&self) -> &std::ffi::CStr {
+        c"StreamsDataFilter"
+    }
+
+    fn filter(&mut self, level: u32, key: &[u8], _value: &[u8]) -> CompactionDecision {
+        if !self.config.removes_at(level) {
+            return record(FilterKind::StreamsData, FilterOutcome::Kept);
+        }
+        let current_time = self.config.expire_before(clock::now_micros());
+
+        let parsed = match ParsedStreamsDataKey::from_slice(key) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                debug!("StreamsDataFilter: Failed to parse key {key:?}: {e}, remove.");
+                let outcome = self.config.resolve(FilterOutcome::RemovedCorrupt);
+                return record(FilterKind::StreamsData, outcome);
+            }
+        };
+        let meta_key = match BaseKey::with_reserve1(parsed.key(), *parsed.reserve1()).encode() {
+            Ok(meta_key) => meta_key,
+            Err(e) => {
+                debug!("StreamsDataFilter: Failed to encode meta key of {key:?}: {e}, keep.");
+                return record(FilterKind::StreamsData, FilterOutcome::Kept);
+            }
+        };
+
+        if meta_key != self.cur_key {
+            // The db is closing, leave the entry to a later compaction
+            let Some(db) = self.db.upgrade() else {
+                return record(FilterKind::StreamsData, FilterOutcome::Kept);
+            };
+            if let Err(e) = self.load_meta(&db, meta_key) {
+                debug!("StreamsDataFilter: Failed to read meta value for key {key:?}: {e}, keep.");
+                self.cur_key.clear();
+                return record(FilterKind::StreamsData, FilterOutcome::Kept);
+            }
+        }
+
+        let outcome = self
+            .config
+            .resolve(self.decide(parsed.version(), parsed.id(), current_time));
+        record_filtered(ColumnFamilyIndex::StreamsDataCF, outcome);
+        record(FilterKind::StreamsData, outcome)
+    }
+}
+
+impl StreamsDataFilterFactory {
+    pub fn new(db: Arc<OnceLock<Weak<DB>>>, config: SharedFilterConfig) -> Self {
+        Self {
+            db,
+            config,
+            bottommost_only: false,
+        }
+    }
+
+    /// Makes the filters remove only in the compactions into the
+    /// bottommost level
+    pub fn with_bottommost_only(mut self, bottommost_only: bool) -> Self {
+        self.bottommost_only = bottommost_only;
+        self
+    }
+}
+
+impl CompactionFilterFactory for StreamsDataFilterFactory {
+    type Filter = StreamsDataFilter;
+
+    fn create(
+        &mut self,
+        context: rocksdb::compaction_filter_factory::CompactionFilterContext,
+    ) -> Self::Filter {
+        StreamsDataFilter::new(self.db.get().cloned().unwrap_or_default()).with_config(
+            self.config
+                .get()
+                .for_compaction(self.bottommost_only, &context),
+        )
+    }
+
+    fn name(&self) -> &std::ffi::CStr {
+        c"StreamsDataFilterFactory"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        streams_data_key_format::StreamsDataKey, streams_meta_value_format::StreamsMetaValue,
+        unique_test_db_path, BgTaskHandler, Redis, StorageOptions,
+    };
+    use kstd::lock_mgr::LockMgr;
+
+    #[test]
+    fn test_streams_data_filter() {
+        let test_db_path = unique_test_db_path();
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(test_db_path.to_str().unwrap()).unwrap();
+        {
+            let db = redis.db.as_ref().unwrap();
+            let mut meta = StreamsMetaValue::new(10u64.to_le_bytes().to_vec());
+            let version = meta.update_version();
+            meta.set_last_id(StreamId::new(20, 0));
+            // A logical trim of the entries up to 10-0
+            meta.set_max_deleted_id(StreamId::new(10, 0));
+            db.put(redis.base_key(b"s").encode().unwrap(), meta.encode())
+                .unwrap();
+
+            let mut filter = StreamsDataFilter::new(Arc::downgrade(db));
+            for (key, version, id, keep) in [
+                (&b"s"[..], version, StreamId::new(11, 0), true),
+                (b"s", version, StreamId::new(20, 0), true),
+                (b"s", version, StreamId::new(10, 0), false),
+                (b"s", version, StreamId::new(1, 5), false),
+                (b"s", version - 1, StreamId::new(15, 0), false),
+                (b"s", version + 1, StreamId::new(1, 0), true),
+                (b"missing", version, StreamId::new(15, 0), false),
+            ] {
+                let data_key = StreamsDataKey::new(key, version, id).encode().unwrap();
+                let decision = filter.filter(0, &data_key, b"");
+                assert_eq!(matches!(decision, CompactionDecision::Keep), keep);
+            }
+
+            // Behind a reclaim fence the trimmed entries stay for the replicas
+            let mut fenced = StreamsDataFilter::new(Arc::downgrade(db)).with_config(FilterConfig {
+                reclaim_fence: Some(0),
+                ..FilterConfig::default()
+            });
+            let data_key = StreamsDataKey::new(b"s", version, StreamId::new(10, 0))
+                .encode()
+                .unwrap();
+            let decision = fenced.filter(0, &data_key, b"");
+            assert!(matches!(decision, CompactionDecision::Keep));
+        }
+
+        redis.set_need_close(true);
+        drop(redis);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
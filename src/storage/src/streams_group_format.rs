@@ -0,0 +1,318 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![cfg_attr(not(test), allow(dead_code))]
+
+use crate::{
+    base_data_value_format::{BaseDataValue, ParsedBaseDataValue},
+    error::{InvalidFormatSnafu, Result},
+    hashes_data_key_format::{HashesDataKey, ParsedHashesDataKey},
+    storage_define::{decode_user_key, encode_user_key, ENCODED_KEY_DELIM_SIZE},
+    streams_data_key_format::{StreamId, STREAM_ID_LENGTH},
+};
+use bytes::{Buf, BufMut, BytesMut};
+use snafu::ensure;
+
+const U64_LEN: usize = 8;
+
+/*
+ * Consumer group keys live in stream_group_cf and share the hash data key
+ * layout, the field being the encoded group name optionally followed by the
+ * ID of a pending entry:
+ *
+ * group:   | reserve1 | key | version | group |          reserve2 |
+ * pending: | reserve1 | key | version | group | entry id | reserve2 |
+ * |            8B     |     |    8B   |       |   16B    |   16B    |
+ *
+ * The group name is escaped and terminated like the user key, so a group
+ * sorts right before its pending entries and those sort by ID, which is what
+ * XPENDING and XCLAIM scan in.
+ */
+
+/// The key of a consumer group, or of one of its pending entries.
+pub struct StreamsGroupKey {
+    key: Vec<u8>,
+    version: u64,
+    group: Vec<u8>,
+    id: Option<StreamId>,
+}
+
+impl StreamsGroupKey {
+    pub fn group(key: &[u8], version: u64, group: &[u8]) -> Self {
+        Self {
+            key: key.to_vec(),
+            version,
+            group: group.to_vec(),
+            id: None,
+        }
+    }
+
+    pub fn pending(key: &[u8], version: u64, group: &[u8], id: StreamId) -> Self {
+        Self {
+            id: Some(id),
+            ..Self::group(key, version, group)
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        HashesDataKey::new(&self.key, self.version, &self.encode_field()?).encode()
+    }
+
+    /// Encodes the key without reserve2, every pending entry of the group
+    /// starts with this when `id` is `None`.
+    pub fn encode_seek_key(&self) -> Result<Vec<u8>> {
+        HashesDataKey::new(&self.key, self.version, &self.encode_field()?).encode_seek_key()
+    }
+
+    fn encode_field(&self) -> Result<Vec<u8>> {
+        let mut field =
+            BytesMut::with_capacity(self.group.len() + ENCODED_KEY_DELIM_SIZE + STREAM_ID_LENGTH);
+        encode_user_key(&self.group, &mut field)?;
+        if let Some(id) = self.id {
+            field.put_slice(&id.encode());
+        }
+        Ok(field.to_vec())
+    }
+}
+
+pub struct ParsedStreamsGroupKey {
+    key: Vec<u8>,
+    version: u64,
+    group: Vec<u8>,
+    id: Option<StreamId>,
+}
+
+impl ParsedStreamsGroupKey {
+    pub fn decode(key: &[u8]) -> Result<Self> {
+        let parsed = ParsedHashesDataKey::decode(key)?;
+        let field = parsed.field();
+
+        let pos = field
+            .windows(ENCODED_KEY_DELIM_SIZE)
+            .position(|window| window == b"\x00\x00")
+            .map(|p| p + ENCODED_KEY_DELIM_SIZE)
+            .ok_or_else(|| crate::error::Error::InvalidFormat {
+                message: "group name delimiter not found".to_string(),
+                location: snafu::location!(),
+            })?;
+        let mut group = BytesMut::with_capacity(pos);
+        decode_user_key(&field[..pos], &mut group)?;
+
+        let id = match &field[pos..] {
+            [] => None,
+            rest => Some(StreamId::decode(rest)?),
+        };
+
+        Ok(Self {
+            key: parsed.key().to_vec(),
+            version: parsed.version(),
+            group: group.to_vec(),
+            id,
+        })
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn group(&self) -> &[u8] {
+        &self.group
+    }
+
+    /// The ID of the pending entry, `None` for the group itself.
+    pub fn id(&self) -> Option<StreamId> {
+        self.id
+    }
+}
+
+/*
+ * Consumer group value, the user value of a base data value
+ * | last delivered id | entries read | reserve | ctime |
+ * |        16B        |      8B      |   16B   |   8B  |
+ */
+
+/// The state of a consumer group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamsGroupValue {
+    /// The ID of the last entry delivered to any consumer of the group.
+    pub last_delivered_id: StreamId,
+    /// The number of entries delivered to the group so far.
+    pub entries_read: u64,
+}
+
+impl StreamsGroupValue {
+    pub fn new(last_delivered_id: StreamId) -> Self {
+        Self {
+            last_delivered_id,
+            entries_read: 0,
+        }
+    }
+
+    pub fn encode(&self) -> BytesMut {
+        let mut user_value = BytesMut::with_capacity(STREAM_ID_LENGTH + U64_LEN);
+        user_value.put_slice(&self.last_delivered_id.encode());
+        user_value.put_u64_le(self.entries_read);
+        BaseDataValue::new(user_value.freeze()).encode()
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self> {
+        let user_value = ParsedBaseDataValue::new(value)?.user_value();
+        ensure!(
+            user_value.len() == STREAM_ID_LENGTH + U64_LEN,
+            InvalidFormatSnafu {
+                message: format!("invalid consumer group value length: {}", user_value.len()),
+            }
+        );
+        let mut src = &user_value[STREAM_ID_LENGTH..];
+        Ok(Self {
+            last_delivered_id: StreamId::decode(&user_value[..STREAM_ID_LENGTH])?,
+            entries_read: src.get_u64_le(),
+        })
+    }
+}
+
+/*
+ * Pending entry value, the user value of a base data value
+ * | delivery time | delivery count | consumer | reserve | ctime |
+ * |      8B       |       8B       |          |   16B   |   8B  |
+ */
+
+/// An entry delivered to a consumer and not acknowledged yet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamsPendingValue {
+    /// The consumer that owns the entry.
+    pub consumer: Vec<u8>,
+    /// Unix time in milliseconds of the last delivery.
+    pub delivery_time: u64,
+    /// How many times the entry was delivered.
+    pub delivery_count: u64,
+}
+
+impl StreamsPendingValue {
+    pub fn new(consumer: &[u8], delivery_time: u64) -> Self {
+        Self {
+            consumer: consumer.to_vec(),
+            delivery_time,
+            delivery_count: 1,
+        }
+    }
+
+    /// Records another delivery, as XCLAIM does when it moves the entry.
+    pub fn redeliver(&mut self, consumer: &[u8], delivery_time: u64) {
+        self.consumer = consumer.to_vec();
+        self.delivery_time = delivery_time;
+        self.delivery_count = self.delivery_count.saturating_add(1);
+    }
+
+    pub fn encode(&self) -> BytesMut {
+        let mut user_value = BytesMut::with_capacity(2 * U64_LEN + self.consumer.len());
+        user_value.put_u64_le(self.delivery_time);
+        user_value.put_u64_le(self.delivery_count);
+        user_value.put_slice(&self.consumer);
+        BaseDataValue::new(user_value.freeze()).encode()
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self> {
+        let user_value = ParsedBaseDataValue::new(value)?.user_value();
+        ensure!(
+            user_value.len() >= 2 * U64_LEN,
+            InvalidFormatSnafu {
+                message: "pending entry value truncated".to_string(),
+            }
+        );
+        let mut src = &user_value[..];
+        let delivery_time = src.get_u64_le();
+        let delivery_count = src.get_u64_le();
+        Ok(Self {
+            consumer: src.to_vec(),
+            delivery_time,
+            delivery_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_key_roundtrip() -> Result<()> {
+        let encoded = StreamsGroupKey::group(b"stream\x00", 7, b"g\x00roup").encode()?;
+        let parsed = ParsedStreamsGroupKey::decode(&encoded)?;
+        assert_eq!(parsed.key(), b"stream\x00");
+        assert_eq!(parsed.version(), 7);
+        assert_eq!(parsed.group(), b"g\x00roup");
+        assert_eq!(parsed.id(), None);
+
+        let id = StreamId::new(1700000000000, 3);
+        let encoded = StreamsGroupKey::pending(b"stream\x00", 7, b"g\x00roup", id).encode()?;
+        let parsed = ParsedStreamsGroupKey::decode(&encoded)?;
+        assert_eq!(parsed.group(), b"g\x00roup");
+        assert_eq!(parsed.id(), Some(id));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_entries_follow_their_group() -> Result<()> {
+        let group = StreamsGroupKey::group(b"s", 1, b"g").encode()?;
+        let prefix = StreamsGroupKey::group(b"s", 1, b"g").encode_seek_key()?;
+        let mut keys = vec![group.clone()];
+        for id in [StreamId::MIN, StreamId::new(1, 0), StreamId::new(1, 1)] {
+            let key = StreamsGroupKey::pending(b"s", 1, b"g", id).encode()?;
+            assert!(key.starts_with(&prefix));
+            keys.push(key);
+        }
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        // a group whose name extends "g" is not inside the prefix of "g"
+        let other = StreamsGroupKey::pending(b"s", 1, b"g2", StreamId::MIN).encode()?;
+        assert!(!other.starts_with(&prefix));
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_value_roundtrip() -> Result<()> {
+        let mut value = StreamsGroupValue::new(StreamId::new(5, 1));
+        value.entries_read = 42;
+        assert_eq!(StreamsGroupValue::decode(&value.encode())?, value);
+
+        let pending = StreamsPendingValue::new(b"alice", 1000);
+        assert!(StreamsGroupValue::decode(&pending.encode()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_value_roundtrip() -> Result<()> {
+        let mut value = StreamsPendingValue::new(b"alice", 1000);
+        assert_eq!(value.delivery_count, 1);
+        value.redeliver(b"bob", 2000);
+        assert_eq!(value.consumer, b"bob");
+        assert_eq!(value.delivery_count, 2);
+
+        let decoded = StreamsPendingValue::decode(&value.encode())?;
+        assert_eq!(decoded, value);
+
+        let empty = StreamsPendingValue::new(b"", 0);
+        assert_eq!(StreamsPendingValue::decode(&empty.encode())?, empty);
+        Ok(())
+    }
+}
@@ -0,0 +1,389 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{
+    base_value_format::{DataType, InternalValue, ParsedInternalValue, ValueBuf},
+    checksum, delegate_internal_value, delegate_parsed_value,
+    error::{CorruptValueSnafu, Result},
+    format_version::{FormatMigrator, ValueLayout},
+    impl_value_format,
+    storage_define::{
+        BASE_META_VALUE_COUNT_LENGTH, SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH, TYPE_LENGTH,
+        VERSION_LENGTH,
+    },
+    streams_data_key_format::{StreamId, STREAM_ID_LENGTH},
+    version_seq,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use snafu::ensure;
+use std::io::Cursor;
+
+const GROUPS_COUNT_LENGTH: usize = 8;
+
+/*
+ * | type | length | version | last id | max deleted id | groups count | reserve | cdate | timestamp |
+ * |  1B  |   8B   |    8B   |   16B   |      16B       |      8B      |   16B   |   8B  |     8B    |
+ *
+ * The ids are stored big-endian like in the data keys. A stream exists
+ * while it is not expired, even when it has no entries left.
+ */
+#[allow(dead_code)]
+pub struct StreamsMetaValue {
+    pub inner: InternalValue,
+    last_id: StreamId,
+    max_deleted_id: StreamId,
+    groups_count: u64,
+}
+
+delegate_internal_value!(StreamsMetaValue);
+impl_value_format!(StreamsMetaValue, ParsedStreamsMetaValue);
+#[allow(dead_code)]
+impl StreamsMetaValue {
+    pub fn new<T>(length: T) -> Self
+    where
+        T: Into<Bytes>,
+    {
+        Self {
+            inner: InternalValue::new(DataType::Stream, length),
+            last_id: StreamId::MIN,
+            max_deleted_id: StreamId::MIN,
+            groups_count: 0,
+        }
+    }
+
+    pub fn update_version(&mut self) -> u64 {
+        self.inner.version = version_seq::next_version(self.inner.version);
+        self.inner.version
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = id;
+    }
+
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    pub fn set_max_deleted_id(&mut self, id: StreamId) {
+        self.max_deleted_id = id;
+    }
+
+    pub fn groups_count(&self) -> u64 {
+        self.groups_count
+    }
+
+    pub fn set_groups_count(&mut self, groups_count: u64) {
+        self.groups_count = groups_count;
+    }
+
+    pub fn encode(&self) -> BytesMut {
+        let needed = TYPE_LENGTH
+            + self.inner.user_value.len()
+            + VERSION_LENGTH
+            + 2 * STREAM_ID_LENGTH
+            + GROUPS_COUNT_LENGTH
+            + SUFFIX_RESERVE_LENGTH
+            + 2 * TIMESTAMP_LENGTH;
+        let mut buf = BytesMut::with_capacity(needed);
+
+        buf.put_u8(self.inner.data_type as u8);
+        buf.extend_from_slice(&self.inner.user_value);
+        buf.put_u64_le(self.inner.version);
+        buf.put_slice(&self.last_id.encode());
+        buf.put_slice(&self.max_deleted_id.encode());
+        buf.put_u64_le(self.groups_count);
+        let reserve_start = buf.len();
+        buf.extend_from_slice(&self.inner.reserve);
+        buf.put_u64_le(self.inner.ctime);
+        buf.put_u64_le(self.inner.etime);
+        checksum::seal(&mut buf, reserve_start);
+
+        buf
+    }
+}
+
+#[allow(dead_code)]
+pub struct ParsedStreamsMetaValue {
+    inner: ParsedInternalValue,
+    length: u64,
+    last_id: StreamId,
+    max_deleted_id: StreamId,
+    groups_count: u64,
+}
+
+delegate_parsed_value! {ParsedStreamsMetaValue}
+#[allow(dead_code)]
+impl ParsedStreamsMetaValue {
+    const LAST_ID_OFFSET: usize = TYPE_LENGTH + BASE_META_VALUE_COUNT_LENGTH + VERSION_LENGTH;
+    const MAX_DELETED_ID_OFFSET: usize = Self::LAST_ID_OFFSET + STREAM_ID_LENGTH;
+    const GROUPS_COUNT_OFFSET: usize = Self::MAX_DELETED_ID_OFFSET + STREAM_ID_LENGTH;
+    const STREAMS_META_VALUE_LENGTH: usize = Self::GROUPS_COUNT_OFFSET
+        + GROUPS_COUNT_LENGTH
+        + SUFFIX_RESERVE_LENGTH
+        + 2 * TIMESTAMP_LENGTH;
+
+    pub fn new<T>(internal_value: T) -> Result<Self>
+    where
+        T: Into<ValueBuf>,
+    {
+        let value =
+            FormatMigrator::global().upgrade(ValueLayout::StreamsMeta, internal_value.into())?;
+        ensure!(
+            value.len() >= Self::STREAMS_META_VALUE_LENGTH,
+            CorruptValueSnafu {
+                data_type: DataType::Stream,
+                field: "stream meta value",
+                offset: 0usize,
+                expected: Self::STREAMS_META_VALUE_LENGTH,
+                actual: value.len(),
+                key: None::<String>,
+            }
+        );
+
+        let mut val_reader = Cursor::new(&value[..]);
+        let data_type: DataType = val_reader.get_u8().try_into()?;
+        let pos = val_reader.position() as usize;
+
+        let length_range = pos..pos + BASE_META_VALUE_COUNT_LENGTH;
+        let length = val_reader.get_u64_le();
+        let version = val_reader.get_u64_le();
+
+        let last_id = StreamId::decode(
+            &value[Self::LAST_ID_OFFSET..Self::LAST_ID_OFFSET + STREAM_ID_LENGTH],
+        )?;
+        let max_deleted_id = StreamId::decode(
+            &value[Self::MAX_DELETED_ID_OFFSET..Self::MAX_DELETED_ID_OFFSET + STREAM_ID_LENGTH],
+        )?;
+        val_reader.advance(2 * STREAM_ID_LENGTH);
+        let groups_count = val_reader.get_u64_le();
+        let pos = val_reader.position() as usize;
+
+        let reserve_range = pos..pos + SUFFIX_RESERVE_LENGTH;
+        val_reader.advance(SUFFIX_RESERVE_LENGTH);
+        let ctime = val_reader.get_u64_le();
+        let etime = val_reader.get_u64_le();
+        checksum::verify(&value, reserve_range.start)?;
+
+        Ok(Self {
+            inner: ParsedInternalValue::new(
+                value,
+                data_type,
+                length_range,
+                reserve_range,
+                version,
+                ctime,
+                etime,
+            ),
+            length,
+            last_id,
+            max_deleted_id,
+            groups_count,
+        })
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !self.inner.is_stale()
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    pub fn set_length(&mut self, length: u64) {
+        self.length = length;
+        self.put_u64_at(TYPE_LENGTH, length);
+    }
+
+    pub fn modify_length(&mut self, delta: i64) {
+        self.set_length(self.length.saturating_add_signed(delta));
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = id;
+        self.put_id_at(Self::LAST_ID_OFFSET, id);
+    }
+
+    /// Only trims raise it, the entries up to it are all removed
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    pub fn set_max_deleted_id(&mut self, id: StreamId) {
+        self.max_deleted_id = id;
+        self.put_id_at(Self::MAX_DELETED_ID_OFFSET, id);
+    }
+
+    pub fn groups_count(&self) -> u64 {
+        self.groups_count
+    }
+
+    pub fn set_groups_count(&mut self, groups_count: u64) {
+        self.groups_count = groups_count;
+        self.put_u64_at(Self::GROUPS_COUNT_OFFSET, groups_count);
+    }
+
+    pub fn set_etime(&mut self, etime: u64) {
+        self.inner.etime = etime;
+        let offset = self.inner.value.len() - TIMESTAMP_LENGTH;
+        self.put_u64_at(offset, etime);
+    }
+
+    pub fn set_ctime(&mut self, ctime: u64) {
+        self.inner.ctime = ctime;
+        let offset = self.inner.value.len() - 2 * TIMESTAMP_LENGTH;
+        self.put_u64_at(offset, ctime);
+    }
+
+    pub fn update_version(&mut self) -> u64 {
+        self.inner.version = version_seq::next_version(self.inner.version);
+        self.put_u64_at(
+            TYPE_LENGTH + BASE_META_VALUE_COUNT_LENGTH,
+            self.inner.version,
+        );
+        self.inner.version
+    }
+
+    /// Resets the stream for a new version: no entries, ids and groups, no
+    /// expiration. Returns the new version.
+    pub fn initial_meta_value(&mut self) -> u64 {
+        self.set_length(0);
+        self.set_last_id(StreamId::MIN);
+        self.set_max_deleted_id(StreamId::MIN);
+        self.set_groups_count(0);
+        self.set_etime(0);
+        self.set_ctime(0);
+        self.update_version()
+    }
+
+    fn put_u64_at(&mut self, offset: usize, value: u64) {
+        self.inner.value[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        checksum::refresh(&mut self.inner.value, self.inner.reserve_range.start);
+    }
+
+    fn put_id_at(&mut self, offset: usize, id: StreamId) {
+        self.inner.value[offset..offset + STREAM_ID_LENGTH].copy_from_slice(&id.encode());
+        checksum::refresh(&mut self.inner.value, self.inner.reserve_range.start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_LENGTH: u64 = 3;
+    const TEST_VERSION: u64 = 123456789;
+    const TEST_CTIME: u64 = 1620000000;
+    const TEST_ETIME: u64 = 1630000000;
+
+    fn create_test_streams_meta_value() -> StreamsMetaValue {
+        let mut meta = StreamsMetaValue::new(TEST_LENGTH.to_le_bytes().to_vec());
+        meta.inner.version = TEST_VERSION;
+        meta.inner.ctime = TEST_CTIME;
+        meta.inner.etime = TEST_ETIME;
+        meta.set_last_id(StreamId::new(100, 2));
+        meta.set_max_deleted_id(StreamId::new(100, 1));
+        meta.set_groups_count(4);
+        meta
+    }
+
+    #[test]
+    fn test_streams_meta_value_roundtrip() {
+        let encoded = create_test_streams_meta_value().encode();
+        assert_eq!(
+            encoded.len(),
+            ParsedStreamsMetaValue::STREAMS_META_VALUE_LENGTH
+        );
+
+        let parsed = ParsedStreamsMetaValue::new(encoded).unwrap();
+        assert_eq!(parsed.inner.data_type, DataType::Stream);
+        assert_eq!(parsed.length(), TEST_LENGTH);
+        assert_eq!(parsed.version(), TEST_VERSION);
+        assert_eq!(parsed.last_id(), StreamId::new(100, 2));
+        assert_eq!(parsed.max_deleted_id(), StreamId::new(100, 1));
+        assert_eq!(parsed.groups_count(), 4);
+        assert_eq!(parsed.ctime(), TEST_CTIME);
+        assert_eq!(parsed.etime(), TEST_ETIME);
+    }
+
+    #[test]
+    fn test_parsed_streams_meta_value_write_back() {
+        let encoded = create_test_streams_meta_value().encode();
+        let mut parsed = ParsedStreamsMetaValue::new(encoded).unwrap();
+
+        parsed.modify_length(2);
+        parsed.modify_length(-1);
+        parsed.set_last_id(StreamId::new(200, 0));
+        parsed.set_max_deleted_id(StreamId::new(150, 3));
+        parsed.set_groups_count(1);
+        parsed.set_etime(0);
+        let version = parsed.update_version();
+
+        let reparsed = ParsedStreamsMetaValue::new(parsed.inner.value.clone()).unwrap();
+        assert_eq!(reparsed.length(), TEST_LENGTH + 1);
+        assert_eq!(reparsed.last_id(), StreamId::new(200, 0));
+        assert_eq!(reparsed.max_deleted_id(), StreamId::new(150, 3));
+        assert_eq!(reparsed.groups_count(), 1);
+        assert_eq!(reparsed.etime(), 0);
+        assert_eq!(reparsed.ctime(), TEST_CTIME);
+        assert_eq!(reparsed.version(), version);
+        assert!(reparsed.is_valid());
+    }
+
+    #[test]
+    fn test_parsed_streams_meta_value_checksum() {
+        checksum::with_checksum(|| {
+            let encoded = create_test_streams_meta_value().encode();
+            let mut parsed = ParsedStreamsMetaValue::new(encoded).unwrap();
+
+            parsed.set_last_id(StreamId::new(300, 0));
+            parsed.set_groups_count(2);
+            assert!(ParsedStreamsMetaValue::new(parsed.inner.value.clone()).is_ok());
+
+            let mut corrupted = parsed.inner.value.clone();
+            corrupted[ParsedStreamsMetaValue::LAST_ID_OFFSET] ^= 0xff;
+            assert!(ParsedStreamsMetaValue::new(corrupted).is_err());
+        });
+    }
+
+    #[test]
+    fn test_parsed_streams_meta_value_initial_meta_value() {
+        let encoded = create_test_streams_meta_value().encode();
+        let mut parsed = ParsedStreamsMetaValue::new(encoded).unwrap();
+
+        let version = parsed.initial_meta_value();
+        assert!(version > TEST_VERSION);
+        assert_eq!(parsed.length(), 0);
+        assert_eq!(parsed.last_id(), StreamId::MIN);
+        assert_eq!(parsed.groups_count(), 0);
+    }
+
+    #[test]
+    fn test_parsed_streams_meta_value_too_short() {
+        let encoded = create_test_streams_meta_value().encode();
+        assert!(ParsedStreamsMetaValue::new(encoded[..encoded.len() - 1].to_vec()).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Stream trimming: the MAXLEN and MINID arguments of XADD and XTRIM
+//!
+//! An exact trim removes every entry over the threshold. An approximate
+//! one (`~`) only removes whole chunks of `STREAM_TRIM_CHUNK` entries, so a
+//! stream that grows one entry at a time is trimmed once per chunk instead
+//! of on every XADD, and at most `limit` entries are removed at once. The
+//! stream may keep a few more entries than asked, as in Redis.
+
+#![cfg_attr(not(test), allow(dead_code))]
+
+use crate::{streams_data_key_format::StreamId, streams_meta_value_format::ParsedStreamsMetaValue};
+
+/// An approximate trim removes the entries by chunks of this many, the
+/// default stream-node-max-entries of Redis
+pub const STREAM_TRIM_CHUNK: u64 = 100;
+
+/// The most entries an approximate trim removes without LIMIT
+pub const STREAM_TRIM_DEFAULT_LIMIT: u64 = 100 * STREAM_TRIM_CHUNK;
+
+/// What a trim keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// The newest n entries (MAXLEN)
+    MaxLen(u64),
+    /// The entries with an ID of at least this one (MINID)
+    MinId(StreamId),
+}
+
+/// The trim arguments of XADD and XTRIM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamTrim {
+    pub strategy: TrimStrategy,
+    /// Removes whole chunks only (`~`)
+    pub approx: bool,
+    /// The most entries removed at once, 0 for no limit. Only an
+    /// approximate trim has a limit
+    pub limit: u64,
+}
+
+impl StreamTrim {
+    pub fn exact(strategy: TrimStrategy) -> Self {
+        Self {
+            strategy,
+            approx: false,
+            limit: 0,
+        }
+    }
+
+    pub fn approx(strategy: TrimStrategy) -> Self {
+        Self {
+            strategy,
+            approx: true,
+            limit: STREAM_TRIM_DEFAULT_LIMIT,
+        }
+    }
+
+    /// Sets the LIMIT of an approximate trim
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        if self.approx {
+            self.limit = limit;
+        }
+        self
+    }
+
+    /// Whether a stream of length entries may have entries to remove,
+    /// without reading them. XADD only schedules a trim that is due, so
+    /// appending to a trimmed stream stays a single write most of the time
+    pub fn is_due(&self, length: u64) -> bool {
+        match self.strategy {
+            TrimStrategy::MaxLen(max_len) => self.quota(length.saturating_sub(max_len)) > 0,
+            // The meta value does not know the first ID of the stream
+            TrimStrategy::MinId(_) => length > 0,
+        }
+    }
+
+    /// How many of the excess entries are removed
+    fn quota(&self, excess: u64) -> u64 {
+        if !self.approx {
+            return excess;
+        }
+        let excess = match self.limit {
+            0 => excess,
+            limit => excess.min(limit),
+        };
+        excess - excess % STREAM_TRIM_CHUNK
+    }
+
+    /// The entries to remove from a stream of length entries, ids being the
+    /// IDs of the stream from the oldest. Reads no further than the last
+    /// entry removed, plus one chunk at most for an approximate MINID
+    pub fn plan(&self, length: u64, ids: impl IntoIterator<Item = StreamId>) -> TrimPlan {
+        let deleted = match self.strategy {
+            TrimStrategy::MaxLen(max_len) => {
+                let quota = self.quota(length.saturating_sub(max_len));
+                ids.into_iter().take(quota as usize).collect()
+            }
+            TrimStrategy::MinId(min_id) => {
+                let older = ids.into_iter().take_while(|id| *id < min_id);
+                let mut deleted: Vec<StreamId> = match (self.approx, self.limit) {
+                    (true, limit) if limit > 0 => older.take(limit as usize).collect(),
+                    _ => older.collect(),
+                };
+                deleted.truncate(self.quota(deleted.len() as u64) as usize);
+                deleted
+            }
+        };
+        TrimPlan { deleted }
+    }
+}
+
+/// The entries a trim removes, from the oldest.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrimPlan {
+    pub deleted: Vec<StreamId>,
+}
+
+impl TrimPlan {
+    pub fn is_empty(&self) -> bool {
+        self.deleted.is_empty()
+    }
+
+    /// Updates the meta value of the stream for the removed entries, to
+    /// write in the batch deleting them. A trim removes the oldest entries,
+    /// so every entry up to the max deleted ID is gone and
+    /// `StreamsDataFilter` drops the ones whose keys are left behind.
+    pub fn apply(&self, meta: &mut ParsedStreamsMetaValue) {
+        let Some(last) = self.deleted.last() else {
+            return;
+        };
+        meta.set_length(meta.length().saturating_sub(self.deleted.len() as u64));
+        if *last > meta.max_deleted_id() {
+            meta.set_max_deleted_id(*last);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streams_meta_value_format::StreamsMetaValue;
+
+    fn ids(n: u64) -> impl Iterator<Item = StreamId> {
+        (1..=n).map(|ms| StreamId::new(ms, 0))
+    }
+
+    #[test]
+    fn test_exact_maxlen() {
+        let trim = StreamTrim::exact(TrimStrategy::MaxLen(10));
+        assert!(!trim.is_due(10));
+        assert!(trim.is_due(11));
+        let plan = trim.plan(13, ids(13));
+        assert_eq!(plan.deleted, ids(3).collect::<Vec<_>>());
+        assert!(trim.plan(5, ids(5)).is_empty());
+
+        // LIMIT only applies to an approximate trim
+        assert_eq!(trim.with_limit(1).plan(13, ids(13)).deleted.len(), 3);
+    }
+
+    #[test]
+    fn test_approx_maxlen_trims_whole_chunks() {
+        let trim = StreamTrim::approx(TrimStrategy::MaxLen(10));
+        assert!(!trim.is_due(10 + STREAM_TRIM_CHUNK - 1));
+        assert!(trim.is_due(10 + STREAM_TRIM_CHUNK));
+        assert!(trim.plan(109, ids(109)).is_empty());
+        assert_eq!(trim.plan(250, ids(250)).deleted.len(), 200);
+
+        let limited = trim.with_limit(150);
+        assert_eq!(limited.plan(1000, ids(1000)).deleted.len(), 100);
+        let unlimited = trim.with_limit(0);
+        assert_eq!(unlimited.plan(50_000, ids(50_000)).deleted.len(), 49_900);
+        assert_eq!(
+            trim.plan(50_000, ids(50_000)).deleted.len() as u64,
+            STREAM_TRIM_DEFAULT_LIMIT
+        );
+    }
+
+    #[test]
+    fn test_minid() {
+        let min_id = StreamId::new(251, 0);
+        let exact = StreamTrim::exact(TrimStrategy::MinId(min_id));
+        assert!(exact.is_due(1));
+        assert!(!exact.is_due(0));
+        let plan = exact.plan(300, ids(300));
+        assert_eq!(plan.deleted.len(), 250);
+        assert!(plan.deleted.iter().all(|id| *id < min_id));
+
+        let approx = StreamTrim::approx(TrimStrategy::MinId(min_id));
+        assert_eq!(approx.plan(300, ids(300)).deleted.len(), 200);
+        assert_eq!(
+            approx.with_limit(120).plan(300, ids(300)).deleted.len(),
+            100
+        );
+        assert!(approx.with_limit(99).plan(300, ids(300)).is_empty());
+
+        // The scan stops at the first entry to keep
+        let mut read = 0;
+        let counted = ids(300).inspect(|_| read += 1);
+        exact.plan(300, counted);
+        assert_eq!(read, 251);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut meta = StreamsMetaValue::new(300u64.to_le_bytes().to_vec());
+        meta.set_max_deleted_id(StreamId::new(1000, 0));
+        let mut parsed = ParsedStreamsMetaValue::new(meta.encode()).unwrap();
+
+        let plan = StreamTrim::exact(TrimStrategy::MaxLen(200)).plan(300, ids(300));
+        plan.apply(&mut parsed);
+        assert_eq!(parsed.length(), 200);
+        // An entry deleted earlier had a higher ID
+        assert_eq!(parsed.max_deleted_id(), StreamId::new(1000, 0));
+
+        let plan = StreamTrim::exact(TrimStrategy::MinId(StreamId::new(2000, 1)))
+            .plan(200, (1001..=1200).map(|ms| StreamId::new(ms, 0)));
+        plan.apply(&mut parsed);
+        assert_eq!(parsed.length(), 0);
+        assert_eq!(parsed.max_deleted_id(), StreamId::new(1200, 0));
+
+        TrimPlan::default().apply(&mut parsed);
+        assert_eq!(parsed.length(), 0);
+    }
+}
@@ -17,12 +17,17 @@
  * limitations under the License.
  */
 
-use crate::base_value_format::{DataType, InternalValue, ParsedInternalValue};
+use crate::base_value_format::{DataType, InternalValue, ParsedInternalValue, ValueBuf};
+use crate::checksum;
+use crate::compression::{self, STRING_COMPRESSION_FLAGS};
 use crate::delegate_internal_value;
 use crate::delegate_parsed_value;
-use crate::error::{InvalidFormatSnafu, Result};
+use crate::error::{CorruptValueSnafu, Result};
+use crate::format_version::{FormatMigrator, ValueLayout};
+use crate::impl_value_format;
 use crate::storage_define::{
-    STRING_VALUE_SUFFIXLENGTH, SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH, TYPE_LENGTH,
+    RESERVE_FLAGS_OFFSET, STRING_VALUE_SUFFIXLENGTH, SUFFIX_RESERVE_LENGTH, TIMESTAMP_LENGTH,
+    TYPE_LENGTH,
 };
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use rocksdb::CompactionDecision;
@@ -31,44 +36,192 @@ use snafu::ensure;
 /*
  * | type | value | reserve | cdate | timestamp |
  * |  1B  |       |   16B   |   8B  |     8B    |
+ *
+ * A value that is the decimal form of an i64 is stored as the 8 bytes of
+ * the integer (little endian) with STRING_INT_FLAG set in the reserve.
+ * A bitmap written by SETBIT has STRING_SEGMENTED_FLAG set instead, and its
+ * value is the header of the segments (see bitmap_segment_format).
+ * A large value may be stored compressed, with the flag of its codec set
+ * (see compression)
  */
 #[derive(Debug, Clone)]
 pub struct StringValue {
     inner: InternalValue,
+    int: Option<i64>,
+    compressed: Option<(u8, Bytes)>,
+}
+
+/// Set in the reserve flags of a string value stored as an i64, like the
+/// OBJ_ENCODING_INT of Redis
+pub const STRING_INT_FLAG: u8 = 0x08;
+
+/// Set in the reserve flags of a bitmap stored in segments, which keeps
+/// SETBIT on a large bitmap from rewriting the whole value
+pub const STRING_SEGMENTED_FLAG: u8 = 0x10;
+
+const INT_VALUE_LENGTH: usize = std::mem::size_of::<i64>();
+
+/// The integer whose decimal form is exactly value. "+1", "01" and "-0"
+/// are not, so that decoding gives back the bytes that were written
+fn parse_int(value: &[u8]) -> Option<i64> {
+    // i64::MIN is 20 characters long
+    if value.is_empty() || value.len() > 20 {
+        return None;
+    }
+    let int: i64 = std::str::from_utf8(value).ok()?.parse().ok()?;
+    (int.to_string().as_bytes() == value).then_some(int)
 }
 
 delegate_internal_value!(StringValue);
+impl_value_format!(StringValue, ParsedStringsValue);
 #[allow(dead_code)]
 impl StringValue {
     pub fn new<T>(user_value: T) -> Self
     where
         T: Into<Bytes>,
     {
+        let user_value = user_value.into();
+        let int = parse_int(&user_value);
+        let compressed = match int {
+            Some(_) => None,
+            None => compression::compress(&user_value)
+                .map(|(flag, compressed)| (flag, Bytes::from(compressed))),
+        };
         Self {
             inner: InternalValue::new(DataType::String, user_value),
+            int,
+            compressed,
+        }
+    }
+
+    /// A value holding an integer, e.g. the result of INCR
+    pub fn from_int(int: i64) -> Self {
+        Self {
+            inner: InternalValue::new(DataType::String, int.to_string()),
+            int: Some(int),
+            compressed: None,
+        }
+    }
+
+    /// The value of a bitmap stored in segments, header is its
+    /// `SegmentedBitmapHeader`
+    pub fn segmented<T>(header: T) -> Self
+    where
+        T: Into<Bytes>,
+    {
+        let mut inner = InternalValue::new(DataType::String, header);
+        inner.reserve[RESERVE_FLAGS_OFFSET] |= STRING_SEGMENTED_FLAG;
+        Self {
+            inner,
+            int: None,
+            compressed: None,
+        }
+    }
+
+    /// Whether the value will be stored as an i64 rather than as text
+    pub fn is_int_encoded(&self) -> bool {
+        self.int.is_some()
+    }
+
+    /// Whether the value will be stored compressed
+    pub fn is_compressed(&self) -> bool {
+        self.compressed.is_some()
+    }
+
+    fn payload_len(&self) -> usize {
+        match (self.int, &self.compressed) {
+            (Some(_), _) => INT_VALUE_LENGTH,
+            (None, Some((_, compressed))) => compressed.len(),
+            (None, None) => self.inner.user_value.len(),
         }
     }
 
     pub fn encode(&self) -> BytesMut {
-        let needed = TYPE_LENGTH
-            + self.inner.user_value.len()
-            + SUFFIX_RESERVE_LENGTH
-            + 2 * TIMESTAMP_LENGTH;
-        let mut buf = BytesMut::with_capacity(needed);
+        let mut buf = BytesMut::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf);
+        buf
+    }
 
+    pub fn encoded_len(&self) -> usize {
+        TYPE_LENGTH + self.payload_len() + SUFFIX_RESERVE_LENGTH + 2 * TIMESTAMP_LENGTH
+    }
+
+    /// Appends the encoded value to buf, which can be a pooled buffer
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        let start = buf.len();
         buf.put_u8(DataType::String as u8);
-        buf.put_slice(&self.inner.user_value);
-        buf.put_bytes(0, SUFFIX_RESERVE_LENGTH);
+        let mut reserve = self.inner.reserve;
+        reserve[RESERVE_FLAGS_OFFSET] &= !(STRING_INT_FLAG | STRING_COMPRESSION_FLAGS);
+        match (self.int, &self.compressed) {
+            (Some(int), _) => {
+                buf.put_i64_le(int);
+                reserve[RESERVE_FLAGS_OFFSET] |= STRING_INT_FLAG;
+            }
+            (None, Some((flag, compressed))) => {
+                buf.put_slice(compressed);
+                reserve[RESERVE_FLAGS_OFFSET] |= flag;
+            }
+            (None, None) => buf.put_slice(&self.inner.user_value),
+        }
+        buf.put_slice(&reserve);
         buf.put_u64_le(self.inner.ctime);
         buf.put_u64_le(self.inner.etime);
-
-        buf
+        checksum::seal(&mut buf[start..], TYPE_LENGTH + self.payload_len());
     }
 }
 
+/// Rewrites an int encoded value into the text layout, so that the parsed
+/// value reads and is updated in place like any other string value
+fn expand_int(value: &[u8], reserve_start: usize) -> Result<(BytesMut, i64)> {
+    ensure!(
+        reserve_start == TYPE_LENGTH + INT_VALUE_LENGTH,
+        CorruptValueSnafu {
+            data_type: DataType::String,
+            field: "int",
+            offset: TYPE_LENGTH,
+            expected: INT_VALUE_LENGTH + STRING_VALUE_SUFFIXLENGTH,
+            actual: value.len() - TYPE_LENGTH,
+            key: None::<String>,
+        }
+    );
+    let int = (&value[TYPE_LENGTH..reserve_start]).get_i64_le();
+    let text = int.to_string();
+    Ok((
+        relayout(value, reserve_start, text.as_bytes(), STRING_INT_FLAG),
+        int,
+    ))
+}
+
+/// Same as `expand_int` for a compressed value
+fn expand_compressed(value: &[u8], reserve_start: usize) -> Result<BytesMut> {
+    let flags = value[reserve_start + RESERVE_FLAGS_OFFSET];
+    let user_value = compression::decompress(flags, &value[TYPE_LENGTH..reserve_start])?;
+    Ok(relayout(
+        value,
+        reserve_start,
+        &user_value,
+        STRING_COMPRESSION_FLAGS,
+    ))
+}
+
+/// The value with user_value in place of its payload and flags cleared
+fn relayout(value: &[u8], reserve_start: usize, user_value: &[u8], flags: u8) -> BytesMut {
+    let mut buf =
+        BytesMut::with_capacity(TYPE_LENGTH + user_value.len() + STRING_VALUE_SUFFIXLENGTH);
+    buf.put_u8(value[0]);
+    buf.put_slice(user_value);
+    let expanded_reserve_start = buf.len();
+    buf.put_slice(&value[reserve_start..]);
+    buf[expanded_reserve_start + RESERVE_FLAGS_OFFSET] &= !flags;
+    checksum::refresh(&mut buf, expanded_reserve_start);
+    buf
+}
+
 #[allow(dead_code)]
 pub struct ParsedStringsValue {
     inner: ParsedInternalValue,
+    int: Option<i64>,
+    compressed: bool,
 }
 
 delegate_parsed_value!(ParsedStringsValue);
@@ -76,43 +229,46 @@ delegate_parsed_value!(ParsedStringsValue);
 impl ParsedStringsValue {
     pub fn new<T>(internal_value: T) -> Result<Self>
     where
-        T: Into<BytesMut>,
+        T: Into<ValueBuf>,
     {
-        let value: BytesMut = internal_value.into();
+        let mut value =
+            FormatMigrator::global().upgrade(ValueLayout::String, internal_value.into())?;
         ensure!(
-            value.len() >= STRING_VALUE_SUFFIXLENGTH,
-            InvalidFormatSnafu {
-                message: format!(
-                    "invalid string value length: {} < {STRING_VALUE_SUFFIXLENGTH}",
-                    value.len()
-                )
+            value.len() >= TYPE_LENGTH + STRING_VALUE_SUFFIXLENGTH,
+            CorruptValueSnafu {
+                data_type: DataType::String,
+                field: "string value",
+                offset: 0usize,
+                expected: TYPE_LENGTH + STRING_VALUE_SUFFIXLENGTH,
+                actual: value.len(),
+                key: None::<String>,
             }
         );
 
         let data_type = DataType::try_from(value[0])?;
 
-        let user_value_len = value.len() - TYPE_LENGTH - STRING_VALUE_SUFFIXLENGTH;
+        let reserve_start = value.len() - STRING_VALUE_SUFFIXLENGTH;
+        checksum::verify(&value, reserve_start)?;
+        let flags = value[reserve_start + RESERVE_FLAGS_OFFSET];
+        let mut int = None;
+        let compressed = flags & STRING_COMPRESSION_FLAGS != 0;
+        if flags & STRING_INT_FLAG != 0 {
+            let (expanded, decoded) = expand_int(&value, reserve_start)?;
+            value = expanded.into();
+            int = Some(decoded);
+        } else if compressed {
+            value = expand_compressed(&value, reserve_start)?.into();
+        }
+
         let user_value_start = TYPE_LENGTH;
-        let user_value_end = user_value_start + user_value_len;
+        let user_value_end = value.len() - STRING_VALUE_SUFFIXLENGTH;
         let user_value_range = user_value_start..user_value_end;
 
-        let suffix_start = user_value_end;
-        let reserve_start = suffix_start;
+        let reserve_start = user_value_end;
         let reserve_end = reserve_start + SUFFIX_RESERVE_LENGTH;
         let reserve_range = reserve_start..reserve_end;
 
         let mut time_reader = &value[reserve_end..];
-        debug_assert!(time_reader.len() >= 2 * TIMESTAMP_LENGTH);
-        ensure!(
-            time_reader.len() >= 2 * TIMESTAMP_LENGTH,
-            InvalidFormatSnafu {
-                message: format!(
-                    "invalid string value length: {} < {}",
-                    time_reader.len(),
-                    2 * TIMESTAMP_LENGTH,
-                )
-            }
-        );
         let ctime = time_reader.get_u64_le();
         let etime = time_reader.get_u64_le();
 
@@ -126,9 +282,34 @@ impl ParsedStringsValue {
                 ctime,
                 etime,
             ),
+            int,
+            compressed,
         })
     }
 
+    /// Whether the value was stored as an i64. The parsed value always
+    /// holds its decimal form
+    pub fn is_int_encoded(&self) -> bool {
+        self.int.is_some()
+    }
+
+    /// Whether the value was stored compressed. The parsed value always
+    /// holds the original bytes
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Whether the value is the header of a bitmap stored in segments
+    pub fn is_segmented(&self) -> bool {
+        self.reserve()[RESERVE_FLAGS_OFFSET] & STRING_SEGMENTED_FLAG != 0
+    }
+
+    /// The value as an integer, for INCR and DECR. Only a value stored as
+    /// text is parsed. None if the value is not the decimal form of an i64
+    pub fn int_value(&self) -> Option<i64> {
+        self.int.or_else(|| parse_int(self.user_value_slice()))
+    }
+
     pub fn strip_suffix(&mut self) {
         self.inner.value.advance(TYPE_LENGTH);
 
@@ -179,6 +360,7 @@ impl ParsedStringsValue {
 #[cfg(test)]
 mod tests_string_value {
     use super::*;
+    use crate::options::ValueCompression;
 
     const TEST_CTIME: u64 = 1620000000;
     const TEST_ETIME: u64 = 1630000000;
@@ -212,6 +394,32 @@ mod tests_string_value {
         expected.put_u64_le(TEST_CTIME);
         expected.put_u64_le(TEST_ETIME);
         assert_eq!(encoded, expected);
+        assert_eq!(encoded.len(), string_value.encoded_len());
+
+        // encode_into appends to what the buffer already holds
+        let mut buf = BytesMut::from(&b"prefix"[..]);
+        string_value.encode_into(&mut buf);
+        assert_eq!(&buf[..6], b"prefix");
+        assert_eq!(buf[6..], expected);
+    }
+
+    #[test]
+    fn test_string_value_checksum() {
+        let string_value = create_test_string_value();
+        let mut encoded = checksum::with_checksum(|| string_value.encode());
+        let reserve_start = TYPE_LENGTH + TEST_VALUE.len();
+        assert_eq!(
+            &encoded[reserve_start..reserve_start + 4],
+            &checksum::crc32(&encoded[..reserve_start]).to_le_bytes()
+        );
+        assert!(checksum::with_checksum(|| ParsedStringsValue::new(encoded.clone())).is_ok());
+
+        encoded[TYPE_LENGTH] ^= 0xff;
+        let result = checksum::with_checksum(|| ParsedStringsValue::new(encoded));
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::ChecksumMismatch { .. })
+        ));
     }
 
     #[test]
@@ -224,6 +432,159 @@ mod tests_string_value {
         assert_eq!(parsed.inner.ctime, TEST_CTIME);
         assert_eq!(parsed.inner.etime, TEST_ETIME);
     }
+
+    #[test]
+    fn test_string_value_int_encoding() {
+        let mut string_value = StringValue::new(&b"-1234567"[..]);
+        string_value.set_ctime(TEST_CTIME);
+        string_value.set_etime(TEST_ETIME);
+        assert!(string_value.is_int_encoded());
+
+        let encoded = string_value.encode();
+        assert_eq!(encoded.len(), string_value.encoded_len());
+        assert_eq!(
+            encoded.len(),
+            TYPE_LENGTH + INT_VALUE_LENGTH + STRING_VALUE_SUFFIXLENGTH
+        );
+        assert_eq!(
+            &encoded[TYPE_LENGTH..TYPE_LENGTH + INT_VALUE_LENGTH],
+            &(-1234567i64).to_le_bytes()
+        );
+        let reserve_start = TYPE_LENGTH + INT_VALUE_LENGTH;
+        assert_eq!(
+            encoded[reserve_start + RESERVE_FLAGS_OFFSET] & STRING_INT_FLAG,
+            STRING_INT_FLAG
+        );
+
+        let mut parsed = ParsedStringsValue::new(encoded).unwrap();
+        assert!(parsed.is_int_encoded());
+        assert_eq!(parsed.int_value(), Some(-1234567));
+        assert_eq!(parsed.user_value_slice(), b"-1234567");
+        assert_eq!(parsed.reserve()[RESERVE_FLAGS_OFFSET] & STRING_INT_FLAG, 0);
+        assert_eq!(parsed.ctime(), TEST_CTIME);
+        assert_eq!(parsed.etime(), TEST_ETIME);
+
+        // the parsed value is in the text layout and parses again as is
+        parsed.set_etime(TEST_ETIME + 1);
+        let reparsed = ParsedStringsValue::new(parsed.inner.value.to_vec()).unwrap();
+        assert!(!reparsed.is_int_encoded());
+        assert_eq!(reparsed.int_value(), Some(-1234567));
+        assert_eq!(reparsed.etime(), TEST_ETIME + 1);
+
+        for int in [0, i64::MIN, i64::MAX] {
+            let parsed = ParsedStringsValue::new(StringValue::from_int(int).encode()).unwrap();
+            assert_eq!(parsed.int_value(), Some(int));
+            assert_eq!(parsed.user_value_slice(), int.to_string().as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_string_value_int_encoding_is_exact() {
+        for text in [
+            &b"+5"[..],
+            b"007",
+            b"-0",
+            b" 1",
+            b"",
+            b"1.5",
+            b"9223372036854775808",
+        ] {
+            let string_value = StringValue::new(text);
+            assert!(!string_value.is_int_encoded());
+            let parsed = ParsedStringsValue::new(string_value.encode()).unwrap();
+            assert_eq!(parsed.user_value_slice(), text);
+            assert_eq!(parsed.int_value(), None);
+        }
+    }
+
+    #[test]
+    fn test_string_value_int_encoding_checksum() {
+        let encoded = checksum::with_checksum(|| StringValue::from_int(42).encode());
+        let mut parsed =
+            checksum::with_checksum(|| ParsedStringsValue::new(encoded.clone())).unwrap();
+        assert_eq!(parsed.int_value(), Some(42));
+        // the checksum of the expanded value covers the text
+        let value = parsed.inner.value.to_vec();
+        assert!(checksum::with_checksum(|| ParsedStringsValue::new(value)).is_ok());
+        parsed.strip_suffix();
+        assert_eq!(&parsed.inner.value[..], b"42");
+
+        let mut corrupted = encoded;
+        corrupted[TYPE_LENGTH] ^= 0xff;
+        let result = checksum::with_checksum(|| ParsedStringsValue::new(corrupted));
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_string_value_segmented() {
+        // a header made of digits is still not int encoded
+        let header: &[u8] = b"1234567812345678";
+        let value = StringValue::segmented(header);
+        assert!(!value.is_int_encoded());
+
+        let parsed = ParsedStringsValue::new(value.encode()).unwrap();
+        assert!(parsed.is_segmented());
+        assert!(!parsed.is_int_encoded());
+        assert_eq!(parsed.user_value_slice(), header);
+
+        let plain = ParsedStringsValue::new(StringValue::new(header).encode()).unwrap();
+        assert!(!plain.is_segmented());
+    }
+
+    #[test]
+    fn test_string_value_compression() {
+        let blob = b"{\"kiwi\":\"rs\"},".repeat(64);
+        for codec in [ValueCompression::Lz4, ValueCompression::Zstd] {
+            let encoded = compression::with_compression(codec, 64, || {
+                checksum::with_checksum(|| {
+                    let mut value = StringValue::new(blob.clone());
+                    value.set_etime(TEST_ETIME);
+                    assert!(value.is_compressed());
+                    let encoded = value.encode();
+                    assert_eq!(encoded.len(), value.encoded_len());
+                    assert!(encoded.len() < blob.len());
+                    encoded
+                })
+            });
+
+            // Decompressed whatever the current settings
+            let mut parsed =
+                checksum::with_checksum(|| ParsedStringsValue::new(encoded.clone())).unwrap();
+            assert!(parsed.is_compressed());
+            assert_eq!(parsed.user_value_slice(), &blob[..]);
+            assert_eq!(
+                parsed.reserve()[RESERVE_FLAGS_OFFSET] & STRING_COMPRESSION_FLAGS,
+                0
+            );
+            assert_eq!(parsed.etime(), TEST_ETIME);
+
+            // the parsed value is in the plain layout and parses again as is
+            parsed.set_etime(TEST_ETIME + 1);
+            let value = parsed.inner.value.to_vec();
+            let reparsed = checksum::with_checksum(|| ParsedStringsValue::new(value)).unwrap();
+            assert!(!reparsed.is_compressed());
+            assert_eq!(reparsed.user_value_slice(), &blob[..]);
+
+            let mut corrupted = encoded;
+            corrupted[TYPE_LENGTH + 1] ^= 0xff;
+            let result = checksum::with_checksum(|| ParsedStringsValue::new(corrupted));
+            assert!(matches!(
+                result,
+                Err(crate::error::Error::ChecksumMismatch { .. })
+            ));
+        }
+
+        // Ints, values under the threshold and values that do not shrink
+        // stay uncompressed
+        compression::with_compression(ValueCompression::Lz4, 4, || {
+            assert!(!StringValue::new(&b"12345"[..]).is_compressed());
+            assert!(!StringValue::new(&b"abc"[..]).is_compressed());
+            assert!(!StringValue::new(&b"abcdef"[..]).is_compressed());
+        });
+    }
 }
 
 #[allow(dead_code)]
@@ -262,7 +623,16 @@ mod tests_parsed_string_value {
         buf.put_u8(DataType::String as u8);
         buf.put_slice(TEST_VALUE);
         let parsed = ParsedStringsValue::new(buf);
-        assert!(parsed.is_err());
+        assert!(matches!(
+            parsed,
+            Err(crate::error::Error::CorruptValue {
+                data_type: DataType::String,
+                expected,
+                actual,
+                ..
+            }) if expected == TYPE_LENGTH + STRING_VALUE_SUFFIXLENGTH
+                && actual == TYPE_LENGTH + TEST_VALUE.len()
+        ));
     }
 
     #[test]
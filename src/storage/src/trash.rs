@@ -0,0 +1,290 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Soft deletion
+//!
+//! With `StorageOptions::trash_retention_s` set, DEL does not drop a key but
+//! moves its meta value to the trash column family, stamped with the time of
+//! the deletion. The data entries of a collection are left in place, so
+//! UNDELETE only has to move the meta value back. `TrashFilter` purges the
+//! trash entries once the retention has passed.
+
+use kstd::lock_mgr::ScopeRecordLock;
+use log::debug;
+use rocksdb::{
+    compaction_filter::CompactionFilter, compaction_filter_factory::CompactionFilterFactory,
+    CompactionDecision, WriteBatch,
+};
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    clock,
+    coding::{decode_fixed, encode_fixed},
+    error::{OptionNoneSnafu, RocksSnafu},
+    filter_stats::{record, FilterKind, FilterOutcome},
+    redis_keys::is_live_meta_value,
+    ColumnFamilyIndex, Redis, Result,
+};
+
+/// Length of the deletion time stored in front of a trashed meta value.
+const DELETED_AT_LENGTH: usize = 8;
+
+/// Builds the trash value of a meta value deleted at `deleted_at`:
+/// | deleted_at 8 (microseconds) | meta value |
+pub fn encode_trash_value(deleted_at: u64, meta_value: &[u8]) -> Vec<u8> {
+    let mut dst = vec![0u8; DELETED_AT_LENGTH + meta_value.len()];
+    encode_fixed(&mut dst[..DELETED_AT_LENGTH], deleted_at);
+    dst[DELETED_AT_LENGTH..].copy_from_slice(meta_value);
+    dst
+}
+
+/// Splits a trash value into its deletion time and the original meta value,
+/// `None` when it is too short to hold one.
+pub fn decode_trash_value(value: &[u8]) -> Option<(u64, &[u8])> {
+    if value.len() <= DELETED_AT_LENGTH {
+        return None;
+    }
+    let deleted_at = decode_fixed(&value[..DELETED_AT_LENGTH]);
+    Some((deleted_at, &value[DELETED_AT_LENGTH..]))
+}
+
+/// Whether a key deleted at `deleted_at` has outlived its retention at `now`.
+fn is_purgeable(deleted_at: u64, retention_micros: u64, now: u64) -> bool {
+    deleted_at.saturating_add(retention_micros) < now
+}
+
+impl Redis {
+    /// Deletes the existing keys of `keys` and returns how many there were.
+    /// The meta values are moved to the trash when soft deletion is enabled.
+    pub fn del(&self, keys: &[&[u8]]) -> Result<usize> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let mut deleted = 0;
+        for &key in keys {
+            let key_str = String::from_utf8_lossy(key).to_string();
+            let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+            let meta_key = self.base_key(key).encode()?;
+            let Some(meta_value) = db
+                .get_cf_opt(&meta_cf, &meta_key, &self.read_options)
+                .context(RocksSnafu)?
+            else {
+                continue;
+            };
+            if !is_live_meta_value(&meta_value) {
+                continue;
+            }
+
+            let mut batch = WriteBatch::default();
+            self.stage_delete(&mut batch, &meta_key, &meta_value)?;
+            db.write_opt(batch, &self.write_options)
+                .context(RocksSnafu)?;
+            self.enqueue_lazy_delete(key, &meta_value);
+            deleted += 1;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Adds the deletion of the key `meta_key`, whose meta value is
+    /// `meta_value`, to `batch`: the meta value is moved to the trash when
+    /// soft deletion is enabled and dropped otherwise.
+    pub(crate) fn stage_delete(
+        &self,
+        batch: &mut WriteBatch,
+        meta_key: &[u8],
+        meta_value: &[u8],
+    ) -> Result<()> {
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        batch.delete_cf(&meta_cf, meta_key);
+
+        if self.storage.trash_retention_s > 0 {
+            let trash_cf =
+                self.get_cf_handle(ColumnFamilyIndex::TrashCF)
+                    .context(OptionNoneSnafu {
+                        message: "cf is not initialized".to_string(),
+                    })?;
+            let deleted_at = clock::now_micros();
+            batch.put_cf(
+                &trash_cf,
+                meta_key,
+                encode_trash_value(deleted_at, meta_value),
+            );
+        }
+        Ok(())
+    }
+
+    /// Restores a key moved to the trash by `del`. Returns false when the key
+    /// is not in the trash, its retention has passed, or a live key of the
+    /// same name has been created since.
+    pub fn undelete(&self, key: &[u8]) -> Result<bool> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let meta_cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let trash_cf = self
+            .get_cf_handle(ColumnFamilyIndex::TrashCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let _lock = ScopeRecordLock::new(self.lock_mgr.as_ref(), &key_str);
+
+        let meta_key = self.base_key(key).encode()?;
+        let Some(trash_value) = db
+            .get_cf_opt(&trash_cf, &meta_key, &self.read_options)
+            .context(RocksSnafu)?
+        else {
+            return Ok(false);
+        };
+        let Some((deleted_at, meta_value)) = decode_trash_value(&trash_value) else {
+            return Ok(false);
+        };
+        let retention_micros = self.storage.trash_retention_s.saturating_mul(1_000_000);
+        let now = clock::now_micros();
+        if is_purgeable(deleted_at, retention_micros, now) {
+            return Ok(false);
+        }
+
+        let current = db
+            .get_cf_opt(&meta_cf, &meta_key, &self.read_options)
+            .context(RocksSnafu)?;
+        if current.is_some_and(|value| is_live_meta_value(&value)) {
+            return Ok(false);
+        }
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&meta_cf, &meta_key, meta_value);
+        batch.delete_cf(&trash_cf, &meta_key);
+        db.write_opt(batch, &self.write_options)
+            .context(RocksSnafu)?;
+
+        Ok(true)
+    }
+}
+
+/// Removes the trash entries older than the retention.
+#[derive(Debug)]
+pub struct TrashFilter {
+    retention_micros: u64,
+    now: u64,
+}
+
+impl TrashFilter {
+    pub fn new(retention_s: u64) -> Self {
+        Self {
+            retention_micros: retention_s.saturating_mul(1_000_000),
+            now: clock::now_micros(),
+        }
+    }
+}
+
+impl CompactionFilter for TrashFilter {
+    fn name(&self) -> &std::ffi::CStr {
+        c"TrashFilter"
+    }
+
+    fn filter(&mut self, _level: u32, key: &[u8], value: &[u8]) -> CompactionDecision {
+        let outcome = match decode_trash_value(value) {
+            Some((deleted_at, _)) if !is_purgeable(deleted_at, self.retention_micros, self.now) => {
+                FilterOutcome::Kept
+            }
+            Some(_) => FilterOutcome::RemovedExpired,
+            None => {
+                debug!("TrashFilter: invalid trash value for key {key:?}, remove.");
+                FilterOutcome::RemovedCorrupt
+            }
+        };
+        record(FilterKind::Trash, outcome)
+    }
+}
+
+#[derive(Debug)]
+pub struct TrashFilterFactory {
+    retention_s: u64,
+}
+
+impl TrashFilterFactory {
+    pub fn new(retention_s: u64) -> Self {
+        Self { retention_s }
+    }
+}
+
+impl CompactionFilterFactory for TrashFilterFactory {
+    type Filter = TrashFilter;
+
+    fn create(
+        &mut self,
+        _context: rocksdb::compaction_filter_factory::CompactionFilterContext,
+    ) -> Self::Filter {
+        TrashFilter::new(self.retention_s)
+    }
+
+    fn name(&self) -> &std::ffi::CStr {
+        c"TrashFilterFactory"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trash_value_roundtrip() {
+        let encoded = encode_trash_value(42, b"meta");
+        let (deleted_at, meta_value) = decode_trash_value(&encoded).unwrap();
+        assert_eq!(deleted_at, 42);
+        assert_eq!(meta_value, b"meta");
+
+        assert!(decode_trash_value(&encoded[..DELETED_AT_LENGTH]).is_none());
+    }
+
+    #[test]
+    fn test_trash_filter() {
+        let mut filter = TrashFilter::new(60);
+        let now = clock::now_micros();
+
+        let fresh = encode_trash_value(now, b"meta");
+        let decision = filter.filter(0, b"key", &fresh);
+        assert!(matches!(decision, CompactionDecision::Keep));
+
+        let expired = encode_trash_value(now - 120 * 1_000_000, b"meta");
+        let decision = filter.filter(0, b"key", &expired);
+        assert!(matches!(decision, CompactionDecision::Remove));
+
+        let decision = filter.filter(0, b"key", b"bad");
+        assert!(matches!(decision, CompactionDecision::Remove));
+    }
+}
@@ -19,6 +19,8 @@
 
 //! Utility functions and data structures for the storage engine
 
+use crate::error::{AbortedSnafu, Result};
+use kstd::cancel::CancelToken;
 use std::fs;
 use std::io;
 use std::path::Path;
@@ -82,9 +84,150 @@ pub fn delete_dir<P: AsRef<Path>>(dirname: P) -> io::Result<()> {
     Ok(())
 }
 
+/// Fails with `Error::Aborted` once `token` is cancelled or expired.
+pub fn check_abort(token: &CancelToken) -> Result<()> {
+    match token.abort_reason() {
+        Some(reason) => AbortedSnafu { reason }.fail(),
+        None => Ok(()),
+    }
+}
+
+/// Glob-style pattern matching, following the rules of the Redis KEYS command:
+/// `*` matches any sequence, `?` any byte, `[...]` a set of bytes (with `^`
+/// negation and `a-z` ranges) and `\` escapes the next byte.
+pub fn string_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    let (mut p, mut s) = (0, 0);
+    // Position of the last `*` and of the byte it is currently matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if p < pattern.len() {
+            let matched = match pattern[p] {
+                b'*' => {
+                    star = Some((p, s));
+                    p += 1;
+                    continue;
+                }
+                b'?' => Some(1),
+                b'[' => {
+                    let (matched, len) = match_class(&pattern[p..], string[s], nocase);
+                    matched.then_some(len)
+                }
+                b'\\' if p + 1 < pattern.len() => eq(pattern[p + 1], string[s]).then_some(2),
+                c => eq(c, string[s]).then_some(1),
+            };
+            if let Some(len) = matched {
+                p += len;
+                s += 1;
+                continue;
+            }
+        }
+        // Mismatch: let the last `*` swallow one more byte.
+        match star {
+            Some((star_p, star_s)) => {
+                p = star_p + 1;
+                s = star_s + 1;
+                star = Some((star_p, s));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the `[...]` class at the start of `pattern`, returns
+/// whether it matched and the length of the class in the pattern.
+fn match_class(pattern: &[u8], c: u8, nocase: bool) -> (bool, usize) {
+    let fold = |b: u8| if nocase { b.to_ascii_lowercase() } else { b };
+    let c = fold(c);
+
+    let mut i = 1;
+    let negate = pattern.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < pattern.len() && pattern[i] != b']' {
+        if pattern[i] == b'\\' && i + 1 < pattern.len() {
+            i += 1;
+            matched |= fold(pattern[i]) == c;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' {
+            let (mut start, mut end) = (fold(pattern[i]), fold(pattern[i + 2]));
+            if start > end {
+                std::mem::swap(&mut start, &mut end);
+            }
+            matched |= (start..=end).contains(&c);
+            i += 2;
+        } else {
+            matched |= fold(pattern[i]) == c;
+        }
+        i += 1;
+    }
+
+    // An unterminated class extends to the end of the pattern.
+    (matched != negate, (i + 1).min(pattern.len()))
+}
+
 pub fn unique_test_db_path() -> std::path::PathBuf {
     tempfile::tempdir()
         .expect("Failed to create temp dir")
         .path()
         .join("kiwi-test-db")
 }
+
+/// Runs f on an instance opened with options at a `unique_test_db_path`,
+/// removed afterwards
+#[cfg(test)]
+pub(crate) fn with_redis(options: crate::StorageOptions, f: impl FnOnce(&crate::Redis)) {
+    let test_db_path = unique_test_db_path();
+    let (bg_task_handler, _) = crate::BgTaskHandler::new();
+    let mut redis = crate::Redis::new(
+        std::sync::Arc::new(options),
+        1,
+        std::sync::Arc::new(bg_task_handler),
+        std::sync::Arc::new(kstd::lock_mgr::LockMgr::new(1000)),
+    );
+    redis.open(test_db_path.to_str().unwrap()).unwrap();
+    f(&redis);
+    redis.set_need_close(true);
+    drop(redis);
+    let _ = std::fs::remove_dir_all(test_db_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_match() {
+        assert!(string_match(b"*", b"", false));
+        assert!(string_match(b"*", b"anything", false));
+        assert!(string_match(b"h?llo", b"hello", false));
+        assert!(!string_match(b"h?llo", b"hllo", false));
+        assert!(string_match(b"h*llo", b"hllo", false));
+        assert!(string_match(b"h*llo", b"heeeello", false));
+        assert!(string_match(b"*llo*", b"hello world", false));
+        assert!(!string_match(b"h*llo", b"hellox", false));
+        assert!(string_match(b"h[ae]llo", b"hallo", false));
+        assert!(!string_match(b"h[ae]llo", b"hillo", false));
+        assert!(string_match(b"h[^e]llo", b"hallo", false));
+        assert!(!string_match(b"h[^e]llo", b"hello", false));
+        assert!(string_match(b"h[a-c]llo", b"hbllo", false));
+        assert!(string_match(b"h[c-a]llo", b"hbllo", false));
+        assert!(!string_match(b"h[a-c]llo", b"hdllo", false));
+        assert!(string_match(b"h\\*llo", b"h*llo", false));
+        assert!(!string_match(b"h\\*llo", b"hello", false));
+        assert!(string_match(b"user:[\\]]", b"user:]", false));
+        assert!(string_match(b"HELLO", b"hello", true));
+        assert!(!string_match(b"HELLO", b"hello", false));
+        assert!(string_match(b"h[A-C]llo", b"hbllo", true));
+    }
+}
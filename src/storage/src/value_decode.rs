@@ -0,0 +1,374 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Field breakdown of encoded values, for DEBUG DECODE
+//!
+//! Decodes a raw value with the Parsed* type of its format and lists every
+//! field, so a value reported in a format bug can be inspected without
+//! reading the layouts by hand. A value of the meta column family is decoded
+//! by its type byte; data values carry no type and need their format named.
+
+use chrono::DateTime;
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    base_data_value_format::ParsedBaseDataValue,
+    base_meta_value_format::ParsedBaseMetaValue,
+    error::{InvalidFormatSnafu, OptionNoneSnafu, RocksSnafu},
+    hashes_data_value_format::ParsedHashesDataValue,
+    list_meta_value_format::ParsedListsMetaValue,
+    storage_define::{RESERVE_FLAGS_OFFSET, RESERVE_VERSION_OFFSET},
+    streams_meta_value_format::ParsedStreamsMetaValue,
+    strings_value_format::ParsedStringsValue,
+    ColumnFamilyIndex, DataType, Redis, Result,
+};
+
+/// The value formats that can be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeFormat {
+    /// A string value of the meta column family.
+    String,
+    /// The meta value of a hash, set or sorted set.
+    Meta,
+    /// The meta value of a list.
+    ListsMeta,
+    /// The meta value of a stream.
+    StreamsMeta,
+    /// A set member, sorted set member or list element value.
+    Data,
+    /// A hash field value, with its optional etime.
+    HashesData,
+}
+
+impl DecodeFormat {
+    pub const NAMES: [&'static str; 6] = ["string", "meta", "list", "stream", "data", "hashdata"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "string" => Some(DecodeFormat::String),
+            "meta" => Some(DecodeFormat::Meta),
+            "list" => Some(DecodeFormat::ListsMeta),
+            "stream" => Some(DecodeFormat::StreamsMeta),
+            "data" => Some(DecodeFormat::Data),
+            "hashdata" => Some(DecodeFormat::HashesData),
+            _ => None,
+        }
+    }
+
+    /// The format of a value of the meta column family, by its type byte.
+    pub fn of_meta_value(value: &[u8]) -> Option<Self> {
+        let data_type = DataType::try_from(*value.first()?).ok()?;
+        match data_type {
+            DataType::String => Some(DecodeFormat::String),
+            DataType::Hash | DataType::Set | DataType::ZSet => Some(DecodeFormat::Meta),
+            DataType::List => Some(DecodeFormat::ListsMeta),
+            DataType::Stream => Some(DecodeFormat::StreamsMeta),
+            DataType::None | DataType::All => None,
+        }
+    }
+}
+
+/// One decoded field, its value rendered for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedField {
+    pub name: &'static str,
+    pub value: String,
+}
+
+impl DecodedField {
+    fn new(name: &'static str, value: impl ToString) -> Self {
+        Self {
+            name,
+            value: value.to_string(),
+        }
+    }
+}
+
+/// Lowercase hex of bytes.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Parses hex digits, ignoring an optional `0x` prefix.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let escaped: String = bytes
+        .iter()
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(char::from)
+        .collect();
+    format!("\"{escaped}\" ({} bytes)", bytes.len())
+}
+
+fn timestamp(micros: u64) -> String {
+    if micros == 0 {
+        return "0".to_string();
+    }
+    match DateTime::from_timestamp_micros(micros as i64) {
+        Some(time) => format!("{micros} ({})", time.to_rfc3339()),
+        None => micros.to_string(),
+    }
+}
+
+fn type_byte(raw: &[u8]) -> DecodedField {
+    let byte = raw[0];
+    let name = DataType::try_from(byte)
+        .map(crate::data_type_to_string)
+        .unwrap_or("invalid");
+    DecodedField::new("type", format!("{byte} ({name})"))
+}
+
+fn reserve_fields(fields: &mut Vec<DecodedField>, reserve: &[u8]) {
+    fields.push(DecodedField::new("reserve", to_hex(reserve)));
+    fields.push(DecodedField::new(
+        "reserve.flags",
+        format!("{:#04x}", reserve[RESERVE_FLAGS_OFFSET]),
+    ));
+    fields.push(DecodedField::new(
+        "reserve.format_version",
+        reserve[RESERVE_VERSION_OFFSET],
+    ));
+}
+
+/// Decodes raw as a value of format and lists its fields in layout order.
+pub fn decode_value(format: DecodeFormat, raw: &[u8]) -> Result<Vec<DecodedField>> {
+    let mut fields = Vec::new();
+    match format {
+        DecodeFormat::String => {
+            let parsed = ParsedStringsValue::new(raw)?;
+            fields.push(type_byte(raw));
+            fields.push(DecodedField::new(
+                "value",
+                escape(parsed.user_value_slice()),
+            ));
+            let encoding = if parsed.is_int_encoded() {
+                "int"
+            } else if parsed.is_segmented() {
+                "segmented"
+            } else if parsed.is_compressed() {
+                "compressed"
+            } else {
+                "raw"
+            };
+            fields.push(DecodedField::new("encoding", encoding));
+            reserve_fields(&mut fields, parsed.reserve());
+            fields.push(DecodedField::new("ctime", timestamp(parsed.ctime())));
+            fields.push(DecodedField::new("etime", timestamp(parsed.etime())));
+        }
+        DecodeFormat::Meta => {
+            let parsed = ParsedBaseMetaValue::new(raw)?;
+            fields.push(type_byte(raw));
+            fields.push(DecodedField::new("count", parsed.count()));
+            fields.push(DecodedField::new("version", parsed.version()));
+            if let Some(collection) = parsed.inline_collection()? {
+                for (key, value) in collection.iter() {
+                    fields.push(DecodedField::new("inline", escape(key)));
+                    fields.push(DecodedField::new("inline_value", escape(value)));
+                }
+            }
+            reserve_fields(&mut fields, parsed.reserve());
+            fields.push(DecodedField::new("ctime", timestamp(parsed.ctime())));
+            fields.push(DecodedField::new("etime", timestamp(parsed.etime())));
+        }
+        DecodeFormat::ListsMeta => {
+            let parsed = ParsedListsMetaValue::new(raw)?;
+            fields.push(type_byte(raw));
+            fields.push(DecodedField::new("count", parsed.count()));
+            fields.push(DecodedField::new("version", parsed.version()));
+            fields.push(DecodedField::new("left_index", parsed.left_index()));
+            fields.push(DecodedField::new("right_index", parsed.right_index()));
+            reserve_fields(&mut fields, parsed.reserve());
+            fields.push(DecodedField::new("ctime", timestamp(parsed.ctime())));
+            fields.push(DecodedField::new("etime", timestamp(parsed.etime())));
+        }
+        DecodeFormat::StreamsMeta => {
+            let parsed = ParsedStreamsMetaValue::new(raw)?;
+            fields.push(type_byte(raw));
+            fields.push(DecodedField::new("length", parsed.length()));
+            fields.push(DecodedField::new("version", parsed.version()));
+            fields.push(DecodedField::new("last_id", parsed.last_id()));
+            fields.push(DecodedField::new("max_deleted_id", parsed.max_deleted_id()));
+            fields.push(DecodedField::new("groups_count", parsed.groups_count()));
+            reserve_fields(&mut fields, parsed.reserve());
+            fields.push(DecodedField::new("ctime", timestamp(parsed.ctime())));
+            fields.push(DecodedField::new("etime", timestamp(parsed.etime())));
+        }
+        DecodeFormat::Data => {
+            let parsed = ParsedBaseDataValue::new(raw)?;
+            fields.push(DecodedField::new(
+                "value",
+                escape(parsed.user_value_slice()),
+            ));
+            reserve_fields(&mut fields, parsed.reserve());
+            fields.push(DecodedField::new("ctime", timestamp(parsed.ctime())));
+        }
+        DecodeFormat::HashesData => {
+            let parsed = ParsedHashesDataValue::new(raw)?;
+            fields.push(DecodedField::new(
+                "value",
+                escape(parsed.user_value_slice()),
+            ));
+            fields.push(DecodedField::new("etime", timestamp(parsed.etime())));
+            reserve_fields(&mut fields, parsed.reserve());
+            fields.push(DecodedField::new("ctime", timestamp(parsed.ctime())));
+        }
+    }
+    Ok(fields)
+}
+
+impl Redis {
+    /// The raw value of key in the meta column family, expired or not.
+    pub fn raw_meta_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        db.get_cf_opt(&cf, self.base_key(key).encode()?, &self.read_options)
+            .context(RocksSnafu)
+    }
+}
+
+/// Decodes the meta value of a key read with `Redis::raw_meta_value`.
+pub fn decode_meta_value(raw: &[u8]) -> Result<Vec<DecodedField>> {
+    let format = DecodeFormat::of_meta_value(raw).context(InvalidFormatSnafu {
+        message: format!("unknown type byte in meta value {}", to_hex(raw)),
+    })?;
+    decode_value(format, raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_data_value_format::BaseDataValue;
+    use crate::base_meta_value_format::BaseMetaValue;
+    use crate::inline_collection_format::InlineCollection;
+    use crate::storage_define::SUFFIX_RESERVE_LENGTH;
+    use crate::strings_value_format::StringValue;
+    use bytes::{BufMut, BytesMut};
+
+    fn field<'a>(fields: &'a [DecodedField], name: &str) -> &'a str {
+        &fields.iter().find(|f| f.name == name).unwrap().value
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00ab10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(from_hex("0x00AB"), Some(vec![0x00, 0xab]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_decode_string() {
+        let mut value = StringValue::new(&b"v\x00"[..]);
+        value.set_ctime(1_700_000_000_000_000);
+        value.set_etime(0);
+        let encoded = value.encode();
+
+        let fields = decode_meta_value(&encoded).unwrap();
+        let names: Vec<_> = fields.iter().map(|f| f.name).collect();
+        assert_eq!(
+            names,
+            [
+                "type",
+                "value",
+                "encoding",
+                "reserve",
+                "reserve.flags",
+                "reserve.format_version",
+                "ctime",
+                "etime"
+            ]
+        );
+        assert_eq!(field(&fields, "type"), "0 (string)");
+        assert_eq!(field(&fields, "value"), "\"v\\x00\" (2 bytes)");
+        assert_eq!(field(&fields, "encoding"), "raw");
+        assert_eq!(field(&fields, "etime"), "0");
+        assert!(field(&fields, "ctime").starts_with("1700000000000000 (2023-11-14"));
+
+        let fields = decode_meta_value(&StringValue::from_int(-7).encode()).unwrap();
+        assert_eq!(field(&fields, "value"), "\"-7\" (2 bytes)");
+        assert_eq!(field(&fields, "encoding"), "int");
+    }
+
+    #[test]
+    fn test_decode_meta() {
+        // | type | count | version | reserve | ctime | etime |
+        let mut encoded = BytesMut::new();
+        encoded.put_u8(DataType::Hash as u8);
+        encoded.put_u64_le(3);
+        encoded.put_u64_le(42);
+        encoded.put_bytes(0, SUFFIX_RESERVE_LENGTH);
+        encoded.put_u64_le(0);
+        encoded.put_u64_le(0);
+
+        let fields = decode_meta_value(&encoded).unwrap();
+        assert_eq!(field(&fields, "type"), "1 (hash)");
+        assert_eq!(field(&fields, "count"), "3");
+        assert_eq!(field(&fields, "version"), "42");
+    }
+
+    #[test]
+    fn test_decode_inline_meta() {
+        let mut collection = InlineCollection::new();
+        collection.insert(b"f", b"v");
+        let mut meta = BaseMetaValue::new(0);
+        meta.inner.data_type = DataType::Hash;
+        meta.set_inline(Some(&collection));
+
+        let fields = decode_meta_value(&meta.encode()).unwrap();
+        assert_eq!(field(&fields, "count"), "1");
+        assert_eq!(field(&fields, "inline"), "\"f\" (1 bytes)");
+        assert_eq!(field(&fields, "inline_value"), "\"v\" (1 bytes)");
+        assert_eq!(field(&fields, "reserve.flags"), "0x04");
+    }
+
+    #[test]
+    fn test_decode_data() {
+        let encoded = BaseDataValue::new(&b"member"[..]).encode();
+        let fields = decode_value(DecodeFormat::Data, &encoded).unwrap();
+        assert_eq!(field(&fields, "value"), "\"member\" (6 bytes)");
+        assert_eq!(field(&fields, "reserve.format_version"), "0");
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(decode_meta_value(&[0xff, 0x00]).is_err());
+        assert!(decode_value(DecodeFormat::Meta, b"short").is_err());
+        assert_eq!(
+            DecodeFormat::from_name("HashData"),
+            Some(DecodeFormat::HashesData)
+        );
+        assert_eq!(DecodeFormat::from_name("nope"), None);
+    }
+}
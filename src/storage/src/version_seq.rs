@@ -0,0 +1,322 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Versions of the collection meta values
+//!
+//! The data keys of a hash, set, sorted set, list or stream carry the
+//! version of their meta value, and a new version orphans the data keys of
+//! the previous one until compaction drops them. Versions used to be the
+//! time in microseconds, so a clock set back across a restart could hand out
+//! a version that orphaned data keys still hold, and make them visible
+//! again. Versions now come from a process wide counter that never goes
+//! back. It is reserved in blocks of `VERSION_RESERVE_BATCH`: the end of the
+//! reserved block is persisted in the system column family of each instance,
+//! and an instance opening moves the counter past it. `next_version`
+//! persists the next block itself once half of the reserved one is used, so
+//! every path handing out versions is covered.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use log::error;
+use rocksdb::{WriteOptions, DB};
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    clock,
+    coding::decode_fixed,
+    error::{OptionNoneSnafu, RocksSnafu},
+    ColumnFamilyIndex, Redis, Result,
+};
+
+/// Key of the version ceiling in the system column family
+const VERSION_CEILING_KEY: &[u8] = b"version_ceiling";
+
+/// Number of versions reserved at once
+const VERSION_RESERVE_BATCH: u64 = 1 << 20;
+
+/// The last version handed out
+static LAST: AtomicU64 = AtomicU64::new(0);
+/// The end of the reserved versions
+static CEILING: AtomicU64 = AtomicU64::new(0);
+
+/// The open instances, the ceiling is persisted in each of them
+fn instances() -> &'static Mutex<Vec<Weak<DB>>> {
+    static INSTANCES: OnceLock<Mutex<Vec<Weak<DB>>>> = OnceLock::new();
+    INSTANCES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// A version greater than current and than every version handed out before
+pub(crate) fn next_version(current: u64) -> u64 {
+    // Start from the clock, like the versions written before the counter
+    if LAST.load(Ordering::Relaxed) == 0 {
+        LAST.fetch_max(clock::now_micros(), Ordering::Relaxed);
+    }
+    LAST.fetch_max(current, Ordering::Relaxed);
+    let version = LAST.fetch_add(1, Ordering::Relaxed) + 1;
+    if needs_reserve(version) {
+        reserve_and_persist();
+    }
+    version
+}
+
+/// Whether half of the reserved versions are used once `last` is
+fn needs_reserve(last: u64) -> bool {
+    last.saturating_add(VERSION_RESERVE_BATCH / 2) >= CEILING.load(Ordering::Relaxed)
+}
+
+/// Moves the counter past a ceiling persisted by a previous run
+fn restore(ceiling: u64) {
+    LAST.fetch_max(ceiling.max(clock::now_micros()), Ordering::Relaxed);
+}
+
+/// Reserves a block of versions after the last one handed out and returns
+/// the ceiling to persist
+fn reserve() -> u64 {
+    let ceiling = LAST.load(Ordering::Relaxed) + VERSION_RESERVE_BATCH;
+    CEILING.fetch_max(ceiling, Ordering::Relaxed);
+    ceiling
+}
+
+/// Reserves the next block and persists its end in every open instance,
+/// before the version that used up half of the current block is returned
+fn reserve_and_persist() {
+    let mut instances = instances().lock().unwrap_or_else(|e| e.into_inner());
+    // Another thread reserved the block meanwhile
+    if !needs_reserve(LAST.load(Ordering::Relaxed)) {
+        return;
+    }
+    persist(&mut instances, reserve());
+}
+
+/// Writes `ceiling` to the open instances and forgets the closed ones. A
+/// failed write is logged, the instance keeps its previous ceiling
+fn persist(instances: &mut Vec<Weak<DB>>, ceiling: u64) {
+    instances.retain(|db| db.strong_count() > 0);
+    for db in instances.iter().filter_map(Weak::upgrade) {
+        let Some(cf) = db.cf_handle(ColumnFamilyIndex::SystemCF.name()) else {
+            continue;
+        };
+        if let Err(e) = db.put_cf_opt(
+            &cf,
+            VERSION_CEILING_KEY,
+            ceiling.to_le_bytes(),
+            &sync_write(),
+        ) {
+            error!("persisting the version ceiling {ceiling} failed: {e}");
+        }
+    }
+}
+
+/// The ceiling must be on disk before any version under it is used
+fn sync_write() -> WriteOptions {
+    let mut write_options = WriteOptions::default();
+    write_options.set_sync(true);
+    write_options
+}
+
+pub(crate) fn decode_ceiling(value: &[u8]) -> u64 {
+    match value.len() {
+        8 => decode_fixed(value),
+        _ => 0,
+    }
+}
+
+impl Redis {
+    /// Moves the version counter past the ceiling persisted by the last run,
+    /// reserves the first block of versions and registers the instance for
+    /// the next blocks. Called when opening
+    pub(crate) fn restore_version_sequence(&self) -> Result<()> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::SystemCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let stored = db
+            .get_cf_opt(&cf, VERSION_CEILING_KEY, &self.read_options)
+            .context(RocksSnafu)?;
+        restore(stored.as_deref().map(decode_ceiling).unwrap_or(0));
+
+        // Under the lock, no block is reserved before the instance is
+        // registered. The other instances get the new block too
+        let mut instances = instances().lock().unwrap_or_else(|e| e.into_inner());
+        let ceiling = reserve();
+        db.put_cf_opt(
+            &cf,
+            VERSION_CEILING_KEY,
+            ceiling.to_le_bytes(),
+            &sync_write(),
+        )
+        .context(RocksSnafu)?;
+        persist(&mut instances, ceiling);
+        instances.push(Arc::downgrade(db));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_meta_value_format::BaseMetaValue;
+    use crate::clock::MockClock;
+    use crate::intent_log::Intent;
+    use crate::list_meta_value_format::ListsMetaValue;
+    use crate::streams_meta_value_format::StreamsMetaValue;
+    use crate::{unique_test_db_path, BgTaskHandler, StorageOptions};
+    use bytes::Bytes;
+    use kstd::lock_mgr::LockMgr;
+    use std::sync::Arc;
+
+    fn open(path: &std::path::Path) -> Redis {
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let mut redis = Redis::new(
+            Arc::new(StorageOptions::default()),
+            1,
+            Arc::new(bg_task_handler),
+            Arc::new(LockMgr::new(1000)),
+        );
+        redis.open(path.to_str().unwrap()).unwrap();
+        redis
+    }
+
+    fn stored_ceiling(redis: &Redis) -> u64 {
+        let db = redis.db.as_ref().unwrap();
+        let cf = redis.get_cf_handle(ColumnFamilyIndex::SystemCF).unwrap();
+        let value = db.get_cf(&cf, VERSION_CEILING_KEY).unwrap().unwrap();
+        decode_ceiling(&value)
+    }
+
+    #[test]
+    fn test_next_version_is_increasing() {
+        let first = next_version(0);
+        let second = next_version(0);
+        assert!(second > first);
+        assert!(next_version(first) > second);
+
+        let high = second + 1_000_000;
+        assert!(next_version(high) > high);
+        assert!(next_version(0) > high);
+    }
+
+    /// The versions given by each path that hands them out
+    fn versions(redis: &Redis) -> [u64; 4] {
+        let intent = Intent::RestoreMeta {
+            key: b"key".to_vec(),
+            value: Vec::new(),
+        };
+        [
+            BaseMetaValue::new(0).update_version(),
+            ListsMetaValue::new(Bytes::copy_from_slice(&0u64.to_le_bytes())).update_version(),
+            StreamsMetaValue::new(Bytes::copy_from_slice(&0u64.to_le_bytes())).update_version(),
+            redis.log_intent(&intent).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_clock_rollback_does_not_lower_versions() {
+        let test_db_path = unique_test_db_path();
+        let clock = Arc::new(MockClock::new(u64::MAX / 4));
+        let before = clock::with_clock(clock.clone(), || {
+            let redis = open(&test_db_path);
+            let before = versions(&redis);
+            assert!(before.iter().all(|version| *version > u64::MAX / 4));
+            redis.set_need_close(true);
+            before
+        });
+
+        // A restart with the clock set back still hands out higher versions,
+        // on every path
+        clock.set(0);
+        clock::with_clock(clock, || {
+            let redis = open(&test_db_path);
+            let highest = before.into_iter().max().unwrap();
+            assert!(versions(&redis).iter().all(|version| *version > highest));
+            redis.set_need_close(true);
+        });
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+
+    #[test]
+    fn test_next_version_reserves() {
+        next_version(0);
+        let ceiling = CEILING.load(Ordering::Relaxed);
+        assert!(ceiling > LAST.load(Ordering::Relaxed));
+
+        // Half way through the block the next one is reserved
+        let version = next_version(ceiling - VERSION_RESERVE_BATCH / 2);
+        assert!(CEILING.load(Ordering::Relaxed) >= version + VERSION_RESERVE_BATCH);
+        assert!(!needs_reserve(version));
+    }
+
+    #[test]
+    fn test_versions_survive_clock_going_back() {
+        let test_db_path = unique_test_db_path();
+        let used = next_version(0);
+        let redis = open(&test_db_path);
+        let ceiling = stored_ceiling(&redis);
+        assert!(ceiling > used);
+        redis.set_need_close(true);
+        drop(redis);
+
+        // A restart with the clock set back to 0 still starts past the
+        // ceiling of the previous run
+        let clock = Arc::new(MockClock::new(0));
+        clock::with_clock(clock, || {
+            let redis = open(&test_db_path);
+            assert!(next_version(0) > ceiling);
+            assert!(stored_ceiling(&redis) > ceiling);
+            redis.set_need_close(true);
+        });
+
+        assert_eq!(decode_ceiling(b"bad"), 0);
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+
+    #[test]
+    fn test_versions_of_other_paths_survive_restart() {
+        let test_db_path = unique_test_db_path();
+        let redis = open(&test_db_path);
+        let ceiling = stored_ceiling(&redis);
+
+        // An intent id is a version written without any meta value, half
+        // way through the block it still has the next one persisted
+        LAST.fetch_max(ceiling - VERSION_RESERVE_BATCH / 2, Ordering::Relaxed);
+        let id = redis
+            .log_intent(&Intent::RestoreMeta {
+                key: b"key".to_vec(),
+                value: Vec::new(),
+            })
+            .unwrap();
+        assert!(stored_ceiling(&redis) > id);
+        redis.set_need_close(true);
+        drop(redis);
+
+        let clock = Arc::new(MockClock::new(0));
+        clock::with_clock(clock, || {
+            let redis = open(&test_db_path);
+            assert!(next_version(0) > id);
+            redis.set_need_close(true);
+        });
+        let _ = std::fs::remove_dir_all(test_db_path);
+    }
+}
@@ -0,0 +1,352 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Block cache warm-up
+//!
+//! A restarted node starts with an empty block cache, so the first reads of
+//! its hottest keys pay for index and data block IO. Warming reads those
+//! keys once through the normal read path to load their blocks into cache.
+//! The hot keys of the last run are kept in the system column family, see
+//! `Storage::save_hot_keys_snapshot`.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bytes::BytesMut;
+use kstd::cancel::CancelToken;
+use rocksdb::{Direction, IteratorMode, ReadOptions};
+use snafu::{OptionExt, ResultExt};
+
+use crate::{
+    base_key_format::ParsedBaseKey,
+    base_meta_value_format::ParsedBaseMetaValue,
+    coding::encode_fixed,
+    error::{IoSnafu, OptionNoneSnafu, RocksSnafu},
+    list_meta_value_format::ParsedListsMetaValue,
+    storage_define::{
+        encode_user_key, ENCODED_KEY_DELIM_SIZE, PREFIX_RESERVE_LENGTH, VERSION_LENGTH,
+    },
+    util::check_abort,
+    ColumnFamilyIndex, DataType, Redis, Result,
+};
+
+/// Maximum number of data entries read per collection, so warming a huge
+/// hash does not evict everything else from the cache.
+const WARMUP_MAX_DATA_ENTRIES: usize = 1024;
+
+/// Number of keys visited between two checks of the cancel token.
+const ABORT_CHECK_INTERVAL: usize = 256;
+
+/// Key of the hot-key snapshot in the system column family.
+const HOT_KEYS_SNAPSHOT_KEY: &[u8] = b"hot_keys_snapshot";
+
+/// What to load into the block cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarmupTarget {
+    /// The given user keys.
+    Keys(Vec<Vec<u8>>),
+    /// Every key starting with the given prefix.
+    Prefix(Vec<u8>),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WarmupStats {
+    /// Keys whose meta value was found and read.
+    pub keys: u64,
+    /// Requested keys that do not exist.
+    pub missing: u64,
+    /// Entries read from the data column families.
+    pub data_entries: u64,
+}
+
+impl WarmupStats {
+    pub fn merge(&mut self, other: &WarmupStats) {
+        self.keys += other.keys;
+        self.missing += other.missing;
+        self.data_entries += other.data_entries;
+    }
+}
+
+impl Redis {
+    /// Reads the meta value of every key in `keys` and the first data entries
+    /// of collections, filling the block cache with their blocks.
+    pub fn warmup_keys(&self, keys: &[Vec<u8>], token: &CancelToken) -> Result<WarmupStats> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        let mut stats = WarmupStats::default();
+        for (i, key) in keys.iter().enumerate() {
+            if i % ABORT_CHECK_INTERVAL == 0 {
+                check_abort(token)?;
+            }
+            let meta_key = self.base_key(key).encode()?;
+            match db
+                .get_cf_opt(&cf, &meta_key, &self.read_options)
+                .context(RocksSnafu)?
+            {
+                Some(value) => self.warmup_data(key, &value, &mut stats)?,
+                None => stats.missing += 1,
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Warms every key of the meta column family starting with `prefix`.
+    pub fn warmup_prefix(&self, prefix: &[u8], token: &CancelToken) -> Result<WarmupStats> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::MetaCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+
+        // The escaping of user keys works byte by byte, so the encoded prefix
+        // without its delimiter is a prefix of every matching encoded key.
+        // With the slot prefix the matching keys are spread over all slots,
+        // and the whole column family is scanned instead.
+        let seek_key = match self.storage.slot_prefix {
+            true => BytesMut::new(),
+            false => {
+                let mut seek_key = BytesMut::zeroed(PREFIX_RESERVE_LENGTH);
+                encode_user_key(prefix, &mut seek_key)?;
+                seek_key.truncate(seek_key.len() - ENCODED_KEY_DELIM_SIZE);
+                seek_key
+            }
+        };
+
+        let mut stats = WarmupStats::default();
+        let iter = db.iterator_cf(&cf, IteratorMode::From(&seek_key, Direction::Forward));
+        for (i, item) in iter.enumerate() {
+            if i % ABORT_CHECK_INTERVAL == 0 {
+                check_abort(token)?;
+            }
+            let (key, value) = item.context(RocksSnafu)?;
+            if !key.starts_with(&seek_key) {
+                break;
+            }
+            let parsed_key = ParsedBaseKey::new(&key)?;
+            if !parsed_key.key().starts_with(prefix) {
+                continue;
+            }
+            self.warmup_data(parsed_key.key(), &value, &mut stats)?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Stores the hot-key list in the system column family, replacing the
+    /// previous snapshot.
+    pub fn save_hot_keys_snapshot(&self, keys: &[Vec<u8>]) -> Result<()> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::SystemCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        db.put_cf_opt(
+            &cf,
+            HOT_KEYS_SNAPSHOT_KEY,
+            encode_hot_keys(keys),
+            &self.write_options,
+        )
+        .context(RocksSnafu)
+    }
+
+    /// Returns the hot-key list saved by `save_hot_keys_snapshot`, empty when
+    /// there is none.
+    pub fn load_hot_keys_snapshot(&self) -> Result<Vec<Vec<u8>>> {
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let cf = self
+            .get_cf_handle(ColumnFamilyIndex::SystemCF)
+            .context(OptionNoneSnafu {
+                message: "cf is not initialized".to_string(),
+            })?;
+        let value = db
+            .get_cf_opt(&cf, HOT_KEYS_SNAPSHOT_KEY, &self.read_options)
+            .context(RocksSnafu)?;
+        Ok(value.map(|data| decode_hot_keys(&data)).unwrap_or_default())
+    }
+
+    fn warmup_data(&self, key: &[u8], meta_value: &[u8], stats: &mut WarmupStats) -> Result<()> {
+        stats.keys += 1;
+
+        let Some(data_type) = meta_value.first().and_then(|t| DataType::try_from(*t).ok()) else {
+            return Ok(());
+        };
+        let (version, cfs): (u64, &[ColumnFamilyIndex]) = match data_type {
+            DataType::Hash => (
+                ParsedBaseMetaValue::new(meta_value)?.version(),
+                &[ColumnFamilyIndex::HashesDataCF],
+            ),
+            DataType::Set => (
+                ParsedBaseMetaValue::new(meta_value)?.version(),
+                &[ColumnFamilyIndex::SetsDataCF],
+            ),
+            DataType::ZSet => (
+                ParsedBaseMetaValue::new(meta_value)?.version(),
+                &[
+                    ColumnFamilyIndex::ZsetsDataCF,
+                    ColumnFamilyIndex::ZsetsScoreCF,
+                ],
+            ),
+            DataType::List => (
+                ParsedListsMetaValue::new(meta_value)?.version(),
+                &[ColumnFamilyIndex::ListsDataCF],
+            ),
+            // TODO: warm the stream entries once they have a column family
+            DataType::String | DataType::Stream | DataType::None | DataType::All => return Ok(()),
+        };
+
+        let db = self.db.as_ref().context(OptionNoneSnafu {
+            message: "db is not initialized".to_string(),
+        })?;
+        let prefix = data_key_prefix(self.key_reserve1(key), key, version)?;
+        for &cf_index in cfs {
+            let Some(cf) = self.get_cf_handle(cf_index) else {
+                continue;
+            };
+            let mut read_options = ReadOptions::default();
+            read_options.fill_cache(true);
+            let iter = db.iterator_cf_opt(
+                &cf,
+                read_options,
+                IteratorMode::From(&prefix, Direction::Forward),
+            );
+            for item in iter.take(WARMUP_MAX_DATA_ENTRIES) {
+                let (data_key, _) = item.context(RocksSnafu)?;
+                if !data_key.starts_with(&prefix) {
+                    break;
+                }
+                stats.data_entries += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `| reserve1 | key | version |`, shared by all data keys of a collection.
+fn data_key_prefix(
+    reserve1: [u8; PREFIX_RESERVE_LENGTH],
+    key: &[u8],
+    version: u64,
+) -> Result<Vec<u8>> {
+    let mut dst = BytesMut::from(&reserve1[..]);
+    encode_user_key(key, &mut dst)?;
+    let mut version_buf = [0u8; VERSION_LENGTH];
+    encode_fixed(&mut version_buf, version);
+    dst.extend_from_slice(&version_buf);
+    Ok(dst.to_vec())
+}
+
+/// Writes a hot-key list to `path` in the `encode_hot_keys` format. The file
+/// is replaced atomically.
+pub fn save_hot_keys(path: impl AsRef<Path>, keys: &[Vec<u8>]) -> Result<()> {
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, encode_hot_keys(keys)).context(IoSnafu)?;
+    fs::rename(&tmp_path, path).context(IoSnafu)
+}
+
+/// Reads a hot-key list written by `save_hot_keys`. A missing file yields an
+/// empty list.
+pub fn load_hot_keys(path: impl AsRef<Path>) -> Result<Vec<Vec<u8>>> {
+    match fs::read(path.as_ref()) {
+        Ok(data) => Ok(decode_hot_keys(&data)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).context(IoSnafu),
+    }
+}
+
+/// Serializes a hot-key list, each key as a 4-byte little endian length
+/// followed by its bytes.
+pub fn encode_hot_keys(keys: &[Vec<u8>]) -> Vec<u8> {
+    let mut dst = Vec::with_capacity(keys.iter().map(|key| key.len() + 4).sum());
+    for key in keys {
+        dst.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        dst.extend_from_slice(key);
+    }
+    dst
+}
+
+/// Reverses `encode_hot_keys`, a truncated trailing entry is ignored.
+pub fn decode_hot_keys(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut keys = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 4 {
+        let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        rest = &rest[4..];
+        if rest.len() < len {
+            log::warn!("hot key list is truncated, ignoring the last entry");
+            break;
+        }
+        keys.push(rest[..len].to_vec());
+        rest = &rest[len..];
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unique_test_db_path;
+
+    #[test]
+    fn test_hot_keys_round_trip() {
+        let dir = unique_test_db_path();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hot_keys");
+
+        assert!(load_hot_keys(&path).unwrap().is_empty());
+
+        let keys = vec![b"a".to_vec(), Vec::new(), b"key\x00with\x00zeros".to_vec()];
+        save_hot_keys(&path, &keys).unwrap();
+        assert_eq!(load_hot_keys(&path).unwrap(), keys);
+
+        // a partially written entry at the end is dropped
+        let mut data = fs::read(&path).unwrap();
+        data.extend_from_slice(&[9, 0, 0, 0, b'x']);
+        fs::write(&path, data).unwrap();
+        assert_eq!(load_hot_keys(&path).unwrap(), keys);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_data_key_prefix() {
+        let prefix = data_key_prefix([0; PREFIX_RESERVE_LENGTH], b"k\x00", 7).unwrap();
+        let mut expected = vec![0u8; PREFIX_RESERVE_LENGTH];
+        expected.extend_from_slice(b"k\x00\x01\x00\x00");
+        expected.extend_from_slice(&7u64.to_le_bytes());
+        assert_eq!(prefix, expected);
+    }
+}
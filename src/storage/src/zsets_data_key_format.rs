@@ -0,0 +1,597 @@
+/*
+ * Copyright (c) 2024-present, arana-db Community.  All rights reserved.
+ *
+ * Licensed to the Apache Software Foundation (ASF) under one or more
+ * contributor license agreements.  See the NOTICE file distributed with
+ * this work for additional information regarding copyright ownership.
+ * The ASF licenses this file to You under the Apache License, Version 2.0
+ * (the "License"); you may not use this file except in compliance with
+ * the License.  You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![cfg_attr(not(test), allow(dead_code))]
+
+use crate::coding::{decode_fixed, encode_fixed};
+use crate::error::Result;
+use crate::storage_define::{
+    decode_user_key, encode_user_key, ENCODED_KEY_DELIM_SIZE, NEED_TRANSFORM_CHARACTER,
+};
+use bytes::BytesMut;
+
+// Constants for fixed-length fields
+const RESERVE1_LEN: usize = 8;
+const RESERVE2_LEN: usize = 16;
+const U64_LEN: usize = 8;
+const SCORE_LEN: usize = 8;
+
+/*
+ * Format for ZSet member key, stored in zset_data_cf, value is the score
+ * | reserve1 | key | version | member | reserve2 |
+ * |    8B    |     |    8B   |        |   16B    |
+ *
+ * Format for ZSet score key, stored in zset_score_cf, value is empty
+ * | reserve1 | key | version | score | member | reserve2 |
+ * |    8B    |     |    8B   |   8B  |        |   16B    |
+ *
+ * The score is stored with `encode_score`, so that the bytewise order of the
+ * score keys of one zset is the numeric order of their scores.
+ */
+pub struct ZSetsMemberKey {
+    reserve1: [u8; 8],
+    key: Vec<u8>,
+    version: u64,
+    member: Vec<u8>,
+    reserve2: [u8; 16],
+}
+
+impl ZSetsMemberKey {
+    pub fn new(key: &[u8], version: u64, member: &[u8]) -> Self {
+        Self::with_reserves(key, version, member, [0; 8], [0; 16])
+    }
+
+    pub fn with_reserves(
+        key: &[u8],
+        version: u64,
+        member: &[u8],
+        reserve1: [u8; 8],
+        reserve2: [u8; 16],
+    ) -> Self {
+        Self {
+            reserve1,
+            key: key.to_vec(),
+            version,
+            member: member.to_vec(),
+            reserve2,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut dst = encode_prefix(
+            &self.reserve1,
+            &self.key,
+            self.version,
+            self.member.len() + RESERVE2_LEN,
+        )?;
+        dst.extend_from_slice(&self.member);
+        dst.extend_from_slice(&self.reserve2);
+        Ok(dst)
+    }
+
+    /// Encodes `| reserve1 | key | version |`, the prefix shared by all the
+    /// member keys of one zset.
+    pub fn encode_prefix(&self) -> Result<Vec<u8>> {
+        encode_prefix(&self.reserve1, &self.key, self.version, 0)
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        &self.reserve1
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        &self.reserve2
+    }
+}
+
+pub struct ParsedZSetsMemberKey {
+    key_str: Vec<u8>,
+    reserve1: [u8; 8],
+    version: u64,
+    member: Vec<u8>,
+    reserve2: [u8; 16],
+}
+
+impl ParsedZSetsMemberKey {
+    pub fn from_slice(key: &[u8]) -> Result<Self> {
+        Self::decode(key)
+    }
+
+    pub fn decode(key: &[u8]) -> Result<Self> {
+        let parts = decode_prefix(key)?;
+        Ok(Self {
+            key_str: parts.key_str,
+            reserve1: parts.reserve1,
+            version: parts.version,
+            member: parts.rest.to_vec(),
+            reserve2: parts.reserve2,
+        })
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key_str
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn member(&self) -> &[u8] {
+        &self.member
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        &self.reserve1
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        &self.reserve2
+    }
+}
+
+pub struct ZSetsScoreKey {
+    reserve1: [u8; 8],
+    key: Vec<u8>,
+    version: u64,
+    score: f64,
+    member: Vec<u8>,
+    reserve2: [u8; 16],
+}
+
+impl ZSetsScoreKey {
+    pub fn new(key: &[u8], version: u64, score: f64, member: &[u8]) -> Self {
+        Self::with_reserves(key, version, score, member, [0; 8], [0; 16])
+    }
+
+    pub fn with_reserves(
+        key: &[u8],
+        version: u64,
+        score: f64,
+        member: &[u8],
+        reserve1: [u8; 8],
+        reserve2: [u8; 16],
+    ) -> Self {
+        Self {
+            reserve1,
+            key: key.to_vec(),
+            version,
+            score,
+            member: member.to_vec(),
+            reserve2,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut dst = self.encode_seek_key()?;
+        dst.extend_from_slice(&self.member);
+        dst.extend_from_slice(&self.reserve2);
+        Ok(dst)
+    }
+
+    /// Encodes `| reserve1 | key | version | score |`, where an iteration
+    /// over the members with a score of at least `score` starts.
+    pub fn encode_seek_key(&self) -> Result<Vec<u8>> {
+        let mut dst = encode_prefix(
+            &self.reserve1,
+            &self.key,
+            self.version,
+            SCORE_LEN + self.member.len() + RESERVE2_LEN,
+        )?;
+        dst.extend_from_slice(&encode_score(self.score));
+        Ok(dst)
+    }
+
+    /// Encodes `| reserve1 | key | version |`, the prefix shared by all the
+    /// score keys of one zset.
+    pub fn encode_prefix(&self) -> Result<Vec<u8>> {
+        encode_prefix(&self.reserve1, &self.key, self.version, 0)
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        &self.reserve1
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        &self.reserve2
+    }
+}
+
+pub struct ParsedZSetsScoreKey {
+    key_str: Vec<u8>,
+    reserve1: [u8; 8],
+    version: u64,
+    score: f64,
+    member: Vec<u8>,
+    reserve2: [u8; 16],
+}
+
+impl ParsedZSetsScoreKey {
+    pub fn from_slice(key: &[u8]) -> Result<Self> {
+        Self::decode(key)
+    }
+
+    pub fn decode(key: &[u8]) -> Result<Self> {
+        let parts = decode_prefix(key)?;
+        if parts.rest.len() < SCORE_LEN {
+            return Err(crate::error::Error::InvalidFormat {
+                message: "Key too short for score field".to_string(),
+                location: snafu::location!(),
+            });
+        }
+
+        let score = decode_score(&parts.rest[..SCORE_LEN]);
+        Ok(Self {
+            key_str: parts.key_str,
+            reserve1: parts.reserve1,
+            version: parts.version,
+            score,
+            member: parts.rest[SCORE_LEN..].to_vec(),
+            reserve2: parts.reserve2,
+        })
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key_str
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+
+    pub fn member(&self) -> &[u8] {
+        &self.member
+    }
+
+    pub fn reserve1(&self) -> &[u8; 8] {
+        &self.reserve1
+    }
+
+    pub fn reserve2(&self) -> &[u8; 16] {
+        &self.reserve2
+    }
+}
+
+/// Encodes a score into 8 bytes whose bytewise order is the numeric order:
+/// big-endian IEEE 754 bits, with all bits flipped for negative numbers and
+/// only the sign bit flipped otherwise.
+pub fn encode_score(score: f64) -> [u8; 8] {
+    let bits = score.to_bits();
+    let ordered = if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | (1 << 63)
+    };
+    ordered.to_be_bytes()
+}
+
+/// Encodes the inclusive lower bound of a score range. -0.0 sorts just
+/// before 0.0 while the two are equal scores, so a range starting at 0.0
+/// starts at -0.0.
+pub fn encode_score_lower_bound(min: f64) -> [u8; 8] {
+    encode_score(if min == 0.0 { -0.0 } else { min })
+}
+
+/// Encodes the inclusive upper bound of a score range, see
+/// `encode_score_lower_bound`.
+pub fn encode_score_upper_bound(max: f64) -> [u8; 8] {
+    encode_score(if max == 0.0 { 0.0 } else { max })
+}
+
+/// Reverses `encode_score`.
+pub fn decode_score(buf: &[u8]) -> f64 {
+    let mut bytes = [0u8; SCORE_LEN];
+    bytes.copy_from_slice(&buf[..SCORE_LEN]);
+    let ordered = u64::from_be_bytes(bytes);
+    let bits = if ordered >> 63 == 1 {
+        ordered & !(1 << 63)
+    } else {
+        !ordered
+    };
+    f64::from_bits(bits)
+}
+
+fn encode_prefix(
+    reserve1: &[u8; 8],
+    key: &[u8],
+    version: u64,
+    extra_capacity: usize,
+) -> Result<Vec<u8>> {
+    // Each NEED_TRANSFORM_CHARACTER (0) is escaped as two bytes, so we need to
+    // account for the extra space.
+    let nzero = key
+        .iter()
+        .filter(|&&c| c == NEED_TRANSFORM_CHARACTER as u8)
+        .count();
+    let encoded_key_len = key.len() + nzero + ENCODED_KEY_DELIM_SIZE;
+
+    let mut dst = Vec::with_capacity(RESERVE1_LEN + encoded_key_len + U64_LEN + extra_capacity);
+
+    // 1. reserve1 (8 bytes)
+    dst.extend_from_slice(reserve1);
+
+    // 2. encoded user key
+    let mut temp_buf = BytesMut::with_capacity(encoded_key_len);
+    encode_user_key(key, &mut temp_buf)?;
+    dst.extend_from_slice(&temp_buf);
+
+    // 3. version (8 bytes)
+    let mut version_buf = [0u8; U64_LEN];
+    encode_fixed(&mut version_buf, version);
+    dst.extend_from_slice(&version_buf);
+
+    Ok(dst)
+}
+
+struct PrefixParts<'a> {
+    key_str: Vec<u8>,
+    reserve1: [u8; 8],
+    version: u64,
+    // bytes between the version and reserve2
+    rest: &'a [u8],
+    reserve2: [u8; 16],
+}
+
+fn decode_prefix(key: &[u8]) -> Result<PrefixParts<'_>> {
+    let min_len = RESERVE1_LEN + ENCODED_KEY_DELIM_SIZE + U64_LEN + RESERVE2_LEN;
+    if key.len() < min_len {
+        return Err(crate::error::Error::InvalidFormat {
+            message: "Key too short for zset data key".to_string(),
+            location: snafu::location!(),
+        });
+    }
+
+    // skip head reserve1 and tail reserve2
+    let encoded_key_start = RESERVE1_LEN;
+    let encoded_key_end = key.len() - RESERVE2_LEN;
+    let encoded_key_slice = &key[encoded_key_start..encoded_key_end];
+
+    // Zero bytes of the user key are escaped as "\x00\x01", so the first
+    // "\x00\x00" is always the delimiter even if the member contains zeros.
+    let pos = encoded_key_slice
+        .windows(ENCODED_KEY_DELIM_SIZE)
+        .position(|window| window == b"\x00\x00")
+        .map(|p| p + ENCODED_KEY_DELIM_SIZE)
+        .ok_or_else(|| crate::error::Error::InvalidFormat {
+            message: "Encoded key delimiter not found".to_string(),
+            location: snafu::location!(),
+        })?;
+
+    let mut key_str_buf = BytesMut::with_capacity(pos);
+    decode_user_key(&encoded_key_slice[..pos], &mut key_str_buf)?;
+
+    let version_offset = encoded_key_start + pos;
+    let rest_offset = version_offset + U64_LEN;
+    if rest_offset > encoded_key_end {
+        return Err(crate::error::Error::InvalidFormat {
+            message: "Key too short for version field".to_string(),
+            location: snafu::location!(),
+        });
+    }
+
+    let reserve1 =
+        key[..RESERVE1_LEN]
+            .try_into()
+            .map_err(|_| crate::error::Error::InvalidFormat {
+                message: "Failed to read reserve1 field".to_string(),
+                location: snafu::location!(),
+            })?;
+    let reserve2 =
+        key[encoded_key_end..]
+            .try_into()
+            .map_err(|_| crate::error::Error::InvalidFormat {
+                message: "Failed to read reserve2 field".to_string(),
+                location: snafu::location!(),
+            })?;
+
+    Ok(PrefixParts {
+        key_str: key_str_buf.to_vec(),
+        reserve1,
+        version: decode_fixed(&key[version_offset..rest_offset]),
+        rest: &key[rest_offset..encoded_key_end],
+        reserve2,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn test_member_key_encode_decode() -> Result<()> {
+        let key = b"zset\x00key";
+        let member = b"mem\x00\x00ber";
+
+        let encoded = ZSetsMemberKey::new(key, 42, member).encode()?;
+        let parsed = ParsedZSetsMemberKey::from_slice(&encoded)?;
+
+        assert_eq!(parsed.key(), key);
+        assert_eq!(parsed.version(), 42);
+        assert_eq!(parsed.member(), member);
+        Ok(())
+    }
+
+    #[test]
+    fn test_score_key_encode_decode() -> Result<()> {
+        let key = b"zset\x00key";
+        for score in [0.0, -0.0, 1.5, -1.5, f64::MAX, f64::MIN, f64::INFINITY] {
+            let encoded = ZSetsScoreKey::new(key, 7, score, b"member").encode()?;
+            let parsed = ParsedZSetsScoreKey::from_slice(&encoded)?;
+
+            assert_eq!(parsed.key(), key);
+            assert_eq!(parsed.version(), 7);
+            assert_eq!(parsed.score().to_bits(), score.to_bits());
+            assert_eq!(parsed.member(), b"member");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_score_encoding_preserves_order() {
+        let scores = [
+            f64::NEG_INFINITY,
+            f64::MIN,
+            -1e10,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            1.0,
+            1.5,
+            1e10,
+            f64::MAX,
+            f64::INFINITY,
+        ];
+        for pair in scores.windows(2) {
+            assert!(
+                encode_score(pair[0]) < encode_score(pair[1]),
+                "{} should sort before {}",
+                pair[0],
+                pair[1]
+            );
+        }
+        for score in scores {
+            assert_eq!(
+                decode_score(&encode_score(score)).to_bits(),
+                score.to_bits()
+            );
+        }
+    }
+
+    /// splitmix64, so the random doubles are the same on every run
+    fn random_bits(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    #[test]
+    fn test_score_encoding_random_doubles() {
+        let mut state = 42;
+        let mut scores = vec![f64::NEG_INFINITY, f64::INFINITY, -0.0, 0.0];
+        for _ in 0..2000 {
+            // random bit patterns cover every exponent, with subnormals
+            scores.push(f64::from_bits(random_bits(&mut state)));
+            // and doubles of the magnitude of real scores
+            let small = (random_bits(&mut state) >> 11) as f64 / (1u64 << 53) as f64;
+            scores.push((small - 0.5) * 1e6);
+        }
+        scores.retain(|score| !score.is_nan());
+
+        for score in &scores {
+            let decoded = decode_score(&encode_score(*score));
+            assert_eq!(decoded.to_bits(), score.to_bits());
+        }
+        for pair in scores.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            // the bytewise order is the IEEE 754 total order, which is the
+            // numeric order apart from -0.0 sorting before 0.0
+            assert_eq!(
+                encode_score(a).cmp(&encode_score(b)),
+                a.total_cmp(&b),
+                "{a} and {b}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_score_range_bounds_include_both_zeros() {
+        for zero in [-0.0, 0.0] {
+            let encoded = encode_score(zero);
+            assert!(encode_score_lower_bound(0.0) <= encoded);
+            assert!(encode_score_lower_bound(-0.0) <= encoded);
+            assert!(encoded <= encode_score_upper_bound(0.0));
+            assert!(encoded <= encode_score_upper_bound(-0.0));
+        }
+        assert!(encode_score(-f64::MIN_POSITIVE) < encode_score_lower_bound(0.0));
+        assert!(encode_score(f64::MIN_POSITIVE) > encode_score_upper_bound(-0.0));
+        assert_eq!(encode_score_lower_bound(1.5), encode_score(1.5));
+        assert_eq!(encode_score_upper_bound(-1.5), encode_score(-1.5));
+    }
+
+    #[test]
+    fn test_score_keys_sort_by_score() -> Result<()> {
+        let mut encoded = Vec::new();
+        for (score, member) in [(3.0, &b"a"[..]), (-2.0, b"b"), (0.5, b"c"), (-2.0, b"a")] {
+            encoded.push(ZSetsScoreKey::new(b"z", 1, score, member).encode()?);
+        }
+        encoded.sort();
+
+        let order: Vec<(f64, Vec<u8>)> = encoded
+            .iter()
+            .map(|k| {
+                let parsed = ParsedZSetsScoreKey::from_slice(k).unwrap();
+                (parsed.score(), parsed.member().to_vec())
+            })
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (-2.0, b"a".to_vec()),
+                (-2.0, b"b".to_vec()),
+                (0.5, b"c".to_vec()),
+                (3.0, b"a".to_vec()),
+            ]
+        );
+
+        let prefix = ZSetsScoreKey::new(b"z", 1, 0.0, b"").encode_prefix()?;
+        assert!(encoded.iter().all(|k| k.starts_with(&prefix)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_encoding() {
+        let result = ParsedZSetsMemberKey::from_slice(b"invalid\x00\x02data");
+        assert!(matches!(result, Err(Error::InvalidFormat { .. })));
+
+        // a member key has no room for the score
+        let member_key = ZSetsMemberKey::new(b"k", 1, b"m").encode().unwrap();
+        let result = ParsedZSetsScoreKey::from_slice(&member_key);
+        assert!(matches!(result, Err(Error::InvalidFormat { .. })));
+    }
+
+    #[test]
+    fn test_reserve_fields_round_trip() -> Result<()> {
+        let reserve1 = [1, 2, 3, 4, 5, 6, 7, 8];
+        let reserve2 = [
+            9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        ];
+
+        let encoded =
+            ZSetsScoreKey::with_reserves(b"key", 3, 2.5, b"m", reserve1, reserve2).encode()?;
+        let parsed = ParsedZSetsScoreKey::from_slice(&encoded)?;
+        assert_eq!(parsed.reserve1(), &reserve1);
+        assert_eq!(parsed.reserve2(), &reserve2);
+        assert_eq!(parsed.score(), 2.5);
+
+        let encoded =
+            ZSetsMemberKey::with_reserves(b"key", 3, b"m", reserve1, reserve2).encode()?;
+        let parsed = ParsedZSetsMemberKey::from_slice(&encoded)?;
+        assert_eq!(parsed.reserve1(), &reserve1);
+        assert_eq!(parsed.reserve2(), &reserve2);
+        assert_eq!(parsed.member(), b"m");
+        Ok(())
+    }
+}
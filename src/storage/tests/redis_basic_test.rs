@@ -56,9 +56,9 @@ mod redis_basic_test {
 
         assert_eq!(redis.is_starting.load(Ordering::SeqCst), false);
         assert!(redis.db.is_some());
-        assert_eq!(redis.handles.len(), 6);
+        assert_eq!(redis.handles.len(), 12);
 
-        for cf_index in 0..6 {
+        for cf_index in 0..12 {
             let cf_enum = match cf_index {
                 0 => ColumnFamilyIndex::MetaCF,
                 1 => ColumnFamilyIndex::HashesDataCF,
@@ -66,6 +66,12 @@ mod redis_basic_test {
                 3 => ColumnFamilyIndex::ListsDataCF,
                 4 => ColumnFamilyIndex::ZsetsDataCF,
                 5 => ColumnFamilyIndex::ZsetsScoreCF,
+                6 => ColumnFamilyIndex::SystemCF,
+                7 => ColumnFamilyIndex::TrashCF,
+                8 => ColumnFamilyIndex::StreamsGroupCF,
+                9 => ColumnFamilyIndex::BitmapDataCF,
+                10 => ColumnFamilyIndex::IntentLogCF,
+                11 => ColumnFamilyIndex::StreamsDataCF,
                 _ => panic!("Invalid CF index"),
             };
 
@@ -78,12 +84,18 @@ mod redis_basic_test {
         }
 
         let expected_cf_names = [
-            "default",       // MetaCF
-            "hash_data_cf",  // HashesDataCF
-            "set_data_cf",   // SetsDataCF
-            "list_data_cf",  // ListsDataCF
-            "zset_data_cf",  // ZsetsDataCF
-            "zset_score_cf", // ZsetsScoreCF
+            "default",         // MetaCF
+            "hash_data_cf",    // HashesDataCF
+            "set_data_cf",     // SetsDataCF
+            "list_data_cf",    // ListsDataCF
+            "zset_data_cf",    // ZsetsDataCF
+            "zset_score_cf",   // ZsetsScoreCF
+            "system_cf",       // SystemCF
+            "trash_cf",        // TrashCF
+            "stream_group_cf", // StreamsGroupCF
+            "bitmap_data_cf",  // BitmapDataCF
+            "intent_log_cf",   // IntentLogCF
+            "stream_data_cf",  // StreamsDataCF
         ];
 
         for (i, expected_name) in expected_cf_names.iter().enumerate() {
@@ -111,6 +123,12 @@ mod redis_basic_test {
         assert_eq!(ColumnFamilyIndex::ListsDataCF as usize, 3);
         assert_eq!(ColumnFamilyIndex::ZsetsDataCF as usize, 4);
         assert_eq!(ColumnFamilyIndex::ZsetsScoreCF as usize, 5);
+        assert_eq!(ColumnFamilyIndex::SystemCF as usize, 6);
+        assert_eq!(ColumnFamilyIndex::TrashCF as usize, 7);
+        assert_eq!(ColumnFamilyIndex::StreamsGroupCF as usize, 8);
+        assert_eq!(ColumnFamilyIndex::BitmapDataCF as usize, 9);
+        assert_eq!(ColumnFamilyIndex::IntentLogCF as usize, 10);
+        assert_eq!(ColumnFamilyIndex::StreamsDataCF as usize, 11);
 
         assert_eq!(ColumnFamilyIndex::MetaCF.name(), "default");
         assert_eq!(ColumnFamilyIndex::HashesDataCF.name(), "hash_data_cf");
@@ -118,6 +136,12 @@ mod redis_basic_test {
         assert_eq!(ColumnFamilyIndex::ListsDataCF.name(), "list_data_cf");
         assert_eq!(ColumnFamilyIndex::ZsetsDataCF.name(), "zset_data_cf");
         assert_eq!(ColumnFamilyIndex::ZsetsScoreCF.name(), "zset_score_cf");
+        assert_eq!(ColumnFamilyIndex::SystemCF.name(), "system_cf");
+        assert_eq!(ColumnFamilyIndex::TrashCF.name(), "trash_cf");
+        assert_eq!(ColumnFamilyIndex::StreamsGroupCF.name(), "stream_group_cf");
+        assert_eq!(ColumnFamilyIndex::BitmapDataCF.name(), "bitmap_data_cf");
+        assert_eq!(ColumnFamilyIndex::IntentLogCF.name(), "intent_log_cf");
+        assert_eq!(ColumnFamilyIndex::StreamsDataCF.name(), "stream_data_cf");
     }
 
     #[cfg(not(miri))]
@@ -19,9 +19,13 @@
 
 #[cfg(test)]
 mod redis_string_test {
+    use kstd::cancel::CancelToken;
     use kstd::lock_mgr::LockMgr;
     use std::{sync::Arc, thread, time::Duration};
-    use storage::{unique_test_db_path, BgTaskHandler, Redis, StorageOptions};
+    use storage::{
+        error::Error, unique_test_db_path, BgTaskHandler, DataType, Pipeline, PipelineOp,
+        PipelineResult, Redis, SetCondition, SetOptions, StorageOptions,
+    };
 
     #[cfg(not(miri))]
     #[test]
@@ -434,4 +438,303 @@ mod redis_string_test {
             std::fs::remove_dir_all(test_db_path).unwrap();
         }
     }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_redis_warmup() {
+        let test_db_path = unique_test_db_path();
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(&test_db_path).unwrap();
+        }
+
+        let storage_options = Arc::new(StorageOptions::default());
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let lock_mgr = Arc::new(LockMgr::new(1000));
+        let mut redis = Redis::new(storage_options, 1, Arc::new(bg_task_handler), lock_mgr);
+
+        let result = redis.open(test_db_path.to_str().unwrap());
+        assert!(result.is_ok(), "open redis db failed: {:?}", result.err());
+
+        for key in [&b"user:1"[..], b"user:2", b"user:\x003", b"session:1"] {
+            redis.set(key, b"value").unwrap();
+        }
+
+        let token = CancelToken::new();
+        let keys = vec![b"user:1".to_vec(), b"missing".to_vec()];
+        let stats = redis.warmup_keys(&keys, &token).unwrap();
+        assert_eq!(stats.keys, 1);
+        assert_eq!(stats.missing, 1);
+
+        let stats = redis.warmup_prefix(b"user:", &token).unwrap();
+        assert_eq!(stats.keys, 3);
+
+        assert!(redis.load_hot_keys_snapshot().unwrap().is_empty());
+        redis.save_hot_keys_snapshot(&keys).unwrap();
+        assert_eq!(redis.load_hot_keys_snapshot().unwrap(), keys);
+
+        token.cancel();
+        assert!(redis.warmup_prefix(b"", &token).is_err());
+
+        redis.set_need_close(true);
+        drop(redis);
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(test_db_path).unwrap();
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_redis_key_types() {
+        let test_db_path = unique_test_db_path();
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(&test_db_path).unwrap();
+        }
+
+        let storage_options = Arc::new(StorageOptions::default());
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let lock_mgr = Arc::new(LockMgr::new(1000));
+        let mut redis = Redis::new(storage_options, 1, Arc::new(bg_task_handler), lock_mgr);
+
+        let result = redis.open(test_db_path.to_str().unwrap());
+        assert!(result.is_ok(), "open redis db failed: {:?}", result.err());
+
+        redis.set(b"k1", b"v1").unwrap();
+        redis.set(b"k2", b"v2").unwrap();
+
+        let keys: [&[u8]; 4] = [b"k1", b"missing", b"k1", b"k2"];
+        let types = redis.key_types(&keys).unwrap();
+        assert_eq!(
+            types,
+            vec![
+                Some(DataType::String),
+                None,
+                Some(DataType::String),
+                Some(DataType::String)
+            ]
+        );
+        assert!(redis.key_types(&[]).unwrap().is_empty());
+
+        redis.set_need_close(true);
+        drop(redis);
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(test_db_path).unwrap();
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_redis_del_undelete() {
+        let test_db_path = unique_test_db_path();
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(&test_db_path).unwrap();
+        }
+
+        let mut storage_options = StorageOptions::default();
+        storage_options.set_trash_retention_s(3600);
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let lock_mgr = Arc::new(LockMgr::new(1000));
+        let mut redis = Redis::new(
+            Arc::new(storage_options),
+            1,
+            Arc::new(bg_task_handler),
+            lock_mgr,
+        );
+
+        let result = redis.open(test_db_path.to_str().unwrap());
+        assert!(result.is_ok(), "open redis db failed: {:?}", result.err());
+
+        redis.set(b"k1", b"v1").unwrap();
+        redis.set(b"k2", b"v2").unwrap();
+
+        let keys: [&[u8]; 3] = [b"k1", b"missing", b"k2"];
+        assert_eq!(redis.del(&keys).unwrap(), 2);
+        assert!(redis.get(b"k1").is_err());
+        assert!(!redis.undelete(b"missing").unwrap());
+
+        assert!(redis.undelete(b"k1").unwrap());
+        assert_eq!(redis.get(b"k1").unwrap(), "v1");
+        assert!(!redis.undelete(b"k1").unwrap());
+
+        // A key created again after the deletion is not overwritten
+        redis.set(b"k2", b"new").unwrap();
+        assert!(!redis.undelete(b"k2").unwrap());
+        assert_eq!(redis.get(b"k2").unwrap(), "new");
+
+        redis.set_need_close(true);
+        drop(redis);
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(test_db_path).unwrap();
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_redis_execute_pipeline() {
+        let test_db_path = unique_test_db_path();
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(&test_db_path).unwrap();
+        }
+
+        let storage_options = Arc::new(StorageOptions::default());
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let lock_mgr = Arc::new(LockMgr::new(1000));
+        let mut redis = Redis::new(storage_options, 1, Arc::new(bg_task_handler), lock_mgr);
+
+        let result = redis.open(test_db_path.to_str().unwrap());
+        assert!(result.is_ok(), "open redis db failed: {:?}", result.err());
+
+        redis.set(b"old", b"v").unwrap();
+
+        let mut pipeline = Pipeline::new();
+        pipeline
+            .set("k1", "v1")
+            .del("old")
+            .del("missing")
+            .set("k2", "v2")
+            .del("k2")
+            .set("k1", "v1b");
+        let ops: Vec<&PipelineOp> = pipeline.ops().iter().collect();
+        let results = redis.execute_pipeline(&ops).unwrap();
+        assert_eq!(
+            results,
+            vec![
+                PipelineResult::Set,
+                PipelineResult::Del(true),
+                PipelineResult::Del(false),
+                PipelineResult::Set,
+                PipelineResult::Del(true),
+                PipelineResult::Set,
+            ]
+        );
+
+        assert_eq!(redis.get(b"k1").unwrap(), "v1b");
+        assert!(redis.get(b"k2").is_err());
+        assert!(redis.get(b"old").is_err());
+        assert!(redis.execute_pipeline(&[]).unwrap().is_empty());
+
+        redis.set_need_close(true);
+        drop(redis);
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(test_db_path).unwrap();
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_redis_value_size_limit() {
+        let test_db_path = unique_test_db_path();
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(&test_db_path).unwrap();
+        }
+
+        let mut storage_options = StorageOptions::default();
+        storage_options.set_max_value_size(4);
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let lock_mgr = Arc::new(LockMgr::new(1000));
+        let mut redis = Redis::new(
+            Arc::new(storage_options),
+            1,
+            Arc::new(bg_task_handler),
+            lock_mgr,
+        );
+
+        let result = redis.open(test_db_path.to_str().unwrap());
+        assert!(result.is_ok(), "open redis db failed: {:?}", result.err());
+
+        redis.set(b"k1", b"1234").unwrap();
+        assert!(matches!(
+            redis.set(b"k1", b"12345"),
+            Err(Error::ValueTooLarge {
+                size: 5,
+                limit: 4,
+                ..
+            })
+        ));
+        assert_eq!(redis.get(b"k1").unwrap(), "1234");
+
+        // One op over the limit rejects the whole pipeline
+        let mut pipeline = Pipeline::new();
+        pipeline.set("k2", "v2").set("k1", "too long");
+        let ops: Vec<&PipelineOp> = pipeline.ops().iter().collect();
+        assert!(matches!(
+            redis.execute_pipeline(&ops),
+            Err(Error::ValueTooLarge { .. })
+        ));
+        assert!(redis.get(b"k2").is_err());
+        assert_eq!(redis.get(b"k1").unwrap(), "1234");
+
+        redis.set_need_close(true);
+        drop(redis);
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(test_db_path).unwrap();
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_redis_set_with_options() {
+        let test_db_path = unique_test_db_path();
+
+        let storage_options = Arc::new(StorageOptions::default());
+        let (bg_task_handler, _) = BgTaskHandler::new();
+        let lock_mgr = Arc::new(LockMgr::new(1000));
+        let mut redis = Redis::new(storage_options, 1, Arc::new(bg_task_handler), lock_mgr);
+
+        let result = redis.open(test_db_path.to_str().unwrap());
+        assert!(result.is_ok(), "open redis db failed: {:?}", result.err());
+
+        let nx = SetOptions {
+            condition: SetCondition::IfMissing,
+            get: false,
+        };
+        let xx_get = SetOptions {
+            condition: SetCondition::IfExists,
+            get: true,
+        };
+
+        // XX on a missing key neither writes nor returns a value
+        let result = redis.set_with(b"k", b"v1", xx_get).unwrap();
+        assert!(!result.did_set);
+        assert_eq!(result.old_value, None);
+        assert!(redis.get(b"k").is_err());
+
+        assert!(redis.set_with(b"k", b"v1", nx).unwrap().did_set);
+        assert!(!redis.set_with(b"k", b"v2", nx).unwrap().did_set);
+        assert_eq!(redis.get(b"k").unwrap(), "v1");
+
+        let result = redis.set_with(b"k", b"v2", xx_get).unwrap();
+        assert!(result.did_set);
+        assert_eq!(result.old_value, Some(b"v1".to_vec()));
+        assert_eq!(redis.get(b"k").unwrap(), "v2");
+
+        // GET on another type fails and writes nothing, NX sees the key
+        let mut res = 0;
+        redis.hset(b"h", b"f", b"v", &mut res).unwrap();
+        assert!(!redis.set_with(b"h", b"v", nx).unwrap().did_set);
+        let get = SetOptions {
+            get: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            redis.set_with(b"h", b"v", get),
+            Err(Error::WrongType { .. })
+        ));
+
+        redis.set_need_close(true);
+        drop(redis);
+
+        if test_db_path.exists() {
+            std::fs::remove_dir_all(test_db_path).unwrap();
+        }
+    }
 }
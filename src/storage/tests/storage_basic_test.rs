@@ -17,9 +17,14 @@
  * limitations under the License.
  */
 
+use kstd::cancel::CancelToken;
 use std::sync::Arc;
+use std::time::Duration;
+use storage::error::Error;
 use storage::storage::Storage;
-use storage::{BgTask, BgTaskHandler, DataType};
+use storage::{
+    unique_test_db_path, BgTask, BgTaskHandler, DataType, ListEnd, SelfTestOptions, StorageOptions,
+};
 
 // This test ensures:
 // - All tasks are sent successfully (no panic)
@@ -68,3 +73,134 @@ async fn test_bg_task_worker_concurrent() {
     handler.send(BgTask::Shutdown).await.unwrap();
     worker_handle.await.unwrap();
 }
+
+#[tokio::test]
+async fn test_keyspace_events_replay() {
+    let test_db_path = unique_test_db_path();
+    let mut options = StorageOptions::default();
+    options.set_keyspace_events_replay_len(2);
+    let mut storage = Storage::new(1, 0);
+    storage.open(Arc::new(options), &test_db_path).unwrap();
+
+    let mut keyspace = storage.subscribe(&[b"__keyspace@0__:k1"]);
+    let mut keyevent = storage.psubscribe(&[b"__keyevent@0__:*"]);
+
+    for key in [b"k1", b"k2", b"k3"] {
+        storage.set(key, b"v").unwrap();
+    }
+    let keys = vec![
+        b"k1".to_vec(),
+        b"missing".to_vec(),
+        b"k2".to_vec(),
+        b"k3".to_vec(),
+    ];
+    assert_eq!(storage.del(&keys).unwrap().deleted, 3);
+
+    assert_eq!(keyspace.recv().await.unwrap().payload, "del");
+    for key in ["k1", "k2", "k3"] {
+        let message = keyevent.recv().await.unwrap();
+        assert_eq!(message.channel, b"__keyevent@0__:del");
+        assert_eq!(message.payload, key);
+    }
+
+    // Only the last two events are kept
+    let last = storage.last_keyspace_events(10);
+    let seqs: Vec<_> = last.iter().map(|e| (e.seq, e.key.clone())).collect();
+    assert_eq!(seqs, vec![(2, b"k2".to_vec()), (3, b"k3".to_vec())]);
+    assert_eq!(storage.keyspace_events_since(2).unwrap().len(), 1);
+    assert!(storage.keyspace_events_since(0).is_none());
+
+    storage.shutdown().await;
+}
+
+#[test]
+fn test_self_test() {
+    let test_db_path = unique_test_db_path();
+    let mut storage = Storage::new(1, 0);
+    storage
+        .open(Arc::new(StorageOptions::default()), &test_db_path)
+        .unwrap();
+
+    let options = SelfTestOptions {
+        keys: 100,
+        value_len: 32,
+    };
+    let report = storage.self_test(&options).unwrap();
+    assert!(report.passed(), "{report}");
+    assert_eq!(report.phases.len(), 4);
+    assert!(report.phases.iter().all(|phase| phase.ops == 100));
+
+    // The test keys are gone
+    let keys = storage
+        .keys(b"__kiwi_self_test__:*", &CancelToken::new())
+        .unwrap();
+    assert!(keys.is_empty());
+}
+
+#[tokio::test]
+async fn test_read_your_writes_offset() {
+    let test_db_path = unique_test_db_path();
+    let mut options = StorageOptions::default();
+    options.set_read_your_writes_wait_ms(20);
+    let mut storage = Storage::new(1, 0);
+    storage.open(Arc::new(options), &test_db_path).unwrap();
+
+    assert_eq!(storage.applied_offset(), Some(0));
+    storage.advance_applied_offset(42);
+    assert_eq!(storage.applied_offset(), Some(42));
+    storage.wait_for_offset(42).unwrap();
+    let err = storage.wait_for_offset(43).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::OffsetNotApplied {
+            offset: 43,
+            applied: 42,
+            ..
+        }
+    ));
+    assert!(err.to_string().starts_with("TRYAGAIN "));
+
+    storage.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_blmpop_wakes_on_push() {
+    let test_db_path = unique_test_db_path();
+    let mut storage = Storage::new(1, 0);
+    storage
+        .open(Arc::new(StorageOptions::default()), &test_db_path)
+        .unwrap();
+    let storage = Arc::new(storage);
+    let keys = vec![b"q1".to_vec(), b"q2".to_vec()];
+
+    let popped = storage
+        .blmpop(&keys, ListEnd::Left, 1, Duration::from_millis(20))
+        .await
+        .unwrap();
+    assert!(popped.is_none());
+
+    let pusher = Arc::clone(&storage);
+    let push = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        pusher
+            .push(b"q2", ListEnd::Right, &[b"job1".to_vec(), b"job2".to_vec()])
+            .unwrap();
+    });
+    let popped = storage
+        .blmpop(&keys, ListEnd::Left, 1, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(popped, Some((b"q2".to_vec(), vec![b"job1".to_vec()])));
+    push.await.unwrap();
+
+    // Claim the job left in the queue, then ack it
+    let job = storage
+        .lmove(b"q2", b"processing", ListEnd::Left, ListEnd::Left)
+        .unwrap();
+    assert_eq!(job, Some(b"job2".to_vec()));
+    assert_eq!(storage.lrem(b"processing", 1, b"job2").unwrap(), 1);
+    assert!(storage
+        .pop(b"processing", ListEnd::Left, 1)
+        .unwrap()
+        .is_empty());
+}